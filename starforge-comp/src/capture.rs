@@ -0,0 +1,238 @@
+//! Screen capture of outputs and windows
+//!
+//! Captures are filled right after their output rendered a frame, from what the output
+//! shows without the cursor; clients that ask for the cursor get it drawn over the copy,
+//! and new frames when it moves.
+//! Windows are drawn on their own into an offscreen target of their size, with their
+//! subsurfaces and popups, whether they are shown or not; each session keeps its target
+//! while the window keeps its size. Captures are completed once the frame was presented.
+
+use smithay::{
+    output::Output,
+    reexports::{
+        wayland_protocols::ext::image_copy_capture::v1::server::{
+            ext_image_copy_capture_frame_v1::FailureReason,
+            ext_image_copy_capture_session_v1::ExtImageCopyCaptureSessionV1,
+        },
+        wayland_server::{Resource, protocol::wl_buffer::WlBuffer},
+    },
+    utils::{Physical, Rectangle, Size, Transform},
+    wayland::{dmabuf::get_dmabuf, shell::xdg::PopupSurface},
+};
+use starforge_core::{
+    StarforgeError, StarforgeResult, StarforgeState,
+    animation::SnapshotLayer,
+    protocols::image_capture::{ImageCaptureSource, PendingCapture, ToplevelCapture},
+};
+use starforge_render::{
+    CaptureTarget, CursorTexture, OffscreenLayer, OffscreenTarget, OutputId, StarforgeRenderer,
+    TextureId,
+};
+use tracing::error;
+
+/// Offscreen targets of the toplevel capture sessions
+#[derive(Default)]
+pub struct ToplevelTargets {
+    targets: Vec<(
+        ExtImageCopyCaptureSessionV1,
+        Size<i32, Physical>,
+        OffscreenTarget,
+    )>,
+}
+
+impl ToplevelTargets {
+    /// Drop every target, after the renderer they were created with lost its device
+    pub fn clear(&mut self) {
+        self.targets.clear();
+    }
+
+    /// The target of `session`, created again if the window changed size
+    fn get(
+        &mut self,
+        renderer: &StarforgeRenderer,
+        session: &ExtImageCopyCaptureSessionV1,
+        size: Size<i32, Physical>,
+    ) -> StarforgeResult<&OffscreenTarget> {
+        self.targets.retain(|(session, _, _)| session.is_alive());
+        let index = match self.targets.iter().position(|(s, _, _)| s == session) {
+            Some(index) if self.targets[index].1 == size => index,
+            Some(index) => {
+                self.targets[index].2 = renderer.create_offscreen_target(size)?;
+                self.targets[index].1 = size;
+                index
+            }
+            None => {
+                let target = renderer.create_offscreen_target(size)?;
+                self.targets.push((session.clone(), size, target));
+                self.targets.len() - 1
+            }
+        };
+        Ok(&self.targets[index].2)
+    }
+}
+
+/// Fill the captures with new content after `output`, the renderer's output `id`, rendered
+/// a frame with `damage`, which shows `cursor`
+///
+/// Returns the filled captures, to complete once the frame was presented; the others
+/// failed.
+#[allow(clippy::too_many_arguments)]
+pub fn fill_captures(
+    state: &mut StarforgeState,
+    renderer: &StarforgeRenderer,
+    targets: &mut ToplevelTargets,
+    output: &Output,
+    id: OutputId,
+    damage: &[Rectangle<i32, Physical>],
+    cursor: Option<(CursorTexture, Rectangle<i32, Physical>)>,
+) -> Vec<PendingCapture> {
+    // Outputs aren't transformed, so their buffer is laid out like the frame
+    let to_buffer = |damage: &[Rectangle<i32, Physical>]| -> Vec<_> {
+        damage
+            .iter()
+            .map(|rect| {
+                Rectangle::new(
+                    (rect.loc.x, rect.loc.y).into(),
+                    (rect.size.w, rect.size.h).into(),
+                )
+            })
+            .collect()
+    };
+    state
+        .image_copy_capture_state
+        .damage_output(output, &to_buffer(damage));
+    let cursor_damage = renderer.cursor_damage(id, cursor);
+    state
+        .image_copy_capture_state
+        .damage_output_cursor(output, &to_buffer(&cursor_damage));
+
+    let captures = state.image_copy_capture_state.take_pending_captures();
+    let mut filled = Vec::new();
+    for capture in captures {
+        match fill(
+            renderer,
+            targets,
+            output,
+            id,
+            cursor,
+            &state.popups,
+            &capture,
+        ) {
+            Ok(()) => filled.push(capture),
+            Err(e) => {
+                error!("Failed to capture: {}", e);
+                capture.fail(FailureReason::Unknown);
+            }
+        }
+    }
+    filled
+}
+
+/// Copy the source of `capture` into its buffer
+fn fill(
+    renderer: &StarforgeRenderer,
+    targets: &mut ToplevelTargets,
+    output: &Output,
+    id: OutputId,
+    cursor: Option<(CursorTexture, Rectangle<i32, Physical>)>,
+    popups: &[PopupSurface],
+    capture: &PendingCapture,
+) -> StarforgeResult<()> {
+    let target = capture_target(&capture.buffer);
+    match &capture.source {
+        ImageCaptureSource::Output(_) => {
+            if capture.source.output().as_ref() != Some(output) {
+                return Err(StarforgeError::OutputNotFound);
+            }
+            let cursor = cursor.filter(|_| capture.paint_cursors);
+            renderer.capture_output(id, target, &capture.copy_regions, cursor)
+        }
+        ImageCaptureSource::Toplevel(_) => {
+            let toplevel = capture.source.toplevel_capture(popups).ok_or_else(|| {
+                StarforgeError::RendererError("the toplevel has nothing to show".to_string())
+            })?;
+            let mut shm_textures = Vec::new();
+            let result = capture_toplevel(
+                renderer,
+                targets,
+                &toplevel,
+                target,
+                capture,
+                &mut shm_textures,
+            );
+            for texture in shm_textures {
+                if let Err(e) = renderer.release_buffer(texture) {
+                    error!("Failed to release texture: {}", e);
+                }
+            }
+            result
+        }
+    }
+}
+
+/// Draw a toplevel into the offscreen target of the capture's session and copy it into
+/// `target`, collecting the wl_shm uploads into `shm_textures` for the caller to release
+///
+/// Client buffers aren't waited for here: the frame rendered before waited for the acquire
+/// points of the windows it shows, and windows that aren't shown rely on implicit
+/// synchronization.
+fn capture_toplevel(
+    renderer: &StarforgeRenderer,
+    targets: &mut ToplevelTargets,
+    toplevel: &ToplevelCapture,
+    target: CaptureTarget<'_>,
+    capture: &PendingCapture,
+    shm_textures: &mut Vec<TextureId>,
+) -> StarforgeResult<()> {
+    let layers = toplevel
+        .snapshot
+        .layers
+        .iter()
+        .map(|layer| {
+            let texture = import(renderer, layer, shm_textures)?;
+            let rect = toplevel.layer_rect(layer);
+            Ok(OffscreenLayer {
+                texture,
+                rect: Rectangle::new(
+                    (rect.loc.x, rect.loc.y).into(),
+                    (rect.size.w, rect.size.h).into(),
+                ),
+                source: layer.source_uv().map(|uv| uv as f32),
+            })
+        })
+        .collect::<StarforgeResult<Vec<_>>>()?;
+    let size = toplevel
+        .bounds
+        .size
+        .to_buffer(toplevel.scale, Transform::Normal);
+    let offscreen = targets.get(renderer, capture.session(), Size::from((size.w, size.h)))?;
+    renderer.render_offscreen(offscreen, &layers)?;
+    renderer.capture_offscreen(offscreen, target, &capture.copy_regions)
+}
+
+/// Import the buffer of a layer with the surface's colour representation, adding wl_shm
+/// uploads to `shm_textures`
+fn import(
+    renderer: &StarforgeRenderer,
+    layer: &SnapshotLayer,
+    shm_textures: &mut Vec<TextureId>,
+) -> StarforgeResult<TextureId> {
+    let texture = match get_dmabuf(&layer.buffer) {
+        Ok(dmabuf) => renderer.import_dma_buf(dmabuf)?,
+        Err(_) => {
+            let texture = renderer.import_shm_buffer(&layer.buffer)?;
+            shm_textures.push(texture);
+            texture
+        }
+    };
+    renderer.set_color_representation(texture, layer.color_representation)?;
+    Ok(texture)
+}
+
+/// Where the renderer writes a capture into `buffer`
+fn capture_target(buffer: &WlBuffer) -> CaptureTarget<'_> {
+    match get_dmabuf(buffer) {
+        Ok(dmabuf) => CaptureTarget::Dmabuf(dmabuf),
+        Err(_) => CaptureTarget::Shm(buffer),
+    }
+}
@@ -1,9 +1,11 @@
 //! Starforge Compositor - The reference Starforge compositor implementation
 
+mod capture;
+mod render;
 mod winit;
 
-use starforge_config::StarforgeConfig;
-use starforge_core::StarforgeState;
+use starforge_config::{DecorationPolicyConfig, StarforgeConfig};
+use starforge_core::{StarforgeState, cursor::resolve_cursor_theme, decoration::DecorationPolicy};
 
 use smithay::reexports::calloop::EventLoop;
 use std::error::Error;
@@ -29,12 +31,28 @@ fn main() -> Result<(), Box<dyn Error>> {
     // Initialize compositor state
     let mut compositor_state = StarforgeState::new(&event_loop)?;
     compositor_state.init_event_loop(&mut event_loop)?;
+    let (cursor_theme, cursor_size) =
+        resolve_cursor_theme(config.cursor.theme.as_deref(), config.cursor.size);
+    compositor_state
+        .cursor_state
+        .set_theme(&cursor_theme, cursor_size);
+    compositor_state.set_decoration_policy(match config.decorations.policy {
+        DecorationPolicyConfig::PreferServer => DecorationPolicy::PreferServer,
+        DecorationPolicyConfig::PreferClient => DecorationPolicy::PreferClient,
+        DecorationPolicyConfig::Force => DecorationPolicy::ForceServer,
+    });
     info!("Compositor state initialized");
 
     // Initialize Winit backend
-    winit::init_winit(&mut event_loop, &mut compositor_state)?;
+    winit::init_winit(&mut event_loop, &mut compositor_state, &config)?;
     info!("Winit backend initialized");
 
+    // Host X11 applications, Xwayland starts when the first one connects
+    #[cfg(feature = "xwayland")]
+    if config.xwayland.enabled {
+        compositor_state.listen_xwayland(event_loop.handle())?;
+    }
+
     event_loop.run(None, &mut compositor_state, move |_state| {
         // Starforge is running
     })?;
@@ -0,0 +1,379 @@
+//! Render elements of what an output shows
+//!
+//! Windows are drawn bottom to top, each with its subsurfaces, title bar and popups, and
+//! the statistics HUD goes on top. The cursor is kept apart, for captures to leave it out. Windows the compositor decorates get the
+//! configured corners, border and shadow, and a blurred backdrop when they aren't opaque.
+//! Dma-bufs stay imported as long as their client keeps them, wl_shm buffers are uploaded
+//! for every frame they're shown in. Surfaces that committed damage since the output's
+//! last frame are damaged whole, for the renderer to tell what changed.
+
+use smithay::{
+    backend::renderer::utils::{CommitCounter, RendererSurfaceStateUserData},
+    output::Output,
+    reexports::wayland_server::{Resource, protocol::wl_surface::WlSurface},
+    utils::{Logical, Physical, Point, Rectangle, Size},
+    wayland::{
+        compositor::{RectangleKind, SurfaceAttributes, with_states},
+        dmabuf::get_dmabuf,
+    },
+};
+use starforge_config::{RenderConfig, WindowShapeConfig};
+use starforge_core::{
+    StarforgeResult, StarforgeState,
+    animation::{LayerKind, SnapshotLayer, WindowSnapshot},
+    cursor::CursorImage,
+    protocols::color_management::ImageDescription,
+    scale::to_physical_rect,
+};
+use starforge_render::{
+    BlurSettings, Border, CornerRadii, CursorTexture, OutputId, RenderElement, Shadow,
+    StarforgeRenderer, TextureId, WindowShape,
+};
+use std::{collections::HashMap, sync::Arc};
+use tracing::error;
+
+/// Distance of the statistics HUD from the top left corner of the output, in pixels
+const HUD_MARGIN: i32 = 8;
+
+/// How the windows the compositor decorates are drawn
+pub struct WindowStyle {
+    /// Shape in logical pixels, with square top corners below the title bar
+    shape: WindowShape,
+    /// Blur behind windows that aren't opaque
+    backdrop_blur: Option<BlurSettings>,
+}
+
+impl WindowStyle {
+    pub fn new(config: &RenderConfig) -> Self {
+        let windows = &config.windows;
+        Self {
+            shape: WindowShape {
+                corner_radii: CornerRadii {
+                    bottom_right: windows.corner_radius,
+                    bottom_left: windows.corner_radius,
+                    ..CornerRadii::ZERO
+                },
+                border: window_border(windows),
+                shadow: windows.shadow.map(|shadow| Shadow {
+                    offset: shadow.offset,
+                    radius: shadow.radius,
+                    spread: shadow.spread,
+                    color: shadow.color,
+                }),
+            },
+            backdrop_blur: config.blur.enabled.then_some(BlurSettings {
+                radius: config.blur.radius,
+                passes: config.blur.passes,
+            }),
+        }
+    }
+
+    /// The shape in physical pixels at `scale`
+    fn shape(&self, scale: f64) -> WindowShape {
+        let scale = scale as f32;
+        let radii = self.shape.corner_radii;
+        WindowShape {
+            corner_radii: CornerRadii {
+                top_left: radii.top_left * scale,
+                top_right: radii.top_right * scale,
+                bottom_right: radii.bottom_right * scale,
+                bottom_left: radii.bottom_left * scale,
+            },
+            border: self.shape.border.map(|border| Border {
+                width: border.width * scale,
+                ..border
+            }),
+            shadow: self.shape.shadow.map(|shadow| Shadow {
+                offset: shadow.offset.map(|offset| offset * scale),
+                radius: shadow.radius * scale,
+                spread: shadow.spread * scale,
+                ..shadow
+            }),
+        }
+    }
+}
+
+/// Border of decorated windows and their title bars, `None` when it has no width
+pub fn window_border(config: &WindowShapeConfig) -> Option<Border> {
+    (config.border_width > 0.0).then_some(Border {
+        width: config.border_width,
+        color: config.border_color,
+    })
+}
+
+/// Whether the opaque region of `surface` covers all of its `size`
+fn is_opaque(surface: &WlSurface, size: Size<i32, Logical>) -> bool {
+    with_states(surface, |states| {
+        let mut attributes = states.cached_state.get::<SurfaceAttributes>();
+        let Some(region) = &attributes.current().opaque_region else {
+            return false;
+        };
+        // Holes cut into the region count as translucent wherever they are
+        if region
+            .rects
+            .iter()
+            .any(|(kind, _)| matches!(kind, RectangleKind::Subtract))
+        {
+            return false;
+        }
+        let opaque = region.rects.iter().map(|(_, rect)| *rect);
+        Rectangle::subtract_rects_many([Rectangle::from_size(size)], opaque).is_empty()
+    })
+}
+
+/// The commit each surface was drawn with in the last frame of an output
+#[derive(Default)]
+pub struct DrawnCommits {
+    commits: HashMap<WlSurface, CommitCounter>,
+}
+
+/// The elements of one frame, holding the textures uploaded for it
+pub struct Frame {
+    pub elements: Vec<RenderElement>,
+    /// The cursor, drawn over the elements
+    pub cursor: Option<(CursorTexture, Rectangle<i32, Physical>)>,
+    /// Renderer id of the output the frame is for
+    output: OutputId,
+    /// wl_shm uploads, released once the frame was rendered
+    shm_textures: Vec<TextureId>,
+    /// Commits of the surfaces drawn in the output's last frame
+    last_commits: HashMap<WlSurface, CommitCounter>,
+    /// Commits of the surfaces drawn in this frame
+    commits: HashMap<WlSurface, CommitCounter>,
+}
+
+impl Frame {
+    /// Release the textures uploaded for the frame, after rendering it
+    pub fn release(self, renderer: &StarforgeRenderer) {
+        for texture in self.shm_textures {
+            if let Err(e) = renderer.release_buffer(texture) {
+                error!("Failed to release texture: {}", e);
+            }
+        }
+    }
+
+    /// Import the buffer of a layer for the frame, which waits for its acquire point and
+    /// converts it with the surface's colour representation
+    fn import(
+        &mut self,
+        renderer: &StarforgeRenderer,
+        layer: &SnapshotLayer,
+    ) -> StarforgeResult<TextureId> {
+        let texture = match get_dmabuf(&layer.buffer) {
+            Ok(dmabuf) => renderer.import_dma_buf(dmabuf)?,
+            Err(_) => {
+                let texture = renderer.import_shm_buffer(&layer.buffer)?;
+                self.shm_textures.push(texture);
+                texture
+            }
+        };
+        renderer.set_color_representation(texture, layer.color_representation)?;
+        renderer.use_client_buffer(self.output, &layer.buffer, layer.acquire_point.as_ref())?;
+        Ok(texture)
+    }
+
+    /// Whether the content of `layer` changed since the output's last frame, remembering
+    /// the commit it's drawn with
+    fn content_changed(&mut self, layer: &SnapshotLayer) -> bool {
+        if !layer.surface.is_alive() {
+            return true;
+        }
+        let last = self.last_commits.get(&layer.surface).copied();
+        let commit = with_states(&layer.surface, |states| {
+            let data = states.data_map.get::<RendererSurfaceStateUserData>()?;
+            let data = data.lock().unwrap();
+            let damaged = last.is_none() || !data.damage_since(last).is_empty();
+            Some((data.current_commit(), damaged))
+        });
+        match commit {
+            Some((commit, damaged)) => {
+                self.commits.insert(layer.surface.clone(), commit);
+                damaged
+            }
+            None => true,
+        }
+    }
+
+    /// Add snapshot layers of a window whose main surface is at `origin`, drawing the main
+    /// surface with `shape` over its `backdrop_blur`
+    #[allow(clippy::too_many_arguments)]
+    fn push_layers(
+        &mut self,
+        renderer: &StarforgeRenderer,
+        layers: &[SnapshotLayer],
+        origin: Point<i32, Logical>,
+        scale: f64,
+        shape: WindowShape,
+        backdrop_blur: Option<BlurSettings>,
+    ) {
+        let srgb = Arc::new(ImageDescription::srgb());
+        for layer in layers {
+            let main = layer.kind == LayerKind::Main;
+            let texture_id = match self.import(renderer, layer) {
+                Ok(texture_id) => texture_id,
+                Err(e) => {
+                    error!("Failed to import surface buffer: {}", e);
+                    continue;
+                }
+            };
+            let rect = to_physical_rect(Rectangle::new(origin + layer.location, layer.size), scale);
+            let size = (rect.size.w as u32, rect.size.h as u32);
+            let damage = match self.content_changed(layer) {
+                true => vec![(0, 0, size.0, size.1)],
+                false => Vec::new(),
+            };
+            self.elements.push(RenderElement::ClientSurface {
+                texture_id,
+                position: (rect.loc.x, rect.loc.y),
+                size,
+                source: layer.source_uv().map(|uv| uv as f32),
+                damage,
+                image_description: layer.image_description.clone().unwrap_or(srgb.clone()),
+                backdrop_blur: backdrop_blur.filter(|_| main),
+                shape: match main {
+                    true => shape,
+                    false => WindowShape::default(),
+                },
+                opaque_region: Vec::new(),
+            });
+        }
+    }
+}
+
+/// Build the frame of `output`, the renderer's output `id`, with `cursor` and the statistics
+/// HUD if `stats_hud` is set, importing the buffers it shows
+///
+/// `drawn` holds the commits the output's last frame was drawn with, and is updated for
+/// this one.
+#[allow(clippy::too_many_arguments)]
+pub fn frame_elements(
+    state: &StarforgeState,
+    renderer: &StarforgeRenderer,
+    output: &Output,
+    id: OutputId,
+    style: &WindowStyle,
+    stats_hud: bool,
+    cursor: Option<&CursorImage>,
+    drawn: &mut DrawnCommits,
+) -> Frame {
+    let mut frame = Frame {
+        elements: Vec::new(),
+        cursor: None,
+        output: id,
+        shm_textures: Vec::new(),
+        last_commits: std::mem::take(&mut drawn.commits),
+        commits: HashMap::new(),
+    };
+    let scale = output.current_scale().fractional_scale();
+
+    let visible = state.visible_surfaces(output);
+    let mut title_bars = state.title_bars(output).into_iter();
+    for window in state.window_surfaces() {
+        if !visible.contains(&window) {
+            continue;
+        }
+        let snapshot = WindowSnapshot::capture_with_popups(&window, &state.popups);
+        let decorated = state.is_server_decorated(&window);
+        let (shape, backdrop_blur) = match decorated {
+            true => {
+                let opaque = snapshot
+                    .layers
+                    .iter()
+                    .find(|layer| layer.kind == LayerKind::Main)
+                    .is_some_and(|layer| is_opaque(&window, layer.size));
+                let backdrop_blur = style.backdrop_blur.filter(|_| !opaque);
+                (style.shape(scale), backdrop_blur)
+            }
+            false => (WindowShape::default(), None),
+        };
+        // Title bars go over the shadow of their window and below its popups
+        let popups = snapshot
+            .layers
+            .iter()
+            .position(|layer| layer.kind == LayerKind::Popup)
+            .unwrap_or(snapshot.layers.len());
+        let (layers, popups) = snapshot.layers.split_at(popups);
+        let location = state.window_surface_location(&window);
+        frame.push_layers(renderer, layers, location, scale, shape, backdrop_blur);
+        // Title bars come in the order of their windows
+        if decorated && let Some(title_bar) = title_bars.next() {
+            let rect = to_physical_rect(title_bar.geometry, scale);
+            frame.elements.push(RenderElement::TitleBar {
+                key: title_bar.key,
+                rect: (
+                    rect.loc.x,
+                    rect.loc.y,
+                    rect.size.w as u32,
+                    rect.size.h as u32,
+                ),
+            });
+        }
+        frame.push_layers(
+            renderer,
+            popups,
+            location,
+            scale,
+            WindowShape::default(),
+            None,
+        );
+    }
+    // X11 menus and tooltips place themselves over everything else
+    #[cfg(feature = "xwayland")]
+    for surface in state.xwayland_state.override_redirect_surfaces() {
+        let Some(geometry) = state.xwayland_state.override_redirect_geometry(&surface) else {
+            continue;
+        };
+        let snapshot = WindowSnapshot::capture(&surface);
+        frame.push_layers(
+            renderer,
+            &snapshot.layers,
+            geometry.loc,
+            scale,
+            WindowShape::default(),
+            None,
+        );
+    }
+
+    if stats_hud {
+        frame.elements.push(RenderElement::StatsHud {
+            position: (HUD_MARGIN, HUD_MARGIN),
+        });
+    }
+
+    let pointer = state
+        .seat
+        .get_pointer()
+        .map_or_else(Default::default, |pointer| pointer.current_location());
+    frame.cursor = match cursor {
+        Some(CursorImage::Named(cursor)) => {
+            let position = pointer.to_physical(scale).to_i32_round() - cursor.hotspot;
+            Some((
+                CursorTexture::Named(cursor.key),
+                Rectangle::new(position, cursor.size),
+            ))
+        }
+        // Captures draw a single cursor texture, so subsurfaces of cursor surfaces are left
+        // out
+        Some(CursorImage::Surface { surface, hotspot }) => WindowSnapshot::capture(surface)
+            .layers
+            .iter()
+            .find(|layer| layer.kind == LayerKind::Main)
+            .and_then(|layer| match frame.import(renderer, layer) {
+                Ok(texture_id) => {
+                    let origin = pointer.to_i32_round() - *hotspot;
+                    let rect = Rectangle::new(origin + layer.location, layer.size);
+                    Some((
+                        CursorTexture::Client(texture_id),
+                        to_physical_rect(rect, scale),
+                    ))
+                }
+                Err(e) => {
+                    error!("Failed to import cursor buffer: {}", e);
+                    None
+                }
+            }),
+        None => None,
+    };
+    drawn.commits = std::mem::take(&mut frame.commits);
+    frame
+}
@@ -1,26 +1,83 @@
+use crate::capture::{ToplevelTargets, fill_captures};
+use crate::render::{DrawnCommits, WindowStyle, frame_elements, window_border};
 use smithay::{
     backend::{
-        renderer::gles::GlesRenderer,
-        winit::{self, WinitEvent},
+        allocator::Fourcc,
+        drm::{DrmDeviceFd, DrmNode, NodeType},
+        input::{
+            AbsolutePositionEvent, ButtonState, Event, InputEvent, KeyState, KeyboardKeyEvent,
+            PointerButtonEvent,
+        },
+        renderer::{Frame as _, ImportMem, Renderer, gles::GlesRenderer},
+        winit::{self, WinitEvent, WinitGraphicsBackend},
     },
+    input::keyboard::{FilterResult, Keysym, ModifiersState},
     output::{Mode, Output, PhysicalProperties, Subpixel},
-    reexports::calloop::EventLoop,
-    utils::Transform,
+    reexports::{
+        calloop::{
+            EventLoop,
+            timer::{TimeoutAction, Timer},
+        },
+        rustix::fs::{Dev, Mode as FileMode, OFlags, open},
+        wayland_protocols::wp::presentation_time::server::wp_presentation_feedback,
+    },
+    utils::{DeviceFd, Logical, Point, Rectangle, SERIAL_COUNTER, Transform},
+};
+use starforge_config::{
+    AccessibilityConfig, ColorFilterConfig, DecorationConfig, MagnifierFollowConfig,
+    NightLightConfig, StarforgeConfig, ToneMappingOperator, WindowShapeConfig,
 };
-use starforge_core::{StarforgeResult, StarforgeState};
-use starforge_render::StarforgeRenderer;
+use starforge_core::{
+    StarforgeResult, StarforgeState,
+    cursor::CursorImage,
+    handlers::output_logical_size,
+    night_light::NightLightSchedule,
+    protocols::{color_management::ImageDescription, image_capture::BufferConstraints},
+    scale::to_physical_rect,
+};
+use starforge_render::{
+    ColorAdjustment, ColorBlindness, ColorFilter, GAMMA_LUT_SIZE, MagnifierFollow,
+    MagnifierSettings, NEUTRAL_TEMPERATURE, OutputId, PresentFlags, StarforgeRenderer,
+    TitleBarStyle, ToneMapping,
+};
+use std::cell::RefCell;
+use std::error::Error;
+use std::rc::Rc;
+use std::time::{Duration, SystemTime};
+use tracing::{error, warn};
+
+/// Renderer id of the winit window's output
+const WINIT_OUTPUT: OutputId = OutputId(0);
+
+/// Linux input code of the left mouse button
+const BTN_LEFT: u32 = 0x110;
+
+/// How often the night light temperature follows the sun
+const NIGHT_LIGHT_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Accessibility shortcuts, pressed with Super and Alt
+#[derive(Clone, Copy, Debug)]
+enum Shortcut {
+    /// C switches to the next colour filter
+    NextColorFilter,
+    /// M turns the magnifier on or off
+    ToggleMagnifier,
+}
 
 pub fn init_winit(
-    event_loop: &mut EventLoop<StarforgeState>,
+    event_loop: &mut EventLoop<'static, StarforgeState>,
     state: &mut StarforgeState,
+    config: &StarforgeConfig,
 ) -> StarforgeResult<()> {
     let display_handle = &mut state.dh;
 
     // Initialize Winit backend for a test window
-    let (mut backend, winit) = winit::init::<GlesRenderer>()?;
+    let (backend, winit) = winit::init::<GlesRenderer>()?;
+    // Shared with the frame scheduler, which asks for redraws
+    let backend = Rc::new(RefCell::new(backend));
 
     let mode = Mode {
-        size: backend.window_size(),
+        size: backend.borrow().window_size(),
         refresh: 60_000,
     };
 
@@ -42,30 +99,324 @@ pub fn init_winit(
     );
     output.set_preferred(mode);
 
-    let renderer = StarforgeRenderer::new()?;
+    // Frames are rendered on the window's redraw events, requested once they are due
+    let loop_handle = event_loop.handle();
+    let redraw_backend = backend.clone();
+    state
+        .frame_scheduling_state
+        .set_redraw_callback(Box::new(move |_, delay| {
+            let backend = redraw_backend.clone();
+            let timer = loop_handle.insert_source(Timer::from_duration(delay), move |_, _, _| {
+                backend.borrow().window().request_redraw();
+                TimeoutAction::Drop
+            });
+            if let Err(e) = timer {
+                error!("Failed to schedule a frame: {}", e);
+            }
+        }));
+    state
+        .frame_scheduling_state
+        .set_safety_margin(Duration::from_micros(
+            config.rendering.frame_safety_margin_us,
+        ));
+    state.add_output(output.clone());
+    state.set_output_scale(&output, config.output(&output.name()).scale);
+    state
+        .color_management_state
+        .set_output_image_description(&output, ImageDescription::srgb());
+
+    // Shared with the dma-buf importer, which runs while clients are dispatched
+    let renderer = Rc::new(RefCell::new(StarforgeRenderer::new()?));
+    // Frames are rendered offscreen and drawn into the window through its GL context
+    renderer
+        .borrow()
+        .register_offscreen_output(WINIT_OUTPUT, mode.size)?;
+    if config.rendering.enable_hdr {
+        warn!("HDR output needs a Vulkan swapchain, the winit window is drawn in SDR");
+    }
+    renderer
+        .borrow()
+        .set_tone_mapping(WINIT_OUTPUT, tone_mapping(config.rendering.tone_mapping))?;
+    let (filter, magnifier) = accessibility_settings(&config.accessibility);
+    renderer
+        .borrow()
+        .set_accessibility_defaults(filter, magnifier);
+    renderer.borrow().set_title_bar_style(title_bar_style(
+        &config.decorations,
+        &config.rendering.windows,
+    ));
+    state
+        .gamma_control_state
+        .set_gamma_size(&output, Some(GAMMA_LUT_SIZE as u32));
 
+    let night_light = config.night_light;
+    let night_light_renderer = renderer.clone();
+    let night_light_output = output.clone();
     event_loop
         .handle()
-        .insert_source(winit, move |event, _, state| {
-            let display = &mut state.dh;
+        .insert_source(Timer::immediate(), move |_, _, state| {
+            let adjustment = night_light_adjustment(&night_light, SystemTime::now());
+            night_light_renderer
+                .borrow()
+                .set_color_adjustment(WINIT_OUTPUT, adjustment);
+            state.frame_scheduling_state.damage(&night_light_output);
+            TimeoutAction::ToDuration(NIGHT_LIGHT_INTERVAL)
+        })?;
+
+    if let Some(node) = renderer.borrow().drm_node() {
+        let formats = renderer.borrow().dmabuf_formats();
+        let importer = renderer.clone();
+        state.enable_dmabuf(
+            node,
+            formats,
+            None,
+            Box::new(move |dmabuf| importer.borrow().import_dma_buf(dmabuf).map(|_| ())),
+        )?;
+        match open_render_node(node) {
+            Ok(device) => {
+                state.enable_drm_syncobj(device);
+            }
+            Err(e) => warn!(
+                "Failed to open the render node, explicit sync disabled: {}",
+                e
+            ),
+        }
+        state
+            .image_copy_capture_state
+            .set_buffer_constraints(BufferConstraints {
+                dmabuf: Some((node, renderer.borrow().capture_dmabuf_formats())),
+                ..Default::default()
+            });
+    }
+
+    // Debug trigger: pretend the GPU was lost after this many frames
+    let simulate_device_loss_at: Option<u64> = std::env::var("STARFORGE_SIMULATE_DEVICE_LOSS")
+        .ok()
+        .and_then(|frame| frame.parse().ok());
+    let mut frame: u64 = 0;
+    let allow_tearing = config.rendering.allow_tearing;
+    let window_style = WindowStyle::new(&config.rendering);
+    let show_stats_hud = config.rendering.show_stats_hud;
+    let contrast = config.accessibility.contrast;
+    // Where the pointer last was in the window, for button presses
+    let mut pointer_location = Point::<f64, Logical>::default();
+    let mut toplevel_targets = ToplevelTargets::default();
+    let mut drawn_commits = DrawnCommits::default();
 
+    event_loop
+        .handle()
+        .insert_source(winit, move |event, _, state| {
             match event {
-                WinitEvent::Resized { size, .. } => output.change_current_state(
-                    Some(Mode {
-                        size,
-                        refresh: 60_000,
-                    }),
-                    None,
-                    None,
-                    None,
-                ),
-                WinitEvent::Input(event) => {}
+                WinitEvent::Resized { size, .. } => {
+                    output.change_current_state(
+                        Some(Mode {
+                            size,
+                            refresh: 60_000,
+                        }),
+                        None,
+                        None,
+                        None,
+                    );
+                    if let Err(e) = renderer
+                        .borrow()
+                        .register_offscreen_output(WINIT_OUTPUT, size)
+                    {
+                        error!("Failed to resize the output image: {}", e);
+                    }
+                    state.image_copy_capture_state.output_changed(&output);
+                    state.frame_scheduling_state.damage(&output);
+                }
+                WinitEvent::Input(event) => match event {
+                    // Clients don't get pointer input yet, only decorations react to it
+                    InputEvent::PointerMotionAbsolute { event } => {
+                        let Some(size) = output_logical_size(&output) else {
+                            return;
+                        };
+                        pointer_location = event.position_transformed(size);
+                        state.decoration_pointer_motion(pointer_location);
+                    }
+                    InputEvent::PointerButton { event } if event.button_code() == BTN_LEFT => {
+                        let pressed = event.state() == ButtonState::Pressed;
+                        state.decoration_pointer_button(pointer_location, pressed);
+                    }
+                    InputEvent::Keyboard { event } => {
+                        let Some(keyboard) = state.seat.get_keyboard() else {
+                            return;
+                        };
+                        let pressed = event.state() == KeyState::Pressed;
+                        let shortcut = keyboard.input(
+                            state,
+                            event.key_code(),
+                            event.state(),
+                            SERIAL_COUNTER.next_serial(),
+                            event.time_msec(),
+                            |_, modifiers, keysym| match shortcut(
+                                modifiers,
+                                keysym.modified_sym(),
+                                pressed,
+                            ) {
+                                Some(shortcut) => FilterResult::Intercept(shortcut),
+                                None => FilterResult::Forward,
+                            },
+                        );
+                        let renderer = renderer.borrow();
+                        match shortcut {
+                            Some(Shortcut::NextColorFilter) => {
+                                let filter = renderer.color_filter(WINIT_OUTPUT);
+                                renderer.set_color_filter(
+                                    WINIT_OUTPUT,
+                                    next_color_filter(filter, contrast),
+                                );
+                            }
+                            Some(Shortcut::ToggleMagnifier) => {
+                                let mut settings = renderer.magnifier(WINIT_OUTPUT).settings();
+                                settings.enabled = !settings.enabled;
+                                renderer.set_magnifier(WINIT_OUTPUT, settings);
+                            }
+                            None => return,
+                        }
+                        state.frame_scheduling_state.damage(&output);
+                    }
+                    _ => {}
+                },
                 WinitEvent::Redraw => {
-                    backend.bind().unwrap();
+                    // The window system asks for redraws too, e.g. when the window is
+                    // exposed; those wait for the next scheduled frame
+                    let Some(presentation) = state.frame_scheduling_state.frame_due(&output) else {
+                        state.frame_scheduling_state.damage(&output);
+                        return;
+                    };
+                    let started = state.frame_scheduling_state.now();
+                    state.frame_started(presentation);
+
+                    frame += 1;
+                    let mut renderer = renderer.borrow_mut();
+                    if simulate_device_loss_at == Some(frame) {
+                        renderer.simulate_device_loss();
+                    }
+                    if renderer.is_device_lost() {
+                        // Clients keep running; only GPU state is rebuilt
+                        toplevel_targets.clear();
+                        if let Err(e) = renderer.recover_from_device_loss() {
+                            error!("Failed to recover from device loss: {}", e);
+                        }
+                    }
+
+                    renderer.reload_effect_shaders();
+
+                    // Tearing lasts as long as a fullscreen window asks for it. The window
+                    // is drawn through GL, so frames only stop waiting for the vblank in
+                    // the scheduler, the renderer has no present mode to switch
+                    let tearing = allow_tearing && state.tearing_surface(&output).is_some();
+                    state.frame_scheduling_state.set_tearing(&output, tearing);
+                    let animating = state.animation_state.advance_to(presentation);
+
+                    for (_, ramp) in state.gamma_control_state.take_gamma_changes() {
+                        renderer.set_gamma_ramp(WINIT_OUTPUT, ramp.as_ref());
+                    }
+
+                    let scale = output.current_scale().fractional_scale();
+                    let pointer = state.seat.get_pointer().map_or_else(Default::default, |p| {
+                        p.current_location().to_physical(scale)
+                    });
+                    let focus = state
+                        .focus_geometry(&output)
+                        .map(|geometry| to_physical_rect(geometry, scale));
+                    let magnifying = renderer.update_magnifier(
+                        WINIT_OUTPUT,
+                        state.animation_state.now(),
+                        pointer,
+                        focus,
+                    );
 
-                    backend.submit(None).unwrap();
-                    backend.window().request_redraw();
-                    let _ = display.flush_clients();
+                    // Themed cursor frames are uploaded once, client cursors come in
+                    // through their surface's buffer
+                    let now = state.animation_state.now();
+                    let cursor = state.cursor_state.image(now);
+                    if let Some(CursorImage::Named(frame)) = &cursor
+                        && let Err(e) =
+                            renderer.upload_cursor_frame(frame.key, frame.size, &frame.pixels)
+                    {
+                        error!("Failed to upload cursor: {}", e);
+                    }
+                    // Title bars are only rasterized again when they changed
+                    for title_bar in state.title_bars(&output) {
+                        let size = to_physical_rect(title_bar.geometry, scale).size;
+                        if let Err(e) = renderer.upload_title_bar(&title_bar, size, scale) {
+                            error!("Failed to upload title bar: {}", e);
+                        }
+                    }
+
+                    let scene = frame_elements(
+                        state,
+                        &renderer,
+                        &output,
+                        WINIT_OUTPUT,
+                        &window_style,
+                        show_stats_hud,
+                        cursor.as_ref(),
+                        &mut drawn_commits,
+                    );
+                    // Whatever is left of a frame that failed may have changed anywhere
+                    let damage = renderer
+                        .render_frame(WINIT_OUTPUT, &scene.elements)
+                        .unwrap_or_else(|e| {
+                            error!("Failed to render frame: {}", e);
+                            output.current_mode().map_or_else(Vec::new, |mode| {
+                                vec![Rectangle::from_size((mode.size.w, mode.size.h).into())]
+                            })
+                        });
+                    let captures = fill_captures(
+                        state,
+                        &renderer,
+                        &mut toplevel_targets,
+                        &output,
+                        WINIT_OUTPUT,
+                        &damage,
+                        scene.cursor,
+                    );
+                    if let Some((texture, rect)) = scene.cursor
+                        && let Err(e) = renderer.draw_output_cursor(WINIT_OUTPUT, texture, rect)
+                    {
+                        error!("Failed to draw cursor: {}", e);
+                    }
+                    scene.release(&renderer);
+                    if let Err(e) = renderer.retire_frames() {
+                        error!("Failed to release client buffers: {}", e);
+                    }
+                    renderer.end_title_bar_frame();
+                    {
+                        let mut backend = backend.borrow_mut();
+                        let presented = renderer
+                            .read_output_pixels(WINIT_OUTPUT)
+                            .map_err(Into::into)
+                            .and_then(|pixels| present_pixels(&mut backend, &pixels));
+                        if let Err(e) = presented {
+                            error!("Failed to present frame: {}", e);
+                        }
+                        backend.submit(None).unwrap();
+                    }
+
+                    let submitted = state.frame_scheduling_state.now();
+                    state.frame_submitted(&output, submitted - started);
+                    // Winit reports no presentation feedback, frames count as shown once
+                    // they are submitted
+                    let feedback =
+                        renderer.frame_presented(WINIT_OUTPUT, submitted, submitted, None);
+                    state.frame_presented(
+                        &output,
+                        feedback.time,
+                        feedback.refresh,
+                        feedback.sequence,
+                        presentation_kind(feedback.flags),
+                    );
+                    for capture in captures {
+                        capture.succeed(feedback.time);
+                    }
+                    if animating || magnifying || state.cursor_state.is_animated() {
+                        state.frame_scheduling_state.damage(&output);
+                    }
+                    let _ = state.dh.flush_clients();
                 }
                 WinitEvent::CloseRequested => state.loop_signal.stop(),
                 _ => {}
@@ -74,3 +425,149 @@ pub fn init_winit(
 
     Ok(())
 }
+
+/// Open the render node of the device `node` belongs to, to import client timelines on
+fn open_render_node(node: Dev) -> Result<DrmDeviceFd, Box<dyn Error>> {
+    let node = DrmNode::from_dev_id(node)?;
+    let node = match node.node_with_type(NodeType::Render) {
+        Some(render) => render?,
+        None => node,
+    };
+    let path = node.dev_path().ok_or("DRM node has no device file")?;
+    let fd = open(path, OFlags::RDWR | OFlags::CLOEXEC, FileMode::empty())?;
+    Ok(DrmDeviceFd::new(DeviceFd::from(fd)))
+}
+
+/// Draw a frame read back from the output image into the window
+fn present_pixels(
+    backend: &mut WinitGraphicsBackend<GlesRenderer>,
+    pixels: &[u8],
+) -> Result<(), Box<dyn Error>> {
+    let size = backend.window_size();
+    backend.bind()?;
+    let renderer = backend.renderer();
+    // B8G8R8A8 in memory is Argb8888 in DRM's little-endian naming
+    let texture =
+        renderer.import_memory(pixels, Fourcc::Argb8888, (size.w, size.h).into(), false)?;
+    let mut frame = renderer.render(size, Transform::Flipped180)?;
+    frame.render_texture_at(
+        &texture,
+        Point::default(),
+        1,
+        1.0,
+        Transform::Normal,
+        &[Rectangle::from_size(size)],
+        &[],
+        1.0,
+    )?;
+    // Swapping the window's buffers waits for the rendering, so the sync point isn't needed
+    let _ = frame.finish()?;
+    Ok(())
+}
+
+/// Renderer colour filter and magnifier for the accessibility config
+fn accessibility_settings(config: &AccessibilityConfig) -> (ColorFilter, MagnifierSettings) {
+    let filter = match config.color_filter {
+        ColorFilterConfig::None => ColorFilter::None,
+        ColorFilterConfig::Greyscale => ColorFilter::Greyscale,
+        ColorFilterConfig::Invert => ColorFilter::Invert,
+        ColorFilterConfig::Contrast => ColorFilter::Contrast(config.contrast),
+        ColorFilterConfig::Protanopia => ColorFilter::Daltonize(ColorBlindness::Protanopia),
+        ColorFilterConfig::Deuteranopia => ColorFilter::Daltonize(ColorBlindness::Deuteranopia),
+        ColorFilterConfig::Tritanopia => ColorFilter::Daltonize(ColorBlindness::Tritanopia),
+    };
+    let magnifier = MagnifierSettings {
+        enabled: config.magnifier.enabled,
+        zoom: config.magnifier.zoom,
+        follow: match config.magnifier.follow {
+            MagnifierFollowConfig::Pointer => MagnifierFollow::Pointer,
+            MagnifierFollowConfig::Focus => MagnifierFollow::Focus,
+        },
+        smoothing: Duration::from_millis(config.magnifier.smoothing_ms),
+    };
+    (filter, magnifier)
+}
+
+/// The accessibility shortcut `keysym` stands for with `modifiers` held, if it was pressed
+fn shortcut(modifiers: &ModifiersState, keysym: Keysym, pressed: bool) -> Option<Shortcut> {
+    if !pressed || !modifiers.logo || !modifiers.alt {
+        return None;
+    }
+    match keysym {
+        Keysym::c => Some(Shortcut::NextColorFilter),
+        Keysym::m => Some(Shortcut::ToggleMagnifier),
+        _ => None,
+    }
+}
+
+/// The colour filter the shortcut switches to after `filter`, boosting contrast by
+/// `contrast`
+fn next_color_filter(filter: ColorFilter, contrast: f32) -> ColorFilter {
+    match filter {
+        ColorFilter::None => ColorFilter::Greyscale,
+        ColorFilter::Greyscale => ColorFilter::Invert,
+        ColorFilter::Invert => ColorFilter::Contrast(contrast),
+        ColorFilter::Contrast(_) => ColorFilter::Daltonize(ColorBlindness::Protanopia),
+        ColorFilter::Daltonize(ColorBlindness::Protanopia) => {
+            ColorFilter::Daltonize(ColorBlindness::Deuteranopia)
+        }
+        ColorFilter::Daltonize(ColorBlindness::Deuteranopia) => {
+            ColorFilter::Daltonize(ColorBlindness::Tritanopia)
+        }
+        ColorFilter::Daltonize(ColorBlindness::Tritanopia) => ColorFilter::None,
+    }
+}
+
+/// Renderer title bar style for the decoration config, rounded and bordered like windows
+fn title_bar_style(config: &DecorationConfig, windows: &WindowShapeConfig) -> TitleBarStyle {
+    TitleBarStyle {
+        background: config.title_bar_color,
+        foreground: config.title_text_color,
+        corner_radius: windows.corner_radius,
+        border: window_border(windows),
+        ..Default::default()
+    }
+}
+
+/// Renderer tone mapping operator for the configured one
+fn tone_mapping(operator: ToneMappingOperator) -> ToneMapping {
+    match operator {
+        ToneMappingOperator::Clip => ToneMapping::Clip,
+        ToneMappingOperator::Reinhard => ToneMapping::Reinhard,
+        ToneMappingOperator::Bt2390 => ToneMapping::Bt2390,
+        ToneMappingOperator::Hable => ToneMapping::Hable,
+    }
+}
+
+/// Presentation feedback flags for how the renderer presented a frame
+fn presentation_kind(flags: PresentFlags) -> wp_presentation_feedback::Kind {
+    let mut kind = wp_presentation_feedback::Kind::empty();
+    kind.set(wp_presentation_feedback::Kind::Vsync, flags.vsync);
+    kind.set(wp_presentation_feedback::Kind::HwClock, flags.hw_clock);
+    kind.set(
+        wp_presentation_feedback::Kind::HwCompletion,
+        flags.hw_completion,
+    );
+    kind.set(wp_presentation_feedback::Kind::ZeroCopy, flags.zero_copy);
+    kind
+}
+
+/// Colour adjustment of outputs at `time` for the night light config
+fn night_light_adjustment(config: &NightLightConfig, time: SystemTime) -> ColorAdjustment {
+    let temperature = if config.enabled {
+        let schedule = NightLightSchedule {
+            day_temperature: config.day_temperature,
+            night_temperature: config.night_temperature,
+            location: config.latitude.zip(config.longitude),
+            transition: Duration::from_secs(config.transition_minutes as u64 * 60),
+        };
+        schedule.temperature(time)
+    } else {
+        NEUTRAL_TEMPERATURE
+    };
+    ColorAdjustment {
+        temperature,
+        gamma: config.gamma,
+        brightness: config.brightness,
+    }
+}
@@ -3,6 +3,7 @@
 //! This library provides a modular, extensible configuration system.
 
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::fs;
 use std::path::Path;
 use thiserror::Error;
@@ -15,6 +16,30 @@ pub struct StarforgeConfig {
 
     /// Rendering configuration
     pub rendering: RenderConfig,
+
+    /// Pointer cursor theme
+    #[serde(default)]
+    pub cursor: CursorConfig,
+
+    /// Colour filters and screen magnification
+    #[serde(default)]
+    pub accessibility: AccessibilityConfig,
+
+    /// Colour temperature, gamma and brightness of outputs
+    #[serde(default)]
+    pub night_light: NightLightConfig,
+
+    /// Hosting X11 applications
+    #[serde(default)]
+    pub xwayland: XWaylandConfig,
+
+    /// Window decorations drawn by the compositor
+    #[serde(default)]
+    pub decorations: DecorationConfig,
+
+    /// Per-output settings, by output name
+    #[serde(default)]
+    pub outputs: HashMap<String, OutputConfig>,
 }
 
 /// General configuration options
@@ -33,6 +58,269 @@ fn default_app_name() -> String {
     "Starforge Compositor".to_string()
 }
 
+/// Pointer cursor configuration
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct CursorConfig {
+    /// XCursor theme name, `XCURSOR_THEME` is used when unset
+    #[serde(default)]
+    pub theme: Option<String>,
+
+    /// Nominal cursor size in pixels, `XCURSOR_SIZE` is used when unset
+    #[serde(default)]
+    pub size: Option<u32>,
+}
+
+/// Settings of a single output
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct OutputConfig {
+    /// Scale from logical to physical pixels, e.g. 1.25 or 1.5 for HiDPI displays.
+    /// Rounded to multiples of 1/120.
+    #[serde(default = "default_output_scale")]
+    pub scale: f64,
+}
+
+impl Default for OutputConfig {
+    fn default() -> Self {
+        Self {
+            scale: default_output_scale(),
+        }
+    }
+}
+
+fn default_output_scale() -> f64 {
+    1.0
+}
+
+/// XWayland configuration, only used when the compositor is built with X11 support
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct XWaylandConfig {
+    /// Let X11 applications connect, Xwayland is started when the first one does
+    #[serde(default)]
+    pub enabled: bool,
+}
+
+/// Window decoration configuration
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct DecorationConfig {
+    /// Who draws the title bars of windows
+    #[serde(default)]
+    pub policy: DecorationPolicyConfig,
+
+    /// Title bar color (RGBA)
+    #[serde(default = "default_title_bar_color")]
+    pub title_bar_color: [f32; 4],
+
+    /// Title and button icon color (RGBA)
+    #[serde(default = "default_title_text_color")]
+    pub title_text_color: [f32; 4],
+}
+
+impl Default for DecorationConfig {
+    fn default() -> Self {
+        Self {
+            policy: DecorationPolicyConfig::default(),
+            title_bar_color: default_title_bar_color(),
+            title_text_color: default_title_text_color(),
+        }
+    }
+}
+
+/// Who decorates windows of clients that support server-side decorations
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DecorationPolicyConfig {
+    /// The compositor, unless a client asks to draw its own
+    #[default]
+    PreferServer,
+    /// The client, unless it asks for server-side decorations
+    PreferClient,
+    /// Always the compositor
+    Force,
+}
+
+fn default_title_bar_color() -> [f32; 4] {
+    [0.16, 0.16, 0.2, 1.0]
+}
+
+fn default_title_text_color() -> [f32; 4] {
+    [0.9, 0.9, 0.9, 1.0]
+}
+
+/// Night light configuration, applied to every output
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct NightLightConfig {
+    /// Warm up the screen at night
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// Colour temperature in Kelvin while the sun is up
+    #[serde(default = "default_day_temperature")]
+    pub day_temperature: u32,
+
+    /// Colour temperature in Kelvin while the sun is down
+    #[serde(default = "default_night_temperature")]
+    pub night_temperature: u32,
+
+    /// Latitude in degrees, north positive. Without a location it is always night.
+    #[serde(default)]
+    pub latitude: Option<f64>,
+
+    /// Longitude in degrees, east positive
+    #[serde(default)]
+    pub longitude: Option<f64>,
+
+    /// Length of the transition around sunrise and sunset, in minutes
+    #[serde(default = "default_transition_minutes")]
+    pub transition_minutes: u32,
+
+    /// Gamma applied to every output, above 1 brightens mid-tones
+    #[serde(default = "default_gamma")]
+    pub gamma: f32,
+
+    /// Brightness factor applied to every output
+    #[serde(default = "default_brightness")]
+    pub brightness: f32,
+}
+
+impl Default for NightLightConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            day_temperature: default_day_temperature(),
+            night_temperature: default_night_temperature(),
+            latitude: None,
+            longitude: None,
+            transition_minutes: default_transition_minutes(),
+            gamma: default_gamma(),
+            brightness: default_brightness(),
+        }
+    }
+}
+
+fn default_day_temperature() -> u32 {
+    6500
+}
+
+fn default_night_temperature() -> u32 {
+    4000
+}
+
+fn default_transition_minutes() -> u32 {
+    45
+}
+
+fn default_gamma() -> f32 {
+    1.0
+}
+
+fn default_brightness() -> f32 {
+    1.0
+}
+
+/// Accessibility configuration, applied to every output
+///
+/// Super+Alt+C switches between the colour filters and Super+Alt+M turns the magnifier
+/// on and off at runtime.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct AccessibilityConfig {
+    /// Colour filter applied to everything on screen
+    #[serde(default)]
+    pub color_filter: ColorFilterConfig,
+
+    /// Contrast factor of the `contrast` filter, above 1 increases contrast
+    #[serde(default = "default_contrast")]
+    pub contrast: f32,
+
+    /// Full-screen magnifier
+    #[serde(default)]
+    pub magnifier: MagnifierConfig,
+}
+
+impl Default for AccessibilityConfig {
+    fn default() -> Self {
+        Self {
+            color_filter: ColorFilterConfig::default(),
+            contrast: default_contrast(),
+            magnifier: MagnifierConfig::default(),
+        }
+    }
+}
+
+/// Colour filters for low vision and colour blindness
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ColorFilterConfig {
+    /// Show colours unchanged
+    #[default]
+    None,
+    /// Show everything in shades of grey
+    Greyscale,
+    /// Invert all colours
+    Invert,
+    /// Boost contrast by `contrast`
+    Contrast,
+    /// Correct colours for missing red cones
+    Protanopia,
+    /// Correct colours for missing green cones
+    Deuteranopia,
+    /// Correct colours for missing blue cones
+    Tritanopia,
+}
+
+/// Full-screen magnifier configuration
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct MagnifierConfig {
+    /// Magnify the screen at startup
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// Magnification factor, at least 1
+    #[serde(default = "default_magnifier_zoom")]
+    pub zoom: f32,
+
+    /// What the magnified view follows
+    #[serde(default)]
+    pub follow: MagnifierFollowConfig,
+
+    /// Time constant of zooming and panning in milliseconds, 0 disables smoothing
+    #[serde(default = "default_magnifier_smoothing_ms")]
+    pub smoothing_ms: u64,
+}
+
+impl Default for MagnifierConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            zoom: default_magnifier_zoom(),
+            follow: MagnifierFollowConfig::default(),
+            smoothing_ms: default_magnifier_smoothing_ms(),
+        }
+    }
+}
+
+/// What the magnified view keeps centred
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MagnifierFollowConfig {
+    /// The pointer
+    #[default]
+    Pointer,
+    /// The keyboard focus, falling back to the pointer
+    Focus,
+}
+
+fn default_contrast() -> f32 {
+    1.5
+}
+
+fn default_magnifier_zoom() -> f32 {
+    2.0
+}
+
+fn default_magnifier_smoothing_ms() -> u64 {
+    100
+}
+
 /// Rendering configuration
 #[derive(Debug, Serialize, Deserialize)]
 pub struct RenderConfig {
@@ -43,6 +331,171 @@ pub struct RenderConfig {
     /// Background color (RGBA)
     #[serde(default = "default_background_color")]
     pub background_color: [f32; 4],
+
+    /// Use HDR output formats when the display supports them
+    #[serde(default)]
+    pub enable_hdr: bool,
+
+    /// Tone mapping operator for content brighter than the output
+    #[serde(default)]
+    pub tone_mapping: ToneMappingOperator,
+
+    /// Backdrop blur behind translucent windows
+    #[serde(default)]
+    pub blur: BlurConfig,
+
+    /// Rounded corners, borders and drop shadows of windows
+    #[serde(default)]
+    pub windows: WindowShapeConfig,
+
+    /// Show frame timing statistics on every output
+    #[serde(default)]
+    pub show_stats_hud: bool,
+
+    /// Extra time, in microseconds, a frame starts rendering before it is needed on top
+    /// of the longest recent render time
+    #[serde(default = "default_frame_safety_margin_us")]
+    pub frame_safety_margin_us: u64,
+
+    /// Let a fullscreen window that asks for it present without waiting for the vblank,
+    /// trading tearing for latency
+    #[serde(default)]
+    pub allow_tearing: bool,
+}
+
+/// Dual-Kawase backdrop blur parameters
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct BlurConfig {
+    /// Blur behind decorated windows that aren't opaque
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// Distance between samples of each pass, in texels
+    #[serde(default = "default_blur_radius")]
+    pub radius: f32,
+
+    /// Number of downsample passes, at most 6
+    #[serde(default = "default_blur_passes")]
+    pub passes: u32,
+}
+
+impl Default for BlurConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            radius: default_blur_radius(),
+            passes: default_blur_passes(),
+        }
+    }
+}
+
+fn default_blur_radius() -> f32 {
+    4.0
+}
+
+fn default_blur_passes() -> u32 {
+    3
+}
+
+/// Default shape of window decorations drawn by the compositor
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct WindowShapeConfig {
+    /// Corner radius in pixels
+    #[serde(default = "default_corner_radius")]
+    pub corner_radius: f32,
+
+    /// Border width in pixels, 0 disables borders
+    #[serde(default)]
+    pub border_width: f32,
+
+    /// Border color (RGBA)
+    #[serde(default = "default_border_color")]
+    pub border_color: [f32; 4],
+
+    /// Drop shadow, `None` disables shadows
+    #[serde(default = "default_shadow")]
+    pub shadow: Option<ShadowConfig>,
+}
+
+impl Default for WindowShapeConfig {
+    fn default() -> Self {
+        Self {
+            corner_radius: default_corner_radius(),
+            border_width: 0.0,
+            border_color: default_border_color(),
+            shadow: default_shadow(),
+        }
+    }
+}
+
+/// Drop shadow below windows
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ShadowConfig {
+    /// Offset from the window in pixels
+    #[serde(default = "default_shadow_offset")]
+    pub offset: [f32; 2],
+
+    /// Blur radius in pixels
+    #[serde(default = "default_shadow_radius")]
+    pub radius: f32,
+
+    /// Distance the shadow extends beyond the window before blurring
+    #[serde(default)]
+    pub spread: f32,
+
+    /// Shadow color (RGBA)
+    #[serde(default = "default_shadow_color")]
+    pub color: [f32; 4],
+}
+
+impl Default for ShadowConfig {
+    fn default() -> Self {
+        Self {
+            offset: default_shadow_offset(),
+            radius: default_shadow_radius(),
+            spread: 0.0,
+            color: default_shadow_color(),
+        }
+    }
+}
+
+fn default_corner_radius() -> f32 {
+    8.0
+}
+
+fn default_border_color() -> [f32; 4] {
+    [0.3, 0.3, 0.4, 1.0]
+}
+
+fn default_shadow() -> Option<ShadowConfig> {
+    Some(ShadowConfig::default())
+}
+
+fn default_shadow_offset() -> [f32; 2] {
+    [0.0, 4.0]
+}
+
+fn default_shadow_radius() -> f32 {
+    16.0
+}
+
+fn default_shadow_color() -> [f32; 4] {
+    [0.0, 0.0, 0.0, 0.4]
+}
+
+/// Tone mapping operators, used when HDR content is shown on a dimmer output
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ToneMappingOperator {
+    /// Clip at the output's peak luminance
+    Clip,
+    /// Extended Reinhard curve
+    Reinhard,
+    /// ITU-R BT.2390 EETF
+    #[default]
+    Bt2390,
+    /// Hable's filmic curve
+    Hable,
 }
 
 fn default_vsync() -> bool {
@@ -53,6 +506,10 @@ fn default_background_color() -> [f32; 4] {
     [0.1, 0.1, 0.2, 1.0] // Dark blue
 }
 
+fn default_frame_safety_margin_us() -> u64 {
+    1500
+}
+
 impl Default for StarforgeConfig {
     fn default() -> Self {
         Self {
@@ -63,7 +520,20 @@ impl Default for StarforgeConfig {
             rendering: RenderConfig {
                 vsync: default_vsync(),
                 background_color: default_background_color(),
+                enable_hdr: false,
+                tone_mapping: ToneMappingOperator::default(),
+                blur: BlurConfig::default(),
+                windows: WindowShapeConfig::default(),
+                show_stats_hud: false,
+                frame_safety_margin_us: default_frame_safety_margin_us(),
+                allow_tearing: false,
             },
+            cursor: CursorConfig::default(),
+            accessibility: AccessibilityConfig::default(),
+            night_light: NightLightConfig::default(),
+            xwayland: XWaylandConfig::default(),
+            decorations: DecorationConfig::default(),
+            outputs: HashMap::new(),
         }
     }
 }
@@ -89,6 +559,11 @@ impl StarforgeConfig {
         Ok(config)
     }
 
+    /// Settings of the output called `name`, the defaults if it has none
+    pub fn output(&self, name: &str) -> OutputConfig {
+        self.outputs.get(name).copied().unwrap_or_default()
+    }
+
     /// Save configuration to a file
     pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), ConfigError> {
         let content = toml::to_string_pretty(self)?;
@@ -96,3 +571,43 @@ impl StarforgeConfig {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A config with the required sections and `rest` after them
+    fn parse(rest: &str) -> StarforgeConfig {
+        toml::from_str(&format!("[general]\n[rendering]\n{rest}")).unwrap()
+    }
+
+    #[test]
+    fn outputs_are_configured_by_name() {
+        let config = parse("[outputs.eDP-1]\nscale = 1.5\n");
+        assert_eq!(config.output("eDP-1").scale, 1.5);
+    }
+
+    #[test]
+    fn unconfigured_outputs_get_the_defaults() {
+        let config = parse("[outputs.eDP-1]\nscale = 1.5\n");
+        assert_eq!(config.output("HDMI-A-1"), OutputConfig::default());
+        assert_eq!(OutputConfig::default().scale, 1.0);
+    }
+
+    #[test]
+    fn output_scale_defaults_to_one() {
+        let config = parse("[outputs.eDP-1]\n");
+        assert_eq!(config.output("eDP-1").scale, 1.0);
+    }
+
+    #[test]
+    fn outputs_survive_a_round_trip() {
+        let mut config = StarforgeConfig::default();
+        config
+            .outputs
+            .insert("DP-2".to_string(), OutputConfig { scale: 2.0 });
+        let saved = toml::to_string_pretty(&config).unwrap();
+        let loaded: StarforgeConfig = toml::from_str(&saved).unwrap();
+        assert_eq!(loaded.output("DP-2").scale, 2.0);
+    }
+}
@@ -0,0 +1,72 @@
+//! Time sources for animations
+//!
+//! Animation timelines are measured on `CLOCK_MONOTONIC`, the clock presentation feedback
+//! reports in, so frames can be sampled at their predicted presentation time.
+
+use smithay::utils::{Clock as SmithayClock, Monotonic};
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+/// A source of presentation clock timestamps
+pub trait Clock {
+    /// The current time, as an offset from the clock's epoch
+    fn now(&self) -> Duration;
+}
+
+/// The system monotonic clock
+#[derive(Debug)]
+pub struct MonotonicClock(SmithayClock<Monotonic>);
+
+impl MonotonicClock {
+    pub fn new() -> Self {
+        Self(SmithayClock::new())
+    }
+}
+
+impl Default for MonotonicClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for MonotonicClock {
+    fn now(&self) -> Duration {
+        self.0.now().into()
+    }
+}
+
+/// A clock that only moves when told to, for deterministic tests and replays
+///
+/// Clones share the same time, so one handle can be given to an [`AnimationState`]
+/// while another advances it.
+///
+/// [`AnimationState`]: super::AnimationState
+#[derive(Clone, Debug, Default)]
+pub struct VirtualClock(Arc<AtomicU64>);
+
+impl VirtualClock {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Move the clock forward by `step`
+    pub fn advance(&self, step: Duration) {
+        self.0.fetch_add(step.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    /// Set the clock to `time`
+    pub fn set(&self, time: Duration) {
+        self.0.store(time.as_nanos() as u64, Ordering::Relaxed);
+    }
+}
+
+impl Clock for VirtualClock {
+    fn now(&self) -> Duration {
+        Duration::from_nanos(self.0.load(Ordering::Relaxed))
+    }
+}
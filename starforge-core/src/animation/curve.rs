@@ -0,0 +1,274 @@
+//! Timing curves mapping elapsed time to animation progress
+//!
+//! Progress runs from 0 at the start of an animation to 1 at its end. Easing curves reach 1
+//! after a fixed duration; springs settle when their motion becomes imperceptible and may
+//! overshoot on the way.
+
+use std::time::Duration;
+
+/// Distance from the target below which a spring counts as settled, in progress units
+const SPRING_REST_DISTANCE: f64 = 1e-3;
+
+/// Speed below which a spring counts as settled, in progress units per second
+const SPRING_REST_VELOCITY: f64 = 1e-2;
+
+/// Longest time a spring is simulated before it is considered settled
+const SPRING_MAX_DURATION: Duration = Duration::from_secs(10);
+
+/// Easing functions for fixed-duration animations
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Easing {
+    Linear,
+    EaseInCubic,
+    EaseOutCubic,
+    EaseInOutCubic,
+    EaseOutExpo,
+    /// CSS-style cubic Bézier through (0, 0), (x1, y1), (x2, y2) and (1, 1)
+    CubicBezier(f64, f64, f64, f64),
+}
+
+impl Easing {
+    /// Eased progress for linear progress `t` in `[0, 1]`
+    pub fn apply(self, t: f64) -> f64 {
+        let t = t.clamp(0.0, 1.0);
+        match self {
+            Self::Linear => t,
+            Self::EaseInCubic => t * t * t,
+            Self::EaseOutCubic => 1.0 - (1.0 - t).powi(3),
+            Self::EaseInOutCubic if t < 0.5 => 4.0 * t * t * t,
+            Self::EaseInOutCubic => 1.0 - (-2.0 * t + 2.0).powi(3) / 2.0,
+            Self::EaseOutExpo if t >= 1.0 => 1.0,
+            Self::EaseOutExpo => 1.0 - 2f64.powf(-10.0 * t),
+            Self::CubicBezier(x1, y1, x2, y2) => {
+                let s = solve_bezier(t, x1, x2);
+                bezier(s, y1, y2)
+            }
+        }
+    }
+}
+
+/// One coordinate of a cubic Bézier from 0 to 1 with control points `p1` and `p2`
+fn bezier(s: f64, p1: f64, p2: f64) -> f64 {
+    let inv = 1.0 - s;
+    3.0 * inv * inv * s * p1 + 3.0 * inv * s * s * p2 + s * s * s
+}
+
+fn bezier_derivative(s: f64, p1: f64, p2: f64) -> f64 {
+    let inv = 1.0 - s;
+    3.0 * inv * inv * p1 + 6.0 * inv * s * (p2 - p1) + 3.0 * s * s * (1.0 - p2)
+}
+
+/// Find the curve parameter whose x coordinate is `x`
+fn solve_bezier(x: f64, x1: f64, x2: f64) -> f64 {
+    // Newton's method converges in a few steps unless the slope is close to flat
+    let mut s = x;
+    for _ in 0..8 {
+        let error = bezier(s, x1, x2) - x;
+        if error.abs() < 1e-7 {
+            return s;
+        }
+        let slope = bezier_derivative(s, x1, x2);
+        if slope.abs() < 1e-6 {
+            break;
+        }
+        s -= error / slope;
+    }
+
+    // x is monotonic in s for control points inside [0, 1], so bisection always works
+    let (mut low, mut high) = (0.0, 1.0);
+    s = x;
+    while high - low > 1e-7 {
+        if bezier(s, x1, x2) < x {
+            low = s;
+        } else {
+            high = s;
+        }
+        s = (low + high) / 2.0;
+    }
+    s
+}
+
+/// A damped harmonic oscillator pulling progress towards 1
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Spring {
+    /// Damping relative to critical damping: below 1 overshoots, 1 and above does not
+    pub damping_ratio: f64,
+    /// Spring constant
+    pub stiffness: f64,
+    pub mass: f64,
+    /// Velocity at the start, in progress units per second
+    pub initial_velocity: f64,
+}
+
+impl Default for Spring {
+    fn default() -> Self {
+        Self {
+            damping_ratio: 1.0,
+            stiffness: 800.0,
+            mass: 1.0,
+            initial_velocity: 0.0,
+        }
+    }
+}
+
+impl Spring {
+    /// Progress after `t` seconds
+    pub fn value(&self, t: f64) -> f64 {
+        1.0 + self.displacement(t)
+    }
+
+    /// Distance from the target after `t` seconds, starting at -1
+    fn displacement(&self, t: f64) -> f64 {
+        let x0 = -1.0;
+        let v0 = self.initial_velocity;
+        let omega = (self.stiffness / self.mass).sqrt();
+        let zeta = self.damping_ratio;
+
+        if (zeta - 1.0).abs() < 1e-6 {
+            (-omega * t).exp() * (x0 + (v0 + omega * x0) * t)
+        } else if zeta < 1.0 {
+            let omega_d = omega * (1.0 - zeta * zeta).sqrt();
+            (-zeta * omega * t).exp()
+                * (x0 * (omega_d * t).cos()
+                    + (v0 + zeta * omega * x0) / omega_d * (omega_d * t).sin())
+        } else {
+            let root = (zeta * zeta - 1.0).sqrt();
+            let r1 = -omega * (zeta - root);
+            let r2 = -omega * (zeta + root);
+            let c2 = (v0 - r1 * x0) / (r2 - r1);
+            let c1 = x0 - c2;
+            c1 * (r1 * t).exp() + c2 * (r2 * t).exp()
+        }
+    }
+
+    /// Time after which the spring stays at rest
+    pub fn settle_time(&self) -> Duration {
+        const STEP: f64 = 0.001;
+        let max = SPRING_MAX_DURATION.as_secs_f64();
+        let mut t = 0.0;
+        while t < max {
+            let x = self.displacement(t);
+            let velocity = (self.displacement(t + STEP) - x) / STEP;
+            if x.abs() < SPRING_REST_DISTANCE && velocity.abs() < SPRING_REST_VELOCITY {
+                return Duration::from_secs_f64(t);
+            }
+            t += STEP;
+        }
+        SPRING_MAX_DURATION
+    }
+}
+
+/// How an animation moves from its start to its end value
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Curve {
+    Eased { duration: Duration, easing: Easing },
+    Spring(Spring),
+}
+
+impl Curve {
+    pub const fn eased(duration: Duration, easing: Easing) -> Self {
+        Self::Eased { duration, easing }
+    }
+
+    /// Progress after `elapsed`, exactly 1 once the curve has finished
+    pub fn progress(&self, elapsed: Duration, duration: Duration) -> f64 {
+        if elapsed >= duration {
+            return 1.0;
+        }
+        match self {
+            Self::Eased { duration, easing } => {
+                easing.apply(elapsed.as_secs_f64() / duration.as_secs_f64())
+            }
+            Self::Spring(spring) => spring.value(elapsed.as_secs_f64()),
+        }
+    }
+
+    /// Time until the curve finishes
+    pub fn duration(&self) -> Duration {
+        match self {
+            Self::Eased { duration, .. } => *duration,
+            Self::Spring(spring) => spring.settle_time(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EASINGS: [Easing; 6] = [
+        Easing::Linear,
+        Easing::EaseInCubic,
+        Easing::EaseOutCubic,
+        Easing::EaseInOutCubic,
+        Easing::EaseOutExpo,
+        Easing::CubicBezier(0.25, 0.1, 0.25, 1.0),
+    ];
+
+    #[test]
+    fn easings_start_at_zero_and_end_at_one() {
+        for easing in EASINGS {
+            assert!(easing.apply(0.0).abs() < 1e-6, "{easing:?}");
+            assert!((easing.apply(1.0) - 1.0).abs() < 1e-6, "{easing:?}");
+        }
+    }
+
+    #[test]
+    fn easings_are_monotonic() {
+        for easing in EASINGS {
+            let mut previous = 0.0;
+            for i in 1..=100 {
+                let value = easing.apply(i as f64 / 100.0);
+                assert!(value >= previous - 1e-9, "{easing:?} at {i}");
+                previous = value;
+            }
+        }
+    }
+
+    #[test]
+    fn linear_bezier_is_identity() {
+        let easing = Easing::CubicBezier(1.0 / 3.0, 1.0 / 3.0, 2.0 / 3.0, 2.0 / 3.0);
+        for i in 0..=10 {
+            let t = i as f64 / 10.0;
+            assert!((easing.apply(t) - t).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn critically_damped_spring_does_not_overshoot() {
+        let spring = Spring::default();
+        let settle = spring.settle_time();
+        assert!(settle > Duration::ZERO && settle < SPRING_MAX_DURATION);
+        let mut t = 0.0;
+        while t < settle.as_secs_f64() {
+            assert!(spring.value(t) <= 1.0 + 1e-9);
+            t += 0.001;
+        }
+    }
+
+    #[test]
+    fn underdamped_spring_overshoots_and_settles() {
+        let spring = Spring {
+            damping_ratio: 0.3,
+            ..Default::default()
+        };
+        let settle = spring.settle_time();
+        let peak = (0..settle.as_millis())
+            .map(|ms| spring.value(ms as f64 / 1000.0))
+            .fold(f64::MIN, f64::max);
+        assert!(peak > 1.1);
+        assert!((spring.value(settle.as_secs_f64()) - 1.0).abs() < SPRING_REST_DISTANCE);
+    }
+
+    #[test]
+    fn overdamped_spring_reaches_its_target() {
+        let spring = Spring {
+            damping_ratio: 2.0,
+            ..Default::default()
+        };
+        let curve = Curve::Spring(spring);
+        let duration = curve.duration();
+        assert!(curve.progress(duration / 2, duration) < 1.0);
+        assert_eq!(curve.progress(duration, duration), 1.0);
+    }
+}
@@ -0,0 +1,762 @@
+//! Time-based animation of window properties
+//!
+//! Windows fade and scale in when they open and out when they close, slide to new
+//! geometry when moved, and whole workspaces slide during a switch. Every animation is
+//! a start and end value joined by a [`Curve`] on the presentation clock timeline.
+//!
+//! [`AnimationState`] samples all animations at one point in time per frame, set with
+//! [`AnimationState::advance`] or [`AnimationState::advance_to`], so everything drawn in a
+//! frame is consistent. Closing windows keep a [`WindowSnapshot`] of their last buffers
+//! so the close animation can play after the client destroyed the surface.
+
+mod clock;
+mod curve;
+
+pub use clock::{Clock, MonotonicClock, VirtualClock};
+pub use curve::{Curve, Easing, Spring};
+
+use crate::handlers::stored_acquire_point;
+use crate::protocols::color_management::{ColorManagementSurfaceCachedState, ImageDescription};
+use crate::protocols::color_representation::ColorRepresentationSurfaceCachedState;
+use smithay::{
+    backend::renderer::utils::{Buffer, RendererSurfaceStateUserData},
+    reexports::wayland_server::protocol::wl_surface::WlSurface,
+    utils::{Logical, Point, Rectangle, Size, Transform},
+    wayland::{
+        compositor::{TraversalAction, with_states, with_surface_tree_downward},
+        drm_syncobj::DrmSyncPoint,
+        shell::xdg::{PopupSurface, SurfaceCachedState, XdgPopupSurfaceData},
+    },
+};
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+/// Values that can be interpolated by an animation
+pub trait Animatable: Copy {
+    /// The value at `t` between `from` (0) and `to` (1); springs may pass values outside
+    /// `[0, 1]`
+    fn interpolate(from: Self, to: Self, t: f64) -> Self;
+}
+
+impl Animatable for f64 {
+    fn interpolate(from: Self, to: Self, t: f64) -> Self {
+        from + (to - from) * t
+    }
+}
+
+impl Animatable for Point<f64, Logical> {
+    fn interpolate(from: Self, to: Self, t: f64) -> Self {
+        Point::from((
+            f64::interpolate(from.x, to.x, t),
+            f64::interpolate(from.y, to.y, t),
+        ))
+    }
+}
+
+impl Animatable for Rectangle<f64, Logical> {
+    fn interpolate(from: Self, to: Self, t: f64) -> Self {
+        Rectangle::new(
+            Point::interpolate(from.loc, to.loc, t),
+            Size::from((
+                f64::interpolate(from.size.w, to.size.w, t).max(0.0),
+                f64::interpolate(from.size.h, to.size.h, t).max(0.0),
+            )),
+        )
+    }
+}
+
+/// Transform applied to a window on top of its geometry
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct WindowTransform {
+    /// Translation in logical pixels
+    pub offset: Point<f64, Logical>,
+    /// Scale around the centre of the window
+    pub scale: f64,
+}
+
+impl WindowTransform {
+    pub fn scaled(scale: f64) -> Self {
+        Self {
+            scale,
+            ..Default::default()
+        }
+    }
+}
+
+impl Default for WindowTransform {
+    fn default() -> Self {
+        Self {
+            offset: Point::from((0.0, 0.0)),
+            scale: 1.0,
+        }
+    }
+}
+
+impl Animatable for WindowTransform {
+    fn interpolate(from: Self, to: Self, t: f64) -> Self {
+        Self {
+            offset: Point::interpolate(from.offset, to.offset, t),
+            scale: f64::interpolate(from.scale, to.scale, t).max(0.0),
+        }
+    }
+}
+
+/// A single property moving from one value to another
+#[derive(Clone, Copy, Debug)]
+pub struct Animation<T> {
+    from: T,
+    to: T,
+    start: Duration,
+    duration: Duration,
+    curve: Curve,
+}
+
+impl<T: Animatable> Animation<T> {
+    pub fn new(from: T, to: T, start: Duration, curve: Curve) -> Self {
+        Self {
+            from,
+            to,
+            start,
+            duration: curve.duration(),
+            curve,
+        }
+    }
+
+    /// The value at `now`
+    pub fn value(&self, now: Duration) -> T {
+        let elapsed = now.saturating_sub(self.start);
+        T::interpolate(
+            self.from,
+            self.to,
+            self.curve.progress(elapsed, self.duration),
+        )
+    }
+
+    pub fn target(&self) -> T {
+        self.to
+    }
+
+    pub fn is_done(&self, now: Duration) -> bool {
+        now >= self.start + self.duration
+    }
+}
+
+/// Properties of a window at one point in time
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct WindowProperties {
+    pub opacity: f64,
+    pub transform: WindowTransform,
+    /// Geometry to draw the window at, if it differs from the window's own geometry
+    pub geometry: Option<Rectangle<f64, Logical>>,
+}
+
+impl Default for WindowProperties {
+    fn default() -> Self {
+        Self {
+            opacity: 1.0,
+            transform: WindowTransform::default(),
+            geometry: None,
+        }
+    }
+}
+
+/// Running animations of one window
+#[derive(Debug, Default)]
+struct WindowAnimations {
+    opacity: Option<Animation<f64>>,
+    transform: Option<Animation<WindowTransform>>,
+    geometry: Option<Animation<Rectangle<f64, Logical>>>,
+}
+
+impl WindowAnimations {
+    fn properties(&self, now: Duration) -> WindowProperties {
+        let defaults = WindowProperties::default();
+        WindowProperties {
+            opacity: self
+                .opacity
+                .map_or(defaults.opacity, |animation| animation.value(now)),
+            transform: self
+                .transform
+                .map_or(defaults.transform, |animation| animation.value(now)),
+            geometry: self.geometry.map(|animation| animation.value(now)),
+        }
+    }
+
+    /// Drop finished animations, returning whether any is still running
+    fn retain_running(&mut self, now: Duration) -> bool {
+        fn retain<T: Animatable>(animation: &mut Option<Animation<T>>, now: Duration) {
+            if animation.is_some_and(|animation| animation.is_done(now)) {
+                *animation = None;
+            }
+        }
+        retain(&mut self.opacity, now);
+        retain(&mut self.transform, now);
+        retain(&mut self.geometry, now);
+        self.opacity.is_some() || self.transform.is_some() || self.geometry.is_some()
+    }
+}
+
+/// One surface of a [`WindowSnapshot`]
+#[derive(Debug, Clone)]
+pub struct SnapshotLayer {
+    /// The surface the buffer was attached to
+    pub surface: WlSurface,
+    /// The buffer, held so the client can't reuse it while the snapshot lives
+    pub buffer: Buffer,
+    /// Position relative to the window's main surface
+    pub location: Point<i32, Logical>,
+    /// Part of the buffer shown, in logical surface coordinates
+    pub src: Rectangle<f64, Logical>,
+    /// Size the buffer is drawn at
+    pub size: Size<i32, Logical>,
+    /// Size of the whole buffer in logical coordinates, larger than `src` when a viewport
+    /// crops it
+    pub buffer_size: Size<i32, Logical>,
+    pub buffer_scale: i32,
+    pub buffer_transform: Transform,
+    /// Colour space of the buffer, `None` for sRGB
+    pub image_description: Option<Arc<ImageDescription>>,
+    /// How YUV values of the buffer map to RGB
+    pub color_representation: ColorRepresentationSurfaceCachedState,
+    /// Point the buffer can be sampled after, `None` for implicit synchronization
+    pub acquire_point: Option<DrmSyncPoint>,
+    pub kind: LayerKind,
+}
+
+/// The part of a window a [`SnapshotLayer`] shows
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LayerKind {
+    /// The window's main surface
+    Main,
+    /// A subsurface of the window
+    Subsurface,
+    /// A popup opened from the window, or a subsurface of one
+    Popup,
+}
+
+impl SnapshotLayer {
+    /// `src` relative to the whole buffer, as x, y, width and height between 0 and 1
+    pub fn source_uv(&self) -> [f64; 4] {
+        let w = f64::from(self.buffer_size.w.max(1));
+        let h = f64::from(self.buffer_size.h.max(1));
+        [
+            self.src.loc.x / w,
+            self.src.loc.y / h,
+            self.src.size.w / w,
+            self.src.size.h / h,
+        ]
+    }
+}
+
+/// The last buffers of a window's surface tree, bottom-most first
+#[derive(Debug, Clone, Default)]
+pub struct WindowSnapshot {
+    pub layers: Vec<SnapshotLayer>,
+}
+
+impl WindowSnapshot {
+    /// Snapshot the current buffers of `surface` and its subsurfaces
+    ///
+    /// Only surfaces whose buffers are tracked with
+    /// [`on_commit_buffer_handler`](smithay::backend::renderer::utils::on_commit_buffer_handler)
+    /// are captured.
+    pub fn capture(surface: &WlSurface) -> Self {
+        let mut layers = Vec::new();
+        with_surface_tree_downward(
+            surface,
+            Point::from((0, 0)),
+            |_, states, location| {
+                let view = states
+                    .data_map
+                    .get::<RendererSurfaceStateUserData>()
+                    .and_then(|data| data.lock().unwrap().view());
+                match view {
+                    Some(view) => TraversalAction::DoChildren(*location + view.offset),
+                    None => TraversalAction::SkipChildren,
+                }
+            },
+            |layer_surface, states, location| {
+                let Some(data) = states.data_map.get::<RendererSurfaceStateUserData>() else {
+                    return;
+                };
+                let data = data.lock().unwrap();
+                if let (Some(view), Some(buffer)) = (data.view(), data.buffer()) {
+                    layers.push(SnapshotLayer {
+                        surface: layer_surface.clone(),
+                        buffer: buffer.clone(),
+                        location: *location + view.offset,
+                        src: view.src,
+                        size: view.dst,
+                        buffer_size: data.buffer_size().unwrap_or(view.dst),
+                        buffer_scale: data.buffer_scale(),
+                        buffer_transform: data.buffer_transform(),
+                        image_description: states
+                            .cached_state
+                            .get::<ColorManagementSurfaceCachedState>()
+                            .current()
+                            .image_description()
+                            .cloned(),
+                        color_representation: *states
+                            .cached_state
+                            .get::<ColorRepresentationSurfaceCachedState>()
+                            .current(),
+                        acquire_point: stored_acquire_point(states),
+                        kind: match layer_surface == surface {
+                            true => LayerKind::Main,
+                            false => LayerKind::Subsurface,
+                        },
+                    });
+                }
+            },
+            |_, _, _| true,
+        );
+        Self { layers }
+    }
+
+    /// Like [`Self::capture`], with the popups opened from the window on top
+    ///
+    /// `popups` can be every popup the compositor knows; those that don't belong to the
+    /// window are skipped.
+    pub fn capture_with_popups(surface: &WlSurface, popups: &[PopupSurface]) -> Self {
+        let mut snapshot = Self::capture(surface);
+        let mut placed = vec![(surface.clone(), Point::from((0, 0)))];
+        // Parents come before their popups, so nested popups stack above them
+        loop {
+            let mut found = false;
+            for popup in popups {
+                let popup_surface = popup.wl_surface();
+                if placed.iter().any(|(placed, _)| placed == popup_surface) {
+                    continue;
+                }
+                let Some(parent) = popup.get_parent_surface() else {
+                    continue;
+                };
+                let Some(&(_, parent_location)) =
+                    placed.iter().find(|(placed, _)| *placed == parent)
+                else {
+                    continue;
+                };
+                let geometry = with_states(popup_surface, |states| {
+                    states
+                        .data_map
+                        .get::<XdgPopupSurfaceData>()
+                        .map(|data| data.lock().unwrap().current.geometry)
+                        .unwrap_or_default()
+                });
+                // Popups are placed relative to the window geometry of their parent
+                let location = parent_location + window_geometry_offset(&parent) + geometry.loc
+                    - window_geometry_offset(popup_surface);
+                placed.push((popup_surface.clone(), location));
+                found = true;
+            }
+            if !found {
+                break;
+            }
+        }
+        for (popup_surface, location) in placed.into_iter().skip(1) {
+            snapshot
+                .layers
+                .extend(
+                    Self::capture(&popup_surface)
+                        .layers
+                        .into_iter()
+                        .map(|mut layer| {
+                            layer.location += location;
+                            layer.kind = LayerKind::Popup;
+                            layer
+                        }),
+                );
+        }
+        snapshot
+    }
+
+    /// Highest scale of the layers' buffers, at which the window shows every detail
+    pub fn buffer_scale(&self) -> i32 {
+        self.layers
+            .iter()
+            .map(|layer| layer.buffer_scale)
+            .max()
+            .unwrap_or(1)
+    }
+
+    /// Smallest rectangle containing every layer, relative to the main surface
+    pub fn bounding_box(&self) -> Rectangle<i32, Logical> {
+        self.layers
+            .iter()
+            .map(|layer| Rectangle::new(layer.location, layer.size))
+            .reduce(|a, b| a.merge(b))
+            .unwrap_or_default()
+    }
+}
+
+/// Where the window geometry of `surface` starts within the surface
+pub(crate) fn window_geometry_offset(surface: &WlSurface) -> Point<i32, Logical> {
+    with_states(surface, |states| {
+        states
+            .cached_state
+            .get::<SurfaceCachedState>()
+            .current()
+            .geometry
+            .map(|geometry| geometry.loc)
+            .unwrap_or_default()
+    })
+}
+
+/// A window playing its close animation after the client destroyed it
+#[derive(Debug)]
+pub struct ClosingWindow {
+    pub snapshot: WindowSnapshot,
+    /// Where the window was when it closed
+    pub geometry: Rectangle<f64, Logical>,
+    animations: WindowAnimations,
+}
+
+impl ClosingWindow {
+    pub fn properties(&self, now: Duration) -> WindowProperties {
+        self.animations.properties(now)
+    }
+}
+
+/// Offsets of the workspaces involved in a switch
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct WorkspaceOffsets {
+    /// Offset of the workspace being switched to
+    pub incoming: Point<f64, Logical>,
+    /// Offset of the workspace being left
+    pub outgoing: Point<f64, Logical>,
+}
+
+#[derive(Debug)]
+struct WorkspaceTransition {
+    offset: Animation<Point<f64, Logical>>,
+    delta: Point<f64, Logical>,
+}
+
+/// Curves and start values of the built-in transitions
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AnimationSettings {
+    /// Fade and scale in of new windows
+    pub open: Curve,
+    /// Fade and scale out of closed windows
+    pub close: Curve,
+    /// Scale new windows start from and closed windows end at
+    pub open_close_scale: f64,
+    /// Moves and resizes
+    pub geometry: Curve,
+    /// Workspace switches
+    pub workspace: Curve,
+}
+
+impl Default for AnimationSettings {
+    fn default() -> Self {
+        Self {
+            open: Curve::eased(Duration::from_millis(200), Easing::EaseOutCubic),
+            close: Curve::eased(Duration::from_millis(150), Easing::EaseInCubic),
+            open_close_scale: 0.9,
+            geometry: Curve::Spring(Spring::default()),
+            workspace: Curve::Spring(Spring::default()),
+        }
+    }
+}
+
+/// Animations of all windows and workspaces
+pub struct AnimationState {
+    clock: Box<dyn Clock>,
+    now: Duration,
+    pub settings: AnimationSettings,
+    windows: HashMap<WlSurface, WindowAnimations>,
+    closing: Vec<ClosingWindow>,
+    workspace: Option<WorkspaceTransition>,
+}
+
+impl AnimationState {
+    pub fn new(clock: impl Clock + 'static) -> Self {
+        let now = clock.now();
+        Self {
+            clock: Box::new(clock),
+            now,
+            settings: AnimationSettings::default(),
+            windows: HashMap::new(),
+            closing: Vec::new(),
+            workspace: None,
+        }
+    }
+
+    /// The time animations are currently sampled at
+    pub fn now(&self) -> Duration {
+        self.now
+    }
+
+    /// Sample animations at the clock's current time
+    ///
+    /// Returns whether any animation is still running and needs another frame.
+    pub fn advance(&mut self) -> bool {
+        let now = self.clock.now();
+        self.advance_to(now)
+    }
+
+    /// Sample animations at `time`, usually the predicted presentation time of the next
+    /// frame. Time never moves backwards.
+    pub fn advance_to(&mut self, time: Duration) -> bool {
+        self.now = self.now.max(time);
+        let now = self.now;
+        self.windows
+            .retain(|_, animations| animations.retain_running(now));
+        self.closing
+            .retain_mut(|window| window.animations.retain_running(now));
+        if self
+            .workspace
+            .as_ref()
+            .is_some_and(|transition| transition.offset.is_done(now))
+        {
+            self.workspace = None;
+        }
+        self.is_animating()
+    }
+
+    pub fn is_animating(&self) -> bool {
+        !self.windows.is_empty() || !self.closing.is_empty() || self.workspace.is_some()
+    }
+
+    /// Start the open animation of a new window
+    pub fn window_opened(&mut self, surface: &WlSurface) {
+        let settings = self.settings;
+        let animations = self.windows.entry(surface.clone()).or_default();
+        animations.opacity = Some(Animation::new(0.0, 1.0, self.now, settings.open));
+        animations.transform = Some(Animation::new(
+            WindowTransform::scaled(settings.open_close_scale),
+            WindowTransform::default(),
+            self.now,
+            settings.open,
+        ));
+    }
+
+    /// Start the close animation of a window, drawing `snapshot` at `geometry` until it ends
+    ///
+    /// Animations already running on the window continue from their current values.
+    pub fn window_closed(
+        &mut self,
+        surface: &WlSurface,
+        snapshot: WindowSnapshot,
+        geometry: Rectangle<f64, Logical>,
+    ) {
+        let current = self
+            .windows
+            .remove(surface)
+            .map(|animations| animations.properties(self.now))
+            .unwrap_or_default();
+        if snapshot.layers.is_empty() {
+            return;
+        }
+
+        let settings = self.settings;
+        let close_transform = WindowTransform {
+            scale: settings.open_close_scale,
+            ..current.transform
+        };
+        self.closing.push(ClosingWindow {
+            snapshot,
+            geometry: current.geometry.unwrap_or(geometry),
+            animations: WindowAnimations {
+                opacity: Some(Animation::new(
+                    current.opacity,
+                    0.0,
+                    self.now,
+                    settings.close,
+                )),
+                transform: Some(Animation::new(
+                    current.transform,
+                    close_transform,
+                    self.now,
+                    settings.close,
+                )),
+                geometry: None,
+            },
+        });
+    }
+
+    /// Animate a window moving or resizing from `from` to `to`
+    ///
+    /// If the window is already moving, the new animation starts where the window is
+    /// currently drawn.
+    pub fn window_moved(
+        &mut self,
+        surface: &WlSurface,
+        from: Rectangle<f64, Logical>,
+        to: Rectangle<f64, Logical>,
+    ) {
+        let now = self.now;
+        let curve = self.settings.geometry;
+        let animations = self.windows.entry(surface.clone()).or_default();
+        let from = animations
+            .geometry
+            .map_or(from, |animation| animation.value(now));
+        animations.geometry = Some(Animation::new(from, to, now, curve));
+    }
+
+    /// Slide to another workspace that starts displaced by `delta`, while the current one
+    /// slides away by the same amount
+    pub fn switch_workspace(&mut self, delta: Point<f64, Logical>) {
+        let current = self
+            .workspace_offsets()
+            .map_or(Point::from((0.0, 0.0)), |offsets| offsets.incoming);
+        self.workspace = Some(WorkspaceTransition {
+            offset: Animation::new(
+                current + delta,
+                Point::from((0.0, 0.0)),
+                self.now,
+                self.settings.workspace,
+            ),
+            delta,
+        });
+    }
+
+    /// Properties of a window, at their resting values when it isn't animating
+    pub fn window(&self, surface: &WlSurface) -> WindowProperties {
+        self.windows
+            .get(surface)
+            .map(|animations| animations.properties(self.now))
+            .unwrap_or_default()
+    }
+
+    /// Windows still playing their close animation, oldest first
+    pub fn closing_windows(&self) -> impl Iterator<Item = (&ClosingWindow, WindowProperties)> {
+        self.closing
+            .iter()
+            .map(|window| (window, window.properties(self.now)))
+    }
+
+    /// Workspace offsets while a switch is running
+    pub fn workspace_offsets(&self) -> Option<WorkspaceOffsets> {
+        self.workspace.as_ref().map(|transition| {
+            let incoming = transition.offset.value(self.now);
+            WorkspaceOffsets {
+                incoming,
+                outgoing: incoming - transition.delta,
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FRAME: Duration = Duration::from_micros(16_667);
+
+    fn state() -> (AnimationState, VirtualClock) {
+        let clock = VirtualClock::new();
+        let mut state = AnimationState::new(clock.clone());
+        state.settings.close = Curve::eased(Duration::from_millis(100), Easing::Linear);
+        state.settings.workspace = Curve::eased(Duration::from_millis(100), Easing::Linear);
+        (state, clock)
+    }
+
+    fn closing_window() -> ClosingWindow {
+        ClosingWindow {
+            snapshot: WindowSnapshot::default(),
+            geometry: Rectangle::from_size((100.0, 100.0).into()),
+            animations: WindowAnimations::default(),
+        }
+    }
+
+    #[test]
+    fn animation_values_follow_the_clock() {
+        let animation = Animation::new(
+            10.0,
+            20.0,
+            Duration::from_millis(100),
+            Curve::eased(Duration::from_millis(100), Easing::Linear),
+        );
+        assert_eq!(animation.value(Duration::ZERO), 10.0);
+        assert_eq!(animation.value(Duration::from_millis(150)), 15.0);
+        assert_eq!(animation.value(Duration::from_millis(200)), 20.0);
+        assert!(!animation.is_done(Duration::from_millis(199)));
+        assert!(animation.is_done(Duration::from_millis(200)));
+    }
+
+    #[test]
+    fn time_does_not_move_backwards() {
+        let (mut state, clock) = state();
+        clock.set(Duration::from_secs(2));
+        state.advance();
+        state.advance_to(Duration::from_secs(1));
+        assert_eq!(state.now(), Duration::from_secs(2));
+    }
+
+    #[test]
+    fn closing_windows_fade_out_and_are_dropped() {
+        let (mut state, clock) = state();
+        let mut window = closing_window();
+        window.animations.opacity = Some(Animation::new(1.0, 0.0, state.now, state.settings.close));
+        state.closing.push(window);
+
+        let mut previous = f64::MAX;
+        let mut frames = 0;
+        while state.advance() {
+            let (_, properties) = state.closing_windows().next().unwrap();
+            assert!(properties.opacity < previous);
+            previous = properties.opacity;
+            clock.advance(FRAME);
+            frames += 1;
+        }
+        // 100ms at 60Hz
+        assert_eq!(frames, 6);
+        assert_eq!(state.closing_windows().count(), 0);
+    }
+
+    #[test]
+    fn workspace_switch_slides_both_workspaces() {
+        let (mut state, clock) = state();
+        state.switch_workspace(Point::from((1920.0, 0.0)));
+        let offsets = state.workspace_offsets().unwrap();
+        assert_eq!(offsets.incoming, Point::from((1920.0, 0.0)));
+        assert_eq!(offsets.outgoing, Point::from((0.0, 0.0)));
+
+        clock.advance(Duration::from_millis(50));
+        state.advance();
+        let offsets = state.workspace_offsets().unwrap();
+        assert_eq!(offsets.incoming, Point::from((960.0, 0.0)));
+        assert_eq!(offsets.outgoing, Point::from((-960.0, 0.0)));
+
+        clock.advance(Duration::from_millis(50));
+        assert!(!state.advance());
+        assert_eq!(state.workspace_offsets(), None);
+    }
+
+    #[test]
+    fn workspace_switch_retargets_from_the_current_offset() {
+        let (mut state, clock) = state();
+        state.switch_workspace(Point::from((1000.0, 0.0)));
+        clock.advance(Duration::from_millis(50));
+        state.advance();
+        state.switch_workspace(Point::from((1000.0, 0.0)));
+        let offsets = state.workspace_offsets().unwrap();
+        assert_eq!(offsets.incoming, Point::from((1500.0, 0.0)));
+        assert_eq!(offsets.outgoing, Point::from((500.0, 0.0)));
+    }
+
+    #[test]
+    fn window_animations_drop_once_finished() {
+        let mut animations = WindowAnimations {
+            opacity: Some(Animation::new(
+                0.0,
+                1.0,
+                Duration::ZERO,
+                Curve::eased(Duration::from_millis(10), Easing::Linear),
+            )),
+            transform: Some(Animation::new(
+                WindowTransform::scaled(0.5),
+                WindowTransform::default(),
+                Duration::ZERO,
+                Curve::Spring(Spring::default()),
+            )),
+            geometry: None,
+        };
+        assert!(animations.retain_running(Duration::from_millis(20)));
+        assert!(animations.opacity.is_none());
+        let properties = animations.properties(Duration::from_millis(20));
+        assert_eq!(properties.opacity, 1.0);
+        assert!(properties.transform.scale > 0.5 && properties.transform.scale < 1.0);
+        assert!(!animations.retain_running(Duration::from_secs(10)));
+    }
+}
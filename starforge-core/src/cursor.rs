@@ -0,0 +1,383 @@
+//! Pointer cursor images
+//!
+//! Named cursors, requested by clients through wp_cursor_shape_v1 or chosen by the
+//! compositor, are loaded from XCursor themes. The theme and size come from the
+//! configuration, then `XCURSOR_THEME` and `XCURSOR_SIZE`. Cursors with several frames
+//! animate on the presentation clock. When a theme lacks a cursor, its default cursor is
+//! used instead, and a built-in arrow when the theme can't be found at all.
+//!
+//! Clients may also set a surface as cursor image, which is drawn with the hotspot they
+//! gave to `wl_pointer.set_cursor`.
+
+use smithay::{
+    input::pointer::{CursorIcon, CursorImageStatus, CursorImageSurfaceData},
+    reexports::wayland_server::{Resource, protocol::wl_surface::WlSurface},
+    utils::{Logical, Physical, Point, Size},
+    wayland::compositor::with_states,
+};
+use std::{
+    collections::HashMap,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+use tracing::warn;
+
+/// Theme used when neither the configuration nor the environment name one
+pub const DEFAULT_CURSOR_THEME: &str = "default";
+
+/// Nominal cursor size used when neither the configuration nor the environment set one
+pub const DEFAULT_CURSOR_SIZE: u32 = 24;
+
+/// Source of unique [`CursorFrame::key`]s
+static NEXT_FRAME_KEY: AtomicU64 = AtomicU64::new(0);
+
+/// Pick the cursor theme and size: configured values win over `XCURSOR_THEME` and
+/// `XCURSOR_SIZE`, which win over the defaults
+pub fn resolve_cursor_theme(theme: Option<&str>, size: Option<u32>) -> (String, u32) {
+    resolve(
+        theme,
+        size,
+        std::env::var("XCURSOR_THEME").ok().as_deref(),
+        std::env::var("XCURSOR_SIZE").ok().as_deref(),
+    )
+}
+
+fn resolve(
+    theme: Option<&str>,
+    size: Option<u32>,
+    env_theme: Option<&str>,
+    env_size: Option<&str>,
+) -> (String, u32) {
+    let theme = theme
+        .or(env_theme)
+        .filter(|theme| !theme.is_empty())
+        .unwrap_or(DEFAULT_CURSOR_THEME);
+    let size = size
+        .or_else(|| env_size.and_then(|size| size.parse().ok()))
+        .filter(|&size| size > 0)
+        .unwrap_or(DEFAULT_CURSOR_SIZE);
+    (theme.to_string(), size)
+}
+
+/// One image of a cursor
+#[derive(Debug, Clone)]
+pub struct CursorFrame {
+    /// Identifies the pixels, so renderers can upload each frame once
+    pub key: u64,
+    pub size: Size<i32, Physical>,
+    /// Position of the pointer in the image
+    pub hotspot: Point<i32, Physical>,
+    /// How long the frame is shown in animated cursors
+    pub delay: Duration,
+    /// Premultiplied RGBA pixels, row by row
+    pub pixels: Arc<[u8]>,
+}
+
+impl CursorFrame {
+    fn new(width: u32, height: u32, hotspot: (u32, u32), delay: u32, pixels: Vec<u8>) -> Self {
+        Self {
+            key: NEXT_FRAME_KEY.fetch_add(1, Ordering::Relaxed),
+            size: Size::from((width as i32, height as i32)),
+            hotspot: Point::from((hotspot.0 as i32, hotspot.1 as i32)),
+            delay: Duration::from_millis(delay as u64),
+            pixels: pixels.into(),
+        }
+    }
+}
+
+/// A cursor of a theme at one size, possibly animated
+#[derive(Debug)]
+pub struct XCursor {
+    frames: Vec<CursorFrame>,
+    duration: Duration,
+}
+
+impl XCursor {
+    fn new(frames: Vec<CursorFrame>) -> Self {
+        let duration = frames.iter().map(|frame| frame.delay).sum();
+        Self { frames, duration }
+    }
+
+    /// The frames of the nominal size in `images` closest to `size`
+    fn from_images(images: Vec<xcursor::parser::Image>, size: u32) -> Option<Self> {
+        let nominal = images
+            .iter()
+            .map(|image| image.size)
+            .min_by_key(|nominal| nominal.abs_diff(size))?;
+        let frames = images
+            .into_iter()
+            .filter(|image| image.size == nominal)
+            .map(|image| {
+                CursorFrame::new(
+                    image.width,
+                    image.height,
+                    (image.xhot, image.yhot),
+                    image.delay,
+                    image.pixels_rgba,
+                )
+            })
+            .collect();
+        Some(Self::new(frames))
+    }
+
+    /// A plain arrow, for when no theme is installed
+    fn fallback(size: u32) -> Self {
+        let size = size.max(8);
+        let (width, height) = (size * 2 / 3, size);
+        let inside = |x: i64, y: i64| {
+            (0..width as i64).contains(&x) && (0..height as i64).contains(&y) && x <= y && {
+                x * 3 + y < height as i64 * 2
+            }
+        };
+        let mut pixels = Vec::with_capacity((width * height * 4) as usize);
+        for y in 0..height as i64 {
+            for x in 0..width as i64 {
+                let pixel = if !inside(x, y) {
+                    [0, 0, 0, 0]
+                } else if [(-1, 0), (1, 0), (0, -1), (0, 1)]
+                    .iter()
+                    .all(|(dx, dy)| inside(x + dx, y + dy))
+                {
+                    [255, 255, 255, 255]
+                } else {
+                    [0, 0, 0, 255]
+                };
+                pixels.extend_from_slice(&pixel);
+            }
+        }
+        Self::new(vec![CursorFrame::new(width, height, (0, 0), 0, pixels)])
+    }
+
+    /// The frame shown at `time` on the presentation clock
+    pub fn frame(&self, time: Duration) -> &CursorFrame {
+        if self.frames.len() == 1 || self.duration.is_zero() {
+            return &self.frames[0];
+        }
+        let mut offset = Duration::from_nanos((time.as_nanos() % self.duration.as_nanos()) as u64);
+        for frame in &self.frames {
+            if offset < frame.delay {
+                return frame;
+            }
+            offset -= frame.delay;
+        }
+        &self.frames[0]
+    }
+
+    pub fn is_animated(&self) -> bool {
+        self.frames.len() > 1
+    }
+}
+
+/// Loads and caches the cursors of one XCursor theme
+pub struct CursorTheme {
+    name: String,
+    size: u32,
+    theme: xcursor::CursorTheme,
+    cursors: HashMap<CursorIcon, Arc<XCursor>>,
+}
+
+impl CursorTheme {
+    pub fn load(name: &str, size: u32) -> Self {
+        Self {
+            name: name.to_string(),
+            size,
+            theme: xcursor::CursorTheme::load(name),
+            cursors: HashMap::new(),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    /// The cursor for `icon`, falling back to the default cursor
+    pub fn get(&mut self, icon: CursorIcon) -> Arc<XCursor> {
+        if let Some(cursor) = self.cursors.get(&icon) {
+            return cursor.clone();
+        }
+        let cursor = match self.load_icon(icon) {
+            Some(cursor) => Arc::new(cursor),
+            None if icon != CursorIcon::Default => self.get(CursorIcon::Default),
+            None => {
+                warn!(
+                    "Cursor theme {} has no default cursor, using a built-in arrow.",
+                    self.name
+                );
+                Arc::new(XCursor::fallback(self.size))
+            }
+        };
+        self.cursors.insert(icon, cursor.clone());
+        cursor
+    }
+
+    fn load_icon(&self, icon: CursorIcon) -> Option<XCursor> {
+        std::iter::once(icon.name())
+            .chain(icon.alt_names().iter().copied())
+            .find_map(|name| {
+                let path = self.theme.load_icon(name)?;
+                let content = std::fs::read(&path).ok()?;
+                let images = xcursor::parser::parse_xcursor(&content)?;
+                XCursor::from_images(images, self.size)
+            })
+    }
+}
+
+/// What to draw at the pointer position
+#[derive(Debug, Clone)]
+pub enum CursorImage {
+    /// A frame of a themed cursor
+    Named(CursorFrame),
+    /// A client surface, with the hotspot the client set for it
+    Surface {
+        surface: WlSurface,
+        hotspot: Point<i32, Logical>,
+    },
+}
+
+/// The cursor image of the seat
+pub struct CursorState {
+    status: CursorImageStatus,
+    theme: CursorTheme,
+}
+
+impl CursorState {
+    pub fn new(theme: &str, size: u32) -> Self {
+        Self {
+            status: CursorImageStatus::default_named(),
+            theme: CursorTheme::load(theme, size),
+        }
+    }
+
+    pub fn status(&self) -> &CursorImageStatus {
+        &self.status
+    }
+
+    /// Replace the cursor image, as requested by a client or the compositor
+    pub fn set_status(&mut self, status: CursorImageStatus) {
+        self.status = status;
+    }
+
+    /// Switch to another theme or size, dropping every cursor loaded from the old one
+    pub fn set_theme(&mut self, theme: &str, size: u32) {
+        if self.theme.name() != theme || self.theme.size() != size {
+            self.theme = CursorTheme::load(theme, size);
+        }
+    }
+
+    /// The image to draw at `time` on the presentation clock, `None` if hidden
+    pub fn image(&mut self, time: Duration) -> Option<CursorImage> {
+        if let CursorImageStatus::Surface(surface) = &self.status
+            && !surface.is_alive()
+        {
+            self.status = CursorImageStatus::default_named();
+        }
+        match &self.status {
+            CursorImageStatus::Hidden => None,
+            CursorImageStatus::Named(icon) => Some(CursorImage::Named(
+                self.theme.get(*icon).frame(time).clone(),
+            )),
+            CursorImageStatus::Surface(surface) => {
+                let hotspot = with_states(surface, |states| {
+                    states
+                        .data_map
+                        .get::<CursorImageSurfaceData>()
+                        .map(|attributes| attributes.lock().unwrap().hotspot)
+                        .unwrap_or_default()
+                });
+                Some(CursorImage::Surface {
+                    surface: surface.clone(),
+                    hotspot,
+                })
+            }
+        }
+    }
+
+    /// Whether the cursor changes over time and needs redraws while shown
+    pub fn is_animated(&mut self) -> bool {
+        match self.status {
+            CursorImageStatus::Named(icon) => self.theme.get(icon).is_animated(),
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(delay: u32) -> CursorFrame {
+        CursorFrame::new(1, 1, (0, 0), delay, vec![0; 4])
+    }
+
+    #[test]
+    fn configuration_wins_over_the_environment() {
+        assert_eq!(
+            resolve(Some("Adwaita"), Some(32), Some("breeze"), Some("48")),
+            ("Adwaita".to_string(), 32)
+        );
+        assert_eq!(
+            resolve(None, None, Some("breeze"), Some("48")),
+            ("breeze".to_string(), 48)
+        );
+        assert_eq!(
+            resolve(None, None, Some(""), Some("big")),
+            (DEFAULT_CURSOR_THEME.to_string(), DEFAULT_CURSOR_SIZE)
+        );
+    }
+
+    #[test]
+    fn animated_cursors_loop_through_their_frames() {
+        let cursor = XCursor::new(vec![frame(10), frame(20), frame(30)]);
+        let keys: Vec<_> = cursor.frames.iter().map(|frame| frame.key).collect();
+        let at = |ms| cursor.frame(Duration::from_millis(ms)).key;
+
+        assert_eq!(at(0), keys[0]);
+        assert_eq!(at(9), keys[0]);
+        assert_eq!(at(10), keys[1]);
+        assert_eq!(at(35), keys[2]);
+        assert_eq!(at(60), keys[0]);
+        assert!(cursor.is_animated());
+    }
+
+    #[test]
+    fn the_closest_nominal_size_is_picked() {
+        let image = |size| xcursor::parser::Image {
+            size,
+            width: size,
+            height: size,
+            xhot: 1,
+            yhot: 2,
+            delay: 0,
+            pixels_rgba: vec![0; (size * size * 4) as usize],
+            pixels_argb: vec![0; (size * size * 4) as usize],
+        };
+        let cursor = XCursor::from_images(vec![image(24), image(32), image(48)], 36).unwrap();
+        assert_eq!(cursor.frames.len(), 1);
+        assert_eq!(cursor.frames[0].size, Size::from((32, 32)));
+        assert_eq!(cursor.frames[0].hotspot, Point::from((1, 2)));
+    }
+
+    #[test]
+    fn the_fallback_arrow_has_an_outline() {
+        let cursor = XCursor::fallback(24);
+        let frame = cursor.frame(Duration::ZERO);
+        let pixel = |x: i32, y: i32| {
+            let offset = ((y * frame.size.w + x) * 4) as usize;
+            &frame.pixels[offset..offset + 4]
+        };
+        assert_eq!(
+            frame.pixels.len(),
+            (frame.size.w * frame.size.h * 4) as usize
+        );
+        assert_eq!(pixel(0, 0), [0, 0, 0, 255]);
+        assert_eq!(pixel(2, 8), [255, 255, 255, 255]);
+        assert_eq!(pixel(frame.size.w - 1, 0), [0, 0, 0, 0]);
+    }
+}
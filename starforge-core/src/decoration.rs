@@ -0,0 +1,941 @@
+//! Server-side window decorations
+//!
+//! Clients negotiate who draws the title bar through xdg-decoration or the older KDE
+//! server-decoration protocol, and the [`DecorationPolicy`] has the final say. Clients
+//! that use neither protocol decorate themselves.
+//!
+//! Windows the compositor decorates get a title bar on top of their geometry, with
+//! minimize, maximize and close buttons on its right. Dragging the title bar moves the
+//! window, dragging just outside its frame resizes it. Where windows are is kept in a
+//! [`WindowPlacement`] in their surface data; the title bar is part of the placed frame.
+
+use crate::StarforgeState;
+use crate::animation::window_geometry_offset;
+use crate::handlers::{output_logical_size, window_root};
+use smithay::{
+    backend::renderer::utils::RendererSurfaceStateUserData,
+    input::pointer::{CursorIcon, CursorImageStatus},
+    output::Output,
+    reexports::{
+        wayland_protocols::xdg::{
+            decoration::zv1::server::zxdg_toplevel_decoration_v1,
+            shell::server::xdg_toplevel::{self, ResizeEdge},
+        },
+        wayland_protocols_misc::server_decoration::server::org_kde_kwin_server_decoration::{
+            self, OrgKdeKwinServerDecoration,
+        },
+        wayland_server::protocol::wl_surface::WlSurface,
+    },
+    utils::{Logical, Point, Rectangle, Size},
+    wayland::{
+        compositor::with_states,
+        shell::xdg::{SurfaceCachedState, ToplevelSurface, XdgToplevelSurfaceData},
+    },
+};
+use std::sync::{
+    Mutex,
+    atomic::{AtomicU64, Ordering},
+};
+
+/// Height of the title bar in logical pixels
+pub const TITLE_BAR_HEIGHT: i32 = 30;
+
+/// Width of the band outside a decorated window's frame that resizes it
+pub const RESIZE_BORDER: i32 = 6;
+
+/// Source of unique [`TitleBar::key`]s
+static NEXT_TITLE_BAR_KEY: AtomicU64 = AtomicU64::new(0);
+
+/// Who draws a window's decorations
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum DecorationMode {
+    /// The client, inside its own surface
+    Client,
+    /// The compositor, around the client's surface
+    Server,
+}
+
+impl DecorationMode {
+    fn from_xdg(mode: zxdg_toplevel_decoration_v1::Mode) -> Option<Self> {
+        match mode {
+            zxdg_toplevel_decoration_v1::Mode::ClientSide => Some(Self::Client),
+            zxdg_toplevel_decoration_v1::Mode::ServerSide => Some(Self::Server),
+            _ => None,
+        }
+    }
+
+    fn to_xdg(self) -> zxdg_toplevel_decoration_v1::Mode {
+        match self {
+            Self::Client => zxdg_toplevel_decoration_v1::Mode::ClientSide,
+            Self::Server => zxdg_toplevel_decoration_v1::Mode::ServerSide,
+        }
+    }
+
+    fn from_kde(mode: org_kde_kwin_server_decoration::Mode) -> Option<Self> {
+        match mode {
+            // Undecorated windows are left to the policy like any other
+            org_kde_kwin_server_decoration::Mode::Client => Some(Self::Client),
+            org_kde_kwin_server_decoration::Mode::Server => Some(Self::Server),
+            _ => None,
+        }
+    }
+
+    fn to_kde(self) -> org_kde_kwin_server_decoration::Mode {
+        match self {
+            Self::Client => org_kde_kwin_server_decoration::Mode::Client,
+            Self::Server => org_kde_kwin_server_decoration::Mode::Server,
+        }
+    }
+}
+
+/// How the decoration mode clients ask for is settled
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum DecorationPolicy {
+    /// The compositor decorates windows unless their client asks to do it
+    #[default]
+    PreferServer,
+    /// Clients decorate their windows unless they ask the compositor to do it
+    PreferClient,
+    /// The compositor decorates every window that negotiates, whatever its client asks
+    ForceServer,
+}
+
+impl DecorationPolicy {
+    /// The mode a window gets when its client asks for `requested`, or leaves the choice
+    /// to the compositor with `None`
+    pub fn mode(self, requested: Option<DecorationMode>) -> DecorationMode {
+        match self {
+            Self::PreferServer => requested.unwrap_or(DecorationMode::Server),
+            Self::PreferClient => requested.unwrap_or(DecorationMode::Client),
+            Self::ForceServer => DecorationMode::Server,
+        }
+    }
+}
+
+/// Buttons on the right of a title bar
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum TitleBarButton {
+    Minimize,
+    Maximize,
+    Close,
+}
+
+impl TitleBarButton {
+    /// Every button, from left to right
+    pub const ALL: [Self; 3] = [Self::Minimize, Self::Maximize, Self::Close];
+
+    /// Where the button is in the title bar at `title_bar`; square, counted from the
+    /// right edge
+    pub fn rect(self, title_bar: Rectangle<i32, Logical>) -> Rectangle<i32, Logical> {
+        let from_right = match self {
+            Self::Minimize => 3,
+            Self::Maximize => 2,
+            Self::Close => 1,
+        };
+        let size = title_bar.size.h;
+        Rectangle::new(
+            (
+                title_bar.loc.x + title_bar.size.w - size * from_right,
+                title_bar.loc.y,
+            )
+                .into(),
+            (size, size).into(),
+        )
+    }
+}
+
+/// Part of a decorated window's frame
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum DecorationHit {
+    /// The title bar outside of its buttons, which moves the window
+    TitleBar,
+    Button(TitleBarButton),
+    /// The band around the frame, which resizes the window from these edges
+    Edge(ResizeEdge),
+}
+
+impl DecorationHit {
+    /// Cursor shown over this part of the frame
+    pub fn cursor(self) -> CursorIcon {
+        match self {
+            Self::TitleBar | Self::Button(_) => CursorIcon::Default,
+            Self::Edge(edge) => match edge {
+                ResizeEdge::Top => CursorIcon::NResize,
+                ResizeEdge::Bottom => CursorIcon::SResize,
+                ResizeEdge::Left => CursorIcon::WResize,
+                ResizeEdge::Right => CursorIcon::EResize,
+                ResizeEdge::TopLeft => CursorIcon::NwResize,
+                ResizeEdge::TopRight => CursorIcon::NeResize,
+                ResizeEdge::BottomLeft => CursorIcon::SwResize,
+                ResizeEdge::BottomRight => CursorIcon::SeResize,
+                _ => CursorIcon::Default,
+            },
+        }
+    }
+}
+
+/// The title bar above a window with geometry `geometry`
+pub fn title_bar_rect(geometry: Rectangle<i32, Logical>) -> Rectangle<i32, Logical> {
+    Rectangle::new(
+        (geometry.loc.x, geometry.loc.y - TITLE_BAR_HEIGHT).into(),
+        (geometry.size.w, TITLE_BAR_HEIGHT).into(),
+    )
+}
+
+/// The part of the decorations of a window with geometry `geometry` at `point`
+///
+/// `None` inside the client's surface and away from the window.
+pub fn decoration_hit(
+    geometry: Rectangle<i32, Logical>,
+    point: Point<f64, Logical>,
+) -> Option<DecorationHit> {
+    let title_bar = title_bar_rect(geometry);
+    let frame = title_bar.merge(geometry);
+    let outer = Rectangle::new(
+        frame.loc - Point::from((RESIZE_BORDER, RESIZE_BORDER)),
+        frame.size + Size::from((RESIZE_BORDER * 2, RESIZE_BORDER * 2)),
+    );
+    if !outer.to_f64().contains(point) {
+        return None;
+    }
+    if !frame.to_f64().contains(point) {
+        let frame = frame.to_f64();
+        let mut edges = 0;
+        if point.y < frame.loc.y {
+            edges |= ResizeEdge::Top as u32;
+        } else if point.y >= frame.loc.y + frame.size.h {
+            edges |= ResizeEdge::Bottom as u32;
+        }
+        if point.x < frame.loc.x {
+            edges |= ResizeEdge::Left as u32;
+        } else if point.x >= frame.loc.x + frame.size.w {
+            edges |= ResizeEdge::Right as u32;
+        }
+        return ResizeEdge::try_from(edges).ok().map(DecorationHit::Edge);
+    }
+    if !title_bar.to_f64().contains(point) {
+        return None;
+    }
+    let button = TitleBarButton::ALL
+        .into_iter()
+        .find(|button| button.rect(title_bar).to_f64().contains(point));
+    Some(button.map_or(DecorationHit::TitleBar, DecorationHit::Button))
+}
+
+/// Geometry of a window resized from `edges` by dragging the pointer by `delta`, from
+/// geometry `initial`, keeping at least `min_size`
+///
+/// The edges opposite the dragged ones stay where they are.
+pub fn resized_geometry(
+    initial: Rectangle<i32, Logical>,
+    edges: ResizeEdge,
+    delta: Point<f64, Logical>,
+    min_size: Size<i32, Logical>,
+) -> Rectangle<i32, Logical> {
+    let edges = edges as u32;
+    let delta = delta.to_i32_round::<i32>();
+    let mut geometry = initial;
+    if edges & ResizeEdge::Left as u32 != 0 {
+        geometry.size.w = (initial.size.w - delta.x).max(min_size.w);
+        geometry.loc.x = initial.loc.x + initial.size.w - geometry.size.w;
+    } else if edges & ResizeEdge::Right as u32 != 0 {
+        geometry.size.w = (initial.size.w + delta.x).max(min_size.w);
+    }
+    if edges & ResizeEdge::Top as u32 != 0 {
+        geometry.size.h = (initial.size.h - delta.y).max(min_size.h);
+        geometry.loc.y = initial.loc.y + initial.size.h - geometry.size.h;
+    } else if edges & ResizeEdge::Bottom as u32 != 0 {
+        geometry.size.h = (initial.size.h + delta.y).max(min_size.h);
+    }
+    geometry
+}
+
+/// Where a window is placed, kept in its surface data
+#[derive(Debug)]
+pub struct WindowPlacement {
+    /// Top left corner of the window's frame, its title bar if it has one
+    pub location: Point<i32, Logical>,
+    /// Where the frame was before the window was maximized
+    pub restore: Option<Point<i32, Logical>>,
+    /// Hidden until restored with [`StarforgeState::unminimize_window`]
+    pub minimized: bool,
+    /// Identifies the window's title bar to the renderer
+    key: u64,
+}
+
+impl Default for WindowPlacement {
+    fn default() -> Self {
+        Self {
+            location: Point::from((0, 0)),
+            restore: None,
+            minimized: false,
+            key: NEXT_TITLE_BAR_KEY.fetch_add(1, Ordering::Relaxed),
+        }
+    }
+}
+
+/// Run `f` with the placement of window `surface`
+pub fn with_placement<T>(surface: &WlSurface, f: impl FnOnce(&mut WindowPlacement) -> T) -> T {
+    with_states(surface, |states| {
+        states
+            .data_map
+            .insert_if_missing_threadsafe(|| Mutex::new(WindowPlacement::default()));
+        let mut placement = states
+            .data_map
+            .get::<Mutex<WindowPlacement>>()
+            .unwrap()
+            .lock()
+            .unwrap();
+        f(&mut placement)
+    })
+}
+
+/// Whether window `surface` is minimized
+pub fn is_minimized(surface: &WlSurface) -> bool {
+    with_states(surface, |states| {
+        states
+            .data_map
+            .get::<Mutex<WindowPlacement>>()
+            .is_some_and(|placement| placement.lock().unwrap().minimized)
+    })
+}
+
+/// Decoration negotiation of a window, kept in its surface data
+#[derive(Debug, Default)]
+struct DecorationData {
+    /// The mode its client asked for, `None` when it leaves the choice to the compositor
+    requested: Option<DecorationMode>,
+    /// The window's KDE server-decoration object
+    kde: Option<OrgKdeKwinServerDecoration>,
+    /// The mode last sent through the KDE object
+    kde_mode: Option<DecorationMode>,
+}
+
+fn with_decoration_data<T>(surface: &WlSurface, f: impl FnOnce(&mut DecorationData) -> T) -> T {
+    with_states(surface, |states| {
+        states
+            .data_map
+            .insert_if_missing_threadsafe(|| Mutex::new(DecorationData::default()));
+        let mut data = states
+            .data_map
+            .get::<Mutex<DecorationData>>()
+            .unwrap()
+            .lock()
+            .unwrap();
+        f(&mut data)
+    })
+}
+
+/// A title bar to draw, as seen by the renderer
+#[derive(Clone, Debug, PartialEq)]
+pub struct TitleBar {
+    /// Stays the same for a window as long as it exists
+    pub key: u64,
+    /// Where the title bar is on the output
+    pub geometry: Rectangle<i32, Logical>,
+    pub title: String,
+    /// The button under the pointer
+    pub hovered: Option<TitleBarButton>,
+    /// The button being clicked
+    pub pressed: Option<TitleBarButton>,
+    /// Maximized windows show a restore button
+    pub maximized: bool,
+}
+
+/// A drag started on a window's decorations
+#[derive(Clone, Debug)]
+enum DecorationGrab {
+    Move {
+        window: WlSurface,
+        start: Point<f64, Logical>,
+        initial: Point<i32, Logical>,
+    },
+    Resize {
+        window: WlSurface,
+        start: Point<f64, Logical>,
+        initial: Rectangle<i32, Logical>,
+        edges: ResizeEdge,
+    },
+    /// Buttons act when released over them
+    Button {
+        window: WlSurface,
+        button: TitleBarButton,
+    },
+}
+
+/// Decoration policy and pointer interaction with title bars
+#[derive(Debug, Default)]
+pub struct DecorationState {
+    policy: DecorationPolicy,
+    grab: Option<DecorationGrab>,
+    /// The window decoration under the pointer
+    hover: Option<(WlSurface, DecorationHit)>,
+}
+
+impl DecorationState {
+    pub fn policy(&self) -> DecorationPolicy {
+        self.policy
+    }
+}
+
+impl StarforgeState {
+    /// Change the decoration policy, settling the mode of existing windows again
+    pub fn set_decoration_policy(&mut self, policy: DecorationPolicy) {
+        self.decoration_state.policy = policy;
+        for toplevel in self.xdg_shell_state.toplevel_surfaces().to_vec() {
+            let has_xdg_decoration =
+                toplevel.with_pending_state(|state| state.decoration_mode.is_some());
+            if has_xdg_decoration {
+                self.negotiate_xdg_decoration(&toplevel);
+            }
+            self.negotiate_kde_decoration(toplevel.wl_surface());
+        }
+    }
+
+    /// Record the xdg-decoration mode the client of `toplevel` asks for and tell it the
+    /// one it gets
+    pub(crate) fn request_xdg_decoration(
+        &mut self,
+        toplevel: &ToplevelSurface,
+        requested: Option<zxdg_toplevel_decoration_v1::Mode>,
+    ) {
+        with_decoration_data(toplevel.wl_surface(), |data| {
+            data.requested = requested.and_then(DecorationMode::from_xdg);
+        });
+        self.negotiate_xdg_decoration(toplevel);
+    }
+
+    fn negotiate_xdg_decoration(&mut self, toplevel: &ToplevelSurface) {
+        let requested = with_decoration_data(toplevel.wl_surface(), |data| data.requested);
+        let mode = self.decoration_state.policy.mode(requested);
+        toplevel.with_pending_state(|state| state.decoration_mode = Some(mode.to_xdg()));
+        if toplevel.is_initial_configure_sent() {
+            toplevel.send_pending_configure();
+        }
+    }
+
+    /// Record the KDE server-decoration object of `surface` and the mode its client asks
+    /// for, and tell it the one it gets
+    pub(crate) fn request_kde_decoration(
+        &mut self,
+        surface: &WlSurface,
+        decoration: &OrgKdeKwinServerDecoration,
+        requested: Option<org_kde_kwin_server_decoration::Mode>,
+    ) {
+        with_decoration_data(surface, |data| {
+            data.kde = Some(decoration.clone());
+            data.requested = requested.and_then(DecorationMode::from_kde);
+        });
+        self.negotiate_kde_decoration(surface);
+    }
+
+    fn negotiate_kde_decoration(&mut self, surface: &WlSurface) {
+        let policy = self.decoration_state.policy;
+        let changed = with_decoration_data(surface, |data| {
+            let Some(decoration) = &data.kde else {
+                return false;
+            };
+            let mode = policy.mode(data.requested);
+            decoration.mode(mode.to_kde());
+            data.kde_mode.replace(mode) != Some(mode)
+        });
+        if changed {
+            self.damage_surface(surface);
+        }
+    }
+
+    /// Forget the KDE server-decoration object of `surface`, it decorates itself again
+    pub(crate) fn release_kde_decoration(&mut self, surface: &WlSurface) {
+        with_decoration_data(surface, |data| {
+            data.kde = None;
+            data.kde_mode = None;
+        });
+        self.damage_surface(surface);
+    }
+
+    /// Whether the compositor draws the decorations of window `window`
+    ///
+    /// Fullscreen windows are never decorated.
+    pub fn is_server_decorated(&self, window: &WlSurface) -> bool {
+        if self.is_fullscreen(window) {
+            return false;
+        }
+        let xdg_mode = with_states(window, |states| {
+            states
+                .data_map
+                .get::<XdgToplevelSurfaceData>()
+                .and_then(|data| data.lock().unwrap().current.decoration_mode)
+        });
+        let mode = match xdg_mode {
+            Some(mode) => DecorationMode::from_xdg(mode),
+            None => with_decoration_data(window, |data| data.kde_mode),
+        };
+        mode == Some(DecorationMode::Server)
+    }
+
+    /// Where the geometry of window `window` is on the output, below its title bar if it
+    /// has one
+    pub fn window_geometry(&self, window: &WlSurface) -> Rectangle<i32, Logical> {
+        // X11 windows place themselves
+        #[cfg(feature = "xwayland")]
+        if let Some(window) = self.xwayland_state.window(window) {
+            return window.geometry();
+        }
+        let mut location = with_placement(window, |placement| placement.location);
+        if self.is_server_decorated(window) {
+            location.y += TITLE_BAR_HEIGHT;
+        }
+        Rectangle::new(location, window_size(window))
+    }
+
+    /// Where the main surface of `window` is on the output, which is off its geometry by
+    /// the client's shadows and decorations
+    pub fn window_surface_location(&self, window: &WlSurface) -> Point<i32, Logical> {
+        self.window_geometry(window).loc - window_geometry_offset(window)
+    }
+
+    /// Title bars drawn on `output`, bottom to top
+    pub fn title_bars(&self, output: &Output) -> Vec<TitleBar> {
+        if !self.shows_windows(output) {
+            return Vec::new();
+        }
+        let visible = self.visible_surfaces(output);
+        self.window_surfaces()
+            .into_iter()
+            .filter(|window| visible.contains(window) && self.is_server_decorated(window))
+            .map(|window| {
+                let hovered = match &self.decoration_state.hover {
+                    Some((hovered, DecorationHit::Button(button))) if *hovered == window => {
+                        Some(*button)
+                    }
+                    _ => None,
+                };
+                let pressed = match &self.decoration_state.grab {
+                    Some(DecorationGrab::Button {
+                        window: grabbed,
+                        button,
+                    }) if *grabbed == window => Some(*button),
+                    _ => None,
+                };
+                TitleBar {
+                    key: with_placement(&window, |placement| placement.key),
+                    geometry: title_bar_rect(self.window_geometry(&window)),
+                    title: window_title(&window),
+                    hovered,
+                    pressed,
+                    maximized: is_maximized(&window),
+                }
+            })
+            .collect()
+    }
+
+    /// The decorated window and the part of its decorations at `location` on the
+    /// [window output](Self::window_output)
+    fn decoration_at(&self, location: Point<f64, Logical>) -> Option<(WlSurface, DecorationHit)> {
+        let output = self.window_output()?;
+        let visible = self.visible_surfaces(output);
+        // Later windows are drawn on top
+        for window in self.window_surfaces().into_iter().rev() {
+            if !visible.contains(&window) {
+                continue;
+            }
+            let geometry = self.window_geometry(&window);
+            if self.is_server_decorated(&window)
+                && let Some(hit) = decoration_hit(geometry, location)
+            {
+                return Some((window, hit));
+            }
+            if geometry.to_f64().contains(location) {
+                return None;
+            }
+        }
+        None
+    }
+
+    /// The pointer moved to `location` on the first output
+    ///
+    /// Moves or resizes the window whose decorations are being dragged. Returns whether the
+    /// pointer is over decorations, or dragging them, rather than a client.
+    pub fn decoration_pointer_motion(&mut self, location: Point<f64, Logical>) -> bool {
+        match self.decoration_state.grab.clone() {
+            Some(DecorationGrab::Move {
+                window,
+                start,
+                initial,
+            }) => {
+                let location = initial + (location - start).to_i32_round();
+                with_placement(&window, |placement| placement.location = location);
+                self.damage_all_outputs();
+                true
+            }
+            Some(DecorationGrab::Resize {
+                window,
+                start,
+                initial,
+                edges,
+            }) => {
+                let geometry =
+                    resized_geometry(initial, edges, location - start, min_size(&window));
+                if let Some(toplevel) = self.toplevel(&window) {
+                    toplevel.with_pending_state(|state| {
+                        state.states.set(xdg_toplevel::State::Resizing);
+                        state.size = Some(geometry.size);
+                    });
+                    toplevel.send_pending_configure();
+                }
+                // Resizing from the top or left moves the frame too
+                let location = geometry.loc - Point::from((0, TITLE_BAR_HEIGHT));
+                with_placement(&window, |placement| placement.location = location);
+                self.damage_all_outputs();
+                true
+            }
+            Some(DecorationGrab::Button { .. }) => true,
+            None => {
+                let hover = self.decoration_at(location);
+                if hover != self.decoration_state.hover {
+                    let cursor = hover.as_ref().map(|(_, hit)| hit.cursor());
+                    let was_over_edge = matches!(
+                        self.decoration_state.hover,
+                        Some((_, DecorationHit::Edge(_)))
+                    );
+                    if let Some(cursor) = cursor {
+                        self.cursor_state
+                            .set_status(CursorImageStatus::Named(cursor));
+                    } else if was_over_edge {
+                        self.cursor_state
+                            .set_status(CursorImageStatus::default_named());
+                    }
+                    self.decoration_state.hover = hover;
+                    self.damage_all_outputs();
+                }
+                self.decoration_state.hover.is_some()
+            }
+        }
+    }
+
+    /// The left pointer button was pressed or released at `location` on the first output
+    ///
+    /// Starts and ends drags of decorations, and clicks title bar buttons. Returns whether
+    /// the button was meant for decorations rather than a client.
+    pub fn decoration_pointer_button(
+        &mut self,
+        location: Point<f64, Logical>,
+        pressed: bool,
+    ) -> bool {
+        if !pressed {
+            let Some(grab) = self.decoration_state.grab.take() else {
+                return false;
+            };
+            match grab {
+                DecorationGrab::Button { window, button } => {
+                    if self.decoration_at(location)
+                        == Some((window.clone(), DecorationHit::Button(button)))
+                    {
+                        self.title_bar_button_clicked(&window, button);
+                    }
+                }
+                DecorationGrab::Resize { window, .. } => {
+                    if let Some(toplevel) = self.toplevel(&window) {
+                        toplevel.with_pending_state(|state| {
+                            state.states.unset(xdg_toplevel::State::Resizing);
+                        });
+                        toplevel.send_pending_configure();
+                    }
+                }
+                DecorationGrab::Move { .. } => {}
+            }
+            self.damage_all_outputs();
+            return true;
+        }
+
+        let Some((window, hit)) = self.decoration_at(location) else {
+            return false;
+        };
+        self.decoration_state.grab = Some(match hit {
+            DecorationHit::TitleBar => DecorationGrab::Move {
+                initial: with_placement(&window, |placement| placement.location),
+                window,
+                start: location,
+            },
+            DecorationHit::Button(button) => DecorationGrab::Button { window, button },
+            DecorationHit::Edge(edges) => DecorationGrab::Resize {
+                initial: self.window_geometry(&window),
+                window,
+                start: location,
+                edges,
+            },
+        });
+        self.damage_all_outputs();
+        true
+    }
+
+    fn title_bar_button_clicked(&mut self, window: &WlSurface, button: TitleBarButton) {
+        let Some(toplevel) = self.toplevel(window) else {
+            return;
+        };
+        match button {
+            TitleBarButton::Close => toplevel.send_close(),
+            TitleBarButton::Maximize => {
+                let maximized = is_maximized(window);
+                self.set_maximized(&toplevel, !maximized);
+            }
+            TitleBarButton::Minimize => self.minimize_window(window),
+        }
+    }
+
+    /// Maximize `toplevel` to the [window output](Self::window_output), or restore it to
+    /// where it was
+    pub fn set_maximized(&mut self, toplevel: &ToplevelSurface, maximized: bool) {
+        let window = toplevel.wl_surface();
+        if maximized {
+            let Some(mut size) = self.window_output().and_then(output_logical_size) else {
+                return;
+            };
+            if self.is_server_decorated(window) {
+                size.h -= TITLE_BAR_HEIGHT;
+            }
+            with_placement(window, |placement| {
+                placement.restore.get_or_insert(placement.location);
+                placement.location = Point::from((0, 0));
+            });
+            toplevel.with_pending_state(|state| {
+                state.states.set(xdg_toplevel::State::Maximized);
+                state.size = Some(size);
+            });
+        } else {
+            with_placement(window, |placement| {
+                if let Some(location) = placement.restore.take() {
+                    placement.location = location;
+                }
+            });
+            toplevel.with_pending_state(|state| {
+                state.states.unset(xdg_toplevel::State::Maximized);
+                state.size = None;
+            });
+        }
+        if toplevel.is_initial_configure_sent() {
+            toplevel.send_pending_configure();
+        }
+        self.damage_all_outputs();
+    }
+
+    /// Hide window `window` until it's restored with [`Self::unminimize_window`]
+    pub fn minimize_window(&mut self, window: &WlSurface) {
+        with_placement(window, |placement| placement.minimized = true);
+        self.damage_all_outputs();
+    }
+
+    /// Show a minimized window again
+    pub fn unminimize_window(&mut self, window: &WlSurface) {
+        with_placement(window, |placement| placement.minimized = false);
+        self.damage_surface(window);
+    }
+
+    /// Stop dragging the decorations of a window that closed
+    pub(crate) fn decorated_window_closed(&mut self, window: &WlSurface) {
+        let grabbed = match &self.decoration_state.grab {
+            Some(
+                DecorationGrab::Move {
+                    window: grabbed, ..
+                }
+                | DecorationGrab::Resize {
+                    window: grabbed, ..
+                }
+                | DecorationGrab::Button {
+                    window: grabbed, ..
+                },
+            ) => grabbed == window,
+            None => false,
+        };
+        if grabbed {
+            self.decoration_state.grab = None;
+        }
+        if matches!(&self.decoration_state.hover, Some((hovered, _)) if hovered == window) {
+            self.decoration_state.hover = None;
+        }
+    }
+
+    /// The xdg toplevel of root surface `window`
+    fn toplevel(&self, window: &WlSurface) -> Option<ToplevelSurface> {
+        let window = window_root(window);
+        self.xdg_shell_state
+            .toplevel_surfaces()
+            .iter()
+            .find(|toplevel| *toplevel.wl_surface() == window)
+            .cloned()
+    }
+}
+
+/// Size of a window's geometry, as set by its client or else that of its main surface
+fn window_size(window: &WlSurface) -> Size<i32, Logical> {
+    with_states(window, |states| {
+        let geometry = states
+            .cached_state
+            .get::<SurfaceCachedState>()
+            .current()
+            .geometry;
+        geometry.map(|geometry| geometry.size).unwrap_or_else(|| {
+            states
+                .data_map
+                .get::<RendererSurfaceStateUserData>()
+                .and_then(|data| data.lock().unwrap().surface_size())
+                .unwrap_or_default()
+        })
+    })
+}
+
+/// The smallest size a window may be resized to: what its client asks for, and room for
+/// the title bar buttons
+fn min_size(window: &WlSurface) -> Size<i32, Logical> {
+    let client_min = with_states(window, |states| {
+        states
+            .cached_state
+            .get::<SurfaceCachedState>()
+            .current()
+            .min_size
+    });
+    let buttons = TITLE_BAR_HEIGHT * TitleBarButton::ALL.len() as i32;
+    Size::from((client_min.w.max(buttons), client_min.h.max(1)))
+}
+
+fn window_title(window: &WlSurface) -> String {
+    with_states(window, |states| {
+        states
+            .data_map
+            .get::<XdgToplevelSurfaceData>()
+            .and_then(|data| data.lock().unwrap().title.clone())
+            .unwrap_or_default()
+    })
+}
+
+fn is_maximized(window: &WlSurface) -> bool {
+    with_states(window, |states| {
+        states
+            .data_map
+            .get::<XdgToplevelSurfaceData>()
+            .is_some_and(|data| {
+                data.lock()
+                    .unwrap()
+                    .current
+                    .states
+                    .contains(xdg_toplevel::State::Maximized)
+            })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn geometry() -> Rectangle<i32, Logical> {
+        Rectangle::new((100, 100).into(), (400, 300).into())
+    }
+
+    fn hit(x: f64, y: f64) -> Option<DecorationHit> {
+        decoration_hit(geometry(), Point::from((x, y)))
+    }
+
+    #[test]
+    fn policies_settle_modes() {
+        use DecorationMode::*;
+        let policy = DecorationPolicy::PreferServer;
+        assert_eq!(policy.mode(None), Server);
+        assert_eq!(policy.mode(Some(Client)), Client);
+        let policy = DecorationPolicy::PreferClient;
+        assert_eq!(policy.mode(None), Client);
+        assert_eq!(policy.mode(Some(Server)), Server);
+        let policy = DecorationPolicy::ForceServer;
+        assert_eq!(policy.mode(None), Server);
+        assert_eq!(policy.mode(Some(Client)), Server);
+    }
+
+    #[test]
+    fn the_title_bar_sits_above_the_geometry() {
+        assert_eq!(
+            title_bar_rect(geometry()),
+            Rectangle::new((100, 70).into(), (400, TITLE_BAR_HEIGHT).into())
+        );
+        assert_eq!(hit(150.0, 80.0), Some(DecorationHit::TitleBar));
+        // The client's own surface is not part of the decorations
+        assert_eq!(hit(150.0, 150.0), None);
+        assert_eq!(hit(50.0, 50.0), None);
+    }
+
+    #[test]
+    fn buttons_line_up_on_the_right() {
+        let close = TitleBarButton::Close.rect(title_bar_rect(geometry()));
+        assert_eq!(close.loc.x + close.size.w, 500);
+        assert_eq!(
+            hit(495.0, 80.0),
+            Some(DecorationHit::Button(TitleBarButton::Close))
+        );
+        assert_eq!(
+            hit(465.0, 80.0),
+            Some(DecorationHit::Button(TitleBarButton::Maximize))
+        );
+        assert_eq!(
+            hit(435.0, 80.0),
+            Some(DecorationHit::Button(TitleBarButton::Minimize))
+        );
+        assert_eq!(hit(405.0, 80.0), Some(DecorationHit::TitleBar));
+    }
+
+    #[test]
+    fn the_band_around_the_frame_resizes() {
+        assert_eq!(
+            hit(98.0, 200.0),
+            Some(DecorationHit::Edge(ResizeEdge::Left))
+        );
+        assert_eq!(
+            hit(502.0, 200.0),
+            Some(DecorationHit::Edge(ResizeEdge::Right))
+        );
+        assert_eq!(hit(300.0, 67.0), Some(DecorationHit::Edge(ResizeEdge::Top)));
+        assert_eq!(
+            hit(300.0, 401.0),
+            Some(DecorationHit::Edge(ResizeEdge::Bottom))
+        );
+        assert_eq!(
+            hit(97.0, 66.0),
+            Some(DecorationHit::Edge(ResizeEdge::TopLeft))
+        );
+        assert_eq!(
+            hit(503.0, 404.0),
+            Some(DecorationHit::Edge(ResizeEdge::BottomRight))
+        );
+        assert_eq!(hit(300.0, 100.0 - 30.0 - RESIZE_BORDER as f64 - 1.0), None);
+    }
+
+    #[test]
+    fn resizing_keeps_the_opposite_edges() {
+        let min = Size::from((90, 1));
+        let resized = resized_geometry(
+            geometry(),
+            ResizeEdge::TopLeft,
+            Point::from((-20.0, 10.0)),
+            min,
+        );
+        assert_eq!(resized, Rectangle::new((80, 110).into(), (420, 290).into()));
+        let resized = resized_geometry(
+            geometry(),
+            ResizeEdge::BottomRight,
+            Point::from((15.4, -20.0)),
+            min,
+        );
+        assert_eq!(
+            resized,
+            Rectangle::new((100, 100).into(), (415, 280).into())
+        );
+    }
+
+    #[test]
+    fn resizing_stops_at_the_minimum_size() {
+        let resized = resized_geometry(
+            geometry(),
+            ResizeEdge::Left,
+            Point::from((1000.0, 0.0)),
+            Size::from((90, 1)),
+        );
+        assert_eq!(resized, Rectangle::new((410, 100).into(), (90, 300).into()));
+    }
+}
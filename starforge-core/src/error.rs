@@ -1,6 +1,9 @@
 use smithay::{
     backend::winit::{self, WinitEventLoop},
-    reexports::{calloop, wayland_server},
+    reexports::{
+        calloop::{self, timer::Timer},
+        wayland_server,
+    },
 };
 use thiserror::Error;
 
@@ -13,10 +16,17 @@ pub enum StarforgeError {
     WaylandServerInitError(#[from] wayland_server::backend::InitError),
     #[error("Calloop Winit Insert Error: {0}")]
     CalloopWinitInsertError(#[from] calloop::InsertError<WinitEventLoop>),
+    #[error("Calloop Timer Insert Error: {0}")]
+    CalloopTimerInsertError(#[from] calloop::InsertError<Timer>),
     #[error("Renderer Error: {0}")]
     RendererError(String),
     #[error("Output Not Found")]
     OutputNotFound,
+    #[error("GPU Device Lost")]
+    DeviceLost,
+    #[cfg(feature = "xwayland")]
+    #[error("XWayland Error: {0}")]
+    XWaylandError(String),
 }
 
 /// Result type for Starforge
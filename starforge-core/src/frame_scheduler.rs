@@ -0,0 +1,588 @@
+//! Per-output frame scheduling
+//!
+//! Every output renders at most one frame per refresh cycle, and only when something on it
+//! changed. A frame starts just in time for the vblank it is meant for: the next
+//! presentation is predicted from the last one the backend reported and the refresh
+//! interval, and rendering starts the longest recent render time plus a safety margin
+//! before it. Starting any earlier only adds latency. While an output tears, frames
+//! start as soon as they are damaged instead.
+//!
+//! Frame callbacks go out once a frame was presented, and only to the surfaces drawn in
+//! it, so clients that aren't shown stop rendering instead of spinning. Presentation
+//! feedback requested with the content of a frame is answered the same way.
+
+use crate::animation::Clock;
+use smithay::{
+    output::{Output, WeakOutput},
+    reexports::wayland_server::protocol::wl_surface::WlSurface,
+    wayland::{
+        compositor::{SurfaceAttributes, TraversalAction, with_states, with_surface_tree_downward},
+        presentation::{PresentationFeedbackCachedState, PresentationFeedbackCallback},
+    },
+};
+use std::{collections::VecDeque, time::Duration};
+
+/// Number of recent render times the render time estimate covers
+const RENDER_TIME_SAMPLES: usize = 16;
+
+/// What an output's scheduler waits for
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Phase {
+    /// Nothing changed since the last frame
+    Idle,
+    /// A frame starts rendering at `start`, to be presented at `presentation`
+    Scheduled {
+        start: Duration,
+        presentation: Duration,
+    },
+    /// A frame was submitted and waits for its presentation feedback
+    Pending,
+}
+
+/// What clients are owed once a frame leaves the pipeline
+#[derive(Debug, Default)]
+pub struct FrameContent {
+    /// Surfaces drawn in the frame, which are owed frame callbacks
+    pub surfaces: Vec<WlSurface>,
+    /// Presentation feedback requested for the content drawn in the frame
+    pub feedback: Vec<PresentationFeedbackCallback>,
+}
+
+/// What a frame that left the pipeline leaves to do
+#[derive(Debug, Default)]
+pub struct FrameDone {
+    pub content: FrameContent,
+    /// When the next frame should start rendering, if the output was damaged meanwhile
+    pub next_start: Option<Duration>,
+}
+
+/// Decides when one output renders its frames
+///
+/// All times are on the presentation clock, `CLOCK_MONOTONIC`.
+#[derive(Debug)]
+pub struct FrameScheduler {
+    /// Interval between vblanks, zero if unknown
+    refresh: Duration,
+    safety_margin: Duration,
+    /// Frames are presented without waiting for a vblank
+    tearing: bool,
+    last_presentation: Option<Duration>,
+    render_times: VecDeque<Duration>,
+    damaged: bool,
+    phase: Phase,
+    /// What the pending frame shows
+    frame_content: FrameContent,
+}
+
+impl FrameScheduler {
+    pub fn new(refresh: Duration, safety_margin: Duration) -> Self {
+        Self {
+            refresh,
+            safety_margin,
+            tearing: false,
+            last_presentation: None,
+            render_times: VecDeque::with_capacity(RENDER_TIME_SAMPLES),
+            damaged: false,
+            phase: Phase::Idle,
+            frame_content: FrameContent::default(),
+        }
+    }
+
+    pub fn refresh(&self) -> Duration {
+        self.refresh
+    }
+
+    /// Change the refresh interval, e.g. after a mode change. Zero renders frames as
+    /// soon as they are damaged.
+    pub fn set_refresh(&mut self, refresh: Duration) {
+        self.refresh = refresh;
+    }
+
+    pub fn set_safety_margin(&mut self, safety_margin: Duration) {
+        self.safety_margin = safety_margin;
+    }
+
+    /// Set whether frames are presented as soon as they are ready, without waiting for
+    /// a vblank
+    pub fn set_tearing(&mut self, tearing: bool) {
+        self.tearing = tearing;
+    }
+
+    /// The longest of the recent render times
+    pub fn render_time(&self) -> Duration {
+        self.render_times.iter().copied().max().unwrap_or_default()
+    }
+
+    /// Whether something changed that the last frame doesn't show
+    pub fn is_damaged(&self) -> bool {
+        self.damaged
+    }
+
+    /// The earliest presentation a frame started at `now` can make
+    ///
+    /// Without presentation feedback or while tearing, that is as soon as it can be
+    /// rendered.
+    pub fn predict_presentation(&self, now: Duration) -> Duration {
+        let ready = now + self.render_time() + self.safety_margin;
+        match self.last_presentation {
+            Some(last) if !self.refresh.is_zero() && !self.tearing => {
+                if ready <= last {
+                    return last + self.refresh;
+                }
+                let cycles = (ready - last).as_nanos().div_ceil(self.refresh.as_nanos());
+                last + Duration::from_nanos((self.refresh.as_nanos() * cycles) as u64)
+            }
+            _ => ready,
+        }
+    }
+
+    /// Mark the output as changed at `now`
+    ///
+    /// Returns when the next frame should start rendering if this scheduled one. While a
+    /// frame is already scheduled or waiting to be presented, the damage goes into the
+    /// next frame after it.
+    pub fn damage(&mut self, now: Duration) -> Option<Duration> {
+        self.damaged = true;
+        if self.phase != Phase::Idle {
+            return None;
+        }
+        Some(self.schedule(now))
+    }
+
+    /// Schedule a frame for the first vblank at or after `target`, for content held back
+    /// until then
+    ///
+    /// Returns when the frame should start rendering if this scheduled one. A frame
+    /// already on its way is left as it is.
+    pub fn schedule_for(&mut self, now: Duration, target: Duration) -> Option<Duration> {
+        if self.phase != Phase::Idle {
+            return None;
+        }
+        let start = target.saturating_sub(self.render_time() + self.safety_margin);
+        Some(self.schedule(start.max(now)))
+    }
+
+    fn schedule(&mut self, now: Duration) -> Duration {
+        let presentation = self.predict_presentation(now);
+        let start = presentation
+            .saturating_sub(self.render_time() + self.safety_margin)
+            .max(now);
+        self.phase = Phase::Scheduled {
+            start,
+            presentation,
+        };
+        start
+    }
+
+    /// The presentation time the scheduled frame targets, if it should be rendered at `now`
+    pub fn frame_due(&self, now: Duration) -> Option<Duration> {
+        match self.phase {
+            Phase::Scheduled {
+                start,
+                presentation,
+            } if now >= start => Some(presentation),
+            _ => None,
+        }
+    }
+
+    /// Record that a frame showing `content` was submitted after rendering for
+    /// `render_time`
+    pub fn frame_submitted(&mut self, render_time: Duration, content: FrameContent) {
+        if self.render_times.len() == RENDER_TIME_SAMPLES {
+            self.render_times.pop_front();
+        }
+        self.render_times.push_back(render_time);
+        self.damaged = false;
+        self.phase = Phase::Pending;
+        self.frame_content = content;
+    }
+
+    /// The pending frame reached the screen at `time`, with the output refreshing every
+    /// `refresh` if the backend knows
+    pub fn presented(
+        &mut self,
+        time: Duration,
+        refresh: Option<Duration>,
+        now: Duration,
+    ) -> FrameDone {
+        self.last_presentation = Some(time);
+        if let Some(refresh) = refresh {
+            self.refresh = refresh;
+        }
+        self.finish_frame(now)
+    }
+
+    /// The pending frame was dropped without being shown
+    pub fn discarded(&mut self, now: Duration) -> FrameDone {
+        self.finish_frame(now)
+    }
+
+    fn finish_frame(&mut self, now: Duration) -> FrameDone {
+        self.phase = Phase::Idle;
+        let content = std::mem::take(&mut self.frame_content);
+        let next_start = self.damaged.then(|| self.schedule(now));
+        FrameDone {
+            content,
+            next_start,
+        }
+    }
+}
+
+/// Called with an output and how long until its next frame should start rendering
+pub type RedrawCallback = Box<dyn FnMut(&Output, Duration)>;
+
+/// Frame schedulers of every output
+pub struct FrameSchedulingState {
+    clock: Box<dyn Clock>,
+    schedulers: Vec<(WeakOutput, FrameScheduler)>,
+    safety_margin: Duration,
+    redraw: Option<RedrawCallback>,
+}
+
+impl FrameSchedulingState {
+    pub fn new(clock: impl Clock + 'static) -> Self {
+        Self {
+            clock: Box::new(clock),
+            schedulers: Vec::new(),
+            safety_margin: Duration::ZERO,
+            redraw: None,
+        }
+    }
+
+    /// The current time on the presentation clock
+    pub fn now(&self) -> Duration {
+        self.clock.now()
+    }
+
+    /// Set how the backend is asked to render, usually by arming a timer
+    pub fn set_redraw_callback(&mut self, redraw: RedrawCallback) {
+        self.redraw = Some(redraw);
+    }
+
+    /// Set the safety margin of every output
+    pub fn set_safety_margin(&mut self, safety_margin: Duration) {
+        self.safety_margin = safety_margin;
+        for (_, scheduler) in &mut self.schedulers {
+            scheduler.set_safety_margin(safety_margin);
+        }
+    }
+
+    /// Start scheduling frames of `output`, beginning with a first one
+    pub fn add_output(&mut self, output: &Output) {
+        self.schedulers.retain(|(weak, _)| weak.upgrade().is_some());
+        if self.scheduler(output).is_none() {
+            let scheduler = FrameScheduler::new(output_refresh(output), self.safety_margin);
+            self.schedulers.push((output.downgrade(), scheduler));
+        }
+        self.damage(output);
+    }
+
+    pub fn output_removed(&mut self, output: &Output) {
+        self.schedulers.retain(|(weak, _)| weak != output);
+    }
+
+    /// Pick up a new refresh rate of `output` from its current mode
+    pub fn mode_changed(&mut self, output: &Output) {
+        let refresh = output_refresh(output);
+        if let Some(scheduler) = self.scheduler_mut(output) {
+            scheduler.set_refresh(refresh);
+        }
+    }
+
+    /// Set whether frames of `output` are presented without waiting for a vblank
+    pub fn set_tearing(&mut self, output: &Output, tearing: bool) {
+        if let Some(scheduler) = self.scheduler_mut(output) {
+            scheduler.set_tearing(tearing);
+        }
+    }
+
+    pub fn scheduler(&self, output: &Output) -> Option<&FrameScheduler> {
+        self.schedulers
+            .iter()
+            .find(|(weak, _)| weak == output)
+            .map(|(_, scheduler)| scheduler)
+    }
+
+    fn scheduler_mut(&mut self, output: &Output) -> Option<&mut FrameScheduler> {
+        self.schedulers
+            .iter_mut()
+            .find(|(weak, _)| weak == output)
+            .map(|(_, scheduler)| scheduler)
+    }
+
+    /// Mark `output` as changed, asking the backend for a frame if none is on its way
+    pub fn damage(&mut self, output: &Output) {
+        let now = self.now();
+        let start = self
+            .scheduler_mut(output)
+            .and_then(|scheduler| scheduler.damage(now));
+        self.request_redraw(output, start, now);
+    }
+
+    /// Ask for a frame of `output` presented at or after `target`, if none is on its way
+    pub fn schedule_for(&mut self, output: &Output, target: Duration) {
+        let now = self.now();
+        let start = self
+            .scheduler_mut(output)
+            .and_then(|scheduler| scheduler.schedule_for(now, target));
+        self.request_redraw(output, start, now);
+    }
+
+    /// The presentation time the next frame of `output` targets, if it should be rendered
+    /// now
+    pub fn frame_due(&self, output: &Output) -> Option<Duration> {
+        let now = self.now();
+        self.scheduler(output)?.frame_due(now)
+    }
+
+    /// Record that a frame of `output` showing `content` was submitted after rendering
+    /// for `render_time`
+    ///
+    /// Content of an output without a scheduler is discarded right away.
+    pub fn frame_submitted(
+        &mut self,
+        output: &Output,
+        render_time: Duration,
+        content: FrameContent,
+    ) {
+        match self.scheduler_mut(output) {
+            Some(scheduler) => scheduler.frame_submitted(render_time, content),
+            None => discard_feedback(content.feedback),
+        }
+    }
+
+    /// The pending frame of `output` reached the screen at `time`. Returns what it showed.
+    pub fn presented(
+        &mut self,
+        output: &Output,
+        time: Duration,
+        refresh: Option<Duration>,
+    ) -> FrameContent {
+        let now = self.now();
+        let Some(scheduler) = self.scheduler_mut(output) else {
+            return FrameContent::default();
+        };
+        let done = scheduler.presented(time, refresh, now);
+        self.request_redraw(output, done.next_start, now);
+        done.content
+    }
+
+    /// The pending frame of `output` was dropped without being shown. Returns what it
+    /// would have shown.
+    pub fn discarded(&mut self, output: &Output) -> FrameContent {
+        let now = self.now();
+        let Some(scheduler) = self.scheduler_mut(output) else {
+            return FrameContent::default();
+        };
+        let done = scheduler.discarded(now);
+        self.request_redraw(output, done.next_start, now);
+        done.content
+    }
+
+    fn request_redraw(&mut self, output: &Output, start: Option<Duration>, now: Duration) {
+        if let (Some(start), Some(redraw)) = (start, self.redraw.as_mut()) {
+            redraw(output, start.saturating_sub(now));
+        }
+    }
+}
+
+/// Refresh interval of the current mode of `output`, zero if it has none
+fn output_refresh(output: &Output) -> Duration {
+    output
+        .current_mode()
+        .filter(|mode| mode.refresh > 0)
+        .map_or(Duration::ZERO, |mode| {
+            Duration::from_secs_f64(1000.0 / mode.refresh as f64)
+        })
+}
+
+/// Send the frame callbacks of `surface` and its subsurfaces, for a frame presented at
+/// `time`
+pub fn send_frame_callbacks(surface: &WlSurface, time: Duration) {
+    let time = time.as_millis() as u32;
+    with_surface_tree_downward(
+        surface,
+        (),
+        |_, _, _| TraversalAction::DoChildren(()),
+        |_, states, _| {
+            let callbacks = std::mem::take(
+                &mut states
+                    .cached_state
+                    .get::<SurfaceAttributes>()
+                    .current()
+                    .frame_callbacks,
+            );
+            for callback in callbacks {
+                callback.done(time);
+            }
+        },
+        |_, _, _| true,
+    );
+}
+
+/// Take the presentation feedback requested by the current content of `surface` and its
+/// subsurfaces
+pub fn take_presentation_feedback(surface: &WlSurface) -> Vec<PresentationFeedbackCallback> {
+    let mut feedback = Vec::new();
+    with_surface_tree_downward(
+        surface,
+        (),
+        |_, _, _| TraversalAction::DoChildren(()),
+        |_, states, _| {
+            feedback.append(
+                &mut states
+                    .cached_state
+                    .get::<PresentationFeedbackCachedState>()
+                    .current()
+                    .callbacks,
+            );
+        },
+        |_, _, _| true,
+    );
+    feedback
+}
+
+/// Discard the presentation feedback of content `surface` committed that won't be shown
+pub fn discard_surface_feedback(surface: &WlSurface) {
+    let feedback = with_states(surface, |states| {
+        std::mem::take(
+            &mut states
+                .cached_state
+                .get::<PresentationFeedbackCachedState>()
+                .current()
+                .callbacks,
+        )
+    });
+    discard_feedback(feedback);
+}
+
+fn discard_feedback(feedback: Vec<PresentationFeedbackCallback>) {
+    for callback in feedback {
+        callback.discarded();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const REFRESH: Duration = Duration::from_micros(16_667);
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    fn scheduler() -> FrameScheduler {
+        FrameScheduler::new(REFRESH, ms(1))
+    }
+
+    #[test]
+    fn nothing_renders_without_damage() {
+        let scheduler = scheduler();
+        assert!(!scheduler.is_damaged());
+        assert_eq!(scheduler.frame_due(ms(100)), None);
+    }
+
+    #[test]
+    fn first_frame_renders_right_away() {
+        let mut scheduler = scheduler();
+        assert_eq!(scheduler.damage(ms(5)), Some(ms(5)));
+        assert_eq!(scheduler.frame_due(ms(5)), Some(ms(6)));
+    }
+
+    #[test]
+    fn frames_start_just_in_time_for_the_next_vblank() {
+        let mut scheduler = scheduler();
+        scheduler.damage(Duration::ZERO);
+        scheduler.frame_submitted(ms(3), FrameContent::default());
+        let done = scheduler.presented(ms(10), None, ms(10));
+        assert_eq!(done.next_start, None);
+
+        // Vblanks at 10, 26.667, 43.333; rendering takes 3 ms plus 1 ms of margin
+        let start = scheduler.damage(ms(12)).unwrap();
+        assert_eq!(start, ms(10) + REFRESH - ms(4));
+        assert_eq!(scheduler.frame_due(ms(12)), None);
+        assert_eq!(scheduler.frame_due(start), Some(ms(10) + REFRESH));
+
+        // Too late for the next vblank, the one after it is targeted
+        let mut scheduler = self::scheduler();
+        scheduler.damage(Duration::ZERO);
+        scheduler.frame_submitted(ms(3), FrameContent::default());
+        scheduler.presented(ms(10), None, ms(10));
+        let start = scheduler.damage(ms(24)).unwrap();
+        assert_eq!(start, ms(10) + REFRESH * 2 - ms(4));
+    }
+
+    #[test]
+    fn damage_during_a_pending_frame_renders_after_it() {
+        let mut scheduler = scheduler();
+        scheduler.damage(Duration::ZERO);
+        scheduler.frame_submitted(ms(2), FrameContent::default());
+        assert_eq!(scheduler.damage(ms(3)), None);
+        assert_eq!(scheduler.frame_due(ms(3)), None);
+        let done = scheduler.presented(ms(16), None, ms(16));
+        assert!(done.next_start.is_some());
+        assert!(scheduler.is_damaged());
+    }
+
+    #[test]
+    fn render_time_is_the_longest_recent_one() {
+        let mut scheduler = scheduler();
+        for i in 0..RENDER_TIME_SAMPLES + 4 {
+            let render_time = if i == 0 { ms(12) } else { ms(2) };
+            scheduler.damage(Duration::ZERO);
+            scheduler.frame_submitted(render_time, FrameContent::default());
+            scheduler.discarded(Duration::ZERO);
+            if i == 1 {
+                assert_eq!(scheduler.render_time(), ms(12));
+            }
+        }
+        // The slow frame fell out of the window
+        assert_eq!(scheduler.render_time(), ms(2));
+    }
+
+    #[test]
+    fn feedback_updates_the_refresh_interval() {
+        let mut scheduler = scheduler();
+        scheduler.damage(Duration::ZERO);
+        scheduler.frame_submitted(Duration::ZERO, FrameContent::default());
+        scheduler.presented(ms(10), Some(ms(8)), ms(10));
+        assert_eq!(scheduler.refresh(), ms(8));
+        assert_eq!(scheduler.predict_presentation(ms(11)), ms(18));
+    }
+
+    #[test]
+    fn unknown_refresh_presents_as_soon_as_possible() {
+        let mut scheduler = FrameScheduler::new(Duration::ZERO, Duration::ZERO);
+        scheduler.damage(Duration::ZERO);
+        scheduler.frame_submitted(ms(2), FrameContent::default());
+        scheduler.presented(ms(3), None, ms(3));
+        assert_eq!(scheduler.damage(ms(7)), Some(ms(7)));
+    }
+
+    #[test]
+    fn tearing_frames_start_right_away() {
+        let mut scheduler = scheduler();
+        scheduler.damage(Duration::ZERO);
+        scheduler.frame_submitted(ms(3), FrameContent::default());
+        scheduler.presented(ms(10), None, ms(10));
+        scheduler.set_tearing(true);
+        assert_eq!(scheduler.damage(ms(12)), Some(ms(12)));
+        assert_eq!(scheduler.frame_due(ms(12)), Some(ms(16)));
+    }
+
+    #[test]
+    fn frames_for_a_target_present_at_the_first_vblank_after_it() {
+        let mut scheduler = scheduler();
+        scheduler.damage(Duration::ZERO);
+        scheduler.frame_submitted(ms(3), FrameContent::default());
+        scheduler.presented(ms(10), None, ms(10));
+
+        // Vblanks at 10, 26.667, 43.333, 60
+        let start = scheduler.schedule_for(ms(12), ms(40)).unwrap();
+        assert_eq!(start, ms(10) + REFRESH * 2 - ms(4));
+        assert_eq!(scheduler.frame_due(start), Some(ms(10) + REFRESH * 2));
+        // Damage goes into the frame already scheduled
+        assert_eq!(scheduler.damage(ms(13)), None);
+    }
+}
@@ -1,6 +1,10 @@
+use super::drm_syncobj;
 use crate::StarforgeState;
 use crate::state::StarforgeClientState;
+#[cfg(feature = "xwayland")]
+use smithay::xwayland::XWaylandClientData;
 use smithay::{
+    backend::renderer::utils::on_commit_buffer_handler,
     delegate_compositor,
     reexports::wayland_server::{Client, protocol::wl_surface::WlSurface},
     wayland::compositor::{CompositorClientState, CompositorHandler, CompositorState},
@@ -13,6 +17,10 @@ impl CompositorHandler for StarforgeState {
     }
 
     fn client_compositor_state<'a>(&self, client: &'a Client) -> &'a CompositorClientState {
+        #[cfg(feature = "xwayland")]
+        if let Some(data) = client.get_data::<XWaylandClientData>() {
+            return &data.compositor_state;
+        }
         &client
             .get_data::<StarforgeClientState>()
             .unwrap()
@@ -20,11 +28,17 @@ impl CompositorHandler for StarforgeState {
     }
 
     fn commit(&mut self, surface: &WlSurface) {
-        // For now, just log that we got a commit
-        tracing::debug!("Surface committed");
-
-        // In a real implementation, we'd update our internal state,
-        // mark the surface as needing a redraw, etc.
+        // Keep track of the current buffer, so it can be drawn and snapshotted
+        drm_syncobj::store_acquire_point(surface);
+        on_commit_buffer_handler::<Self>(surface);
+        // Captures are filled with the frames of the output windows are shown on, which
+        // has to draw one even for windows it doesn't show
+        if self.image_copy_capture_state.surface_committed(surface)
+            && let Some(output) = self.window_output().cloned()
+        {
+            self.frame_scheduling_state.damage(&output);
+        }
+        self.damage_surface(surface);
     }
 }
 
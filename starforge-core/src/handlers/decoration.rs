@@ -0,0 +1,61 @@
+use crate::StarforgeState;
+use smithay::{
+    delegate_kde_decoration, delegate_xdg_decoration,
+    reexports::{
+        wayland_protocols::xdg::decoration::zv1::server::zxdg_toplevel_decoration_v1::Mode,
+        wayland_protocols_misc::server_decoration::server::org_kde_kwin_server_decoration::{
+            Mode as KdeMode, OrgKdeKwinServerDecoration,
+        },
+        wayland_server::{WEnum, protocol::wl_surface::WlSurface},
+    },
+    wayland::shell::{
+        kde::decoration::{KdeDecorationHandler, KdeDecorationState},
+        xdg::{ToplevelSurface, decoration::XdgDecorationHandler},
+    },
+};
+
+/// Implementation of the xdg-decoration protocol
+impl XdgDecorationHandler for StarforgeState {
+    fn new_decoration(&mut self, toplevel: ToplevelSurface) {
+        self.request_xdg_decoration(&toplevel, None);
+    }
+
+    fn request_mode(&mut self, toplevel: ToplevelSurface, mode: Mode) {
+        self.request_xdg_decoration(&toplevel, Some(mode));
+    }
+
+    fn unset_mode(&mut self, toplevel: ToplevelSurface) {
+        self.request_xdg_decoration(&toplevel, None);
+    }
+}
+
+// Delegate the xdg-decoration protocol implementation to our handler
+delegate_xdg_decoration!(StarforgeState);
+
+/// Implementation of the KDE server-decoration protocol, for clients predating
+/// xdg-decoration
+impl KdeDecorationHandler for StarforgeState {
+    fn kde_decoration_state(&self) -> &KdeDecorationState {
+        &self.kde_decoration_state
+    }
+
+    fn new_decoration(&mut self, surface: &WlSurface, decoration: &OrgKdeKwinServerDecoration) {
+        self.request_kde_decoration(surface, decoration, None);
+    }
+
+    fn request_mode(
+        &mut self,
+        surface: &WlSurface,
+        decoration: &OrgKdeKwinServerDecoration,
+        mode: WEnum<KdeMode>,
+    ) {
+        self.request_kde_decoration(surface, decoration, mode.into_result().ok());
+    }
+
+    fn release(&mut self, _decoration: &OrgKdeKwinServerDecoration, surface: &WlSurface) {
+        self.release_kde_decoration(surface);
+    }
+}
+
+// Delegate the KDE server-decoration protocol implementation to our handler
+delegate_kde_decoration!(StarforgeState);
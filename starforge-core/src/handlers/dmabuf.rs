@@ -0,0 +1,63 @@
+use crate::{StarforgeResult, StarforgeState};
+use smithay::backend::allocator::dmabuf::Dmabuf;
+use smithay::backend::allocator::{Buffer, Format};
+use smithay::delegate_dmabuf;
+use smithay::reexports::wayland_server::protocol::wl_surface::WlSurface;
+use smithay::wayland::dmabuf::{
+    DmabufFeedback, DmabufGlobal, DmabufHandler, DmabufState, ImportNotifier,
+};
+use std::collections::HashSet;
+
+/// Imports a client dma-buf into the renderer, so it can be sampled later
+pub type DmabufImporter = Box<dyn FnMut(&Dmabuf) -> StarforgeResult<()>>;
+
+/// The linux-dmabuf global and what it advertises
+pub struct DmabufGlobalState {
+    pub(crate) global: DmabufGlobal,
+    /// Every format and modifier the global advertises
+    pub(crate) formats: HashSet<Format>,
+    /// Feedback for surfaces, preferring scanout-capable formats when there are any
+    pub(crate) surface_feedback: DmabufFeedback,
+    pub(crate) importer: DmabufImporter,
+}
+
+/// Implementation of the linux-dmabuf protocol
+impl DmabufHandler for StarforgeState {
+    fn dmabuf_state(&mut self) -> &mut DmabufState {
+        &mut self.dmabuf_state
+    }
+
+    fn dmabuf_imported(&mut self, global: &DmabufGlobal, dmabuf: Dmabuf, notifier: ImportNotifier) {
+        let Some(state) = self.dmabuf.as_mut().filter(|state| state.global == *global) else {
+            notifier.failed();
+            return;
+        };
+        if !state.formats.contains(&dmabuf.format()) {
+            notifier.invalid_format();
+            return;
+        }
+        match (state.importer)(&dmabuf) {
+            Ok(()) => {
+                let _ = notifier.successful::<Self>();
+            }
+            Err(e) => {
+                tracing::warn!("Failed to import dma-buf: {}", e);
+                notifier.failed();
+            }
+        }
+    }
+
+    fn new_surface_feedback(
+        &mut self,
+        _surface: &WlSurface,
+        global: &DmabufGlobal,
+    ) -> Option<DmabufFeedback> {
+        self.dmabuf
+            .as_ref()
+            .filter(|state| state.global == *global)
+            .map(|state| state.surface_feedback.clone())
+    }
+}
+
+// Delegate the dmabuf protocol implementation to our handler
+delegate_dmabuf!(StarforgeState);
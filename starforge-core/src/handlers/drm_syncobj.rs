@@ -0,0 +1,67 @@
+use crate::StarforgeState;
+use smithay::delegate_drm_syncobj;
+use smithay::reexports::wayland_server::protocol::wl_surface::WlSurface;
+use smithay::wayland::compositor::{SurfaceData, with_states};
+use smithay::wayland::drm_syncobj::{
+    DrmSyncPoint, DrmSyncobjCachedState, DrmSyncobjHandler, DrmSyncobjState,
+};
+use std::sync::Mutex;
+
+/// Acquire point of the buffer a surface committed last
+#[derive(Default)]
+struct SurfaceAcquirePoint(Mutex<Option<DrmSyncPoint>>);
+
+/// Implementation of the linux-drm-syncobj-v1 protocol
+impl DrmSyncobjHandler for StarforgeState {
+    fn drm_syncobj_state(&mut self) -> &mut DrmSyncobjState {
+        // The global only exists once the state was created
+        self.drm_syncobj_state.as_mut().unwrap()
+    }
+}
+
+/// Remember the acquire point of a commit for the renderer
+///
+/// Has to run before `on_commit_buffer_handler`, which moves the sync points into the
+/// surface's buffer.
+pub(crate) fn store_acquire_point(surface: &WlSurface) {
+    with_states(surface, |states| {
+        let acquire_point = states
+            .cached_state
+            .get::<DrmSyncobjCachedState>()
+            .current()
+            .acquire_point
+            .clone();
+        if acquire_point.is_none() && states.data_map.get::<SurfaceAcquirePoint>().is_none() {
+            return;
+        }
+        states
+            .data_map
+            .insert_if_missing_threadsafe(SurfaceAcquirePoint::default);
+        *states
+            .data_map
+            .get::<SurfaceAcquirePoint>()
+            .unwrap()
+            .0
+            .lock()
+            .unwrap() = acquire_point;
+    });
+}
+
+/// Acquire point the renderer has to wait for before sampling the surface's buffer
+///
+/// `None` for surfaces without explicit synchronization, whose dma-bufs carry implicit
+/// fences instead.
+pub fn acquire_point(surface: &WlSurface) -> Option<DrmSyncPoint> {
+    with_states(surface, stored_acquire_point)
+}
+
+/// [`acquire_point`] of the surface `states` belong to, for callers already holding them
+pub(crate) fn stored_acquire_point(states: &SurfaceData) -> Option<DrmSyncPoint> {
+    states
+        .data_map
+        .get::<SurfaceAcquirePoint>()
+        .and_then(|point| point.0.lock().unwrap().clone())
+}
+
+// Delegate the drm syncobj protocol implementation to our handler
+delegate_drm_syncobj!(StarforgeState);
@@ -0,0 +1,50 @@
+use crate::StarforgeState;
+use smithay::{
+    delegate_foreign_toplevel_list,
+    reexports::wayland_server::{Resource, Weak, protocol::wl_surface::WlSurface},
+    wayland::{
+        compositor::with_states,
+        foreign_toplevel_list::{
+            ForeignToplevelHandle, ForeignToplevelListHandler, ForeignToplevelListState,
+        },
+    },
+};
+
+/// Implementation of the ext-foreign-toplevel-list protocol
+impl ForeignToplevelListHandler for StarforgeState {
+    fn foreign_toplevel_list_state(&mut self) -> &mut ForeignToplevelListState {
+        &mut self.foreign_toplevel_list_state
+    }
+}
+
+/// Announce a new toplevel, an xdg toplevel or X11 window shown by `surface`, to
+/// foreign toplevel list clients
+pub(crate) fn add_toplevel(state: &mut ForeignToplevelListState, surface: &WlSurface) {
+    let handle = state.new_toplevel::<StarforgeState>("", "");
+    handle
+        .user_data()
+        .insert_if_missing_threadsafe(|| surface.downgrade());
+    with_states(surface, |states| {
+        states
+            .data_map
+            .insert_if_missing_threadsafe(|| handle.clone())
+    });
+}
+
+/// Handle of a toplevel in the foreign toplevel list
+pub fn toplevel_handle(surface: &WlSurface) -> Option<ForeignToplevelHandle> {
+    with_states(surface, |states| {
+        states.data_map.get::<ForeignToplevelHandle>().cloned()
+    })
+}
+
+/// Surface of the toplevel a foreign toplevel handle stands for, while it exists
+pub fn toplevel_surface(handle: &ForeignToplevelHandle) -> Option<WlSurface> {
+    handle
+        .user_data()
+        .get::<Weak<WlSurface>>()
+        .and_then(|surface| surface.upgrade().ok())
+}
+
+// Delegate the foreign toplevel list protocol implementation to our handler
+delegate_foreign_toplevel_list!(StarforgeState);
@@ -0,0 +1,18 @@
+use crate::StarforgeState;
+use crate::scale::send_surface_scale;
+use smithay::{
+    delegate_fractional_scale, reexports::wayland_server::protocol::wl_surface::WlSurface,
+    wayland::fractional_scale::FractionalScaleHandler,
+};
+
+/// Implementation of the wp_fractional_scale_v1 protocol
+impl FractionalScaleHandler for StarforgeState {
+    fn new_fractional_scale(&mut self, surface: WlSurface) {
+        // Send the scale right away, so the first buffer is already rendered at it
+        let (scale, transform) = self.surface_scale(&surface);
+        send_surface_scale(&surface, scale, transform);
+    }
+}
+
+// Delegate the fractional scale protocol implementation to our handler
+delegate_fractional_scale!(StarforgeState);
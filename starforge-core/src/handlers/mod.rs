@@ -1,7 +1,24 @@
 //! Wayland protocol handlers for Starforge
 
 mod compositor;
+mod decoration;
+mod dmabuf;
+mod drm_syncobj;
+mod foreign_toplevel_list;
+mod fractional_scale;
+mod presentation;
 mod shm;
+mod viewporter;
 mod wl_output;
 mod wl_seat;
 mod xdg_shell;
+#[cfg(feature = "xwayland")]
+mod xwayland;
+
+pub use dmabuf::{DmabufGlobalState, DmabufImporter};
+pub use drm_syncobj::acquire_point;
+pub(crate) use drm_syncobj::stored_acquire_point;
+pub(crate) use foreign_toplevel_list::add_toplevel;
+pub use foreign_toplevel_list::{toplevel_handle, toplevel_surface};
+pub use xdg_shell::output_logical_size;
+pub(crate) use xdg_shell::window_root;
@@ -0,0 +1,6 @@
+use crate::StarforgeState;
+use smithay::delegate_presentation;
+
+// Feedback requested with a commit is collected when a frame showing it is submitted and
+// answered once the backend reports that frame as presented or discarded
+delegate_presentation!(StarforgeState);
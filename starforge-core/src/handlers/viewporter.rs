@@ -0,0 +1,6 @@
+use crate::StarforgeState;
+use smithay::delegate_viewporter;
+
+// Viewports are applied by `on_commit_buffer_handler`, which crops and scales the
+// surface's buffer to its destination size
+delegate_viewporter!(StarforgeState);
@@ -1,15 +1,23 @@
 use crate::StarforgeState;
+#[cfg(feature = "xwayland")]
+use smithay::wayland::selection::{SelectionSource, SelectionTarget};
 use smithay::{
-    delegate_data_device, delegate_seat,
-    input::{SeatHandler, SeatState},
+    delegate_cursor_shape, delegate_data_device, delegate_primary_selection, delegate_seat,
+    input::{Seat, SeatHandler, SeatState, pointer::CursorImageStatus},
     reexports::wayland_server::protocol::wl_surface::WlSurface,
-    wayland::selection::{
-        SelectionHandler,
-        data_device::{
-            ClientDndGrabHandler, DataDeviceHandler, DataDeviceState, ServerDndGrabHandler,
+    wayland::{
+        selection::{
+            SelectionHandler,
+            data_device::{
+                ClientDndGrabHandler, DataDeviceHandler, DataDeviceState, ServerDndGrabHandler,
+            },
+            primary_selection::{PrimarySelectionHandler, PrimarySelectionState},
         },
+        tablet_manager::TabletSeatHandler,
     },
 };
+#[cfg(feature = "xwayland")]
+use std::os::fd::OwnedFd;
 
 impl SeatHandler for StarforgeState {
     type KeyboardFocus = WlSurface;
@@ -19,10 +27,20 @@ impl SeatHandler for StarforgeState {
     fn seat_state(&mut self) -> &mut SeatState<Self> {
         &mut self.seat_state
     }
+
+    fn cursor_image(&mut self, _seat: &Seat<Self>, image: CursorImageStatus) {
+        self.cursor_state.set_status(image);
+        self.damage_all_outputs();
+    }
 }
 
 delegate_seat!(StarforgeState);
 
+// Shapes set through wp_cursor_shape_v1 arrive as named cursor images
+impl TabletSeatHandler for StarforgeState {}
+
+delegate_cursor_shape!(StarforgeState);
+
 impl ClientDndGrabHandler for StarforgeState {}
 
 impl ServerDndGrabHandler for StarforgeState {}
@@ -35,7 +53,50 @@ impl DataDeviceHandler for StarforgeState {
 
 impl SelectionHandler for StarforgeState {
     type SelectionUserData = ();
+
+    // Selections of Wayland clients are offered to X11 clients too
+    #[cfg(feature = "xwayland")]
+    fn new_selection(
+        &mut self,
+        ty: SelectionTarget,
+        source: Option<SelectionSource>,
+        _seat: Seat<Self>,
+    ) {
+        if let Some(wm) = self.xwayland_state.wm.as_mut()
+            && let Err(err) = wm.new_selection(ty, source.map(|source| source.mime_types()))
+        {
+            tracing::warn!("Failed to offer {ty:?} selection to X11: {err}");
+        }
+    }
+
+    // Wayland clients pasting what an X11 client offered
+    #[cfg(feature = "xwayland")]
+    fn send_selection(
+        &mut self,
+        ty: SelectionTarget,
+        mime_type: String,
+        fd: OwnedFd,
+        _seat: Seat<Self>,
+        _user_data: &(),
+    ) {
+        let Some(loop_handle) = self.xwayland_state.loop_handle().cloned() else {
+            return;
+        };
+        if let Some(wm) = self.xwayland_state.wm.as_mut()
+            && let Err(err) = wm.send_selection(ty, mime_type, fd, loop_handle)
+        {
+            tracing::warn!("Failed to request {ty:?} selection from X11: {err}");
+        }
+    }
 }
 
 // Delegate the data device implementation to our handler
 delegate_data_device!(StarforgeState);
+
+impl PrimarySelectionHandler for StarforgeState {
+    fn primary_selection_state(&self) -> &PrimarySelectionState {
+        &self.primary_selection_state
+    }
+}
+
+delegate_primary_selection!(StarforgeState);
@@ -1,10 +1,21 @@
 use crate::StarforgeState;
+use crate::animation::WindowSnapshot;
+use crate::handlers::{add_toplevel, toplevel_handle};
+use crate::scale::send_surface_scale;
 use smithay::{
     delegate_xdg_shell,
-    reexports::wayland_server::protocol::wl_seat::WlSeat,
-    utils::Serial,
-    wayland::shell::xdg::{
-        PopupSurface, PositionerState, ToplevelSurface, XdgShellHandler, XdgShellState,
+    output::Output,
+    reexports::{
+        wayland_protocols::xdg::shell::server::xdg_toplevel,
+        wayland_server::protocol::{wl_output::WlOutput, wl_seat::WlSeat, wl_surface::WlSurface},
+    },
+    utils::{Logical, Serial, Size},
+    wayland::{
+        compositor::{SurfaceData, get_parent, with_states},
+        shell::xdg::{
+            PopupSurface, PositionerState, ToplevelSurface, XdgPopupSurfaceData, XdgShellHandler,
+            XdgShellState, XdgToplevelSurfaceData, XdgToplevelSurfaceRoleAttributes,
+        },
     },
 };
 
@@ -20,11 +31,93 @@ impl XdgShellHandler for StarforgeState {
 
         // In a real implementation, we'd assign a position, size,
         // and add the surface to our rendering list
+        self.animation_state.window_opened(surface.wl_surface());
+        let (scale, transform) = self.surface_scale(surface.wl_surface());
+        send_surface_scale(surface.wl_surface(), scale, transform);
+        add_toplevel(&mut self.foreign_toplevel_list_state, surface.wl_surface());
+    }
+
+    fn toplevel_destroyed(&mut self, surface: ToplevelSurface) {
+        // Hold on to the last buffers so the close animation can still draw the window
+        let snapshot = WindowSnapshot::capture(surface.wl_surface());
+        let geometry = snapshot.bounding_box().to_f64();
+        self.animation_state
+            .window_closed(surface.wl_surface(), snapshot, geometry);
+        self.damage_all_outputs();
+        self.decorated_window_closed(surface.wl_surface());
+
+        self.image_copy_capture_state
+            .toplevel_closed(surface.wl_surface());
+        if let Some(handle) = toplevel_handle(surface.wl_surface()) {
+            self.foreign_toplevel_list_state.remove_toplevel(&handle);
+        }
+    }
+
+    fn fullscreen_request(&mut self, surface: ToplevelSurface, _output: Option<WlOutput>) {
+        let Some(output) = self.window_output() else {
+            return;
+        };
+        let size = output_logical_size(output);
+        surface.with_pending_state(|state| {
+            state.states.set(xdg_toplevel::State::Fullscreen);
+            state.size = size;
+        });
+        surface.send_configure();
+    }
+
+    fn unfullscreen_request(&mut self, surface: ToplevelSurface) {
+        surface.with_pending_state(|state| {
+            state.states.unset(xdg_toplevel::State::Fullscreen);
+            state.size = None;
+        });
+        surface.send_configure();
+    }
+
+    fn maximize_request(&mut self, surface: ToplevelSurface) {
+        self.set_maximized(&surface, true);
+    }
+
+    fn unmaximize_request(&mut self, surface: ToplevelSurface) {
+        self.set_maximized(&surface, false);
+    }
+
+    fn minimize_request(&mut self, surface: ToplevelSurface) {
+        self.minimize_window(surface.wl_surface());
     }
 
-    fn new_popup(&mut self, _surface: PopupSurface, _positioner: PositionerState) {
+    fn title_changed(&mut self, surface: ToplevelSurface) {
+        // The title bar shows it
+        self.damage_surface(surface.wl_surface());
+        if let Some(handle) = toplevel_handle(surface.wl_surface()) {
+            let title = with_states(surface.wl_surface(), |states| {
+                toplevel_attributes(states).title.clone()
+            });
+            handle.send_title(title.as_deref().unwrap_or_default());
+            handle.send_done();
+        }
+    }
+
+    fn app_id_changed(&mut self, surface: ToplevelSurface) {
+        if let Some(handle) = toplevel_handle(surface.wl_surface()) {
+            let app_id = with_states(surface.wl_surface(), |states| {
+                toplevel_attributes(states).app_id.clone()
+            });
+            handle.send_app_id(app_id.as_deref().unwrap_or_default());
+            handle.send_done();
+        }
+    }
+
+    fn new_popup(&mut self, surface: PopupSurface, _positioner: PositionerState) {
         // Handle popup windows
         tracing::info!("New popup created");
+        let (scale, transform) = self.surface_scale(surface.wl_surface());
+        send_surface_scale(surface.wl_surface(), scale, transform);
+        self.popups.push(surface);
+    }
+
+    fn popup_destroyed(&mut self, surface: PopupSurface) {
+        self.popups.retain(|popup| popup != &surface);
+        self.damage_all_outputs();
     }
 
     fn grab(&mut self, _surface: PopupSurface, _seat: WlSeat, _serial: Serial) {
@@ -41,5 +134,48 @@ impl XdgShellHandler for StarforgeState {
     }
 }
 
+/// Size of `output` in logical pixels, once it has a mode
+pub fn output_logical_size(output: &Output) -> Option<Size<i32, Logical>> {
+    output.current_mode().map(|mode| {
+        output
+            .current_transform()
+            .transform_size(mode.size)
+            .to_f64()
+            .to_logical(output.current_scale().fractional_scale())
+            .to_i32_round()
+    })
+}
+
+/// The toplevel a surface belongs to, through subsurfaces and popups
+pub(crate) fn window_root(surface: &WlSurface) -> WlSurface {
+    let mut surface = surface.clone();
+    loop {
+        while let Some(parent) = get_parent(&surface) {
+            surface = parent;
+        }
+        let popup_parent = with_states(&surface, |states| {
+            states
+                .data_map
+                .get::<XdgPopupSurfaceData>()
+                .and_then(|data| data.lock().unwrap().parent.clone())
+        });
+        match popup_parent {
+            Some(parent) => surface = parent,
+            None => return surface,
+        }
+    }
+}
+
+fn toplevel_attributes(
+    states: &SurfaceData,
+) -> std::sync::MutexGuard<'_, XdgToplevelSurfaceRoleAttributes> {
+    states
+        .data_map
+        .get::<XdgToplevelSurfaceData>()
+        .unwrap()
+        .lock()
+        .unwrap()
+}
+
 // Delegate the XDG shell protocol implementation to our handler
 delegate_xdg_shell!(StarforgeState);
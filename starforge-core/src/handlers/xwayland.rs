@@ -0,0 +1,277 @@
+use crate::StarforgeState;
+use crate::animation::WindowSnapshot;
+use crate::handlers::{add_toplevel, output_logical_size, toplevel_handle};
+use smithay::{
+    delegate_xwayland_shell,
+    reexports::wayland_server::{Resource, protocol::wl_surface::WlSurface},
+    utils::{Logical, Rectangle},
+    wayland::{
+        selection::{
+            SelectionTarget,
+            data_device::{
+                clear_data_device_selection, current_data_device_selection_userdata,
+                request_data_device_client_selection, set_data_device_selection,
+            },
+            primary_selection::{
+                clear_primary_selection, current_primary_selection_userdata,
+                request_primary_client_selection, set_primary_selection,
+            },
+        },
+        xwayland_shell::{XWaylandShellHandler, XWaylandShellState},
+    },
+    xwayland::{
+        X11Surface, X11Wm, XwmHandler,
+        xwm::{Reorder, ResizeEdge, WmWindowProperty, X11Window, XwmId},
+    },
+};
+use std::os::fd::OwnedFd;
+use tracing::warn;
+
+/// Window management of X11 windows shown through Xwayland
+impl XwmHandler for StarforgeState {
+    fn xwm_state(&mut self, _xwm: XwmId) -> &mut X11Wm {
+        self.xwayland_state.wm.as_mut().unwrap()
+    }
+
+    fn new_window(&mut self, _xwm: XwmId, window: X11Surface) {
+        self.xwayland_state.windows.push(window);
+    }
+
+    fn new_override_redirect_window(&mut self, _xwm: XwmId, window: X11Surface) {
+        self.xwayland_state.windows.push(window);
+    }
+
+    fn map_window_request(&mut self, _xwm: XwmId, window: X11Surface) {
+        // Windows keep the geometry they asked for, there is no layout yet
+        if let Err(err) = window
+            .set_mapped(true)
+            .and_then(|_| window.configure(window.geometry()))
+        {
+            warn!("Failed to map X11 window: {err}");
+        }
+    }
+
+    fn mapped_override_redirect_window(&mut self, _xwm: XwmId, window: X11Surface) {
+        if let Some(surface) = window.wl_surface() {
+            self.damage_surface(&surface);
+        }
+    }
+
+    fn unmapped_window(&mut self, _xwm: XwmId, window: X11Surface) {
+        self.x11_window_closed(&window);
+        if !window.is_override_redirect() {
+            let _ = window.set_mapped(false);
+        }
+        self.damage_all_outputs();
+    }
+
+    fn destroyed_window(&mut self, _xwm: XwmId, window: X11Surface) {
+        self.x11_window_closed(&window);
+        self.xwayland_state.windows.retain(|known| known != &window);
+        self.damage_all_outputs();
+    }
+
+    fn configure_request(
+        &mut self,
+        _xwm: XwmId,
+        window: X11Surface,
+        x: Option<i32>,
+        y: Option<i32>,
+        w: Option<u32>,
+        h: Option<u32>,
+        _reorder: Option<Reorder>,
+    ) {
+        // Grant whatever geometry is asked for, fullscreen windows keep theirs
+        if window.is_fullscreen() {
+            let _ = window.configure(None);
+            return;
+        }
+        let mut geometry = window.geometry();
+        geometry.loc.x = x.unwrap_or(geometry.loc.x);
+        geometry.loc.y = y.unwrap_or(geometry.loc.y);
+        geometry.size.w = w.map_or(geometry.size.w, |w| w as i32);
+        geometry.size.h = h.map_or(geometry.size.h, |h| h as i32);
+        if let Err(err) = window.configure(geometry) {
+            warn!("Failed to configure X11 window: {err}");
+        }
+    }
+
+    fn configure_notify(
+        &mut self,
+        _xwm: XwmId,
+        window: X11Surface,
+        _geometry: Rectangle<i32, Logical>,
+        _above: Option<X11Window>,
+    ) {
+        if let Some(surface) = window.wl_surface() {
+            self.damage_surface(&surface);
+        }
+    }
+
+    fn property_notify(&mut self, _xwm: XwmId, window: X11Surface, property: WmWindowProperty) {
+        if matches!(property, WmWindowProperty::Title | WmWindowProperty::Class) {
+            send_identity(&window);
+        }
+    }
+
+    fn fullscreen_request(&mut self, _xwm: XwmId, window: X11Surface) {
+        let Some(output) = self.window_output() else {
+            return;
+        };
+        // X11 coordinates are logical ones times Xwayland's client scale
+        let Some(size) = output_logical_size(output) else {
+            return;
+        };
+        let size = size.upscale(self.xwayland_scale() as i32);
+        let geometry = Rectangle::new((0, 0).into(), (size.w, size.h).into());
+        if let Err(err) = window.set_fullscreen(true) {
+            warn!("Failed to make X11 window fullscreen: {err}");
+        } else if let Err(err) = window.configure(geometry) {
+            warn!("Failed to make X11 window fullscreen: {err}");
+        }
+    }
+
+    fn unfullscreen_request(&mut self, _xwm: XwmId, window: X11Surface) {
+        if let Err(err) = window.set_fullscreen(false) {
+            warn!("Failed to leave fullscreen on X11 window: {err}");
+        }
+    }
+
+    fn resize_request(
+        &mut self,
+        _xwm: XwmId,
+        _window: X11Surface,
+        _button: u32,
+        _resize_edge: ResizeEdge,
+    ) {
+        // Interactive resizes aren't supported yet
+    }
+
+    fn move_request(&mut self, _xwm: XwmId, _window: X11Surface, _button: u32) {
+        // Interactive moves aren't supported yet
+    }
+
+    fn allow_selection_access(&mut self, _xwm: XwmId, _selection: SelectionTarget) -> bool {
+        // Only while an X11 window has keyboard focus, like Wayland clients
+        let focus_client = self
+            .seat
+            .get_keyboard()
+            .and_then(|keyboard| keyboard.current_focus())
+            .and_then(|surface| surface.client());
+        match (focus_client, &self.xwayland_state.client) {
+            (Some(focus), Some(xwayland)) => focus.id() == xwayland.id(),
+            _ => false,
+        }
+    }
+
+    fn send_selection(
+        &mut self,
+        _xwm: XwmId,
+        selection: SelectionTarget,
+        mime_type: String,
+        fd: OwnedFd,
+    ) {
+        let result = match selection {
+            SelectionTarget::Clipboard => {
+                request_data_device_client_selection(&self.seat, mime_type, fd)
+                    .map_err(|err| err.to_string())
+            }
+            SelectionTarget::Primary => request_primary_client_selection(&self.seat, mime_type, fd)
+                .map_err(|err| err.to_string()),
+        };
+        if let Err(err) = result {
+            warn!("Failed to send {selection:?} selection to X11: {err}");
+        }
+    }
+
+    fn new_selection(&mut self, _xwm: XwmId, selection: SelectionTarget, mime_types: Vec<String>) {
+        match selection {
+            SelectionTarget::Clipboard => {
+                set_data_device_selection(&self.dh, &self.seat, mime_types, ())
+            }
+            SelectionTarget::Primary => set_primary_selection(&self.dh, &self.seat, mime_types, ()),
+        }
+    }
+
+    fn cleared_selection(&mut self, _xwm: XwmId, selection: SelectionTarget) {
+        // Only clear a selection that came from X11: the compositor sets those on its
+        // behalf, with user data, while the ones of Wayland clients have none
+        match selection {
+            SelectionTarget::Clipboard => {
+                if current_data_device_selection_userdata(&self.seat).is_some() {
+                    clear_data_device_selection(&self.dh, &self.seat);
+                }
+            }
+            SelectionTarget::Primary => {
+                if current_primary_selection_userdata(&self.seat).is_some() {
+                    clear_primary_selection(&self.dh, &self.seat);
+                }
+            }
+        }
+    }
+
+    fn disconnected(&mut self, _xwm: XwmId) {
+        tracing::info!("Xwayland disconnected");
+        for window in std::mem::take(&mut self.xwayland_state.windows) {
+            self.x11_window_closed(&window);
+        }
+        self.xwayland_stopped();
+        self.damage_all_outputs();
+    }
+}
+
+/// Implementation of the xwayland-shell protocol, used by Xwayland to tell which
+/// `wl_surface` shows which X11 window
+impl XWaylandShellHandler for StarforgeState {
+    fn xwayland_shell_state(&mut self) -> &mut XWaylandShellState {
+        &mut self.xwayland_state.shell_state
+    }
+
+    fn surface_associated(&mut self, _xwm: XwmId, wl_surface: WlSurface, window: X11Surface) {
+        if window.is_override_redirect() {
+            self.damage_surface(&wl_surface);
+            return;
+        }
+        // Managed windows are toplevels like xdg ones from now on
+        tracing::info!("New X11 window associated");
+        self.xwayland_state
+            .toplevels
+            .push((window.clone(), wl_surface.clone()));
+        self.animation_state.window_opened(&wl_surface);
+        add_toplevel(&mut self.foreign_toplevel_list_state, &wl_surface);
+        send_identity(&window);
+        self.damage_surface(&wl_surface);
+    }
+}
+
+impl StarforgeState {
+    /// Take a managed X11 window out of the window list, if it made it there
+    fn x11_window_closed(&mut self, window: &X11Surface) {
+        let toplevels = &mut self.xwayland_state.toplevels;
+        let Some(index) = toplevels.iter().position(|(known, _)| known == window) else {
+            return;
+        };
+        let (_, surface) = toplevels.remove(index);
+        // Hold on to the last buffers so the close animation can still draw the window
+        let snapshot = WindowSnapshot::capture(&surface);
+        let geometry = snapshot.bounding_box().to_f64();
+        self.animation_state
+            .window_closed(&surface, snapshot, geometry);
+        self.image_copy_capture_state.toplevel_closed(&surface);
+        if let Some(handle) = toplevel_handle(&surface) {
+            self.foreign_toplevel_list_state.remove_toplevel(&handle);
+        }
+    }
+}
+
+/// Tell foreign toplevel list clients the title and class of an X11 window
+fn send_identity(window: &X11Surface) {
+    if let Some(handle) = window.wl_surface().as_ref().and_then(toplevel_handle) {
+        handle.send_title(&window.title());
+        handle.send_app_id(&window.class());
+        handle.send_done();
+    }
+}
+
+// Delegate the xwayland-shell protocol implementation to our handler
+delegate_xwayland_shell!(StarforgeState);
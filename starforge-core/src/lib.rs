@@ -1,6 +1,15 @@
+pub mod animation;
+pub mod cursor;
+pub mod decoration;
 pub mod error;
+pub mod frame_scheduler;
 pub mod handlers;
+pub mod night_light;
+pub mod protocols;
+pub mod scale;
 pub mod state;
+#[cfg(feature = "xwayland")]
+pub mod xwayland;
 
 pub use error::{StarforgeError, StarforgeResult};
 pub use state::StarforgeState;
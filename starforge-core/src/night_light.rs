@@ -0,0 +1,196 @@
+//! Night light schedule: the colour temperature outputs should have at a given time
+//!
+//! With a configured location the temperature follows the sun, moving between the day
+//! and night temperatures over a transition centred on sunrise and sunset. Sun times come
+//! from the sunrise equation, so no network or time zone database is needed. Without a
+//! location the night temperature applies all the time.
+
+use std::time::{Duration, SystemTime};
+
+/// Julian date of the Unix epoch
+const UNIX_EPOCH_JULIAN_DATE: f64 = 2_440_587.5;
+/// Julian date of J2000.0
+const J2000: f64 = 2_451_545.0;
+const SECONDS_PER_DAY: f64 = 86_400.0;
+/// Tilt of the earth's axis in degrees
+const OBLIQUITY: f64 = 23.4397;
+/// Solar altitude at sunrise and sunset, accounting for refraction and the sun's radius
+const HORIZON_ALTITUDE: f64 = -0.833;
+
+/// Sunrise and sunset of one day
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SunTimes {
+    /// Sunrise and sunset in seconds since the Unix epoch
+    Normal { sunrise: f64, sunset: f64 },
+    /// The sun doesn't set
+    PolarDay,
+    /// The sun doesn't rise
+    PolarNight,
+}
+
+/// Sunrise and sunset on the UTC day `day` (days since the Unix epoch) at a location in
+/// degrees, north and east positive
+pub fn sun_times(day: i64, latitude: f64, longitude: f64) -> SunTimes {
+    // Days since J2000 of the solar noon closest to the day's UTC noon
+    let julian_day = (day as f64 + 0.5 + UNIX_EPOCH_JULIAN_DATE - J2000 + 0.0008).round();
+    let mean_solar_time = julian_day - longitude / 360.0;
+
+    let mean_anomaly = (357.5291 + 0.985_600_28 * mean_solar_time).rem_euclid(360.0);
+    let m = mean_anomaly.to_radians();
+    let center = 1.9148 * m.sin() + 0.02 * (2.0 * m).sin() + 0.0003 * (3.0 * m).sin();
+    let ecliptic_longitude = (mean_anomaly + center + 180.0 + 102.9372)
+        .rem_euclid(360.0)
+        .to_radians();
+    let transit =
+        J2000 + mean_solar_time + 0.0053 * m.sin() - 0.0069 * (2.0 * ecliptic_longitude).sin();
+
+    let declination = (ecliptic_longitude.sin() * OBLIQUITY.to_radians().sin()).asin();
+    let latitude = latitude.to_radians();
+    let cos_hour_angle = (HORIZON_ALTITUDE.to_radians().sin() - latitude.sin() * declination.sin())
+        / (latitude.cos() * declination.cos());
+    if cos_hour_angle > 1.0 {
+        return SunTimes::PolarNight;
+    }
+    if cos_hour_angle < -1.0 {
+        return SunTimes::PolarDay;
+    }
+
+    let half_day = cos_hour_angle.acos().to_degrees() / 360.0;
+    let to_unix = |julian_date: f64| (julian_date - UNIX_EPOCH_JULIAN_DATE) * SECONDS_PER_DAY;
+    SunTimes::Normal {
+        sunrise: to_unix(transit - half_day),
+        sunset: to_unix(transit + half_day),
+    }
+}
+
+/// When outputs use their day and night colour temperatures
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct NightLightSchedule {
+    /// Colour temperature in Kelvin while the sun is up
+    pub day_temperature: u32,
+    /// Colour temperature in Kelvin while the sun is down
+    pub night_temperature: u32,
+    /// Latitude and longitude in degrees, north and east positive
+    pub location: Option<(f64, f64)>,
+    /// Length of the transition around sunrise and sunset
+    pub transition: Duration,
+}
+
+impl NightLightSchedule {
+    /// How much of daylight there is at `time`, from 0 at night to 1 during the day
+    pub fn daylight(&self, time: SystemTime) -> f64 {
+        let Some((latitude, longitude)) = self.location else {
+            return 0.0;
+        };
+        let now = match time.duration_since(SystemTime::UNIX_EPOCH) {
+            Ok(since_epoch) => since_epoch.as_secs_f64(),
+            Err(before_epoch) => -before_epoch.duration().as_secs_f64(),
+        };
+        let today = (now / SECONDS_PER_DAY).floor() as i64;
+        let transition = self.transition.as_secs_f64();
+
+        // The local day may start on the previous or end on the next UTC day
+        (today - 1..=today + 1)
+            .map(|day| match sun_times(day, latitude, longitude) {
+                SunTimes::Normal { sunrise, sunset } => {
+                    ramp(now - sunrise, transition) * ramp(sunset - now, transition)
+                }
+                SunTimes::PolarDay => 1.0,
+                SunTimes::PolarNight => 0.0,
+            })
+            .fold(0.0, f64::max)
+    }
+
+    /// Colour temperature in Kelvin at `time`
+    pub fn temperature(&self, time: SystemTime) -> u32 {
+        let day = self.day_temperature as f64;
+        let night = self.night_temperature as f64;
+        (night + (day - night) * self.daylight(time)).round() as u32
+    }
+}
+
+/// 0 before `-transition / 2`, 1 after `transition / 2`, linear in between
+fn ramp(t: f64, transition: f64) -> f64 {
+    if transition <= 0.0 {
+        return if t >= 0.0 { 1.0 } else { 0.0 };
+    }
+    (t / transition + 0.5).clamp(0.0, 1.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 2024-06-21 and 2024-12-21, in days since the Unix epoch
+    const SUMMER_SOLSTICE: i64 = 19_895;
+    const WINTER_SOLSTICE: i64 = 20_078;
+    const LONDON: (f64, f64) = (51.5074, -0.1278);
+
+    fn utc(day: i64, hour: f64) -> f64 {
+        day as f64 * SECONDS_PER_DAY + hour * 3600.0
+    }
+
+    fn at(seconds: f64) -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_secs_f64(seconds)
+    }
+
+    #[test]
+    fn london_summer_solstice() {
+        let SunTimes::Normal { sunrise, sunset } = sun_times(SUMMER_SOLSTICE, LONDON.0, LONDON.1)
+        else {
+            panic!("the sun rises in London");
+        };
+        // 03:43 and 20:21 UTC
+        assert!((sunrise - utc(SUMMER_SOLSTICE, 3.0 + 43.0 / 60.0)).abs() < 300.0);
+        assert!((sunset - utc(SUMMER_SOLSTICE, 20.0 + 21.0 / 60.0)).abs() < 300.0);
+    }
+
+    #[test]
+    fn far_east_days_start_on_the_previous_utc_day() {
+        // Tokyo, sunrise at 04:25 local time is 19:25 UTC the day before
+        let SunTimes::Normal { sunrise, .. } = sun_times(SUMMER_SOLSTICE, 35.6762, 139.6503) else {
+            panic!("the sun rises in Tokyo");
+        };
+        assert!((sunrise - utc(SUMMER_SOLSTICE - 1, 19.0 + 25.0 / 60.0)).abs() < 300.0);
+    }
+
+    #[test]
+    fn polar_day_and_night() {
+        let tromso = (69.6492, 18.9553);
+        assert_eq!(
+            sun_times(SUMMER_SOLSTICE, tromso.0, tromso.1),
+            SunTimes::PolarDay
+        );
+        assert_eq!(
+            sun_times(WINTER_SOLSTICE, tromso.0, tromso.1),
+            SunTimes::PolarNight
+        );
+    }
+
+    #[test]
+    fn temperature_follows_the_sun() {
+        let schedule = NightLightSchedule {
+            day_temperature: 6500,
+            night_temperature: 3500,
+            location: Some(LONDON),
+            transition: Duration::from_secs(3600),
+        };
+        assert_eq!(schedule.temperature(at(utc(SUMMER_SOLSTICE, 12.0))), 6500);
+        assert_eq!(schedule.temperature(at(utc(SUMMER_SOLSTICE, 0.0))), 3500);
+        let SunTimes::Normal { sunset, .. } = sun_times(SUMMER_SOLSTICE, LONDON.0, LONDON.1) else {
+            unreachable!()
+        };
+        assert_eq!(schedule.temperature(at(sunset)), 5000);
+    }
+
+    #[test]
+    fn without_a_location_it_is_always_night() {
+        let schedule = NightLightSchedule {
+            day_temperature: 6500,
+            night_temperature: 4000,
+            location: None,
+            transition: Duration::ZERO,
+        };
+        assert_eq!(schedule.temperature(at(utc(SUMMER_SOLSTICE, 12.0))), 4000);
+    }
+}
@@ -0,0 +1,810 @@
+use super::{
+    ColorManagementState, ColorManagementSurfaceCachedState, ImageDescription, Luminances,
+    MAX_LUMINANCE, MasteringVolume, NamedPrimaries, NamedTransferFunction, PrimaryChromaticities,
+    icc, output_data,
+};
+use crate::StarforgeState;
+use smithay::{
+    output::Output,
+    reexports::{
+        wayland_protocols::wp::color_management::v1::server::{
+            wp_color_management_output_v1::{self, WpColorManagementOutputV1},
+            wp_color_management_surface_feedback_v1::{self, WpColorManagementSurfaceFeedbackV1},
+            wp_color_management_surface_v1::{self, WpColorManagementSurfaceV1},
+            wp_color_manager_v1::{self, WpColorManagerV1},
+            wp_image_description_creator_icc_v1::{self, WpImageDescriptionCreatorIccV1},
+            wp_image_description_creator_params_v1::{self, WpImageDescriptionCreatorParamsV1},
+            wp_image_description_info_v1::WpImageDescriptionInfoV1,
+            wp_image_description_v1::{self, WpImageDescriptionV1},
+        },
+        wayland_server::{
+            Client, DataInit, Dispatch, DisplayHandle, GlobalDispatch, New, Resource, WEnum, Weak,
+            protocol::wl_surface::WlSurface,
+        },
+    },
+    wayland::compositor,
+};
+use std::{
+    fs::File,
+    os::unix::fs::FileExt,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+};
+
+/// Largest ICC file accepted from clients, as allowed by the protocol
+const MAX_ICC_SIZE: u32 = 32 * 1024 * 1024;
+
+/// Marks a surface that already has a wp_color_management_surface_v1 object
+#[derive(Debug, Default)]
+struct ColorManagementSurfaceData {
+    attached: AtomicBool,
+}
+
+/// User data of a wp_image_description_v1 object
+#[derive(Debug)]
+pub struct ImageDescriptionData {
+    /// The description, or `None` if creating it failed
+    description: Option<Arc<ImageDescription>>,
+    /// Whether clients may call get_information on it
+    queryable: bool,
+}
+
+/// Properties collected by a wp_image_description_creator_params_v1 object
+#[derive(Debug, Default)]
+pub struct ParametricCreator {
+    transfer_function: Option<NamedTransferFunction>,
+    named_primaries: Option<NamedPrimaries>,
+    primaries: Option<PrimaryChromaticities>,
+    luminances: Option<Luminances>,
+    mastering: MasteringVolume,
+}
+
+impl GlobalDispatch<WpColorManagerV1, ()> for StarforgeState {
+    fn bind(
+        _state: &mut Self,
+        _dh: &DisplayHandle,
+        _client: &Client,
+        resource: New<WpColorManagerV1>,
+        _global_data: &(),
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        let manager = data_init.init(resource, ());
+
+        manager.supported_intent(wp_color_manager_v1::RenderIntent::Perceptual);
+        for feature in [
+            wp_color_manager_v1::Feature::IccV2V4,
+            wp_color_manager_v1::Feature::Parametric,
+            wp_color_manager_v1::Feature::SetPrimaries,
+            wp_color_manager_v1::Feature::SetLuminances,
+            wp_color_manager_v1::Feature::SetMasteringDisplayPrimaries,
+            wp_color_manager_v1::Feature::WindowsScrgb,
+        ] {
+            manager.supported_feature(feature);
+        }
+        for tf in NamedTransferFunction::SUPPORTED {
+            manager.supported_tf_named(tf.to_protocol());
+        }
+        for primaries in NamedPrimaries::SUPPORTED {
+            manager.supported_primaries_named(primaries.to_protocol());
+        }
+        manager.done();
+    }
+}
+
+impl Dispatch<WpColorManagerV1, ()> for StarforgeState {
+    fn request(
+        state: &mut Self,
+        _client: &Client,
+        manager: &WpColorManagerV1,
+        request: wp_color_manager_v1::Request,
+        _data: &(),
+        _dh: &DisplayHandle,
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        match request {
+            wp_color_manager_v1::Request::GetOutput { id, output } => {
+                let output = Output::from_resource(&output);
+                let instance = data_init.init(id, output.clone());
+                if let Some(output) = output {
+                    output_data(&output)
+                        .lock()
+                        .unwrap()
+                        .instances
+                        .push(instance);
+                }
+            }
+            wp_color_manager_v1::Request::GetSurface { id, surface } => {
+                let already_attached = compositor::with_states(&surface, |states| {
+                    states
+                        .data_map
+                        .insert_if_missing_threadsafe(ColorManagementSurfaceData::default);
+                    states
+                        .data_map
+                        .get::<ColorManagementSurfaceData>()
+                        .unwrap()
+                        .attached
+                        .swap(true, Ordering::SeqCst)
+                });
+                if already_attached {
+                    manager.post_error(
+                        wp_color_manager_v1::Error::SurfaceExists,
+                        "the surface already has a color management surface object",
+                    );
+                    return;
+                }
+                data_init.init(id, surface.downgrade());
+            }
+            wp_color_manager_v1::Request::GetSurfaceFeedback { id, surface } => {
+                let feedback = data_init.init(id, surface.downgrade());
+                state.color_management_state.feedbacks.push(feedback);
+            }
+            wp_color_manager_v1::Request::CreateIccCreator { obj } => {
+                data_init.init(obj, Mutex::new(None::<Vec<u8>>));
+            }
+            wp_color_manager_v1::Request::CreateParametricCreator { obj } => {
+                data_init.init(obj, Mutex::new(ParametricCreator::default()));
+            }
+            wp_color_manager_v1::Request::CreateWindowsScrgb { image_description } => {
+                let identity = state.color_management_state.next_identity();
+                let mut description = ImageDescription::named(
+                    identity,
+                    NamedPrimaries::Srgb,
+                    NamedTransferFunction::ExtLinear,
+                );
+                // 1.0 is 80 cd/m², and BT.2408 reference white sits at 2.5375
+                description.luminances = Luminances {
+                    min: 0.0,
+                    max: 80.0,
+                    reference: 203.0,
+                };
+                let resource = data_init.init(
+                    image_description,
+                    ImageDescriptionData {
+                        description: Some(Arc::new(description)),
+                        queryable: false,
+                    },
+                );
+                resource.ready(identity);
+            }
+            wp_color_manager_v1::Request::Destroy => {}
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl Dispatch<WpColorManagementOutputV1, Option<Output>> for StarforgeState {
+    fn request(
+        _state: &mut Self,
+        _client: &Client,
+        _resource: &WpColorManagementOutputV1,
+        request: wp_color_management_output_v1::Request,
+        output: &Option<Output>,
+        _dh: &DisplayHandle,
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        match request {
+            wp_color_management_output_v1::Request::GetImageDescription { image_description } => {
+                let Some(output) = output else {
+                    // The wl_output is gone, so the object is inert
+                    let resource = data_init.init(
+                        image_description,
+                        ImageDescriptionData {
+                            description: None,
+                            queryable: false,
+                        },
+                    );
+                    resource.failed(
+                        wp_image_description_v1::Cause::NoOutput,
+                        "the output no longer exists".into(),
+                    );
+                    return;
+                };
+                let description = ColorManagementState::output_image_description(output);
+                let identity = description.identity;
+                let resource = data_init.init(
+                    image_description,
+                    ImageDescriptionData {
+                        description: Some(description),
+                        queryable: true,
+                    },
+                );
+                resource.ready(identity);
+            }
+            wp_color_management_output_v1::Request::Destroy => {}
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl Dispatch<WpColorManagementSurfaceV1, Weak<WlSurface>> for StarforgeState {
+    fn request(
+        _state: &mut Self,
+        _client: &Client,
+        resource: &WpColorManagementSurfaceV1,
+        request: wp_color_management_surface_v1::Request,
+        surface: &Weak<WlSurface>,
+        _dh: &DisplayHandle,
+        _data_init: &mut DataInit<'_, Self>,
+    ) {
+        let Ok(surface) = surface.upgrade() else {
+            if !matches!(request, wp_color_management_surface_v1::Request::Destroy) {
+                resource.post_error(
+                    wp_color_management_surface_v1::Error::Inert,
+                    "the surface has been destroyed",
+                );
+            }
+            return;
+        };
+
+        match request {
+            wp_color_management_surface_v1::Request::SetImageDescription {
+                image_description,
+                render_intent,
+            } => {
+                if render_intent != WEnum::Value(wp_color_manager_v1::RenderIntent::Perceptual) {
+                    resource.post_error(
+                        wp_color_management_surface_v1::Error::RenderIntent,
+                        "unsupported render intent",
+                    );
+                    return;
+                }
+                let Some(description) = image_description
+                    .data::<ImageDescriptionData>()
+                    .and_then(|data| data.description.clone())
+                else {
+                    resource.post_error(
+                        wp_color_management_surface_v1::Error::ImageDescription,
+                        "the image description failed",
+                    );
+                    return;
+                };
+                compositor::with_states(&surface, |states| {
+                    states
+                        .cached_state
+                        .get::<ColorManagementSurfaceCachedState>()
+                        .pending()
+                        .description = Some(description);
+                });
+            }
+            wp_color_management_surface_v1::Request::UnsetImageDescription => {
+                unset_image_description(&surface);
+            }
+            wp_color_management_surface_v1::Request::Destroy => {
+                // Destroying the object unsets the description on the next commit
+                unset_image_description(&surface);
+                compositor::with_states(&surface, |states| {
+                    if let Some(data) = states.data_map.get::<ColorManagementSurfaceData>() {
+                        data.attached.store(false, Ordering::SeqCst);
+                    }
+                });
+            }
+            _ => unreachable!(),
+        }
+    }
+}
+
+fn unset_image_description(surface: &WlSurface) {
+    compositor::with_states(surface, |states| {
+        states
+            .cached_state
+            .get::<ColorManagementSurfaceCachedState>()
+            .pending()
+            .description = None;
+    });
+}
+
+impl Dispatch<WpColorManagementSurfaceFeedbackV1, Weak<WlSurface>> for StarforgeState {
+    fn request(
+        state: &mut Self,
+        _client: &Client,
+        resource: &WpColorManagementSurfaceFeedbackV1,
+        request: wp_color_management_surface_feedback_v1::Request,
+        surface: &Weak<WlSurface>,
+        _dh: &DisplayHandle,
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        match request {
+            wp_color_management_surface_feedback_v1::Request::GetPreferred {
+                image_description,
+            }
+            | wp_color_management_surface_feedback_v1::Request::GetPreferredParametric {
+                image_description,
+            } => {
+                if !surface.is_alive() {
+                    resource.post_error(
+                        wp_color_management_surface_feedback_v1::Error::Inert,
+                        "the surface has been destroyed",
+                    );
+                    return;
+                }
+                let description = state.color_management_state.preferred_image_description();
+                let identity = description.identity;
+                let resource = data_init.init(
+                    image_description,
+                    ImageDescriptionData {
+                        description: Some(description),
+                        queryable: true,
+                    },
+                );
+                resource.ready(identity);
+            }
+            wp_color_management_surface_feedback_v1::Request::Destroy => {
+                state
+                    .color_management_state
+                    .feedbacks
+                    .retain(|feedback| feedback != resource);
+            }
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl Dispatch<WpImageDescriptionCreatorIccV1, Mutex<Option<Vec<u8>>>> for StarforgeState {
+    fn request(
+        state: &mut Self,
+        _client: &Client,
+        resource: &WpImageDescriptionCreatorIccV1,
+        request: wp_image_description_creator_icc_v1::Request,
+        data: &Mutex<Option<Vec<u8>>>,
+        _dh: &DisplayHandle,
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        use wp_image_description_creator_icc_v1::Error;
+
+        match request {
+            wp_image_description_creator_icc_v1::Request::SetIccFile {
+                icc_profile,
+                offset,
+                length,
+            } => {
+                let mut profile = data.lock().unwrap();
+                if profile.is_some() {
+                    resource.post_error(Error::AlreadySet, "the ICC file was already set");
+                    return;
+                }
+                if length == 0 || length > MAX_ICC_SIZE {
+                    resource.post_error(Error::BadSize, "invalid ICC file size");
+                    return;
+                }
+                let file = File::from(icc_profile);
+                let Ok(metadata) = file.metadata() else {
+                    resource.post_error(Error::BadFd, "the ICC file descriptor is not readable");
+                    return;
+                };
+                if !metadata.is_file() {
+                    resource.post_error(Error::BadFd, "the ICC file descriptor is not seekable");
+                    return;
+                }
+                if offset as u64 + length as u64 > metadata.len() {
+                    resource.post_error(Error::OutOfFile, "the ICC data is out of file bounds");
+                    return;
+                }
+                let mut bytes = vec![0; length as usize];
+                if file.read_exact_at(&mut bytes, offset as u64).is_err() {
+                    resource.post_error(Error::BadFd, "failed to read the ICC file");
+                    return;
+                }
+                *profile = Some(bytes);
+            }
+            wp_image_description_creator_icc_v1::Request::Create { image_description } => {
+                let Some(profile) = data.lock().unwrap().take() else {
+                    resource.post_error(Error::IncompleteSet, "no ICC file was set");
+                    return;
+                };
+                match icc::parse(&profile) {
+                    Ok((primaries, transfer_function)) => {
+                        let identity = state.color_management_state.next_identity();
+                        let description = ImageDescription {
+                            identity,
+                            transfer_function,
+                            named_primaries: None,
+                            primaries,
+                            luminances: transfer_function.default_luminances(),
+                            mastering: MasteringVolume::default(),
+                        };
+                        let resource = data_init.init(
+                            image_description,
+                            ImageDescriptionData {
+                                description: Some(Arc::new(description)),
+                                queryable: false,
+                            },
+                        );
+                        resource.ready(identity);
+                    }
+                    Err(message) => {
+                        tracing::debug!("Rejected ICC profile: {}", message);
+                        let resource = data_init.init(
+                            image_description,
+                            ImageDescriptionData {
+                                description: None,
+                                queryable: false,
+                            },
+                        );
+                        resource.failed(wp_image_description_v1::Cause::Unsupported, message);
+                    }
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl Dispatch<WpImageDescriptionCreatorParamsV1, Mutex<ParametricCreator>> for StarforgeState {
+    fn request(
+        state: &mut Self,
+        _client: &Client,
+        resource: &WpImageDescriptionCreatorParamsV1,
+        request: wp_image_description_creator_params_v1::Request,
+        data: &Mutex<ParametricCreator>,
+        _dh: &DisplayHandle,
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        use wp_image_description_creator_params_v1::{Error, Request};
+
+        let mut creator = data.lock().unwrap();
+        match request {
+            Request::SetTfNamed { tf } => {
+                if creator.transfer_function.is_some() {
+                    resource.post_error(Error::AlreadySet, "the transfer function was already set");
+                    return;
+                }
+                let Some(tf) = tf
+                    .into_result()
+                    .ok()
+                    .and_then(NamedTransferFunction::from_protocol)
+                else {
+                    resource.post_error(Error::InvalidTf, "unsupported transfer function");
+                    return;
+                };
+                creator.transfer_function = Some(tf);
+            }
+            Request::SetTfPower { .. } => {
+                resource.post_error(
+                    Error::UnsupportedFeature,
+                    "power transfer functions are not supported",
+                );
+            }
+            Request::SetPrimariesNamed { primaries } => {
+                if creator.primaries.is_some() {
+                    resource.post_error(Error::AlreadySet, "the primaries were already set");
+                    return;
+                }
+                let Some(primaries) = primaries
+                    .into_result()
+                    .ok()
+                    .and_then(NamedPrimaries::from_protocol)
+                else {
+                    resource.post_error(Error::InvalidPrimariesNamed, "unsupported primaries");
+                    return;
+                };
+                creator.named_primaries = Some(primaries);
+                creator.primaries = Some(primaries.chromaticities());
+            }
+            Request::SetPrimaries {
+                r_x,
+                r_y,
+                g_x,
+                g_y,
+                b_x,
+                b_y,
+                w_x,
+                w_y,
+            } => {
+                if creator.primaries.is_some() {
+                    resource.post_error(Error::AlreadySet, "the primaries were already set");
+                    return;
+                }
+                creator.primaries = Some(chromaticities([r_x, r_y, g_x, g_y, b_x, b_y, w_x, w_y]));
+            }
+            Request::SetLuminances {
+                min_lum,
+                max_lum,
+                reference_lum,
+            } => {
+                if creator.luminances.is_some() {
+                    resource.post_error(Error::AlreadySet, "the luminances were already set");
+                    return;
+                }
+                // The minimum is sent in units of 0.0001 cd/m²
+                let luminances = Luminances {
+                    min: min_lum as f32 / 10_000.0,
+                    max: max_lum as f32,
+                    reference: reference_lum as f32,
+                };
+                if luminances.max <= luminances.min || luminances.reference <= luminances.min {
+                    resource.post_error(Error::InvalidLuminance, "invalid luminance range");
+                    return;
+                }
+                creator.luminances = Some(luminances);
+            }
+            Request::SetMasteringDisplayPrimaries {
+                r_x,
+                r_y,
+                g_x,
+                g_y,
+                b_x,
+                b_y,
+                w_x,
+                w_y,
+            } => {
+                if creator.mastering.primaries.is_some() {
+                    resource.post_error(
+                        Error::AlreadySet,
+                        "the mastering display primaries were already set",
+                    );
+                    return;
+                }
+                creator.mastering.primaries =
+                    Some(chromaticities([r_x, r_y, g_x, g_y, b_x, b_y, w_x, w_y]));
+            }
+            Request::SetMasteringLuminance { min_lum, max_lum } => {
+                if creator.mastering.luminance.is_some() {
+                    resource
+                        .post_error(Error::AlreadySet, "the mastering luminance was already set");
+                    return;
+                }
+                let min = min_lum as f32 / 10_000.0;
+                let max = max_lum as f32;
+                if max <= min || max > MAX_LUMINANCE {
+                    resource.post_error(Error::InvalidLuminance, "invalid mastering luminance");
+                    return;
+                }
+                creator.mastering.luminance = Some((min, max));
+            }
+            Request::SetMaxCll { max_cll } => {
+                if creator.mastering.max_cll.is_some() {
+                    resource.post_error(Error::AlreadySet, "max_cll was already set");
+                    return;
+                }
+                creator.mastering.max_cll = Some(max_cll as f32);
+            }
+            Request::SetMaxFall { max_fall } => {
+                if creator.mastering.max_fall.is_some() {
+                    resource.post_error(Error::AlreadySet, "max_fall was already set");
+                    return;
+                }
+                creator.mastering.max_fall = Some(max_fall as f32);
+            }
+            Request::Create { image_description } => {
+                let (Some(transfer_function), Some(primaries)) =
+                    (creator.transfer_function, creator.primaries)
+                else {
+                    resource.post_error(
+                        Error::IncompleteSet,
+                        "the transfer function and primaries must be set",
+                    );
+                    return;
+                };
+                // The protocol has no error for these, so the description fails instead
+                let mastering_valid = creator
+                    .mastering
+                    .primaries
+                    .is_none_or(|primaries| primaries.is_valid());
+                if !primaries.is_valid() || !mastering_valid {
+                    let resource = data_init.init(
+                        image_description,
+                        ImageDescriptionData {
+                            description: None,
+                            queryable: false,
+                        },
+                    );
+                    resource.failed(
+                        wp_image_description_v1::Cause::Unsupported,
+                        "the primaries don't span a color space containing the white point".into(),
+                    );
+                    return;
+                }
+                let identity = state.color_management_state.next_identity();
+                let description = ImageDescription {
+                    identity,
+                    transfer_function,
+                    named_primaries: creator.named_primaries,
+                    primaries,
+                    luminances: creator
+                        .luminances
+                        .unwrap_or_else(|| transfer_function.default_luminances()),
+                    mastering: creator.mastering,
+                };
+                let resource = data_init.init(
+                    image_description,
+                    ImageDescriptionData {
+                        description: Some(Arc::new(description)),
+                        queryable: false,
+                    },
+                );
+                resource.ready(identity);
+            }
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl Dispatch<WpImageDescriptionV1, ImageDescriptionData> for StarforgeState {
+    fn request(
+        _state: &mut Self,
+        _client: &Client,
+        resource: &WpImageDescriptionV1,
+        request: wp_image_description_v1::Request,
+        data: &ImageDescriptionData,
+        _dh: &DisplayHandle,
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        match request {
+            wp_image_description_v1::Request::GetInformation { information } => {
+                let Some(description) = &data.description else {
+                    resource.post_error(
+                        wp_image_description_v1::Error::NotReady,
+                        "the image description failed",
+                    );
+                    return;
+                };
+                if !data.queryable {
+                    resource.post_error(
+                        wp_image_description_v1::Error::NoInformation,
+                        "the image description can't be queried",
+                    );
+                    return;
+                }
+                let info = data_init.init(information, ());
+                send_information(&info, description);
+            }
+            wp_image_description_v1::Request::Destroy => {}
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl Dispatch<WpImageDescriptionInfoV1, ()> for StarforgeState {
+    fn request(
+        _state: &mut Self,
+        _client: &Client,
+        _resource: &WpImageDescriptionInfoV1,
+        _request: <WpImageDescriptionInfoV1 as Resource>::Request,
+        _data: &(),
+        _dh: &DisplayHandle,
+        _data_init: &mut DataInit<'_, Self>,
+    ) {
+        // wp_image_description_info_v1 has no requests
+    }
+}
+
+/// Send every parameter of a description through a wp_image_description_info_v1
+fn send_information(info: &WpImageDescriptionInfoV1, description: &ImageDescription) {
+    let [r_x, r_y, g_x, g_y, b_x, b_y, w_x, w_y] = chromaticity_values(&description.primaries);
+    info.primaries(r_x, r_y, g_x, g_y, b_x, b_y, w_x, w_y);
+    if let Some(primaries) = description.named_primaries {
+        info.primaries_named(primaries.to_protocol());
+    }
+    info.tf_named(description.transfer_function.to_protocol());
+
+    let luminances = description.luminances;
+    info.luminances(
+        (luminances.min * 10_000.0) as u32,
+        luminances.max as u32,
+        luminances.reference as u32,
+    );
+
+    // Without mastering metadata the target volume is the description's own volume
+    let mastering = &description.mastering;
+    let [r_x, r_y, g_x, g_y, b_x, b_y, w_x, w_y] = chromaticity_values(
+        mastering
+            .primaries
+            .as_ref()
+            .unwrap_or(&description.primaries),
+    );
+    info.target_primaries(r_x, r_y, g_x, g_y, b_x, b_y, w_x, w_y);
+    let (min, max) = mastering
+        .luminance
+        .unwrap_or((luminances.min, luminances.max));
+    info.target_luminance((min * 10_000.0) as u32, max as u32);
+    if let Some(max_cll) = mastering.max_cll {
+        info.target_max_cll(max_cll as u32);
+    }
+    if let Some(max_fall) = mastering.max_fall {
+        info.target_max_fall(max_fall as u32);
+    }
+    info.done();
+}
+
+/// Chromaticities are sent as integers in units of 1/1000000
+fn chromaticities(values: [i32; 8]) -> PrimaryChromaticities {
+    let [r_x, r_y, g_x, g_y, b_x, b_y, w_x, w_y] = values.map(|v| v as f32 / 1_000_000.0);
+    PrimaryChromaticities {
+        red: [r_x, r_y],
+        green: [g_x, g_y],
+        blue: [b_x, b_y],
+        white: [w_x, w_y],
+    }
+}
+
+fn chromaticity_values(primaries: &PrimaryChromaticities) -> [i32; 8] {
+    [
+        primaries.red,
+        primaries.green,
+        primaries.blue,
+        primaries.white,
+    ]
+    .as_flattened()
+    .iter()
+    .map(|&v| (v * 1_000_000.0).round() as i32)
+    .collect::<Vec<_>>()
+    .try_into()
+    .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocols::test_client::TestClient;
+    use wp_color_manager_v1::TransferFunction;
+    use wp_image_description_creator_params_v1 as params;
+
+    /// Create a description from sRGB-encoded `primaries`, returning its protocol id
+    fn create_description(client: &mut TestClient, primaries: [[f32; 2]; 4]) -> u32 {
+        let (_, creator) = client.create::<WpImageDescriptionCreatorParamsV1, _>(
+            1,
+            Mutex::new(ParametricCreator::default()),
+        );
+        client.request(
+            creator,
+            params::REQ_SET_TF_NAMED_OPCODE,
+            &[TransferFunction::Srgb as u32],
+        );
+        let values = chromaticity_values(&PrimaryChromaticities {
+            red: primaries[0],
+            green: primaries[1],
+            blue: primaries[2],
+            white: primaries[3],
+        });
+        client.request(
+            creator,
+            params::REQ_SET_PRIMARIES_OPCODE,
+            &values.map(|v| v as u32),
+        );
+        let description = client.new_id();
+        client.request(creator, params::REQ_CREATE_OPCODE, &[description]);
+        client.dispatch();
+        description
+    }
+
+    fn description_failed(client: &mut TestClient, id: u32) -> bool {
+        let description = client.object::<WpImageDescriptionV1>(id);
+        let data = description.data::<ImageDescriptionData>().unwrap();
+        let failed = client.events().iter().any(|event| {
+            event.sender == id && event.opcode == wp_image_description_v1::EVT_FAILED_OPCODE
+        });
+        assert_eq!(failed, data.description.is_none());
+        failed
+    }
+
+    #[test]
+    fn primaries_spanning_a_color_space_are_accepted() {
+        let mut client = TestClient::connect();
+        let bt2020 = NamedPrimaries::Bt2020.chromaticities();
+        let id = create_description(
+            &mut client,
+            [bt2020.red, bt2020.green, bt2020.blue, bt2020.white],
+        );
+        assert!(!description_failed(&mut client, id));
+    }
+
+    #[test]
+    fn degenerate_primaries_fail_the_description() {
+        let mut client = TestClient::connect();
+        let white = [0.3127, 0.3290];
+        for primaries in [
+            // Two primaries at the same point
+            [[0.64, 0.33], [0.64, 0.33], [0.15, 0.06], white],
+            // A primary without luminance
+            [[0.64, 0.33], [0.30, 0.60], [0.15, 0.0], white],
+            // A white point outside the gamut
+            [[0.64, 0.33], [0.30, 0.60], [0.15, 0.06], [0.7, 0.25]],
+        ] {
+            let id = create_description(&mut client, primaries);
+            assert!(description_failed(&mut client, id), "{primaries:?}");
+        }
+        assert_eq!(client.protocol_error(), None);
+    }
+}
@@ -0,0 +1,499 @@
+//! Minimal ICC profile parsing for wp_color_management_v1
+//!
+//! Only matrix/TRC RGB display profiles are understood. The colorants and tone curves are
+//! reduced to primaries and one of the named transfer functions the renderer implements.
+
+use super::{NamedTransferFunction, PrimaryChromaticities};
+
+/// Size of the fixed ICC header
+const HEADER_SIZE: usize = 128;
+
+/// PCS illuminant (D50) in XYZ, as ICC profiles encode it
+const D50_XYZ: [f32; 3] = [0.9642, 1.0, 0.8249];
+
+/// Largest error a sampled tone curve may have against a named transfer function
+const CURVE_TOLERANCE: f32 = 0.01;
+
+type Mat3 = [[f32; 3]; 3];
+
+/// Reduce an ICC profile to primaries and a named transfer function
+pub fn parse(profile: &[u8]) -> Result<(PrimaryChromaticities, NamedTransferFunction), String> {
+    if profile.len() < HEADER_SIZE + 4 || &profile[36..40] != b"acsp" {
+        return Err("not an ICC profile".into());
+    }
+    if &profile[16..20] != b"RGB " {
+        return Err("only RGB profiles are supported".into());
+    }
+
+    let tags = TagTable::new(profile)?;
+    let red = tags.xyz(b"rXYZ")?;
+    let green = tags.xyz(b"gXYZ")?;
+    let blue = tags.xyz(b"bXYZ")?;
+
+    // Colorants are stored adapted to the D50 PCS; undo that to find the real primaries
+    let (adaptation, white) = match tags.find(b"chad") {
+        Some(chad) => {
+            let chad = read_sf32_matrix(chad)?;
+            let inverse = invert(&chad).ok_or("singular chromatic adaptation matrix")?;
+            (inverse, apply(&inverse, D50_XYZ))
+        }
+        None => {
+            let white = tags.xyz(b"wtpt").unwrap_or(D50_XYZ);
+            (bradford(D50_XYZ, white), white)
+        }
+    };
+    let primaries = PrimaryChromaticities {
+        red: xyz_to_xy(apply(&adaptation, red))?,
+        green: xyz_to_xy(apply(&adaptation, green))?,
+        blue: xyz_to_xy(apply(&adaptation, blue))?,
+        white: xyz_to_xy(white)?,
+    };
+    if !primaries.is_valid() {
+        return Err("the colorants don't span a color space containing the white point".into());
+    }
+
+    // All three channels have to share a curve for a single transfer function to fit
+    let curves = [b"rTRC", b"gTRC", b"bTRC"].map(|sig| {
+        tags.find(sig)
+            .ok_or_else(|| "missing tone curve".to_string())
+            .and_then(Curve::parse)
+    });
+    let [red_curve, green_curve, blue_curve] = curves;
+    let (red_curve, green_curve, blue_curve) = (red_curve?, green_curve?, blue_curve?);
+    let transfer_function = red_curve.classify()?;
+    if green_curve.classify()? != transfer_function || blue_curve.classify()? != transfer_function {
+        return Err("tone curves differ between channels".into());
+    }
+
+    Ok((primaries, transfer_function))
+}
+
+/// The tag directory following the header
+struct TagTable<'a> {
+    profile: &'a [u8],
+    entries: Vec<([u8; 4], usize, usize)>,
+}
+
+impl<'a> TagTable<'a> {
+    fn new(profile: &'a [u8]) -> Result<Self, String> {
+        let count = read_u32(profile, HEADER_SIZE)? as usize;
+        let mut entries = Vec::with_capacity(count.min(64));
+        for i in 0..count {
+            let entry = HEADER_SIZE + 4 + i * 12;
+            let signature = profile
+                .get(entry..entry + 4)
+                .ok_or("truncated tag table")?
+                .try_into()
+                .unwrap();
+            let offset = read_u32(profile, entry + 4)? as usize;
+            let size = read_u32(profile, entry + 8)? as usize;
+            if offset
+                .checked_add(size)
+                .is_none_or(|end| end > profile.len())
+            {
+                return Err("tag data out of bounds".into());
+            }
+            entries.push((signature, offset, size));
+        }
+        Ok(Self { profile, entries })
+    }
+
+    fn find(&self, signature: &[u8; 4]) -> Option<&'a [u8]> {
+        self.entries
+            .iter()
+            .find(|(sig, _, _)| sig == signature)
+            .map(|&(_, offset, size)| &self.profile[offset..offset + size])
+    }
+
+    fn xyz(&self, signature: &[u8; 4]) -> Result<[f32; 3], String> {
+        let tag = self
+            .find(signature)
+            .ok_or_else(|| format!("missing {} tag", String::from_utf8_lossy(signature)))?;
+        if tag.get(..4) != Some(b"XYZ ") {
+            return Err("malformed XYZ tag".into());
+        }
+        Ok([
+            read_s15_fixed16(tag, 8)?,
+            read_s15_fixed16(tag, 12)?,
+            read_s15_fixed16(tag, 16)?,
+        ])
+    }
+}
+
+/// A decoded `curv` or `para` tone curve
+enum Curve {
+    Table(Vec<f32>),
+    Parametric {
+        g: f32,
+        a: f32,
+        b: f32,
+        c: f32,
+        d: f32,
+        e: f32,
+        f: f32,
+    },
+}
+
+impl Curve {
+    fn parse(tag: &[u8]) -> Result<Self, String> {
+        match tag.get(..4) {
+            Some(b"curv") => {
+                let count = read_u32(tag, 8)? as usize;
+                match count {
+                    0 => Ok(Self::power(1.0)),
+                    1 => Ok(Self::power(read_u16(tag, 12)? as f32 / 256.0)),
+                    _ => (0..count)
+                        .map(|i| Ok(read_u16(tag, 12 + i * 2)? as f32 / 65535.0))
+                        .collect::<Result<_, String>>()
+                        .map(Self::Table),
+                }
+            }
+            Some(b"para") => {
+                let function = read_u16(tag, 8)?;
+                let count = match function {
+                    0 => 1,
+                    1 => 3,
+                    2 => 4,
+                    3 => 5,
+                    4 => 7,
+                    _ => return Err("unknown parametric curve".into()),
+                };
+                let mut p = [0.0; 7];
+                for (i, value) in p.iter_mut().enumerate().take(count) {
+                    *value = read_s15_fixed16(tag, 12 + i * 4)?;
+                }
+                let [g, a, b, c, d, e, f] = p;
+                // Express every function type as the general type 4 curve
+                Ok(match function {
+                    0 => Self::power(g),
+                    1 => Self::Parametric {
+                        g,
+                        a,
+                        b,
+                        c: 0.0,
+                        d: -b / a,
+                        e: 0.0,
+                        f: 0.0,
+                    },
+                    2 => Self::Parametric {
+                        g,
+                        a,
+                        b,
+                        c: 0.0,
+                        d: -b / a,
+                        e: c,
+                        f: c,
+                    },
+                    3 => Self::Parametric {
+                        g,
+                        a,
+                        b,
+                        c,
+                        d,
+                        e: 0.0,
+                        f: 0.0,
+                    },
+                    _ => Self::Parametric {
+                        g,
+                        a,
+                        b,
+                        c,
+                        d,
+                        e,
+                        f,
+                    },
+                })
+            }
+            _ => Err("unknown tone curve type".into()),
+        }
+    }
+
+    fn power(g: f32) -> Self {
+        Self::Parametric {
+            g,
+            a: 1.0,
+            b: 0.0,
+            c: 0.0,
+            d: 0.0,
+            e: 0.0,
+            f: 0.0,
+        }
+    }
+
+    fn eval(&self, x: f32) -> f32 {
+        match self {
+            Self::Table(table) => {
+                let position = x * (table.len() - 1) as f32;
+                let i = (position.floor() as usize).min(table.len() - 2);
+                let t = position - i as f32;
+                table[i] * (1.0 - t) + table[i + 1] * t
+            }
+            &Self::Parametric {
+                g,
+                a,
+                b,
+                c,
+                d,
+                e,
+                f,
+            } => {
+                if x >= d {
+                    (a * x + b).max(0.0).powf(g) + e
+                } else {
+                    c * x + f
+                }
+            }
+        }
+    }
+
+    /// Find the named transfer function this curve matches
+    ///
+    /// sRGB and gamma 2.2 are close enough for both to be within the tolerance, so the
+    /// closest one is taken.
+    fn classify(&self) -> Result<NamedTransferFunction, String> {
+        [
+            NamedTransferFunction::ExtLinear,
+            NamedTransferFunction::Srgb,
+            NamedTransferFunction::Gamma22,
+        ]
+        .into_iter()
+        .map(|tf| {
+            let error = (0..=32)
+                .map(|i| {
+                    let x = i as f32 / 32.0;
+                    (self.eval(x) - reference_eotf(tf, x)).abs()
+                })
+                .fold(0.0, f32::max);
+            (tf, error)
+        })
+        .filter(|&(_, error)| error < CURVE_TOLERANCE)
+        .min_by(|(_, a), (_, b)| a.total_cmp(b))
+        .map(|(tf, _)| tf)
+        .ok_or_else(|| "tone curve matches no supported transfer function".into())
+    }
+}
+
+/// EOTF of the transfer functions an ICC tone curve can be matched against
+fn reference_eotf(tf: NamedTransferFunction, x: f32) -> f32 {
+    match tf {
+        NamedTransferFunction::Srgb if x <= 0.04045 => x / 12.92,
+        NamedTransferFunction::Srgb => ((x + 0.055) / 1.055).powf(2.4),
+        NamedTransferFunction::Gamma22 => x.powf(2.2),
+        _ => x,
+    }
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Result<u16, String> {
+    data.get(offset..offset + 2)
+        .map(|b| u16::from_be_bytes([b[0], b[1]]))
+        .ok_or_else(|| "truncated profile".into())
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Result<u32, String> {
+    data.get(offset..offset + 4)
+        .map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+        .ok_or_else(|| "truncated profile".into())
+}
+
+fn read_s15_fixed16(data: &[u8], offset: usize) -> Result<f32, String> {
+    Ok(read_u32(data, offset)? as i32 as f32 / 65536.0)
+}
+
+fn read_sf32_matrix(tag: &[u8]) -> Result<Mat3, String> {
+    if tag.get(..4) != Some(b"sf32") {
+        return Err("malformed chromatic adaptation tag".into());
+    }
+    let mut m = [[0.0; 3]; 3];
+    for (i, value) in m.iter_mut().flatten().enumerate() {
+        *value = read_s15_fixed16(tag, 8 + i * 4)?;
+    }
+    Ok(m)
+}
+
+fn xyz_to_xy([x, y, z]: [f32; 3]) -> Result<[f32; 2], String> {
+    let sum = x + y + z;
+    if !sum.is_finite() || sum <= 0.0 {
+        return Err("colorant without a chromaticity".into());
+    }
+    Ok([x / sum, y / sum])
+}
+
+fn apply(m: &Mat3, v: [f32; 3]) -> [f32; 3] {
+    m.map(|row| row[0] * v[0] + row[1] * v[1] + row[2] * v[2])
+}
+
+fn mul(a: &Mat3, b: &Mat3) -> Mat3 {
+    std::array::from_fn(|r| std::array::from_fn(|c| (0..3).map(|k| a[r][k] * b[k][c]).sum()))
+}
+
+fn invert(m: &Mat3) -> Option<Mat3> {
+    let cofactor =
+        |r0: usize, r1: usize, c0: usize, c1: usize| m[r0][c0] * m[r1][c1] - m[r0][c1] * m[r1][c0];
+    let det = m[0][0] * cofactor(1, 2, 1, 2) - m[0][1] * cofactor(1, 2, 0, 2)
+        + m[0][2] * cofactor(1, 2, 0, 1);
+    if !det.is_finite() || det.abs() < f32::EPSILON {
+        return None;
+    }
+    let adjugate = [
+        [
+            cofactor(1, 2, 1, 2),
+            -cofactor(0, 2, 1, 2),
+            cofactor(0, 1, 1, 2),
+        ],
+        [
+            -cofactor(1, 2, 0, 2),
+            cofactor(0, 2, 0, 2),
+            -cofactor(0, 1, 0, 2),
+        ],
+        [
+            cofactor(1, 2, 0, 1),
+            -cofactor(0, 2, 0, 1),
+            cofactor(0, 1, 0, 1),
+        ],
+    ];
+    Some(adjugate.map(|row| row.map(|v| v / det)))
+}
+
+/// Bradford chromatic adaptation between two XYZ white points
+fn bradford(from: [f32; 3], to: [f32; 3]) -> Mat3 {
+    const BRADFORD: Mat3 = [
+        [0.8951, 0.2664, -0.1614],
+        [-0.7502, 1.7135, 0.0367],
+        [0.0389, -0.0685, 1.0296],
+    ];
+    let src = apply(&BRADFORD, from);
+    let dst = apply(&BRADFORD, to);
+    let gain = [
+        [dst[0] / src[0], 0.0, 0.0],
+        [0.0, dst[1] / src[1], 0.0],
+        [0.0, 0.0, dst[2] / src[2]],
+    ];
+    let inverse = invert(&BRADFORD).expect("Bradford matrix is invertible");
+    mul(&inverse, &mul(&gain, &BRADFORD))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// sRGB colorants, adapted to D50
+    const SRGB_COLORANTS: [[f32; 3]; 3] = [
+        [0.4361, 0.2225, 0.0139],
+        [0.3851, 0.7169, 0.0971],
+        [0.1431, 0.0606, 0.7141],
+    ];
+
+    fn xyz_tag(xyz: [f32; 3]) -> Vec<u8> {
+        let mut tag = b"XYZ \0\0\0\0".to_vec();
+        for v in xyz {
+            tag.extend(((v * 65536.0).round() as i32).to_be_bytes());
+        }
+        tag
+    }
+
+    /// A curv tag holding a single gamma value
+    fn gamma_tag(gamma: f32) -> Vec<u8> {
+        let mut tag = b"curv\0\0\0\0".to_vec();
+        tag.extend(1u32.to_be_bytes());
+        tag.extend(((gamma * 256.0).round() as u16).to_be_bytes());
+        tag
+    }
+
+    /// Tags of a gamma 2.2 profile with the given colorants and a D50 white point
+    fn tags(colorants: [[f32; 3]; 3]) -> Vec<([u8; 4], Vec<u8>)> {
+        let [red, green, blue] = colorants;
+        vec![
+            (*b"rXYZ", xyz_tag(red)),
+            (*b"gXYZ", xyz_tag(green)),
+            (*b"bXYZ", xyz_tag(blue)),
+            (*b"wtpt", xyz_tag(D50_XYZ)),
+            (*b"rTRC", gamma_tag(2.2)),
+            (*b"gTRC", gamma_tag(2.2)),
+            (*b"bTRC", gamma_tag(2.2)),
+        ]
+    }
+
+    /// An RGB display profile made of a header, the tag table and the tag data
+    fn profile(tags: &[([u8; 4], Vec<u8>)]) -> Vec<u8> {
+        let mut profile = vec![0; HEADER_SIZE];
+        profile[16..20].copy_from_slice(b"RGB ");
+        profile[36..40].copy_from_slice(b"acsp");
+        profile.extend((tags.len() as u32).to_be_bytes());
+        let mut offset = HEADER_SIZE + 4 + tags.len() * 12;
+        let mut data = Vec::new();
+        for (signature, tag) in tags {
+            profile.extend(signature);
+            profile.extend((offset as u32).to_be_bytes());
+            profile.extend((tag.len() as u32).to_be_bytes());
+            offset += tag.len();
+            data.extend_from_slice(tag);
+        }
+        profile.extend(data);
+        profile
+    }
+
+    fn assert_close(actual: [f32; 2], expected: [f32; 2]) {
+        assert!(
+            (actual[0] - expected[0]).abs() < 1e-3 && (actual[1] - expected[1]).abs() < 1e-3,
+            "{actual:?} != {expected:?}"
+        );
+    }
+
+    #[test]
+    fn matrix_profiles_are_reduced_to_primaries_and_a_transfer_function() {
+        let (primaries, tf) = parse(&profile(&tags(SRGB_COLORANTS))).unwrap();
+        assert_eq!(tf, NamedTransferFunction::Gamma22);
+        assert_close(primaries.red, [0.6485, 0.3309]);
+        assert_close(primaries.green, [0.3213, 0.5982]);
+        assert_close(primaries.blue, [0.1559, 0.0660]);
+        assert_close(primaries.white, [0.3457, 0.3585]);
+    }
+
+    #[test]
+    fn truncated_tags_are_rejected() {
+        let mut truncated = profile(&tags(SRGB_COLORANTS));
+        truncated.truncate(truncated.len() - 1);
+        assert!(parse(&truncated).is_err());
+
+        // The tag fits the profile, but is too short for its values
+        let mut tags = tags(SRGB_COLORANTS);
+        tags[0].1.truncate(12);
+        assert!(parse(&profile(&tags)).is_err());
+
+        // The tag table claims more entries than the profile holds
+        let mut truncated = profile(&tags);
+        truncated.truncate(HEADER_SIZE + 4 + 12);
+        assert!(parse(&truncated).is_err());
+    }
+
+    #[test]
+    fn zero_colorants_are_rejected() {
+        let [red, green, _] = SRGB_COLORANTS;
+        assert!(parse(&profile(&tags([red, green, [0.0; 3]]))).is_err());
+        // A colorant with X and Z but no luminance has no valid chromaticity either
+        assert!(parse(&profile(&tags([red, green, [0.5, 0.0, 0.5]]))).is_err());
+    }
+
+    #[test]
+    fn colorants_that_dont_span_a_color_space_are_rejected() {
+        let [red, green, _] = SRGB_COLORANTS;
+        assert!(parse(&profile(&tags([red, green, red]))).is_err());
+    }
+
+    #[test]
+    fn srgb_curves_are_told_apart_from_gamma_22() {
+        // Parametric type 3, the piecewise sRGB curve
+        let mut srgb = b"para\0\0\0\0".to_vec();
+        srgb.extend(3u16.to_be_bytes());
+        srgb.extend([0, 0]);
+        for v in [2.4, 1.0 / 1.055, 0.055 / 1.055, 1.0 / 12.92, 0.04045] {
+            srgb.extend(((v * 65536.0_f32).round() as i32).to_be_bytes());
+        }
+        let mut tags = tags(SRGB_COLORANTS);
+        for (_, tag) in &mut tags[4..] {
+            *tag = srgb.clone();
+        }
+        let (_, tf) = parse(&profile(&tags)).unwrap();
+        assert_eq!(tf, NamedTransferFunction::Srgb);
+    }
+}
@@ -0,0 +1,410 @@
+//! Implementation of the wp_color_management_v1 protocol
+//!
+//! Clients describe the color space of their content with image descriptions, built either
+//! from parametric values or from an ICC file. A surface keeps the description of its latest
+//! commit in [`ColorManagementSurfaceCachedState`], and every output advertises a preferred
+//! description that clients can query.
+
+mod dispatch;
+mod icc;
+
+use smithay::{
+    output::Output,
+    reexports::{
+        wayland_protocols::wp::color_management::v1::server::{
+            wp_color_management_output_v1::WpColorManagementOutputV1,
+            wp_color_management_surface_feedback_v1::WpColorManagementSurfaceFeedbackV1,
+            wp_color_manager_v1::{self, WpColorManagerV1},
+        },
+        wayland_server::{DisplayHandle, Resource, backend::GlobalId},
+    },
+    wayland::compositor::Cacheable,
+};
+use std::sync::{Arc, Mutex};
+
+/// Version of the wp_color_manager_v1 global
+const VERSION: u32 = 1;
+
+/// Highest luminance accepted in a description, in cd/m²
+const MAX_LUMINANCE: f32 = 10_000.0;
+
+/// Transfer functions accepted in image descriptions
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum NamedTransferFunction {
+    /// IEC 61966-2-1 piecewise sRGB curve
+    Srgb,
+    /// Pure power curve with an exponent of 2.2
+    Gamma22,
+    /// Linear encoding, allowing values outside `[0, 1]`
+    ExtLinear,
+    /// SMPTE ST 2084 perceptual quantizer
+    St2084Pq,
+    /// ARIB STD-B67 hybrid log-gamma
+    Hlg,
+}
+
+impl NamedTransferFunction {
+    const SUPPORTED: [Self; 5] = [
+        Self::Srgb,
+        Self::Gamma22,
+        Self::ExtLinear,
+        Self::St2084Pq,
+        Self::Hlg,
+    ];
+
+    fn from_protocol(tf: wp_color_manager_v1::TransferFunction) -> Option<Self> {
+        use wp_color_manager_v1::TransferFunction as Tf;
+        Some(match tf {
+            Tf::Srgb => Self::Srgb,
+            Tf::Gamma22 => Self::Gamma22,
+            Tf::ExtLinear => Self::ExtLinear,
+            Tf::St2084Pq => Self::St2084Pq,
+            Tf::Hlg => Self::Hlg,
+            _ => return None,
+        })
+    }
+
+    fn to_protocol(self) -> wp_color_manager_v1::TransferFunction {
+        use wp_color_manager_v1::TransferFunction as Tf;
+        match self {
+            Self::Srgb => Tf::Srgb,
+            Self::Gamma22 => Tf::Gamma22,
+            Self::ExtLinear => Tf::ExtLinear,
+            Self::St2084Pq => Tf::St2084Pq,
+            Self::Hlg => Tf::Hlg,
+        }
+    }
+
+    /// Luminances the protocol defines for a description that doesn't set its own
+    pub fn default_luminances(self) -> Luminances {
+        match self {
+            Self::St2084Pq => Luminances {
+                min: 0.005,
+                max: 10_000.0,
+                reference: 203.0,
+            },
+            Self::Hlg => Luminances {
+                min: 0.005,
+                max: 1_000.0,
+                reference: 203.0,
+            },
+            _ => Luminances {
+                min: 0.2,
+                max: 80.0,
+                reference: 80.0,
+            },
+        }
+    }
+}
+
+/// CIE 1931 xy chromaticities of the red, green and blue primaries and the white point
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PrimaryChromaticities {
+    pub red: [f32; 2],
+    pub green: [f32; 2],
+    pub blue: [f32; 2],
+    pub white: [f32; 2],
+}
+
+impl PrimaryChromaticities {
+    /// Whether the primaries span a color space that contains the white point
+    ///
+    /// The renderer derives its conversion matrices from the primaries, which needs them
+    /// to be linearly independent and to reach white with positive amounts of each.
+    pub fn is_valid(&self) -> bool {
+        let points = [self.red, self.green, self.blue, self.white];
+        if points
+            .iter()
+            .any(|&[x, y]| !x.is_finite() || !y.is_finite() || y <= 0.0)
+        {
+            return false;
+        }
+        // CIE XYZ of each point at a luminance of 1
+        let [r, g, b, w] = points.map(|[x, y]| [x / y, 1.0, (1.0 - x - y) / y]);
+        let det = determinant(r, g, b);
+        if !det.is_finite() || det.abs() < f32::EPSILON {
+            return false;
+        }
+        // Amounts of each primary that add up to white, by Cramer's rule
+        let scales = [
+            determinant(w, g, b) / det,
+            determinant(r, w, b) / det,
+            determinant(r, g, w) / det,
+        ];
+        scales.iter().all(|&s| s.is_finite() && s > 0.0)
+            && (det * scales.iter().product::<f32>()).abs() >= f32::EPSILON
+    }
+}
+
+/// Determinant of the 3x3 matrix with columns `a`, `b` and `c`
+fn determinant(a: [f32; 3], b: [f32; 3], c: [f32; 3]) -> f32 {
+    a[0] * (b[1] * c[2] - b[2] * c[1]) - a[1] * (b[0] * c[2] - b[2] * c[0])
+        + a[2] * (b[0] * c[1] - b[1] * c[0])
+}
+
+/// CIE standard illuminant D65
+const D65: [f32; 2] = [0.3127, 0.3290];
+
+/// Named sets of primaries accepted in image descriptions
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum NamedPrimaries {
+    /// ITU-R BT.709, shared with sRGB
+    Srgb,
+    /// ITU-R BT.470 System M
+    PalM,
+    /// ITU-R BT.601 625-line
+    Pal,
+    /// ITU-R BT.601 525-line
+    Ntsc,
+    /// Generic film with illuminant C
+    GenericFilm,
+    /// ITU-R BT.2020, shared with BT.2100
+    Bt2020,
+    /// SMPTE RP 431-2 (DCI-P3)
+    DciP3,
+    /// Display P3 (DCI-P3 primaries with a D65 white point)
+    DisplayP3,
+    /// Adobe RGB (1998)
+    AdobeRgb,
+}
+
+impl NamedPrimaries {
+    const SUPPORTED: [Self; 9] = [
+        Self::Srgb,
+        Self::PalM,
+        Self::Pal,
+        Self::Ntsc,
+        Self::GenericFilm,
+        Self::Bt2020,
+        Self::DciP3,
+        Self::DisplayP3,
+        Self::AdobeRgb,
+    ];
+
+    fn from_protocol(primaries: wp_color_manager_v1::Primaries) -> Option<Self> {
+        use wp_color_manager_v1::Primaries as P;
+        Some(match primaries {
+            P::Srgb => Self::Srgb,
+            P::PalM => Self::PalM,
+            P::Pal => Self::Pal,
+            P::Ntsc => Self::Ntsc,
+            P::GenericFilm => Self::GenericFilm,
+            P::Bt2020 => Self::Bt2020,
+            P::DciP3 => Self::DciP3,
+            P::DisplayP3 => Self::DisplayP3,
+            P::AdobeRgb => Self::AdobeRgb,
+            _ => return None,
+        })
+    }
+
+    fn to_protocol(self) -> wp_color_manager_v1::Primaries {
+        use wp_color_manager_v1::Primaries as P;
+        match self {
+            Self::Srgb => P::Srgb,
+            Self::PalM => P::PalM,
+            Self::Pal => P::Pal,
+            Self::Ntsc => P::Ntsc,
+            Self::GenericFilm => P::GenericFilm,
+            Self::Bt2020 => P::Bt2020,
+            Self::DciP3 => P::DciP3,
+            Self::DisplayP3 => P::DisplayP3,
+            Self::AdobeRgb => P::AdobeRgb,
+        }
+    }
+
+    /// The chromaticity coordinates of the primaries
+    pub fn chromaticities(self) -> PrimaryChromaticities {
+        let (red, green, blue, white) = match self {
+            Self::Srgb => ([0.64, 0.33], [0.30, 0.60], [0.15, 0.06], D65),
+            Self::PalM => ([0.67, 0.33], [0.21, 0.71], [0.14, 0.08], [0.310, 0.316]),
+            Self::Pal => ([0.64, 0.33], [0.29, 0.60], [0.15, 0.06], D65),
+            Self::Ntsc => ([0.630, 0.340], [0.310, 0.595], [0.155, 0.070], D65),
+            Self::GenericFilm => (
+                [0.681, 0.319],
+                [0.243, 0.692],
+                [0.145, 0.049],
+                [0.310, 0.316],
+            ),
+            Self::Bt2020 => ([0.708, 0.292], [0.170, 0.797], [0.131, 0.046], D65),
+            Self::DciP3 => (
+                [0.680, 0.320],
+                [0.265, 0.690],
+                [0.150, 0.060],
+                [0.314, 0.351],
+            ),
+            Self::DisplayP3 => ([0.680, 0.320], [0.265, 0.690], [0.150, 0.060], D65),
+            Self::AdobeRgb => ([0.64, 0.33], [0.21, 0.71], [0.15, 0.06], D65),
+        };
+        PrimaryChromaticities {
+            red,
+            green,
+            blue,
+            white,
+        }
+    }
+}
+
+/// Luminance range of a description, in cd/m²
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Luminances {
+    /// Luminance of black
+    pub min: f32,
+    /// Luminance of the nominal peak
+    pub max: f32,
+    /// Luminance of reference white
+    pub reference: f32,
+}
+
+/// The color volume of the display content was mastered on
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct MasteringVolume {
+    pub primaries: Option<PrimaryChromaticities>,
+    /// Minimum and maximum luminance in cd/m²
+    pub luminance: Option<(f32, f32)>,
+    /// Maximum content light level in cd/m²
+    pub max_cll: Option<f32>,
+    /// Maximum frame-average light level in cd/m²
+    pub max_fall: Option<f32>,
+}
+
+/// A complete color description of surface content or an output.
+///
+/// ICC profiles are reduced to these parameters when they are parsed, so the renderer
+/// only ever deals with parametric descriptions.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ImageDescription {
+    /// Identity sent to clients with the `ready` event
+    pub identity: u32,
+    pub transfer_function: NamedTransferFunction,
+    /// Named primaries, if the description was built from them
+    pub named_primaries: Option<NamedPrimaries>,
+    pub primaries: PrimaryChromaticities,
+    pub luminances: Luminances,
+    pub mastering: MasteringVolume,
+}
+
+impl ImageDescription {
+    /// Build a description from named primaries and the default luminances of `tf`
+    pub fn named(identity: u32, primaries: NamedPrimaries, tf: NamedTransferFunction) -> Self {
+        Self {
+            identity,
+            transfer_function: tf,
+            named_primaries: Some(primaries),
+            primaries: primaries.chromaticities(),
+            luminances: tf.default_luminances(),
+            mastering: MasteringVolume::default(),
+        }
+    }
+
+    /// The sRGB description, used for surfaces and outputs that don't set one
+    pub fn srgb() -> Self {
+        Self::named(0, NamedPrimaries::Srgb, NamedTransferFunction::Srgb)
+    }
+}
+
+/// Double-buffered color management state of a surface
+#[derive(Clone, Debug, Default)]
+pub struct ColorManagementSurfaceCachedState {
+    description: Option<Arc<ImageDescription>>,
+}
+
+impl ColorManagementSurfaceCachedState {
+    /// The description set by the client, or `None` when content should be treated as sRGB
+    pub fn image_description(&self) -> Option<&Arc<ImageDescription>> {
+        self.description.as_ref()
+    }
+}
+
+impl Cacheable for ColorManagementSurfaceCachedState {
+    fn commit(&mut self, _dh: &DisplayHandle) -> Self {
+        self.clone()
+    }
+
+    fn merge_into(self, into: &mut Self, _dh: &DisplayHandle) {
+        *into = self;
+    }
+}
+
+/// Per-output color management data, stored in the output's user data
+#[derive(Debug, Default)]
+struct OutputColorData {
+    description: Option<Arc<ImageDescription>>,
+    instances: Vec<WpColorManagementOutputV1>,
+}
+
+/// State of the wp_color_manager_v1 global
+#[derive(Debug)]
+pub struct ColorManagementState {
+    global: GlobalId,
+    next_identity: u32,
+    preferred: Arc<ImageDescription>,
+    feedbacks: Vec<WpColorManagementSurfaceFeedbackV1>,
+}
+
+impl ColorManagementState {
+    /// Create the wp_color_manager_v1 global
+    pub fn new(dh: &DisplayHandle) -> Self {
+        let global = dh.create_global::<crate::StarforgeState, WpColorManagerV1, ()>(VERSION, ());
+        Self {
+            global,
+            // Identity 0 is reserved for the built-in sRGB description
+            next_identity: 1,
+            preferred: Arc::new(ImageDescription::srgb()),
+            feedbacks: Vec::new(),
+        }
+    }
+
+    /// The global of the protocol
+    pub fn global(&self) -> GlobalId {
+        self.global.clone()
+    }
+
+    /// Allocate an identity for a new image description
+    pub fn next_identity(&mut self) -> u32 {
+        let identity = self.next_identity;
+        self.next_identity = self.next_identity.wrapping_add(1).max(1);
+        identity
+    }
+
+    /// The image description an output advertises
+    pub fn output_image_description(output: &Output) -> Arc<ImageDescription> {
+        output_data(output)
+            .lock()
+            .unwrap()
+            .description
+            .clone()
+            .unwrap_or_else(|| Arc::new(ImageDescription::srgb()))
+    }
+
+    /// Change the image description an output advertises and notify its clients
+    pub fn set_output_image_description(&mut self, output: &Output, description: ImageDescription) {
+        let mut data = output_data(output).lock().unwrap();
+        data.description = Some(Arc::new(description));
+        data.instances.retain(|instance| instance.is_alive());
+        for instance in &data.instances {
+            instance.image_description_changed();
+        }
+    }
+
+    /// The description surfaces should prefer for their content
+    pub fn preferred_image_description(&self) -> Arc<ImageDescription> {
+        self.preferred.clone()
+    }
+
+    /// Change the description surfaces should prefer and notify the clients
+    pub fn set_preferred_image_description(&mut self, description: ImageDescription) {
+        let identity = description.identity;
+        self.preferred = Arc::new(description);
+        self.feedbacks.retain(|feedback| feedback.is_alive());
+        for feedback in &self.feedbacks {
+            feedback.preferred_changed(identity);
+        }
+    }
+}
+
+fn output_data(output: &Output) -> &Mutex<OutputColorData> {
+    output
+        .user_data()
+        .insert_if_missing_threadsafe(|| Mutex::new(OutputColorData::default()));
+    output.user_data().get::<Mutex<OutputColorData>>().unwrap()
+}
@@ -0,0 +1,368 @@
+use super::{
+    ChromaLocation, ColorRange, ColorRepresentationSurfaceCachedState, MatrixCoefficients,
+    SUPPORTED_COEFFICIENTS,
+};
+use crate::StarforgeState;
+use smithay::{
+    reexports::{
+        wayland_protocols::wp::color_representation::v1::server::{
+            wp_color_representation_manager_v1::{self, WpColorRepresentationManagerV1},
+            wp_color_representation_surface_v1::{self, WpColorRepresentationSurfaceV1},
+        },
+        wayland_server::{
+            Client, DataInit, Dispatch, DisplayHandle, GlobalDispatch, New, Resource, WEnum, Weak,
+            protocol::wl_surface::WlSurface,
+        },
+    },
+    wayland::compositor,
+};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Marks a surface that already has a wp_color_representation_surface_v1 object
+#[derive(Debug, Default)]
+struct ColorRepresentationSurfaceData {
+    attached: AtomicBool,
+}
+
+impl GlobalDispatch<WpColorRepresentationManagerV1, ()> for StarforgeState {
+    fn bind(
+        _state: &mut Self,
+        _dh: &DisplayHandle,
+        _client: &Client,
+        resource: New<WpColorRepresentationManagerV1>,
+        _global_data: &(),
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        let manager = data_init.init(resource, ());
+
+        manager.supported_alpha_mode(
+            wp_color_representation_surface_v1::AlphaMode::PremultipliedElectrical,
+        );
+        for (coefficients, range) in SUPPORTED_COEFFICIENTS {
+            manager
+                .supported_coefficients_and_ranges(coefficients.to_protocol(), range.to_protocol());
+        }
+        manager.done();
+    }
+}
+
+impl Dispatch<WpColorRepresentationManagerV1, ()> for StarforgeState {
+    fn request(
+        _state: &mut Self,
+        _client: &Client,
+        manager: &WpColorRepresentationManagerV1,
+        request: wp_color_representation_manager_v1::Request,
+        _data: &(),
+        _dh: &DisplayHandle,
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        match request {
+            wp_color_representation_manager_v1::Request::GetSurface { id, surface } => {
+                let already_attached = compositor::with_states(&surface, |states| {
+                    states
+                        .data_map
+                        .insert_if_missing_threadsafe(ColorRepresentationSurfaceData::default);
+                    states
+                        .data_map
+                        .get::<ColorRepresentationSurfaceData>()
+                        .unwrap()
+                        .attached
+                        .swap(true, Ordering::SeqCst)
+                });
+                if already_attached {
+                    manager.post_error(
+                        wp_color_representation_manager_v1::Error::SurfaceExists,
+                        "the surface already has a color representation surface object",
+                    );
+                    return;
+                }
+                data_init.init(id, surface.downgrade());
+            }
+            wp_color_representation_manager_v1::Request::Destroy => {}
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl Dispatch<WpColorRepresentationSurfaceV1, Weak<WlSurface>> for StarforgeState {
+    fn request(
+        _state: &mut Self,
+        _client: &Client,
+        resource: &WpColorRepresentationSurfaceV1,
+        request: wp_color_representation_surface_v1::Request,
+        surface: &Weak<WlSurface>,
+        _dh: &DisplayHandle,
+        _data_init: &mut DataInit<'_, Self>,
+    ) {
+        let Ok(surface) = surface.upgrade() else {
+            if !matches!(
+                request,
+                wp_color_representation_surface_v1::Request::Destroy
+            ) {
+                resource.post_error(
+                    wp_color_representation_surface_v1::Error::Inert,
+                    "the surface has been destroyed",
+                );
+            }
+            return;
+        };
+
+        match request {
+            wp_color_representation_surface_v1::Request::SetAlphaMode { alpha_mode } => {
+                if alpha_mode
+                    != WEnum::Value(
+                        wp_color_representation_surface_v1::AlphaMode::PremultipliedElectrical,
+                    )
+                {
+                    resource.post_error(
+                        wp_color_representation_surface_v1::Error::AlphaMode,
+                        "unsupported alpha mode",
+                    );
+                }
+            }
+            wp_color_representation_surface_v1::Request::SetCoefficientsAndRange {
+                coefficients,
+                range,
+            } => {
+                let coefficients = coefficients
+                    .into_result()
+                    .ok()
+                    .and_then(MatrixCoefficients::from_protocol);
+                let range = range.into_result().ok().and_then(ColorRange::from_protocol);
+                let Some(pair) = coefficients
+                    .zip(range)
+                    .filter(|pair| SUPPORTED_COEFFICIENTS.contains(pair))
+                else {
+                    resource.post_error(
+                        wp_color_representation_surface_v1::Error::Coefficients,
+                        "unsupported coefficients and range",
+                    );
+                    return;
+                };
+                with_pending(&surface, |pending| pending.coefficients = Some(pair));
+            }
+            wp_color_representation_surface_v1::Request::SetChromaLocation { chroma_location } => {
+                let location = chroma_location
+                    .into_result()
+                    .ok()
+                    .and_then(ChromaLocation::from_protocol);
+                with_pending(&surface, |pending| pending.chroma_location = location);
+            }
+            wp_color_representation_surface_v1::Request::Destroy => {
+                // Destroying the object unsets every value on the next commit
+                with_pending(&surface, |pending| {
+                    *pending = ColorRepresentationSurfaceCachedState::default()
+                });
+                compositor::with_states(&surface, |states| {
+                    if let Some(data) = states.data_map.get::<ColorRepresentationSurfaceData>() {
+                        data.attached.store(false, Ordering::SeqCst);
+                    }
+                });
+            }
+            _ => unreachable!(),
+        }
+    }
+}
+
+fn with_pending(surface: &WlSurface, f: impl FnOnce(&mut ColorRepresentationSurfaceCachedState)) {
+    compositor::with_states(surface, |states| {
+        f(states
+            .cached_state
+            .get::<ColorRepresentationSurfaceCachedState>()
+            .pending())
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocols::{
+        color_representation::surface_color_representation, test_client::TestClient,
+    };
+    use smithay::reexports::wayland_server::protocol::wl_surface;
+    use wp_color_representation_surface_v1::{AlphaMode, Coefficients, Range};
+
+    /// Give the surface of id `surface` a color representation object, returning the ids
+    /// of the manager and the new object
+    fn color_representation(client: &mut TestClient, surface: u32) -> (u32, u32) {
+        let manager = client.bind("wp_color_representation_manager_v1", 1);
+        let id = client.new_id();
+        client.request(
+            manager,
+            wp_color_representation_manager_v1::REQ_GET_SURFACE_OPCODE,
+            &[id, surface],
+        );
+        (manager, id)
+    }
+
+    fn set_coefficients(
+        client: &mut TestClient,
+        id: u32,
+        coefficients: Coefficients,
+        range: Range,
+    ) {
+        client.request(
+            id,
+            wp_color_representation_surface_v1::REQ_SET_COEFFICIENTS_AND_RANGE_OPCODE,
+            &[coefficients as u32, range as u32],
+        );
+    }
+
+    fn applied(client: &TestClient, surface: u32) -> ColorRepresentationSurfaceCachedState {
+        surface_color_representation(&client.object::<WlSurface>(surface))
+    }
+
+    #[test]
+    fn values_apply_on_commit() {
+        let mut client = TestClient::connect();
+        let surface = client.create_surface();
+        let (_, id) = color_representation(&mut client, surface);
+        set_coefficients(&mut client, id, Coefficients::Bt709, Range::Limited);
+        client.request(
+            id,
+            wp_color_representation_surface_v1::REQ_SET_CHROMA_LOCATION_OPCODE,
+            &[wp_color_representation_surface_v1::ChromaLocation::Type2 as u32],
+        );
+        client.request(
+            id,
+            wp_color_representation_surface_v1::REQ_SET_ALPHA_MODE_OPCODE,
+            &[AlphaMode::PremultipliedElectrical as u32],
+        );
+        client.dispatch();
+        assert_eq!(
+            applied(&client, surface),
+            ColorRepresentationSurfaceCachedState::default()
+        );
+
+        client.commit(surface);
+        let state = applied(&client, surface);
+        assert_eq!(
+            state.coefficients(),
+            Some((MatrixCoefficients::Bt709, ColorRange::Limited))
+        );
+        assert_eq!(state.chroma_location(), Some(ChromaLocation::Type2));
+        assert_eq!(client.protocol_error(), None);
+    }
+
+    #[test]
+    fn destroying_the_object_unsets_the_values() {
+        let mut client = TestClient::connect();
+        let surface = client.create_surface();
+        let (manager, id) = color_representation(&mut client, surface);
+        set_coefficients(&mut client, id, Coefficients::Bt601, Range::Full);
+        client.commit(surface);
+
+        client.request(
+            id,
+            wp_color_representation_surface_v1::REQ_DESTROY_OPCODE,
+            &[],
+        );
+        client.commit(surface);
+        assert_eq!(
+            applied(&client, surface),
+            ColorRepresentationSurfaceCachedState::default()
+        );
+
+        // The surface can get a new object once the old one is gone
+        let second = client.new_id();
+        client.request(
+            manager,
+            wp_color_representation_manager_v1::REQ_GET_SURFACE_OPCODE,
+            &[second, surface],
+        );
+        set_coefficients(&mut client, second, Coefficients::Bt2020, Range::Limited);
+        client.commit(surface);
+        assert_eq!(
+            applied(&client, surface).coefficients(),
+            Some((MatrixCoefficients::Bt2020, ColorRange::Limited))
+        );
+        assert_eq!(client.protocol_error(), None);
+    }
+
+    #[test]
+    fn second_object_is_a_protocol_error() {
+        let mut client = TestClient::connect();
+        let surface = client.create_surface();
+        let (manager, _) = color_representation(&mut client, surface);
+        let second = client.new_id();
+        client.request(
+            manager,
+            wp_color_representation_manager_v1::REQ_GET_SURFACE_OPCODE,
+            &[second, surface],
+        );
+        client.dispatch();
+        assert_eq!(
+            client.protocol_error(),
+            Some((
+                manager,
+                wp_color_representation_manager_v1::Error::SurfaceExists as u32
+            ))
+        );
+    }
+
+    #[test]
+    fn identity_with_the_limited_range_is_rejected() {
+        let mut client = TestClient::connect();
+        let surface = client.create_surface();
+        let (_, id) = color_representation(&mut client, surface);
+        set_coefficients(&mut client, id, Coefficients::Identity, Range::Limited);
+        client.dispatch();
+        assert_eq!(
+            client.protocol_error(),
+            Some((
+                id,
+                wp_color_representation_surface_v1::Error::Coefficients as u32
+            ))
+        );
+    }
+
+    #[test]
+    fn unadvertised_coefficients_are_rejected() {
+        let mut client = TestClient::connect();
+        let surface = client.create_surface();
+        let (_, id) = color_representation(&mut client, surface);
+        set_coefficients(&mut client, id, Coefficients::Ictcp, Range::Full);
+        client.dispatch();
+        assert_eq!(
+            client.protocol_error(),
+            Some((
+                id,
+                wp_color_representation_surface_v1::Error::Coefficients as u32
+            ))
+        );
+    }
+
+    #[test]
+    fn straight_alpha_is_rejected() {
+        let mut client = TestClient::connect();
+        let surface = client.create_surface();
+        let (_, id) = color_representation(&mut client, surface);
+        client.request(
+            id,
+            wp_color_representation_surface_v1::REQ_SET_ALPHA_MODE_OPCODE,
+            &[AlphaMode::Straight as u32],
+        );
+        client.dispatch();
+        assert_eq!(
+            client.protocol_error(),
+            Some((
+                id,
+                wp_color_representation_surface_v1::Error::AlphaMode as u32
+            ))
+        );
+    }
+
+    #[test]
+    fn objects_of_destroyed_surfaces_are_inert() {
+        let mut client = TestClient::connect();
+        let surface = client.create_surface();
+        let (_, id) = color_representation(&mut client, surface);
+        client.request(surface, wl_surface::REQ_DESTROY_OPCODE, &[]);
+        set_coefficients(&mut client, id, Coefficients::Bt709, Range::Full);
+        client.dispatch();
+        assert_eq!(
+            client.protocol_error(),
+            Some((id, wp_color_representation_surface_v1::Error::Inert as u32))
+        );
+    }
+}
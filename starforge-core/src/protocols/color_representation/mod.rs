@@ -0,0 +1,198 @@
+//! Implementation of the wp_color_representation_v1 protocol
+//!
+//! Clients tell the compositor how the values in their buffers encode colour: which
+//! matrix turns YCbCr into RGB, whether the values use the full or the limited range,
+//! where chroma samples sit relative to luma samples, and how alpha is applied. A surface
+//! keeps the values of its latest commit in [`ColorRepresentationSurfaceCachedState`];
+//! unset values leave the choice to the renderer.
+//!
+//! Only premultiplied alpha is supported, which is what surfaces without the protocol
+//! use as well. Coefficients set on RGB buffers are ignored rather than rejected.
+
+mod dispatch;
+
+use smithay::{
+    reexports::{
+        wayland_protocols::wp::color_representation::v1::server::{
+            wp_color_representation_manager_v1::WpColorRepresentationManagerV1,
+            wp_color_representation_surface_v1,
+        },
+        wayland_server::{DisplayHandle, backend::GlobalId, protocol::wl_surface::WlSurface},
+    },
+    wayland::compositor::{Cacheable, with_states},
+};
+
+/// Version of the wp_color_representation_manager_v1 global
+const VERSION: u32 = 1;
+
+/// Matrices from YCbCr to RGB accepted from clients
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum MatrixCoefficients {
+    /// The planes hold G, B and R unchanged
+    Identity,
+    /// ITU-R BT.601, used by SD video
+    Bt601,
+    /// ITU-R BT.709, used by HD video
+    Bt709,
+    /// ITU-R BT.2020 non-constant luminance, used by UHD and HDR video
+    Bt2020,
+}
+
+impl MatrixCoefficients {
+    fn from_protocol(
+        coefficients: wp_color_representation_surface_v1::Coefficients,
+    ) -> Option<Self> {
+        use wp_color_representation_surface_v1::Coefficients as C;
+        Some(match coefficients {
+            C::Identity => Self::Identity,
+            C::Bt601 => Self::Bt601,
+            C::Bt709 => Self::Bt709,
+            C::Bt2020 => Self::Bt2020,
+            _ => return None,
+        })
+    }
+
+    fn to_protocol(self) -> wp_color_representation_surface_v1::Coefficients {
+        use wp_color_representation_surface_v1::Coefficients as C;
+        match self {
+            Self::Identity => C::Identity,
+            Self::Bt601 => C::Bt601,
+            Self::Bt709 => C::Bt709,
+            Self::Bt2020 => C::Bt2020,
+        }
+    }
+}
+
+/// Range of the encoded values
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ColorRange {
+    /// Values use every code
+    Full,
+    /// Values leave head- and footroom, e.g. luma from 16 to 235 at 8 bits
+    Limited,
+}
+
+impl ColorRange {
+    fn from_protocol(range: wp_color_representation_surface_v1::Range) -> Option<Self> {
+        use wp_color_representation_surface_v1::Range as R;
+        Some(match range {
+            R::Full => Self::Full,
+            R::Limited => Self::Limited,
+            _ => return None,
+        })
+    }
+
+    fn to_protocol(self) -> wp_color_representation_surface_v1::Range {
+        use wp_color_representation_surface_v1::Range as R;
+        match self {
+            Self::Full => R::Full,
+            Self::Limited => R::Limited,
+        }
+    }
+}
+
+/// Coefficient and range pairs advertised to clients
+///
+/// Identity coefficients only make sense with the full range.
+const SUPPORTED_COEFFICIENTS: [(MatrixCoefficients, ColorRange); 7] = [
+    (MatrixCoefficients::Identity, ColorRange::Full),
+    (MatrixCoefficients::Bt601, ColorRange::Full),
+    (MatrixCoefficients::Bt601, ColorRange::Limited),
+    (MatrixCoefficients::Bt709, ColorRange::Full),
+    (MatrixCoefficients::Bt709, ColorRange::Limited),
+    (MatrixCoefficients::Bt2020, ColorRange::Full),
+    (MatrixCoefficients::Bt2020, ColorRange::Limited),
+];
+
+/// Position of subsampled chroma samples, as in ITU-T H.273 chroma sample location types
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ChromaLocation {
+    /// Horizontally co-sited with the left luma sample, vertically centred (MPEG-2)
+    Type0,
+    /// Centred between the luma samples (MPEG-1, JPEG)
+    Type1,
+    /// Co-sited with the top left luma sample (BT.2020 UHD)
+    Type2,
+    /// Horizontally centred, vertically co-sited with the top luma samples
+    Type3,
+    /// Co-sited with the bottom left luma sample
+    Type4,
+    /// Horizontally centred, vertically co-sited with the bottom luma samples
+    Type5,
+}
+
+impl ChromaLocation {
+    fn from_protocol(location: wp_color_representation_surface_v1::ChromaLocation) -> Option<Self> {
+        use wp_color_representation_surface_v1::ChromaLocation as L;
+        Some(match location {
+            L::Type0 => Self::Type0,
+            L::Type1 => Self::Type1,
+            L::Type2 => Self::Type2,
+            L::Type3 => Self::Type3,
+            L::Type4 => Self::Type4,
+            L::Type5 => Self::Type5,
+            _ => return None,
+        })
+    }
+}
+
+/// Double-buffered color representation state of a surface
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ColorRepresentationSurfaceCachedState {
+    coefficients: Option<(MatrixCoefficients, ColorRange)>,
+    chroma_location: Option<ChromaLocation>,
+}
+
+impl ColorRepresentationSurfaceCachedState {
+    /// The matrix and range set by the client, or `None` when the renderer picks them
+    pub fn coefficients(&self) -> Option<(MatrixCoefficients, ColorRange)> {
+        self.coefficients
+    }
+
+    /// The chroma sample location set by the client, or `None` when the renderer picks it
+    pub fn chroma_location(&self) -> Option<ChromaLocation> {
+        self.chroma_location
+    }
+}
+
+/// The color representation of the content `surface` committed last
+pub fn surface_color_representation(surface: &WlSurface) -> ColorRepresentationSurfaceCachedState {
+    with_states(surface, |states| {
+        *states
+            .cached_state
+            .get::<ColorRepresentationSurfaceCachedState>()
+            .current()
+    })
+}
+
+impl Cacheable for ColorRepresentationSurfaceCachedState {
+    fn commit(&mut self, _dh: &DisplayHandle) -> Self {
+        *self
+    }
+
+    fn merge_into(self, into: &mut Self, _dh: &DisplayHandle) {
+        *into = self;
+    }
+}
+
+/// State of the wp_color_representation_manager_v1 global
+#[derive(Debug)]
+pub struct ColorRepresentationState {
+    global: GlobalId,
+}
+
+impl ColorRepresentationState {
+    /// Create the wp_color_representation_manager_v1 global
+    pub fn new(dh: &DisplayHandle) -> Self {
+        let global = dh.create_global::<crate::StarforgeState, WpColorRepresentationManagerV1, ()>(
+            VERSION,
+            (),
+        );
+        Self { global }
+    }
+
+    /// The global of the protocol
+    pub fn global(&self) -> GlobalId {
+        self.global.clone()
+    }
+}
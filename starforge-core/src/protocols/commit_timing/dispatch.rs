@@ -0,0 +1,269 @@
+use super::CommitTimingSurfaceCachedState;
+use crate::StarforgeState;
+use smithay::{
+    reexports::{
+        wayland_protocols::wp::commit_timing::v1::server::{
+            wp_commit_timer_v1::{self, WpCommitTimerV1},
+            wp_commit_timing_manager_v1::{self, WpCommitTimingManagerV1},
+        },
+        wayland_server::{
+            Client, DataInit, Dispatch, DisplayHandle, GlobalDispatch, New, Resource, Weak,
+            protocol::wl_surface::WlSurface,
+        },
+    },
+    wayland::compositor::{self, add_blocker, add_pre_commit_hook},
+};
+use std::{
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+
+/// Commit timing bookkeeping of a surface that had a wp_commit_timer_v1 object
+#[derive(Debug, Default)]
+struct CommitTimerSurfaceData {
+    /// The surface currently has a wp_commit_timer_v1 object
+    attached: AtomicBool,
+    /// The pre-commit hook was added
+    hooked: AtomicBool,
+}
+
+impl GlobalDispatch<WpCommitTimingManagerV1, ()> for StarforgeState {
+    fn bind(
+        _state: &mut Self,
+        _dh: &DisplayHandle,
+        _client: &Client,
+        resource: New<WpCommitTimingManagerV1>,
+        _global_data: &(),
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        data_init.init(resource, ());
+    }
+}
+
+impl Dispatch<WpCommitTimingManagerV1, ()> for StarforgeState {
+    fn request(
+        _state: &mut Self,
+        _client: &Client,
+        manager: &WpCommitTimingManagerV1,
+        request: wp_commit_timing_manager_v1::Request,
+        _data: &(),
+        _dh: &DisplayHandle,
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        match request {
+            wp_commit_timing_manager_v1::Request::GetTimer { id, surface } => {
+                let (already_attached, hooked) = compositor::with_states(&surface, |states| {
+                    states
+                        .data_map
+                        .insert_if_missing_threadsafe(CommitTimerSurfaceData::default);
+                    let data = states.data_map.get::<CommitTimerSurfaceData>().unwrap();
+                    (
+                        data.attached.swap(true, Ordering::SeqCst),
+                        data.hooked.swap(true, Ordering::SeqCst),
+                    )
+                });
+                if already_attached {
+                    manager.post_error(
+                        wp_commit_timing_manager_v1::Error::CommitTimerExists,
+                        "the surface already has a commit timer",
+                    );
+                    return;
+                }
+                if !hooked {
+                    add_pre_commit_hook::<Self, _>(&surface, hold_commit);
+                }
+                data_init.init(id, surface.downgrade());
+            }
+            wp_commit_timing_manager_v1::Request::Destroy => {}
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl Dispatch<WpCommitTimerV1, Weak<WlSurface>> for StarforgeState {
+    fn request(
+        _state: &mut Self,
+        _client: &Client,
+        resource: &WpCommitTimerV1,
+        request: wp_commit_timer_v1::Request,
+        surface: &Weak<WlSurface>,
+        _dh: &DisplayHandle,
+        _data_init: &mut DataInit<'_, Self>,
+    ) {
+        let Ok(surface) = surface.upgrade() else {
+            if !matches!(request, wp_commit_timer_v1::Request::Destroy) {
+                resource.post_error(
+                    wp_commit_timer_v1::Error::SurfaceDestroyed,
+                    "the surface has been destroyed",
+                );
+            }
+            return;
+        };
+
+        match request {
+            wp_commit_timer_v1::Request::SetTimestamp {
+                tv_sec_hi,
+                tv_sec_lo,
+                tv_nsec,
+            } => {
+                if tv_nsec >= 1_000_000_000 {
+                    resource.post_error(
+                        wp_commit_timer_v1::Error::InvalidTimestamp,
+                        "tv_nsec is a second or more",
+                    );
+                    return;
+                }
+                let target = Duration::new(((tv_sec_hi as u64) << 32) | tv_sec_lo as u64, tv_nsec);
+                let exists = compositor::with_states(&surface, |states| {
+                    let mut cached = states.cached_state.get::<CommitTimingSurfaceCachedState>();
+                    let pending = cached.pending();
+                    pending.target.replace(target).is_some()
+                });
+                if exists {
+                    resource.post_error(
+                        wp_commit_timer_v1::Error::TimestampExists,
+                        "the commit already has a timestamp",
+                    );
+                }
+            }
+            wp_commit_timer_v1::Request::Destroy => {
+                compositor::with_states(&surface, |states| {
+                    if let Some(data) = states.data_map.get::<CommitTimerSurfaceData>() {
+                        data.attached.store(false, Ordering::SeqCst);
+                    }
+                });
+            }
+            _ => unreachable!(),
+        }
+    }
+}
+
+/// Hold back a commit with a target time, and schedule a frame for it
+fn hold_commit(state: &mut StarforgeState, _dh: &DisplayHandle, surface: &WlSurface) {
+    let target = compositor::with_states(surface, |states| {
+        states
+            .cached_state
+            .get::<CommitTimingSurfaceCachedState>()
+            .pending()
+            .target
+    });
+    let Some(target) = target else {
+        return;
+    };
+    // Targets that are due already don't hold anything back
+    if target <= state.frame_scheduling_state.now() {
+        return;
+    }
+    let blocker = state.commit_timing_state.hold(surface, target);
+    add_blocker(surface, blocker);
+
+    // Content that isn't shown yet still needs a frame to be released
+    let mut outputs = state.outputs_showing(surface);
+    if outputs.is_empty() {
+        outputs = state.outputs.clone();
+    }
+    for output in outputs {
+        state.frame_scheduling_state.schedule_for(&output, target);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocols::test_client::TestClient;
+    use smithay::{
+        reexports::wayland_server::protocol::wl_surface, wayland::compositor::SurfaceAttributes,
+    };
+
+    /// Give the surface of id `surface` a commit timer, returning its id
+    fn timer(client: &mut TestClient, surface: u32) -> u32 {
+        let manager = client.bind("wp_commit_timing_manager_v1", 1);
+        let timer = client.new_id();
+        client.request(
+            manager,
+            wp_commit_timing_manager_v1::REQ_GET_TIMER_OPCODE,
+            &[timer, surface],
+        );
+        timer
+    }
+
+    /// Commit a buffer scale of 2 to the surface of id `surface` for `target`
+    fn commit_for(client: &mut TestClient, surface: u32, timer: u32, target: Duration) {
+        let secs = target.as_secs();
+        client.request(
+            timer,
+            wp_commit_timer_v1::REQ_SET_TIMESTAMP_OPCODE,
+            &[(secs >> 32) as u32, secs as u32, target.subsec_nanos()],
+        );
+        client.request(surface, wl_surface::REQ_SET_BUFFER_SCALE_OPCODE, &[2]);
+        client.commit(surface);
+    }
+
+    fn applied_scale(client: &TestClient, surface: u32) -> i32 {
+        let surface = client.object::<WlSurface>(surface);
+        compositor::with_states(&surface, |states| {
+            states
+                .cached_state
+                .get::<SurfaceAttributes>()
+                .current()
+                .buffer_scale
+        })
+    }
+
+    #[test]
+    fn commits_are_held_until_the_frame_for_their_target() {
+        let mut client = TestClient::connect();
+        let output = client.add_output(640, 480);
+        let surface = client.map_fullscreen_toplevel(640, 480);
+        // Let the frame for the mapped window go by, so the scheduler is idle
+        client.state.frame_submitted(&output, Duration::ZERO);
+        let now = client.state.frame_scheduling_state.now();
+        client.state.frame_discarded(&output);
+
+        let target = now + Duration::from_secs(1);
+        let timer = timer(&mut client, surface);
+        commit_for(&mut client, surface, timer, target);
+        assert_eq!(applied_scale(&client, surface), 1);
+        assert_eq!(client.state.commit_timing_state.next_target(), Some(target));
+
+        // The scheduler has a frame lined up for the target, which applies the commit
+        let scheduler = client.state.frame_scheduling_state.scheduler(&output);
+        let presentation = scheduler.unwrap().frame_due(target).unwrap();
+        assert!(presentation >= target);
+        client
+            .state
+            .frame_started(target - Duration::from_millis(1));
+        assert_eq!(applied_scale(&client, surface), 1);
+        client.state.frame_started(presentation);
+        assert_eq!(applied_scale(&client, surface), 2);
+        assert_eq!(client.state.commit_timing_state.next_target(), None);
+    }
+
+    #[test]
+    fn past_targets_apply_right_away() {
+        let mut client = TestClient::connect();
+        client.add_output(640, 480);
+        let surface = client.create_surface();
+        let timer = timer(&mut client, surface);
+        let now = client.state.frame_scheduling_state.now();
+        commit_for(&mut client, surface, timer, now);
+        assert_eq!(applied_scale(&client, surface), 2);
+    }
+
+    #[test]
+    fn timestamps_with_a_second_of_nanoseconds_are_rejected() {
+        let mut client = TestClient::connect();
+        let surface = client.create_surface();
+        let timer = timer(&mut client, surface);
+        client.request(
+            timer,
+            wp_commit_timer_v1::REQ_SET_TIMESTAMP_OPCODE,
+            &[0, 1, 1_000_000_000],
+        );
+        client.dispatch();
+        assert_eq!(
+            client.protocol_error(),
+            Some((timer, wp_commit_timer_v1::Error::InvalidTimestamp as u32))
+        );
+    }
+}
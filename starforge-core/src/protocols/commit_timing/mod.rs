@@ -0,0 +1,131 @@
+//! Implementation of the wp_commit_timing_v1 protocol
+//!
+//! Clients attach a target presentation time to a commit, on the clock of wp_presentation.
+//! The commit is held back by a blocker until the compositor starts the frame predicted to
+//! be presented at or after that time, so its content is never shown early. A frame is
+//! scheduled for the target, so the commit is applied even if nothing else changes.
+
+mod dispatch;
+
+use smithay::{
+    reexports::{
+        wayland_protocols::wp::commit_timing::v1::server::wp_commit_timing_manager_v1::WpCommitTimingManagerV1,
+        wayland_server::{
+            Client, DisplayHandle, Resource, Weak, backend::GlobalId,
+            protocol::wl_surface::WlSurface,
+        },
+    },
+    wayland::compositor::{Blocker, BlockerState, Cacheable},
+};
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::Duration,
+};
+
+/// Version of the wp_commit_timing_manager_v1 global
+const VERSION: u32 = 1;
+
+/// Double-buffered commit timing state of a surface
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CommitTimingSurfaceCachedState {
+    /// Earliest presentation time of the commit's content
+    target: Option<Duration>,
+}
+
+impl Cacheable for CommitTimingSurfaceCachedState {
+    fn commit(&mut self, _dh: &DisplayHandle) -> Self {
+        // A timestamp only applies to the commit it was set for
+        std::mem::take(self)
+    }
+
+    fn merge_into(self, into: &mut Self, _dh: &DisplayHandle) {
+        *into = self;
+    }
+}
+
+/// Holds back a commit until the frame for its target starts
+struct TimingBlocker(Arc<AtomicBool>);
+
+impl Blocker for TimingBlocker {
+    fn state(&self) -> BlockerState {
+        if self.0.load(Ordering::SeqCst) {
+            BlockerState::Released
+        } else {
+            BlockerState::Pending
+        }
+    }
+}
+
+/// A commit waiting for its target presentation time
+#[derive(Debug)]
+struct TimedCommit {
+    target: Duration,
+    surface: Weak<WlSurface>,
+    released: Arc<AtomicBool>,
+}
+
+/// State of the wp_commit_timing_manager_v1 global
+#[derive(Debug)]
+pub struct CommitTimingState {
+    global: GlobalId,
+    pending: Vec<TimedCommit>,
+}
+
+impl CommitTimingState {
+    /// Create the wp_commit_timing_manager_v1 global
+    pub fn new(dh: &DisplayHandle) -> Self {
+        let global =
+            dh.create_global::<crate::StarforgeState, WpCommitTimingManagerV1, ()>(VERSION, ());
+        Self {
+            global,
+            pending: Vec::new(),
+        }
+    }
+
+    /// The global of the protocol
+    pub fn global(&self) -> GlobalId {
+        self.global.clone()
+    }
+
+    /// The earliest target of the commits still held back
+    pub fn next_target(&self) -> Option<Duration> {
+        self.pending.iter().map(|commit| commit.target).min()
+    }
+
+    /// Release the commits whose target the frame presented at `presentation` reaches
+    ///
+    /// Returns the clients whose queued commits have to be applied now.
+    pub fn release_due(&mut self, presentation: Duration) -> Vec<Client> {
+        let mut clients: Vec<Client> = Vec::new();
+        self.pending.retain(|commit| {
+            let Ok(surface) = commit.surface.upgrade() else {
+                return false;
+            };
+            if commit.target > presentation {
+                return true;
+            }
+            commit.released.store(true, Ordering::SeqCst);
+            if let Some(client) = surface.client()
+                && !clients.iter().any(|known| known.id() == client.id())
+            {
+                clients.push(client);
+            }
+            false
+        });
+        clients
+    }
+
+    /// Hold back the commit of `surface` being made until the frame for `target`
+    fn hold(&mut self, surface: &WlSurface, target: Duration) -> TimingBlocker {
+        let released = Arc::new(AtomicBool::new(false));
+        self.pending.push(TimedCommit {
+            target,
+            surface: surface.downgrade(),
+            released: released.clone(),
+        });
+        TimingBlocker(released)
+    }
+}
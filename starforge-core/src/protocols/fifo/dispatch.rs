@@ -0,0 +1,254 @@
+use super::{FifoBarrier, FifoBlocker, FifoSurfaceCachedState};
+use crate::StarforgeState;
+use smithay::{
+    reexports::{
+        wayland_protocols::wp::fifo::v1::server::{
+            wp_fifo_manager_v1::{self, WpFifoManagerV1},
+            wp_fifo_v1::{self, WpFifoV1},
+        },
+        wayland_server::{
+            Client, DataInit, Dispatch, DisplayHandle, GlobalDispatch, New, Resource, Weak,
+            protocol::wl_surface::WlSurface,
+        },
+    },
+    wayland::compositor::{self, add_blocker, add_pre_commit_hook},
+};
+use std::sync::{
+    Arc, Mutex,
+    atomic::{AtomicBool, Ordering},
+};
+
+/// Fifo bookkeeping of a surface that had a wp_fifo_v1 object
+#[derive(Debug, Default)]
+struct FifoSurfaceData {
+    /// The surface currently has a wp_fifo_v1 object
+    attached: AtomicBool,
+    /// The pre-commit hook was added
+    hooked: AtomicBool,
+    /// Barrier of the latest commit that set one
+    latest_barrier: Mutex<Option<Arc<FifoBarrier>>>,
+}
+
+impl GlobalDispatch<WpFifoManagerV1, ()> for StarforgeState {
+    fn bind(
+        _state: &mut Self,
+        _dh: &DisplayHandle,
+        _client: &Client,
+        resource: New<WpFifoManagerV1>,
+        _global_data: &(),
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        data_init.init(resource, ());
+    }
+}
+
+impl Dispatch<WpFifoManagerV1, ()> for StarforgeState {
+    fn request(
+        _state: &mut Self,
+        _client: &Client,
+        manager: &WpFifoManagerV1,
+        request: wp_fifo_manager_v1::Request,
+        _data: &(),
+        _dh: &DisplayHandle,
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        match request {
+            wp_fifo_manager_v1::Request::GetFifo { id, surface } => {
+                let (already_attached, hooked) = compositor::with_states(&surface, |states| {
+                    states
+                        .data_map
+                        .insert_if_missing_threadsafe(FifoSurfaceData::default);
+                    let data = states.data_map.get::<FifoSurfaceData>().unwrap();
+                    (
+                        data.attached.swap(true, Ordering::SeqCst),
+                        data.hooked.swap(true, Ordering::SeqCst),
+                    )
+                });
+                if already_attached {
+                    manager.post_error(
+                        wp_fifo_manager_v1::Error::AlreadyExists,
+                        "the surface already has a fifo object",
+                    );
+                    return;
+                }
+                if !hooked {
+                    add_pre_commit_hook::<Self, _>(&surface, queue_commit);
+                }
+                data_init.init(id, surface.downgrade());
+            }
+            wp_fifo_manager_v1::Request::Destroy => {}
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl Dispatch<WpFifoV1, Weak<WlSurface>> for StarforgeState {
+    fn request(
+        _state: &mut Self,
+        _client: &Client,
+        resource: &WpFifoV1,
+        request: wp_fifo_v1::Request,
+        surface: &Weak<WlSurface>,
+        _dh: &DisplayHandle,
+        _data_init: &mut DataInit<'_, Self>,
+    ) {
+        let Ok(surface) = surface.upgrade() else {
+            if !matches!(request, wp_fifo_v1::Request::Destroy) {
+                resource.post_error(
+                    wp_fifo_v1::Error::SurfaceDestroyed,
+                    "the surface has been destroyed",
+                );
+            }
+            return;
+        };
+
+        match request {
+            wp_fifo_v1::Request::SetBarrier => {
+                with_pending(&surface, |pending| pending.set_barrier = true);
+            }
+            wp_fifo_v1::Request::WaitBarrier => {
+                with_pending(&surface, |pending| pending.wait_barrier = true);
+            }
+            wp_fifo_v1::Request::Destroy => {
+                compositor::with_states(&surface, |states| {
+                    if let Some(data) = states.data_map.get::<FifoSurfaceData>() {
+                        data.attached.store(false, Ordering::SeqCst);
+                    }
+                });
+            }
+            _ => unreachable!(),
+        }
+    }
+}
+
+/// Hold back a commit that waits for a barrier, and set the barrier it asks for
+fn queue_commit(state: &mut StarforgeState, _dh: &DisplayHandle, surface: &WlSurface) {
+    let shown = !state.outputs_showing(surface).is_empty();
+    let blocking = compositor::with_states(surface, |states| {
+        let data = states.data_map.get::<FifoSurfaceData>()?;
+        let mut cached = states.cached_state.get::<FifoSurfaceCachedState>();
+        let pending = cached.pending();
+        let mut latest_barrier = data.latest_barrier.lock().unwrap();
+        let blocking = latest_barrier
+            .clone()
+            .filter(|barrier| pending.wait_barrier && !barrier.is_cleared());
+        if pending.set_barrier && shown {
+            let barrier = Arc::new(FifoBarrier::default());
+            pending.barrier = Some(barrier.clone());
+            *latest_barrier = Some(barrier);
+        }
+        blocking
+    });
+    if let Some(barrier) = blocking {
+        add_blocker(surface, FifoBlocker(barrier));
+    }
+}
+
+fn with_pending(surface: &WlSurface, f: impl FnOnce(&mut FifoSurfaceCachedState)) {
+    compositor::with_states(surface, |states| {
+        f(states
+            .cached_state
+            .get::<FifoSurfaceCachedState>()
+            .pending())
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocols::test_client::TestClient;
+    use smithay::{
+        reexports::{
+            wayland_protocols::wp::presentation_time::server::wp_presentation_feedback::Kind,
+            wayland_server::protocol::wl_surface,
+        },
+        wayland::compositor::SurfaceAttributes,
+    };
+    use std::time::Duration;
+
+    /// Give the surface of id `surface` a fifo object, returning its id
+    fn fifo(client: &mut TestClient, surface: u32) -> u32 {
+        let manager = client.bind("wp_fifo_manager_v1", 1);
+        let fifo = client.new_id();
+        client.request(
+            manager,
+            wp_fifo_manager_v1::REQ_GET_FIFO_OPCODE,
+            &[fifo, surface],
+        );
+        fifo
+    }
+
+    /// Commit a buffer scale of `scale` to the surface of id `surface`, which tells
+    /// whether the commit was applied
+    fn commit_scale(client: &mut TestClient, surface: u32, scale: u32) {
+        client.request(surface, wl_surface::REQ_SET_BUFFER_SCALE_OPCODE, &[scale]);
+        client.commit(surface);
+    }
+
+    fn applied_scale(client: &TestClient, surface: u32) -> i32 {
+        let surface = client.object::<WlSurface>(surface);
+        compositor::with_states(&surface, |states| {
+            states
+                .cached_state
+                .get::<SurfaceAttributes>()
+                .current()
+                .buffer_scale
+        })
+    }
+
+    #[test]
+    fn waiting_commits_are_held_until_the_barrier_was_presented() {
+        let mut client = TestClient::connect();
+        let output = client.add_output(640, 480);
+        let surface = client.map_fullscreen_toplevel(640, 480);
+        let fifo = fifo(&mut client, surface);
+        client.request(fifo, wp_fifo_v1::REQ_SET_BARRIER_OPCODE, &[]);
+        commit_scale(&mut client, surface, 1);
+
+        client.request(fifo, wp_fifo_v1::REQ_WAIT_BARRIER_OPCODE, &[]);
+        commit_scale(&mut client, surface, 2);
+        assert_eq!(applied_scale(&client, surface), 1);
+
+        // The frame showing the barrier's content latches it once presented
+        client.state.frame_submitted(&output, Duration::ZERO);
+        assert_eq!(applied_scale(&client, surface), 1);
+        let now = client.state.frame_scheduling_state.now();
+        client
+            .state
+            .frame_presented(&output, now, None, 0, Kind::empty());
+        assert_eq!(applied_scale(&client, surface), 2);
+    }
+
+    #[test]
+    fn hidden_surfaces_set_no_barriers() {
+        let mut client = TestClient::connect();
+        client.add_output(640, 480);
+        let surface = client.create_surface();
+        let fifo = fifo(&mut client, surface);
+        client.request(fifo, wp_fifo_v1::REQ_SET_BARRIER_OPCODE, &[]);
+        commit_scale(&mut client, surface, 1);
+
+        client.request(fifo, wp_fifo_v1::REQ_WAIT_BARRIER_OPCODE, &[]);
+        commit_scale(&mut client, surface, 2);
+        assert_eq!(applied_scale(&client, surface), 2);
+    }
+
+    #[test]
+    fn second_fifo_is_a_protocol_error() {
+        let mut client = TestClient::connect();
+        let surface = client.create_surface();
+        fifo(&mut client, surface);
+        let manager = client.bind("wp_fifo_manager_v1", 1);
+        let second = client.new_id();
+        client.request(
+            manager,
+            wp_fifo_manager_v1::REQ_GET_FIFO_OPCODE,
+            &[second, surface],
+        );
+        client.dispatch();
+        assert_eq!(
+            client.protocol_error(),
+            Some((manager, wp_fifo_manager_v1::Error::AlreadyExists as u32))
+        );
+    }
+}
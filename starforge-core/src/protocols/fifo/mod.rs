@@ -0,0 +1,125 @@
+//! Implementation of the wp_fifo_v1 protocol
+//!
+//! Clients pace their frames by queueing commits first in, first out. A commit can set a
+//! barrier, which is cleared once its content was latched, i.e. the first frame showing
+//! it was presented. A later commit can wait for the barrier, and is then held back by a
+//! blocker until the barrier is cleared.
+//!
+//! Content that isn't shown anywhere would never be latched, so commits made while a
+//! surface is hidden don't set barriers; clients keep running instead of stalling.
+
+mod dispatch;
+
+use smithay::{
+    reexports::{
+        wayland_protocols::wp::fifo::v1::server::wp_fifo_manager_v1::WpFifoManagerV1,
+        wayland_server::{DisplayHandle, backend::GlobalId, protocol::wl_surface::WlSurface},
+    },
+    wayland::compositor::{
+        Blocker, BlockerState, Cacheable, TraversalAction, with_surface_tree_downward,
+    },
+};
+use std::sync::{
+    Arc,
+    atomic::{AtomicBool, Ordering},
+};
+
+/// Version of the wp_fifo_manager_v1 global
+const VERSION: u32 = 1;
+
+/// Barrier set by a commit, cleared once its content was latched
+#[derive(Debug, Default)]
+pub struct FifoBarrier {
+    cleared: AtomicBool,
+}
+
+impl FifoBarrier {
+    pub fn clear(&self) {
+        self.cleared.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cleared(&self) -> bool {
+        self.cleared.load(Ordering::SeqCst)
+    }
+}
+
+/// Holds back a commit until a barrier is cleared
+struct FifoBlocker(Arc<FifoBarrier>);
+
+impl Blocker for FifoBlocker {
+    fn state(&self) -> BlockerState {
+        if self.0.is_cleared() {
+            BlockerState::Released
+        } else {
+            BlockerState::Pending
+        }
+    }
+}
+
+/// Double-buffered fifo state of a surface
+#[derive(Clone, Debug, Default)]
+pub struct FifoSurfaceCachedState {
+    /// The commit sets a barrier
+    set_barrier: bool,
+    /// The commit waits for the barrier of an earlier one
+    wait_barrier: bool,
+    /// Barrier of the content, set once the commit is queued
+    barrier: Option<Arc<FifoBarrier>>,
+}
+
+impl Cacheable for FifoSurfaceCachedState {
+    fn commit(&mut self, _dh: &DisplayHandle) -> Self {
+        // Barrier requests only apply to the commit they were made for
+        std::mem::take(self)
+    }
+
+    fn merge_into(self, into: &mut Self, _dh: &DisplayHandle) {
+        *into = self;
+    }
+}
+
+/// Clear the barriers of the current content of `surface` and its subsurfaces, once a
+/// frame showing it was presented or discarded
+///
+/// Returns whether a barrier was cleared, which may release commits waiting for it.
+pub fn clear_barriers(surface: &WlSurface) -> bool {
+    let mut cleared = false;
+    with_surface_tree_downward(
+        surface,
+        (),
+        |_, _, _| TraversalAction::DoChildren(()),
+        |_, states, _| {
+            let barrier = states
+                .cached_state
+                .get::<FifoSurfaceCachedState>()
+                .current()
+                .barrier
+                .take();
+            if let Some(barrier) = barrier {
+                barrier.clear();
+                cleared = true;
+            }
+        },
+        |_, _, _| true,
+    );
+    cleared
+}
+
+/// State of the wp_fifo_manager_v1 global
+#[derive(Debug)]
+pub struct FifoState {
+    global: GlobalId,
+}
+
+impl FifoState {
+    /// Create the wp_fifo_manager_v1 global
+    pub fn new(dh: &DisplayHandle) -> Self {
+        let global = dh.create_global::<crate::StarforgeState, WpFifoManagerV1, ()>(VERSION, ());
+        Self { global }
+    }
+
+    /// The global of the protocol
+    pub fn global(&self) -> GlobalId {
+        self.global.clone()
+    }
+}
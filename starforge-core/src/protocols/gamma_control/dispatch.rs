@@ -0,0 +1,129 @@
+use super::{GammaControlData, GammaRamp};
+use crate::StarforgeState;
+use smithay::{
+    output::Output,
+    reexports::{
+        wayland_protocols_wlr::gamma_control::v1::server::{
+            zwlr_gamma_control_manager_v1::{self, ZwlrGammaControlManagerV1},
+            zwlr_gamma_control_v1::{self, ZwlrGammaControlV1},
+        },
+        wayland_server::{
+            Client, DataInit, Dispatch, DisplayHandle, GlobalDispatch, New, Resource,
+            backend::ClientId,
+        },
+    },
+};
+use std::{fs::File, io::ErrorKind, os::unix::fs::FileExt};
+
+impl GlobalDispatch<ZwlrGammaControlManagerV1, ()> for StarforgeState {
+    fn bind(
+        _state: &mut Self,
+        _dh: &DisplayHandle,
+        _client: &Client,
+        resource: New<ZwlrGammaControlManagerV1>,
+        _global_data: &(),
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        data_init.init(resource, ());
+    }
+}
+
+impl Dispatch<ZwlrGammaControlManagerV1, ()> for StarforgeState {
+    fn request(
+        state: &mut Self,
+        _client: &Client,
+        _manager: &ZwlrGammaControlManagerV1,
+        request: zwlr_gamma_control_manager_v1::Request,
+        _data: &(),
+        _dh: &DisplayHandle,
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        match request {
+            zwlr_gamma_control_manager_v1::Request::GetGammaControl { id, output } => {
+                let output = Output::from_resource(&output);
+                let control = data_init.init(
+                    id,
+                    GammaControlData {
+                        output: output.as_ref().map(Output::downgrade).unwrap_or_default(),
+                    },
+                );
+                let gamma = &mut state.gamma_control_state;
+                let size = output.as_ref().and_then(|output| {
+                    gamma
+                        .gamma_size(output)
+                        .filter(|_| gamma.control(output).is_none())
+                });
+                match size {
+                    Some(size) => {
+                        gamma.controls.push(control.clone());
+                        control.gamma_size(size);
+                    }
+                    // Gone, without gamma tables or already held by another control
+                    None => control.failed(),
+                }
+            }
+            zwlr_gamma_control_manager_v1::Request::Destroy => {}
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl Dispatch<ZwlrGammaControlV1, GammaControlData> for StarforgeState {
+    fn request(
+        state: &mut Self,
+        _client: &Client,
+        control: &ZwlrGammaControlV1,
+        request: zwlr_gamma_control_v1::Request,
+        data: &GammaControlData,
+        _dh: &DisplayHandle,
+        _data_init: &mut DataInit<'_, Self>,
+    ) {
+        match request {
+            zwlr_gamma_control_v1::Request::SetGamma { fd } => {
+                let gamma = &mut state.gamma_control_state;
+                // A failed control stays inert until the client destroys it
+                let Some(output) = data.output.upgrade() else {
+                    return;
+                };
+                if !gamma.is_active(control) {
+                    return;
+                }
+                let size = gamma.gamma_size(&output).unwrap_or(0) as usize;
+                let mut bytes = vec![0u8; size * 3 * 2];
+                let file = File::from(fd);
+                match file.read_exact_at(&mut bytes, 0) {
+                    Ok(()) => {
+                        let ramp = GammaRamp::from_bytes(size, &bytes);
+                        gamma.changes.push((output.clone(), ramp));
+                        state.frame_scheduling_state.damage(&output);
+                    }
+                    Err(e) if e.kind() == ErrorKind::UnexpectedEof => control.post_error(
+                        zwlr_gamma_control_v1::Error::InvalidGamma,
+                        "gamma tables are shorter than three ramps of the gamma size",
+                    ),
+                    Err(e) => {
+                        tracing::warn!("Failed to read gamma tables: {}", e);
+                        control.failed();
+                        gamma.release(control);
+                        state.frame_scheduling_state.damage(&output);
+                    }
+                }
+            }
+            zwlr_gamma_control_v1::Request::Destroy => {}
+            _ => unreachable!(),
+        }
+    }
+
+    fn destroyed(
+        state: &mut Self,
+        _client: ClientId,
+        control: &ZwlrGammaControlV1,
+        data: &GammaControlData,
+    ) {
+        state.gamma_control_state.release(control);
+        // Redraw with the original tables
+        if let Some(output) = data.output.upgrade() {
+            state.frame_scheduling_state.damage(&output);
+        }
+    }
+}
@@ -0,0 +1,207 @@
+//! Implementation of the wlr-gamma-control-unstable-v1 protocol
+//!
+//! Privileged clients such as wlsunset or gammastep take exclusive control of the gamma
+//! tables of an output and upload a ramp per channel. Outputs only offer gamma control
+//! once the backend gave them a table size with [`GammaControlState::set_gamma_size`].
+//!
+//! The compositor collects uploaded tables with [`GammaControlState::take_gamma_changes`]
+//! and hands them to the renderer. A `None` table means the control went away and the
+//! output's own colour adjustments apply again.
+
+mod dispatch;
+
+use smithay::{
+    output::{Output, WeakOutput},
+    reexports::{
+        wayland_protocols_wlr::gamma_control::v1::server::{
+            zwlr_gamma_control_manager_v1::ZwlrGammaControlManagerV1,
+            zwlr_gamma_control_v1::ZwlrGammaControlV1,
+        },
+        wayland_server::{DisplayHandle, Resource, backend::GlobalId},
+    },
+};
+
+/// Version of the gamma control manager global
+const VERSION: u32 = 1;
+
+/// Gamma tables of an output, one ramp per channel mapping encoded values to new ones
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GammaRamp {
+    pub red: Vec<u16>,
+    pub green: Vec<u16>,
+    pub blue: Vec<u16>,
+}
+
+impl GammaRamp {
+    /// Parse the red, green and blue ramps of `size` native-endian entries each
+    pub fn from_bytes(size: usize, bytes: &[u8]) -> Option<Self> {
+        if size == 0 || bytes.len() != size * 3 * 2 {
+            return None;
+        }
+        let mut entries = bytes
+            .chunks_exact(2)
+            .map(|entry| u16::from_ne_bytes([entry[0], entry[1]]));
+        let mut ramp = || entries.by_ref().take(size).collect::<Vec<_>>();
+        Some(Self {
+            red: ramp(),
+            green: ramp(),
+            blue: ramp(),
+        })
+    }
+
+    /// A ramp of `size` entries that leaves values unchanged
+    pub fn identity(size: usize) -> Self {
+        let ramp: Vec<u16> = (0..size)
+            .map(|i| (i * u16::MAX as usize / (size - 1).max(1)) as u16)
+            .collect();
+        Self {
+            red: ramp.clone(),
+            green: ramp.clone(),
+            blue: ramp,
+        }
+    }
+
+    /// Number of entries in each ramp
+    pub fn len(&self) -> usize {
+        self.red.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.red.is_empty()
+    }
+}
+
+/// User data of a gamma control
+#[derive(Debug)]
+pub struct GammaControlData {
+    output: WeakOutput,
+}
+
+/// State of the gamma control manager global
+#[derive(Debug)]
+pub struct GammaControlState {
+    global: GlobalId,
+    /// Table sizes of outputs that support gamma control
+    sizes: Vec<(WeakOutput, u32)>,
+    /// The control holding each output, at most one per output
+    controls: Vec<ZwlrGammaControlV1>,
+    changes: Vec<(Output, Option<GammaRamp>)>,
+}
+
+impl GammaControlState {
+    /// Create the zwlr_gamma_control_manager_v1 global
+    pub fn new(dh: &DisplayHandle) -> Self {
+        let global =
+            dh.create_global::<crate::StarforgeState, ZwlrGammaControlManagerV1, ()>(VERSION, ());
+        Self {
+            global,
+            sizes: Vec::new(),
+            controls: Vec::new(),
+            changes: Vec::new(),
+        }
+    }
+
+    pub fn global(&self) -> GlobalId {
+        self.global.clone()
+    }
+
+    /// Offer gamma control of an output with ramps of `size` entries, or stop offering
+    /// it with `None`. A control holding the output fails when the size changes.
+    pub fn set_gamma_size(&mut self, output: &Output, size: Option<u32>) {
+        let previous = self.gamma_size(output);
+        self.sizes
+            .retain(|(weak, _)| weak.upgrade().is_some_and(|o| &o != output));
+        if let Some(size) = size.filter(|&size| size > 0) {
+            self.sizes.push((output.downgrade(), size));
+        }
+        if previous != self.gamma_size(output) {
+            self.fail_control(output);
+        }
+    }
+
+    /// Number of entries per ramp of an output, `None` without gamma control
+    pub fn gamma_size(&self, output: &Output) -> Option<u32> {
+        self.sizes
+            .iter()
+            .find(|(weak, _)| weak.upgrade().is_some_and(|o| &o == output))
+            .map(|&(_, size)| size)
+    }
+
+    /// Stop offering gamma control of an output that is going away
+    pub fn output_removed(&mut self, output: &Output) {
+        self.set_gamma_size(output, None);
+    }
+
+    /// Gamma tables uploaded or released since the last call, in order
+    pub fn take_gamma_changes(&mut self) -> Vec<(Output, Option<GammaRamp>)> {
+        std::mem::take(&mut self.changes)
+    }
+
+    /// The control holding an output
+    fn control(&self, output: &Output) -> Option<&ZwlrGammaControlV1> {
+        self.controls.iter().find(|control| {
+            control
+                .data::<GammaControlData>()
+                .and_then(|data| data.output.upgrade())
+                .is_some_and(|o| &o == output)
+        })
+    }
+
+    fn is_active(&self, control: &ZwlrGammaControlV1) -> bool {
+        self.controls.contains(control)
+    }
+
+    /// Release an output's control, restoring its tables
+    fn fail_control(&mut self, output: &Output) {
+        if let Some(control) = self.control(output).cloned() {
+            control.failed();
+            self.release(&control);
+        }
+    }
+
+    /// Forget a control, restoring the tables of its output if it held one
+    fn release(&mut self, control: &ZwlrGammaControlV1) {
+        if !self.is_active(control) {
+            return;
+        }
+        self.controls.retain(|c| c != control);
+        if let Some(output) = control
+            .data::<GammaControlData>()
+            .and_then(|data| data.output.upgrade())
+        {
+            self.changes.push((output, None));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bytes(values: &[u16]) -> Vec<u8> {
+        values.iter().flat_map(|v| v.to_ne_bytes()).collect()
+    }
+
+    #[test]
+    fn ramps_are_split_by_channel() {
+        let ramp = GammaRamp::from_bytes(2, &bytes(&[0, 1, 2, 3, 4, 5])).unwrap();
+        assert_eq!(ramp.red, vec![0, 1]);
+        assert_eq!(ramp.green, vec![2, 3]);
+        assert_eq!(ramp.blue, vec![4, 5]);
+    }
+
+    #[test]
+    fn tables_of_the_wrong_size_are_rejected() {
+        assert!(GammaRamp::from_bytes(2, &bytes(&[0, 1, 2, 3, 4])).is_none());
+        assert!(GammaRamp::from_bytes(0, &[]).is_none());
+    }
+
+    #[test]
+    fn identity_spans_the_full_range() {
+        let ramp = GammaRamp::identity(256);
+        assert_eq!(ramp.len(), 256);
+        assert_eq!(ramp.red[0], 0);
+        assert_eq!(ramp.red[255], u16::MAX);
+        assert_eq!(ramp.blue[128], 128 * 257);
+    }
+}
@@ -0,0 +1,425 @@
+use super::{
+    BufferConstraints, CaptureDamage, CaptureFrameData, CaptureSessionData, FrameInner,
+    ImageCaptureSource, SessionInner, finish_frame, send_constraints, stop_session,
+};
+use crate::StarforgeState;
+use smithay::{
+    backend::allocator::Buffer as _,
+    output::{Output, WeakOutput},
+    reexports::{
+        wayland_protocols::ext::{
+            image_capture_source::v1::server::{
+                ext_foreign_toplevel_image_capture_source_manager_v1::{
+                    self, ExtForeignToplevelImageCaptureSourceManagerV1,
+                },
+                ext_image_capture_source_v1::{self, ExtImageCaptureSourceV1},
+                ext_output_image_capture_source_manager_v1::{
+                    self, ExtOutputImageCaptureSourceManagerV1,
+                },
+            },
+            image_copy_capture::v1::server::{
+                ext_image_copy_capture_cursor_session_v1::{
+                    self, ExtImageCopyCaptureCursorSessionV1,
+                },
+                ext_image_copy_capture_frame_v1::{
+                    self, ExtImageCopyCaptureFrameV1, FailureReason,
+                },
+                ext_image_copy_capture_manager_v1::{self, ExtImageCopyCaptureManagerV1, Options},
+                ext_image_copy_capture_session_v1::{self, ExtImageCopyCaptureSessionV1},
+            },
+        },
+        wayland_server::{
+            Client, DataInit, Dispatch, DisplayHandle, GlobalDispatch, New, Resource, WEnum,
+            backend::ClientId, protocol::wl_buffer::WlBuffer,
+        },
+    },
+    utils::{Rectangle, Size},
+    wayland::{
+        dmabuf::get_dmabuf, foreign_toplevel_list::ForeignToplevelHandle, shm::with_buffer_contents,
+    },
+};
+use std::sync::{
+    Mutex,
+    atomic::{AtomicBool, Ordering},
+};
+
+impl GlobalDispatch<ExtOutputImageCaptureSourceManagerV1, ()> for StarforgeState {
+    fn bind(
+        _state: &mut Self,
+        _dh: &DisplayHandle,
+        _client: &Client,
+        resource: New<ExtOutputImageCaptureSourceManagerV1>,
+        _global_data: &(),
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        data_init.init(resource, ());
+    }
+}
+
+impl Dispatch<ExtOutputImageCaptureSourceManagerV1, ()> for StarforgeState {
+    fn request(
+        _state: &mut Self,
+        _client: &Client,
+        _manager: &ExtOutputImageCaptureSourceManagerV1,
+        request: ext_output_image_capture_source_manager_v1::Request,
+        _data: &(),
+        _dh: &DisplayHandle,
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        match request {
+            ext_output_image_capture_source_manager_v1::Request::CreateSource {
+                source,
+                output,
+            } => {
+                // Sources of outputs that are already gone stop their sessions right away
+                let output = Output::from_resource(&output)
+                    .map(|output| output.downgrade())
+                    .unwrap_or_default();
+                data_init.init(source, ImageCaptureSource::Output(output));
+            }
+            ext_output_image_capture_source_manager_v1::Request::Destroy => {}
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl GlobalDispatch<ExtForeignToplevelImageCaptureSourceManagerV1, ()> for StarforgeState {
+    fn bind(
+        _state: &mut Self,
+        _dh: &DisplayHandle,
+        _client: &Client,
+        resource: New<ExtForeignToplevelImageCaptureSourceManagerV1>,
+        _global_data: &(),
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        data_init.init(resource, ());
+    }
+}
+
+impl Dispatch<ExtForeignToplevelImageCaptureSourceManagerV1, ()> for StarforgeState {
+    fn request(
+        _state: &mut Self,
+        _client: &Client,
+        _manager: &ExtForeignToplevelImageCaptureSourceManagerV1,
+        request: ext_foreign_toplevel_image_capture_source_manager_v1::Request,
+        _data: &(),
+        _dh: &DisplayHandle,
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        match request {
+            ext_foreign_toplevel_image_capture_source_manager_v1::Request::CreateSource {
+                source,
+                toplevel_handle,
+            } => {
+                let source_data = match ForeignToplevelHandle::from_resource(&toplevel_handle) {
+                    Some(handle) => ImageCaptureSource::Toplevel(handle.downgrade()),
+                    // Not a handle of ours, the source never shows anything
+                    None => ImageCaptureSource::Output(WeakOutput::default()),
+                };
+                data_init.init(source, source_data);
+            }
+            ext_foreign_toplevel_image_capture_source_manager_v1::Request::Destroy => {}
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl Dispatch<ExtImageCaptureSourceV1, ImageCaptureSource> for StarforgeState {
+    fn request(
+        _state: &mut Self,
+        _client: &Client,
+        _source: &ExtImageCaptureSourceV1,
+        request: ext_image_capture_source_v1::Request,
+        _data: &ImageCaptureSource,
+        _dh: &DisplayHandle,
+        _data_init: &mut DataInit<'_, Self>,
+    ) {
+        match request {
+            ext_image_capture_source_v1::Request::Destroy => {}
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl GlobalDispatch<ExtImageCopyCaptureManagerV1, ()> for StarforgeState {
+    fn bind(
+        _state: &mut Self,
+        _dh: &DisplayHandle,
+        _client: &Client,
+        resource: New<ExtImageCopyCaptureManagerV1>,
+        _global_data: &(),
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        data_init.init(resource, ());
+    }
+}
+
+impl Dispatch<ExtImageCopyCaptureManagerV1, ()> for StarforgeState {
+    fn request(
+        state: &mut Self,
+        _client: &Client,
+        manager: &ExtImageCopyCaptureManagerV1,
+        request: ext_image_copy_capture_manager_v1::Request,
+        _data: &(),
+        _dh: &DisplayHandle,
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        match request {
+            ext_image_copy_capture_manager_v1::Request::CreateSession {
+                session,
+                source,
+                options,
+            } => {
+                let paint_cursors = match options {
+                    WEnum::Value(options) => options.contains(Options::PaintCursors),
+                    WEnum::Unknown(_) => {
+                        manager.post_error(
+                            ext_image_copy_capture_manager_v1::Error::InvalidOption,
+                            "unknown capture options",
+                        );
+                        return;
+                    }
+                };
+                let source = source.data::<ImageCaptureSource>().unwrap().clone();
+                let session = new_session(data_init, session, Some(source), paint_cursors);
+                let state = &mut state.image_copy_capture_state;
+                send_constraints(&session, &state.constraints);
+                state.sessions.push(session);
+            }
+            ext_image_copy_capture_manager_v1::Request::CreatePointerCursorSession {
+                session,
+                ..
+            } => {
+                data_init.init(session, AtomicBool::new(false));
+            }
+            ext_image_copy_capture_manager_v1::Request::Destroy => {}
+            _ => unreachable!(),
+        }
+    }
+}
+
+fn new_session(
+    data_init: &mut DataInit<'_, StarforgeState>,
+    session: New<ExtImageCopyCaptureSessionV1>,
+    source: Option<ImageCaptureSource>,
+    paint_cursors: bool,
+) -> ExtImageCopyCaptureSessionV1 {
+    let size = source
+        .as_ref()
+        .and_then(|source| source.buffer_size())
+        .unwrap_or(Size::from((0, 0)));
+    data_init.init(
+        session,
+        CaptureSessionData {
+            inner: Mutex::new(SessionInner {
+                source,
+                paint_cursors,
+                damage: CaptureDamage::new(size),
+                frame: None,
+                stopped: false,
+            }),
+        },
+    )
+}
+
+/// The flag marks cursor sessions that already created their capture session
+impl Dispatch<ExtImageCopyCaptureCursorSessionV1, AtomicBool> for StarforgeState {
+    fn request(
+        _state: &mut Self,
+        _client: &Client,
+        cursor_session: &ExtImageCopyCaptureCursorSessionV1,
+        request: ext_image_copy_capture_cursor_session_v1::Request,
+        data: &AtomicBool,
+        _dh: &DisplayHandle,
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        match request {
+            ext_image_copy_capture_cursor_session_v1::Request::GetCaptureSession { session } => {
+                if data.swap(true, Ordering::SeqCst) {
+                    cursor_session.post_error(
+                        ext_image_copy_capture_cursor_session_v1::Error::DuplicateSession,
+                        "the cursor session already has a capture session",
+                    );
+                    return;
+                }
+                // Cursors are only captured painted into the source
+                let session = new_session(data_init, session, None, false);
+                stop_session(&session);
+            }
+            ext_image_copy_capture_cursor_session_v1::Request::Destroy => {}
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl Dispatch<ExtImageCopyCaptureSessionV1, CaptureSessionData> for StarforgeState {
+    fn request(
+        _state: &mut Self,
+        _client: &Client,
+        session: &ExtImageCopyCaptureSessionV1,
+        request: ext_image_copy_capture_session_v1::Request,
+        data: &CaptureSessionData,
+        _dh: &DisplayHandle,
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        match request {
+            ext_image_copy_capture_session_v1::Request::CreateFrame { frame } => {
+                let mut inner = data.inner.lock().unwrap();
+                if inner.frame.as_ref().is_some_and(|frame| frame.is_alive()) {
+                    session.post_error(
+                        ext_image_copy_capture_session_v1::Error::DuplicateFrame,
+                        "the session already has a frame",
+                    );
+                    return;
+                }
+                let frame = data_init.init(
+                    frame,
+                    CaptureFrameData {
+                        inner: Mutex::new(FrameInner {
+                            session: Some(session.clone()),
+                            ..Default::default()
+                        }),
+                    },
+                );
+                if inner.stopped {
+                    frame.failed(FailureReason::Stopped);
+                } else {
+                    inner.frame = Some(frame);
+                }
+            }
+            ext_image_copy_capture_session_v1::Request::Destroy => {}
+            _ => unreachable!(),
+        }
+    }
+
+    fn destroyed(
+        state: &mut Self,
+        _client: ClientId,
+        session: &ExtImageCopyCaptureSessionV1,
+        _data: &CaptureSessionData,
+    ) {
+        state
+            .image_copy_capture_state
+            .sessions
+            .retain(|existing| existing != session);
+    }
+}
+
+impl Dispatch<ExtImageCopyCaptureFrameV1, CaptureFrameData> for StarforgeState {
+    fn request(
+        state: &mut Self,
+        _client: &Client,
+        frame: &ExtImageCopyCaptureFrameV1,
+        request: ext_image_copy_capture_frame_v1::Request,
+        data: &CaptureFrameData,
+        _dh: &DisplayHandle,
+        _data_init: &mut DataInit<'_, Self>,
+    ) {
+        let mut inner = data.inner.lock().unwrap();
+        if inner.captured && !matches!(request, ext_image_copy_capture_frame_v1::Request::Destroy) {
+            frame.post_error(
+                ext_image_copy_capture_frame_v1::Error::AlreadyCaptured,
+                "the frame was already captured",
+            );
+            return;
+        }
+
+        match request {
+            ext_image_copy_capture_frame_v1::Request::AttachBuffer { buffer } => {
+                inner.buffer = Some(buffer);
+            }
+            ext_image_copy_capture_frame_v1::Request::DamageBuffer {
+                x,
+                y,
+                width,
+                height,
+            } => {
+                if x < 0 || y < 0 || width <= 0 || height <= 0 {
+                    frame.post_error(
+                        ext_image_copy_capture_frame_v1::Error::InvalidBufferDamage,
+                        "buffer damage must be a positive rectangle inside the buffer",
+                    );
+                    return;
+                }
+                inner
+                    .buffer_damage
+                    .push(Rectangle::new((x, y).into(), (width, height).into()));
+            }
+            ext_image_copy_capture_frame_v1::Request::Capture => {
+                let Some(buffer) = inner.buffer.clone() else {
+                    frame.post_error(
+                        ext_image_copy_capture_frame_v1::Error::NoBuffer,
+                        "capture requested without a buffer",
+                    );
+                    return;
+                };
+                inner.captured = true;
+
+                // A session that stopped in the meantime already failed the frame
+                let Some(session) = inner.session.clone() else {
+                    return;
+                };
+                let session_data = session.data::<CaptureSessionData>().unwrap();
+                let (size, new_content) = {
+                    let session = session_data.inner.lock().unwrap();
+                    let source = session.source.clone();
+                    (
+                        source.as_ref().and_then(|source| source.buffer_size()),
+                        source.filter(|_| session.damage.has_new_content()),
+                    )
+                };
+                // Captures are only filled when an output draws a frame: the captured
+                // output, or the one windows are shown on for toplevels, whether it
+                // shows them or not
+                let redraw = new_content.and_then(|source| match &source {
+                    ImageCaptureSource::Output(_) => source.output(),
+                    ImageCaptureSource::Toplevel(_) => state.window_output().cloned(),
+                });
+                let fits = size.is_some_and(|size| {
+                    buffer_fits(&buffer, size, &state.image_copy_capture_state.constraints)
+                });
+                if !fits {
+                    drop(inner);
+                    frame.failed(FailureReason::BufferConstraints);
+                    finish_frame(frame);
+                } else if let Some(output) = redraw {
+                    state.frame_scheduling_state.damage(&output);
+                }
+            }
+            ext_image_copy_capture_frame_v1::Request::Destroy => {}
+            _ => unreachable!(),
+        }
+    }
+
+    fn destroyed(
+        _state: &mut Self,
+        _client: ClientId,
+        frame: &ExtImageCopyCaptureFrameV1,
+        _data: &CaptureFrameData,
+    ) {
+        finish_frame(frame);
+    }
+}
+
+/// Whether `buffer` has the size of the source and a format captures can be copied in
+fn buffer_fits(
+    buffer: &WlBuffer,
+    size: Size<i32, smithay::utils::Buffer>,
+    constraints: &BufferConstraints,
+) -> bool {
+    if let Ok(dmabuf) = get_dmabuf(buffer) {
+        let dmabuf_size = dmabuf.size();
+        return (dmabuf_size.w, dmabuf_size.h) == (size.w, size.h)
+            && constraints
+                .dmabuf
+                .as_ref()
+                .is_some_and(|(_, formats)| formats.contains(&dmabuf.format()));
+    }
+    with_buffer_contents(buffer, |_, len, data| {
+        (data.width, data.height) == (size.w, size.h)
+            && data.stride >= data.width * 4
+            && data.offset as usize + (data.stride * data.height) as usize <= len
+            && constraints.shm_formats.contains(&data.format)
+    })
+    .unwrap_or(false)
+}
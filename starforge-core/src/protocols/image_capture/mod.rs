@@ -0,0 +1,914 @@
+//! Implementation of the ext-image-capture-source-v1 and ext-image-copy-capture-v1 protocols
+//!
+//! Clients create an image capture source for what they want to capture, an output or a
+//! toplevel from the foreign toplevel list, then a capture
+//! session on it. The session advertises the buffer size and formats the compositor can
+//! copy into; the client attaches one of its buffers to a frame and asks for a capture.
+//!
+//! Captures wait in the state until the source shows new content, then the compositor
+//! takes them with [`ImageCopyCaptureState::take_pending_captures`], has the renderer copy
+//! the source into the buffer and completes them with [`PendingCapture::succeed`] or
+//! [`PendingCapture::fail`]. Each capture only asks for the regions of the buffer that are
+//! out of date: what changed on the source since that buffer was last filled, plus what
+//! the client damaged itself.
+//!
+//! Toplevels are captured from their buffers, with subsurfaces and popups, at the highest
+//! scale among them, so they can be captured while hidden or on another workspace. Their
+//! capture is the size of the window without popups; popups reaching further are cut off.
+//!
+//! Cursor capture sessions are accepted, but their capture sessions stop right away;
+//! clients fall back to the `paint_cursors` option.
+
+mod dispatch;
+
+use crate::animation::{SnapshotLayer, WindowSnapshot};
+use crate::handlers::{toplevel_surface, window_root};
+use smithay::{
+    backend::allocator::Format,
+    output::{Output, WeakOutput},
+    reexports::{
+        rustix::fs::Dev,
+        wayland_protocols::ext::{
+            image_capture_source::v1::server::{
+                ext_foreign_toplevel_image_capture_source_manager_v1::ExtForeignToplevelImageCaptureSourceManagerV1,
+                ext_output_image_capture_source_manager_v1::ExtOutputImageCaptureSourceManagerV1,
+            },
+            image_copy_capture::v1::server::{
+                ext_image_copy_capture_frame_v1::{ExtImageCopyCaptureFrameV1, FailureReason},
+                ext_image_copy_capture_manager_v1::ExtImageCopyCaptureManagerV1,
+                ext_image_copy_capture_session_v1::ExtImageCopyCaptureSessionV1,
+            },
+        },
+        wayland_server::{
+            DisplayHandle, Resource,
+            backend::GlobalId,
+            protocol::{wl_buffer::WlBuffer, wl_shm, wl_surface::WlSurface},
+        },
+    },
+    utils::{Buffer, Logical, Rectangle, Size, Transform},
+    wayland::{foreign_toplevel_list::ForeignToplevelWeakHandle, shell::xdg::PopupSurface},
+};
+use std::{collections::HashMap, hash::Hash, sync::Mutex, time::Duration};
+
+/// Version of the capture source manager and copy capture manager globals
+const VERSION: u32 = 1;
+
+/// Damage rectangles kept per buffer before they are merged into their bounding box
+const MAX_DAMAGE_RECTS: usize = 16;
+
+/// What a capture source shows
+#[derive(Clone, Debug)]
+pub enum ImageCaptureSource {
+    /// Everything shown on an output
+    Output(WeakOutput),
+    /// A toplevel with its subsurfaces and popups, wherever it is shown
+    Toplevel(ForeignToplevelWeakHandle),
+}
+
+impl ImageCaptureSource {
+    /// The output shown by the source, if it still exists
+    pub fn output(&self) -> Option<Output> {
+        match self {
+            Self::Output(output) => output.upgrade(),
+            Self::Toplevel(_) => None,
+        }
+    }
+
+    /// Surface of the toplevel shown by the source, if it still exists
+    pub fn toplevel(&self) -> Option<WlSurface> {
+        match self {
+            Self::Output(_) => None,
+            Self::Toplevel(handle) => handle
+                .upgrade()
+                .filter(|handle| !handle.is_closed())
+                .and_then(|handle| toplevel_surface(&handle)),
+        }
+    }
+
+    /// What to draw for a capture of a toplevel source, given every popup the compositor
+    /// knows
+    pub fn toplevel_capture(&self, popups: &[PopupSurface]) -> Option<ToplevelCapture> {
+        let surface = self.toplevel()?;
+        let window = WindowSnapshot::capture(&surface);
+        let bounds = window.bounding_box();
+        if bounds.is_empty() {
+            return None;
+        }
+        let snapshot = WindowSnapshot::capture_with_popups(&surface, popups);
+        Some(ToplevelCapture {
+            scale: snapshot.buffer_scale(),
+            snapshot,
+            bounds,
+        })
+    }
+
+    /// Size of the buffers captures of the source go into, `None` once it's gone
+    fn buffer_size(&self) -> Option<Size<i32, Buffer>> {
+        match self {
+            Self::Output(output) => {
+                let mode = output.upgrade()?.current_mode()?;
+                Some(Size::from((mode.size.w, mode.size.h)))
+            }
+            Self::Toplevel(_) => {
+                let surface = self.toplevel()?;
+                let window = WindowSnapshot::capture(&surface);
+                let bounds = window.bounding_box();
+                if bounds.is_empty() {
+                    return None;
+                }
+                Some(
+                    bounds
+                        .size
+                        .to_buffer(window.buffer_scale(), Transform::Normal),
+                )
+            }
+        }
+    }
+
+    fn transform(&self) -> Transform {
+        match self {
+            Self::Output(output) => output
+                .upgrade()
+                .map(|output| output.current_transform())
+                .unwrap_or(Transform::Normal),
+            Self::Toplevel(_) => Transform::Normal,
+        }
+    }
+
+    fn shows_output(&self, output: &Output) -> bool {
+        match self {
+            Self::Output(weak) => weak == output,
+            Self::Toplevel(_) => false,
+        }
+    }
+
+    fn shows_toplevel(&self, surface: &WlSurface) -> bool {
+        match self {
+            Self::Output(_) => false,
+            Self::Toplevel(_) => self.toplevel().as_ref() == Some(surface),
+        }
+    }
+}
+
+/// The surfaces of a toplevel to draw into a capture of it
+#[derive(Debug)]
+pub struct ToplevelCapture {
+    /// Buffers of the toplevel, its subsurfaces and popups, bottom-most first
+    pub snapshot: WindowSnapshot,
+    /// Part of the window captured, relative to its main surface
+    pub bounds: Rectangle<i32, Logical>,
+    /// Scale from logical to capture buffer coordinates
+    pub scale: i32,
+}
+
+impl ToplevelCapture {
+    /// Where a layer of the snapshot is drawn in the capture buffer
+    pub fn layer_rect(&self, layer: &SnapshotLayer) -> Rectangle<i32, Buffer> {
+        self.capture_rect(Rectangle::new(layer.location, layer.size))
+    }
+
+    fn capture_rect(&self, rect: Rectangle<i32, Logical>) -> Rectangle<i32, Buffer> {
+        Rectangle::new(
+            (rect.loc - self.bounds.loc).to_buffer(
+                self.scale,
+                Transform::Normal,
+                &self.bounds.size,
+            ),
+            rect.size.to_buffer(self.scale, Transform::Normal),
+        )
+    }
+}
+
+/// Buffers the renderer can copy captures into
+#[derive(Clone, Debug)]
+pub struct BufferConstraints {
+    pub shm_formats: Vec<wl_shm::Format>,
+    /// Device dma-bufs have to be allocated on, with the formats it can write
+    pub dmabuf: Option<(Dev, Vec<Format>)>,
+}
+
+impl Default for BufferConstraints {
+    fn default() -> Self {
+        Self {
+            shm_formats: vec![wl_shm::Format::Argb8888, wl_shm::Format::Xrgb8888],
+            dmabuf: None,
+        }
+    }
+}
+
+/// Regions of a capture buffer
+type BufferRegions = Vec<Rectangle<i32, Buffer>>;
+
+/// Tracks which parts of a session's buffers are out of date
+///
+/// Buffers are identified by `K`. A buffer the session has not filled before is out of
+/// date everywhere.
+#[derive(Debug)]
+pub struct CaptureDamage<K> {
+    size: Size<i32, Buffer>,
+    /// Source damage since the last captured frame, sent with the next one
+    since_last_frame: Vec<Rectangle<i32, Buffer>>,
+    /// Whether the next frame is the first one, which is captured right away
+    first_frame: bool,
+    /// Source damage since each buffer was last filled
+    buffers: HashMap<K, Vec<Rectangle<i32, Buffer>>>,
+}
+
+impl<K: Eq + Hash> CaptureDamage<K> {
+    pub fn new(size: Size<i32, Buffer>) -> Self {
+        Self {
+            size,
+            since_last_frame: Vec::new(),
+            first_frame: true,
+            buffers: HashMap::new(),
+        }
+    }
+
+    fn full(&self) -> Rectangle<i32, Buffer> {
+        Rectangle::from_size(self.size)
+    }
+
+    /// Record that `damage` changed on the source
+    pub fn add(&mut self, damage: &[Rectangle<i32, Buffer>]) {
+        let full = self.full();
+        let damage: Vec<_> = damage
+            .iter()
+            .filter_map(|rect| rect.intersection(full))
+            .collect();
+        if damage.is_empty() {
+            return;
+        }
+        push_damage(&mut self.since_last_frame, &damage);
+        for buffer_damage in self.buffers.values_mut() {
+            push_damage(buffer_damage, &damage);
+        }
+    }
+
+    /// Whether the source changed since the last frame, so a capture can proceed
+    pub fn has_new_content(&self) -> bool {
+        self.first_frame || !self.since_last_frame.is_empty()
+    }
+
+    /// Start a frame into `buffer`
+    ///
+    /// Returns the regions of the buffer that have to be copied, and the damage to report
+    /// to the client. `client_damage` is what the client changed in the buffer itself.
+    pub fn take_frame(
+        &mut self,
+        buffer: K,
+        client_damage: &[Rectangle<i32, Buffer>],
+    ) -> (BufferRegions, BufferRegions) {
+        let full = self.full();
+        let mut copy = match self.buffers.insert(buffer, Vec::new()) {
+            Some(damage) => damage,
+            None => vec![full],
+        };
+        let client_damage: Vec<_> = client_damage
+            .iter()
+            .filter_map(|rect| rect.intersection(full))
+            .collect();
+        push_damage(&mut copy, &client_damage);
+
+        let reported = if std::mem::take(&mut self.first_frame) {
+            self.since_last_frame.clear();
+            vec![full]
+        } else {
+            std::mem::take(&mut self.since_last_frame)
+        };
+        (copy, reported)
+    }
+
+    /// Forget the buffers `keep` returns false for, e.g. those the client destroyed
+    pub fn retain_buffers(&mut self, mut keep: impl FnMut(&K) -> bool) {
+        self.buffers.retain(|buffer, _| keep(buffer));
+    }
+}
+
+fn push_damage(damage: &mut Vec<Rectangle<i32, Buffer>>, new: &[Rectangle<i32, Buffer>]) {
+    for rect in new {
+        if !damage.iter().any(|existing| existing.contains_rect(*rect)) {
+            damage.push(*rect);
+        }
+    }
+    if damage.len() > MAX_DAMAGE_RECTS {
+        let bounds = damage.iter().copied().reduce(|a, b| a.merge(b)).unwrap();
+        *damage = vec![bounds];
+    }
+}
+
+/// State of a capture session, stored in the session's user data
+#[derive(Debug)]
+pub struct CaptureSessionData {
+    inner: Mutex<SessionInner>,
+}
+
+#[derive(Debug)]
+struct SessionInner {
+    /// `None` for sessions that never produce frames
+    source: Option<ImageCaptureSource>,
+    paint_cursors: bool,
+    damage: CaptureDamage<WlBuffer>,
+    /// The frame currently attached to the session, at most one at a time
+    frame: Option<ExtImageCopyCaptureFrameV1>,
+    stopped: bool,
+}
+
+/// State of a capture frame, stored in the frame's user data
+#[derive(Debug)]
+pub struct CaptureFrameData {
+    inner: Mutex<FrameInner>,
+}
+
+#[derive(Debug, Default)]
+struct FrameInner {
+    session: Option<ExtImageCopyCaptureSessionV1>,
+    buffer: Option<WlBuffer>,
+    buffer_damage: Vec<Rectangle<i32, Buffer>>,
+    /// Set once the client sent `capture`
+    captured: bool,
+    /// Set once the capture was handed to the compositor
+    taken: bool,
+}
+
+/// A capture waiting for the renderer to copy its source into the client's buffer
+#[derive(Debug)]
+pub struct PendingCapture {
+    session: ExtImageCopyCaptureSessionV1,
+    frame: ExtImageCopyCaptureFrameV1,
+    pub source: ImageCaptureSource,
+    pub buffer: WlBuffer,
+    /// Regions of the buffer to copy, in buffer coordinates
+    pub copy_regions: Vec<Rectangle<i32, Buffer>>,
+    /// Whether the cursor has to be drawn into the buffer
+    pub paint_cursors: bool,
+    transform: Transform,
+    /// Damage reported to the client with the frame
+    damage: Vec<Rectangle<i32, Buffer>>,
+}
+
+impl PendingCapture {
+    /// The session the frame belongs to
+    pub fn session(&self) -> &ExtImageCopyCaptureSessionV1 {
+        &self.session
+    }
+
+    /// Tell the client the buffer holds the frame presented at `presentation_time`
+    pub fn succeed(self, presentation_time: Duration) {
+        if !self.frame.is_alive() {
+            return;
+        }
+        self.frame.transform(self.transform.into());
+        for rect in &self.damage {
+            self.frame
+                .damage(rect.loc.x, rect.loc.y, rect.size.w, rect.size.h);
+        }
+        let secs = presentation_time.as_secs();
+        self.frame.presentation_time(
+            (secs >> 32) as u32,
+            secs as u32,
+            presentation_time.subsec_nanos(),
+        );
+        self.frame.ready();
+        finish_frame(&self.frame);
+    }
+
+    /// Tell the client the capture failed
+    pub fn fail(self, reason: FailureReason) {
+        if self.frame.is_alive() {
+            self.frame.failed(reason);
+        }
+        finish_frame(&self.frame);
+    }
+}
+
+/// Detach a completed frame from its session, so the session accepts a new one
+fn finish_frame(frame: &ExtImageCopyCaptureFrameV1) {
+    let Some(data) = frame.data::<CaptureFrameData>() else {
+        return;
+    };
+    let session = data.inner.lock().unwrap().session.take();
+    if let Some(session) = session
+        && let Some(data) = session.data::<CaptureSessionData>()
+    {
+        let mut session = data.inner.lock().unwrap();
+        if session.frame.as_ref() == Some(frame) {
+            session.frame = None;
+        }
+    }
+}
+
+/// State of the capture source and image copy capture globals
+#[derive(Debug)]
+pub struct ImageCopyCaptureState {
+    output_source_global: GlobalId,
+    toplevel_source_global: GlobalId,
+    copy_capture_global: GlobalId,
+    constraints: BufferConstraints,
+    sessions: Vec<ExtImageCopyCaptureSessionV1>,
+}
+
+impl ImageCopyCaptureState {
+    /// Create the ext_output_image_capture_source_manager_v1,
+    /// ext_foreign_toplevel_image_capture_source_manager_v1 and
+    /// ext_image_copy_capture_manager_v1 globals
+    pub fn new(dh: &DisplayHandle) -> Self {
+        let output_source_global = dh
+            .create_global::<crate::StarforgeState, ExtOutputImageCaptureSourceManagerV1, ()>(
+                VERSION,
+                (),
+            );
+        let toplevel_source_global = dh.create_global::<
+            crate::StarforgeState,
+            ExtForeignToplevelImageCaptureSourceManagerV1,
+            (),
+        >(VERSION, ());
+        let copy_capture_global = dh
+            .create_global::<crate::StarforgeState, ExtImageCopyCaptureManagerV1, ()>(VERSION, ());
+        Self {
+            output_source_global,
+            toplevel_source_global,
+            copy_capture_global,
+            constraints: BufferConstraints::default(),
+            sessions: Vec::new(),
+        }
+    }
+
+    pub fn output_source_global(&self) -> GlobalId {
+        self.output_source_global.clone()
+    }
+
+    pub fn toplevel_source_global(&self) -> GlobalId {
+        self.toplevel_source_global.clone()
+    }
+
+    pub fn copy_capture_global(&self) -> GlobalId {
+        self.copy_capture_global.clone()
+    }
+
+    /// Change the buffers captures can go into and tell every session
+    pub fn set_buffer_constraints(&mut self, constraints: BufferConstraints) {
+        self.constraints = constraints;
+        self.sessions.retain(|session| session.is_alive());
+        for session in &self.sessions {
+            send_constraints(session, &self.constraints);
+        }
+    }
+
+    /// Record that `damage` changed on an output, in output buffer coordinates
+    pub fn damage_output(&mut self, output: &Output, damage: &[Rectangle<i32, Buffer>]) {
+        self.for_each_session(|session| {
+            if session
+                .source
+                .as_ref()
+                .is_some_and(|source| source.shows_output(output))
+            {
+                session.damage.add(damage);
+            }
+        });
+    }
+
+    /// Record that the cursor changed `damage` on an output, in output buffer coordinates
+    ///
+    /// Output captures are taken without the cursor, so only sessions that paint it in
+    /// see the change.
+    pub fn damage_output_cursor(&mut self, output: &Output, damage: &[Rectangle<i32, Buffer>]) {
+        self.for_each_session(|session| {
+            if session.paint_cursors
+                && session
+                    .source
+                    .as_ref()
+                    .is_some_and(|source| source.shows_output(output))
+            {
+                session.damage.add(damage);
+            }
+        });
+    }
+
+    /// Send the new buffer size to the sessions of an output after a mode change
+    pub fn output_changed(&mut self, output: &Output) {
+        self.sessions.retain(|session| session.is_alive());
+        for session in &self.sessions {
+            let data = session.data::<CaptureSessionData>().unwrap();
+            let shows_output = {
+                let mut inner = data.inner.lock().unwrap();
+                match &inner.source {
+                    Some(source) if source.shows_output(output) => {
+                        if let Some(size) = source.buffer_size() {
+                            inner.damage = CaptureDamage::new(size);
+                        }
+                        true
+                    }
+                    _ => false,
+                }
+            };
+            if shows_output {
+                send_constraints(session, &self.constraints);
+            }
+        }
+    }
+
+    /// Stop every session of an output that is going away
+    pub fn output_removed(&mut self, output: &Output) {
+        self.stop_sessions(|source| source.shows_output(output));
+    }
+
+    /// Record a commit to any surface, damaging captures of the toplevel it belongs to
+    ///
+    /// A toplevel whose size or scale changed gets new buffer constraints instead.
+    /// Returns whether a capture of the toplevel waits for the new content.
+    pub fn surface_committed(&mut self, surface: &WlSurface) -> bool {
+        let root = window_root(surface);
+        let mut waiting = false;
+        self.sessions.retain(|session| session.is_alive());
+        for session in &self.sessions {
+            let data = session.data::<CaptureSessionData>().unwrap();
+            let resized = {
+                let mut inner = data.inner.lock().unwrap();
+                let Some(source) = inner.source.clone() else {
+                    continue;
+                };
+                if !source.shows_toplevel(&root) {
+                    continue;
+                }
+                match source.buffer_size() {
+                    Some(size) if size == inner.damage.size => {
+                        let full = inner.damage.full();
+                        inner.damage.add(&[full]);
+                        waiting |= inner.frame.as_ref().is_some_and(frame_waiting);
+                        false
+                    }
+                    Some(size) => {
+                        inner.damage = CaptureDamage::new(size);
+                        true
+                    }
+                    // Unmapped for now, the session resumes with the next buffer
+                    None => false,
+                }
+            };
+            if resized {
+                send_constraints(session, &self.constraints);
+            }
+        }
+        waiting
+    }
+
+    /// Stop every session of a toplevel that is going away
+    pub fn toplevel_closed(&mut self, surface: &WlSurface) {
+        // Sessions of toplevels that went away without notice stop as well
+        self.stop_sessions(|source| {
+            source.shows_toplevel(surface)
+                || matches!(source, ImageCaptureSource::Toplevel(_)) && source.toplevel().is_none()
+        });
+    }
+
+    fn stop_sessions(&mut self, mut stop: impl FnMut(&ImageCaptureSource) -> bool) {
+        self.sessions.retain(|session| {
+            let data = session.data::<CaptureSessionData>().unwrap();
+            let source = data.inner.lock().unwrap().source.clone();
+            let stopped = source.as_ref().is_some_and(&mut stop);
+            if stopped {
+                stop_session(session);
+            }
+            session.is_alive() && !stopped
+        });
+    }
+
+    /// Captures whose sources have new content, for the renderer to fill
+    pub fn take_pending_captures(&mut self) -> Vec<PendingCapture> {
+        self.sessions.retain(|session| session.is_alive());
+        let mut captures = Vec::new();
+        for resource in &self.sessions {
+            let data = resource.data::<CaptureSessionData>().unwrap();
+            let mut session = data.inner.lock().unwrap();
+            let Some(source) = session.source.clone() else {
+                continue;
+            };
+            let Some(frame) = session.frame.clone() else {
+                continue;
+            };
+            if !session.damage.has_new_content() {
+                continue;
+            }
+            let frame_data = frame.data::<CaptureFrameData>().unwrap();
+            let mut frame_inner = frame_data.inner.lock().unwrap();
+            if !frame_inner.captured || frame_inner.taken {
+                continue;
+            }
+            let buffer = frame_inner.buffer.clone().unwrap();
+            frame_inner.taken = true;
+            session.damage.retain_buffers(|buffer| buffer.is_alive());
+            let (copy_regions, damage) = session
+                .damage
+                .take_frame(buffer.clone(), &frame_inner.buffer_damage);
+            captures.push(PendingCapture {
+                session: resource.clone(),
+                frame: frame.clone(),
+                transform: source.transform(),
+                source,
+                buffer,
+                copy_regions,
+                paint_cursors: session.paint_cursors,
+                damage,
+            });
+        }
+        captures
+    }
+
+    fn for_each_session(&mut self, mut f: impl FnMut(&mut SessionInner)) {
+        self.sessions.retain(|session| session.is_alive());
+        for session in &self.sessions {
+            let data = session.data::<CaptureSessionData>().unwrap();
+            f(&mut data.inner.lock().unwrap());
+        }
+    }
+}
+
+/// Whether the client asked to capture `frame` and it wasn't handed to the renderer yet
+fn frame_waiting(frame: &ExtImageCopyCaptureFrameV1) -> bool {
+    let data = frame.data::<CaptureFrameData>().unwrap();
+    let inner = data.inner.lock().unwrap();
+    inner.captured && !inner.taken
+}
+
+/// Send the buffer size and formats of a session's source
+fn send_constraints(session: &ExtImageCopyCaptureSessionV1, constraints: &BufferConstraints) {
+    let data = session.data::<CaptureSessionData>().unwrap();
+    let size = {
+        let inner = data.inner.lock().unwrap();
+        if inner.stopped {
+            return;
+        }
+        inner
+            .source
+            .as_ref()
+            .and_then(|source| source.buffer_size())
+    };
+    let Some(size) = size else {
+        stop_session(session);
+        return;
+    };
+
+    session.buffer_size(size.w as u32, size.h as u32);
+    for format in &constraints.shm_formats {
+        session.shm_format(*format);
+    }
+    if let Some((device, formats)) = &constraints.dmabuf {
+        session.dmabuf_device(device.to_ne_bytes().to_vec());
+        let mut modifiers: HashMap<u32, Vec<u8>> = HashMap::new();
+        for format in formats {
+            modifiers
+                .entry(format.code as u32)
+                .or_default()
+                .extend_from_slice(&u64::from(format.modifier).to_ne_bytes());
+        }
+        for (code, modifiers) in modifiers {
+            session.dmabuf_format(code, modifiers);
+        }
+    }
+    session.done();
+}
+
+/// Tell the client a session won't produce frames anymore and fail its frame
+fn stop_session(session: &ExtImageCopyCaptureSessionV1) {
+    let data = session.data::<CaptureSessionData>().unwrap();
+    let frame = {
+        let mut inner = data.inner.lock().unwrap();
+        if std::mem::replace(&mut inner.stopped, true) {
+            return;
+        }
+        inner.frame.take()
+    };
+    if let Some(frame) = frame
+        && frame.is_alive()
+    {
+        frame.failed(FailureReason::Stopped);
+    }
+    if session.is_alive() {
+        session.stopped();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{StarforgeState, state::StarforgeClientState};
+    use smithay::{
+        backend::allocator::{
+            Fourcc, Modifier,
+            dmabuf::{Dmabuf, DmabufFlags},
+        },
+        output::{Mode, PhysicalProperties, Subpixel},
+        reexports::wayland_server::{Client, Display},
+    };
+    use std::{fs::File, os::unix::net::UnixStream, sync::Arc};
+
+    fn rect(x: i32, y: i32, w: i32, h: i32) -> Rectangle<i32, Buffer> {
+        Rectangle::new((x, y).into(), (w, h).into())
+    }
+
+    /// A frame of `session` the client attached `buffer` to and asked to capture
+    fn captured_frame(
+        dh: &DisplayHandle,
+        client: &Client,
+        session: &ExtImageCopyCaptureSessionV1,
+        buffer: &WlBuffer,
+    ) -> ExtImageCopyCaptureFrameV1 {
+        let frame = client
+            .create_resource::<ExtImageCopyCaptureFrameV1, _, StarforgeState>(
+                dh,
+                VERSION,
+                CaptureFrameData {
+                    inner: Mutex::new(FrameInner {
+                        session: Some(session.clone()),
+                        buffer: Some(buffer.clone()),
+                        captured: true,
+                        ..Default::default()
+                    }),
+                },
+            )
+            .unwrap();
+        let data = session.data::<CaptureSessionData>().unwrap();
+        data.inner.lock().unwrap().frame = Some(frame.clone());
+        frame
+    }
+
+    #[test]
+    fn new_buffers_are_copied_in_full() {
+        let mut damage = CaptureDamage::new(Size::from((100, 50)));
+        assert!(damage.has_new_content());
+        let (copy, reported) = damage.take_frame(1, &[]);
+        assert_eq!(copy, vec![rect(0, 0, 100, 50)]);
+        assert_eq!(reported, vec![rect(0, 0, 100, 50)]);
+        assert!(!damage.has_new_content());
+    }
+
+    #[test]
+    fn repeated_frames_only_copy_what_changed() {
+        let mut damage = CaptureDamage::new(Size::from((100, 50)));
+        damage.take_frame(1, &[]);
+        damage.take_frame(2, &[]);
+
+        damage.add(&[rect(10, 10, 5, 5)]);
+        assert!(damage.has_new_content());
+        let (copy, reported) = damage.take_frame(1, &[]);
+        assert_eq!(copy, vec![rect(10, 10, 5, 5)]);
+        assert_eq!(reported, vec![rect(10, 10, 5, 5)]);
+
+        // Buffer 2 missed the previous frame as well
+        damage.add(&[rect(20, 20, 5, 5)]);
+        let (copy, reported) = damage.take_frame(2, &[rect(0, 0, 1, 1)]);
+        assert_eq!(
+            copy,
+            vec![rect(10, 10, 5, 5), rect(20, 20, 5, 5), rect(0, 0, 1, 1)]
+        );
+        assert_eq!(reported, vec![rect(20, 20, 5, 5)]);
+    }
+
+    #[test]
+    fn toplevel_layers_are_placed_at_buffer_scale() {
+        // A window with client-side shadows starting 10 pixels out, drawn at scale 2
+        let capture = ToplevelCapture {
+            snapshot: WindowSnapshot::default(),
+            bounds: Rectangle::new((-10, -10).into(), (120, 80).into()),
+            scale: 2,
+        };
+        let popup = Rectangle::new((50, 40).into(), (30, 20).into());
+        assert_eq!(capture.capture_rect(popup), rect(120, 100, 60, 40));
+    }
+
+    #[test]
+    fn damage_is_clipped_and_merged() {
+        let mut damage = CaptureDamage::new(Size::from((100, 50)));
+        damage.take_frame(1, &[]);
+        damage.add(&[rect(90, 40, 20, 20), rect(200, 200, 5, 5)]);
+        for i in 0..MAX_DAMAGE_RECTS as i32 {
+            damage.add(&[rect(i, 0, 1, 1)]);
+        }
+        let (copy, _) = damage.take_frame(1, &[]);
+        assert_eq!(copy, vec![rect(0, 0, 100, 50)]);
+    }
+
+    /// An output of 100x50
+    fn test_output() -> Output {
+        let output = Output::new(
+            "test".to_string(),
+            PhysicalProperties {
+                size: (0, 0).into(),
+                subpixel: Subpixel::Unknown,
+                make: "Starforge".to_string(),
+                model: "Test".to_string(),
+            },
+        );
+        output.change_current_state(
+            Some(Mode {
+                size: (100, 50).into(),
+                refresh: 60_000,
+            }),
+            None,
+            None,
+            None,
+        );
+        output
+    }
+
+    /// A session of `client` capturing `output`, added to `state`
+    fn output_session(
+        dh: &DisplayHandle,
+        client: &Client,
+        state: &mut ImageCopyCaptureState,
+        output: &Output,
+        paint_cursors: bool,
+    ) -> ExtImageCopyCaptureSessionV1 {
+        let session = client
+            .create_resource::<ExtImageCopyCaptureSessionV1, _, StarforgeState>(
+                dh,
+                VERSION,
+                CaptureSessionData {
+                    inner: Mutex::new(SessionInner {
+                        source: Some(ImageCaptureSource::Output(output.downgrade())),
+                        paint_cursors,
+                        damage: CaptureDamage::new(Size::from((100, 50))),
+                        frame: None,
+                        stopped: false,
+                    }),
+                },
+            )
+            .unwrap();
+        state.sessions.push(session.clone());
+        session
+    }
+
+    #[test]
+    fn output_captures_complete_and_take_the_next_frame() {
+        let display = Display::<StarforgeState>::new().unwrap();
+        let mut dh = display.handle();
+        let (stream, _client_stream) = UnixStream::pair().unwrap();
+        let client = dh
+            .insert_client(stream, Arc::new(StarforgeClientState::default()))
+            .unwrap();
+        let mut state = ImageCopyCaptureState::new(&dh);
+
+        let output = test_output();
+        let session = output_session(&dh, &client, &mut state, &output, false);
+        let mut dmabuf = Dmabuf::builder(
+            (100, 50),
+            Fourcc::Argb8888,
+            Modifier::Linear,
+            DmabufFlags::empty(),
+        );
+        dmabuf.add_plane(File::open("/dev/null").unwrap().into(), 0, 0, 400);
+        let buffer = client
+            .create_resource::<WlBuffer, _, StarforgeState>(&dh, 1, dmabuf.build().unwrap())
+            .unwrap();
+
+        // The first frame is copied in full as soon as the output draws
+        captured_frame(&dh, &client, &session, &buffer);
+        state.damage_output(&output, &[rect(10, 10, 5, 5)]);
+        let mut captures = state.take_pending_captures();
+        assert_eq!(captures.len(), 1);
+        let capture = captures.pop().unwrap();
+        assert_eq!(capture.buffer, buffer);
+        assert_eq!(capture.copy_regions, vec![rect(0, 0, 100, 50)]);
+        capture.succeed(Duration::from_millis(16));
+        let data = session.data::<CaptureSessionData>().unwrap();
+        assert!(data.inner.lock().unwrap().frame.is_none());
+
+        // The next frame waits for the output to change, then only copies the change
+        captured_frame(&dh, &client, &session, &buffer);
+        assert!(state.take_pending_captures().is_empty());
+        state.damage_output(&output, &[rect(20, 20, 5, 5)]);
+        let mut captures = state.take_pending_captures();
+        assert_eq!(captures.len(), 1);
+        let capture = captures.pop().unwrap();
+        assert_eq!(capture.copy_regions, vec![rect(20, 20, 5, 5)]);
+        capture.succeed(Duration::from_millis(32));
+        assert!(data.inner.lock().unwrap().frame.is_none());
+    }
+
+    #[test]
+    fn cursor_damage_only_reaches_sessions_painting_it() {
+        let display = Display::<StarforgeState>::new().unwrap();
+        let mut dh = display.handle();
+        let (stream, _client_stream) = UnixStream::pair().unwrap();
+        let client = dh
+            .insert_client(stream, Arc::new(StarforgeClientState::default()))
+            .unwrap();
+        let mut state = ImageCopyCaptureState::new(&dh);
+        let output = test_output();
+        let without = output_session(&dh, &client, &mut state, &output, false);
+        let with = output_session(&dh, &client, &mut state, &output, true);
+        // Both sessions captured their first frame already
+        for session in [&without, &with] {
+            let data = session.data::<CaptureSessionData>().unwrap();
+            data.inner.lock().unwrap().damage.first_frame = false;
+        }
+
+        state.damage_output_cursor(&output, &[rect(10, 10, 5, 5)]);
+        let new_content = |session: &ExtImageCopyCaptureSessionV1| {
+            let data = session.data::<CaptureSessionData>().unwrap();
+            data.inner.lock().unwrap().damage.has_new_content()
+        };
+        assert!(!new_content(&without));
+        assert!(new_content(&with));
+    }
+}
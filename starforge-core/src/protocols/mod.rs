@@ -0,0 +1,15 @@
+//! Wayland protocols implemented by Starforge itself
+//!
+//! Smithay provides most protocols through handler traits, which live in [`crate::handlers`].
+//! The protocols here have no Smithay implementation, so Starforge dispatches them directly.
+
+pub mod color_management;
+pub mod color_representation;
+pub mod commit_timing;
+pub mod fifo;
+pub mod gamma_control;
+pub mod image_capture;
+pub mod tearing_control;
+
+#[cfg(test)]
+pub(crate) mod test_client;
@@ -0,0 +1,211 @@
+use super::{PresentationHint, TearingControlSurfaceCachedState};
+use crate::StarforgeState;
+use smithay::{
+    reexports::{
+        wayland_protocols::wp::tearing_control::v1::server::{
+            wp_tearing_control_manager_v1::{self, WpTearingControlManagerV1},
+            wp_tearing_control_v1::{self, WpTearingControlV1},
+        },
+        wayland_server::{
+            Client, DataInit, Dispatch, DisplayHandle, GlobalDispatch, New, Resource, WEnum, Weak,
+            protocol::wl_surface::WlSurface,
+        },
+    },
+    wayland::compositor,
+};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Marks a surface that already has a wp_tearing_control_v1 object
+#[derive(Debug, Default)]
+struct TearingControlSurfaceData {
+    attached: AtomicBool,
+}
+
+impl GlobalDispatch<WpTearingControlManagerV1, ()> for StarforgeState {
+    fn bind(
+        _state: &mut Self,
+        _dh: &DisplayHandle,
+        _client: &Client,
+        resource: New<WpTearingControlManagerV1>,
+        _global_data: &(),
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        data_init.init(resource, ());
+    }
+}
+
+impl Dispatch<WpTearingControlManagerV1, ()> for StarforgeState {
+    fn request(
+        _state: &mut Self,
+        _client: &Client,
+        manager: &WpTearingControlManagerV1,
+        request: wp_tearing_control_manager_v1::Request,
+        _data: &(),
+        _dh: &DisplayHandle,
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        match request {
+            wp_tearing_control_manager_v1::Request::GetTearingControl { id, surface } => {
+                let already_attached = compositor::with_states(&surface, |states| {
+                    states
+                        .data_map
+                        .insert_if_missing_threadsafe(TearingControlSurfaceData::default);
+                    states
+                        .data_map
+                        .get::<TearingControlSurfaceData>()
+                        .unwrap()
+                        .attached
+                        .swap(true, Ordering::SeqCst)
+                });
+                if already_attached {
+                    manager.post_error(
+                        wp_tearing_control_manager_v1::Error::TearingControlExists,
+                        "the surface already has a tearing control object",
+                    );
+                    return;
+                }
+                data_init.init(id, surface.downgrade());
+            }
+            wp_tearing_control_manager_v1::Request::Destroy => {}
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl Dispatch<WpTearingControlV1, Weak<WlSurface>> for StarforgeState {
+    fn request(
+        _state: &mut Self,
+        _client: &Client,
+        _resource: &WpTearingControlV1,
+        request: wp_tearing_control_v1::Request,
+        surface: &Weak<WlSurface>,
+        _dh: &DisplayHandle,
+        _data_init: &mut DataInit<'_, Self>,
+    ) {
+        // Requests on the object of a destroyed surface are ignored
+        let Ok(surface) = surface.upgrade() else {
+            return;
+        };
+
+        match request {
+            wp_tearing_control_v1::Request::SetPresentationHint { hint } => {
+                let hint = match hint {
+                    WEnum::Value(wp_tearing_control_v1::PresentationHint::Async) => {
+                        PresentationHint::Async
+                    }
+                    _ => PresentationHint::Vsync,
+                };
+                with_pending(&surface, |pending| pending.hint = hint);
+            }
+            wp_tearing_control_v1::Request::Destroy => {
+                // Destroying the object goes back to vsync on the next commit
+                with_pending(&surface, |pending| {
+                    *pending = TearingControlSurfaceCachedState::default()
+                });
+                compositor::with_states(&surface, |states| {
+                    if let Some(data) = states.data_map.get::<TearingControlSurfaceData>() {
+                        data.attached.store(false, Ordering::SeqCst);
+                    }
+                });
+            }
+            _ => unreachable!(),
+        }
+    }
+}
+
+fn with_pending(surface: &WlSurface, f: impl FnOnce(&mut TearingControlSurfaceCachedState)) {
+    compositor::with_states(surface, |states| {
+        f(states
+            .cached_state
+            .get::<TearingControlSurfaceCachedState>()
+            .pending())
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocols::{tearing_control::surface_presentation_hint, test_client::TestClient};
+    use wp_tearing_control_v1::PresentationHint as Hint;
+
+    /// Create a surface with a tearing control object, returning the ids of both
+    fn tearing_control(client: &mut TestClient) -> (u32, u32) {
+        let manager = client.bind("wp_tearing_control_manager_v1", 1);
+        let surface = client.create_surface();
+        let control = client.new_id();
+        client.request(
+            manager,
+            wp_tearing_control_manager_v1::REQ_GET_TEARING_CONTROL_OPCODE,
+            &[control, surface],
+        );
+        client.dispatch();
+        (surface, control)
+    }
+
+    #[test]
+    fn hint_applies_on_commit() {
+        let mut client = TestClient::connect();
+        let (surface, control) = tearing_control(&mut client);
+        client.request(
+            control,
+            wp_tearing_control_v1::REQ_SET_PRESENTATION_HINT_OPCODE,
+            &[Hint::Async as u32],
+        );
+        client.dispatch();
+        let wl_surface = client.object::<WlSurface>(surface);
+        assert_eq!(
+            surface_presentation_hint(&wl_surface),
+            PresentationHint::Vsync
+        );
+
+        client.commit(surface);
+        assert_eq!(
+            surface_presentation_hint(&wl_surface),
+            PresentationHint::Async
+        );
+    }
+
+    #[test]
+    fn destroying_the_object_goes_back_to_vsync() {
+        let mut client = TestClient::connect();
+        let (surface, control) = tearing_control(&mut client);
+        client.request(
+            control,
+            wp_tearing_control_v1::REQ_SET_PRESENTATION_HINT_OPCODE,
+            &[Hint::Async as u32],
+        );
+        client.commit(surface);
+
+        client.request(control, wp_tearing_control_v1::REQ_DESTROY_OPCODE, &[]);
+        client.commit(surface);
+        let wl_surface = client.object::<WlSurface>(surface);
+        assert_eq!(
+            surface_presentation_hint(&wl_surface),
+            PresentationHint::Vsync
+        );
+        assert_eq!(client.protocol_error(), None);
+    }
+
+    #[test]
+    fn second_tearing_control_is_a_protocol_error() {
+        let mut client = TestClient::connect();
+        let manager = client.bind("wp_tearing_control_manager_v1", 1);
+        let surface = client.create_surface();
+        for _ in 0..2 {
+            let control = client.new_id();
+            client.request(
+                manager,
+                wp_tearing_control_manager_v1::REQ_GET_TEARING_CONTROL_OPCODE,
+                &[control, surface],
+            );
+        }
+        client.dispatch();
+        assert_eq!(
+            client.protocol_error(),
+            Some((
+                manager,
+                wp_tearing_control_manager_v1::Error::TearingControlExists as u32
+            ))
+        );
+    }
+}
@@ -0,0 +1,85 @@
+//! Implementation of the wp_tearing_control_v1 protocol
+//!
+//! Clients hint whether the content of a surface should be presented in sync with the
+//! display's vblank or as soon as possible, accepting tearing for lower latency. A surface
+//! keeps the hint of its latest commit in [`TearingControlSurfaceCachedState`]; surfaces
+//! without a hint use vsync.
+//!
+//! The hint is only a request: whether an output actually tears is up to the compositor's
+//! policy, see [`StarforgeState::tearing_surface`](crate::StarforgeState::tearing_surface).
+
+mod dispatch;
+
+use smithay::{
+    reexports::{
+        wayland_protocols::wp::tearing_control::v1::server::wp_tearing_control_manager_v1::WpTearingControlManagerV1,
+        wayland_server::{DisplayHandle, backend::GlobalId, protocol::wl_surface::WlSurface},
+    },
+    wayland::compositor::{Cacheable, with_states},
+};
+
+/// Version of the wp_tearing_control_manager_v1 global
+const VERSION: u32 = 1;
+
+/// How a surface wants its content presented
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum PresentationHint {
+    /// Wait for the vblank, never tear
+    #[default]
+    Vsync,
+    /// Present as soon as possible, even if it tears
+    Async,
+}
+
+/// Double-buffered tearing control state of a surface
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TearingControlSurfaceCachedState {
+    hint: PresentationHint,
+}
+
+impl TearingControlSurfaceCachedState {
+    pub fn hint(&self) -> PresentationHint {
+        self.hint
+    }
+}
+
+/// The presentation hint of the content `surface` committed last
+pub fn surface_presentation_hint(surface: &WlSurface) -> PresentationHint {
+    with_states(surface, |states| {
+        states
+            .cached_state
+            .get::<TearingControlSurfaceCachedState>()
+            .current()
+            .hint
+    })
+}
+
+impl Cacheable for TearingControlSurfaceCachedState {
+    fn commit(&mut self, _dh: &DisplayHandle) -> Self {
+        *self
+    }
+
+    fn merge_into(self, into: &mut Self, _dh: &DisplayHandle) {
+        *into = self;
+    }
+}
+
+/// State of the wp_tearing_control_manager_v1 global
+#[derive(Debug)]
+pub struct TearingControlState {
+    global: GlobalId,
+}
+
+impl TearingControlState {
+    /// Create the wp_tearing_control_manager_v1 global
+    pub fn new(dh: &DisplayHandle) -> Self {
+        let global =
+            dh.create_global::<crate::StarforgeState, WpTearingControlManagerV1, ()>(VERSION, ());
+        Self { global }
+    }
+
+    /// The global of the protocol
+    pub fn global(&self) -> GlobalId {
+        self.global.clone()
+    }
+}
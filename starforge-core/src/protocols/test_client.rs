@@ -0,0 +1,298 @@
+//! A raw Wayland client for testing how the protocols are dispatched
+//!
+//! Requests are written to the socket in the wire format, so they go through the same
+//! dispatch code as those of real clients. Objects the client starts from are created on
+//! the server side, which saves binding globals through the registry.
+
+use crate::{StarforgeState, state::StarforgeClientState};
+use smithay::{
+    backend::allocator::{
+        Fourcc, Modifier,
+        dmabuf::{Dmabuf, DmabufFlags},
+    },
+    output::{Mode, Output, PhysicalProperties, Subpixel},
+    reexports::{
+        calloop::EventLoop,
+        wayland_protocols::xdg::shell::server::{xdg_surface, xdg_toplevel, xdg_wm_base},
+        wayland_server::{
+            Client, Dispatch, Display, DisplayHandle, Resource,
+            protocol::{wl_buffer::WlBuffer, wl_compositor, wl_surface},
+        },
+    },
+};
+use std::{
+    fs::File,
+    io::{ErrorKind, Read, Write},
+    os::unix::net::UnixStream,
+    sync::Arc,
+};
+
+/// wl_display.get_registry, which servers have no bindings for
+const GET_REGISTRY_OPCODE: u16 = 1;
+/// wl_registry.bind
+const BIND_OPCODE: u16 = 0;
+/// wl_registry.global
+const GLOBAL_OPCODE: u16 = 0;
+
+/// A message sent by the compositor
+pub struct Event {
+    /// Protocol id of the object that sent it
+    pub sender: u32,
+    pub opcode: u16,
+    /// Arguments as 32-bit words
+    pub args: Vec<u32>,
+}
+
+/// A compositor and a single client connected to it
+pub struct TestClient {
+    pub display: Display<StarforgeState>,
+    pub state: StarforgeState,
+    pub client: Client,
+    stream: UnixStream,
+    /// Id of the next object the client creates, 1 being the wl_display
+    next_id: u32,
+}
+
+impl TestClient {
+    /// Start a compositor and connect a client to it
+    pub fn connect() -> Self {
+        let display = Display::<StarforgeState>::new().unwrap();
+        let event_loop = EventLoop::try_new().unwrap();
+        let state = StarforgeState::with_display(&display, &event_loop);
+        let (server, stream) = UnixStream::pair().unwrap();
+        stream.set_nonblocking(true).unwrap();
+        let client = display
+            .handle()
+            .insert_client(server, Arc::new(StarforgeClientState::default()))
+            .unwrap();
+        Self {
+            display,
+            state,
+            client,
+            stream,
+            next_id: 2,
+        }
+    }
+
+    pub fn dh(&self) -> DisplayHandle {
+        self.display.handle()
+    }
+
+    /// Create an object of the client on the server side, along with its protocol id
+    pub fn create<I, U>(&self, version: u32, data: U) -> (I, u32)
+    where
+        I: Resource + 'static,
+        U: Send + Sync + 'static,
+        StarforgeState: Dispatch<I, U>,
+    {
+        let resource = self
+            .client
+            .create_resource::<I, U, StarforgeState>(&self.dh(), version, data)
+            .unwrap();
+        let id = resource.id().protocol_id();
+        (resource, id)
+    }
+
+    /// Add an output of `width`x`height` to the compositor, which windows are shown on if
+    /// it's the first one
+    pub fn add_output(&mut self, width: i32, height: i32) -> Output {
+        let output = Output::new(
+            "test".to_string(),
+            PhysicalProperties {
+                size: (0, 0).into(),
+                subpixel: Subpixel::Unknown,
+                make: "Starforge".to_string(),
+                model: "Test".to_string(),
+            },
+        );
+        let mode = Mode {
+            size: (width, height).into(),
+            refresh: 60_000,
+        };
+        output.change_current_state(Some(mode), None, None, Some((0, 0).into()));
+        self.state.add_output(output.clone());
+        output
+    }
+
+    /// Bind the global of `interface`, returning the id of the new object
+    pub fn bind(&mut self, interface: &str, version: u32) -> u32 {
+        let registry = self.new_id();
+        self.request(1, GET_REGISTRY_OPCODE, &[registry]);
+        self.dispatch();
+        let name = self
+            .events()
+            .into_iter()
+            .find(|event| {
+                event.sender == registry
+                    && event.opcode == GLOBAL_OPCODE
+                    && event.args[1..].starts_with(&string(interface))
+            })
+            .unwrap_or_else(|| panic!("no {interface} global"))
+            .args[0];
+        let id = self.new_id();
+        let mut args = vec![name];
+        args.extend(string(interface));
+        args.extend([version, id]);
+        self.request(registry, BIND_OPCODE, &args);
+        id
+    }
+
+    /// A dma-buf backed buffer of `width`x`height` owned by the client
+    pub fn buffer(&self, width: i32, height: i32) -> u32 {
+        let mut dmabuf = Dmabuf::builder(
+            (width, height),
+            Fourcc::Argb8888,
+            Modifier::Linear,
+            DmabufFlags::empty(),
+        );
+        dmabuf.add_plane(
+            File::open("/dev/null").unwrap().into(),
+            0,
+            0,
+            width as u32 * 4,
+        );
+        self.create::<WlBuffer, _>(1, dmabuf.build().unwrap()).1
+    }
+
+    /// Create a surface, returning its id
+    pub fn create_surface(&mut self) -> u32 {
+        let compositor = self.bind("wl_compositor", 4);
+        let surface = self.new_id();
+        self.request(
+            compositor,
+            wl_compositor::REQ_CREATE_SURFACE_OPCODE,
+            &[surface],
+        );
+        surface
+    }
+
+    /// Map a fullscreen xdg toplevel with a buffer of `width`x`height`, returning the id of
+    /// its surface
+    ///
+    /// Asking for fullscreen gets the window configured, which it needs before it can
+    /// attach a buffer.
+    pub fn map_fullscreen_toplevel(&mut self, width: i32, height: i32) -> u32 {
+        let wm_base = self.bind("xdg_wm_base", 1);
+        let surface = self.create_surface();
+        let xdg_surface = self.new_id();
+        self.request(
+            wm_base,
+            xdg_wm_base::REQ_GET_XDG_SURFACE_OPCODE,
+            &[xdg_surface, surface],
+        );
+        let toplevel = self.new_id();
+        self.request(
+            xdg_surface,
+            xdg_surface::REQ_GET_TOPLEVEL_OPCODE,
+            &[toplevel],
+        );
+        self.request(toplevel, xdg_toplevel::REQ_SET_FULLSCREEN_OPCODE, &[0]);
+        self.commit(surface);
+
+        let serial = self
+            .events()
+            .into_iter()
+            .rev()
+            .find(|event| {
+                event.sender == xdg_surface && event.opcode == xdg_surface::EVT_CONFIGURE_OPCODE
+            })
+            .expect("fullscreen windows are configured")
+            .args[0];
+        self.request(
+            xdg_surface,
+            xdg_surface::REQ_ACK_CONFIGURE_OPCODE,
+            &[serial],
+        );
+        let buffer = self.buffer(width, height);
+        self.request(surface, wl_surface::REQ_ATTACH_OPCODE, &[buffer, 0, 0]);
+        self.commit(surface);
+        surface
+    }
+
+    /// Commit the surface `id` and let the compositor apply it
+    pub fn commit(&mut self, id: u32) {
+        self.request(id, wl_surface::REQ_COMMIT_OPCODE, &[]);
+        self.dispatch();
+    }
+
+    /// Allocate the id of an object the next request creates
+    pub fn new_id(&mut self) -> u32 {
+        let id = self.next_id;
+        self.next_id += 1;
+        id
+    }
+
+    /// Send a request to the object `id`, with its arguments as 32-bit words
+    pub fn request(&mut self, id: u32, opcode: u16, args: &[u32]) {
+        let size = (8 + args.len() * 4) as u32;
+        let mut message = Vec::with_capacity(size as usize);
+        message.extend(id.to_ne_bytes());
+        message.extend(((size << 16) | opcode as u32).to_ne_bytes());
+        for arg in args {
+            message.extend(arg.to_ne_bytes());
+        }
+        self.stream.write_all(&message).unwrap();
+    }
+
+    /// Let the compositor handle the requests sent so far and send its events
+    pub fn dispatch(&mut self) {
+        self.display.dispatch_clients(&mut self.state).unwrap();
+        self.display.flush_clients().unwrap();
+    }
+
+    /// The server side of the client's object `id`
+    pub fn object<I: Resource + 'static>(&self, id: u32) -> I {
+        self.client.object_from_protocol_id(&self.dh(), id).unwrap()
+    }
+
+    /// The events the compositor sent since the last call
+    pub fn events(&mut self) -> Vec<Event> {
+        let mut bytes = Vec::new();
+        match self.stream.read_to_end(&mut bytes) {
+            Ok(_) => {}
+            Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+            Err(e) => panic!("failed to read events: {e}"),
+        }
+        let words: Vec<u32> = bytes
+            .chunks_exact(4)
+            .map(|word| u32::from_ne_bytes(word.try_into().unwrap()))
+            .collect();
+        let mut events = Vec::new();
+        let mut rest = &words[..];
+        while let [sender, header, ..] = *rest {
+            let len = (header >> 16) as usize / 4;
+            events.push(Event {
+                sender,
+                opcode: header as u16,
+                args: rest[2..len].to_vec(),
+            });
+            rest = &rest[len..];
+        }
+        events
+    }
+
+    /// The object and code of the protocol error the client was disconnected with
+    pub fn protocol_error(&mut self) -> Option<(u32, u32)> {
+        // wl_display.error is the first event of the display
+        self.events()
+            .into_iter()
+            .find(|event| event.sender == 1 && event.opcode == 0)
+            .map(|event| (event.args[0], event.args[1]))
+    }
+}
+
+/// A string argument as 32-bit words: its length with the terminating NUL, then its
+/// bytes padded to a word
+fn string(s: &str) -> Vec<u32> {
+    let mut bytes = s.as_bytes().to_vec();
+    bytes.push(0);
+    let len = bytes.len() as u32;
+    bytes.resize(bytes.len().next_multiple_of(4), 0);
+    let mut words = vec![len];
+    words.extend(
+        bytes
+            .chunks_exact(4)
+            .map(|word| u32::from_ne_bytes(word.try_into().unwrap())),
+    );
+    words
+}
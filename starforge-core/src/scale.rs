@@ -0,0 +1,118 @@
+//! Fractional output scales and the rounding between logical and physical coordinates
+//!
+//! Outputs can have any scale in steps of 1/120, the precision wp_fractional_scale_v1
+//! sends scales with. Clients that support the protocol render at exactly the output's
+//! scale and use wp_viewporter to give their buffer a logical size; other clients get
+//! the next integer buffer scale and are scaled down.
+//!
+//! At fractional scales, rounding a rectangle's position and size separately can leave
+//! a gap or an overlap of one pixel between surfaces that touch in logical coordinates.
+//! [`to_physical_rect`] rounds each edge instead, so touching surfaces share their edge.
+
+use smithay::{
+    reexports::wayland_server::protocol::wl_surface::WlSurface,
+    utils::{Logical, Physical, Point, Rectangle, Transform},
+    wayland::{
+        compositor::{TraversalAction, send_surface_state, with_surface_tree_downward},
+        fractional_scale::with_fractional_scale,
+    },
+};
+
+/// Steps per unit wp_fractional_scale_v1 sends scales in
+const FRACTIONAL_SCALE_DENOMINATOR: f64 = 120.0;
+
+/// Smallest and largest output scale accepted
+const MIN_SCALE: f64 = 0.25;
+const MAX_SCALE: f64 = 8.0;
+
+/// `scale` clamped to a sensible range and rounded to the nearest step clients can be told
+pub fn snap_scale(scale: f64) -> f64 {
+    let scale = if scale.is_finite() { scale } else { 1.0 };
+    (scale.clamp(MIN_SCALE, MAX_SCALE) * FRACTIONAL_SCALE_DENOMINATOR).round()
+        / FRACTIONAL_SCALE_DENOMINATOR
+}
+
+/// Integer buffer scale for clients without fractional scaling, the next one up so their
+/// buffers are scaled down rather than up
+pub fn integer_scale(scale: f64) -> i32 {
+    (scale - 1.0 / FRACTIONAL_SCALE_DENOMINATOR).ceil().max(1.0) as i32
+}
+
+/// A logical point in physical pixels, rounded to the nearest pixel
+pub fn to_physical_point(point: Point<i32, Logical>, scale: f64) -> Point<i32, Physical> {
+    point.to_f64().to_physical(scale).to_i32_round()
+}
+
+/// A logical rectangle in physical pixels, with each edge rounded to the nearest pixel
+///
+/// Rectangles that touch in logical coordinates touch in physical coordinates too.
+pub fn to_physical_rect(rect: Rectangle<i32, Logical>, scale: f64) -> Rectangle<i32, Physical> {
+    let top_left = to_physical_point(rect.loc, scale);
+    let bottom_right = to_physical_point(rect.loc + rect.size.to_point(), scale);
+    Rectangle::from_extremities(top_left, bottom_right)
+}
+
+/// Tell `surface` and its subsurfaces which scale and transform to render at
+///
+/// Clients bound to wp_fractional_scale_v1 get the exact `scale`, everyone else gets the
+/// integer scale above it as the preferred buffer scale.
+pub fn send_surface_scale(surface: &WlSurface, scale: f64, transform: Transform) {
+    with_surface_tree_downward(
+        surface,
+        (),
+        |_, _, _| TraversalAction::DoChildren(()),
+        |surface, states, _| {
+            send_surface_state(surface, states, integer_scale(scale), transform);
+            with_fractional_scale(states, |fractional| {
+                fractional.set_preferred_scale(scale);
+            });
+        },
+        |_, _, _| true,
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rect(x: i32, y: i32, w: i32, h: i32) -> Rectangle<i32, Logical> {
+        Rectangle::new((x, y).into(), (w, h).into())
+    }
+
+    #[test]
+    fn scales_snap_to_protocol_steps() {
+        assert_eq!(snap_scale(1.25), 1.25);
+        assert_eq!(snap_scale(1.5), 1.5);
+        assert_eq!(snap_scale(1.333), 160.0 / 120.0);
+        assert_eq!(snap_scale(0.0), MIN_SCALE);
+        assert_eq!(snap_scale(f64::NAN), 1.0);
+    }
+
+    #[test]
+    fn integer_scale_rounds_up() {
+        assert_eq!(integer_scale(1.0), 1);
+        assert_eq!(integer_scale(1.25), 2);
+        assert_eq!(integer_scale(1.5), 2);
+        assert_eq!(integer_scale(2.0), 2);
+        assert_eq!(integer_scale(0.5), 1);
+    }
+
+    #[test]
+    fn touching_rectangles_share_their_edge() {
+        for scale in [1.25, 1.5, 1.75, 160.0 / 120.0] {
+            for x in 0..40 {
+                let left = to_physical_rect(rect(0, 0, x, 10), scale);
+                let right = to_physical_rect(rect(x, 0, 7, 10), scale);
+                assert_eq!(left.loc.x + left.size.w, right.loc.x, "at {x} x {scale}");
+            }
+        }
+    }
+
+    #[test]
+    fn integer_scales_are_exact() {
+        assert_eq!(
+            to_physical_rect(rect(3, 5, 7, 11), 2.0),
+            Rectangle::new((6, 10).into(), (14, 22).into())
+        );
+    }
+}
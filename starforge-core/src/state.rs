@@ -1,24 +1,67 @@
 //! Core state management for the Starforge compositor.
 
-use crate::StarforgeResult;
+use crate::animation::{AnimationState, MonotonicClock};
+use crate::cursor::{CursorState, resolve_cursor_theme};
+use crate::decoration::{DecorationState, is_minimized};
+use crate::frame_scheduler::{
+    FrameContent, FrameSchedulingState, discard_surface_feedback, send_frame_callbacks,
+    take_presentation_feedback,
+};
+use crate::handlers::{DmabufGlobalState, DmabufImporter, window_root};
+use crate::protocols::color_management::ColorManagementState;
+use crate::protocols::color_representation::ColorRepresentationState;
+use crate::protocols::commit_timing::CommitTimingState;
+use crate::protocols::fifo::{FifoState, clear_barriers};
+use crate::protocols::gamma_control::GammaControlState;
+use crate::protocols::image_capture::ImageCopyCaptureState;
+use crate::protocols::tearing_control::{
+    PresentationHint, TearingControlState, surface_presentation_hint,
+};
+use crate::scale::{send_surface_scale, snap_scale};
+#[cfg(feature = "xwayland")]
+use crate::xwayland::XWaylandState;
+use crate::{StarforgeError, StarforgeResult};
 use smithay::{
-    input::{Seat, SeatState},
+    backend::{allocator::Format, drm::DrmDeviceFd, renderer::utils::RendererSurfaceStateUserData},
+    input::{Seat, SeatState, pointer::CursorImageStatus},
+    output::{Output, Scale},
     reexports::{
         calloop::{EventLoop, LoopSignal},
+        rustix::fs::Dev,
+        wayland_protocols::wp::linux_dmabuf::zv1::server::zwp_linux_dmabuf_feedback_v1::TrancheFlags,
+        wayland_protocols::{
+            wp::presentation_time::server::wp_presentation_feedback,
+            xdg::shell::server::xdg_toplevel,
+        },
+        wayland_protocols_misc::server_decoration::server::org_kde_kwin_server_decoration_manager,
         wayland_server::{
-            Display, DisplayHandle,
+            Client, Display, DisplayHandle, Resource,
             backend::{ClientData, ClientId, DisconnectReason},
+            protocol::wl_surface::WlSurface,
         },
     },
+    utils::{ClockSource, Logical, Monotonic, Rectangle, Time, Transform},
     wayland::{
-        compositor::{CompositorClientState, CompositorState},
+        compositor::{CompositorClientState, CompositorHandler, CompositorState, with_states},
+        cursor_shape::CursorShapeManagerState,
+        dmabuf::{DmabufFeedbackBuilder, DmabufState},
+        drm_syncobj::{DrmSyncobjState, supports_syncobj_eventfd},
+        foreign_toplevel_list::ForeignToplevelListState,
+        fractional_scale::FractionalScaleManagerState,
         output::OutputManagerState,
-        selection::data_device::DataDeviceState,
-        shell::xdg::XdgShellState,
+        presentation::{PresentationState, Refresh},
+        selection::{data_device::DataDeviceState, primary_selection::PrimarySelectionState},
+        shell::{
+            kde::decoration::KdeDecorationState,
+            xdg::{
+                PopupSurface, XdgShellState, XdgToplevelSurfaceData, decoration::XdgDecorationState,
+            },
+        },
         shm::ShmState,
+        viewporter::ViewporterState,
     },
 };
-use std::sync::Arc;
+use std::time::Duration;
 
 /// The core state of a Starforge compositor.
 ///
@@ -34,26 +77,98 @@ pub struct StarforgeState {
     // Smithay state
     pub compositor_state: CompositorState,
     pub data_device_state: DataDeviceState,
+    pub primary_selection_state: PrimarySelectionState,
     pub output_manager_state: OutputManagerState,
+    /// Outputs added by the backend, the first one is where new windows appear
+    pub outputs: Vec<Output>,
     pub xdg_shell_state: XdgShellState,
+    pub xdg_decoration_state: XdgDecorationState,
+    pub kde_decoration_state: KdeDecorationState,
+    /// Popups of every toplevel, in the order they were created
+    pub popups: Vec<PopupSurface>,
     pub shm_state: ShmState,
     pub seat_state: SeatState<Self>,
+    /// The only seat, with a keyboard and a pointer
+    pub seat: Seat<Self>,
+    pub cursor_shape_manager_state: CursorShapeManagerState,
+    pub foreign_toplevel_list_state: ForeignToplevelListState,
+    pub viewporter_state: ViewporterState,
+    pub fractional_scale_manager_state: FractionalScaleManagerState,
+    pub presentation_state: PresentationState,
+    /// Explicit sync, only available once a backend provides a DRM device
+    pub drm_syncobj_state: Option<DrmSyncobjState>,
+    pub dmabuf_state: DmabufState,
+    /// The linux-dmabuf global, only available once a renderer can import dma-bufs
+    pub dmabuf: Option<DmabufGlobalState>,
+
+    // Starforge protocol state
+    pub color_management_state: ColorManagementState,
+    pub color_representation_state: ColorRepresentationState,
+    pub commit_timing_state: CommitTimingState,
+    pub fifo_state: FifoState,
+    pub gamma_control_state: GammaControlState,
+    pub image_copy_capture_state: ImageCopyCaptureState,
+    pub tearing_control_state: TearingControlState,
+
+    /// Xwayland and the X11 windows it shows
+    #[cfg(feature = "xwayland")]
+    pub xwayland_state: XWaylandState,
+
+    /// Image of the pointer cursor
+    pub cursor_state: CursorState,
+
+    /// Decoration policy and title bar interaction
+    pub decoration_state: DecorationState,
+
+    // Window and workspace transitions
+    pub animation_state: AnimationState,
+
+    /// When each output renders its next frame
+    pub frame_scheduling_state: FrameSchedulingState,
 }
 
 impl StarforgeState {
     /// Create a new Starforge state
     pub fn new(event_loop: &EventLoop<Self>) -> StarforgeResult<Self> {
         let display: Display<Self> = Display::new()?;
+        Ok(Self::with_display(&display, event_loop))
+    }
+
+    /// Create a new Starforge state serving the clients of `display`
+    pub fn with_display(display: &Display<Self>, event_loop: &EventLoop<Self>) -> Self {
         let dh = display.handle();
 
         let loop_signal = event_loop.get_signal();
 
         let compositor_state = CompositorState::new::<Self>(&dh);
         let data_device_state = DataDeviceState::new::<Self>(&dh);
+        let primary_selection_state = PrimarySelectionState::new::<Self>(&dh);
         let output_manager_state = OutputManagerState::new_with_xdg_output::<Self>(&dh);
         let xdg_shell_state = XdgShellState::new::<Self>(&dh);
+        let xdg_decoration_state = XdgDecorationState::new::<Self>(&dh);
+        // Clients are told the mode the policy settles on for each window right after
+        let kde_decoration_state = KdeDecorationState::new::<Self>(
+            &dh,
+            org_kde_kwin_server_decoration_manager::Mode::Server,
+        );
         let shm_state = ShmState::new::<Self>(&dh, vec![]);
         let mut seat_state = SeatState::new();
+        let cursor_shape_manager_state = CursorShapeManagerState::new::<Self>(&dh);
+        let foreign_toplevel_list_state = ForeignToplevelListState::new::<Self>(&dh);
+        let viewporter_state = ViewporterState::new::<Self>(&dh);
+        let fractional_scale_manager_state = FractionalScaleManagerState::new::<Self>(&dh);
+        // Presentation times are on the clock frames are scheduled with
+        let presentation_state = PresentationState::new::<Self>(&dh, Monotonic::ID as u32);
+        let color_management_state = ColorManagementState::new(&dh);
+        let color_representation_state = ColorRepresentationState::new(&dh);
+        let commit_timing_state = CommitTimingState::new(&dh);
+        let fifo_state = FifoState::new(&dh);
+        let gamma_control_state = GammaControlState::new(&dh);
+        let image_copy_capture_state = ImageCopyCaptureState::new(&dh);
+        let tearing_control_state = TearingControlState::new(&dh);
+        #[cfg(feature = "xwayland")]
+        let xwayland_state = XWaylandState::new(&dh);
+        let (cursor_theme, cursor_size) = resolve_cursor_theme(None, None);
 
         // A seat is a group of keyboards, pointer and touch devices.
         // A seat typically has a pointer and maintains a keyboard focus and a pointer focus.
@@ -67,24 +182,389 @@ impl StarforgeState {
         // Here we assume that there is always pointer plugged in
         seat.add_pointer();
 
-        Ok(Self {
+        Self {
             dh,
             loop_signal,
             compositor_state,
             data_device_state,
+            primary_selection_state,
             output_manager_state,
+            outputs: Vec::new(),
             xdg_shell_state,
+            xdg_decoration_state,
+            kde_decoration_state,
+            popups: Vec::new(),
             shm_state,
             seat_state,
+            seat,
+            cursor_shape_manager_state,
+            foreign_toplevel_list_state,
+            viewporter_state,
+            fractional_scale_manager_state,
+            presentation_state,
+            drm_syncobj_state: None,
+            dmabuf_state: DmabufState::new(),
+            dmabuf: None,
+            color_management_state,
+            color_representation_state,
+            commit_timing_state,
+            fifo_state,
+            gamma_control_state,
+            image_copy_capture_state,
+            tearing_control_state,
+            #[cfg(feature = "xwayland")]
+            xwayland_state,
+            cursor_state: CursorState::new(&cursor_theme, cursor_size),
+            decoration_state: DecorationState::default(),
+            animation_state: AnimationState::new(MonotonicClock::new()),
+            frame_scheduling_state: FrameSchedulingState::new(MonotonicClock::new()),
+        }
+    }
+
+    /// Make an output known to the compositor
+    ///
+    /// Its global has to be created by the backend.
+    pub fn add_output(&mut self, output: Output) {
+        if !self.outputs.contains(&output) {
+            self.frame_scheduling_state.add_output(&output);
+            self.outputs.push(output);
+            #[cfg(feature = "xwayland")]
+            self.update_xwayland_scale();
+        }
+    }
+
+    /// The output windows are shown on
+    ///
+    /// Windows aren't placed on outputs yet, they are all shown on the first one. Code
+    /// that needs to know where a window is goes through here or [`Self::shows_windows`].
+    pub fn window_output(&self) -> Option<&Output> {
+        self.outputs.first()
+    }
+
+    /// Whether windows are shown on `output`, see [`Self::window_output`]
+    pub fn shows_windows(&self, output: &Output) -> bool {
+        self.window_output() == Some(output)
+    }
+
+    /// Root surfaces of the windows in the window list: xdg toplevels, then X11 windows
+    pub fn window_surfaces(&self) -> Vec<WlSurface> {
+        let windows = self
+            .xdg_shell_state
+            .toplevel_surfaces()
+            .iter()
+            .map(|toplevel| toplevel.wl_surface());
+        #[cfg(feature = "xwayland")]
+        let windows = windows.chain(self.xwayland_state.toplevel_surfaces());
+        windows.cloned().collect()
+    }
+
+    /// Surfaces drawn on `output` with their subsurfaces: windows, popups and a client
+    /// cursor
+    pub fn visible_surfaces(&self, output: &Output) -> Vec<WlSurface> {
+        if !self.shows_windows(output) {
+            return Vec::new();
+        }
+        let mut surfaces = self.window_surfaces();
+        surfaces.extend(self.popups.iter().map(|popup| popup.wl_surface().clone()));
+        #[cfg(feature = "xwayland")]
+        surfaces.extend(self.xwayland_state.override_redirect_surfaces());
+        if let CursorImageStatus::Surface(surface) = self.cursor_state.status() {
+            surfaces.push(surface.clone());
+        }
+        surfaces.retain(|surface| is_mapped(surface) && !is_minimized(&window_root(surface)));
+        surfaces
+    }
+
+    /// The window whose frames may tear on `output`
+    ///
+    /// That is only the case while it is the one window shown there, fullscreen, and
+    /// hinting async presentation. Whether tearing is allowed at all is up to the backend.
+    pub fn tearing_surface(&self, output: &Output) -> Option<WlSurface> {
+        if !self.shows_windows(output) || self.animation_state.closing_windows().next().is_some() {
+            return None;
+        }
+        let mut windows = self.window_surfaces();
+        windows.retain(is_mapped);
+        let [window] = windows.as_slice() else {
+            return None;
+        };
+        (self.is_fullscreen(window) && surface_presentation_hint(window) == PresentationHint::Async)
+            .then(|| window.clone())
+    }
+
+    /// Where the focused window is on `output`, for the magnifier to follow
+    ///
+    /// That is the window with keyboard focus, or the topmost one while none has it.
+    pub fn focus_geometry(&self, output: &Output) -> Option<Rectangle<i32, Logical>> {
+        if !self.shows_windows(output) {
+            return None;
+        }
+        let focus = self
+            .seat
+            .get_keyboard()
+            .and_then(|keyboard| keyboard.current_focus())
+            .map(|surface| window_root(&surface));
+        let mut windows = self.window_surfaces();
+        windows.retain(|window| is_mapped(window) && !is_minimized(window));
+        let window = match focus {
+            Some(focus) => windows.into_iter().find(|window| *window == focus)?,
+            None => windows.pop()?,
+        };
+        Some(self.window_geometry(&window))
+    }
+
+    /// Whether the window of root surface `window` is fullscreen
+    pub(crate) fn is_fullscreen(&self, window: &WlSurface) -> bool {
+        #[cfg(feature = "xwayland")]
+        if let Some(window) = self.xwayland_state.window(window) {
+            return window.is_fullscreen();
+        }
+        with_states(window, |states| {
+            states
+                .data_map
+                .get::<XdgToplevelSurfaceData>()
+                .is_some_and(|data| {
+                    data.lock()
+                        .unwrap()
+                        .current
+                        .states
+                        .contains(xdg_toplevel::State::Fullscreen)
+                })
         })
     }
 
+    /// Outputs `surface` is drawn on, as part of a window, popup or cursor
+    pub fn outputs_showing(&self, surface: &WlSurface) -> Vec<Output> {
+        let root = window_root(surface);
+        self.outputs
+            .iter()
+            .filter(|output| self.visible_surfaces(output).contains(&root))
+            .cloned()
+            .collect()
+    }
+
+    /// Schedule a frame on the outputs showing `surface`, after it committed new content
+    pub fn damage_surface(&mut self, surface: &WlSurface) {
+        let outputs = self.outputs_showing(surface);
+        if outputs.is_empty() {
+            discard_surface_feedback(surface);
+        }
+        for output in outputs {
+            self.frame_scheduling_state.damage(&output);
+        }
+    }
+
+    /// Schedule a frame on every output, e.g. after a window closed
+    pub fn damage_all_outputs(&mut self) {
+        for output in &self.outputs {
+            self.frame_scheduling_state.damage(output);
+        }
+    }
+
+    /// Record that the backend submitted a frame of `output` after rendering for
+    /// `render_time`
+    ///
+    /// The surfaces shown now are the ones owed frame callbacks and presentation feedback
+    /// once it's presented.
+    pub fn frame_submitted(&mut self, output: &Output, render_time: Duration) {
+        let surfaces = self.visible_surfaces(output);
+        let feedback = surfaces
+            .iter()
+            .flat_map(take_presentation_feedback)
+            .collect();
+        self.frame_scheduling_state.frame_submitted(
+            output,
+            render_time,
+            FrameContent { surfaces, feedback },
+        );
+    }
+
+    /// The backend starts rendering a frame to be presented at `presentation`
+    ///
+    /// Applies the commits held back until then, so the frame shows them.
+    pub fn frame_started(&mut self, presentation: Duration) {
+        let clients = self.commit_timing_state.release_due(presentation);
+        self.unblock_clients(clients);
+    }
+
+    /// The last frame submitted for `output` reached the screen at `time`, at vblank
+    /// `sequence`
+    ///
+    /// Sends the presentation feedback and frame callbacks of the surfaces drawn in it.
+    pub fn frame_presented(
+        &mut self,
+        output: &Output,
+        time: Duration,
+        refresh: Option<Duration>,
+        sequence: u64,
+        flags: wp_presentation_feedback::Kind,
+    ) {
+        let content = self.frame_scheduling_state.presented(output, time, refresh);
+        let refresh = self
+            .frame_scheduling_state
+            .scheduler(output)
+            .map(|scheduler| scheduler.refresh())
+            .filter(|refresh| !refresh.is_zero())
+            .map_or(Refresh::Unknown, Refresh::Fixed);
+        for callback in content.feedback {
+            callback.presented(
+                output,
+                Time::<Monotonic>::from(time),
+                refresh,
+                sequence,
+                flags,
+            );
+        }
+        self.frame_done(output, content.surfaces, time);
+    }
+
+    /// The last frame submitted for `output` was dropped without being shown
+    ///
+    /// Its presentation feedback is discarded, but clients still get their frame
+    /// callbacks so they keep drawing.
+    pub fn frame_discarded(&mut self, output: &Output) {
+        let content = self.frame_scheduling_state.discarded(output);
+        for callback in content.feedback {
+            callback.discarded();
+        }
+        let now = self.frame_scheduling_state.now();
+        self.frame_done(output, content.surfaces, now);
+    }
+
+    /// Let the clients drawn in a frame of `output` that left the pipeline at `time` go on
+    ///
+    /// Sends frame callbacks, clears fifo barriers of the content the frame latched and
+    /// schedules a frame for commits still waiting for a target time.
+    fn frame_done(&mut self, output: &Output, surfaces: Vec<WlSurface>, time: Duration) {
+        let mut clients: Vec<Client> = Vec::new();
+        for surface in surfaces.iter().filter(|surface| surface.is_alive()) {
+            send_frame_callbacks(surface, time);
+            if clear_barriers(surface)
+                && let Some(client) = surface.client()
+                && !clients.iter().any(|known| known.id() == client.id())
+            {
+                clients.push(client);
+            }
+        }
+        self.unblock_clients(clients);
+        if let Some(target) = self.commit_timing_state.next_target() {
+            self.frame_scheduling_state.schedule_for(output, target);
+        }
+    }
+
+    /// Apply the commits of `clients` whose blockers were released
+    fn unblock_clients(&mut self, clients: Vec<Client>) {
+        let dh = self.dh.clone();
+        for client in clients {
+            self.client_compositor_state(&client)
+                .blocker_cleared(self, &dh);
+        }
+    }
+
+    /// Change the scale of an output and tell the surfaces on it
+    ///
+    /// The scale is rounded to the 1/120 steps clients can be told about.
+    pub fn set_output_scale(&mut self, output: &Output, scale: f64) {
+        let scale = snap_scale(scale);
+        output.change_current_state(None, None, Some(Scale::Fractional(scale)), None);
+        if !self.shows_windows(output) {
+            return;
+        }
+        let transform = output.current_transform();
+        for toplevel in self.xdg_shell_state.toplevel_surfaces() {
+            send_surface_scale(toplevel.wl_surface(), scale, transform);
+        }
+        #[cfg(feature = "xwayland")]
+        self.update_xwayland_scale();
+    }
+
+    /// Scale and transform `surface` should render at, those of the output it's shown on
+    pub fn surface_scale(&self, _surface: &WlSurface) -> (f64, Transform) {
+        self.window_output()
+            .map(|output| {
+                (
+                    output.current_scale().fractional_scale(),
+                    output.current_transform(),
+                )
+            })
+            .unwrap_or((1.0, Transform::Normal))
+    }
+
+    /// Advertise linux-drm-syncobj-v1, importing client timelines on `import_device`
+    ///
+    /// Does nothing if the device can't wait on timeline points, clients keep using
+    /// implicit sync then. Returns whether the protocol is available.
+    pub fn enable_drm_syncobj(&mut self, import_device: DrmDeviceFd) -> bool {
+        if self.drm_syncobj_state.is_none() {
+            if !supports_syncobj_eventfd(&import_device) {
+                tracing::warn!("DRM device lacks syncobj eventfd support, explicit sync disabled.");
+                return false;
+            }
+            self.drm_syncobj_state = Some(DrmSyncobjState::new::<Self>(&self.dh, import_device));
+        }
+        true
+    }
+
+    /// Advertise linux-dmabuf, importing client buffers with `importer`
+    ///
+    /// `formats` are the formats and modifiers the renderer on `main_device` can sample.
+    /// Formats in `scanout` can be put on a plane of its device directly; surface feedback
+    /// prefers them, so fullscreen clients can allocate buffers that skip composition.
+    pub fn enable_dmabuf(
+        &mut self,
+        main_device: Dev,
+        formats: Vec<Format>,
+        scanout: Option<(Dev, Vec<Format>)>,
+        importer: DmabufImporter,
+    ) -> StarforgeResult<()> {
+        if self.dmabuf.is_some() {
+            return Ok(());
+        }
+        let default_feedback = DmabufFeedbackBuilder::new(main_device, formats.clone())
+            .build()
+            .map_err(|e| StarforgeError::RendererError(e.to_string()))?;
+        let surface_feedback = match scanout {
+            Some((scanout_device, scanout_formats)) => {
+                DmabufFeedbackBuilder::new(main_device, formats.clone())
+                    .add_preference_tranche(
+                        scanout_device,
+                        Some(TrancheFlags::Scanout),
+                        scanout_formats,
+                    )
+                    .build()
+                    .map_err(|e| StarforgeError::RendererError(e.to_string()))?
+            }
+            None => default_feedback.clone(),
+        };
+
+        let global = self
+            .dmabuf_state
+            .create_global_with_default_feedback::<Self>(&self.dh, &default_feedback);
+        self.dmabuf = Some(DmabufGlobalState {
+            global,
+            formats: formats.into_iter().collect(),
+            surface_feedback,
+            importer,
+        });
+        Ok(())
+    }
+
     /// Initialize the event loop
     pub fn init_event_loop<Data>(&mut self, _event_loop: &EventLoop<Data>) -> StarforgeResult<()> {
         Ok(())
     }
 }
 
+/// Whether `surface` has a buffer to draw
+fn is_mapped(surface: &WlSurface) -> bool {
+    with_states(surface, |states| {
+        states
+            .data_map
+            .get::<RendererSurfaceStateUserData>()
+            .is_some_and(|data| data.lock().unwrap().view().is_some())
+    })
+}
+
 #[derive(Default)]
 pub struct StarforgeClientState {
     pub compositor_state: CompositorClientState,
@@ -95,3 +575,69 @@ impl ClientData for StarforgeClientState {
 
     fn disconnected(&self, _client_id: ClientId, _reason: DisconnectReason) {}
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::protocols::test_client::TestClient;
+    use smithay::reexports::{
+        wayland_protocols::wp::tearing_control::v1::server::{
+            wp_tearing_control_manager_v1, wp_tearing_control_v1,
+        },
+        wayland_server::protocol::wl_surface::WlSurface,
+    };
+    use smithay::utils::Rectangle;
+
+    /// Hint async presentation for the surface of id `surface`
+    fn hint_async(client: &mut TestClient, surface: u32) {
+        let manager = client.bind("wp_tearing_control_manager_v1", 1);
+        let control = client.new_id();
+        client.request(
+            manager,
+            wp_tearing_control_manager_v1::REQ_GET_TEARING_CONTROL_OPCODE,
+            &[control, surface],
+        );
+        client.request(
+            control,
+            wp_tearing_control_v1::REQ_SET_PRESENTATION_HINT_OPCODE,
+            &[wp_tearing_control_v1::PresentationHint::Async as u32],
+        );
+        client.commit(surface);
+    }
+
+    #[test]
+    fn fullscreen_window_hinting_async_tears() {
+        let mut client = TestClient::connect();
+        let output = client.add_output(640, 480);
+        let surface = client.map_fullscreen_toplevel(640, 480);
+        assert_eq!(client.state.tearing_surface(&output), None);
+
+        hint_async(&mut client, surface);
+        assert_eq!(
+            client.state.tearing_surface(&output),
+            Some(client.object::<WlSurface>(surface))
+        );
+    }
+
+    #[test]
+    fn no_tearing_with_a_second_window() {
+        let mut client = TestClient::connect();
+        let output = client.add_output(640, 480);
+        let surface = client.map_fullscreen_toplevel(640, 480);
+        hint_async(&mut client, surface);
+        client.map_fullscreen_toplevel(640, 480);
+        assert_eq!(client.state.tearing_surface(&output), None);
+    }
+
+    #[test]
+    fn the_topmost_window_has_focus() {
+        let mut client = TestClient::connect();
+        let output = client.add_output(640, 480);
+        assert_eq!(client.state.focus_geometry(&output), None);
+
+        client.map_fullscreen_toplevel(640, 480);
+        assert_eq!(
+            client.state.focus_geometry(&output),
+            Some(Rectangle::from_size((640, 480).into()))
+        );
+    }
+}
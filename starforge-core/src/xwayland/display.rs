@@ -0,0 +1,197 @@
+//! Claiming an X11 display and spawning Xwayland on it
+//!
+//! The compositor takes the display's lock file and listens on its sockets itself, so
+//! `DISPLAY` is usable before Xwayland runs. The listening sockets are handed to
+//! Xwayland with `-listenfd` when it is spawned, and it accepts whoever was already
+//! waiting on them.
+
+use smithay::reexports::rustix::io::{FdFlags, fcntl_setfd};
+use std::{
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    os::{
+        fd::{AsRawFd, BorrowedFd, RawFd},
+        unix::{
+            net::{UnixListener, UnixStream},
+            process::CommandExt,
+        },
+    },
+    path::PathBuf,
+    process::{Child, Command, Stdio},
+};
+use tracing::debug;
+
+/// Directory holding the X11 sockets
+const SOCKET_DIR: &str = "/tmp/.X11-unix";
+
+/// Highest display number tried when looking for a free one
+const MAX_DISPLAY: u32 = 32;
+
+/// An X11 display claimed through its lock file, with the sockets clients connect to
+pub struct X11Display {
+    number: u32,
+    lock_path: PathBuf,
+    socket_path: PathBuf,
+    listeners: Vec<UnixListener>,
+}
+
+impl X11Display {
+    /// Claim the first free display number
+    ///
+    /// Displays with a lock file are taken, even if the server that created it is gone.
+    pub fn claim() -> io::Result<Self> {
+        fs::create_dir_all(SOCKET_DIR)?;
+        for number in 0..=MAX_DISPLAY {
+            match Self::claim_number(number) {
+                Ok(display) => return Ok(display),
+                Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {}
+                Err(err) if err.kind() == io::ErrorKind::AddrInUse => {}
+                Err(err) => return Err(err),
+            }
+        }
+        Err(io::Error::new(
+            io::ErrorKind::AddrInUse,
+            "no free X11 display number",
+        ))
+    }
+
+    fn claim_number(number: u32) -> io::Result<Self> {
+        let lock_path = PathBuf::from(format!("/tmp/.X{number}-lock"));
+        let mut lock = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&lock_path)?;
+        // Dropping the display removes the lock again if anything below fails
+        let mut display = Self {
+            number,
+            lock_path,
+            socket_path: PathBuf::from(format!("{SOCKET_DIR}/X{number}")),
+            listeners: Vec::new(),
+        };
+        // The format X servers use, the pid right-aligned in ten columns
+        writeln!(lock, "{:>10}", std::process::id())?;
+
+        // The lock is ours, so a socket left behind is stale
+        let _ = fs::remove_file(&display.socket_path);
+        display
+            .listeners
+            .push(UnixListener::bind(&display.socket_path)?);
+        #[cfg(target_os = "linux")]
+        {
+            use std::os::linux::net::SocketAddrExt;
+            let name = format!("{SOCKET_DIR}/X{number}");
+            let address = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+            display.listeners.push(UnixListener::bind_addr(&address)?);
+        }
+        debug!("Claimed X11 display :{number}");
+        Ok(display)
+    }
+
+    /// The display number, as in `DISPLAY=:<number>`
+    pub fn number(&self) -> u32 {
+        self.number
+    }
+
+    /// The sockets X11 clients connect to
+    pub fn listeners(&self) -> &[UnixListener] {
+        &self.listeners
+    }
+}
+
+impl Drop for X11Display {
+    fn drop(&mut self) {
+        if !self.listeners.is_empty() {
+            let _ = fs::remove_file(&self.socket_path);
+        }
+        let _ = fs::remove_file(&self.lock_path);
+    }
+}
+
+/// A spawned Xwayland and the compositor's ends of its connections
+pub(crate) struct XWaylandProcess {
+    pub child: Child,
+    /// Xwayland's Wayland connection
+    pub wayland: UnixStream,
+    /// Xwayland's connection to the window manager
+    pub wm: UnixStream,
+    /// Where Xwayland writes the display number once it's ready
+    pub ready: UnixStream,
+}
+
+/// Spawn Xwayland serving `display`, as a rootless server with a window manager
+pub(crate) fn spawn_xwayland(display: &X11Display) -> io::Result<XWaylandProcess> {
+    let (wayland, wayland_child) = UnixStream::pair()?;
+    let (wm, wm_child) = UnixStream::pair()?;
+    let (ready, ready_child) = UnixStream::pair()?;
+
+    let mut command = Command::new("Xwayland");
+    command
+        .arg(format!(":{}", display.number()))
+        .arg("-rootless")
+        .args(["-wm", &wm_child.as_raw_fd().to_string()])
+        .args(["-displayfd", &ready_child.as_raw_fd().to_string()])
+        .env("WAYLAND_SOCKET", wayland_child.as_raw_fd().to_string())
+        .env_remove("DISPLAY")
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+    for listener in display.listeners() {
+        command.args(["-listenfd", &listener.as_raw_fd().to_string()]);
+    }
+
+    // Everything is close-on-exec, except what Xwayland is told about above
+    let mut inherited: Vec<RawFd> = display
+        .listeners()
+        .iter()
+        .map(|listener| listener.as_raw_fd())
+        .collect();
+    inherited.extend([&wayland_child, &wm_child, &ready_child].map(AsRawFd::as_raw_fd));
+    unsafe {
+        command.pre_exec(move || {
+            for &fd in &inherited {
+                fcntl_setfd(BorrowedFd::borrow_raw(fd), FdFlags::empty())?;
+            }
+            Ok(())
+        });
+    }
+    let child = command.spawn()?;
+    let number = display.number();
+    debug!("Spawned Xwayland on :{number}");
+
+    Ok(XWaylandProcess {
+        child,
+        wayland,
+        wm,
+        ready,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn claimed_displays_are_locked_until_dropped() {
+        let display = X11Display::claim().unwrap();
+        let lock = fs::read_to_string(&display.lock_path).unwrap();
+        assert_eq!(lock, format!("{:>10}\n", std::process::id()));
+        assert!(display.socket_path.exists());
+
+        // The display is taken as long as the lock is there
+        let other = X11Display::claim().unwrap();
+        assert_ne!(other.number(), display.number());
+
+        let (lock_path, socket_path) = (display.lock_path.clone(), display.socket_path.clone());
+        drop(display);
+        assert!(!lock_path.exists());
+        assert!(!socket_path.exists());
+    }
+
+    #[test]
+    fn clients_can_connect_before_xwayland_runs() {
+        let display = X11Display::claim().unwrap();
+        UnixStream::connect(&display.socket_path).unwrap();
+        let (_, address) = display.listeners()[0].accept().unwrap();
+        assert!(address.is_unnamed());
+    }
+}
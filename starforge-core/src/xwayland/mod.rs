@@ -0,0 +1,303 @@
+//! Hosting X11 applications through Xwayland
+//!
+//! Xwayland is started on demand: the compositor claims an X11 display up front and
+//! spawns Xwayland, as a Wayland client of its own, when the first X11 client connects.
+//! Once Xwayland is ready Starforge connects to it as the X11 window manager. If
+//! Xwayland goes away, the next X11 client starts it again. Managed X11 windows join
+//! the xdg toplevels in the window list once Xwayland associates them with a
+//! `wl_surface`; override-redirect windows, menus and tooltips, are shown like popups.
+//! The clipboard and primary selection are bridged both ways.
+//!
+//! X11 has no notion of scale, so the Xwayland client is given the scale of the output
+//! windows are shown on, rounded up as client scales are whole numbers: X11 applications
+//! render at least at the output's resolution instead of being upscaled.
+
+mod display;
+
+pub use display::X11Display;
+
+use crate::state::StarforgeClientState;
+use crate::{StarforgeError, StarforgeResult, StarforgeState};
+use display::spawn_xwayland;
+use smithay::{
+    reexports::{
+        calloop::{Interest, LoopHandle, Mode, PostAction, RegistrationToken, generic::Generic},
+        wayland_server::{Client, DisplayHandle, protocol::wl_surface::WlSurface},
+    },
+    utils::{Logical, Rectangle},
+    wayland::{compositor::CompositorHandler, xwayland_shell::XWaylandShellState},
+    xwayland::{X11Surface, X11Wm},
+};
+use std::{io::Read, os::unix::net::UnixStream, process::Child, sync::Arc};
+use tracing::{info, warn};
+
+/// The Xwayland server and the X11 windows it shows
+pub struct XWaylandState {
+    /// Loop the window manager runs on, set when the display is claimed
+    loop_handle: Option<LoopHandle<'static, StarforgeState>>,
+    /// The display X11 clients connect to, once claimed
+    display: Option<X11Display>,
+    /// Sources watching the display's sockets for a client while Xwayland isn't running
+    listeners: Vec<RegistrationToken>,
+    /// The Xwayland process, while it runs
+    process: Option<Child>,
+    /// The X11 window manager, once Xwayland is ready
+    pub wm: Option<X11Wm>,
+    /// Xwayland's own connection to the compositor
+    pub client: Option<Client>,
+    /// X11 windows, managed and override-redirect, in the order they were created
+    pub windows: Vec<X11Surface>,
+    /// Managed windows in the window list, with the surface they were associated with
+    pub(crate) toplevels: Vec<(X11Surface, WlSurface)>,
+    pub shell_state: XWaylandShellState,
+}
+
+impl XWaylandState {
+    pub fn new(dh: &DisplayHandle) -> Self {
+        Self {
+            loop_handle: None,
+            display: None,
+            listeners: Vec::new(),
+            process: None,
+            wm: None,
+            client: None,
+            windows: Vec::new(),
+            toplevels: Vec::new(),
+            shell_state: XWaylandShellState::new::<StarforgeState>(dh),
+        }
+    }
+
+    /// Loop the window manager runs on, once the display was claimed
+    pub fn loop_handle(&self) -> Option<&LoopHandle<'static, StarforgeState>> {
+        self.loop_handle.as_ref()
+    }
+
+    /// Number of the X11 display clients connect to, as in `DISPLAY=:<number>`
+    pub fn display(&self) -> Option<u32> {
+        self.display.as_ref().map(X11Display::number)
+    }
+
+    /// The managed X11 window whose contents `surface` shows
+    pub fn window(&self, surface: &WlSurface) -> Option<&X11Surface> {
+        self.toplevels
+            .iter()
+            .find(|(_, known)| known == surface)
+            .map(|(window, _)| window)
+    }
+
+    /// Surfaces of the managed X11 windows in the window list
+    pub fn toplevel_surfaces(&self) -> impl Iterator<Item = &WlSurface> {
+        self.toplevels.iter().map(|(_, surface)| surface)
+    }
+
+    /// Surfaces of the mapped override-redirect X11 windows
+    pub fn override_redirect_surfaces(&self) -> impl Iterator<Item = WlSurface> {
+        self.windows
+            .iter()
+            .filter(|window| window.is_override_redirect() && window.is_mapped())
+            .filter_map(X11Surface::wl_surface)
+    }
+
+    /// Where the mapped override-redirect X11 window showing `surface` placed itself
+    pub fn override_redirect_geometry(
+        &self,
+        surface: &WlSurface,
+    ) -> Option<Rectangle<i32, Logical>> {
+        self.windows
+            .iter()
+            .filter(|window| window.is_override_redirect() && window.is_mapped())
+            .find(|window| window.wl_surface().as_ref() == Some(surface))
+            .map(X11Surface::geometry)
+    }
+}
+
+impl StarforgeState {
+    /// Claim an X11 display, Xwayland is started on it when the first X11 client
+    /// connects
+    ///
+    /// Does nothing if a display was claimed already.
+    pub fn listen_xwayland(
+        &mut self,
+        loop_handle: LoopHandle<'static, StarforgeState>,
+    ) -> StarforgeResult<()> {
+        if self.xwayland_state.display.is_some() {
+            return Ok(());
+        }
+        let display =
+            X11Display::claim().map_err(|e| StarforgeError::XWaylandError(e.to_string()))?;
+        for listener in display.listeners() {
+            let listener = listener
+                .try_clone()
+                .map_err(|e| StarforgeError::XWaylandError(e.to_string()))?;
+            // Xwayland accepts the connection itself, so the source stays disabled
+            // while it runs
+            let token = loop_handle
+                .insert_source(
+                    Generic::new(listener, Interest::READ, Mode::Level),
+                    |_, _, state| {
+                        state.start_xwayland();
+                        Ok(PostAction::Disable)
+                    },
+                )
+                .map_err(|e| StarforgeError::XWaylandError(e.to_string()))?;
+            self.xwayland_state.listeners.push(token);
+        }
+        let number = display.number();
+        info!("X11 clients can connect on DISPLAY=:{number}");
+        self.xwayland_state.display = Some(display);
+        self.xwayland_state.loop_handle = Some(loop_handle);
+        Ok(())
+    }
+
+    /// Spawn Xwayland on the claimed display, and become its window manager once it's
+    /// ready
+    ///
+    /// Does nothing if it is running already.
+    fn start_xwayland(&mut self) {
+        if self.xwayland_state.process.is_some() {
+            return;
+        }
+        let (Some(display), Some(loop_handle)) = (
+            self.xwayland_state.display.as_ref(),
+            self.xwayland_state.loop_handle.clone(),
+        ) else {
+            return;
+        };
+        let process = match spawn_xwayland(display) {
+            Ok(process) => process,
+            Err(err) => {
+                warn!("Failed to spawn Xwayland: {err}");
+                return;
+            }
+        };
+        self.xwayland_state.process = Some(process.child);
+        let client = match self
+            .dh
+            .insert_client(process.wayland, Arc::new(StarforgeClientState::default()))
+        {
+            Ok(client) => client,
+            Err(err) => {
+                warn!("Failed to connect Xwayland: {err}");
+                self.xwayland_stopped();
+                return;
+            }
+        };
+        self.xwayland_state.client = Some(client);
+        self.update_xwayland_scale();
+
+        // Xwayland writes its display number once it's ready, and closes the socket
+        // without doing so if it fails
+        let mut wm = Some(process.wm);
+        let mut ready = match process.ready.try_clone() {
+            Ok(ready) => ready,
+            Err(err) => {
+                warn!("Failed to wait for Xwayland: {err}");
+                self.xwayland_stopped();
+                return;
+            }
+        };
+        let inserted = loop_handle.insert_source(
+            Generic::new(process.ready, Interest::READ, Mode::Level),
+            move |_, _, state| {
+                let mut number = [0; 16];
+                match (ready.read(&mut number), wm.take()) {
+                    (Ok(read), Some(wm)) if read > 0 => state.xwayland_ready(wm),
+                    _ => {
+                        warn!("Xwayland exited before it was ready");
+                        state.xwayland_stopped();
+                    }
+                }
+                Ok(PostAction::Remove)
+            },
+        );
+        if let Err(err) = inserted {
+            warn!("Failed to wait for Xwayland: {err}");
+            self.xwayland_stopped();
+        }
+    }
+
+    /// Connect to Xwayland as its window manager
+    fn xwayland_ready(&mut self, x11_socket: UnixStream) {
+        let (Some(loop_handle), Some(client)) = (
+            self.xwayland_state.loop_handle.clone(),
+            self.xwayland_state.client.clone(),
+        ) else {
+            return;
+        };
+        match X11Wm::start_wm(loop_handle, x11_socket, client) {
+            Ok(wm) => {
+                info!("Xwayland ready");
+                self.xwayland_state.wm = Some(wm);
+            }
+            Err(err) => {
+                warn!("Failed to start the X11 window manager: {err}");
+                self.xwayland_stopped();
+            }
+        }
+    }
+
+    /// Forget a gone or failed Xwayland, the next X11 client starts a new one
+    pub(crate) fn xwayland_stopped(&mut self) {
+        if let Some(mut child) = self.xwayland_state.process.take() {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+        self.xwayland_state.wm = None;
+        self.xwayland_state.client = None;
+        if let Some(loop_handle) = &self.xwayland_state.loop_handle {
+            for token in &self.xwayland_state.listeners {
+                if let Err(err) = loop_handle.enable(token) {
+                    warn!("Failed to listen for X11 clients: {err}");
+                }
+            }
+        }
+    }
+
+    /// The scale Xwayland is given, that of the output windows are shown on rounded up
+    pub(crate) fn xwayland_scale(&self) -> u32 {
+        self.window_output().map_or(1, |output| {
+            output.current_scale().fractional_scale().ceil() as u32
+        })
+    }
+
+    /// Give Xwayland the scale of the output windows are shown on
+    pub(crate) fn update_xwayland_scale(&self) {
+        let Some(client) = self.xwayland_state.client.clone() else {
+            return;
+        };
+        self.client_compositor_state(&client)
+            .set_client_scale(self.xwayland_scale());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::protocols::test_client::TestClient;
+    use smithay::reexports::calloop::EventLoop;
+
+    #[test]
+    fn xwayland_gets_the_window_output_scale_rounded_up() {
+        let mut client = TestClient::connect();
+        assert_eq!(client.state.xwayland_scale(), 1);
+
+        let output = client.add_output(640, 480);
+        client.state.set_output_scale(&output, 1.5);
+        assert_eq!(client.state.xwayland_scale(), 2);
+        client.state.set_output_scale(&output, 2.0);
+        assert_eq!(client.state.xwayland_scale(), 2);
+    }
+
+    #[test]
+    fn the_display_is_claimed_once() {
+        let mut client = TestClient::connect();
+        let event_loop = EventLoop::try_new().unwrap();
+        assert_eq!(client.state.xwayland_state.display(), None);
+
+        client.state.listen_xwayland(event_loop.handle()).unwrap();
+        let number = client.state.xwayland_state.display().unwrap();
+        client.state.listen_xwayland(event_loop.handle()).unwrap();
+        assert_eq!(client.state.xwayland_state.display(), Some(number));
+        // Nothing runs until an X11 client connects
+        assert!(client.state.xwayland_state.process.is_none());
+    }
+}
@@ -0,0 +1,26 @@
+#version 450
+
+// Starforge Render - Accessibility Pass
+//
+// Resamples the output through the magnifier's source rectangle and applies the colour
+// filter as an affine matrix. `matrix` holds the columns of the 3x3 matrix with the
+// offset in its fourth column. Matches `AccessibilityPushConstants`.
+
+layout(push_constant) uniform AccessibilityPushConstants {
+    mat4 matrix;
+    vec2 source_offset;
+    vec2 source_scale;
+} params;
+
+layout(set = 0, binding = 0) uniform texture2D source_texture;
+layout(set = 0, binding = 1) uniform sampler source_sampler;
+
+layout(location = 0) in vec2 tex_coord;
+layout(location = 0) out vec4 frag_color;
+
+void main() {
+    vec2 uv = params.source_offset + tex_coord * params.source_scale;
+    vec4 color = texture(sampler2D(source_texture, source_sampler), uv);
+    vec3 filtered = (params.matrix * vec4(color.rgb, 1.0)).rgb;
+    frag_color = vec4(clamp(filtered, 0.0, 1.0), color.a);
+}
@@ -0,0 +1,34 @@
+#version 450
+
+// Starforge Render - Dual-Kawase Upsample
+//
+// Doubles the resolution with a weighted tent of eight taps. `half_pixel` is half a texel
+// of the target, `offset` scales the tap distance. Matches `BlurPushConstants`.
+
+layout(push_constant) uniform BlurPushConstants {
+    vec2 half_pixel;
+    float offset;
+} params;
+
+layout(set = 0, binding = 0) uniform texture2D source_texture;
+layout(set = 0, binding = 1) uniform sampler source_sampler;
+
+layout(location = 0) in vec2 tex_coord;
+layout(location = 0) out vec4 frag_color;
+
+vec4 tap(vec2 uv) {
+    return texture(sampler2D(source_texture, source_sampler), uv);
+}
+
+void main() {
+    vec2 d = params.half_pixel * params.offset;
+    vec4 sum = tap(tex_coord + vec2(-d.x * 2.0, 0.0));
+    sum += tap(tex_coord + vec2(-d.x, d.y)) * 2.0;
+    sum += tap(tex_coord + vec2(0.0, d.y * 2.0));
+    sum += tap(tex_coord + vec2(d.x, d.y)) * 2.0;
+    sum += tap(tex_coord + vec2(d.x * 2.0, 0.0));
+    sum += tap(tex_coord + vec2(d.x, -d.y)) * 2.0;
+    sum += tap(tex_coord + vec2(0.0, -d.y * 2.0));
+    sum += tap(tex_coord + vec2(-d.x, -d.y)) * 2.0;
+    frag_color = sum / 12.0;
+}
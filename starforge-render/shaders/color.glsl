@@ -0,0 +1,173 @@
+// Starforge Render - Color Management
+//
+// Shader side of color.rs. Transfer function identifiers match
+// `TransferFunction::shader_id`, and `ColorTransform` matches `ColorTransformUniform`.
+
+#define TF_LINEAR 0u
+#define TF_SRGB 1u
+#define TF_GAMMA22 2u
+#define TF_PQ 3u
+#define TF_HLG 4u
+
+#define TM_CLIP 0u
+#define TM_REINHARD 1u
+#define TM_BT2390 2u
+#define TM_HABLE 3u
+#define TM_NONE 0xffffffffu
+
+struct ColorTransform {
+    mat3 matrix;
+    vec4 luma;
+    uint input_transfer;
+    uint output_transfer;
+    uint tone_mapping;
+    float source_peak;
+    float target_peak;
+    float gamma;
+    float brightness;
+};
+
+// Per-channel gamma tables, matching `GammaLutUniform`
+#define GAMMA_LUT_SIZE 256
+
+struct GammaLut {
+    vec4 entries[GAMMA_LUT_SIZE];
+};
+
+const float REFERENCE_WHITE_NITS = 203.0;
+const float PQ_MAX_NITS = 10000.0;
+
+const float PQ_M1 = 2610.0 / 16384.0;
+const float PQ_M2 = 2523.0 / 4096.0 * 128.0;
+const float PQ_C1 = 3424.0 / 4096.0;
+const float PQ_C2 = 2413.0 / 4096.0 * 32.0;
+const float PQ_C3 = 2392.0 / 4096.0 * 32.0;
+
+const float HLG_A = 0.17883277;
+const float HLG_B = 0.28466892;
+const float HLG_C = 0.55991073;
+const float HLG_SYSTEM_GAMMA = 1.2;
+const vec3 BT2020_LUMA = vec3(0.2627, 0.6780, 0.0593);
+
+vec3 eotf(uint tf, vec3 encoded) {
+    vec3 e = abs(encoded);
+    vec3 linear = e;
+    if (tf == TF_SRGB) {
+        linear = mix(pow((e + 0.055) / 1.055, vec3(2.4)), e / 12.92, lessThanEqual(e, vec3(0.04045)));
+    } else if (tf == TF_GAMMA22) {
+        linear = pow(e, vec3(2.2));
+    } else if (tf == TF_PQ) {
+        vec3 p = pow(e, vec3(1.0 / PQ_M2));
+        linear = pow(max(p - PQ_C1, 0.0) / (PQ_C2 - PQ_C3 * p), vec3(1.0 / PQ_M1));
+    } else if (tf == TF_HLG) {
+        linear = mix((exp((e - HLG_C) / HLG_A) + HLG_B) / 12.0, e * e / 3.0, lessThanEqual(e, vec3(0.5)));
+    }
+    return sign(encoded) * linear;
+}
+
+vec3 inverse_eotf(uint tf, vec3 linear) {
+    vec3 l = abs(linear);
+    vec3 encoded = l;
+    if (tf == TF_SRGB) {
+        encoded = mix(1.055 * pow(l, vec3(1.0 / 2.4)) - 0.055, l * 12.92, lessThanEqual(l, vec3(0.0031308)));
+    } else if (tf == TF_GAMMA22) {
+        encoded = pow(l, vec3(1.0 / 2.2));
+    } else if (tf == TF_PQ) {
+        vec3 p = pow(l, vec3(PQ_M1));
+        encoded = pow((PQ_C1 + PQ_C2 * p) / (1.0 + PQ_C3 * p), vec3(PQ_M2));
+    } else if (tf == TF_HLG) {
+        encoded = mix(HLG_A * log(max(12.0 * l - HLG_B, 1e-6)) + HLG_C, sqrt(3.0 * l), lessThanEqual(l, vec3(1.0 / 12.0)));
+    }
+    return sign(linear) * encoded;
+}
+
+vec3 hlg_ootf(vec3 rgb) {
+    float y = dot(BT2020_LUMA, rgb);
+    return y > 0.0 ? rgb * pow(y, HLG_SYSTEM_GAMMA - 1.0) : vec3(0.0);
+}
+
+vec3 hlg_inverse_ootf(vec3 rgb) {
+    float y = dot(BT2020_LUMA, rgb);
+    return y > 0.0 ? rgb * pow(y, (1.0 - HLG_SYSTEM_GAMMA) / HLG_SYSTEM_GAMMA) : vec3(0.0);
+}
+
+float pq_from_relative(float l) {
+    return inverse_eotf(TF_PQ, vec3(l * REFERENCE_WHITE_NITS / PQ_MAX_NITS)).x;
+}
+
+float bt2390_eetf(float l, float source_peak, float target_peak) {
+    float source_max = pq_from_relative(source_peak);
+    float e1 = pq_from_relative(l) / source_max;
+    float max_lum = pq_from_relative(target_peak) / source_max;
+    float knee_start = 1.5 * max_lum - 0.5;
+    float e2 = e1;
+    if (e1 >= knee_start) {
+        float t = (e1 - knee_start) / (1.0 - knee_start);
+        float t2 = t * t;
+        float t3 = t2 * t;
+        e2 = (2.0 * t3 - 3.0 * t2 + 1.0) * knee_start
+            + (t3 - 2.0 * t2 + t) * (1.0 - knee_start)
+            + (-2.0 * t3 + 3.0 * t2) * max_lum;
+    }
+    return eotf(TF_PQ, vec3(min(e2, 1.0) * source_max)).x * PQ_MAX_NITS / REFERENCE_WHITE_NITS;
+}
+
+float hable_curve(float x) {
+    const float A = 0.15, B = 0.50, C = 0.10, D = 0.20, E = 0.02, F = 0.30;
+    return ((x * (A * x + C * B) + D * E) / (x * (A * x + B) + D * F)) - E / F;
+}
+
+float tone_map_luminance(uint op, float l, float source_peak, float target_peak) {
+    if (l <= 0.0) {
+        return 0.0;
+    }
+    if (source_peak <= target_peak || op == TM_CLIP) {
+        return min(l, target_peak);
+    }
+    if (op == TM_REINHARD) {
+        float x = l / target_peak;
+        float w = source_peak / target_peak;
+        return min(x * (1.0 + x / (w * w)) / (1.0 + x), 1.0) * target_peak;
+    }
+    if (op == TM_BT2390) {
+        return bt2390_eetf(l, source_peak, target_peak);
+    }
+    return hable_curve(min(l, source_peak)) / hable_curve(source_peak) * target_peak;
+}
+
+vec3 tone_map(ColorTransform t, vec3 rgb) {
+    float l = dot(t.luma.rgb, rgb);
+    if (l <= 0.0) {
+        return vec3(0.0);
+    }
+    return rgb * tone_map_luminance(t.tone_mapping, l, t.source_peak, t.target_peak) / l;
+}
+
+vec3 apply_color_transform(ColorTransform t, vec3 rgb) {
+    vec3 linear = eotf(t.input_transfer, rgb);
+    if (t.input_transfer == TF_HLG) {
+        linear = hlg_ootf(linear);
+    }
+    if (t.tone_mapping != TM_NONE) {
+        linear = tone_map(t, linear);
+    }
+    vec3 converted = t.matrix * linear;
+    if (t.output_transfer == TF_HLG) {
+        converted = hlg_inverse_ootf(converted);
+    }
+    vec3 encoded = inverse_eotf(t.output_transfer, converted);
+    if (t.gamma != 1.0 || t.brightness != 1.0) {
+        encoded = pow(clamp(encoded * t.brightness, 0.0, 1.0), vec3(1.0 / t.gamma));
+    }
+    return encoded;
+}
+
+vec3 apply_gamma_lut(GammaLut lut, vec3 encoded) {
+    vec3 position = clamp(encoded, 0.0, 1.0) * float(GAMMA_LUT_SIZE - 1);
+    ivec3 lower = ivec3(floor(position));
+    ivec3 upper = min(lower + 1, GAMMA_LUT_SIZE - 1);
+    vec3 t = position - vec3(lower);
+    vec3 a = vec3(lut.entries[lower.r].r, lut.entries[lower.g].g, lut.entries[lower.b].b);
+    vec3 b = vec3(lut.entries[upper.r].r, lut.entries[upper.g].g, lut.entries[upper.b].b);
+    return mix(a, b, t);
+}
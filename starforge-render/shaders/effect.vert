@@ -0,0 +1,22 @@
+#version 450
+
+// Starforge Render - Effect Vertex Stage
+//
+// Draws the element rect as a triangle strip of four vertices and hands effect shaders
+// their texture coordinate at location 0.
+
+layout(set = 0, binding = 0) uniform StarforgeUniforms {
+    float time;
+    float opacity;
+    vec2 output_size;
+    vec4 element_rect;
+} starforge;
+
+layout(location = 0) out vec2 tex_coord;
+
+void main() {
+    vec2 corner = vec2(float(gl_VertexIndex & 1), float((gl_VertexIndex >> 1) & 1));
+    vec2 position = starforge.element_rect.xy + corner * starforge.element_rect.zw;
+    tex_coord = corner;
+    gl_Position = vec4(position / starforge.output_size * 2.0 - 1.0, 0.0, 1.0);
+}
@@ -0,0 +1,19 @@
+// Starforge Render - Effect Shader ABI (GLSL)
+//
+// Inserted after the `#version` line of every GLSL effect shader. Must stay in sync with
+// `ShaderUniforms` and the binding constants in shader.rs.
+
+layout(set = 0, binding = 0) uniform StarforgeUniforms {
+    float time;
+    float opacity;
+    vec2 output_size;
+    vec4 element_rect;
+} starforge;
+
+layout(set = 0, binding = 1) uniform texture2D input_texture;
+layout(set = 0, binding = 2) uniform sampler input_sampler;
+layout(set = 0, binding = 3) uniform texture2D backdrop_texture;
+layout(set = 0, binding = 4) uniform sampler backdrop_sampler;
+
+layout(location = 0) in vec2 tex_coord;
+layout(location = 0) out vec4 frag_color;
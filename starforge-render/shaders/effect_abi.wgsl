@@ -0,0 +1,18 @@
+// Starforge Render - Effect Shader ABI (WGSL)
+//
+// Prepended to every WGSL effect shader. Must stay in sync with `ShaderUniforms` and the
+// binding constants in shader.rs. The fragment entry point takes `@location(0) tex_coord`
+// and returns the color at `@location(0)`.
+
+struct StarforgeUniforms {
+    time: f32,
+    opacity: f32,
+    output_size: vec2<f32>,
+    element_rect: vec4<f32>,
+}
+
+@group(0) @binding(0) var<uniform> starforge: StarforgeUniforms;
+@group(0) @binding(1) var input_texture: texture_2d<f32>;
+@group(0) @binding(2) var input_sampler: sampler;
+@group(0) @binding(3) var backdrop_texture: texture_2d<f32>;
+@group(0) @binding(4) var backdrop_sampler: sampler;
@@ -0,0 +1,34 @@
+#version 450
+
+// Starforge Render - Encode Pass
+//
+// Encodes an output's working image for the output with its colour transform, which has
+// the tone mapping and night light folded in, then looks the result up in the gamma
+// tables a client set, if any. Colors are premultiplied, the transform works on straight
+// ones. color.glsl is inserted after the version directive. Matches `EncodeUniform` in
+// composite.rs.
+
+layout(set = 0, binding = 0) uniform texture2D working_texture;
+layout(set = 0, binding = 1) uniform sampler working_sampler;
+
+layout(set = 1, binding = 0) uniform EncodeUniform {
+    ColorTransform transform;
+    GammaLut gamma_lut;
+    uint use_gamma_lut;
+} encode;
+
+layout(location = 0) in vec2 tex_coord;
+layout(location = 0) out vec4 frag_color;
+
+void main() {
+    vec4 color = texture(sampler2D(working_texture, working_sampler), tex_coord);
+    if (color.a <= 0.0) {
+        frag_color = vec4(0.0);
+        return;
+    }
+    vec3 encoded = apply_color_transform(encode.transform, color.rgb / color.a);
+    if (encode.use_gamma_lut != 0u) {
+        encoded = apply_gamma_lut(encode.gamma_lut, encoded);
+    }
+    frag_color = vec4(encoded * color.a, color.a);
+}
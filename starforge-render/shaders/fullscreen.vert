@@ -0,0 +1,12 @@
+#version 450
+
+// Starforge Render - Fullscreen Vertex Stage
+//
+// Covers the render area with a single triangle of three vertices.
+
+layout(location = 0) out vec2 tex_coord;
+
+void main() {
+    tex_coord = vec2(float((gl_VertexIndex << 1) & 2), float(gl_VertexIndex & 2));
+    gl_Position = vec4(tex_coord * 2.0 - 1.0, 0.0, 1.0);
+}
@@ -0,0 +1,93 @@
+#version 450
+
+// Starforge Render - Window Shape
+//
+// Clips a client surface to a rounded rectangle and draws its border and drop shadow,
+// all from the signed distance to the rounded rectangle. Colors are premultiplied, and
+// decoded into the attachment's space with `decode`. Mirrors `rounded_rect_sdf` and
+// `shadow_alpha` in shape.rs. color.glsl is inserted after the version directive.
+
+layout(push_constant) uniform ShapePushConstants {
+    vec4 bounds;
+    vec4 rect;
+    vec4 source;
+    vec4 radii;
+    vec4 border_color;
+    vec4 shadow_color;
+    vec2 output_size;
+    vec2 shadow_offset;
+    float border_width;
+    float shadow_sigma;
+    float shadow_spread;
+    float opacity;
+} shape;
+
+layout(set = 0, binding = 0) uniform texture2D surface_texture;
+layout(set = 0, binding = 1) uniform sampler surface_sampler;
+
+layout(set = 1, binding = 0) uniform DecodeTransform {
+    ColorTransform transform;
+} decode;
+
+layout(location = 0) in vec2 position;
+layout(location = 0) out vec4 frag_color;
+
+// Distance from `p` (relative to the centre) to a rectangle whose corners are rounded by
+// `radii` (top left, top right, bottom right, bottom left). Negative inside.
+float rounded_rect_sdf(vec2 p, vec2 half_size, vec4 radii) {
+    float r = p.x < 0.0 ? (p.y < 0.0 ? radii.x : radii.w) : (p.y < 0.0 ? radii.y : radii.z);
+    vec2 q = abs(p) - half_size + r;
+    return min(max(q.x, q.y), 0.0) + length(max(q, vec2(0.0))) - r;
+}
+
+// Decode a premultiplied color, the transform works on straight colors
+vec4 decode_color(vec4 color) {
+    if (color.a <= 0.0) {
+        return vec4(0.0);
+    }
+    return vec4(apply_color_transform(decode.transform, color.rgb / color.a) * color.a, color.a);
+}
+
+float erf_approx(float x) {
+    float s = sign(x);
+    float a = abs(x);
+    float t = 1.0 + (0.278393 + (0.230389 + 0.078108 * (a * a)) * a) * a;
+    t *= t;
+    return s - s / (t * t);
+}
+
+// Coverage of a Gaussian-blurred edge at signed distance `d`
+float shadow_alpha(float d, float sigma) {
+    if (sigma <= 0.0) {
+        return clamp(0.5 - d, 0.0, 1.0);
+    }
+    return 0.5 - 0.5 * erf_approx(d / (sigma * 1.41421356));
+}
+
+void main() {
+    vec2 half_size = shape.rect.zw * 0.5;
+    vec2 p = position - (shape.rect.xy + half_size);
+
+    float d = rounded_rect_sdf(p, half_size, shape.radii);
+    float content_coverage = clamp(0.5 - d, 0.0, 1.0);
+    // The border grows outwards, so its outer corners are rounded by radius + width
+    float outer_coverage = clamp(0.5 - (d - shape.border_width), 0.0, 1.0);
+
+    // Viewports crop the texture to `source`, which is stretched over the geometry
+    vec2 uv = shape.source.xy + (position - shape.rect.xy) / shape.rect.zw * shape.source.zw;
+    vec4 content = decode_color(texture(sampler2D(surface_texture, surface_sampler), uv));
+    vec4 color = content * content_coverage
+        + decode_color(shape.border_color) * (outer_coverage - content_coverage);
+
+    // Drop shadow of the outer shape, hidden wherever the window itself is drawn
+    float grow = shape.border_width + shape.shadow_spread;
+    float shadow_d = rounded_rect_sdf(
+        p - shape.shadow_offset,
+        half_size + grow,
+        max(shape.radii + grow, vec4(0.0))
+    );
+    float shadow = shadow_alpha(shadow_d, shape.shadow_sigma) * (1.0 - outer_coverage);
+    color += decode_color(shape.shadow_color) * shadow;
+
+    frag_color = color * shape.opacity;
+}
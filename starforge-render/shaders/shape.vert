@@ -0,0 +1,31 @@
+#version 450
+
+// Starforge Render - Window Shape Vertex Stage
+//
+// Covers `bounds` (output pixels) with two triangles of six vertices. Push constants
+// match `ShapePushConstants` in shape.rs.
+
+layout(push_constant) uniform ShapePushConstants {
+    vec4 bounds;
+    vec4 rect;
+    vec4 source;
+    vec4 radii;
+    vec4 border_color;
+    vec4 shadow_color;
+    vec2 output_size;
+    vec2 shadow_offset;
+    float border_width;
+    float shadow_sigma;
+    float shadow_spread;
+    float opacity;
+} shape;
+
+layout(location = 0) out vec2 position;
+
+void main() {
+    int vertex = int(gl_VertexIndex);
+    int index = vertex < 3 ? vertex : 6 - vertex;
+    vec2 corner = vec2(float(index & 1), float((index >> 1) & 1));
+    position = shape.bounds.xy + corner * shape.bounds.zw;
+    gl_Position = vec4(position / shape.output_size * 2.0 - 1.0, 0.0, 1.0);
+}
@@ -0,0 +1,59 @@
+#version 450
+
+// Starforge Render - YUV Conversion
+//
+// Converts the planes of a YUV video buffer into RGB. Luma is read at the pixel's own
+// texel, chroma bilinearly at its position relative to the luma samples. `matrix` holds
+// the columns of the YCbCr to RGB matrix with the offset of the range in its fourth
+// column. Matches `YuvPushConstants`, the layouts match the `LAYOUT_*` constants in yuv.rs.
+
+layout(push_constant) uniform YuvPushConstants {
+    mat4 matrix;
+    vec2 luma_size;
+    vec2 chroma_offset;
+    float value_scale;
+    uint plane_layout;
+} params;
+
+// Y, then CbCr interleaved (NV12, P010)
+const uint LAYOUT_SEMI_PLANAR = 0u;
+// Y, Cb and Cr planes (YUV420)
+const uint LAYOUT_PLANAR = 1u;
+// Y0 Cb Y1 Cr in every texel of plane 0 (YUYV)
+const uint LAYOUT_PACKED = 2u;
+
+layout(set = 0, binding = 0) uniform texture2D plane0;
+layout(set = 0, binding = 1) uniform texture2D plane1;
+layout(set = 0, binding = 2) uniform texture2D plane2;
+layout(set = 0, binding = 3) uniform sampler plane_sampler;
+
+layout(location = 0) in vec2 tex_coord;
+layout(location = 0) out vec4 frag_color;
+
+void main() {
+    vec2 chroma_uv = tex_coord + params.chroma_offset;
+    vec3 ycbcr;
+    if (params.plane_layout == LAYOUT_PACKED) {
+        // Every texel covers two pixels, pick the luma of this one by its parity
+        float pixel = floor(tex_coord.x * params.luma_size.x);
+        float packed_width = ceil(params.luma_size.x * 0.5);
+        vec2 texel_uv = vec2((floor(pixel * 0.5) + 0.5) / packed_width, tex_coord.y);
+        vec4 texel = texture(sampler2D(plane0, plane_sampler), texel_uv);
+        float luma = mod(pixel, 2.0) < 0.5 ? texel.r : texel.b;
+        vec4 chroma = texture(sampler2D(plane0, plane_sampler), chroma_uv);
+        ycbcr = vec3(luma, chroma.g, chroma.a);
+    } else if (params.plane_layout == LAYOUT_PLANAR) {
+        ycbcr = vec3(
+            texture(sampler2D(plane0, plane_sampler), tex_coord).r,
+            texture(sampler2D(plane1, plane_sampler), chroma_uv).r,
+            texture(sampler2D(plane2, plane_sampler), chroma_uv).r
+        );
+    } else {
+        ycbcr = vec3(
+            texture(sampler2D(plane0, plane_sampler), tex_coord).r,
+            texture(sampler2D(plane1, plane_sampler), chroma_uv).rg
+        );
+    }
+    vec3 rgb = (params.matrix * vec4(ycbcr * params.value_scale, 1.0)).rgb;
+    frag_color = vec4(clamp(rgb, 0.0, 1.0), 1.0);
+}
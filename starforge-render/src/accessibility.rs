@@ -0,0 +1,856 @@
+//! Starforge Render - Accessibility
+//!
+//! This module implements the per-output accessibility pass: colour filters for low
+//! vision and colour blindness, and a full-screen magnifier.
+//!
+//! Both run as one full-screen pass after everything else on the output was drawn. The
+//! finished output image is copied aside, then drawn back through the magnifier's source
+//! rectangle with the colour filter applied as an affine matrix. Outputs with neither a
+//! filter nor magnification skip the pass.
+
+use crate::color::Mat3;
+use crate::core::Context;
+use crate::memory::{AllocatedImage, image_barrier};
+use crate::pipeline::{
+    BlendMode, create_graphics_pipeline, create_linear_sampler, create_pipeline_layout,
+    create_texture_set_layout, create_texture_sets, push_constant_bytes, write_texture_set,
+};
+use crate::shader::{compile_internal, create_shader_module};
+use crate::swapchain::OutputId;
+use ash::vk;
+use naga::ShaderStage;
+use smithay::utils::{Physical, Point, Rectangle, Size};
+use starforge_core::StarforgeResult;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+const FULLSCREEN_VERT: &str = include_str!("../shaders/fullscreen.vert");
+const ACCESSIBILITY_FRAG: &str = include_str!("../shaders/accessibility.frag");
+
+/// Zoom below which the magnifier counts as zoomed out
+const ZOOM_EPSILON: f32 = 1e-3;
+
+/// Rec. 709 luma coefficients
+const LUMA: [f32; 3] = [0.2126, 0.7152, 0.0722];
+
+/// Linear RGB to LMS cone responses, as used by Fidaner et al.'s daltonization
+const RGB_TO_LMS: Mat3 = Mat3([
+    [17.8824, 43.5161, 4.11935],
+    [3.45565, 27.1554, 3.86714],
+    [0.0299566, 0.184309, 1.46709],
+]);
+
+/// How much of the colour information a dichromat misses is moved into the green and
+/// blue channels they can still tell apart
+const ERROR_SHIFT: Mat3 = Mat3([[0.0, 0.0, 0.0], [0.7, 1.0, 0.0], [0.7, 0.0, 1.0]]);
+
+/// Kinds of dichromacy that daltonization corrects for
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ColorBlindness {
+    /// Missing long-wavelength (red) cones
+    Protanopia,
+    /// Missing medium-wavelength (green) cones
+    Deuteranopia,
+    /// Missing short-wavelength (blue) cones
+    Tritanopia,
+}
+
+impl ColorBlindness {
+    /// How the cone responses are perceived with this deficiency, in LMS
+    fn simulation(self) -> Mat3 {
+        match self {
+            Self::Protanopia => Mat3([[0.0, 2.02344, -2.52581], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]]),
+            Self::Deuteranopia => {
+                Mat3([[1.0, 0.0, 0.0], [0.494207, 0.0, 1.24827], [0.0, 0.0, 1.0]])
+            }
+            Self::Tritanopia => {
+                Mat3([[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [-0.395913, 0.801109, 0.0]])
+            }
+        }
+    }
+}
+
+/// A colour filter applied to everything shown on an output
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ColorFilter {
+    #[default]
+    None,
+    /// Replace colours by their luma
+    Greyscale,
+    /// Invert every channel
+    Invert,
+    /// Scale the distance from mid-grey by the factor, above 1 increases contrast
+    Contrast(f32),
+    /// Shift colours a dichromat can't distinguish into ones they can
+    Daltonize(ColorBlindness),
+}
+
+impl ColorFilter {
+    /// The filter as an affine transform of the output's encoded RGB values
+    pub fn matrix(&self) -> ColorMatrix {
+        match *self {
+            Self::None => ColorMatrix::IDENTITY,
+            Self::Greyscale => ColorMatrix {
+                matrix: Mat3([LUMA; 3]),
+                offset: [0.0; 3],
+            },
+            Self::Invert => ColorMatrix {
+                matrix: Mat3::IDENTITY.scale(-1.0),
+                offset: [1.0; 3],
+            },
+            Self::Contrast(factor) => ColorMatrix {
+                matrix: Mat3::IDENTITY.scale(factor),
+                offset: [0.5 * (1.0 - factor); 3],
+            },
+            Self::Daltonize(deficiency) => {
+                // rgb + shift * (rgb - simulated(rgb))
+                let lms_to_rgb = RGB_TO_LMS.inverse().expect("RGB to LMS is invertible");
+                let simulated = lms_to_rgb.mul(&deficiency.simulation()).mul(&RGB_TO_LMS);
+                let lost = sub(&Mat3::IDENTITY, &simulated);
+                ColorMatrix {
+                    matrix: add(&Mat3::IDENTITY, &ERROR_SHIFT.mul(&lost)),
+                    offset: [0.0; 3],
+                }
+            }
+        }
+    }
+}
+
+fn add(a: &Mat3, b: &Mat3) -> Mat3 {
+    Mat3(std::array::from_fn(|row| {
+        std::array::from_fn(|col| a.0[row][col] + b.0[row][col])
+    }))
+}
+
+fn sub(a: &Mat3, b: &Mat3) -> Mat3 {
+    add(a, &b.scale(-1.0))
+}
+
+/// An affine colour transform, `matrix * rgb + offset`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ColorMatrix {
+    matrix: Mat3,
+    offset: [f32; 3],
+}
+
+impl ColorMatrix {
+    pub const IDENTITY: Self = Self {
+        matrix: Mat3::IDENTITY,
+        offset: [0.0; 3],
+    };
+
+    /// Transform a colour, clamping the result like the shader does
+    pub fn apply(&self, rgb: [f32; 3]) -> [f32; 3] {
+        let transformed = self.matrix.apply(rgb);
+        std::array::from_fn(|i| (transformed[i] + self.offset[i]).clamp(0.0, 1.0))
+    }
+
+    pub fn is_identity(&self) -> bool {
+        *self == Self::IDENTITY
+    }
+
+    /// Column-major 4x4 matrix with the offset in the last column, for the shader
+    fn to_columns(self) -> [[f32; 4]; 4] {
+        let [c0, c1, c2] = self.matrix.columns();
+        let [o0, o1, o2] = self.offset;
+        [
+            [c0[0], c0[1], c0[2], 0.0],
+            [c1[0], c1[1], c1[2], 0.0],
+            [c2[0], c2[1], c2[2], 0.0],
+            [o0, o1, o2, 1.0],
+        ]
+    }
+}
+
+/// What the magnifier keeps in the middle of the output
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MagnifierFollow {
+    /// The pointer
+    #[default]
+    Pointer,
+    /// The keyboard focus, or the pointer while nothing has focus
+    Focus,
+}
+
+/// Parameters of the full-screen magnifier
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MagnifierSettings {
+    pub enabled: bool,
+    /// Magnification while enabled, at least 1
+    pub zoom: f32,
+    pub follow: MagnifierFollow,
+    /// Time constant of zooming and panning, zero jumps straight to the target
+    pub smoothing: Duration,
+}
+
+impl Default for MagnifierSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            zoom: 2.0,
+            follow: MagnifierFollow::Pointer,
+            smoothing: Duration::from_millis(100),
+        }
+    }
+}
+
+/// The animated view of a full-screen magnifier
+#[derive(Clone, Copy, Debug)]
+pub struct Magnifier {
+    settings: MagnifierSettings,
+    zoom: f32,
+    center: Point<f64, Physical>,
+    last_update: Option<Duration>,
+}
+
+impl Magnifier {
+    pub fn new(settings: MagnifierSettings) -> Self {
+        Self {
+            settings,
+            zoom: 1.0,
+            center: Point::default(),
+            last_update: None,
+        }
+    }
+
+    pub fn settings(&self) -> MagnifierSettings {
+        self.settings
+    }
+
+    /// Change the settings, the zoom animates towards the new magnification
+    pub fn set_settings(&mut self, settings: MagnifierSettings) {
+        self.settings = settings;
+    }
+
+    /// The current magnification
+    pub fn zoom(&self) -> f32 {
+        self.zoom
+    }
+
+    /// Whether the output is magnified at all
+    pub fn is_active(&self) -> bool {
+        self.zoom > 1.0 + ZOOM_EPSILON
+    }
+
+    /// Whether the zoom still moves towards its target
+    pub fn is_animating(&self) -> bool {
+        (self.zoom - self.target_zoom()).abs() > ZOOM_EPSILON
+    }
+
+    fn target_zoom(&self) -> f32 {
+        if self.settings.enabled {
+            self.settings.zoom.max(1.0)
+        } else {
+            1.0
+        }
+    }
+
+    /// Move towards the pointer or focus at time `now`. Returns whether another frame
+    /// is needed to finish the animation.
+    pub fn update(
+        &mut self,
+        now: Duration,
+        pointer: Point<f64, Physical>,
+        focus: Option<Rectangle<i32, Physical>>,
+    ) -> bool {
+        let target = match (self.settings.follow, focus) {
+            (MagnifierFollow::Focus, Some(focus)) => {
+                focus.to_f64().loc + focus.to_f64().size.downscale(2.0).to_point()
+            }
+            _ => pointer,
+        };
+        let elapsed = self
+            .last_update
+            .map_or(Duration::ZERO, |last| now.saturating_sub(last));
+        self.last_update = Some(now);
+
+        // Start zooming in from where the view is going to be
+        if !self.is_active() {
+            self.center = target;
+        }
+        let t = if self.settings.smoothing.is_zero() {
+            1.0
+        } else {
+            1.0 - (-elapsed.as_secs_f64() / self.settings.smoothing.as_secs_f64()).exp()
+        };
+        self.zoom += (self.target_zoom() - self.zoom) * t as f32;
+        self.center += (target - self.center).upscale(t);
+        if !self.is_animating() {
+            self.zoom = self.target_zoom();
+        }
+        // Panning is done once the view is within half a pixel of the target
+        let remaining = target - self.center;
+        self.is_animating() || (self.is_active() && remaining.x.abs().max(remaining.y.abs()) >= 0.5)
+    }
+
+    /// The part of an output of `output_size` shown magnified over all of it
+    pub fn source_rect(&self, output_size: Size<i32, Physical>) -> Rectangle<f64, Physical> {
+        let output_size = output_size.to_f64();
+        let size = output_size.downscale(self.zoom.max(1.0) as f64);
+        let max = output_size - size;
+        let loc = self.center - size.downscale(2.0).to_point();
+        Rectangle::new(
+            (loc.x.clamp(0.0, max.w), loc.y.clamp(0.0, max.h)).into(),
+            size,
+        )
+    }
+
+    /// Where a point of the unmagnified output ends up on screen
+    pub fn to_screen(
+        &self,
+        output_size: Size<i32, Physical>,
+        point: Point<f64, Physical>,
+    ) -> Point<f64, Physical> {
+        let source = self.source_rect(output_size);
+        (point - source.loc).upscale(self.zoom.max(1.0) as f64)
+    }
+}
+
+/// Filter and magnifier of one output
+struct OutputAccessibility {
+    filter: ColorFilter,
+    magnifier: Magnifier,
+    /// Copy of the output image the pass samples
+    source: Option<PassSource>,
+}
+
+impl OutputAccessibility {
+    fn is_active(&self) -> bool {
+        !self.filter.matrix().is_identity() || self.magnifier.is_active()
+    }
+}
+
+/// Push constants of accessibility.frag, matching `AccessibilityPushConstants`
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct AccessibilityPushConstants {
+    matrix: [[f32; 4]; 4],
+    source_offset: [f32; 2],
+    source_scale: [f32; 2],
+}
+
+/// The copy of an output image sampled by the pass
+struct PassSource {
+    context: Arc<Context>,
+    image: AllocatedImage,
+    descriptor_pool: vk::DescriptorPool,
+    descriptor_set: vk::DescriptorSet,
+}
+
+impl PassSource {
+    fn new(
+        context: Arc<Context>,
+        layout: vk::DescriptorSetLayout,
+        sampler: vk::Sampler,
+        extent: vk::Extent2D,
+        format: vk::Format,
+    ) -> StarforgeResult<Self> {
+        let usage = vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED;
+        let image = AllocatedImage::new(context.clone(), extent, format, usage)?;
+        let (descriptor_pool, descriptor_sets) = create_texture_sets(&context, layout, 1)?;
+        write_texture_set(&context, descriptor_sets[0], image.view(), sampler);
+        Ok(Self {
+            context,
+            image,
+            descriptor_pool,
+            descriptor_set: descriptor_sets[0],
+        })
+    }
+}
+
+impl Drop for PassSource {
+    fn drop(&mut self) {
+        unsafe {
+            self.context.untrack(self.descriptor_pool);
+            self.context
+                .device()
+                .destroy_descriptor_pool(self.descriptor_pool, None);
+        }
+    }
+}
+
+/// Records the accessibility pass and owns its pipelines and per-output state
+pub struct AccessibilityRenderer {
+    context: Arc<Context>,
+    vertex_module: vk::ShaderModule,
+    fragment_module: vk::ShaderModule,
+    sampler: vk::Sampler,
+    descriptor_set_layout: vk::DescriptorSetLayout,
+    pipeline_layout: vk::PipelineLayout,
+    pipelines: HashMap<vk::Format, vk::Pipeline>,
+    outputs: HashMap<OutputId, OutputAccessibility>,
+    /// Filter and magnifier of outputs that weren't configured on their own
+    default_filter: ColorFilter,
+    default_magnifier: MagnifierSettings,
+}
+
+impl AccessibilityRenderer {
+    pub fn new(context: Arc<Context>) -> StarforgeResult<Self> {
+        let vertex = compile_internal(FULLSCREEN_VERT, ShaderStage::Vertex)?;
+        let fragment = compile_internal(ACCESSIBILITY_FRAG, ShaderStage::Fragment)?;
+        let vertex_module = create_shader_module(&context, &vertex.spirv)?;
+        let fragment_module = create_shader_module(&context, &fragment.spirv)?;
+
+        let sampler = create_linear_sampler(&context)?;
+        let descriptor_set_layout = create_texture_set_layout(&context)?;
+        let pipeline_layout = create_pipeline_layout::<AccessibilityPushConstants>(
+            &context,
+            descriptor_set_layout,
+            vk::ShaderStageFlags::FRAGMENT,
+        )?;
+
+        Ok(Self {
+            context,
+            vertex_module,
+            fragment_module,
+            sampler,
+            descriptor_set_layout,
+            pipeline_layout,
+            pipelines: HashMap::new(),
+            outputs: HashMap::new(),
+            default_filter: ColorFilter::None,
+            default_magnifier: MagnifierSettings::default(),
+        })
+    }
+
+    /// Carry the settings of every output over to a renderer on a new device
+    pub fn recreate(&self, context: Arc<Context>) -> StarforgeResult<Self> {
+        let mut renderer = Self::new(context)?;
+        renderer.default_filter = self.default_filter;
+        renderer.default_magnifier = self.default_magnifier;
+        for (&id, output) in &self.outputs {
+            renderer.outputs.insert(
+                id,
+                OutputAccessibility {
+                    filter: output.filter,
+                    magnifier: output.magnifier,
+                    source: None,
+                },
+            );
+        }
+        Ok(renderer)
+    }
+
+    /// Set the filter and magnifier of every output, including ones configured before
+    pub fn set_defaults(&mut self, filter: ColorFilter, magnifier: MagnifierSettings) {
+        self.default_filter = filter;
+        self.default_magnifier = magnifier;
+        for output in self.outputs.values_mut() {
+            output.filter = filter;
+            output.magnifier.set_settings(magnifier);
+        }
+    }
+
+    fn output(&mut self, id: OutputId) -> &mut OutputAccessibility {
+        self.outputs
+            .entry(id)
+            .or_insert_with(|| OutputAccessibility {
+                filter: self.default_filter,
+                magnifier: Magnifier::new(self.default_magnifier),
+                source: None,
+            })
+    }
+
+    pub fn color_filter(&self, id: OutputId) -> ColorFilter {
+        self.outputs
+            .get(&id)
+            .map_or(self.default_filter, |output| output.filter)
+    }
+
+    pub fn set_color_filter(&mut self, id: OutputId, filter: ColorFilter) {
+        self.output(id).filter = filter;
+    }
+
+    pub fn magnifier(&self, id: OutputId) -> Magnifier {
+        self.outputs.get(&id).map_or_else(
+            || Magnifier::new(self.default_magnifier),
+            |output| output.magnifier,
+        )
+    }
+
+    pub fn set_magnifier(&mut self, id: OutputId, settings: MagnifierSettings) {
+        self.output(id).magnifier.set_settings(settings);
+    }
+
+    /// Advance the magnifier of an output, see [`Magnifier::update`]
+    pub fn update_magnifier(
+        &mut self,
+        id: OutputId,
+        now: Duration,
+        pointer: Point<f64, Physical>,
+        focus: Option<Rectangle<i32, Physical>>,
+    ) -> bool {
+        self.output(id).magnifier.update(now, pointer, focus)
+    }
+
+    /// Record the pass over the output image `image`, which has to be in
+    /// `TRANSFER_SRC_OPTIMAL` layout and is left in `COLOR_ATTACHMENT_OPTIMAL` layout.
+    ///
+    /// Returns `false` without recording anything when the output has neither a filter
+    /// nor magnification, the image stays untouched then.
+    #[allow(clippy::too_many_arguments)]
+    pub fn record(
+        &mut self,
+        id: OutputId,
+        command_buffer: vk::CommandBuffer,
+        image: vk::Image,
+        view: vk::ImageView,
+        format: vk::Format,
+        extent: vk::Extent2D,
+    ) -> StarforgeResult<bool> {
+        if !self.output(id).is_active() {
+            // Release the copy while the pass is off
+            self.output(id).source = None;
+            return Ok(false);
+        }
+
+        if !self.pipelines.contains_key(&format) {
+            let pipeline = create_graphics_pipeline(
+                &self.context,
+                self.pipeline_layout,
+                self.vertex_module,
+                self.fragment_module,
+                format,
+                BlendMode::Replace,
+            )?;
+            self.pipelines.insert(format, pipeline);
+        }
+        let pipeline = self.pipelines[&format];
+
+        let context = self.context.clone();
+        let (set_layout, pipeline_layout, sampler) = (
+            self.descriptor_set_layout,
+            self.pipeline_layout,
+            self.sampler,
+        );
+        let output = self.output(id);
+        let stale = output.source.as_ref().is_none_or(|source| {
+            source.image.extent() != extent || source.image.format() != format
+        });
+        if stale {
+            output.source = Some(PassSource::new(
+                context.clone(),
+                set_layout,
+                sampler,
+                extent,
+                format,
+            )?);
+        }
+        let source = output
+            .source
+            .as_ref()
+            .expect("pass source was just created");
+
+        let output_size = Size::from((extent.width as i32, extent.height as i32));
+        let rect = output.magnifier.source_rect(output_size);
+        let output_size = output_size.to_f64();
+        let push_constants = AccessibilityPushConstants {
+            matrix: output.filter.matrix().to_columns(),
+            source_offset: [
+                (rect.loc.x / output_size.w) as f32,
+                (rect.loc.y / output_size.h) as f32,
+            ],
+            source_scale: [
+                (rect.size.w / output_size.w) as f32,
+                (rect.size.h / output_size.h) as f32,
+            ],
+        };
+
+        unsafe {
+            record_pass(
+                &context,
+                command_buffer,
+                pipeline_layout,
+                pipeline,
+                source,
+                (image, view, extent),
+                &push_constants,
+            );
+        }
+        Ok(true)
+    }
+
+    pub fn remove_output(&mut self, id: OutputId) {
+        self.outputs.remove(&id);
+    }
+}
+
+impl Drop for AccessibilityRenderer {
+    fn drop(&mut self) {
+        // Sources hold descriptor sets from their own pools, release them first
+        self.outputs.clear();
+        unsafe {
+            let device = self.context.device();
+            for &pipeline in self.pipelines.values() {
+                self.context.untrack(pipeline);
+                device.destroy_pipeline(pipeline, None);
+            }
+            self.context.untrack(self.pipeline_layout);
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+            self.context.untrack(self.descriptor_set_layout);
+            device.destroy_descriptor_set_layout(self.descriptor_set_layout, None);
+            self.context.untrack(self.sampler);
+            device.destroy_sampler(self.sampler, None);
+            self.context.untrack(self.vertex_module);
+            device.destroy_shader_module(self.vertex_module, None);
+            self.context.untrack(self.fragment_module);
+            device.destroy_shader_module(self.fragment_module, None);
+        }
+    }
+}
+
+/// Copy the output image into `source`, then draw it back filtered and magnified
+unsafe fn record_pass(
+    context: &Context,
+    command_buffer: vk::CommandBuffer,
+    pipeline_layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+    source: &PassSource,
+    (image, view, extent): (vk::Image, vk::ImageView, vk::Extent2D),
+    push_constants: &AccessibilityPushConstants,
+) {
+    let device = context.device();
+    unsafe {
+        image_barrier(
+            device,
+            command_buffer,
+            source.image.image(),
+            vk::ImageLayout::UNDEFINED,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            (
+                vk::PipelineStageFlags2::FRAGMENT_SHADER,
+                vk::AccessFlags2::NONE,
+            ),
+            (
+                vk::PipelineStageFlags2::COPY,
+                vk::AccessFlags2::TRANSFER_WRITE,
+            ),
+        );
+        let subresource = vk::ImageSubresourceLayers::default()
+            .aspect_mask(vk::ImageAspectFlags::COLOR)
+            .layer_count(1);
+        let copy = vk::ImageCopy::default()
+            .src_subresource(subresource)
+            .dst_subresource(subresource)
+            .extent(vk::Extent3D {
+                width: extent.width,
+                height: extent.height,
+                depth: 1,
+            });
+        device.cmd_copy_image(
+            command_buffer,
+            image,
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            source.image.image(),
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            &[copy],
+        );
+        image_barrier(
+            device,
+            command_buffer,
+            source.image.image(),
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            (
+                vk::PipelineStageFlags2::COPY,
+                vk::AccessFlags2::TRANSFER_WRITE,
+            ),
+            (
+                vk::PipelineStageFlags2::FRAGMENT_SHADER,
+                vk::AccessFlags2::SHADER_SAMPLED_READ,
+            ),
+        );
+        // The pass overwrites the whole output, only the copy has to finish reading it
+        image_barrier(
+            device,
+            command_buffer,
+            image,
+            vk::ImageLayout::UNDEFINED,
+            vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            (vk::PipelineStageFlags2::COPY, vk::AccessFlags2::NONE),
+            (
+                vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT,
+                vk::AccessFlags2::COLOR_ATTACHMENT_WRITE,
+            ),
+        );
+
+        let attachments = [vk::RenderingAttachmentInfo::default()
+            .image_view(view)
+            .image_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+            .load_op(vk::AttachmentLoadOp::DONT_CARE)
+            .store_op(vk::AttachmentStoreOp::STORE)];
+        let render_area = vk::Rect2D {
+            offset: vk::Offset2D::default(),
+            extent,
+        };
+        let rendering_info = vk::RenderingInfo::default()
+            .render_area(render_area)
+            .layer_count(1)
+            .color_attachments(&attachments);
+        device.cmd_begin_rendering(command_buffer, &rendering_info);
+        device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, pipeline);
+        device.cmd_set_viewport(
+            command_buffer,
+            0,
+            &[vk::Viewport {
+                x: 0.0,
+                y: 0.0,
+                width: extent.width as f32,
+                height: extent.height as f32,
+                min_depth: 0.0,
+                max_depth: 1.0,
+            }],
+        );
+        device.cmd_set_scissor(command_buffer, 0, &[render_area]);
+        device.cmd_bind_descriptor_sets(
+            command_buffer,
+            vk::PipelineBindPoint::GRAPHICS,
+            pipeline_layout,
+            0,
+            &[source.descriptor_set],
+            &[],
+        );
+        device.cmd_push_constants(
+            command_buffer,
+            pipeline_layout,
+            vk::ShaderStageFlags::FRAGMENT,
+            0,
+            push_constant_bytes(push_constants),
+        );
+        device.cmd_draw(command_buffer, 3, 1, 0, 0);
+        device.cmd_end_rendering(command_buffer);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GREYS: [[f32; 3]; 3] = [[0.0; 3], [0.5; 3], [1.0; 3]];
+
+    fn assert_close(a: [f32; 3], b: [f32; 3]) {
+        for i in 0..3 {
+            assert!((a[i] - b[i]).abs() < 1e-3, "{a:?} != {b:?}");
+        }
+    }
+
+    #[test]
+    fn accessibility_shader_compiles() {
+        compile_internal(ACCESSIBILITY_FRAG, ShaderStage::Fragment).unwrap();
+    }
+
+    #[test]
+    fn no_filter_is_the_identity() {
+        assert!(ColorFilter::None.matrix().is_identity());
+        assert!(ColorFilter::Contrast(1.0).matrix().is_identity());
+    }
+
+    #[test]
+    fn greyscale_keeps_luma() {
+        let matrix = ColorFilter::Greyscale.matrix();
+        assert_close(matrix.apply([1.0, 0.0, 0.0]), [LUMA[0]; 3]);
+        assert_close(matrix.apply([1.0; 3]), [1.0; 3]);
+    }
+
+    #[test]
+    fn invert_swaps_black_and_white() {
+        let matrix = ColorFilter::Invert.matrix();
+        assert_close(matrix.apply([0.0; 3]), [1.0; 3]);
+        assert_close(matrix.apply([1.0, 0.25, 0.0]), [0.0, 0.75, 1.0]);
+    }
+
+    #[test]
+    fn contrast_pivots_around_mid_grey() {
+        let matrix = ColorFilter::Contrast(2.0).matrix();
+        assert_close(matrix.apply([0.5; 3]), [0.5; 3]);
+        assert_close(matrix.apply([0.6; 3]), [0.7; 3]);
+        assert_close(matrix.apply([0.9; 3]), [1.0; 3]);
+    }
+
+    #[test]
+    fn daltonization_keeps_greys() {
+        for deficiency in [
+            ColorBlindness::Protanopia,
+            ColorBlindness::Deuteranopia,
+            ColorBlindness::Tritanopia,
+        ] {
+            let matrix = ColorFilter::Daltonize(deficiency).matrix();
+            for grey in GREYS {
+                assert_close(matrix.apply(grey), grey);
+            }
+        }
+    }
+
+    #[test]
+    fn daltonization_changes_confused_colours() {
+        let red = [0.8, 0.2, 0.2];
+        let matrix = ColorFilter::Daltonize(ColorBlindness::Protanopia).matrix();
+        assert!((matrix.apply(red)[2] - red[2]).abs() > 0.05);
+    }
+
+    fn magnifier(zoom: f32, smoothing: Duration) -> Magnifier {
+        Magnifier::new(MagnifierSettings {
+            enabled: true,
+            zoom,
+            follow: MagnifierFollow::Pointer,
+            smoothing,
+        })
+    }
+
+    #[test]
+    fn magnifier_without_smoothing_jumps_to_the_target() {
+        let mut magnifier = magnifier(2.0, Duration::ZERO);
+        let animating = magnifier.update(Duration::ZERO, (400.0, 300.0).into(), None);
+        assert!(!animating);
+        assert_eq!(magnifier.zoom(), 2.0);
+        let rect = magnifier.source_rect((800, 600).into());
+        assert_eq!(
+            rect,
+            Rectangle::new((200.0, 150.0).into(), (400.0, 300.0).into())
+        );
+    }
+
+    #[test]
+    fn magnifier_zooms_smoothly() {
+        let mut magnifier = magnifier(4.0, Duration::from_millis(100));
+        let pointer = (0.0, 0.0).into();
+        assert!(magnifier.update(Duration::ZERO, pointer, None));
+        assert_eq!(magnifier.zoom(), 1.0);
+        assert!(magnifier.update(Duration::from_millis(100), pointer, None));
+        let zoom = magnifier.zoom();
+        assert!(zoom > 1.0 && zoom < 4.0);
+        magnifier.update(Duration::from_secs(5), pointer, None);
+        assert_eq!(magnifier.zoom(), 4.0);
+    }
+
+    #[test]
+    fn magnified_view_stays_on_the_output() {
+        let mut magnifier = magnifier(2.0, Duration::ZERO);
+        magnifier.update(Duration::ZERO, (790.0, 5.0).into(), None);
+        let rect = magnifier.source_rect((800, 600).into());
+        assert_eq!(rect.loc, (400.0, 0.0).into());
+        assert_eq!(
+            magnifier.to_screen((800, 600).into(), (790.0, 5.0).into()),
+            (780.0, 10.0).into()
+        );
+    }
+
+    #[test]
+    fn magnifier_follows_focus() {
+        let mut magnifier = Magnifier::new(MagnifierSettings {
+            enabled: true,
+            zoom: 2.0,
+            follow: MagnifierFollow::Focus,
+            smoothing: Duration::ZERO,
+        });
+        let focus = Rectangle::new((100, 100).into(), (200, 100).into());
+        magnifier.update(Duration::ZERO, (0.0, 0.0).into(), Some(focus));
+        let rect = magnifier.source_rect((800, 600).into());
+        assert_eq!(rect.loc, (0.0, 0.0).into());
+        let focus = Rectangle::new((300, 300).into(), (200, 0).into());
+        magnifier.update(Duration::ZERO, (0.0, 0.0).into(), Some(focus));
+        let rect = magnifier.source_rect((800, 600).into());
+        assert_eq!(rect.loc, (200.0, 150.0).into());
+    }
+}
@@ -0,0 +1,660 @@
+//! Starforge Render - Backdrop Blur
+//!
+//! This module implements the dual-Kawase blur used for frosted-glass windows and panels.
+//!
+//! The backdrop behind a region is copied out of the output image, downsampled `passes`
+//! times to half resolution each, then upsampled back. Results are cached per output and
+//! region, and only recomputed when something below the region was damaged.
+
+use crate::core::Context;
+use crate::memory::{AllocatedImage, image_barrier};
+use crate::pipeline::{
+    BlendMode, create_graphics_pipeline, create_linear_sampler, create_pipeline_layout,
+    create_texture_set_layout, create_texture_sets, push_constant_bytes, write_texture_set,
+};
+use crate::shader::{compile_internal, create_shader_module};
+use crate::swapchain::OutputId;
+use ash::vk;
+use naga::ShaderStage;
+use smithay::utils::{Physical, Point, Rectangle, Size};
+use starforge_core::{StarforgeError, StarforgeResult};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Largest number of downsample passes, beyond which the chain is a handful of pixels
+pub const MAX_BLUR_PASSES: u32 = 6;
+
+const FULLSCREEN_VERT: &str = include_str!("../shaders/fullscreen.vert");
+const BLUR_DOWN_FRAG: &str = include_str!("../shaders/blur_down.frag");
+const BLUR_UP_FRAG: &str = include_str!("../shaders/blur_up.frag");
+
+/// Parameters of a backdrop blur
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BlurSettings {
+    /// Distance between samples in texels of each pass
+    pub radius: f32,
+    /// Number of downsample passes, each followed by an upsample pass on the way back
+    pub passes: u32,
+}
+
+impl Default for BlurSettings {
+    fn default() -> Self {
+        Self {
+            radius: 4.0,
+            passes: 3,
+        }
+    }
+}
+
+impl BlurSettings {
+    fn clamped_passes(&self) -> u32 {
+        self.passes.clamp(1, MAX_BLUR_PASSES)
+    }
+
+    /// How far in pixels the blur spreads content
+    pub fn extent(&self) -> i32 {
+        (self.radius * (1u32 << (self.clamped_passes() + 1)) as f32).ceil() as i32
+    }
+}
+
+/// Grow damage so that every blurred pixel reading a damaged pixel is redrawn.
+///
+/// A change below a blurred region affects blurred pixels up to [`BlurSettings::extent`]
+/// away, so damage touching a region is grown by that much and clipped to the region.
+pub fn expand_blur_damage(
+    damage: &[Rectangle<i32, Physical>],
+    blur_regions: &[(Rectangle<i32, Physical>, BlurSettings)],
+) -> Vec<Rectangle<i32, Physical>> {
+    let mut expanded = damage.to_vec();
+    for rect in damage {
+        for (region, settings) in blur_regions {
+            let extent = settings.extent();
+            let reach = Rectangle::new(
+                rect.loc - Point::from((extent, extent)),
+                rect.size + Size::from((extent * 2, extent * 2)),
+            );
+            if let Some(affected) = reach.intersection(*region) {
+                expanded.push(affected);
+            }
+        }
+    }
+    expanded
+}
+
+/// Whether nothing in `damage_below`, grown with [`expand_blur_damage`], touches `region`,
+/// so that a blur of it can be reused
+pub fn backdrop_unchanged(
+    region: Rectangle<i32, Physical>,
+    damage_below: &[Rectangle<i32, Physical>],
+) -> bool {
+    !damage_below.iter().any(|rect| rect.overlaps(region))
+}
+
+/// Push constants of the blur shaders, matching `BlurPushConstants` in blur_*.frag
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct BlurPushConstants {
+    half_pixel: [f32; 2],
+    offset: f32,
+}
+
+/// Downsample and upsample pipelines for one attachment format
+struct BlurPipelines {
+    down: vk::Pipeline,
+    up: vk::Pipeline,
+}
+
+/// The intermediate images of one blurred region
+struct BlurChain {
+    context: Arc<Context>,
+    /// Level 0 is the region at full size and ends up holding the result
+    levels: Vec<AllocatedImage>,
+    descriptor_pool: vk::DescriptorPool,
+    /// Set `i` samples level `i`
+    descriptor_sets: Vec<vk::DescriptorSet>,
+}
+
+impl BlurChain {
+    fn new(
+        context: Arc<Context>,
+        layout: vk::DescriptorSetLayout,
+        sampler: vk::Sampler,
+        extent: vk::Extent2D,
+        format: vk::Format,
+        passes: u32,
+    ) -> StarforgeResult<Self> {
+        let usage = vk::ImageUsageFlags::TRANSFER_DST
+            | vk::ImageUsageFlags::SAMPLED
+            | vk::ImageUsageFlags::COLOR_ATTACHMENT;
+        let levels = (0..=passes)
+            .map(|level| {
+                let extent = vk::Extent2D {
+                    width: (extent.width >> level).max(1),
+                    height: (extent.height >> level).max(1),
+                };
+                AllocatedImage::new(context.clone(), extent, format, usage)
+            })
+            .collect::<StarforgeResult<Vec<_>>>()?;
+
+        let (descriptor_pool, descriptor_sets) =
+            create_texture_sets(&context, layout, levels.len() as u32)?;
+        for (&set, level) in descriptor_sets.iter().zip(&levels) {
+            write_texture_set(&context, set, level.view(), sampler);
+        }
+
+        Ok(Self {
+            context,
+            levels,
+            descriptor_pool,
+            descriptor_sets,
+        })
+    }
+
+    fn passes(&self) -> u32 {
+        self.levels.len() as u32 - 1
+    }
+}
+
+impl Drop for BlurChain {
+    fn drop(&mut self) {
+        unsafe {
+            self.context.untrack(self.descriptor_pool);
+            self.context
+                .device()
+                .destroy_descriptor_pool(self.descriptor_pool, None);
+        }
+    }
+}
+
+/// A blurred region kept from previous frames
+struct CachedBlur {
+    settings: BlurSettings,
+    chain: BlurChain,
+    /// Whether level 0 holds a blur of the current backdrop
+    valid: bool,
+    /// Whether the region was blurred or reused this frame
+    used: bool,
+}
+
+type RegionKey = (i32, i32, i32, i32);
+
+fn region_key(region: Rectangle<i32, Physical>) -> RegionKey {
+    (region.loc.x, region.loc.y, region.size.w, region.size.h)
+}
+
+/// Blurred regions of one output
+#[derive(Default)]
+struct BlurCache {
+    entries: HashMap<RegionKey, CachedBlur>,
+}
+
+/// Records backdrop blurs and owns their pipelines and per-output caches
+pub struct BlurRenderer {
+    context: Arc<Context>,
+    vertex_module: vk::ShaderModule,
+    down_module: vk::ShaderModule,
+    up_module: vk::ShaderModule,
+    sampler: vk::Sampler,
+    descriptor_set_layout: vk::DescriptorSetLayout,
+    pipeline_layout: vk::PipelineLayout,
+    pipelines: HashMap<vk::Format, BlurPipelines>,
+    caches: HashMap<OutputId, BlurCache>,
+}
+
+impl BlurRenderer {
+    pub fn new(context: Arc<Context>) -> StarforgeResult<Self> {
+        let vertex = compile_internal(FULLSCREEN_VERT, ShaderStage::Vertex)?;
+        let down = compile_internal(BLUR_DOWN_FRAG, ShaderStage::Fragment)?;
+        let up = compile_internal(BLUR_UP_FRAG, ShaderStage::Fragment)?;
+
+        let vertex_module = create_shader_module(&context, &vertex.spirv)?;
+        let down_module = create_shader_module(&context, &down.spirv)?;
+        let up_module = create_shader_module(&context, &up.spirv)?;
+
+        let sampler = create_linear_sampler(&context)?;
+        let descriptor_set_layout = create_texture_set_layout(&context)?;
+        let pipeline_layout = create_pipeline_layout::<BlurPushConstants>(
+            &context,
+            descriptor_set_layout,
+            vk::ShaderStageFlags::FRAGMENT,
+        )?;
+
+        Ok(Self {
+            context,
+            vertex_module,
+            down_module,
+            up_module,
+            sampler,
+            descriptor_set_layout,
+            pipeline_layout,
+            pipelines: HashMap::new(),
+            caches: HashMap::new(),
+        })
+    }
+
+    /// Record the blur of `region` of the output image `backdrop` into `command_buffer`.
+    ///
+    /// `backdrop` has to be in `TRANSFER_SRC_OPTIMAL` layout and `damage_below` holds
+    /// everything that changed below the element since the previous frame, grown with
+    /// [`expand_blur_damage`]. When the cached blur of the region is still valid nothing
+    /// is recorded. Returns the view of the blurred region, which is left in
+    /// `SHADER_READ_ONLY_OPTIMAL` layout.
+    #[allow(clippy::too_many_arguments)]
+    pub fn record(
+        &mut self,
+        output: OutputId,
+        command_buffer: vk::CommandBuffer,
+        backdrop: vk::Image,
+        format: vk::Format,
+        region: Rectangle<i32, Physical>,
+        settings: BlurSettings,
+        damage_below: &[Rectangle<i32, Physical>],
+    ) -> StarforgeResult<vk::ImageView> {
+        if region.is_empty() {
+            return Err(StarforgeError::RendererError(
+                "Cannot blur an empty region".to_string(),
+            ));
+        }
+        let passes = settings.clamped_passes();
+        let cache = self.caches.entry(output).or_default();
+        let key = region_key(region);
+
+        // Reuse the blur when nothing below the region changed
+        if let Some(entry) = cache.entries.get_mut(&key) {
+            entry.used = true;
+            let unchanged = entry.valid
+                && entry.settings == settings
+                && entry.chain.levels[0].format() == format
+                && backdrop_unchanged(region, damage_below);
+            if unchanged {
+                return Ok(entry.chain.levels[0].view());
+            }
+            entry.valid = false;
+        }
+
+        let rebuild = cache.entries.get(&key).is_none_or(|entry| {
+            entry.chain.passes() != passes || entry.chain.levels[0].format() != format
+        });
+        if rebuild {
+            let extent = vk::Extent2D {
+                width: region.size.w as u32,
+                height: region.size.h as u32,
+            };
+            let chain = BlurChain::new(
+                self.context.clone(),
+                self.descriptor_set_layout,
+                self.sampler,
+                extent,
+                format,
+                passes,
+            )?;
+            cache.entries.insert(
+                key,
+                CachedBlur {
+                    settings,
+                    chain,
+                    valid: false,
+                    used: true,
+                },
+            );
+        }
+
+        if !self.pipelines.contains_key(&format) {
+            let pipelines = self.create_pipelines(format)?;
+            self.pipelines.insert(format, pipelines);
+        }
+        let pipelines = &self.pipelines[&format];
+        let entry = self
+            .caches
+            .get_mut(&output)
+            .and_then(|cache| cache.entries.get_mut(&key))
+            .expect("blur cache entry was just inserted");
+
+        unsafe {
+            record_passes(
+                &self.context,
+                command_buffer,
+                self.pipeline_layout,
+                pipelines,
+                &entry.chain,
+                backdrop,
+                region,
+                settings.radius,
+            );
+        }
+        entry.settings = settings;
+        entry.valid = true;
+        Ok(entry.chain.levels[0].view())
+    }
+
+    /// Drop cached blurs of an output that weren't drawn since the last call
+    pub fn end_frame(&mut self, output: OutputId) {
+        if let Some(cache) = self.caches.get_mut(&output) {
+            cache
+                .entries
+                .retain(|_, entry| std::mem::take(&mut entry.used));
+        }
+    }
+
+    /// Forget every cached blur of an output
+    pub fn remove_output(&mut self, output: OutputId) {
+        self.caches.remove(&output);
+    }
+
+    fn create_pipelines(&self, format: vk::Format) -> StarforgeResult<BlurPipelines> {
+        let down = self.create_pipeline(format, self.down_module)?;
+        match self.create_pipeline(format, self.up_module) {
+            Ok(up) => Ok(BlurPipelines { down, up }),
+            Err(e) => {
+                self.context.untrack(down);
+                unsafe { self.context.device().destroy_pipeline(down, None) };
+                Err(e)
+            }
+        }
+    }
+
+    fn create_pipeline(
+        &self,
+        format: vk::Format,
+        fragment_module: vk::ShaderModule,
+    ) -> StarforgeResult<vk::Pipeline> {
+        create_graphics_pipeline(
+            &self.context,
+            self.pipeline_layout,
+            self.vertex_module,
+            fragment_module,
+            format,
+            BlendMode::Replace,
+        )
+    }
+}
+
+impl Drop for BlurRenderer {
+    fn drop(&mut self) {
+        // Chains hold descriptor sets from their own pools, release them first
+        self.caches.clear();
+        unsafe {
+            let device = self.context.device();
+            for pipelines in self.pipelines.values() {
+                self.context.untrack(pipelines.down);
+                device.destroy_pipeline(pipelines.down, None);
+                self.context.untrack(pipelines.up);
+                device.destroy_pipeline(pipelines.up, None);
+            }
+            self.context.untrack(self.pipeline_layout);
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+            self.context.untrack(self.descriptor_set_layout);
+            device.destroy_descriptor_set_layout(self.descriptor_set_layout, None);
+            self.context.untrack(self.sampler);
+            device.destroy_sampler(self.sampler, None);
+            self.context.untrack(self.vertex_module);
+            device.destroy_shader_module(self.vertex_module, None);
+            self.context.untrack(self.down_module);
+            device.destroy_shader_module(self.down_module, None);
+            self.context.untrack(self.up_module);
+            device.destroy_shader_module(self.up_module, None);
+        }
+    }
+}
+
+/// Copy the backdrop into level 0, downsample through every level and upsample back
+#[allow(clippy::too_many_arguments)]
+unsafe fn record_passes(
+    context: &Context,
+    command_buffer: vk::CommandBuffer,
+    pipeline_layout: vk::PipelineLayout,
+    pipelines: &BlurPipelines,
+    chain: &BlurChain,
+    backdrop: vk::Image,
+    region: Rectangle<i32, Physical>,
+    radius: f32,
+) {
+    let device = context.device();
+    let level0 = &chain.levels[0];
+
+    unsafe {
+        image_barrier(
+            device,
+            command_buffer,
+            level0.image(),
+            vk::ImageLayout::UNDEFINED,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            (vk::PipelineStageFlags2::NONE, vk::AccessFlags2::NONE),
+            (
+                vk::PipelineStageFlags2::COPY,
+                vk::AccessFlags2::TRANSFER_WRITE,
+            ),
+        );
+        let subresource = vk::ImageSubresourceLayers::default()
+            .aspect_mask(vk::ImageAspectFlags::COLOR)
+            .layer_count(1);
+        let copy = vk::ImageCopy::default()
+            .src_subresource(subresource)
+            .src_offset(vk::Offset3D {
+                x: region.loc.x,
+                y: region.loc.y,
+                z: 0,
+            })
+            .dst_subresource(subresource)
+            .extent(vk::Extent3D {
+                width: level0.extent().width,
+                height: level0.extent().height,
+                depth: 1,
+            });
+        device.cmd_copy_image(
+            command_buffer,
+            backdrop,
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            level0.image(),
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            &[copy],
+        );
+        image_barrier(
+            device,
+            command_buffer,
+            level0.image(),
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            (
+                vk::PipelineStageFlags2::COPY,
+                vk::AccessFlags2::TRANSFER_WRITE,
+            ),
+            (
+                vk::PipelineStageFlags2::FRAGMENT_SHADER,
+                vk::AccessFlags2::SHADER_SAMPLED_READ,
+            ),
+        );
+
+        let passes = chain.passes() as usize;
+        for target in 1..=passes {
+            draw_pass(
+                device,
+                command_buffer,
+                pipeline_layout,
+                pipelines.down,
+                &chain.levels[target],
+                chain.descriptor_sets[target - 1],
+                radius,
+            );
+        }
+        for target in (0..passes).rev() {
+            draw_pass(
+                device,
+                command_buffer,
+                pipeline_layout,
+                pipelines.up,
+                &chain.levels[target],
+                chain.descriptor_sets[target + 1],
+                radius,
+            );
+        }
+    }
+}
+
+/// Render one pass into `target`, sampling through `source_set`
+unsafe fn draw_pass(
+    device: &ash::Device,
+    command_buffer: vk::CommandBuffer,
+    pipeline_layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+    target: &AllocatedImage,
+    source_set: vk::DescriptorSet,
+    radius: f32,
+) {
+    let extent = target.extent();
+    unsafe {
+        // The pass overwrites the whole level, its previous contents can be discarded
+        image_barrier(
+            device,
+            command_buffer,
+            target.image(),
+            vk::ImageLayout::UNDEFINED,
+            vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            (
+                vk::PipelineStageFlags2::FRAGMENT_SHADER,
+                vk::AccessFlags2::SHADER_SAMPLED_READ,
+            ),
+            (
+                vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT,
+                vk::AccessFlags2::COLOR_ATTACHMENT_WRITE,
+            ),
+        );
+
+        let attachments = [vk::RenderingAttachmentInfo::default()
+            .image_view(target.view())
+            .image_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+            .load_op(vk::AttachmentLoadOp::DONT_CARE)
+            .store_op(vk::AttachmentStoreOp::STORE)];
+        let render_area = vk::Rect2D {
+            offset: vk::Offset2D::default(),
+            extent,
+        };
+        let rendering_info = vk::RenderingInfo::default()
+            .render_area(render_area)
+            .layer_count(1)
+            .color_attachments(&attachments);
+        device.cmd_begin_rendering(command_buffer, &rendering_info);
+        device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, pipeline);
+        device.cmd_set_viewport(
+            command_buffer,
+            0,
+            &[vk::Viewport {
+                x: 0.0,
+                y: 0.0,
+                width: extent.width as f32,
+                height: extent.height as f32,
+                min_depth: 0.0,
+                max_depth: 1.0,
+            }],
+        );
+        device.cmd_set_scissor(command_buffer, 0, &[render_area]);
+        device.cmd_bind_descriptor_sets(
+            command_buffer,
+            vk::PipelineBindPoint::GRAPHICS,
+            pipeline_layout,
+            0,
+            &[source_set],
+            &[],
+        );
+        let push_constants = BlurPushConstants {
+            half_pixel: [0.5 / extent.width as f32, 0.5 / extent.height as f32],
+            offset: radius,
+        };
+        device.cmd_push_constants(
+            command_buffer,
+            pipeline_layout,
+            vk::ShaderStageFlags::FRAGMENT,
+            0,
+            push_constant_bytes(&push_constants),
+        );
+        device.cmd_draw(command_buffer, 3, 1, 0, 0);
+        device.cmd_end_rendering(command_buffer);
+
+        image_barrier(
+            device,
+            command_buffer,
+            target.image(),
+            vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            (
+                vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT,
+                vk::AccessFlags2::COLOR_ATTACHMENT_WRITE,
+            ),
+            (
+                vk::PipelineStageFlags2::FRAGMENT_SHADER,
+                vk::AccessFlags2::SHADER_SAMPLED_READ,
+            ),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rect(x: i32, y: i32, w: i32, h: i32) -> Rectangle<i32, Physical> {
+        Rectangle::new((x, y).into(), (w, h).into())
+    }
+
+    #[test]
+    fn blur_shaders_compile() {
+        compile_internal(FULLSCREEN_VERT, ShaderStage::Vertex).unwrap();
+        compile_internal(BLUR_DOWN_FRAG, ShaderStage::Fragment).unwrap();
+        compile_internal(BLUR_UP_FRAG, ShaderStage::Fragment).unwrap();
+    }
+
+    #[test]
+    fn extent_grows_with_passes() {
+        let settings = BlurSettings {
+            radius: 2.0,
+            passes: 2,
+        };
+        assert_eq!(settings.extent(), 16);
+        let settings = BlurSettings {
+            radius: 2.0,
+            passes: 3,
+        };
+        assert_eq!(settings.extent(), 32);
+    }
+
+    #[test]
+    fn passes_are_clamped() {
+        let settings = BlurSettings {
+            radius: 1.0,
+            passes: 100,
+        };
+        assert_eq!(settings.extent(), 1 << (MAX_BLUR_PASSES + 1));
+    }
+
+    #[test]
+    fn damage_below_a_blur_grows_by_the_extent() {
+        let settings = BlurSettings {
+            radius: 1.0,
+            passes: 2,
+        };
+        let region = rect(0, 0, 100, 100);
+        let damage = [rect(50, 50, 1, 1)];
+        let expanded = expand_blur_damage(&damage, &[(region, settings)]);
+        assert_eq!(expanded, vec![damage[0], rect(42, 42, 17, 17)]);
+    }
+
+    #[test]
+    fn expansion_is_clipped_to_the_region() {
+        let settings = BlurSettings {
+            radius: 1.0,
+            passes: 2,
+        };
+        let region = rect(10, 10, 20, 20);
+        let damage = [rect(5, 5, 2, 2)];
+        let expanded = expand_blur_damage(&damage, &[(region, settings)]);
+        assert_eq!(expanded, vec![damage[0], rect(10, 10, 5, 5)]);
+    }
+
+    #[test]
+    fn damage_far_from_a_blur_is_unchanged() {
+        let region = rect(0, 0, 10, 10);
+        let damage = [rect(500, 500, 10, 10)];
+        let expanded = expand_blur_damage(&damage, &[(region, BlurSettings::default())]);
+        assert_eq!(expanded, damage.to_vec());
+    }
+}
@@ -0,0 +1,809 @@
+//! Starforge Render - Screen Capture
+//!
+//! This module copies what an output shows into the buffers of screen capture clients.
+//!
+//! Outputs without a window, such as headless ones, render into an [`OffscreenTarget`],
+//! which captures read from. Only the regions the client's buffer is missing are copied,
+//! so repeated captures of a mostly still output stay cheap. The cursor can be drawn over
+//! the copy, for clients that ask for it.
+//!
+//! Single windows are captured by drawing their surfaces' textures into an offscreen
+//! target of their own, independently of whether and where the window is shown. Their
+//! buffers are copied in the encoding they have, without colour management.
+
+use crate::color::ColorTransform;
+use crate::core::Context;
+use crate::dmabuf::{CAPTURE_USAGE, DmabufImage};
+use crate::memory::{AllocatedImage, COLOR_SUBRESOURCE_RANGE, MappedBuffer, image_barrier};
+use crate::pipeline::{create_texture_sets, write_texture_set};
+use crate::resources::{ResourceManager, TextureId, TextureImage};
+use crate::shape::{ShapeRenderer, WindowShape};
+use ash::vk;
+use smithay::backend::allocator::dmabuf::Dmabuf;
+use smithay::reexports::wayland_server::protocol::{wl_buffer::WlBuffer, wl_shm};
+use smithay::utils::{Buffer, Physical, Rectangle, Size};
+use smithay::wayland::shm::{BufferData, with_buffer_contents, with_buffer_contents_mut};
+use starforge_core::{StarforgeError, StarforgeResult};
+use std::sync::Arc;
+
+/// Format of offscreen targets and of the shm formats captures can be written in
+const CAPTURE_FORMAT: vk::Format = vk::Format::B8G8R8A8_UNORM;
+
+/// An image outputs without a swapchain render into
+///
+/// Between frames the image is kept in [`OffscreenTarget::LAYOUT`], so whatever renders
+/// into it transitions from and back to that layout.
+pub struct OffscreenTarget {
+    image: AllocatedImage,
+}
+
+impl OffscreenTarget {
+    pub const LAYOUT: vk::ImageLayout = vk::ImageLayout::TRANSFER_SRC_OPTIMAL;
+
+    /// Create a target of `size`, cleared to transparent black
+    pub fn new(context: Arc<Context>, size: Size<i32, Physical>) -> StarforgeResult<Self> {
+        let image = AllocatedImage::new(
+            context.clone(),
+            vk::Extent2D {
+                width: size.w.max(1) as u32,
+                height: size.h.max(1) as u32,
+            },
+            CAPTURE_FORMAT,
+            vk::ImageUsageFlags::COLOR_ATTACHMENT
+                | vk::ImageUsageFlags::SAMPLED
+                | vk::ImageUsageFlags::TRANSFER_SRC
+                | vk::ImageUsageFlags::TRANSFER_DST,
+        )?;
+        context.submit_and_wait(|command_buffer| {
+            let device = context.device();
+            unsafe {
+                image_barrier(
+                    device,
+                    command_buffer,
+                    image.image(),
+                    vk::ImageLayout::UNDEFINED,
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    (vk::PipelineStageFlags2::NONE, vk::AccessFlags2::NONE),
+                    (
+                        vk::PipelineStageFlags2::CLEAR,
+                        vk::AccessFlags2::TRANSFER_WRITE,
+                    ),
+                );
+                device.cmd_clear_color_image(
+                    command_buffer,
+                    image.image(),
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    &vk::ClearColorValue::default(),
+                    &[COLOR_SUBRESOURCE_RANGE],
+                );
+                image_barrier(
+                    device,
+                    command_buffer,
+                    image.image(),
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    Self::LAYOUT,
+                    (
+                        vk::PipelineStageFlags2::CLEAR,
+                        vk::AccessFlags2::TRANSFER_WRITE,
+                    ),
+                    (
+                        vk::PipelineStageFlags2::ALL_COMMANDS,
+                        vk::AccessFlags2::MEMORY_READ | vk::AccessFlags2::MEMORY_WRITE,
+                    ),
+                );
+            }
+            Ok(())
+        })?;
+        Ok(Self { image })
+    }
+
+    pub fn image(&self) -> vk::Image {
+        self.image.image()
+    }
+
+    pub fn view(&self) -> vk::ImageView {
+        self.image.view()
+    }
+
+    pub fn format(&self) -> vk::Format {
+        self.image.format()
+    }
+
+    pub fn extent(&self) -> vk::Extent2D {
+        self.image.extent()
+    }
+}
+
+/// A client texture drawn into an offscreen target
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct OffscreenLayer {
+    pub texture: TextureId,
+    /// Where the texture is stretched to, in target pixels
+    pub rect: Rectangle<i32, Physical>,
+    /// Part of the texture drawn, in texture coordinates, cropped by the surface's viewport
+    pub source: [f32; 4],
+}
+
+/// Draw `layers` into `target` in order, over transparent black
+pub fn render_layers(
+    context: &Arc<Context>,
+    target: &OffscreenTarget,
+    resources: &mut ResourceManager,
+    shapes: &mut ShapeRenderer,
+    layers: &[OffscreenLayer],
+) -> StarforgeResult<()> {
+    let views = layers
+        .iter()
+        .map(|layer| {
+            resources
+                .texture(layer.texture)
+                .map(|texture| texture.view())
+                .ok_or_else(|| {
+                    StarforgeError::RendererError(format!("unknown texture {}", layer.texture.0))
+                })
+        })
+        .collect::<StarforgeResult<Vec<_>>>()?;
+    let mut dmabufs: Vec<_> = layers
+        .iter()
+        .map(|layer| layer.texture)
+        .filter(|&id| {
+            resources
+                .texture(id)
+                .is_some_and(TextureImage::needs_acquire)
+        })
+        .collect();
+    dmabufs.sort_by_key(|id| id.0);
+    dmabufs.dedup();
+
+    let device = context.device();
+    let sampler = shapes.surface_sampler();
+    let (descriptor_pool, sets) = create_texture_sets(
+        context,
+        shapes.texture_set_layout(),
+        layers.len().max(1) as u32,
+    )?;
+    for (&set, &view) in sets.iter().zip(&views) {
+        write_texture_set(context, set, view, sampler);
+    }
+
+    let extent = target.extent();
+    shapes.reset_decodes();
+    let result = context.submit_and_wait(|command_buffer| {
+        for &id in &dmabufs {
+            resources.record_acquire(id, command_buffer);
+        }
+        unsafe {
+            image_barrier(
+                device,
+                command_buffer,
+                target.image(),
+                OffscreenTarget::LAYOUT,
+                vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                (
+                    vk::PipelineStageFlags2::ALL_TRANSFER,
+                    vk::AccessFlags2::TRANSFER_READ,
+                ),
+                (
+                    vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT,
+                    vk::AccessFlags2::COLOR_ATTACHMENT_WRITE,
+                ),
+            );
+            let attachments = [vk::RenderingAttachmentInfo::default()
+                .image_view(target.view())
+                .image_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+                .load_op(vk::AttachmentLoadOp::CLEAR)
+                .store_op(vk::AttachmentStoreOp::STORE)
+                .clear_value(vk::ClearValue::default())];
+            let rendering_info = vk::RenderingInfo::default()
+                .render_area(vk::Rect2D {
+                    offset: vk::Offset2D::default(),
+                    extent,
+                })
+                .layer_count(1)
+                .color_attachments(&attachments);
+            device.cmd_begin_rendering(command_buffer, &rendering_info);
+        }
+        let size = (extent.width as i32, extent.height as i32).into();
+        let drawn = layers.iter().zip(&sets).try_for_each(|(layer, &set)| {
+            shapes.record(
+                command_buffer,
+                target.format(),
+                size,
+                set,
+                layer.rect,
+                layer.source,
+                &WindowShape::default(),
+                &ColorTransform::IDENTITY,
+                1.0,
+            )
+        });
+        unsafe {
+            device.cmd_end_rendering(command_buffer);
+            image_barrier(
+                device,
+                command_buffer,
+                target.image(),
+                vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                OffscreenTarget::LAYOUT,
+                (
+                    vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT,
+                    vk::AccessFlags2::COLOR_ATTACHMENT_WRITE,
+                ),
+                (
+                    vk::PipelineStageFlags2::ALL_TRANSFER,
+                    vk::AccessFlags2::TRANSFER_READ,
+                ),
+            );
+        }
+        for &id in &dmabufs {
+            resources.record_release(id, command_buffer);
+        }
+        drawn
+    });
+
+    unsafe {
+        device.destroy_descriptor_pool(descriptor_pool, None);
+    }
+    result
+}
+
+/// A client buffer a capture is written into
+#[derive(Clone, Copy, Debug)]
+pub enum CaptureTarget<'a> {
+    /// Argb8888 or Xrgb8888 wl_shm buffer
+    Shm(&'a WlBuffer),
+    /// Dma-buf in one of the formats of `StarforgeRenderer::capture_dmabuf_formats`
+    Dmabuf(&'a Dmabuf),
+}
+
+/// Copy `regions` of `source` into `target`, which has the size of the source
+///
+/// With `cursor`, its rectangle is copied as well and `draw_cursor` draws it over the copy,
+/// given the command buffer, attachment format and size while rendering is in progress.
+pub fn capture(
+    context: &Arc<Context>,
+    source: &OffscreenTarget,
+    target: CaptureTarget<'_>,
+    regions: &[Rectangle<i32, Buffer>],
+    cursor: Option<Rectangle<i32, Physical>>,
+    draw_cursor: impl FnOnce(vk::CommandBuffer, vk::Format, Size<i32, Physical>) -> StarforgeResult<()>,
+) -> StarforgeResult<()> {
+    let extent = source.extent();
+    let cursor_region = cursor.map(|rect| {
+        Rectangle::<i32, Buffer>::new(
+            (rect.loc.x, rect.loc.y).into(),
+            (rect.size.w, rect.size.h).into(),
+        )
+    });
+    let rects = clip_regions(regions.iter().copied().chain(cursor_region), extent);
+    if rects.is_empty() {
+        return Ok(());
+    }
+    let cursor = cursor.map(|_| draw_cursor);
+    match target {
+        CaptureTarget::Shm(buffer) => capture_to_shm(context, source, buffer, &rects, cursor),
+        CaptureTarget::Dmabuf(dmabuf) => capture_to_dmabuf(context, source, dmabuf, &rects, cursor),
+    }
+}
+
+/// Clip `regions` to an image of `extent`, dropping those left empty
+fn clip_regions(
+    regions: impl IntoIterator<Item = Rectangle<i32, Buffer>>,
+    extent: vk::Extent2D,
+) -> Vec<Rectangle<i32, Buffer>> {
+    let bounds = Rectangle::from_size((extent.width as i32, extent.height as i32).into());
+    regions
+        .into_iter()
+        .filter_map(|rect| rect.intersection(bounds))
+        .filter(|rect| !rect.is_empty())
+        .collect()
+}
+
+/// Blit `rects` from the offscreen target into `dst`, at the same place
+unsafe fn record_blits(
+    device: &ash::Device,
+    command_buffer: vk::CommandBuffer,
+    source: &OffscreenTarget,
+    dst: vk::Image,
+    dst_layout: vk::ImageLayout,
+    rects: &[Rectangle<i32, Buffer>],
+) {
+    let layers = vk::ImageSubresourceLayers {
+        aspect_mask: vk::ImageAspectFlags::COLOR,
+        mip_level: 0,
+        base_array_layer: 0,
+        layer_count: 1,
+    };
+    let blits: Vec<_> = rects
+        .iter()
+        .map(|rect| {
+            let offsets = [
+                vk::Offset3D {
+                    x: rect.loc.x,
+                    y: rect.loc.y,
+                    z: 0,
+                },
+                vk::Offset3D {
+                    x: rect.loc.x + rect.size.w,
+                    y: rect.loc.y + rect.size.h,
+                    z: 1,
+                },
+            ];
+            vk::ImageBlit::default()
+                .src_subresource(layers)
+                .src_offsets(offsets)
+                .dst_subresource(layers)
+                .dst_offsets(offsets)
+        })
+        .collect();
+    unsafe {
+        device.cmd_blit_image(
+            command_buffer,
+            source.image(),
+            OffscreenTarget::LAYOUT,
+            dst,
+            dst_layout,
+            &blits,
+            vk::Filter::NEAREST,
+        )
+    };
+}
+
+/// Draw the cursor into `view`, keeping what was copied there
+unsafe fn record_cursor_pass(
+    device: &ash::Device,
+    command_buffer: vk::CommandBuffer,
+    view: vk::ImageView,
+    layout: vk::ImageLayout,
+    format: vk::Format,
+    extent: vk::Extent2D,
+    draw_cursor: impl FnOnce(vk::CommandBuffer, vk::Format, Size<i32, Physical>) -> StarforgeResult<()>,
+) -> StarforgeResult<()> {
+    let attachments = [vk::RenderingAttachmentInfo::default()
+        .image_view(view)
+        .image_layout(layout)
+        .load_op(vk::AttachmentLoadOp::LOAD)
+        .store_op(vk::AttachmentStoreOp::STORE)];
+    let rendering_info = vk::RenderingInfo::default()
+        .render_area(vk::Rect2D {
+            offset: vk::Offset2D::default(),
+            extent,
+        })
+        .layer_count(1)
+        .color_attachments(&attachments);
+    unsafe { device.cmd_begin_rendering(command_buffer, &rendering_info) };
+    let result = draw_cursor(
+        command_buffer,
+        format,
+        (extent.width as i32, extent.height as i32).into(),
+    );
+    unsafe { device.cmd_end_rendering(command_buffer) };
+    result
+}
+
+/// Draw over what `target` shows with `draw`, given the command buffer, attachment format
+/// and size while rendering is in progress
+pub fn draw_over(
+    context: &Arc<Context>,
+    target: &OffscreenTarget,
+    draw: impl FnOnce(vk::CommandBuffer, vk::Format, Size<i32, Physical>) -> StarforgeResult<()>,
+) -> StarforgeResult<()> {
+    let attachment = (
+        vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT,
+        vk::AccessFlags2::COLOR_ATTACHMENT_READ | vk::AccessFlags2::COLOR_ATTACHMENT_WRITE,
+    );
+    let any = (
+        vk::PipelineStageFlags2::ALL_COMMANDS,
+        vk::AccessFlags2::MEMORY_READ | vk::AccessFlags2::MEMORY_WRITE,
+    );
+    context.submit_and_wait(|command_buffer| {
+        let device = context.device();
+        unsafe {
+            image_barrier(
+                device,
+                command_buffer,
+                target.image(),
+                OffscreenTarget::LAYOUT,
+                vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                any,
+                attachment,
+            );
+            record_cursor_pass(
+                device,
+                command_buffer,
+                target.view(),
+                vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                target.format(),
+                target.extent(),
+                draw,
+            )?;
+            image_barrier(
+                device,
+                command_buffer,
+                target.image(),
+                vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                OffscreenTarget::LAYOUT,
+                attachment,
+                any,
+            );
+        }
+        Ok(())
+    })
+}
+
+/// Copy through an intermediate image and a host-visible buffer into shared memory
+fn capture_to_shm(
+    context: &Arc<Context>,
+    source: &OffscreenTarget,
+    buffer: &WlBuffer,
+    rects: &[Rectangle<i32, Buffer>],
+    draw_cursor: Option<
+        impl FnOnce(vk::CommandBuffer, vk::Format, Size<i32, Physical>) -> StarforgeResult<()>,
+    >,
+) -> StarforgeResult<()> {
+    let extent = source.extent();
+    let data = with_buffer_contents(buffer, |_, _, data| data)
+        .map_err(|e| StarforgeError::RendererError(e.to_string()))?;
+    if !matches!(
+        data.format,
+        wl_shm::Format::Argb8888 | wl_shm::Format::Xrgb8888
+    ) {
+        return Err(StarforgeError::RendererError(format!(
+            "unsupported capture shm format {:?}",
+            data.format
+        )));
+    }
+    if (data.width as u32, data.height as u32) != (extent.width, extent.height) {
+        return Err(StarforgeError::RendererError(format!(
+            "capture buffer of {}x{} for a source of {}x{}",
+            data.width, data.height, extent.width, extent.height
+        )));
+    }
+
+    let intermediate = AllocatedImage::new(
+        context.clone(),
+        extent,
+        CAPTURE_FORMAT,
+        vk::ImageUsageFlags::TRANSFER_DST
+            | vk::ImageUsageFlags::TRANSFER_SRC
+            | vk::ImageUsageFlags::COLOR_ATTACHMENT,
+    )?;
+    let staging = MappedBuffer::readback(
+        context.clone(),
+        extent.width as vk::DeviceSize * extent.height as vk::DeviceSize * 4,
+        vk::BufferUsageFlags::TRANSFER_DST,
+    )?;
+
+    context.submit_and_wait(|command_buffer| {
+        let device = context.device();
+        unsafe {
+            image_barrier(
+                device,
+                command_buffer,
+                intermediate.image(),
+                vk::ImageLayout::UNDEFINED,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                (vk::PipelineStageFlags2::NONE, vk::AccessFlags2::NONE),
+                (
+                    vk::PipelineStageFlags2::BLIT,
+                    vk::AccessFlags2::TRANSFER_WRITE,
+                ),
+            );
+            record_blits(
+                device,
+                command_buffer,
+                source,
+                intermediate.image(),
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                rects,
+            );
+            let written = match draw_cursor {
+                Some(draw_cursor) => {
+                    image_barrier(
+                        device,
+                        command_buffer,
+                        intermediate.image(),
+                        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                        vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                        (
+                            vk::PipelineStageFlags2::BLIT,
+                            vk::AccessFlags2::TRANSFER_WRITE,
+                        ),
+                        (
+                            vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT,
+                            vk::AccessFlags2::COLOR_ATTACHMENT_READ
+                                | vk::AccessFlags2::COLOR_ATTACHMENT_WRITE,
+                        ),
+                    );
+                    record_cursor_pass(
+                        device,
+                        command_buffer,
+                        intermediate.view(),
+                        vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                        CAPTURE_FORMAT,
+                        extent,
+                        draw_cursor,
+                    )?;
+                    (
+                        vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                        vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT,
+                        vk::AccessFlags2::COLOR_ATTACHMENT_WRITE,
+                    )
+                }
+                None => (
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    vk::PipelineStageFlags2::BLIT,
+                    vk::AccessFlags2::TRANSFER_WRITE,
+                ),
+            };
+            image_barrier(
+                device,
+                command_buffer,
+                intermediate.image(),
+                written.0,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                (written.1, written.2),
+                (
+                    vk::PipelineStageFlags2::COPY,
+                    vk::AccessFlags2::TRANSFER_READ,
+                ),
+            );
+
+            let copies: Vec<_> = rects
+                .iter()
+                .map(|rect| {
+                    vk::BufferImageCopy::default()
+                        .buffer_offset(
+                            (rect.loc.y as vk::DeviceSize * extent.width as vk::DeviceSize
+                                + rect.loc.x as vk::DeviceSize)
+                                * 4,
+                        )
+                        .buffer_row_length(extent.width)
+                        .image_subresource(vk::ImageSubresourceLayers {
+                            aspect_mask: vk::ImageAspectFlags::COLOR,
+                            mip_level: 0,
+                            base_array_layer: 0,
+                            layer_count: 1,
+                        })
+                        .image_offset(vk::Offset3D {
+                            x: rect.loc.x,
+                            y: rect.loc.y,
+                            z: 0,
+                        })
+                        .image_extent(vk::Extent3D {
+                            width: rect.size.w as u32,
+                            height: rect.size.h as u32,
+                            depth: 1,
+                        })
+                })
+                .collect();
+            device.cmd_copy_image_to_buffer(
+                command_buffer,
+                intermediate.image(),
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                staging.buffer(),
+                &copies,
+            );
+            let barriers = [vk::MemoryBarrier2::default()
+                .src_stage_mask(vk::PipelineStageFlags2::COPY)
+                .src_access_mask(vk::AccessFlags2::TRANSFER_WRITE)
+                .dst_stage_mask(vk::PipelineStageFlags2::HOST)
+                .dst_access_mask(vk::AccessFlags2::HOST_READ)];
+            let dependency = vk::DependencyInfo::default().memory_barriers(&barriers);
+            device.cmd_pipeline_barrier2(command_buffer, &dependency);
+        }
+        Ok(())
+    })?;
+
+    let pixels = staging.read()?;
+    let src_stride = extent.width as usize * 4;
+    with_buffer_contents_mut(buffer, |ptr, len, data| {
+        let BufferData { offset, stride, .. } = data;
+        for rect in rects {
+            for (src, dst, size) in row_copies(*rect, src_stride, offset as usize, stride as usize)
+            {
+                if dst + size > len {
+                    return Err(StarforgeError::RendererError(
+                        "shm buffer exceeds its pool".into(),
+                    ));
+                }
+                // The client may write the pool concurrently, so no slice is made of it
+                unsafe {
+                    std::ptr::copy_nonoverlapping(pixels[src..].as_ptr(), ptr.add(dst), size)
+                };
+            }
+        }
+        Ok(())
+    })
+    .map_err(|e| StarforgeError::RendererError(e.to_string()))?
+}
+
+/// Byte ranges to copy row by row for `rect`, as source offset, destination offset and
+/// length, between 4-byte-per-pixel images with the given strides
+fn row_copies(
+    rect: Rectangle<i32, Buffer>,
+    src_stride: usize,
+    dst_offset: usize,
+    dst_stride: usize,
+) -> impl Iterator<Item = (usize, usize, usize)> {
+    let x = rect.loc.x as usize * 4;
+    let size = rect.size.w as usize * 4;
+    (rect.loc.y as usize..(rect.loc.y + rect.size.h) as usize).map(move |row| {
+        (
+            row * src_stride + x,
+            dst_offset + row * dst_stride + x,
+            size,
+        )
+    })
+}
+
+/// Blit straight into the client's dma-buf
+fn capture_to_dmabuf(
+    context: &Arc<Context>,
+    source: &OffscreenTarget,
+    dmabuf: &Dmabuf,
+    rects: &[Rectangle<i32, Buffer>],
+    draw_cursor: Option<
+        impl FnOnce(vk::CommandBuffer, vk::Format, Size<i32, Physical>) -> StarforgeResult<()>,
+    >,
+) -> StarforgeResult<()> {
+    let mut target = DmabufImage::import_with_usage(context.clone(), dmabuf, CAPTURE_USAGE)?;
+    let extent = source.extent();
+    if target.extent() != extent {
+        return Err(StarforgeError::RendererError(format!(
+            "capture buffer of {}x{} for a source of {}x{}",
+            target.extent().width,
+            target.extent().height,
+            extent.width,
+            extent.height
+        )));
+    }
+
+    context.submit_and_wait(|command_buffer| {
+        let device = context.device();
+        target.record_acquire_for_writing(command_buffer);
+        unsafe {
+            record_blits(
+                device,
+                command_buffer,
+                source,
+                target.image(),
+                vk::ImageLayout::GENERAL,
+                rects,
+            );
+            if let Some(draw_cursor) = draw_cursor {
+                let barriers = [vk::MemoryBarrier2::default()
+                    .src_stage_mask(vk::PipelineStageFlags2::BLIT)
+                    .src_access_mask(vk::AccessFlags2::TRANSFER_WRITE)
+                    .dst_stage_mask(vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT)
+                    .dst_access_mask(
+                        vk::AccessFlags2::COLOR_ATTACHMENT_READ
+                            | vk::AccessFlags2::COLOR_ATTACHMENT_WRITE,
+                    )];
+                let dependency = vk::DependencyInfo::default().memory_barriers(&barriers);
+                device.cmd_pipeline_barrier2(command_buffer, &dependency);
+                record_cursor_pass(
+                    device,
+                    command_buffer,
+                    target.view(),
+                    vk::ImageLayout::GENERAL,
+                    target.format(),
+                    extent,
+                    draw_cursor,
+                )?;
+            }
+        }
+        target.record_release_after_writing(command_buffer);
+        Ok(())
+    })
+}
+
+/// Read the whole of `source` back, as rows of B8G8R8A8 pixels without padding
+pub fn read_pixels(context: &Arc<Context>, source: &OffscreenTarget) -> StarforgeResult<Vec<u8>> {
+    let extent = source.extent();
+    let staging = MappedBuffer::readback(
+        context.clone(),
+        extent.width as vk::DeviceSize * extent.height as vk::DeviceSize * 4,
+        vk::BufferUsageFlags::TRANSFER_DST,
+    )?;
+
+    context.submit_and_wait(|command_buffer| {
+        let device = context.device();
+        let copy = vk::BufferImageCopy::default()
+            .buffer_row_length(extent.width)
+            .image_subresource(vk::ImageSubresourceLayers {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                mip_level: 0,
+                base_array_layer: 0,
+                layer_count: 1,
+            })
+            .image_extent(vk::Extent3D {
+                width: extent.width,
+                height: extent.height,
+                depth: 1,
+            });
+        unsafe {
+            device.cmd_copy_image_to_buffer(
+                command_buffer,
+                source.image(),
+                OffscreenTarget::LAYOUT,
+                staging.buffer(),
+                &[copy],
+            );
+            let barriers = [vk::MemoryBarrier2::default()
+                .src_stage_mask(vk::PipelineStageFlags2::COPY)
+                .src_access_mask(vk::AccessFlags2::TRANSFER_WRITE)
+                .dst_stage_mask(vk::PipelineStageFlags2::HOST)
+                .dst_access_mask(vk::AccessFlags2::HOST_READ)];
+            let dependency = vk::DependencyInfo::default().memory_barriers(&barriers);
+            device.cmd_pipeline_barrier2(command_buffer, &dependency);
+        }
+        Ok(())
+    })?;
+
+    Ok(staging.read()?.to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CursorTexture, StarforgeRenderer};
+
+    fn rect(x: i32, y: i32, w: i32, h: i32) -> Rectangle<i32, Buffer> {
+        Rectangle::new((x, y).into(), (w, h).into())
+    }
+
+    #[test]
+    fn regions_are_clipped_to_the_source() {
+        let extent = vk::Extent2D {
+            width: 100,
+            height: 50,
+        };
+        assert_eq!(
+            clip_regions([rect(90, 40, 20, 20), rect(200, 0, 5, 5)], extent),
+            vec![rect(90, 40, 10, 10)]
+        );
+    }
+
+    #[test]
+    fn offscreen_rendering_replaces_what_the_target_showed() {
+        // Machines without a Vulkan device skip this
+        let Ok(renderer) = StarforgeRenderer::new() else {
+            return;
+        };
+        let size = Size::from((4, 4));
+        renderer.upload_cursor_frame(1, size, &[255; 64]).unwrap();
+        let target = renderer.create_offscreen_target(size).unwrap();
+        draw_over(
+            &renderer.context,
+            &target,
+            |command_buffer, format, target_size| {
+                renderer.draw_cursor(
+                    command_buffer,
+                    format,
+                    target_size,
+                    CursorTexture::Named(1),
+                    Rectangle::from_size(size),
+                    &ColorTransform::IDENTITY,
+                )
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            read_pixels(&renderer.context, &target).unwrap(),
+            vec![255; 64]
+        );
+
+        renderer.render_offscreen(&target, &[]).unwrap();
+        assert_eq!(
+            read_pixels(&renderer.context, &target).unwrap(),
+            vec![0; 64]
+        );
+    }
+
+    #[test]
+    fn rows_follow_both_strides() {
+        let copies: Vec<_> = row_copies(rect(2, 1, 3, 2), 40, 16, 64).collect();
+        assert_eq!(copies, vec![(48, 16 + 64 + 8, 12), (88, 16 + 128 + 8, 12)]);
+    }
+}
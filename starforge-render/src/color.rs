@@ -1,3 +1,1163 @@
 //! Starforge Render - Color Management
 //!
 //! This module manages color spaces, transform logic, and the tone mapping pipeline.
+//!
+//! Blending always happens in a linear working space. Client content is decoded with its
+//! transfer function, converted to the working primaries and scaled so that `1.0` is SDR
+//! reference white. The blended result is then encoded once per output for its color space.
+
+use ash::vk;
+use starforge_core::protocols::color_management::{
+    ImageDescription, NamedTransferFunction, PrimaryChromaticities,
+};
+
+/// Luminance of SDR reference white in cd/m² (ITU-R BT.2408)
+pub const REFERENCE_WHITE_NITS: f32 = 203.0;
+/// Highest luminance the PQ transfer function can encode, in cd/m²
+pub const PQ_MAX_NITS: f32 = 10_000.0;
+/// Nominal peak luminance of an HLG display, in cd/m²
+pub const HLG_PEAK_NITS: f32 = 1_000.0;
+
+/// GLSL implementation of the transfer functions, shared by the compositing shaders
+pub const COLOR_GLSL: &str = include_str!("../shaders/color.glsl");
+
+/// Insert [`COLOR_GLSL`] into a shader's source, after its `#version` directive
+pub(crate) fn with_color_glsl(source: &str) -> String {
+    let (version, body) = source.split_once('\n').unwrap_or((source, ""));
+    format!("{version}\n{COLOR_GLSL}\n{body}")
+}
+
+// SMPTE ST 2084 (PQ) constants
+const PQ_M1: f32 = 2610.0 / 16384.0;
+const PQ_M2: f32 = 2523.0 / 4096.0 * 128.0;
+const PQ_C1: f32 = 3424.0 / 4096.0;
+const PQ_C2: f32 = 2413.0 / 4096.0 * 32.0;
+const PQ_C3: f32 = 2392.0 / 4096.0 * 32.0;
+
+// ARIB STD-B67 (HLG) constants
+const HLG_A: f32 = 0.178_832_77;
+const HLG_B: f32 = 0.284_668_92;
+const HLG_C: f32 = 0.559_910_7;
+const HLG_SYSTEM_GAMMA: f32 = 1.2;
+
+/// Luma coefficients of BT.2020, used by the HLG OOTF
+const BT2020_LUMA: [f32; 3] = [0.2627, 0.6780, 0.0593];
+
+/// Transfer characteristics of an encoded signal
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum TransferFunction {
+    /// Linear light, no encoding
+    Linear,
+    /// IEC 61966-2-1 piecewise sRGB curve
+    Srgb,
+    /// Pure power curve with an exponent of 2.2
+    Gamma22,
+    /// SMPTE ST 2084 perceptual quantizer
+    Pq,
+    /// ARIB STD-B67 hybrid log-gamma
+    Hlg,
+}
+
+impl TransferFunction {
+    /// Decode a signal value into linear light.
+    ///
+    /// SDR curves return relative light where `1.0` is the display's white. PQ returns
+    /// absolute luminance normalized to 10000 cd/m², and HLG returns normalized scene
+    /// light (the inverse OETF; see [`hlg_ootf`] for the display mapping).
+    pub fn eotf(self, encoded: f32) -> f32 {
+        let e = encoded.abs();
+        let linear = match self {
+            TransferFunction::Linear => e,
+            TransferFunction::Srgb => {
+                if e <= 0.04045 {
+                    e / 12.92
+                } else {
+                    ((e + 0.055) / 1.055).powf(2.4)
+                }
+            }
+            TransferFunction::Gamma22 => e.powf(2.2),
+            TransferFunction::Pq => {
+                let p = e.powf(1.0 / PQ_M2);
+                ((p - PQ_C1).max(0.0) / (PQ_C2 - PQ_C3 * p)).powf(1.0 / PQ_M1)
+            }
+            TransferFunction::Hlg => {
+                if e <= 0.5 {
+                    e * e / 3.0
+                } else {
+                    (((e - HLG_C) / HLG_A).exp() + HLG_B) / 12.0
+                }
+            }
+        };
+        // Extended-range content (scRGB) carries negative values, which mirror the curve
+        linear.copysign(encoded)
+    }
+
+    /// Encode linear light into a signal value, the inverse of [`TransferFunction::eotf`]
+    pub fn inverse_eotf(self, linear: f32) -> f32 {
+        let l = linear.abs();
+        let encoded = match self {
+            TransferFunction::Linear => l,
+            TransferFunction::Srgb => {
+                if l <= 0.003_130_8 {
+                    l * 12.92
+                } else {
+                    1.055 * l.powf(1.0 / 2.4) - 0.055
+                }
+            }
+            TransferFunction::Gamma22 => l.powf(1.0 / 2.2),
+            TransferFunction::Pq => {
+                let p = l.powf(PQ_M1);
+                ((PQ_C1 + PQ_C2 * p) / (1.0 + PQ_C3 * p)).powf(PQ_M2)
+            }
+            TransferFunction::Hlg => {
+                if l <= 1.0 / 12.0 {
+                    (3.0 * l).sqrt()
+                } else {
+                    HLG_A * (12.0 * l - HLG_B).ln() + HLG_C
+                }
+            }
+        };
+        encoded.copysign(linear)
+    }
+
+    /// Luminance in cd/m² represented by a decoded value of `1.0`
+    pub fn nominal_peak_nits(self) -> f32 {
+        match self {
+            TransferFunction::Pq => PQ_MAX_NITS,
+            TransferFunction::Hlg => HLG_PEAK_NITS,
+            _ => REFERENCE_WHITE_NITS,
+        }
+    }
+
+    /// Whether the transfer function is meant for high dynamic range content
+    pub fn is_hdr(self) -> bool {
+        matches!(self, TransferFunction::Pq | TransferFunction::Hlg)
+    }
+
+    /// Identifier used by the `color.glsl` shader functions
+    pub fn shader_id(self) -> u32 {
+        match self {
+            TransferFunction::Linear => 0,
+            TransferFunction::Srgb => 1,
+            TransferFunction::Gamma22 => 2,
+            TransferFunction::Pq => 3,
+            TransferFunction::Hlg => 4,
+        }
+    }
+}
+
+/// Apply the HLG OOTF, mapping normalized scene light to normalized display light
+pub fn hlg_ootf(rgb: [f32; 3]) -> [f32; 3] {
+    let y = dot(BT2020_LUMA, rgb);
+    if y <= 0.0 {
+        return [0.0; 3];
+    }
+    let gain = y.powf(HLG_SYSTEM_GAMMA - 1.0);
+    rgb.map(|c| c * gain)
+}
+
+/// Inverse of [`hlg_ootf`]
+pub fn hlg_inverse_ootf(rgb: [f32; 3]) -> [f32; 3] {
+    let y = dot(BT2020_LUMA, rgb);
+    if y <= 0.0 {
+        return [0.0; 3];
+    }
+    let gain = y.powf((1.0 - HLG_SYSTEM_GAMMA) / HLG_SYSTEM_GAMMA);
+    rgb.map(|c| c * gain)
+}
+
+impl From<NamedTransferFunction> for TransferFunction {
+    fn from(tf: NamedTransferFunction) -> Self {
+        match tf {
+            NamedTransferFunction::Srgb => TransferFunction::Srgb,
+            NamedTransferFunction::Gamma22 => TransferFunction::Gamma22,
+            NamedTransferFunction::ExtLinear => TransferFunction::Linear,
+            NamedTransferFunction::St2084Pq => TransferFunction::Pq,
+            NamedTransferFunction::Hlg => TransferFunction::Hlg,
+        }
+    }
+}
+
+/// CIE 1931 xy chromaticity coordinates of a set of primaries and its white point
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Chromaticities {
+    pub red: [f32; 2],
+    pub green: [f32; 2],
+    pub blue: [f32; 2],
+    pub white: [f32; 2],
+}
+
+/// CIE standard illuminant D65
+const D65: [f32; 2] = [0.3127, 0.3290];
+
+impl Chromaticities {
+    /// Matrix converting linear RGB in these primaries to CIE XYZ, or `None` if the
+    /// primaries are not linearly independent
+    pub fn rgb_to_xyz(&self) -> Option<Mat3> {
+        let [r, g, b] = [self.red, self.green, self.blue].map(xy_to_xyz);
+        let primaries = Mat3::from_columns([r, g, b]);
+        // Scale each primary so that RGB (1, 1, 1) lands on the white point
+        let s = primaries.inverse()?.apply(xy_to_xyz(self.white));
+        let m = Mat3::from_columns([
+            r.map(|v| v * s[0]),
+            g.map(|v| v * s[1]),
+            b.map(|v| v * s[2]),
+        ]);
+        m.is_finite().then_some(m)
+    }
+
+    /// Matrix converting CIE XYZ to linear RGB in these primaries, or `None` if the
+    /// primaries are not linearly independent
+    pub fn xyz_to_rgb(&self) -> Option<Mat3> {
+        self.rgb_to_xyz()?.inverse()
+    }
+
+    /// Matrix converting linear RGB from these primaries into `target`, or `None` if
+    /// either set of primaries is not linearly independent.
+    ///
+    /// A Bradford chromatic adaptation is applied when the white points differ.
+    pub fn conversion_to(&self, target: &Chromaticities) -> Option<Mat3> {
+        let mut to_xyz = self.rgb_to_xyz()?;
+        if self.white != target.white {
+            to_xyz = bradford_adaptation(self.white, target.white).mul(&to_xyz);
+        }
+        let m = target.xyz_to_rgb()?.mul(&to_xyz);
+        m.is_finite().then_some(m)
+    }
+}
+
+impl From<PrimaryChromaticities> for Chromaticities {
+    fn from(primaries: PrimaryChromaticities) -> Self {
+        Self {
+            red: primaries.red,
+            green: primaries.green,
+            blue: primaries.blue,
+            white: primaries.white,
+        }
+    }
+}
+
+/// Well-known sets of color primaries
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Primaries {
+    /// ITU-R BT.709, shared with sRGB
+    Bt709,
+    /// Display P3 (DCI-P3 primaries with a D65 white point)
+    DisplayP3,
+    /// ITU-R BT.2020, shared with BT.2100
+    Bt2020,
+}
+
+impl Primaries {
+    /// The chromaticity coordinates of the primaries
+    pub fn chromaticities(self) -> Chromaticities {
+        match self {
+            Primaries::Bt709 => Chromaticities {
+                red: [0.640, 0.330],
+                green: [0.300, 0.600],
+                blue: [0.150, 0.060],
+                white: D65,
+            },
+            Primaries::DisplayP3 => Chromaticities {
+                red: [0.680, 0.320],
+                green: [0.265, 0.690],
+                blue: [0.150, 0.060],
+                white: D65,
+            },
+            Primaries::Bt2020 => Chromaticities {
+                red: [0.708, 0.292],
+                green: [0.170, 0.797],
+                blue: [0.131, 0.046],
+                white: D65,
+            },
+        }
+    }
+
+    /// Matrix converting linear RGB from these primaries into `target`
+    pub fn conversion_to(self, target: Primaries) -> Mat3 {
+        if self == target {
+            return Mat3::IDENTITY;
+        }
+        self.chromaticities()
+            .conversion_to(&target.chromaticities())
+            .expect("well-known primaries are linearly independent")
+    }
+}
+
+/// A color space made of a set of primaries and a transfer function
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ColorSpace {
+    pub primaries: Primaries,
+    pub transfer: TransferFunction,
+}
+
+impl ColorSpace {
+    pub const SRGB: Self = Self::new(Primaries::Bt709, TransferFunction::Srgb);
+    pub const EXTENDED_SRGB_LINEAR: Self = Self::new(Primaries::Bt709, TransferFunction::Linear);
+    pub const DISPLAY_P3: Self = Self::new(Primaries::DisplayP3, TransferFunction::Srgb);
+    pub const BT2100_PQ: Self = Self::new(Primaries::Bt2020, TransferFunction::Pq);
+    pub const BT2100_HLG: Self = Self::new(Primaries::Bt2020, TransferFunction::Hlg);
+    pub const BT2020_LINEAR: Self = Self::new(Primaries::Bt2020, TransferFunction::Linear);
+
+    pub const fn new(primaries: Primaries, transfer: TransferFunction) -> Self {
+        Self {
+            primaries,
+            transfer,
+        }
+    }
+
+    /// Map a Vulkan surface color space to its description, if it is supported
+    pub fn from_vk(color_space: vk::ColorSpaceKHR) -> Option<Self> {
+        Some(match color_space {
+            vk::ColorSpaceKHR::SRGB_NONLINEAR => Self::SRGB,
+            vk::ColorSpaceKHR::EXTENDED_SRGB_LINEAR_EXT => Self::EXTENDED_SRGB_LINEAR,
+            vk::ColorSpaceKHR::DISPLAY_P3_NONLINEAR_EXT => Self::DISPLAY_P3,
+            vk::ColorSpaceKHR::DISPLAY_P3_LINEAR_EXT => {
+                Self::new(Primaries::DisplayP3, TransferFunction::Linear)
+            }
+            vk::ColorSpaceKHR::HDR10_ST2084_EXT => Self::BT2100_PQ,
+            vk::ColorSpaceKHR::HDR10_HLG_EXT => Self::BT2100_HLG,
+            vk::ColorSpaceKHR::BT2020_LINEAR_EXT => Self::BT2020_LINEAR,
+            _ => return None,
+        })
+    }
+
+    /// Map the description to a Vulkan surface color space, if one exists
+    pub fn to_vk(self) -> Option<vk::ColorSpaceKHR> {
+        Some(match (self.primaries, self.transfer) {
+            (Primaries::Bt709, TransferFunction::Srgb) => vk::ColorSpaceKHR::SRGB_NONLINEAR,
+            (Primaries::Bt709, TransferFunction::Linear) => {
+                vk::ColorSpaceKHR::EXTENDED_SRGB_LINEAR_EXT
+            }
+            (Primaries::DisplayP3, TransferFunction::Srgb) => {
+                vk::ColorSpaceKHR::DISPLAY_P3_NONLINEAR_EXT
+            }
+            (Primaries::DisplayP3, TransferFunction::Linear) => {
+                vk::ColorSpaceKHR::DISPLAY_P3_LINEAR_EXT
+            }
+            (Primaries::Bt2020, TransferFunction::Pq) => vk::ColorSpaceKHR::HDR10_ST2084_EXT,
+            (Primaries::Bt2020, TransferFunction::Hlg) => vk::ColorSpaceKHR::HDR10_HLG_EXT,
+            (Primaries::Bt2020, TransferFunction::Linear) => vk::ColorSpaceKHR::BT2020_LINEAR_EXT,
+            _ => return None,
+        })
+    }
+}
+
+impl Default for ColorSpace {
+    fn default() -> Self {
+        Self::SRGB
+    }
+}
+
+/// A transform from one encoding to another: decode, convert linear RGB, re-encode
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ColorTransform {
+    /// Transfer function used to decode the input
+    pub input: TransferFunction,
+    /// Tone mapping applied to the decoded values before the primaries conversion
+    pub tone_mapper: Option<ToneMapper>,
+    /// Primaries conversion, with the luminance scaling folded in
+    pub matrix: Mat3,
+    /// Transfer function used to encode the output
+    pub output: TransferFunction,
+    /// Gamma and brightness adjustment of the encoded output
+    pub curve: Option<GammaCurve>,
+}
+
+impl ColorTransform {
+    /// Leaves linear values as they are, for content already in the target's encoding
+    pub const IDENTITY: Self = Self {
+        input: TransferFunction::Linear,
+        tone_mapper: None,
+        matrix: Mat3::IDENTITY,
+        output: TransferFunction::Linear,
+        curve: None,
+    };
+
+    /// Add a tone mapping step to the transform
+    pub fn with_tone_mapping(mut self, tone_mapper: ToneMapper) -> Self {
+        self.tone_mapper = Some(tone_mapper);
+        self
+    }
+
+    /// Run the transform on a single RGB value, as the shaders do per pixel
+    pub fn apply(&self, rgb: [f32; 3]) -> [f32; 3] {
+        let mut linear = rgb.map(|c| self.input.eotf(c));
+        if self.input == TransferFunction::Hlg {
+            linear = hlg_ootf(linear);
+        }
+        if let Some(tone_mapper) = &self.tone_mapper {
+            linear = tone_mapper.apply(linear);
+        }
+        let mut converted = self.matrix.apply(linear);
+        if self.output == TransferFunction::Hlg {
+            converted = hlg_inverse_ootf(converted);
+        }
+        let encoded = converted.map(|c| self.output.inverse_eotf(c));
+        match &self.curve {
+            Some(curve) => encoded.map(|c| curve.apply(c)),
+            None => encoded,
+        }
+    }
+
+    /// The transform laid out for a std140 uniform block
+    pub fn to_uniform(&self) -> ColorTransformUniform {
+        let c = self.matrix.columns();
+        let tone_mapper = self.tone_mapper.unwrap_or(ToneMapper {
+            operator: ToneMapping::Clip,
+            luma: [0.0; 3],
+            source_peak: 1.0,
+            target_peak: 1.0,
+        });
+        let [r, g, b] = tone_mapper.luma;
+        let curve = self.curve.unwrap_or_default();
+        ColorTransformUniform {
+            matrix: c.map(|col| [col[0], col[1], col[2], 0.0]),
+            luma: [r, g, b, 0.0],
+            input_transfer: self.input.shader_id(),
+            output_transfer: self.output.shader_id(),
+            tone_mapping: match self.tone_mapper {
+                Some(tone_mapper) => tone_mapper.operator.shader_id(),
+                None => TONE_MAPPING_NONE,
+            },
+            source_peak: tone_mapper.source_peak,
+            target_peak: tone_mapper.target_peak,
+            gamma: curve.gamma,
+            brightness: curve.brightness,
+            _padding: 0,
+        }
+    }
+}
+
+/// GPU layout of a [`ColorTransform`], matching `ColorTransform` in `color.glsl`
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ColorTransformUniform {
+    /// Column-major 3x3 matrix, each column padded to a vec4
+    pub matrix: [[f32; 4]; 3],
+    /// Luma coefficients used by the tone mapping step
+    pub luma: [f32; 4],
+    pub input_transfer: u32,
+    pub output_transfer: u32,
+    pub tone_mapping: u32,
+    pub source_peak: f32,
+    pub target_peak: f32,
+    pub gamma: f32,
+    pub brightness: f32,
+    pub _padding: u32,
+}
+
+/// A gamma and brightness adjustment of encoded values, `(value * brightness) ^ (1 / gamma)`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GammaCurve {
+    /// Above 1 brightens mid-tones, below 1 darkens them
+    pub gamma: f32,
+    /// Factor applied before the gamma, 1 leaves the peak unchanged
+    pub brightness: f32,
+}
+
+impl Default for GammaCurve {
+    fn default() -> Self {
+        Self {
+            gamma: 1.0,
+            brightness: 1.0,
+        }
+    }
+}
+
+impl GammaCurve {
+    pub fn apply(&self, encoded: f32) -> f32 {
+        (encoded * self.brightness)
+            .clamp(0.0, 1.0)
+            .powf(1.0 / self.gamma)
+    }
+
+    pub fn is_identity(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// Shader identifier for a transform without a tone mapping step
+const TONE_MAPPING_NONE: u32 = u32::MAX;
+
+/// Operators that compress HDR content into the range of a dimmer display
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum ToneMapping {
+    /// Hard clip at the display peak
+    Clip,
+    /// Extended Reinhard curve, reaching the display peak at the content peak
+    Reinhard,
+    /// ITU-R BT.2390 EETF, a hermite roll-off in the PQ domain
+    #[default]
+    Bt2390,
+    /// John Hable's filmic curve, as used in Uncharted 2
+    Hable,
+}
+
+impl ToneMapping {
+    /// Map a luminance value in `[0, source_peak]` into `[0, target_peak]`.
+    ///
+    /// All values are relative to reference white, like the working space.
+    pub fn map(self, luminance: f32, source_peak: f32, target_peak: f32) -> f32 {
+        if luminance <= 0.0 {
+            return 0.0;
+        }
+        if source_peak <= target_peak {
+            return luminance.min(target_peak);
+        }
+        match self {
+            ToneMapping::Clip => luminance.min(target_peak),
+            ToneMapping::Reinhard => {
+                let x = luminance / target_peak;
+                let white = source_peak / target_peak;
+                (x * (1.0 + x / (white * white)) / (1.0 + x)).min(1.0) * target_peak
+            }
+            ToneMapping::Bt2390 => bt2390_eetf(luminance, source_peak, target_peak),
+            ToneMapping::Hable => {
+                let white = hable_curve(source_peak);
+                (hable_curve(luminance.min(source_peak)) / white) * target_peak
+            }
+        }
+    }
+
+    /// Identifier used by the `color.glsl` shader functions
+    pub fn shader_id(self) -> u32 {
+        match self {
+            ToneMapping::Clip => 0,
+            ToneMapping::Reinhard => 1,
+            ToneMapping::Bt2390 => 2,
+            ToneMapping::Hable => 3,
+        }
+    }
+}
+
+/// Parameters of a tone mapping step inside a [`ColorTransform`]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ToneMapper {
+    pub operator: ToneMapping,
+    /// Luma coefficients of the primaries the step operates in
+    pub luma: [f32; 3],
+    /// Peak luminance of the content, relative to reference white
+    pub source_peak: f32,
+    /// Peak luminance of the display, relative to reference white
+    pub target_peak: f32,
+}
+
+impl ToneMapper {
+    /// Tone map a linear RGB value, scaling all channels by the luminance ratio to keep hue
+    pub fn apply(&self, rgb: [f32; 3]) -> [f32; 3] {
+        let luminance = dot(self.luma, rgb);
+        if luminance <= 0.0 {
+            return [0.0; 3];
+        }
+        let mapped = self
+            .operator
+            .map(luminance, self.source_peak, self.target_peak);
+        rgb.map(|c| c * mapped / luminance)
+    }
+}
+
+/// ITU-R BT.2390 EETF with a black level of zero
+fn bt2390_eetf(luminance: f32, source_peak: f32, target_peak: f32) -> f32 {
+    // Work on PQ code values normalized to the content range
+    let pq = |l: f32| TransferFunction::Pq.inverse_eotf(l * REFERENCE_WHITE_NITS / PQ_MAX_NITS);
+    let source_max = pq(source_peak);
+    let e1 = pq(luminance) / source_max;
+    let max_lum = pq(target_peak) / source_max;
+
+    let knee_start = 1.5 * max_lum - 0.5;
+    let e2 = if e1 < knee_start {
+        e1
+    } else {
+        // Hermite spline from the knee to the target peak
+        let t = (e1 - knee_start) / (1.0 - knee_start);
+        let t2 = t * t;
+        let t3 = t2 * t;
+        (2.0 * t3 - 3.0 * t2 + 1.0) * knee_start
+            + (t3 - 2.0 * t2 + t) * (1.0 - knee_start)
+            + (-2.0 * t3 + 3.0 * t2) * max_lum
+    };
+
+    TransferFunction::Pq.eotf(e2.min(1.0) * source_max) * PQ_MAX_NITS / REFERENCE_WHITE_NITS
+}
+
+/// Hable's filmic curve before white point normalization
+fn hable_curve(x: f32) -> f32 {
+    const A: f32 = 0.15; // Shoulder strength
+    const B: f32 = 0.50; // Linear strength
+    const C: f32 = 0.10; // Linear angle
+    const D: f32 = 0.20; // Toe strength
+    const E: f32 = 0.02; // Toe numerator
+    const F: f32 = 0.30; // Toe denominator
+    ((x * (A * x + C * B) + D * E) / (x * (A * x + B) + D * F)) - E / F
+}
+
+/// Static HDR metadata describing the mastering display, sent with `VK_EXT_hdr_metadata`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct HdrMetadata {
+    /// Primaries and white point of the mastering display
+    pub primaries: Chromaticities,
+    /// Peak luminance of the mastering display in cd/m²
+    pub max_luminance: f32,
+    /// Black level of the mastering display in cd/m²
+    pub min_luminance: f32,
+    /// Brightest pixel of the content in cd/m² (MaxCLL)
+    pub max_content_light_level: f32,
+    /// Brightest frame average of the content in cd/m² (MaxFALL)
+    pub max_frame_average_light_level: f32,
+}
+
+impl Default for HdrMetadata {
+    fn default() -> Self {
+        Self {
+            primaries: Primaries::Bt2020.chromaticities(),
+            max_luminance: HLG_PEAK_NITS,
+            min_luminance: 0.005,
+            max_content_light_level: HLG_PEAK_NITS,
+            max_frame_average_light_level: 400.0,
+        }
+    }
+}
+
+impl HdrMetadata {
+    pub fn to_vk(&self) -> vk::HdrMetadataEXT<'static> {
+        let xy = |[x, y]: [f32; 2]| vk::XYColorEXT { x, y };
+        vk::HdrMetadataEXT::default()
+            .display_primary_red(xy(self.primaries.red))
+            .display_primary_green(xy(self.primaries.green))
+            .display_primary_blue(xy(self.primaries.blue))
+            .white_point(xy(self.primaries.white))
+            .max_luminance(self.max_luminance)
+            .min_luminance(self.min_luminance)
+            .max_content_light_level(self.max_content_light_level)
+            .max_frame_average_light_level(self.max_frame_average_light_level)
+    }
+}
+
+/// Builds the transforms between client content, the working space and the outputs
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ColorPipeline {
+    /// Primaries of the linear working space that blending happens in
+    pub working_primaries: Primaries,
+    /// Luminance in cd/m² that a working space value of `1.0` represents
+    pub reference_white_nits: f32,
+}
+
+impl Default for ColorPipeline {
+    fn default() -> Self {
+        Self {
+            // BT.2020 holds every supported gamut without negative components
+            working_primaries: Primaries::Bt2020,
+            reference_white_nits: REFERENCE_WHITE_NITS,
+        }
+    }
+}
+
+impl ColorPipeline {
+    /// The linear color space blending happens in
+    pub fn working_space(&self) -> ColorSpace {
+        ColorSpace::new(self.working_primaries, TransferFunction::Linear)
+    }
+
+    /// Transform that brings content in `source` into the linear working space
+    pub fn decode_transform(&self, source: ColorSpace) -> ColorTransform {
+        let scale = source.transfer.nominal_peak_nits() / self.reference_white_nits;
+        ColorTransform {
+            input: source.transfer,
+            tone_mapper: None,
+            matrix: source
+                .primaries
+                .conversion_to(self.working_primaries)
+                .scale(scale),
+            output: TransferFunction::Linear,
+            curve: None,
+        }
+    }
+
+    /// Transform that brings content with a client image description into the linear
+    /// working space, mapping the description's reference white to working space `1.0`
+    ///
+    /// Returns `None` if the description's primaries don't span a color space.
+    pub fn description_decode_transform(
+        &self,
+        description: &ImageDescription,
+    ) -> Option<ColorTransform> {
+        let transfer = TransferFunction::from(description.transfer_function);
+        // PQ encodes absolute luminance, every other curve is relative to the nominal peak
+        let peak_nits = match transfer {
+            TransferFunction::Pq => PQ_MAX_NITS,
+            _ => description.luminances.max,
+        };
+        let scale = peak_nits / description.luminances.reference;
+        Some(ColorTransform {
+            input: transfer,
+            tone_mapper: None,
+            matrix: Chromaticities::from(description.primaries)
+                .conversion_to(&self.working_primaries.chromaticities())?
+                .scale(scale),
+            output: TransferFunction::Linear,
+            curve: None,
+        })
+    }
+
+    /// Peak luminance of content with a client image description once it's decoded into
+    /// the working space, in cd/m²
+    pub fn description_peak_nits(&self, description: &ImageDescription) -> f32 {
+        let peak_nits = match description.mastering.luminance {
+            Some((_, max)) => max,
+            None if TransferFunction::from(description.transfer_function)
+                == TransferFunction::Pq =>
+            {
+                PQ_MAX_NITS
+            }
+            None => description.luminances.max,
+        };
+        peak_nits / description.luminances.reference * self.reference_white_nits
+    }
+
+    /// Transform that encodes the blended working space for an output in `target`
+    pub fn encode_transform(&self, target: ColorSpace) -> ColorTransform {
+        let scale = self.reference_white_nits / target.transfer.nominal_peak_nits();
+        ColorTransform {
+            input: TransferFunction::Linear,
+            tone_mapper: None,
+            matrix: self
+                .working_primaries
+                .conversion_to(target.primaries)
+                .scale(scale),
+            output: target.transfer,
+            curve: None,
+        }
+    }
+
+    /// Tone mapping step compressing content up to `content_peak_nits` for a display
+    /// that peaks at `display_peak_nits`
+    pub fn tone_mapper(
+        &self,
+        operator: ToneMapping,
+        content_peak_nits: f32,
+        display_peak_nits: f32,
+    ) -> ToneMapper {
+        let xyz = self
+            .working_primaries
+            .chromaticities()
+            .rgb_to_xyz()
+            .expect("well-known primaries are linearly independent");
+        ToneMapper {
+            operator,
+            luma: xyz.0[1],
+            source_peak: content_peak_nits / self.reference_white_nits,
+            target_peak: display_peak_nits / self.reference_white_nits,
+        }
+    }
+}
+
+/// A 3x3 matrix, stored row-major
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Mat3(pub [[f32; 3]; 3]);
+
+impl Mat3 {
+    pub const IDENTITY: Self = Self([[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]]);
+
+    pub fn from_columns(columns: [[f32; 3]; 3]) -> Self {
+        Self(std::array::from_fn(|row| {
+            std::array::from_fn(|col| columns[col][row])
+        }))
+    }
+
+    pub fn columns(&self) -> [[f32; 3]; 3] {
+        std::array::from_fn(|col| std::array::from_fn(|row| self.0[row][col]))
+    }
+
+    /// Multiply a column vector
+    pub fn apply(&self, v: [f32; 3]) -> [f32; 3] {
+        self.0.map(|row| dot(row, v))
+    }
+
+    /// Matrix product `self * rhs`
+    pub fn mul(&self, rhs: &Mat3) -> Mat3 {
+        let columns = rhs.columns();
+        Self(std::array::from_fn(|row| {
+            std::array::from_fn(|col| dot(self.0[row], columns[col]))
+        }))
+    }
+
+    /// Multiply every element by `factor`
+    pub fn scale(&self, factor: f32) -> Mat3 {
+        Self(self.0.map(|row| row.map(|v| v * factor)))
+    }
+
+    pub fn determinant(&self) -> f32 {
+        let m = &self.0;
+        m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+            - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+            + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+    }
+
+    /// Whether every element is a finite number
+    pub fn is_finite(&self) -> bool {
+        self.0.iter().flatten().all(|v| v.is_finite())
+    }
+
+    /// The inverse matrix, or `None` if the matrix is singular
+    pub fn inverse(&self) -> Option<Mat3> {
+        let det = self.determinant();
+        if !det.is_finite() || det.abs() < f32::EPSILON {
+            return None;
+        }
+        let m = &self.0;
+        let cofactor = |r0: usize, r1: usize, c0: usize, c1: usize| {
+            m[r0][c0] * m[r1][c1] - m[r0][c1] * m[r1][c0]
+        };
+        // Adjugate (transposed cofactor matrix) divided by the determinant
+        Some(
+            Self([
+                [
+                    cofactor(1, 2, 1, 2),
+                    -cofactor(0, 2, 1, 2),
+                    cofactor(0, 1, 1, 2),
+                ],
+                [
+                    -cofactor(1, 2, 0, 2),
+                    cofactor(0, 2, 0, 2),
+                    -cofactor(0, 1, 0, 2),
+                ],
+                [
+                    cofactor(1, 2, 0, 1),
+                    -cofactor(0, 2, 0, 1),
+                    cofactor(0, 1, 0, 1),
+                ],
+            ])
+            .scale(1.0 / det),
+        )
+    }
+}
+
+fn dot(a: [f32; 3], b: [f32; 3]) -> f32 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+/// Convert an xy chromaticity to XYZ with a luminance of 1
+pub(crate) fn xy_to_xyz([x, y]: [f32; 2]) -> [f32; 3] {
+    [x / y, 1.0, (1.0 - x - y) / y]
+}
+
+/// Bradford chromatic adaptation from one white point to another, in XYZ
+fn bradford_adaptation(from: [f32; 2], to: [f32; 2]) -> Mat3 {
+    const BRADFORD: Mat3 = Mat3([
+        [0.8951, 0.2664, -0.1614],
+        [-0.7502, 1.7135, 0.0367],
+        [0.0389, -0.0685, 1.0296],
+    ]);
+    let inverse = BRADFORD.inverse().expect("Bradford matrix is invertible");
+    let src = BRADFORD.apply(xy_to_xyz(from));
+    let dst = BRADFORD.apply(xy_to_xyz(to));
+    let gain = Mat3([
+        [dst[0] / src[0], 0.0, 0.0],
+        [0.0, dst[1] / src[1], 0.0],
+        [0.0, 0.0, dst[2] / src[2]],
+    ]);
+    inverse.mul(&gain).mul(&BRADFORD)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EPSILON: f32 = 1e-4;
+
+    fn assert_close(actual: f32, expected: f32) {
+        assert!(
+            (actual - expected).abs() < EPSILON,
+            "expected {expected}, got {actual}"
+        );
+    }
+
+    fn assert_matrix_close(actual: Mat3, expected: [[f32; 3]; 3]) {
+        for (row, expected_row) in actual.0.iter().zip(expected) {
+            for (&a, e) in row.iter().zip(expected_row) {
+                assert!(
+                    (a - e).abs() < 1e-3,
+                    "expected {expected:?}, got {actual:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn srgb_reference_values() {
+        assert_close(TransferFunction::Srgb.eotf(0.5), 0.214_041_1);
+        assert_close(TransferFunction::Srgb.eotf(0.04045), 0.003_130_8);
+        assert_close(TransferFunction::Srgb.inverse_eotf(0.18), 0.461_356);
+        assert_close(TransferFunction::Srgb.eotf(1.0), 1.0);
+    }
+
+    #[test]
+    fn gamma22_reference_values() {
+        assert_close(TransferFunction::Gamma22.eotf(0.5), 0.217_637_6);
+        assert_close(TransferFunction::Gamma22.inverse_eotf(0.217_637_6), 0.5);
+    }
+
+    #[test]
+    fn pq_reference_values() {
+        // Code values from ITU-R BT.2100 / BT.2408 for 100, 203 and 1000 cd/m²
+        assert_close(
+            TransferFunction::Pq.inverse_eotf(100.0 / PQ_MAX_NITS),
+            0.508_078,
+        );
+        assert_close(
+            TransferFunction::Pq.inverse_eotf(203.0 / PQ_MAX_NITS),
+            0.580_688,
+        );
+        assert_close(
+            TransferFunction::Pq.inverse_eotf(1000.0 / PQ_MAX_NITS),
+            0.751_827,
+        );
+        assert_close(TransferFunction::Pq.eotf(1.0), 1.0);
+        assert_close(TransferFunction::Pq.eotf(0.0), 0.0);
+    }
+
+    #[test]
+    fn hlg_reference_values() {
+        assert_close(TransferFunction::Hlg.inverse_eotf(1.0 / 12.0), 0.5);
+        assert_close(TransferFunction::Hlg.inverse_eotf(1.0), 1.0);
+        assert_close(TransferFunction::Hlg.eotf(0.5), 1.0 / 12.0);
+        assert_close(TransferFunction::Hlg.eotf(0.75), 0.264_963);
+    }
+
+    #[test]
+    fn transfer_functions_round_trip() {
+        let functions = [
+            TransferFunction::Linear,
+            TransferFunction::Srgb,
+            TransferFunction::Gamma22,
+            TransferFunction::Pq,
+            TransferFunction::Hlg,
+        ];
+        for function in functions {
+            for i in 0..=20 {
+                let v = i as f32 / 20.0;
+                assert_close(function.inverse_eotf(function.eotf(v)), v);
+            }
+        }
+    }
+
+    #[test]
+    fn extended_range_is_mirrored() {
+        assert_close(TransferFunction::Srgb.eotf(-0.5), -0.214_041_1);
+    }
+
+    #[test]
+    fn bt709_rgb_to_xyz() {
+        assert_matrix_close(
+            Primaries::Bt709.chromaticities().rgb_to_xyz().unwrap(),
+            [
+                [0.4124, 0.3576, 0.1805],
+                [0.2126, 0.7152, 0.0722],
+                [0.0193, 0.1192, 0.9505],
+            ],
+        );
+    }
+
+    #[test]
+    fn bt709_to_bt2020() {
+        // ITU-R BT.2087
+        assert_matrix_close(
+            Primaries::Bt709.conversion_to(Primaries::Bt2020),
+            [
+                [0.6274, 0.3293, 0.0433],
+                [0.0691, 0.9195, 0.0114],
+                [0.0164, 0.0880, 0.8956],
+            ],
+        );
+    }
+
+    #[test]
+    fn display_p3_to_bt709() {
+        assert_matrix_close(
+            Primaries::DisplayP3.conversion_to(Primaries::Bt709),
+            [
+                [1.2249, -0.2247, 0.0],
+                [-0.0420, 1.0419, 0.0],
+                [-0.0197, -0.0786, 1.0979],
+            ],
+        );
+    }
+
+    #[test]
+    fn conversion_preserves_white() {
+        let white = Primaries::Bt2020
+            .conversion_to(Primaries::DisplayP3)
+            .apply([1.0, 1.0, 1.0]);
+        for c in white {
+            assert_close(c, 1.0);
+        }
+    }
+
+    #[test]
+    fn matrix_inverse() {
+        let m = Primaries::DisplayP3.chromaticities().rgb_to_xyz().unwrap();
+        assert_matrix_close(m.mul(&m.inverse().unwrap()), Mat3::IDENTITY.0);
+    }
+
+    #[test]
+    fn degenerate_primaries_have_no_conversion() {
+        let srgb = Primaries::Bt709.chromaticities();
+        let coincident = Chromaticities {
+            green: srgb.red,
+            ..srgb
+        };
+        assert_eq!(coincident.rgb_to_xyz(), None);
+        assert_eq!(coincident.conversion_to(&srgb), None);
+        assert_eq!(srgb.conversion_to(&coincident), None);
+
+        let zero_luminance = Chromaticities {
+            blue: [0.15, 0.0],
+            ..srgb
+        };
+        assert_eq!(zero_luminance.xyz_to_rgb(), None);
+        let black_white = Chromaticities {
+            white: [0.3, 0.0],
+            ..srgb
+        };
+        assert_eq!(black_white.conversion_to(&srgb), None);
+    }
+
+    #[test]
+    fn srgb_white_decodes_to_reference_white() {
+        let pipeline = ColorPipeline::default();
+        let linear = pipeline
+            .decode_transform(ColorSpace::SRGB)
+            .apply([1.0, 1.0, 1.0]);
+        for c in linear {
+            assert_close(c, 1.0);
+        }
+    }
+
+    #[test]
+    fn reference_white_encodes_to_pq_code_value() {
+        let pipeline = ColorPipeline::default();
+        let encoded = pipeline
+            .encode_transform(ColorSpace::BT2100_PQ)
+            .apply([1.0, 1.0, 1.0]);
+        for c in encoded {
+            assert_close(c, 0.580_688);
+        }
+    }
+
+    #[test]
+    fn description_reference_white_decodes_to_reference_white() {
+        use starforge_core::protocols::color_management::NamedPrimaries;
+
+        let pipeline = ColorPipeline::default();
+        let srgb = pipeline
+            .description_decode_transform(&ImageDescription::srgb())
+            .unwrap();
+        for c in srgb.apply([1.0, 1.0, 1.0]) {
+            assert_close(c, 1.0);
+        }
+
+        let pq =
+            ImageDescription::named(1, NamedPrimaries::Bt2020, NamedTransferFunction::St2084Pq);
+        let decoded = pipeline
+            .description_decode_transform(&pq)
+            .unwrap()
+            .apply([0.580_688; 3]);
+        for c in decoded {
+            assert_close(c, 1.0);
+        }
+    }
+
+    #[test]
+    fn decode_then_encode_round_trips() {
+        let pipeline = ColorPipeline::default();
+        let decode = pipeline.decode_transform(ColorSpace::DISPLAY_P3);
+        let encode = pipeline.encode_transform(ColorSpace::DISPLAY_P3);
+        let color = [0.8, 0.4, 0.1];
+        for (c, expected) in encode.apply(decode.apply(color)).into_iter().zip(color) {
+            assert_close(c, expected);
+        }
+    }
+
+    const OPERATORS: [ToneMapping; 4] = [
+        ToneMapping::Clip,
+        ToneMapping::Reinhard,
+        ToneMapping::Bt2390,
+        ToneMapping::Hable,
+    ];
+
+    #[test]
+    fn tone_mapping_maps_content_peak_to_display_peak() {
+        // 1000 cd/m² content on an SDR display, relative to reference white
+        let source_peak = 1000.0 / REFERENCE_WHITE_NITS;
+        for operator in OPERATORS {
+            assert_close(operator.map(source_peak, source_peak, 1.0), 1.0);
+            assert_close(operator.map(0.0, source_peak, 1.0), 0.0);
+        }
+    }
+
+    #[test]
+    fn tone_mapping_is_monotonic() {
+        let source_peak = 4000.0 / REFERENCE_WHITE_NITS;
+        for operator in OPERATORS {
+            let mut previous = 0.0;
+            for i in 1..=100 {
+                let mapped = operator.map(source_peak * i as f32 / 100.0, source_peak, 1.0);
+                assert!(mapped >= previous, "{operator:?} is not monotonic");
+                assert!(mapped <= 1.0 + EPSILON);
+                previous = mapped;
+            }
+        }
+    }
+
+    #[test]
+    fn bt2390_leaves_content_below_the_knee_untouched() {
+        let source_peak = 1000.0 / REFERENCE_WHITE_NITS;
+        let target_peak = 600.0 / REFERENCE_WHITE_NITS;
+        assert_close(ToneMapping::Bt2390.map(0.5, source_peak, target_peak), 0.5);
+    }
+
+    #[test]
+    fn tone_mapper_preserves_hue() {
+        let pipeline = ColorPipeline::default();
+        let tone_mapper = pipeline.tone_mapper(ToneMapping::Reinhard, 1000.0, 203.0);
+        let [r, g, b] = tone_mapper.apply([4.0, 2.0, 1.0]);
+        assert_close(r / g, 2.0);
+        assert_close(g / b, 2.0);
+    }
+
+    #[test]
+    fn identity_keeps_values() {
+        assert_eq!(
+            ColorTransform::IDENTITY.apply([0.25, 0.5, 2.0]),
+            [0.25, 0.5, 2.0]
+        );
+    }
+
+    #[test]
+    fn sdr_descriptions_peak_at_reference_white() {
+        use starforge_core::protocols::color_management::NamedPrimaries;
+
+        let pipeline = ColorPipeline::default();
+        assert_close(
+            pipeline.description_peak_nits(&ImageDescription::srgb()),
+            REFERENCE_WHITE_NITS,
+        );
+        let hlg = ImageDescription::named(0, NamedPrimaries::Bt2020, NamedTransferFunction::Hlg);
+        assert_close(pipeline.description_peak_nits(&hlg), HLG_PEAK_NITS);
+    }
+
+    #[test]
+    fn vk_color_space_round_trip() {
+        for space in [
+            ColorSpace::SRGB,
+            ColorSpace::EXTENDED_SRGB_LINEAR,
+            ColorSpace::DISPLAY_P3,
+            ColorSpace::BT2100_PQ,
+            ColorSpace::BT2100_HLG,
+            ColorSpace::BT2020_LINEAR,
+        ] {
+            assert_eq!(ColorSpace::from_vk(space.to_vk().unwrap()), Some(space));
+        }
+    }
+}
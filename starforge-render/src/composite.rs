@@ -0,0 +1,318 @@
+//! Starforge Render - Composition
+//!
+//! This module holds the working images outputs are blended in, and encodes them for the
+//! outputs.
+//!
+//! Each output has a working image in half floats holding linear light in the working
+//! primaries. Everything drawn into it is decoded on the way, client surfaces from their
+//! own colour space and compositor content from sRGB. The encode pass then runs the
+//! output's [`ColorTransform`] from `color.glsl` over the whole image, followed by the
+//! gamma tables a client set for the output, writing the output's image.
+
+use crate::color::{ColorTransform, ColorTransformUniform, with_color_glsl};
+use crate::core::Context;
+use crate::memory::{AllocatedImage, MappedBuffer};
+use crate::night_light::{GAMMA_LUT_SIZE, GammaLutUniform};
+use crate::pipeline::{
+    BlendMode, create_graphics_pipeline, create_linear_sampler, create_pipeline_layout_with_sets,
+    create_texture_set_layout, create_texture_sets, create_uniform_set, create_uniform_set_layout,
+    push_constant_bytes, write_texture_set,
+};
+use crate::shader::{compile_internal, create_shader_module};
+use crate::swapchain::OutputId;
+use ash::vk;
+use naga::ShaderStage;
+use starforge_core::{StarforgeError, StarforgeResult};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+const FULLSCREEN_VERT: &str = include_str!("../shaders/fullscreen.vert");
+const ENCODE_FRAG: &str = include_str!("../shaders/encode.frag");
+
+/// Format of working images, wide enough for HDR content and values outside the gamut
+pub const WORKING_FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+
+/// Uniform block of the encode pass, matching `EncodeUniform` in encode.frag
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct EncodeUniform {
+    transform: ColorTransformUniform,
+    gamma_lut: GammaLutUniform,
+    use_gamma_lut: u32,
+    _padding: [u32; 3],
+}
+
+/// The working image of an output and the sets the encode pass reads it through
+struct OutputComposite {
+    context: Arc<Context>,
+    working: AllocatedImage,
+    uniforms: MappedBuffer,
+    texture_pool: vk::DescriptorPool,
+    texture_set: vk::DescriptorSet,
+    uniform_pool: vk::DescriptorPool,
+    uniform_set: vk::DescriptorSet,
+}
+
+impl OutputComposite {
+    fn new(
+        context: Arc<Context>,
+        compositor: &Compositor,
+        extent: vk::Extent2D,
+    ) -> StarforgeResult<Self> {
+        let working = AllocatedImage::new(
+            context.clone(),
+            extent,
+            WORKING_FORMAT,
+            vk::ImageUsageFlags::COLOR_ATTACHMENT
+                | vk::ImageUsageFlags::SAMPLED
+                | vk::ImageUsageFlags::TRANSFER_SRC,
+        )?;
+        let uniforms = MappedBuffer::new(
+            context.clone(),
+            std::mem::size_of::<EncodeUniform>() as vk::DeviceSize,
+            vk::BufferUsageFlags::UNIFORM_BUFFER,
+        )?;
+        let (texture_pool, sets) = create_texture_sets(&context, compositor.texture_set_layout, 1)?;
+        write_texture_set(&context, sets[0], working.view(), compositor.sampler);
+        let (uniform_pool, uniform_set) = match create_uniform_set(
+            &context,
+            compositor.uniform_set_layout,
+            vk::DescriptorType::UNIFORM_BUFFER,
+            uniforms.buffer(),
+            uniforms.size(),
+        ) {
+            Ok(set) => set,
+            Err(e) => {
+                context.untrack(texture_pool);
+                unsafe { context.device().destroy_descriptor_pool(texture_pool, None) };
+                return Err(e);
+            }
+        };
+        Ok(Self {
+            context,
+            working,
+            uniforms,
+            texture_pool,
+            texture_set: sets[0],
+            uniform_pool,
+            uniform_set,
+        })
+    }
+}
+
+impl Drop for OutputComposite {
+    fn drop(&mut self) {
+        unsafe {
+            let device = self.context.device();
+            self.context.untrack(self.texture_pool);
+            device.destroy_descriptor_pool(self.texture_pool, None);
+            self.context.untrack(self.uniform_pool);
+            device.destroy_descriptor_pool(self.uniform_pool, None);
+        }
+    }
+}
+
+/// Owns the working images of outputs and records their encode pass
+pub struct Compositor {
+    context: Arc<Context>,
+    vertex_module: vk::ShaderModule,
+    fragment_module: vk::ShaderModule,
+    sampler: vk::Sampler,
+    texture_set_layout: vk::DescriptorSetLayout,
+    uniform_set_layout: vk::DescriptorSetLayout,
+    pipeline_layout: vk::PipelineLayout,
+    pipelines: HashMap<vk::Format, vk::Pipeline>,
+    outputs: HashMap<OutputId, OutputComposite>,
+}
+
+impl Compositor {
+    pub fn new(context: Arc<Context>) -> StarforgeResult<Self> {
+        let vertex = compile_internal(FULLSCREEN_VERT, ShaderStage::Vertex)?;
+        let fragment = compile_internal(&with_color_glsl(ENCODE_FRAG), ShaderStage::Fragment)?;
+        let vertex_module = create_shader_module(&context, &vertex.spirv)?;
+        let fragment_module = create_shader_module(&context, &fragment.spirv)?;
+        let sampler = create_linear_sampler(&context)?;
+        let texture_set_layout = create_texture_set_layout(&context)?;
+        let uniform_set_layout =
+            create_uniform_set_layout(&context, vk::DescriptorType::UNIFORM_BUFFER)?;
+        let pipeline_layout = create_pipeline_layout_with_sets::<()>(
+            &context,
+            &[texture_set_layout, uniform_set_layout],
+            vk::ShaderStageFlags::FRAGMENT,
+        )?;
+
+        Ok(Self {
+            context,
+            vertex_module,
+            fragment_module,
+            sampler,
+            texture_set_layout,
+            uniform_set_layout,
+            pipeline_layout,
+            pipelines: HashMap::new(),
+            outputs: HashMap::new(),
+        })
+    }
+
+    /// Image and view of the working image of an output of `extent`, created again when
+    /// the output's size changed
+    ///
+    /// Its contents are undefined at the start of every frame.
+    pub fn working_image(
+        &mut self,
+        output: OutputId,
+        extent: vk::Extent2D,
+    ) -> StarforgeResult<(vk::Image, vk::ImageView)> {
+        let current = self
+            .outputs
+            .get(&output)
+            .is_some_and(|composite| composite.working.extent() == extent);
+        if !current {
+            let composite = OutputComposite::new(self.context.clone(), self, extent)?;
+            self.outputs.insert(output, composite);
+        }
+        let working = &self.outputs[&output].working;
+        Ok((working.image(), working.view()))
+    }
+
+    /// Encode the working image of an output with `transform` and then `gamma_lut` into
+    /// `view`, an image of `format` and `extent` in `COLOR_ATTACHMENT_OPTIMAL` layout,
+    /// replacing its contents
+    ///
+    /// The working image has to be in `SHADER_READ_ONLY_OPTIMAL` layout. Its uniforms are
+    /// rewritten for every frame, so the previous one has to be complete.
+    #[allow(clippy::too_many_arguments)]
+    pub fn record_encode(
+        &mut self,
+        output: OutputId,
+        command_buffer: vk::CommandBuffer,
+        view: vk::ImageView,
+        format: vk::Format,
+        extent: vk::Extent2D,
+        transform: &ColorTransform,
+        gamma_lut: Option<&GammaLutUniform>,
+    ) -> StarforgeResult<()> {
+        let pipeline = match self.pipelines.get(&format) {
+            Some(&pipeline) => pipeline,
+            None => {
+                let pipeline = create_graphics_pipeline(
+                    &self.context,
+                    self.pipeline_layout,
+                    self.vertex_module,
+                    self.fragment_module,
+                    format,
+                    BlendMode::Replace,
+                )?;
+                self.pipelines.insert(format, pipeline);
+                pipeline
+            }
+        };
+        let composite = self.outputs.get_mut(&output).ok_or_else(|| {
+            StarforgeError::RendererError(format!("output {} has no working image", output.0))
+        })?;
+        let uniform = EncodeUniform {
+            transform: transform.to_uniform(),
+            gamma_lut: gamma_lut.copied().unwrap_or(GammaLutUniform {
+                entries: [[0.0; 4]; GAMMA_LUT_SIZE],
+            }),
+            use_gamma_lut: gamma_lut.is_some() as u32,
+            _padding: [0; 3],
+        };
+        composite.uniforms.write(push_constant_bytes(&uniform))?;
+
+        let device = self.context.device();
+        let attachments = [vk::RenderingAttachmentInfo::default()
+            .image_view(view)
+            .image_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+            .load_op(vk::AttachmentLoadOp::DONT_CARE)
+            .store_op(vk::AttachmentStoreOp::STORE)];
+        let render_area = vk::Rect2D {
+            offset: vk::Offset2D::default(),
+            extent,
+        };
+        let rendering_info = vk::RenderingInfo::default()
+            .render_area(render_area)
+            .layer_count(1)
+            .color_attachments(&attachments);
+        unsafe {
+            device.cmd_begin_rendering(command_buffer, &rendering_info);
+            device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, pipeline);
+            device.cmd_set_viewport(
+                command_buffer,
+                0,
+                &[vk::Viewport {
+                    x: 0.0,
+                    y: 0.0,
+                    width: extent.width as f32,
+                    height: extent.height as f32,
+                    min_depth: 0.0,
+                    max_depth: 1.0,
+                }],
+            );
+            device.cmd_set_scissor(command_buffer, 0, &[render_area]);
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline_layout,
+                0,
+                &[composite.texture_set, composite.uniform_set],
+                &[],
+            );
+            device.cmd_draw(command_buffer, 3, 1, 0, 0);
+            device.cmd_end_rendering(command_buffer);
+        }
+        Ok(())
+    }
+
+    /// Forget the working image of an output
+    pub fn remove_output(&mut self, output: OutputId) {
+        self.outputs.remove(&output);
+    }
+}
+
+impl Drop for Compositor {
+    fn drop(&mut self) {
+        self.outputs.clear();
+        unsafe {
+            let device = self.context.device();
+            for &pipeline in self.pipelines.values() {
+                self.context.untrack(pipeline);
+                device.destroy_pipeline(pipeline, None);
+            }
+            self.context.untrack(self.pipeline_layout);
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+            self.context.untrack(self.uniform_set_layout);
+            device.destroy_descriptor_set_layout(self.uniform_set_layout, None);
+            self.context.untrack(self.texture_set_layout);
+            device.destroy_descriptor_set_layout(self.texture_set_layout, None);
+            self.context.untrack(self.sampler);
+            device.destroy_sampler(self.sampler, None);
+            self.context.untrack(self.vertex_module);
+            device.destroy_shader_module(self.vertex_module, None);
+            self.context.untrack(self.fragment_module);
+            device.destroy_shader_module(self.fragment_module, None);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_shader_compiles() {
+        compile_internal(&with_color_glsl(ENCODE_FRAG), ShaderStage::Fragment).unwrap();
+    }
+
+    #[test]
+    fn encode_uniform_matches_std140() {
+        // ColorTransform is a mat3 padded to three vec4s, a vec4 and eight scalars, the
+        // gamma tables are 256 vec4s and the flag is padded to a vec4
+        assert_eq!(std::mem::offset_of!(EncodeUniform, gamma_lut), 96);
+        assert_eq!(
+            std::mem::offset_of!(EncodeUniform, use_gamma_lut),
+            96 + 4096
+        );
+        assert_eq!(std::mem::size_of::<EncodeUniform>(), 96 + 4096 + 16);
+    }
+}
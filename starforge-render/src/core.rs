@@ -2,9 +2,12 @@
 //!
 //! This module stores the core Vulkan context information, like the instance, device, queues, and allocator
 
+use crate::handles::HandleRegistry;
 use ash::vk;
 use starforge_core::{StarforgeError, StarforgeResult};
 use std::ffi::{CStr, CString};
+use std::mem::ManuallyDrop;
+use std::sync::atomic::{AtomicBool, Ordering};
 use tracing::{error, info, warn};
 
 #[derive(Clone)]
@@ -27,6 +30,8 @@ pub struct Context {
 
     physical_device: vk::PhysicalDevice,
     physical_device_properties: vk::PhysicalDeviceProperties,
+    // `(major, minor)` of the device's DRM node, if the driver reports it
+    drm_node: Option<(i64, i64)>,
 
     // The primary handle for interaction with the GPU
     pub(crate) device: ash::Device,
@@ -35,10 +40,43 @@ pub struct Context {
     compute_queue: Option<QueueInfo>,
     transfer_queue: Option<QueueInfo>,
 
-    // The memory allocator instance
-    allocator: vk_mem::Allocator,
+    // The memory allocator instance, destroyed by hand before the device it allocates from
+    allocator: ManuallyDrop<vk_mem::Allocator>,
+
+    // Live handles created on the device, reported as leaks when the context is dropped
+    handles: HandleRegistry,
 
     enabled_device_extensions: Vec<CString>,
+
+    // Set once the device reported VK_ERROR_DEVICE_LOST, or a loss was simulated
+    lost: DeviceLoss,
+}
+
+/// Whether a device was lost, set by the first result reporting it
+#[derive(Default)]
+pub struct DeviceLoss(AtomicBool);
+
+impl DeviceLoss {
+    pub fn is_lost(&self) -> bool {
+        self.0.load(Ordering::Acquire)
+    }
+
+    pub fn mark_lost(&self) {
+        self.0.store(true, Ordering::Release);
+    }
+
+    /// Convert a Vulkan result, marking the device as lost when the result says so
+    pub fn check<T>(&self, result: ash::prelude::VkResult<T>) -> StarforgeResult<T> {
+        result.map_err(|e| {
+            if e == vk::Result::ERROR_DEVICE_LOST {
+                error!("Vulkan device lost.");
+                self.mark_lost();
+                StarforgeError::DeviceLost
+            } else {
+                StarforgeError::RendererError(e.to_string())
+            }
+        })
+    }
 }
 
 impl Context {
@@ -94,6 +132,17 @@ impl Context {
             }
             instance_extensions.push(ash::khr::get_physical_device_properties2::NAME.as_ptr());
             instance_extensions.push(ash::khr::get_surface_capabilities2::NAME.as_ptr());
+            // HDR10 and scRGB surface color spaces are only exposed with this extension
+            let available_instance_extensions = entry
+                .enumerate_instance_extension_properties(None)
+                .map_err(|e| StarforgeError::RendererError(e.to_string()))?;
+            if available_instance_extensions.iter().any(|ext| {
+                CStr::from_ptr(ext.extension_name.as_ptr()) == ash::ext::swapchain_colorspace::NAME
+            }) {
+                instance_extensions.push(ash::ext::swapchain_colorspace::NAME.as_ptr());
+            } else {
+                warn!("VK_EXT_swapchain_colorspace not available, HDR output disabled.");
+            }
 
             // Step 5: Create Vulkan Instance
             let instance_create_info = vk::InstanceCreateInfo::default()
@@ -209,6 +258,15 @@ impl Context {
                 if timeline_semaphore_features.timeline_semaphore == vk::FALSE {
                     return None;
                 }
+                let mut vulkan13_features = vk::PhysicalDeviceVulkan13Features::default();
+                let mut features2 =
+                    vk::PhysicalDeviceFeatures2::default().push_next(&mut vulkan13_features);
+                instance.get_physical_device_features2(pdev, &mut features2);
+                if vulkan13_features.dynamic_rendering == vk::FALSE
+                    || vulkan13_features.synchronization2 == vk::FALSE
+                {
+                    return None;
+                }
 
                 // TODO: Add checks for other required features (external memory, modifiers, etc.)
                 // using VkPhysicalDeviceFeatures2 and extension-specific feature structs.
@@ -241,6 +299,8 @@ impl Context {
                 )
             );
 
+            let drm_node = query_drm_node(&instance, physical_device);
+
             // -- Logical Device Creation --
             // Step 9: Define Queues to Create
             let queue_priorities = [1.0f32];
@@ -271,10 +331,15 @@ impl Context {
             // Use VkPhysicalDeviceFeatures2 for extension features
             let mut timeline_semaphore_features =
                 vk::PhysicalDeviceTimelineSemaphoreFeatures::default().timeline_semaphore(true); // Enable timeline semaphores
+            // Render passes are recorded with dynamic rendering and synchronization2 barriers
+            let mut vulkan13_features = vk::PhysicalDeviceVulkan13Features::default()
+                .dynamic_rendering(true)
+                .synchronization2(true);
             // Chain other feature structs here (e.g., for modifiers, external memory)
             let mut features2 = vk::PhysicalDeviceFeatures2::default()
                 .features(physical_device_features) // Base features
-                .push_next(&mut timeline_semaphore_features);
+                .push_next(&mut timeline_semaphore_features)
+                .push_next(&mut vulkan13_features);
 
             // Step 11: Create Logical Device
             let enabled_device_extensions_ptr: Vec<*const i8> = required_device_extensions
@@ -338,12 +403,15 @@ impl Context {
                 validation,
                 physical_device,
                 physical_device_properties: pdev_props.properties,
+                drm_node,
                 device,
                 graphics_queue: graphics_queue_info,
                 compute_queue,
                 transfer_queue,
-                allocator,
+                allocator: ManuallyDrop::new(allocator),
+                handles: HandleRegistry::default(),
                 enabled_device_extensions: required_device_extensions_cstr,
+                lost: DeviceLoss::default(),
             })
         }
     }
@@ -356,6 +424,12 @@ impl Context {
     pub fn physical_device(&self) -> vk::PhysicalDevice {
         self.physical_device
     }
+    pub fn physical_device_properties(&self) -> &vk::PhysicalDeviceProperties {
+        &self.physical_device_properties
+    }
+    pub fn drm_node(&self) -> Option<(i64, i64)> {
+        self.drm_node
+    }
     pub fn device(&self) -> &ash::Device {
         &self.device
     }
@@ -365,6 +439,110 @@ impl Context {
     pub fn allocator(&self) -> &vk_mem::Allocator {
         &self.allocator
     }
+
+    // --- Handle Tracking ---
+
+    /// Register a newly created object, so it is reported if it outlives the context
+    pub fn track<H: vk::Handle>(&self, handle: H) {
+        self.handles.track(handle);
+    }
+
+    /// Unregister an object right before destroying it
+    pub fn untrack<H: vk::Handle>(&self, handle: H) {
+        self.handles.untrack(handle);
+    }
+
+    // --- Device Loss ---
+
+    /// Whether the device was lost; the context has to be recreated before further use
+    pub fn is_lost(&self) -> bool {
+        self.lost.is_lost()
+    }
+
+    /// Make every following submission fail with `DeviceLost`, to exercise the recovery
+    /// path on drivers that can't be reset on demand
+    pub fn simulate_device_loss(&self) {
+        warn!("Simulating Vulkan device loss.");
+        self.lost.mark_lost();
+    }
+
+    /// Convert a Vulkan result, remembering when it reports the device as lost
+    pub fn check<T>(&self, result: ash::prelude::VkResult<T>) -> StarforgeResult<T> {
+        self.lost.check(result)
+    }
+
+    /// Submit work to the graphics queue
+    ///
+    /// Every submission goes through here, so device loss is noticed as soon as the
+    /// driver reports it.
+    pub fn submit(&self, submits: &[vk::SubmitInfo2], fence: vk::Fence) -> StarforgeResult<()> {
+        if self.is_lost() {
+            return Err(StarforgeError::DeviceLost);
+        }
+        self.check(unsafe {
+            self.device
+                .queue_submit2(self.graphics_queue.queue, submits, fence)
+        })
+    }
+
+    /// Record commands with `record`, submit them and wait for them to complete
+    pub fn submit_and_wait(
+        &self,
+        record: impl FnOnce(vk::CommandBuffer) -> StarforgeResult<()>,
+    ) -> StarforgeResult<()> {
+        self.submit_and_wait_with(&[], &[], record)
+    }
+
+    /// Like [`Self::submit_and_wait`], with the submission waiting on `waits` and
+    /// signalling `signals`
+    pub fn submit_and_wait_with(
+        &self,
+        waits: &[vk::SemaphoreSubmitInfo],
+        signals: &[vk::SemaphoreSubmitInfo],
+        record: impl FnOnce(vk::CommandBuffer) -> StarforgeResult<()>,
+    ) -> StarforgeResult<()> {
+        let pool_info = vk::CommandPoolCreateInfo::default()
+            .flags(vk::CommandPoolCreateFlags::TRANSIENT)
+            .queue_family_index(self.graphics_queue.family_index);
+        let command_pool =
+            self.check(unsafe { self.device.create_command_pool(&pool_info, None) })?;
+
+        let result = (|| {
+            let allocate_info = vk::CommandBufferAllocateInfo::default()
+                .command_pool(command_pool)
+                .level(vk::CommandBufferLevel::PRIMARY)
+                .command_buffer_count(1);
+            let command_buffer =
+                self.check(unsafe { self.device.allocate_command_buffers(&allocate_info) })?[0];
+            let begin_info = vk::CommandBufferBeginInfo::default()
+                .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+            self.check(unsafe {
+                self.device
+                    .begin_command_buffer(command_buffer, &begin_info)
+            })?;
+            record(command_buffer)?;
+            self.check(unsafe { self.device.end_command_buffer(command_buffer) })?;
+
+            let fence = self.check(unsafe {
+                self.device
+                    .create_fence(&vk::FenceCreateInfo::default(), None)
+            })?;
+            let command_buffers =
+                [vk::CommandBufferSubmitInfo::default().command_buffer(command_buffer)];
+            let submit = vk::SubmitInfo2::default()
+                .wait_semaphore_infos(waits)
+                .command_buffer_infos(&command_buffers)
+                .signal_semaphore_infos(signals);
+            let result = self.submit(&[submit], fence).and_then(|()| {
+                self.check(unsafe { self.device.wait_for_fences(&[fence], true, u64::MAX) })
+            });
+            unsafe { self.device.destroy_fence(fence, None) };
+            result
+        })();
+
+        unsafe { self.device.destroy_command_pool(command_pool, None) };
+        result
+    }
 }
 
 impl Drop for Context {
@@ -375,23 +553,58 @@ impl Drop for Context {
                 error!("Failed to wait for device idle: {}", e);
             }
 
-            // Step 1: Destroy Logical Device
+            // Step 1: Destroy the allocator, which frees its memory blocks on the device
+            ManuallyDrop::drop(&mut self.allocator);
+
+            // Step 2: Every child object should be gone by now
+            self.handles.report_leaks();
+
+            // Step 3: Destroy Logical Device
             self.device.destroy_device(None);
 
-            // Step 2: Destroy Debug Messenger
+            // Step 4: Destroy Debug Messenger
             if let Some(validation) = self.validation.take() {
                 validation
                     .debug_utils
                     .destroy_debug_utils_messenger(validation.debug_messenger, None);
             }
 
-            // Step 3: Destroy Vulkan Instance
+            // Step 5: Destroy Vulkan Instance
             self.instance.destroy_instance(None);
         }
         info!("Vulkan Context destroyed.");
     }
 }
 
+/// `(major, minor)` of the render node of a physical device, or of its primary node if it
+/// has none
+unsafe fn query_drm_node(
+    instance: &ash::Instance,
+    physical_device: vk::PhysicalDevice,
+) -> Option<(i64, i64)> {
+    unsafe {
+        let extensions = instance
+            .enumerate_device_extension_properties(physical_device)
+            .ok()?;
+        if !extensions.iter().any(|ext| {
+            CStr::from_ptr(ext.extension_name.as_ptr()) == ash::ext::physical_device_drm::NAME
+        }) {
+            return None;
+        }
+        let mut drm_properties = vk::PhysicalDeviceDrmPropertiesEXT::default();
+        let mut properties =
+            vk::PhysicalDeviceProperties2::default().push_next(&mut drm_properties);
+        instance.get_physical_device_properties2(physical_device, &mut properties);
+        if drm_properties.has_render == vk::TRUE {
+            Some((drm_properties.render_major, drm_properties.render_minor))
+        } else if drm_properties.has_primary == vk::TRUE {
+            Some((drm_properties.primary_major, drm_properties.primary_minor))
+        } else {
+            None
+        }
+    }
+}
+
 /// --- Debug Callback ---
 extern "system" fn vulkan_debug_callback(
     message_severity: vk::DebugUtilsMessageSeverityFlagsEXT,
@@ -415,3 +628,38 @@ extern "system" fn vulkan_debug_callback(
     }
     vk::FALSE // Should return false according to spec
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn device_lost_results_mark_the_device_as_lost() {
+        let lost = DeviceLoss::default();
+        assert!(!lost.is_lost());
+        assert!(matches!(
+            lost.check::<()>(Err(vk::Result::ERROR_DEVICE_LOST)),
+            Err(StarforgeError::DeviceLost)
+        ));
+        assert!(lost.is_lost());
+    }
+
+    #[test]
+    fn other_results_leave_the_device_usable() {
+        let lost = DeviceLoss::default();
+        assert_eq!(lost.check(Ok(3)).unwrap(), 3);
+        assert!(matches!(
+            lost.check::<()>(Err(vk::Result::ERROR_OUT_OF_DEVICE_MEMORY)),
+            Err(StarforgeError::RendererError(_))
+        ));
+        assert!(!lost.is_lost());
+    }
+
+    #[test]
+    fn devices_stay_lost() {
+        let lost = DeviceLoss::default();
+        lost.mark_lost();
+        assert_eq!(lost.check(Ok(3)).unwrap(), 3);
+        assert!(lost.is_lost());
+    }
+}
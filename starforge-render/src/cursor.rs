@@ -0,0 +1,298 @@
+//! Starforge Render - Cursor
+//!
+//! This module draws the pointer cursor as its own element on top of everything else.
+//!
+//! Themed cursor frames are uploaded once and cached by the key the compositor gives them;
+//! client cursor surfaces are drawn from their imported textures. Each output remembers
+//! where its cursor was drawn last, so moving or changing the cursor only damages the
+//! rectangles it left and entered.
+
+use crate::color::ColorTransform;
+use crate::core::Context;
+use crate::memory::{AllocatedImage, MappedBuffer};
+use crate::pipeline::{create_texture_sets, write_texture_set};
+use crate::resources::{TextureId, upload_image};
+use crate::shape::{FULL_TEXTURE, ShapeRenderer, WindowShape};
+use crate::swapchain::OutputId;
+use ash::vk;
+use smithay::utils::{Physical, Rectangle, Size};
+use starforge_core::{StarforgeError, StarforgeResult};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Cursor textures that can be cached at once, themed frames and client surfaces together
+const MAX_CURSOR_TEXTURES: u32 = 64;
+
+/// Texture a cursor is drawn from
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum CursorTexture {
+    /// A themed cursor frame, by the key it was uploaded with
+    Named(u64),
+    /// A client cursor surface
+    Client(TextureId),
+}
+
+/// Tracks what an output showed of the cursor in its last frame
+#[derive(Debug, Default)]
+pub struct CursorDamage {
+    last: Option<(CursorTexture, Rectangle<i32, Physical>)>,
+}
+
+impl CursorDamage {
+    /// Damage caused by drawing `cursor` instead of the last cursor, in output coordinates
+    pub fn update(
+        &mut self,
+        cursor: Option<(CursorTexture, Rectangle<i32, Physical>)>,
+    ) -> Vec<Rectangle<i32, Physical>> {
+        if self.last == cursor {
+            return Vec::new();
+        }
+        let damage = match (self.last, cursor) {
+            // Same place, new image
+            (Some((_, last)), Some((_, next))) if last == next => vec![next],
+            (last, next) => last.into_iter().chain(next).map(|(_, rect)| rect).collect(),
+        };
+        self.last = cursor;
+        damage
+    }
+}
+
+/// A cached cursor texture
+struct CachedTexture {
+    /// The image of a themed frame; client textures are owned by the resource manager
+    _image: Option<AllocatedImage>,
+    set: vk::DescriptorSet,
+}
+
+/// Caches cursor textures and draws them
+pub struct CursorRenderer {
+    context: Arc<Context>,
+    sampler: vk::Sampler,
+    descriptor_pool: vk::DescriptorPool,
+    free_sets: Vec<vk::DescriptorSet>,
+    textures: HashMap<CursorTexture, CachedTexture>,
+    damage: HashMap<OutputId, CursorDamage>,
+}
+
+impl CursorRenderer {
+    pub fn new(
+        context: Arc<Context>,
+        set_layout: vk::DescriptorSetLayout,
+    ) -> StarforgeResult<Self> {
+        // Cursors are drawn at their own size, so nearest sampling keeps them crisp
+        let sampler_info = vk::SamplerCreateInfo::default()
+            .mag_filter(vk::Filter::NEAREST)
+            .min_filter(vk::Filter::NEAREST)
+            .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE);
+        let sampler = unsafe {
+            context
+                .device()
+                .create_sampler(&sampler_info, None)
+                .inspect(|&sampler| context.track(sampler))
+                .map_err(|e| StarforgeError::RendererError(e.to_string()))?
+        };
+        let (descriptor_pool, free_sets) =
+            match create_texture_sets(&context, set_layout, MAX_CURSOR_TEXTURES) {
+                Ok(pool) => pool,
+                Err(e) => {
+                    context.untrack(sampler);
+                    unsafe { context.device().destroy_sampler(sampler, None) };
+                    return Err(e);
+                }
+            };
+        Ok(Self {
+            context,
+            sampler,
+            descriptor_pool,
+            free_sets,
+            textures: HashMap::new(),
+            damage: HashMap::new(),
+        })
+    }
+
+    /// Upload a themed cursor frame of premultiplied RGBA `pixels`,
+    /// unless a frame with `key` was uploaded before
+    pub fn upload_frame(
+        &mut self,
+        key: u64,
+        size: Size<i32, Physical>,
+        pixels: &[u8],
+    ) -> StarforgeResult<()> {
+        let texture = CursorTexture::Named(key);
+        if self.textures.contains_key(&texture) {
+            return Ok(());
+        }
+        if pixels.len() != (size.w * size.h * 4) as usize {
+            return Err(StarforgeError::RendererError(format!(
+                "cursor frame of {}x{} has {} bytes",
+                size.w,
+                size.h,
+                pixels.len()
+            )));
+        }
+
+        let mut staging = MappedBuffer::new(
+            self.context.clone(),
+            pixels.len() as vk::DeviceSize,
+            vk::BufferUsageFlags::TRANSFER_SRC,
+        )?;
+        staging.write(pixels)?;
+        let image = upload_image(
+            &self.context,
+            &staging,
+            vk::Extent2D {
+                width: size.w as u32,
+                height: size.h as u32,
+            },
+            size.w as u32,
+            vk::Format::R8G8B8A8_UNORM,
+            vk::ComponentMapping::default(),
+        )?;
+        let set = self.allocate_set()?;
+        write_texture_set(&self.context, set, image.view(), self.sampler);
+        self.textures.insert(
+            texture,
+            CachedTexture {
+                _image: Some(image),
+                set,
+            },
+        );
+        Ok(())
+    }
+
+    /// Descriptor set sampling `texture`, with `client_view` as the view of client textures
+    fn texture_set(
+        &mut self,
+        texture: CursorTexture,
+        client_view: Option<vk::ImageView>,
+    ) -> StarforgeResult<vk::DescriptorSet> {
+        if let Some(cached) = self.textures.get(&texture) {
+            return Ok(cached.set);
+        }
+        let (CursorTexture::Client(_), Some(view)) = (texture, client_view) else {
+            return Err(StarforgeError::RendererError(format!(
+                "cursor texture {:?} was not uploaded",
+                texture
+            )));
+        };
+        let set = self.allocate_set()?;
+        write_texture_set(&self.context, set, view, self.sampler);
+        self.textures
+            .insert(texture, CachedTexture { _image: None, set });
+        Ok(set)
+    }
+
+    fn allocate_set(&mut self) -> StarforgeResult<vk::DescriptorSet> {
+        if self.free_sets.is_empty() {
+            // Rare enough, after many distinct cursors, to simply start over
+            self.context
+                .check(unsafe { self.context.device().device_wait_idle() })?;
+            self.free_sets
+                .extend(self.textures.drain().map(|(_, cached)| cached.set));
+        }
+        Ok(self.free_sets.pop().unwrap())
+    }
+
+    /// Stop sampling a client texture that is about to be released
+    pub fn forget_client_texture(&mut self, id: TextureId) {
+        if let Some(cached) = self.textures.remove(&CursorTexture::Client(id)) {
+            self.free_sets.push(cached.set);
+        }
+    }
+
+    /// Draw a cursor into the attachment currently being rendered to, decoding its sRGB
+    /// colors with `decode`
+    #[allow(clippy::too_many_arguments)]
+    pub fn draw(
+        &mut self,
+        command_buffer: vk::CommandBuffer,
+        shapes: &mut ShapeRenderer,
+        format: vk::Format,
+        output_size: Size<i32, Physical>,
+        texture: CursorTexture,
+        client_view: Option<vk::ImageView>,
+        rect: Rectangle<i32, Physical>,
+        decode: &ColorTransform,
+    ) -> StarforgeResult<()> {
+        let set = self.texture_set(texture, client_view)?;
+        shapes.record(
+            command_buffer,
+            format,
+            output_size,
+            set,
+            rect,
+            FULL_TEXTURE,
+            &WindowShape::default(),
+            decode,
+            1.0,
+        )
+    }
+
+    /// Damage caused by the cursor of an output since its last frame
+    pub fn damage(
+        &mut self,
+        output: OutputId,
+        cursor: Option<(CursorTexture, Rectangle<i32, Physical>)>,
+    ) -> Vec<Rectangle<i32, Physical>> {
+        self.damage.entry(output).or_default().update(cursor)
+    }
+
+    pub fn remove_output(&mut self, output: OutputId) {
+        self.damage.remove(&output);
+    }
+}
+
+impl Drop for CursorRenderer {
+    fn drop(&mut self) {
+        self.textures.clear();
+        unsafe {
+            self.context.untrack(self.descriptor_pool);
+            self.context
+                .device()
+                .destroy_descriptor_pool(self.descriptor_pool, None);
+            self.context.untrack(self.sampler);
+            self.context.device().destroy_sampler(self.sampler, None);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rect(x: i32, y: i32) -> Rectangle<i32, Physical> {
+        Rectangle::new((x, y).into(), (24, 24).into())
+    }
+
+    #[test]
+    fn a_still_cursor_causes_no_damage() {
+        let mut damage = CursorDamage::default();
+        let cursor = Some((CursorTexture::Named(1), rect(10, 10)));
+        assert_eq!(damage.update(cursor), vec![rect(10, 10)]);
+        assert!(damage.update(cursor).is_empty());
+    }
+
+    #[test]
+    fn moving_damages_the_old_and_new_rectangles() {
+        let mut damage = CursorDamage::default();
+        damage.update(Some((CursorTexture::Named(1), rect(10, 10))));
+        assert_eq!(
+            damage.update(Some((CursorTexture::Named(1), rect(50, 10)))),
+            vec![rect(10, 10), rect(50, 10)]
+        );
+    }
+
+    #[test]
+    fn animating_in_place_damages_once_and_hiding_clears() {
+        let mut damage = CursorDamage::default();
+        damage.update(Some((CursorTexture::Named(1), rect(10, 10))));
+        assert_eq!(
+            damage.update(Some((CursorTexture::Named(2), rect(10, 10)))),
+            vec![rect(10, 10)]
+        );
+        assert_eq!(damage.update(None), vec![rect(10, 10)]);
+        assert!(damage.update(None).is_empty());
+    }
+}
@@ -0,0 +1,449 @@
+//! Starforge Render - Frame Damage
+//!
+//! This module finds what changed on an output between two of its frames.
+//!
+//! Each element of a frame leaves a footprint: what it draws and where, apart from its
+//! content. Elements whose footprint is the same as in the last frame only damage the
+//! parts of their content that changed; new elements, the places elements left, and
+//! elements that changed places in the stack damage all they cover. Damage is tagged with
+//! the position of the element it comes from, so backdrop blurs only look at what changed
+//! below them. The passes over the whole output that follow the elements damage all of it
+//! when they change, or when the magnifier moves what is shown.
+
+use crate::RenderElement;
+use crate::accessibility::ColorFilter;
+use crate::blur::BlurSettings;
+use crate::color::ColorTransform;
+use crate::cursor::CursorTexture;
+use crate::decoration::{TitleBarContent, TitleBarRenderer};
+use crate::hud;
+use crate::night_light::GammaLutUniform;
+use crate::shape::WindowShape;
+use smithay::utils::{Physical, Rectangle, Size};
+use starforge_core::protocols::color_management::ImageDescription;
+use std::sync::Arc;
+
+/// What an element draws, apart from its content
+#[derive(Clone, Debug, PartialEq)]
+pub enum Look {
+    Surface {
+        rect: Rectangle<i32, Physical>,
+        source: [f32; 4],
+        image_description: Arc<ImageDescription>,
+        backdrop_blur: Option<BlurSettings>,
+        shape: WindowShape,
+    },
+    SolidColor {
+        rect: Rectangle<i32, Physical>,
+        color: [f32; 4],
+    },
+    /// Frame statistics, which change every frame
+    StatsHud,
+    Cursor(CursorTexture),
+    TitleBar {
+        key: u64,
+        /// `None` until it was uploaded
+        content: Option<TitleBarContent>,
+    },
+}
+
+/// What an element left on an output in one frame
+#[derive(Clone, Debug)]
+pub struct Footprint {
+    pub look: Look,
+    /// Everything the element draws, in output coordinates
+    pub bounds: Rectangle<i32, Physical>,
+    /// Parts of `bounds` whose content changed since the last frame
+    pub damage: Vec<Rectangle<i32, Physical>>,
+}
+
+impl Footprint {
+    /// The footprint of `element`, with title bars as uploaded to `title_bars`
+    pub fn of(element: &RenderElement, title_bars: &TitleBarRenderer) -> Self {
+        let to_rect = |(x, y, w, h): (i32, i32, u32, u32)| {
+            Rectangle::new((x, y).into(), (w as i32, h as i32).into())
+        };
+        match element {
+            RenderElement::ClientSurface {
+                position,
+                size,
+                source,
+                damage,
+                image_description,
+                backdrop_blur,
+                shape,
+                ..
+            } => {
+                let rect = to_rect((position.0, position.1, size.0, size.1));
+                Self {
+                    look: Look::Surface {
+                        rect,
+                        source: *source,
+                        image_description: image_description.clone(),
+                        backdrop_blur: *backdrop_blur,
+                        shape: *shape,
+                    },
+                    bounds: shape.visual_bounds(rect),
+                    damage: damage
+                        .iter()
+                        .map(|&(x, y, w, h)| to_rect((x + position.0, y + position.1, w, h)))
+                        .collect(),
+                }
+            }
+            RenderElement::SolidColor { rect: r, color } => Self {
+                look: Look::SolidColor {
+                    rect: to_rect(*r),
+                    color: *color,
+                },
+                bounds: to_rect(*r),
+                damage: Vec::new(),
+            },
+            RenderElement::StatsHud { position } => {
+                let (w, h) = hud::texture_size();
+                let bounds = to_rect((position.0, position.1, w as u32, h as u32));
+                Self {
+                    look: Look::StatsHud,
+                    bounds,
+                    damage: vec![bounds],
+                }
+            }
+            RenderElement::Cursor {
+                texture,
+                position,
+                size,
+            } => Self {
+                look: Look::Cursor(*texture),
+                bounds: to_rect((position.0, position.1, size.0, size.1)),
+                damage: Vec::new(),
+            },
+            RenderElement::TitleBar { key, rect: r } => Self {
+                look: Look::TitleBar {
+                    key: *key,
+                    content: title_bars.content(*key).cloned(),
+                },
+                bounds: to_rect(*r),
+                damage: Vec::new(),
+            },
+        }
+    }
+
+    /// Whether the element draws the same at the same place, whatever its content
+    fn same_place(&self, other: &Self) -> bool {
+        self.look == other.look && self.bounds == other.bounds
+    }
+}
+
+/// Damage of a frame, each rectangle with the position in the stack of the element it
+/// comes from
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FrameDamage {
+    rects: Vec<(usize, Rectangle<i32, Physical>)>,
+}
+
+impl FrameDamage {
+    /// All of the frame's damage
+    pub fn all(&self) -> Vec<Rectangle<i32, Physical>> {
+        self.rects.iter().map(|&(_, rect)| rect).collect()
+    }
+
+    /// Damage of the elements below the element at `index`, which is what changed in
+    /// its backdrop
+    pub fn below(&self, index: usize) -> Vec<Rectangle<i32, Physical>> {
+        self.rects
+            .iter()
+            .filter(|&&(from, _)| from < index)
+            .map(|&(_, rect)| rect)
+            .collect()
+    }
+
+    fn push(&mut self, index: usize, rect: Rectangle<i32, Physical>) {
+        if !rect.is_empty() {
+            self.rects.push((index, rect));
+        }
+    }
+}
+
+/// The passes over the whole output image, after the elements were drawn
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct OutputPasses {
+    pub encode: ColorTransform,
+    pub gamma_lut: Option<GammaLutUniform>,
+    pub filter: ColorFilter,
+    /// Whether the magnifier shows part of the output over all of it
+    pub magnified: bool,
+}
+
+impl OutputPasses {
+    /// Whether the passes show elements' damage where it is: the same passes as `last`,
+    /// without magnification
+    fn keep_damage(&self, last: &Self) -> bool {
+        self.encode == last.encode
+            && self.gamma_lut == last.gamma_lut
+            && self.filter == last.filter
+            && !self.magnified
+            && !last.magnified
+    }
+}
+
+/// Tracks what an output showed in its last frame
+#[derive(Debug, Default)]
+pub struct ElementDamage {
+    /// `None` until the first frame, which is damaged whole
+    last: Option<(Size<i32, Physical>, Vec<Footprint>)>,
+    last_passes: Option<OutputPasses>,
+}
+
+impl ElementDamage {
+    /// Damage of the output image after the frame with `damage` went through `passes`,
+    /// instead of the passes of the last frame
+    pub fn output_damage(
+        &mut self,
+        size: Size<i32, Physical>,
+        damage: Vec<Rectangle<i32, Physical>>,
+        passes: OutputPasses,
+    ) -> Vec<Rectangle<i32, Physical>> {
+        let last = self.last_passes.replace(passes);
+        match last {
+            Some(last) if passes.keep_damage(&last) => damage,
+            _ => vec![Rectangle::from_size(size)],
+        }
+    }
+
+    /// Damage caused by drawing a frame of `size` with `footprints`, bottom-most first,
+    /// instead of the last frame
+    pub fn update(&mut self, size: Size<i32, Physical>, footprints: Vec<Footprint>) -> FrameDamage {
+        let mut damage = FrameDamage::default();
+        let last = match self.last.take() {
+            Some((last_size, last)) if last_size == size => last,
+            _ => {
+                damage.push(0, Rectangle::from_size(size));
+                self.last = Some((size, footprints));
+                return damage;
+            }
+        };
+
+        // Elements still drawn the same keep their place in the last frame
+        let mut matched = vec![false; last.len()];
+        let mut kept = Vec::new();
+        for (index, footprint) in footprints.iter().enumerate() {
+            let found = last
+                .iter()
+                .enumerate()
+                .position(|(i, old)| !matched[i] && old.same_place(footprint));
+            match found {
+                Some(i) => {
+                    matched[i] = true;
+                    kept.push((index, i));
+                    for &rect in &footprint.damage {
+                        damage.push(
+                            index,
+                            rect.intersection(footprint.bounds).unwrap_or_default(),
+                        );
+                    }
+                }
+                None => damage.push(index, footprint.bounds),
+            }
+        }
+        // Elements that are gone were just above the last element below them that is
+        // still there, or below everything if there is none
+        for (i, old) in last.iter().enumerate().filter(|&(i, _)| !matched[i]) {
+            let index = kept
+                .iter()
+                .filter(|&&(_, kept)| kept < i)
+                .map(|&(index, _)| index + 1)
+                .max()
+                .unwrap_or(0);
+            damage.push(index, old.bounds);
+        }
+        // Two elements that swapped places change where they overlap
+        for &(index, i) in &kept {
+            for &(other_index, other_i) in &kept {
+                if other_index < index && other_i > i {
+                    let lower = footprints[other_index].bounds;
+                    if let Some(overlap) = footprints[index].bounds.intersection(lower) {
+                        damage.push(other_index, overlap);
+                    }
+                }
+            }
+        }
+
+        self.last = Some((size, footprints));
+        damage
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blur::{backdrop_unchanged, expand_blur_damage};
+
+    const SIZE: (i32, i32) = (200, 200);
+
+    fn rect(x: i32, y: i32, w: i32, h: i32) -> Rectangle<i32, Physical> {
+        Rectangle::new((x, y).into(), (w, h).into())
+    }
+
+    fn solid(rect: Rectangle<i32, Physical>, damage: &[Rectangle<i32, Physical>]) -> Footprint {
+        Footprint {
+            look: Look::SolidColor {
+                rect,
+                color: [1.0; 4],
+            },
+            bounds: rect,
+            damage: damage.to_vec(),
+        }
+    }
+
+    fn surface(rect: Rectangle<i32, Physical>, backdrop_blur: Option<BlurSettings>) -> Footprint {
+        Footprint {
+            look: Look::Surface {
+                rect,
+                source: [0.0, 0.0, 1.0, 1.0],
+                image_description: Arc::new(ImageDescription::srgb()),
+                backdrop_blur,
+                shape: WindowShape::default(),
+            },
+            bounds: rect,
+            damage: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn first_frame_is_damaged_whole() {
+        let mut tracker = ElementDamage::default();
+        let damage = tracker.update(SIZE.into(), vec![solid(rect(0, 0, 10, 10), &[])]);
+        assert_eq!(damage.all(), vec![Rectangle::from_size(SIZE.into())]);
+    }
+
+    #[test]
+    fn unchanged_frame_has_no_damage() {
+        let mut tracker = ElementDamage::default();
+        let frame = || {
+            vec![
+                solid(rect(0, 0, 10, 10), &[]),
+                solid(rect(5, 5, 10, 10), &[]),
+            ]
+        };
+        tracker.update(SIZE.into(), frame());
+        assert!(tracker.update(SIZE.into(), frame()).all().is_empty());
+    }
+
+    #[test]
+    fn resizing_damages_everything() {
+        let mut tracker = ElementDamage::default();
+        tracker.update(SIZE.into(), Vec::new());
+        let size = Size::from((100, 100));
+        assert_eq!(
+            tracker.update(size, Vec::new()).all(),
+            vec![Rectangle::from_size(size)]
+        );
+    }
+
+    #[test]
+    fn content_damage_is_clipped_to_the_element() {
+        let mut tracker = ElementDamage::default();
+        tracker.update(SIZE.into(), vec![solid(rect(10, 10, 10, 10), &[])]);
+        let damage = tracker.update(
+            SIZE.into(),
+            vec![solid(rect(10, 10, 10, 10), &[rect(15, 15, 20, 20)])],
+        );
+        assert_eq!(damage.all(), vec![rect(15, 15, 5, 5)]);
+    }
+
+    #[test]
+    fn moving_damages_both_places() {
+        let mut tracker = ElementDamage::default();
+        tracker.update(SIZE.into(), vec![solid(rect(0, 0, 10, 10), &[])]);
+        let damage = tracker.update(SIZE.into(), vec![solid(rect(50, 0, 10, 10), &[])]);
+        assert_eq!(damage.all(), vec![rect(50, 0, 10, 10), rect(0, 0, 10, 10)]);
+    }
+
+    #[test]
+    fn restacking_damages_the_overlap() {
+        let mut tracker = ElementDamage::default();
+        let a = || solid(rect(0, 0, 10, 10), &[]);
+        let b = || solid(rect(5, 5, 10, 10), &[]);
+        tracker.update(SIZE.into(), vec![a(), b()]);
+        let damage = tracker.update(SIZE.into(), vec![b(), a()]);
+        assert_eq!(damage.all(), vec![rect(5, 5, 5, 5)]);
+        assert_eq!(damage.below(1), vec![rect(5, 5, 5, 5)]);
+    }
+
+    #[test]
+    fn damage_above_a_blur_is_not_below_it() {
+        let settings = BlurSettings::default();
+        let window = rect(50, 50, 50, 50);
+        let frame = |popup: Rectangle<i32, Physical>| {
+            vec![
+                solid(Rectangle::from_size(SIZE.into()), &[]),
+                surface(window, Some(settings)),
+                solid(popup, &[]),
+            ]
+        };
+        let mut tracker = ElementDamage::default();
+        tracker.update(SIZE.into(), frame(rect(60, 60, 10, 10)));
+        // Only the popup above the blurred window moved
+        let damage = tracker.update(SIZE.into(), frame(rect(70, 70, 10, 10)));
+        assert!(!damage.all().is_empty());
+        let below = expand_blur_damage(&damage.below(1), &[(window, settings)]);
+        assert!(backdrop_unchanged(window, &below));
+    }
+
+    fn passes(filter: ColorFilter, magnified: bool) -> OutputPasses {
+        OutputPasses {
+            encode: ColorTransform::IDENTITY,
+            gamma_lut: None,
+            filter,
+            magnified,
+        }
+    }
+
+    #[test]
+    fn unchanged_passes_keep_the_damage() {
+        let mut tracker = ElementDamage::default();
+        let damage = vec![rect(10, 10, 10, 10)];
+        let full = vec![Rectangle::from_size(SIZE.into())];
+        let none = passes(ColorFilter::None, false);
+        assert_eq!(
+            tracker.output_damage(SIZE.into(), damage.clone(), none),
+            full
+        );
+        assert_eq!(
+            tracker.output_damage(SIZE.into(), damage.clone(), none),
+            damage
+        );
+    }
+
+    #[test]
+    fn changed_passes_damage_everything() {
+        let mut tracker = ElementDamage::default();
+        let damage = vec![rect(10, 10, 10, 10)];
+        let full = vec![Rectangle::from_size(SIZE.into())];
+        let none = passes(ColorFilter::None, false);
+        tracker.output_damage(SIZE.into(), damage.clone(), none);
+        let inverted = passes(ColorFilter::Invert, false);
+        assert_eq!(
+            tracker.output_damage(SIZE.into(), damage.clone(), inverted),
+            full
+        );
+
+        // Magnified content moves with the pointer, and zooming out shows all of it again
+        let magnified = passes(ColorFilter::Invert, true);
+        assert_eq!(
+            tracker.output_damage(SIZE.into(), damage.clone(), magnified),
+            full
+        );
+        assert_eq!(
+            tracker.output_damage(SIZE.into(), damage.clone(), magnified),
+            full
+        );
+        assert_eq!(
+            tracker.output_damage(SIZE.into(), damage.clone(), inverted),
+            full
+        );
+        assert_eq!(
+            tracker.output_damage(SIZE.into(), damage.clone(), inverted),
+            damage
+        );
+    }
+}
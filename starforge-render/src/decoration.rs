@@ -0,0 +1,510 @@
+//! Starforge Render - Title Bars
+//!
+//! This module draws the title bars of windows the compositor decorates.
+//!
+//! Title bars are rasterized on the CPU: a flat background, the window title in the HUD's
+//! pixel font, and minimize, maximize and close buttons on the right, highlighted under
+//! the pointer. Each window's texture is kept until its title bar looks different, and
+//! drawn with the window shape pipeline so its top corners round like the window's.
+
+use crate::color::ColorTransform;
+use crate::core::Context;
+use crate::hud::glyph;
+use crate::memory::{AllocatedImage, MappedBuffer};
+use crate::pipeline::{create_texture_sets, write_texture_set};
+use crate::resources::upload_image;
+use crate::shape::{Border, CornerRadii, FULL_TEXTURE, ShapeRenderer, WindowShape};
+use ash::vk;
+use smithay::utils::{Physical, Rectangle, Size};
+use starforge_core::decoration::{TitleBar, TitleBarButton};
+use starforge_core::{StarforgeError, StarforgeResult};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Title bar textures that can exist at once, replaced ones included
+const MAX_TITLE_BARS: u32 = 64;
+
+/// Frames a replaced texture is kept for, so frames still in flight can finish with it
+const RETIRED_FRAMES: usize = 3;
+
+/// Size of the title bar icons, relative to the button size
+const ICON_SIZE: f64 = 0.34;
+
+/// Space left of the title in logical pixels
+const TITLE_PADDING: f64 = 10.0;
+
+/// Colours of title bars, as straight-alpha RGBA
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TitleBarStyle {
+    pub background: [f32; 4],
+    /// Title and button icons
+    pub foreground: [f32; 4],
+    /// Background of the button under the pointer
+    pub button_hover: [f32; 4],
+    /// Background of the close button under the pointer
+    pub close_hover: [f32; 4],
+    /// Radius of the top corners in logical pixels, usually that of the windows
+    pub corner_radius: f32,
+    /// Border in logical pixels, usually that of the windows
+    pub border: Option<Border>,
+}
+
+impl Default for TitleBarStyle {
+    fn default() -> Self {
+        Self {
+            background: [0.16, 0.16, 0.2, 1.0],
+            foreground: [0.9, 0.9, 0.9, 1.0],
+            button_hover: [0.28, 0.28, 0.34, 1.0],
+            close_hover: [0.8, 0.2, 0.2, 1.0],
+            corner_radius: 8.0,
+            border: None,
+        }
+    }
+}
+
+fn to_pixel([r, g, b, a]: [f32; 4]) -> [u8; 4] {
+    let channel = |value: f32| (value.clamp(0.0, 1.0) * 255.0).round() as u8;
+    [channel(r * a), channel(g * a), channel(b * a), channel(a)]
+}
+
+/// Premultiplied RGBA8 pixels being drawn into
+struct Canvas {
+    width: usize,
+    height: usize,
+    pixels: Vec<u8>,
+}
+
+impl Canvas {
+    fn fill(&mut self, x: usize, y: usize, width: usize, height: usize, color: [u8; 4]) {
+        for y in y..(y + height).min(self.height) {
+            for x in x..(x + width).min(self.width) {
+                let offset = (y * self.width + x) * 4;
+                self.pixels[offset..offset + 4].copy_from_slice(&color);
+            }
+        }
+    }
+
+    fn outline(&mut self, x: usize, y: usize, size: usize, stroke: usize, color: [u8; 4]) {
+        self.fill(x, y, size, stroke, color);
+        self.fill(x, y + size.saturating_sub(stroke), size, stroke, color);
+        self.fill(x, y, stroke, size, color);
+        self.fill(x + size.saturating_sub(stroke), y, stroke, size, color);
+    }
+}
+
+/// Rasterize `title_bar` at `size` into premultiplied RGBA8 pixels, for an output with
+/// `scale`
+pub fn rasterize_title_bar(
+    title_bar: &TitleBar,
+    size: Size<i32, Physical>,
+    scale: f64,
+    style: &TitleBarStyle,
+) -> Vec<u8> {
+    let width = size.w.max(0) as usize;
+    let height = size.h.max(0) as usize;
+    let mut canvas = Canvas {
+        width,
+        height,
+        pixels: to_pixel(style.background).repeat(width * height),
+    };
+    let foreground = to_pixel(style.foreground);
+    let stroke = (scale.round() as usize).max(1);
+
+    // Square buttons on the right, the height of the bar
+    let button_size = height;
+    let buttons = TitleBarButton::ALL.len();
+    for (index, button) in TitleBarButton::ALL.into_iter().enumerate() {
+        let x = width.saturating_sub(button_size * (buttons - index));
+        if title_bar.hovered == Some(button) || title_bar.pressed == Some(button) {
+            let color = match button {
+                TitleBarButton::Close => style.close_hover,
+                _ => style.button_hover,
+            };
+            canvas.fill(x, 0, button_size, height, to_pixel(color));
+        }
+
+        let icon = (button_size as f64 * ICON_SIZE).round() as usize;
+        let icon_x = x + (button_size.saturating_sub(icon)) / 2;
+        let icon_y = height.saturating_sub(icon) / 2;
+        match button {
+            TitleBarButton::Minimize => canvas.fill(
+                icon_x,
+                icon_y + icon.saturating_sub(stroke),
+                icon,
+                stroke,
+                foreground,
+            ),
+            // Maximized windows show two overlapping squares, to restore them
+            TitleBarButton::Maximize if title_bar.maximized => {
+                let offset = icon / 4;
+                let square = icon - offset;
+                canvas.outline(icon_x + offset, icon_y, square, stroke, foreground);
+                canvas.outline(icon_x, icon_y + offset, square, stroke, foreground);
+            }
+            TitleBarButton::Maximize => canvas.outline(icon_x, icon_y, icon, stroke, foreground),
+            TitleBarButton::Close => {
+                for step in 0..icon {
+                    canvas.fill(icon_x + step, icon_y + step, stroke, stroke, foreground);
+                    canvas.fill(
+                        icon_x + icon - 1 - step,
+                        icon_y + step,
+                        stroke,
+                        stroke,
+                        foreground,
+                    );
+                }
+            }
+        }
+    }
+
+    // The title, cut off before the buttons
+    let font_scale = ((scale * 2.0).round() as usize).max(1);
+    let cell_width = 4 * font_scale;
+    let padding = (TITLE_PADDING * scale).round() as usize;
+    let room = width.saturating_sub(button_size * buttons + padding * 2);
+    let origin_y = height.saturating_sub(5 * font_scale) / 2;
+    for (column, c) in title_bar.title.chars().take(room / cell_width).enumerate() {
+        let Some(rows) = glyph(c) else { continue };
+        let origin_x = padding + column * cell_width;
+        for (gy, bits) in rows.iter().enumerate() {
+            for gx in 0..3 {
+                if bits & (0b100 >> gx) != 0 {
+                    canvas.fill(
+                        origin_x + gx * font_scale,
+                        origin_y + gy * font_scale,
+                        font_scale,
+                        font_scale,
+                        foreground,
+                    );
+                }
+            }
+        }
+    }
+
+    canvas.pixels
+}
+
+/// What a title bar texture shows, to tell when it has to be rasterized again
+#[derive(Clone, Debug, PartialEq)]
+pub struct TitleBarContent {
+    title: String,
+    hovered: Option<TitleBarButton>,
+    pressed: Option<TitleBarButton>,
+    maximized: bool,
+    size: Size<i32, Physical>,
+    scale: f64,
+}
+
+/// The texture of one title bar
+struct TitleBarTexture {
+    content: TitleBarContent,
+    _image: AllocatedImage,
+    set: vk::DescriptorSet,
+    /// Whether it was drawn since the last [`TitleBarRenderer::end_frame`]
+    used: bool,
+}
+
+/// Caches title bar textures and draws them
+pub struct TitleBarRenderer {
+    context: Arc<Context>,
+    style: TitleBarStyle,
+    sampler: vk::Sampler,
+    descriptor_pool: vk::DescriptorPool,
+    free_sets: Vec<vk::DescriptorSet>,
+    textures: HashMap<u64, TitleBarTexture>,
+    /// Replaced textures, with the frames left until they are freed
+    retired: Vec<(usize, TitleBarTexture)>,
+}
+
+impl TitleBarRenderer {
+    pub fn new(
+        context: Arc<Context>,
+        set_layout: vk::DescriptorSetLayout,
+        style: TitleBarStyle,
+    ) -> StarforgeResult<Self> {
+        // Title bars are drawn at their own size, so nearest sampling keeps them crisp
+        let sampler_info = vk::SamplerCreateInfo::default()
+            .mag_filter(vk::Filter::NEAREST)
+            .min_filter(vk::Filter::NEAREST)
+            .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE);
+        let sampler = unsafe {
+            context
+                .device()
+                .create_sampler(&sampler_info, None)
+                .inspect(|&sampler| context.track(sampler))
+                .map_err(|e| StarforgeError::RendererError(e.to_string()))?
+        };
+        let (descriptor_pool, free_sets) =
+            match create_texture_sets(&context, set_layout, MAX_TITLE_BARS) {
+                Ok(pool) => pool,
+                Err(e) => {
+                    context.untrack(sampler);
+                    unsafe { context.device().destroy_sampler(sampler, None) };
+                    return Err(e);
+                }
+            };
+        Ok(Self {
+            context,
+            style,
+            sampler,
+            descriptor_pool,
+            free_sets,
+            textures: HashMap::new(),
+            retired: Vec::new(),
+        })
+    }
+
+    /// A new title bar renderer on `context` with the same style
+    pub fn recreate(
+        &self,
+        context: Arc<Context>,
+        set_layout: vk::DescriptorSetLayout,
+    ) -> StarforgeResult<Self> {
+        Self::new(context, set_layout, self.style)
+    }
+
+    /// Change the colours of every title bar
+    pub fn set_style(&mut self, style: TitleBarStyle) {
+        if self.style != style {
+            self.style = style;
+            let textures: Vec<_> = self.textures.drain().map(|(_, texture)| texture).collect();
+            self.retire(textures);
+        }
+    }
+
+    /// Rasterize and upload `title_bar` at `size` on an output with `scale`, unless its
+    /// texture already shows the same
+    pub fn upload(
+        &mut self,
+        title_bar: &TitleBar,
+        size: Size<i32, Physical>,
+        scale: f64,
+    ) -> StarforgeResult<()> {
+        let content = TitleBarContent {
+            title: title_bar.title.clone(),
+            hovered: title_bar.hovered,
+            pressed: title_bar.pressed,
+            maximized: title_bar.maximized,
+            size,
+            scale,
+        };
+        if let Some(texture) = self.textures.get_mut(&title_bar.key) {
+            texture.used = true;
+            if texture.content == content {
+                return Ok(());
+            }
+        }
+        if size.w <= 0 || size.h <= 0 {
+            return Ok(());
+        }
+
+        let pixels = rasterize_title_bar(title_bar, size, scale, &self.style);
+        let mut staging = MappedBuffer::new(
+            self.context.clone(),
+            pixels.len() as vk::DeviceSize,
+            vk::BufferUsageFlags::TRANSFER_SRC,
+        )?;
+        staging.write(&pixels)?;
+        let image = upload_image(
+            &self.context,
+            &staging,
+            vk::Extent2D {
+                width: size.w as u32,
+                height: size.h as u32,
+            },
+            size.w as u32,
+            vk::Format::R8G8B8A8_UNORM,
+            vk::ComponentMapping::default(),
+        )?;
+        let set = self.allocate_set()?;
+        write_texture_set(&self.context, set, image.view(), self.sampler);
+        let replaced = self.textures.insert(
+            title_bar.key,
+            TitleBarTexture {
+                content,
+                _image: image,
+                set,
+                used: true,
+            },
+        );
+        self.retire(replaced);
+        Ok(())
+    }
+
+    /// What the texture of title bar `key` shows, `None` if it wasn't uploaded
+    pub fn content(&self, key: u64) -> Option<&TitleBarContent> {
+        self.textures.get(&key).map(|texture| &texture.content)
+    }
+
+    fn retire(&mut self, textures: impl IntoIterator<Item = TitleBarTexture>) {
+        self.retired.extend(
+            textures
+                .into_iter()
+                .map(|texture| (RETIRED_FRAMES, texture)),
+        );
+    }
+
+    fn allocate_set(&mut self) -> StarforgeResult<vk::DescriptorSet> {
+        if self.free_sets.is_empty() {
+            // Only after many windows or replacements in a short time: wait for the GPU
+            // to be done with replaced textures
+            self.context
+                .check(unsafe { self.context.device().device_wait_idle() })?;
+            self.free_sets
+                .extend(self.retired.drain(..).map(|(_, texture)| texture.set));
+        }
+        self.free_sets.pop().ok_or_else(|| {
+            StarforgeError::RendererError(format!("more than {MAX_TITLE_BARS} title bars"))
+        })
+    }
+
+    /// Draw the title bar of window `key` at `rect` into the attachment currently being
+    /// rendered to, decoding its sRGB colors with `decode`
+    #[allow(clippy::too_many_arguments)]
+    pub fn draw(
+        &mut self,
+        command_buffer: vk::CommandBuffer,
+        shapes: &mut ShapeRenderer,
+        format: vk::Format,
+        output_size: Size<i32, Physical>,
+        key: u64,
+        rect: Rectangle<i32, Physical>,
+        decode: &ColorTransform,
+    ) -> StarforgeResult<()> {
+        let Some(texture) = self.textures.get_mut(&key) else {
+            return Err(StarforgeError::RendererError(format!(
+                "title bar {key} was not uploaded"
+            )));
+        };
+        texture.used = true;
+        let scale = texture.content.scale as f32;
+        let radius = self.style.corner_radius * scale;
+        let shape = WindowShape {
+            corner_radii: CornerRadii {
+                top_left: radius,
+                top_right: radius,
+                ..CornerRadii::ZERO
+            },
+            border: self.style.border.map(|border| Border {
+                width: border.width * scale,
+                ..border
+            }),
+            ..Default::default()
+        };
+        shapes.record(
+            command_buffer,
+            format,
+            output_size,
+            texture.set,
+            rect,
+            FULL_TEXTURE,
+            &shape,
+            decode,
+            1.0,
+        )
+    }
+
+    /// Free the textures of title bars that weren't uploaded or drawn since the last call,
+    /// their windows are gone, and of replaced ones no frame uses anymore
+    pub fn end_frame(&mut self) {
+        let unused: Vec<_> = self
+            .textures
+            .iter()
+            .filter(|(_, texture)| !texture.used)
+            .map(|(&key, _)| key)
+            .collect();
+        let unused: Vec<_> = unused
+            .into_iter()
+            .filter_map(|key| self.textures.remove(&key))
+            .collect();
+        for texture in self.textures.values_mut() {
+            texture.used = false;
+        }
+        let mut free_sets = Vec::new();
+        self.retired.retain_mut(|(frames, texture)| {
+            *frames -= 1;
+            if *frames == 0 {
+                free_sets.push(texture.set);
+            }
+            *frames > 0
+        });
+        self.free_sets.extend(free_sets);
+        self.retire(unused);
+    }
+}
+
+impl Drop for TitleBarRenderer {
+    fn drop(&mut self) {
+        self.textures.clear();
+        self.retired.clear();
+        unsafe {
+            self.context.untrack(self.descriptor_pool);
+            self.context
+                .device()
+                .destroy_descriptor_pool(self.descriptor_pool, None);
+            self.context.untrack(self.sampler);
+            self.context.device().destroy_sampler(self.sampler, None);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use smithay::utils::Rectangle;
+
+    fn title_bar(hovered: Option<TitleBarButton>) -> TitleBar {
+        TitleBar {
+            key: 0,
+            geometry: Rectangle::new((0, 0).into(), (300, 30).into()),
+            title: "terminal".to_string(),
+            hovered,
+            pressed: None,
+            maximized: false,
+        }
+    }
+
+    fn pixel(pixels: &[u8], width: usize, x: usize, y: usize) -> [u8; 4] {
+        pixels[(y * width + x) * 4..][..4].try_into().unwrap()
+    }
+
+    #[test]
+    fn title_bars_fill_their_size() {
+        let style = TitleBarStyle::default();
+        let pixels = rasterize_title_bar(&title_bar(None), (600, 60).into(), 2.0, &style);
+        assert_eq!(pixels.len(), 600 * 60 * 4);
+        assert_eq!(pixel(&pixels, 600, 0, 0), to_pixel(style.background));
+    }
+
+    #[test]
+    fn the_hovered_button_is_highlighted() {
+        let style = TitleBarStyle::default();
+        let size = (300, 30).into();
+        let plain = rasterize_title_bar(&title_bar(None), size, 1.0, &style);
+        let close = rasterize_title_bar(&title_bar(Some(TitleBarButton::Close)), size, 1.0, &style);
+        let maximize = rasterize_title_bar(
+            &title_bar(Some(TitleBarButton::Maximize)),
+            size,
+            1.0,
+            &style,
+        );
+        // Top left corner of the close button, away from its icon
+        assert_eq!(pixel(&plain, 300, 271, 1), to_pixel(style.background));
+        assert_eq!(pixel(&close, 300, 271, 1), to_pixel(style.close_hover));
+        assert_eq!(pixel(&maximize, 300, 241, 1), to_pixel(style.button_hover));
+        assert_eq!(pixel(&maximize, 300, 271, 1), to_pixel(style.background));
+    }
+
+    #[test]
+    fn the_title_is_drawn_after_the_padding() {
+        let style = TitleBarStyle::default();
+        let pixels = rasterize_title_bar(&title_bar(None), (300, 30).into(), 1.0, &style);
+        // Top row of "t" is fully set, two pixels per font pixel at scale 1
+        let y = (30 - 10) / 2;
+        assert_eq!(pixel(&pixels, 300, 10, y), to_pixel(style.foreground));
+        assert_eq!(pixel(&pixels, 300, 15, y), to_pixel(style.foreground));
+        assert_eq!(pixel(&pixels, 300, 9, y), to_pixel(style.background));
+    }
+}
@@ -0,0 +1,563 @@
+//! Starforge Render - DMA-BUF Import
+//!
+//! This module imports client dma-bufs as sampled Vulkan images, or as targets screen
+//! captures are copied into. Single-plane RGB formats are supported, with every modifier
+//! the driver can use and import with one memory plane. Planes of YUV video buffers are
+//! imported as images of their own, see `yuv.rs`.
+
+use crate::core::Context;
+use crate::yuv::YuvFormat;
+use ash::vk;
+use smithay::backend::allocator::{Buffer, Format, Fourcc, Modifier, dmabuf::Dmabuf};
+use starforge_core::{StarforgeError, StarforgeResult};
+use std::os::fd::{AsFd, AsRawFd, IntoRawFd};
+use std::sync::Arc;
+
+/// Importable formats, their Vulkan format and whether alpha is ignored
+const DMABUF_FORMATS: [(Fourcc, vk::Format, bool); 10] = [
+    (Fourcc::Argb8888, vk::Format::B8G8R8A8_UNORM, false),
+    (Fourcc::Xrgb8888, vk::Format::B8G8R8A8_UNORM, true),
+    (Fourcc::Abgr8888, vk::Format::R8G8B8A8_UNORM, false),
+    (Fourcc::Xbgr8888, vk::Format::R8G8B8A8_UNORM, true),
+    (
+        Fourcc::Argb2101010,
+        vk::Format::A2R10G10B10_UNORM_PACK32,
+        false,
+    ),
+    (
+        Fourcc::Xrgb2101010,
+        vk::Format::A2R10G10B10_UNORM_PACK32,
+        true,
+    ),
+    (
+        Fourcc::Abgr2101010,
+        vk::Format::A2B10G10R10_UNORM_PACK32,
+        false,
+    ),
+    (
+        Fourcc::Xbgr2101010,
+        vk::Format::A2B10G10R10_UNORM_PACK32,
+        true,
+    ),
+    (
+        Fourcc::Abgr16161616f,
+        vk::Format::R16G16B16A16_SFLOAT,
+        false,
+    ),
+    (Fourcc::Xbgr16161616f, vk::Format::R16G16B16A16_SFLOAT, true),
+];
+
+/// Vulkan format and view swizzle of a DRM format
+fn vk_format(fourcc: Fourcc) -> Option<(vk::Format, vk::ComponentMapping)> {
+    DMABUF_FORMATS
+        .iter()
+        .find(|(code, _, _)| *code == fourcc)
+        .map(|&(_, format, opaque)| {
+            let components = if opaque {
+                vk::ComponentMapping {
+                    a: vk::ComponentSwizzle::ONE,
+                    ..Default::default()
+                }
+            } else {
+                Default::default()
+            };
+            (format, components)
+        })
+}
+
+/// Every format and modifier pair that can be imported and sampled
+pub fn supported_formats(context: &Context) -> Vec<Format> {
+    let mut formats = formats_with_usage(
+        context,
+        vk::ImageUsageFlags::SAMPLED,
+        vk::FormatFeatureFlags::SAMPLED_IMAGE,
+    );
+    formats.extend(yuv_formats(context));
+    formats
+}
+
+/// YUV formats with every modifier all of their planes can be imported and sampled with
+fn yuv_formats(context: &Context) -> Vec<Format> {
+    let mut formats = Vec::new();
+    for yuv in YuvFormat::ALL {
+        let mut modifiers: Option<Vec<u64>> = None;
+        for plane in yuv.planes() {
+            let plane_modifiers = importable_modifiers(
+                context,
+                plane.format,
+                vk::ImageUsageFlags::SAMPLED,
+                vk::FormatFeatureFlags::SAMPLED_IMAGE,
+            );
+            modifiers = Some(match modifiers {
+                Some(modifiers) => modifiers
+                    .into_iter()
+                    .filter(|modifier| plane_modifiers.contains(modifier))
+                    .collect(),
+                None => plane_modifiers,
+            });
+        }
+        formats.extend(
+            modifiers
+                .unwrap_or_default()
+                .into_iter()
+                .map(|modifier| Format {
+                    code: yuv.fourcc(),
+                    modifier: Modifier::from(modifier),
+                }),
+        );
+    }
+    formats
+}
+
+/// Every format and modifier pair that can be imported as a capture target, which is
+/// blitted and drawn to
+pub fn capture_formats(context: &Context) -> Vec<Format> {
+    formats_with_usage(context, CAPTURE_USAGE, CAPTURE_FEATURES)
+}
+
+/// Usage of dma-bufs imported as capture targets
+pub const CAPTURE_USAGE: vk::ImageUsageFlags = vk::ImageUsageFlags::from_raw(
+    vk::ImageUsageFlags::TRANSFER_DST.as_raw() | vk::ImageUsageFlags::COLOR_ATTACHMENT.as_raw(),
+);
+
+const CAPTURE_FEATURES: vk::FormatFeatureFlags = vk::FormatFeatureFlags::from_raw(
+    vk::FormatFeatureFlags::BLIT_DST.as_raw()
+        | vk::FormatFeatureFlags::COLOR_ATTACHMENT_BLEND.as_raw(),
+);
+
+fn formats_with_usage(
+    context: &Context,
+    usage: vk::ImageUsageFlags,
+    features: vk::FormatFeatureFlags,
+) -> Vec<Format> {
+    let mut formats = Vec::new();
+    for (code, format, _) in DMABUF_FORMATS {
+        for modifier in importable_modifiers(context, format, usage, features) {
+            formats.push(Format {
+                code,
+                modifier: Modifier::from(modifier),
+            });
+        }
+    }
+    formats
+}
+
+/// Modifiers images of `format` can be imported with for `usage`
+fn importable_modifiers(
+    context: &Context,
+    format: vk::Format,
+    usage: vk::ImageUsageFlags,
+    features: vk::FormatFeatureFlags,
+) -> Vec<u64> {
+    modifier_properties(context, format)
+        .into_iter()
+        // Compressed modifiers need auxiliary memory planes, which aren't handled
+        .filter(|properties| {
+            properties.drm_format_modifier_plane_count == 1
+                && properties
+                    .drm_format_modifier_tiling_features
+                    .contains(features)
+                && can_import(context, format, properties.drm_format_modifier, usage)
+        })
+        .map(|properties| properties.drm_format_modifier)
+        .collect()
+}
+
+/// Modifiers the driver supports for `format`
+fn modifier_properties(
+    context: &Context,
+    format: vk::Format,
+) -> Vec<vk::DrmFormatModifierPropertiesEXT> {
+    let instance = context.instance();
+    unsafe {
+        let mut list = vk::DrmFormatModifierPropertiesListEXT::default();
+        let mut properties = vk::FormatProperties2::default().push_next(&mut list);
+        instance.get_physical_device_format_properties2(
+            context.physical_device(),
+            format,
+            &mut properties,
+        );
+
+        let mut modifiers = vec![
+            vk::DrmFormatModifierPropertiesEXT::default();
+            list.drm_format_modifier_count as usize
+        ];
+        let mut list = vk::DrmFormatModifierPropertiesListEXT::default()
+            .drm_format_modifier_properties(&mut modifiers);
+        let mut properties = vk::FormatProperties2::default().push_next(&mut list);
+        instance.get_physical_device_format_properties2(
+            context.physical_device(),
+            format,
+            &mut properties,
+        );
+        let count = list.drm_format_modifier_count as usize;
+        modifiers.truncate(count);
+        modifiers
+    }
+}
+
+/// Whether images of `format` and `modifier` can be imported from dma-bufs for `usage`
+fn can_import(
+    context: &Context,
+    format: vk::Format,
+    modifier: u64,
+    usage: vk::ImageUsageFlags,
+) -> bool {
+    let mut modifier_info = vk::PhysicalDeviceImageDrmFormatModifierInfoEXT::default()
+        .drm_format_modifier(modifier)
+        .sharing_mode(vk::SharingMode::EXCLUSIVE);
+    let mut external_info = vk::PhysicalDeviceExternalImageFormatInfo::default()
+        .handle_type(vk::ExternalMemoryHandleTypeFlags::DMA_BUF_EXT);
+    let format_info = vk::PhysicalDeviceImageFormatInfo2::default()
+        .format(format)
+        .ty(vk::ImageType::TYPE_2D)
+        .tiling(vk::ImageTiling::DRM_FORMAT_MODIFIER_EXT)
+        .usage(usage)
+        .push_next(&mut modifier_info)
+        .push_next(&mut external_info);
+    let mut external_properties = vk::ExternalImageFormatProperties::default();
+    let mut properties = vk::ImageFormatProperties2::default().push_next(&mut external_properties);
+    let supported = unsafe {
+        context
+            .instance()
+            .get_physical_device_image_format_properties2(
+                context.physical_device(),
+                &format_info,
+                &mut properties,
+            )
+    };
+    supported.is_ok()
+        && external_properties
+            .external_memory_properties
+            .external_memory_features
+            .contains(vk::ExternalMemoryFeatureFlags::IMPORTABLE)
+}
+
+/// A client dma-buf imported as a sampled image
+///
+/// The memory stays shared with the client, so every use has to be wrapped in
+/// [`DmabufImage::record_acquire`] and [`DmabufImage::record_release`].
+pub struct DmabufImage {
+    context: Arc<Context>,
+    image: vk::Image,
+    memory: vk::DeviceMemory,
+    view: vk::ImageView,
+    format: vk::Format,
+    extent: vk::Extent2D,
+    /// Whether the image left its initial undefined layout
+    acquired: bool,
+}
+
+impl DmabufImage {
+    pub fn import(context: Arc<Context>, dmabuf: &Dmabuf) -> StarforgeResult<Self> {
+        Self::import_with_usage(context, dmabuf, vk::ImageUsageFlags::SAMPLED)
+    }
+
+    /// Like [`Self::import`], for other uses than sampling
+    pub fn import_with_usage(
+        context: Arc<Context>,
+        dmabuf: &Dmabuf,
+        usage: vk::ImageUsageFlags,
+    ) -> StarforgeResult<Self> {
+        let format = dmabuf.format();
+        let (vk_format, components) = vk_format(format.code).ok_or_else(|| {
+            StarforgeError::RendererError(format!("unsupported dma-buf format {:?}", format.code))
+        })?;
+        if dmabuf.num_planes() != 1 {
+            return Err(StarforgeError::RendererError(format!(
+                "expected 1 dma-buf plane, got {}",
+                dmabuf.num_planes()
+            )));
+        }
+        let extent = vk::Extent2D {
+            width: dmabuf.width(),
+            height: dmabuf.height(),
+        };
+        Self::import_memory_plane(context, dmabuf, 0, vk_format, components, extent, usage)
+    }
+
+    /// Import plane `plane` of a multi-planar dma-buf as a sampled image of its own, with
+    /// `format` and `extent`
+    pub fn import_plane(
+        context: Arc<Context>,
+        dmabuf: &Dmabuf,
+        plane: usize,
+        format: vk::Format,
+        extent: vk::Extent2D,
+    ) -> StarforgeResult<Self> {
+        if plane >= dmabuf.num_planes() {
+            return Err(StarforgeError::RendererError(format!(
+                "dma-buf has no plane {plane}"
+            )));
+        }
+        Self::import_memory_plane(
+            context,
+            dmabuf,
+            plane,
+            format,
+            vk::ComponentMapping::default(),
+            extent,
+            vk::ImageUsageFlags::SAMPLED,
+        )
+    }
+
+    fn import_memory_plane(
+        context: Arc<Context>,
+        dmabuf: &Dmabuf,
+        plane: usize,
+        vk_format: vk::Format,
+        components: vk::ComponentMapping,
+        extent: vk::Extent2D,
+        usage: vk::ImageUsageFlags,
+    ) -> StarforgeResult<Self> {
+        let modifier = dmabuf.format().modifier;
+
+        // From here on `Drop` destroys whatever was created if a step fails
+        let mut imported = Self {
+            context: context.clone(),
+            image: vk::Image::null(),
+            memory: vk::DeviceMemory::null(),
+            view: vk::ImageView::null(),
+            format: vk_format,
+            extent,
+            acquired: false,
+        };
+        let device = context.device();
+
+        let plane_layouts = [vk::SubresourceLayout {
+            offset: dmabuf.offsets().nth(plane).unwrap() as vk::DeviceSize,
+            row_pitch: dmabuf.strides().nth(plane).unwrap() as vk::DeviceSize,
+            ..Default::default()
+        }];
+        let mut modifier_info = vk::ImageDrmFormatModifierExplicitCreateInfoEXT::default()
+            .drm_format_modifier(modifier.into())
+            .plane_layouts(&plane_layouts);
+        let mut external_info = vk::ExternalMemoryImageCreateInfo::default()
+            .handle_types(vk::ExternalMemoryHandleTypeFlags::DMA_BUF_EXT);
+        let image_info = vk::ImageCreateInfo::default()
+            .image_type(vk::ImageType::TYPE_2D)
+            .format(vk_format)
+            .extent(vk::Extent3D {
+                width: extent.width,
+                height: extent.height,
+                depth: 1,
+            })
+            .mip_levels(1)
+            .array_layers(1)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .tiling(vk::ImageTiling::DRM_FORMAT_MODIFIER_EXT)
+            .usage(usage)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .push_next(&mut modifier_info)
+            .push_next(&mut external_info);
+        imported.image = context.check(unsafe { device.create_image(&image_info, None) })?;
+        context.track(imported.image);
+
+        // The allocation takes ownership of its own copy of the file descriptor
+        let fd = dmabuf
+            .handles()
+            .nth(plane)
+            .unwrap()
+            .as_fd()
+            .try_clone_to_owned()
+            .map_err(|e| StarforgeError::RendererError(e.to_string()))?;
+        let memory_fd = ash::khr::external_memory_fd::Device::new(context.instance(), device);
+        let mut fd_properties = vk::MemoryFdPropertiesKHR::default();
+        context.check(unsafe {
+            memory_fd.get_memory_fd_properties(
+                vk::ExternalMemoryHandleTypeFlags::DMA_BUF_EXT,
+                fd.as_raw_fd(),
+                &mut fd_properties,
+            )
+        })?;
+        let requirements = unsafe { device.get_image_memory_requirements(imported.image) };
+        let memory_types = requirements.memory_type_bits & fd_properties.memory_type_bits;
+        if memory_types == 0 {
+            return Err(StarforgeError::RendererError(
+                "no memory type can hold the dma-buf".into(),
+            ));
+        }
+
+        let mut dedicated_info = vk::MemoryDedicatedAllocateInfo::default().image(imported.image);
+        let mut import_info = vk::ImportMemoryFdInfoKHR::default()
+            .handle_type(vk::ExternalMemoryHandleTypeFlags::DMA_BUF_EXT)
+            .fd(fd.as_raw_fd());
+        let allocate_info = vk::MemoryAllocateInfo::default()
+            .allocation_size(requirements.size)
+            .memory_type_index(memory_types.trailing_zeros())
+            .push_next(&mut import_info)
+            .push_next(&mut dedicated_info);
+        imported.memory = context.check(unsafe { device.allocate_memory(&allocate_info, None) })?;
+        // The memory owns the file descriptor once the import succeeded
+        let _ = fd.into_raw_fd();
+        context.track(imported.memory);
+        context.check(unsafe { device.bind_image_memory(imported.image, imported.memory, 0) })?;
+
+        let view_info = vk::ImageViewCreateInfo::default()
+            .image(imported.image)
+            .view_type(vk::ImageViewType::TYPE_2D)
+            .format(vk_format)
+            .components(if usage.contains(vk::ImageUsageFlags::SAMPLED) {
+                components
+            } else {
+                // Attachments can't swizzle, writes leave the ignored alpha alone anyway
+                vk::ComponentMapping::default()
+            })
+            .subresource_range(SUBRESOURCE_RANGE);
+        imported.view = context.check(unsafe { device.create_image_view(&view_info, None) })?;
+        context.track(imported.view);
+
+        Ok(imported)
+    }
+
+    pub fn image(&self) -> vk::Image {
+        self.image
+    }
+
+    pub fn view(&self) -> vk::ImageView {
+        self.view
+    }
+
+    pub fn format(&self) -> vk::Format {
+        self.format
+    }
+
+    pub fn extent(&self) -> vk::Extent2D {
+        self.extent
+    }
+
+    /// Take the image over from the client before sampling it in `command_buffer`
+    pub fn record_acquire(&mut self, command_buffer: vk::CommandBuffer) {
+        let old_layout = if self.acquired {
+            vk::ImageLayout::GENERAL
+        } else {
+            vk::ImageLayout::UNDEFINED
+        };
+        self.acquired = true;
+        let barrier = vk::ImageMemoryBarrier2::default()
+            .src_stage_mask(vk::PipelineStageFlags2::NONE)
+            .dst_stage_mask(vk::PipelineStageFlags2::FRAGMENT_SHADER)
+            .dst_access_mask(vk::AccessFlags2::SHADER_SAMPLED_READ)
+            .old_layout(old_layout)
+            .new_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .src_queue_family_index(vk::QUEUE_FAMILY_EXTERNAL)
+            .dst_queue_family_index(self.context.graphics_queue().family_index)
+            .image(self.image)
+            .subresource_range(SUBRESOURCE_RANGE);
+        self.record_barrier(command_buffer, barrier);
+    }
+
+    /// Hand the image back to the client after the last use in `command_buffer`
+    pub fn record_release(&self, command_buffer: vk::CommandBuffer) {
+        let barrier = vk::ImageMemoryBarrier2::default()
+            .src_stage_mask(vk::PipelineStageFlags2::FRAGMENT_SHADER)
+            .src_access_mask(vk::AccessFlags2::SHADER_SAMPLED_READ)
+            .dst_stage_mask(vk::PipelineStageFlags2::NONE)
+            .old_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .new_layout(vk::ImageLayout::GENERAL)
+            .src_queue_family_index(self.context.graphics_queue().family_index)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_EXTERNAL)
+            .image(self.image)
+            .subresource_range(SUBRESOURCE_RANGE);
+        self.record_barrier(command_buffer, barrier);
+    }
+
+    /// Take the image over from the client before writing a capture into it in
+    /// `command_buffer`
+    ///
+    /// The image stays in the general layout, so what the client has in it is kept and
+    /// only the captured regions are overwritten.
+    pub fn record_acquire_for_writing(&mut self, command_buffer: vk::CommandBuffer) {
+        self.acquired = true;
+        let barrier = vk::ImageMemoryBarrier2::default()
+            .src_stage_mask(vk::PipelineStageFlags2::NONE)
+            .dst_stage_mask(WRITE_STAGES)
+            .dst_access_mask(WRITE_ACCESS)
+            .old_layout(vk::ImageLayout::GENERAL)
+            .new_layout(vk::ImageLayout::GENERAL)
+            .src_queue_family_index(vk::QUEUE_FAMILY_EXTERNAL)
+            .dst_queue_family_index(self.context.graphics_queue().family_index)
+            .image(self.image)
+            .subresource_range(SUBRESOURCE_RANGE);
+        self.record_barrier(command_buffer, barrier);
+    }
+
+    /// Hand the image back to the client after a capture was written in `command_buffer`
+    pub fn record_release_after_writing(&self, command_buffer: vk::CommandBuffer) {
+        let barrier = vk::ImageMemoryBarrier2::default()
+            .src_stage_mask(WRITE_STAGES)
+            .src_access_mask(WRITE_ACCESS)
+            .dst_stage_mask(vk::PipelineStageFlags2::NONE)
+            .old_layout(vk::ImageLayout::GENERAL)
+            .new_layout(vk::ImageLayout::GENERAL)
+            .src_queue_family_index(self.context.graphics_queue().family_index)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_EXTERNAL)
+            .image(self.image)
+            .subresource_range(SUBRESOURCE_RANGE);
+        self.record_barrier(command_buffer, barrier);
+    }
+
+    fn record_barrier(&self, command_buffer: vk::CommandBuffer, barrier: vk::ImageMemoryBarrier2) {
+        let barriers = [barrier];
+        let dependency = vk::DependencyInfo::default().image_memory_barriers(&barriers);
+        unsafe {
+            self.context
+                .device()
+                .cmd_pipeline_barrier2(command_buffer, &dependency)
+        };
+    }
+}
+
+impl Drop for DmabufImage {
+    fn drop(&mut self) {
+        let context = &self.context;
+        unsafe {
+            context.untrack(self.view);
+            context.device().destroy_image_view(self.view, None);
+            context.untrack(self.image);
+            context.device().destroy_image(self.image, None);
+            context.untrack(self.memory);
+            context.device().free_memory(self.memory, None);
+        }
+    }
+}
+
+/// Stages and accesses of capture writes: blits, then cursor drawing
+const WRITE_STAGES: vk::PipelineStageFlags2 = vk::PipelineStageFlags2::from_raw(
+    vk::PipelineStageFlags2::BLIT.as_raw()
+        | vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT.as_raw(),
+);
+const WRITE_ACCESS: vk::AccessFlags2 = vk::AccessFlags2::from_raw(
+    vk::AccessFlags2::TRANSFER_WRITE.as_raw()
+        | vk::AccessFlags2::COLOR_ATTACHMENT_READ.as_raw()
+        | vk::AccessFlags2::COLOR_ATTACHMENT_WRITE.as_raw(),
+);
+
+const SUBRESOURCE_RANGE: vk::ImageSubresourceRange = vk::ImageSubresourceRange {
+    aspect_mask: vk::ImageAspectFlags::COLOR,
+    base_mip_level: 0,
+    level_count: 1,
+    base_array_layer: 0,
+    layer_count: 1,
+};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn opaque_formats_ignore_alpha() {
+        let (format, components) = vk_format(Fourcc::Xrgb8888).unwrap();
+        assert_eq!(format, vk::Format::B8G8R8A8_UNORM);
+        assert_eq!(components.a, vk::ComponentSwizzle::ONE);
+
+        let (_, components) = vk_format(Fourcc::Argb8888).unwrap();
+        assert_eq!(components.a, vk::ComponentSwizzle::IDENTITY);
+    }
+
+    #[test]
+    fn yuv_formats_are_not_importable() {
+        assert!(vk_format(Fourcc::Nv12).is_none());
+    }
+}
@@ -1,3 +1,64 @@
 //! Starforge Render - Error Handling
 //!
 //! This module defines the error types and handling for the Starforge Render library
+
+use starforge_core::StarforgeError;
+use std::fmt;
+use std::path::PathBuf;
+use thiserror::Error;
+
+/// A single problem found while compiling an effect shader
+#[derive(Clone, Debug, PartialEq)]
+pub struct ShaderDiagnostic {
+    /// Line in the shader source, or `None` when the problem isn't tied to user code
+    pub line: Option<u32>,
+    pub column: u32,
+    pub message: String,
+}
+
+impl fmt::Display for ShaderDiagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "{line}:{}: {}", self.column, self.message),
+            None => f.write_str(&self.message),
+        }
+    }
+}
+
+/// Error type for shader loading and compilation
+#[derive(Error, Debug)]
+pub enum ShaderError {
+    #[error("Failed to read shader {0}: {1}")]
+    Io(PathBuf, std::io::Error),
+    #[error("Unknown shader language for {0}, expected a .glsl, .frag or .wgsl file")]
+    UnknownLanguage(PathBuf),
+    #[error("Shader failed to compile:{}", format_diagnostics(.0))]
+    Compile(Vec<ShaderDiagnostic>),
+    #[error("Shader does not match the effect ABI:{}", format_diagnostics(.0))]
+    Abi(Vec<ShaderDiagnostic>),
+    #[error("Failed to create shader module: {0}")]
+    Vulkan(ash::vk::Result),
+}
+
+impl ShaderError {
+    /// The diagnostics of a compile or ABI error
+    pub fn diagnostics(&self) -> &[ShaderDiagnostic] {
+        match self {
+            ShaderError::Compile(diagnostics) | ShaderError::Abi(diagnostics) => diagnostics,
+            _ => &[],
+        }
+    }
+}
+
+impl From<ShaderError> for StarforgeError {
+    fn from(error: ShaderError) -> Self {
+        StarforgeError::RendererError(error.to_string())
+    }
+}
+
+fn format_diagnostics(diagnostics: &[ShaderDiagnostic]) -> String {
+    diagnostics
+        .iter()
+        .map(|diagnostic| format!("\n  {diagnostic}"))
+        .collect()
+}
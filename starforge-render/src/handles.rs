@@ -0,0 +1,153 @@
+//! Starforge Render - Live Handle Tracking
+//!
+//! This module keeps a registry of the Vulkan objects created on a context. In debug builds
+//! every handle is recorded with the backtrace of its creation, so objects still alive when
+//! the context is destroyed can be reported as leaks. Release builds track nothing.
+
+use ash::vk;
+#[cfg(debug_assertions)]
+use std::{backtrace::Backtrace, collections::HashMap, sync::Mutex};
+use tracing::error;
+
+/// A Vulkan object that was never destroyed
+#[derive(Debug)]
+pub struct LeakedHandle {
+    pub object_type: vk::ObjectType,
+    pub raw: u64,
+    /// Where the object was created
+    pub backtrace: String,
+}
+
+/// Registry of the live Vulkan handles of one context
+#[derive(Default)]
+pub struct HandleRegistry {
+    #[cfg(debug_assertions)]
+    live: Mutex<HashMap<(vk::ObjectType, u64), Backtrace>>,
+}
+
+impl HandleRegistry {
+    /// Record that `handle` was just created
+    #[cfg(debug_assertions)]
+    pub fn track<H: vk::Handle>(&self, handle: H) {
+        let key = (H::TYPE, handle.as_raw());
+        if key.1 != 0 {
+            self.live
+                .lock()
+                .unwrap()
+                .insert(key, Backtrace::force_capture());
+        }
+    }
+
+    /// Record that `handle` is about to be destroyed
+    #[cfg(debug_assertions)]
+    pub fn untrack<H: vk::Handle>(&self, handle: H) {
+        self.live
+            .lock()
+            .unwrap()
+            .remove(&(H::TYPE, handle.as_raw()));
+    }
+
+    /// Every handle that was tracked but not untracked, sorted by type
+    #[cfg(debug_assertions)]
+    pub fn leaks(&self) -> Vec<LeakedHandle> {
+        let mut leaks: Vec<_> = self
+            .live
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(&(object_type, raw), backtrace)| LeakedHandle {
+                object_type,
+                raw,
+                backtrace: backtrace.to_string(),
+            })
+            .collect();
+        leaks.sort_by_key(|leak| (leak.object_type.as_raw(), leak.raw));
+        leaks
+    }
+
+    #[cfg(not(debug_assertions))]
+    pub fn track<H: vk::Handle>(&self, _handle: H) {}
+
+    #[cfg(not(debug_assertions))]
+    pub fn untrack<H: vk::Handle>(&self, _handle: H) {}
+
+    #[cfg(not(debug_assertions))]
+    pub fn leaks(&self) -> Vec<LeakedHandle> {
+        Vec::new()
+    }
+
+    /// Log every leaked handle with its creation backtrace, returning how many there were
+    pub fn report_leaks(&self) -> usize {
+        let leaks = self.leaks();
+        for leak in &leaks {
+            error!(
+                "Leaked Vulkan {:?} {:#x}, created at:\n{}",
+                leak.object_type, leak.raw, leak.backtrace
+            );
+        }
+        if !leaks.is_empty() {
+            error!("{} Vulkan objects leaked.", leaks.len());
+        }
+        leaks.len()
+    }
+}
+
+#[cfg(all(test, debug_assertions))]
+mod tests {
+    use super::*;
+    use ash::vk::Handle;
+
+    #[test]
+    fn destroyed_handles_are_not_leaks() {
+        let registry = HandleRegistry::default();
+        registry.track(vk::Buffer::from_raw(1));
+        registry.track(vk::Image::from_raw(2));
+        registry.untrack(vk::Buffer::from_raw(1));
+
+        let leaks = registry.leaks();
+        assert_eq!(leaks.len(), 1);
+        assert_eq!(leaks[0].object_type, vk::ObjectType::IMAGE);
+        assert_eq!(leaks[0].raw, 2);
+        assert!(!leaks[0].backtrace.is_empty());
+    }
+
+    #[test]
+    fn handles_of_different_types_are_distinct() {
+        let registry = HandleRegistry::default();
+        registry.track(vk::Buffer::from_raw(7));
+        registry.track(vk::Sampler::from_raw(7));
+        registry.untrack(vk::Sampler::from_raw(7));
+        assert_eq!(registry.leaks()[0].object_type, vk::ObjectType::BUFFER);
+    }
+
+    #[test]
+    fn null_handles_are_ignored() {
+        let registry = HandleRegistry::default();
+        registry.track(vk::QueryPool::null());
+        assert_eq!(registry.report_leaks(), 0);
+    }
+
+    #[test]
+    fn leaks_are_reported_with_where_they_were_created() {
+        let registry = HandleRegistry::default();
+        registry.track(vk::Fence::from_raw(3));
+        registry.track(vk::Semaphore::from_raw(4));
+        assert_eq!(registry.report_leaks(), 2);
+
+        let leaks = registry.leaks();
+        assert!(leaks.iter().all(|leak| {
+            leak.backtrace
+                .contains("leaks_are_reported_with_where_they_were_created")
+        }));
+    }
+
+    #[test]
+    fn untracking_unknown_handles_is_harmless() {
+        let registry = HandleRegistry::default();
+        registry.untrack(vk::ImageView::from_raw(5));
+        registry.track(vk::ImageView::from_raw(5));
+        registry.untrack(vk::ImageView::from_raw(5));
+        registry.untrack(vk::ImageView::from_raw(5));
+        assert_eq!(registry.report_leaks(), 0);
+    }
+}
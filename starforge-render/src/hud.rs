@@ -0,0 +1,460 @@
+//! Starforge Render - Statistics HUD
+//!
+//! This module draws an output's frame statistics on top of it.
+//!
+//! The text is rasterized on the CPU with a built-in 3x5 pixel font, uploaded to a small
+//! texture per frame in flight, and drawn with the window shape pipeline so it gets
+//! rounded corners like everything else.
+
+use crate::color::ColorTransform;
+use crate::core::Context;
+use crate::memory::{AllocatedImage, MappedBuffer, image_barrier};
+use crate::pipeline::{create_texture_sets, write_texture_set};
+use crate::profiling::{FrameStatsSummary, Percentiles};
+use crate::shape::{CornerRadii, FULL_TEXTURE, ShapeRenderer, WindowShape};
+use crate::swapchain::OutputId;
+use ash::vk;
+use smithay::utils::{Physical, Point, Rectangle, Size};
+use starforge_core::{StarforgeError, StarforgeResult};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Columns of text the HUD has room for
+const HUD_COLUMNS: usize = 40;
+
+/// Lines of text the HUD has room for; passes beyond it are left out
+const HUD_LINES: usize = 16;
+
+/// Pixels per font pixel
+const HUD_SCALE: usize = 2;
+
+/// Size of a character cell in font pixels, including spacing
+const CELL_WIDTH: usize = 4;
+const CELL_HEIGHT: usize = 7;
+
+/// Padding around the text in texture pixels
+const PADDING: usize = 6;
+
+const TEXT_COLOR: [u8; 4] = [230, 230, 230, 255];
+
+/// Premultiplied background color
+const BACKGROUND_COLOR: [u8; 4] = [10, 10, 14, 190];
+
+/// 3x5 glyphs, one row per byte with the leftmost pixel in bit 2
+const FONT: &[(char, [u8; 5])] = &[
+    ('0', [0b111, 0b101, 0b101, 0b101, 0b111]),
+    ('1', [0b010, 0b110, 0b010, 0b010, 0b111]),
+    ('2', [0b111, 0b001, 0b111, 0b100, 0b111]),
+    ('3', [0b111, 0b001, 0b111, 0b001, 0b111]),
+    ('4', [0b101, 0b101, 0b111, 0b001, 0b001]),
+    ('5', [0b111, 0b100, 0b111, 0b001, 0b111]),
+    ('6', [0b111, 0b100, 0b111, 0b101, 0b111]),
+    ('7', [0b111, 0b001, 0b001, 0b001, 0b001]),
+    ('8', [0b111, 0b101, 0b111, 0b101, 0b111]),
+    ('9', [0b111, 0b101, 0b111, 0b001, 0b111]),
+    ('a', [0b010, 0b101, 0b111, 0b101, 0b101]),
+    ('b', [0b110, 0b101, 0b110, 0b101, 0b110]),
+    ('c', [0b011, 0b100, 0b100, 0b100, 0b011]),
+    ('d', [0b110, 0b101, 0b101, 0b101, 0b110]),
+    ('e', [0b111, 0b100, 0b110, 0b100, 0b111]),
+    ('f', [0b111, 0b100, 0b110, 0b100, 0b100]),
+    ('g', [0b011, 0b100, 0b101, 0b101, 0b011]),
+    ('h', [0b101, 0b101, 0b111, 0b101, 0b101]),
+    ('i', [0b111, 0b010, 0b010, 0b010, 0b111]),
+    ('j', [0b001, 0b001, 0b001, 0b101, 0b010]),
+    ('k', [0b101, 0b101, 0b110, 0b101, 0b101]),
+    ('l', [0b100, 0b100, 0b100, 0b100, 0b111]),
+    ('m', [0b101, 0b111, 0b111, 0b101, 0b101]),
+    ('n', [0b110, 0b101, 0b101, 0b101, 0b101]),
+    ('o', [0b010, 0b101, 0b101, 0b101, 0b010]),
+    ('p', [0b110, 0b101, 0b110, 0b100, 0b100]),
+    ('q', [0b010, 0b101, 0b101, 0b110, 0b011]),
+    ('r', [0b110, 0b101, 0b110, 0b101, 0b101]),
+    ('s', [0b011, 0b100, 0b010, 0b001, 0b110]),
+    ('t', [0b111, 0b010, 0b010, 0b010, 0b010]),
+    ('u', [0b101, 0b101, 0b101, 0b101, 0b111]),
+    ('v', [0b101, 0b101, 0b101, 0b101, 0b010]),
+    ('w', [0b101, 0b101, 0b111, 0b111, 0b101]),
+    ('x', [0b101, 0b101, 0b010, 0b101, 0b101]),
+    ('y', [0b101, 0b101, 0b010, 0b010, 0b010]),
+    ('z', [0b111, 0b001, 0b010, 0b100, 0b111]),
+    ('.', [0b000, 0b000, 0b000, 0b000, 0b010]),
+    (':', [0b000, 0b010, 0b000, 0b010, 0b000]),
+    ('-', [0b000, 0b000, 0b111, 0b000, 0b000]),
+    ('_', [0b000, 0b000, 0b000, 0b000, 0b111]),
+    ('/', [0b001, 0b001, 0b010, 0b100, 0b100]),
+    ('%', [0b101, 0b001, 0b010, 0b100, 0b101]),
+    ('?', [0b111, 0b001, 0b010, 0b000, 0b010]),
+];
+
+pub(crate) fn glyph(c: char) -> Option<[u8; 5]> {
+    let c = c.to_ascii_lowercase();
+    FONT.iter()
+        .find(|(glyph, _)| *glyph == c)
+        .map(|(_, rows)| *rows)
+        .or_else(|| (!c.is_whitespace()).then_some(FONT[FONT.len() - 1].1))
+}
+
+/// Lines of text describing `summary`, in milliseconds
+pub fn hud_text(summary: &FrameStatsSummary) -> Vec<String> {
+    fn line(label: &str, percentiles: Option<Percentiles>) -> String {
+        let mut label = label.to_string();
+        label.truncate(8);
+        match percentiles {
+            Some(p) => {
+                let ms = |d: std::time::Duration| d.as_secs_f64() * 1000.0;
+                format!(
+                    "{label:<8}{:>8.2}{:>8.2}{:>8.2}{:>8.2}",
+                    ms(p.p50),
+                    ms(p.p90),
+                    ms(p.p99),
+                    ms(p.max)
+                )
+            }
+            None => format!("{label:<8}{:>8}", "-"),
+        }
+    }
+
+    let mut lines = vec![
+        format!(
+            "{:<8}{:>8}{:>8}{:>8}{:>8}",
+            "ms", "p50", "p90", "p99", "max"
+        ),
+        line("cpu", summary.cpu_record),
+        line("gpu", summary.gpu),
+        line("acquire", summary.acquire_wait),
+        line("present", summary.present_latency),
+    ];
+    lines.extend(
+        summary
+            .passes
+            .iter()
+            .map(|(label, percentiles)| line(&format!(" {label}"), Some(*percentiles))),
+    );
+    lines.truncate(HUD_LINES);
+    lines
+}
+
+/// Size of the HUD texture in pixels, which it is drawn at
+pub fn texture_size() -> (usize, usize) {
+    (
+        HUD_COLUMNS * CELL_WIDTH * HUD_SCALE + PADDING * 2,
+        HUD_LINES * CELL_HEIGHT * HUD_SCALE + PADDING * 2,
+    )
+}
+
+/// Rasterize `lines` into premultiplied RGBA8 pixels of [`texture_size`]
+pub fn rasterize(lines: &[String]) -> Vec<u8> {
+    let (width, height) = texture_size();
+    let mut pixels = BACKGROUND_COLOR.repeat(width * height);
+
+    for (row, line) in lines.iter().enumerate().take(HUD_LINES) {
+        for (column, c) in line.chars().enumerate().take(HUD_COLUMNS) {
+            let Some(rows) = glyph(c) else { continue };
+            let origin_x = PADDING + column * CELL_WIDTH * HUD_SCALE;
+            let origin_y = PADDING + row * CELL_HEIGHT * HUD_SCALE;
+            for (gy, bits) in rows.iter().enumerate() {
+                for gx in 0..3 {
+                    if bits & (0b100 >> gx) == 0 {
+                        continue;
+                    }
+                    for sy in 0..HUD_SCALE {
+                        for sx in 0..HUD_SCALE {
+                            let x = origin_x + gx * HUD_SCALE + sx;
+                            let y = origin_y + gy * HUD_SCALE + sy;
+                            let offset = (y * width + x) * 4;
+                            pixels[offset..offset + 4].copy_from_slice(&TEXT_COLOR);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    pixels
+}
+
+/// The HUD texture of one frame in flight
+struct HudTexture {
+    image: AllocatedImage,
+    staging: MappedBuffer,
+    set: vk::DescriptorSet,
+}
+
+/// HUD textures of one output
+struct OutputHud {
+    context: Arc<Context>,
+    descriptor_pool: vk::DescriptorPool,
+    textures: Vec<HudTexture>,
+}
+
+impl OutputHud {
+    fn new(
+        context: Arc<Context>,
+        set_layout: vk::DescriptorSetLayout,
+        sampler: vk::Sampler,
+        frames_in_flight: usize,
+    ) -> StarforgeResult<Self> {
+        let (width, height) = texture_size();
+        let (descriptor_pool, sets) =
+            create_texture_sets(&context, set_layout, frames_in_flight as u32)?;
+        let mut hud = Self {
+            context: context.clone(),
+            descriptor_pool,
+            textures: Vec::with_capacity(frames_in_flight),
+        };
+        for set in sets {
+            let image = AllocatedImage::new(
+                context.clone(),
+                vk::Extent2D {
+                    width: width as u32,
+                    height: height as u32,
+                },
+                vk::Format::R8G8B8A8_UNORM,
+                vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED,
+            )?;
+            let staging = MappedBuffer::new(
+                context.clone(),
+                (width * height * 4) as vk::DeviceSize,
+                vk::BufferUsageFlags::TRANSFER_SRC,
+            )?;
+            write_texture_set(&context, set, image.view(), sampler);
+            hud.textures.push(HudTexture {
+                image,
+                staging,
+                set,
+            });
+        }
+        Ok(hud)
+    }
+}
+
+impl Drop for OutputHud {
+    fn drop(&mut self) {
+        unsafe {
+            self.context.untrack(self.descriptor_pool);
+            self.context
+                .device()
+                .destroy_descriptor_pool(self.descriptor_pool, None);
+        }
+    }
+}
+
+/// Uploads and draws the statistics HUD of every output that shows one
+pub struct HudRenderer {
+    context: Arc<Context>,
+    sampler: vk::Sampler,
+    outputs: HashMap<OutputId, OutputHud>,
+}
+
+impl HudRenderer {
+    pub fn new(context: Arc<Context>) -> StarforgeResult<Self> {
+        // The texture is drawn at its own size, so nearest sampling keeps the text crisp
+        let sampler_info = vk::SamplerCreateInfo::default()
+            .mag_filter(vk::Filter::NEAREST)
+            .min_filter(vk::Filter::NEAREST)
+            .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE);
+        let sampler = unsafe {
+            context
+                .device()
+                .create_sampler(&sampler_info, None)
+                .inspect(|&sampler| context.track(sampler))
+                .map_err(|e| StarforgeError::RendererError(e.to_string()))?
+        };
+        Ok(Self {
+            context,
+            sampler,
+            outputs: HashMap::new(),
+        })
+    }
+
+    /// Upload the HUD of frame `frame` on an output. Has to be recorded outside of
+    /// rendering, before [`Self::draw`].
+    pub fn upload(
+        &mut self,
+        output: OutputId,
+        command_buffer: vk::CommandBuffer,
+        shapes: &ShapeRenderer,
+        frames_in_flight: usize,
+        frame: usize,
+        summary: &FrameStatsSummary,
+    ) -> StarforgeResult<()> {
+        let hud = match self.outputs.entry(output) {
+            std::collections::hash_map::Entry::Occupied(entry) => entry.into_mut(),
+            std::collections::hash_map::Entry::Vacant(entry) => entry.insert(OutputHud::new(
+                self.context.clone(),
+                shapes.texture_set_layout(),
+                self.sampler,
+                frames_in_flight.max(1),
+            )?),
+        };
+        let index = frame % hud.textures.len();
+        let texture = &mut hud.textures[index];
+
+        texture.staging.write(&rasterize(&hud_text(summary)))?;
+
+        let extent = texture.image.extent();
+        let device = self.context.device();
+        unsafe {
+            image_barrier(
+                device,
+                command_buffer,
+                texture.image.image(),
+                vk::ImageLayout::UNDEFINED,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                (
+                    vk::PipelineStageFlags2::FRAGMENT_SHADER,
+                    vk::AccessFlags2::NONE,
+                ),
+                (
+                    vk::PipelineStageFlags2::COPY,
+                    vk::AccessFlags2::TRANSFER_WRITE,
+                ),
+            );
+            let region = vk::BufferImageCopy::default()
+                .image_subresource(vk::ImageSubresourceLayers {
+                    aspect_mask: vk::ImageAspectFlags::COLOR,
+                    mip_level: 0,
+                    base_array_layer: 0,
+                    layer_count: 1,
+                })
+                .image_extent(vk::Extent3D {
+                    width: extent.width,
+                    height: extent.height,
+                    depth: 1,
+                });
+            device.cmd_copy_buffer_to_image(
+                command_buffer,
+                texture.staging.buffer(),
+                texture.image.image(),
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &[region],
+            );
+            image_barrier(
+                device,
+                command_buffer,
+                texture.image.image(),
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                (
+                    vk::PipelineStageFlags2::COPY,
+                    vk::AccessFlags2::TRANSFER_WRITE,
+                ),
+                (
+                    vk::PipelineStageFlags2::FRAGMENT_SHADER,
+                    vk::AccessFlags2::SHADER_SAMPLED_READ,
+                ),
+            );
+        }
+        Ok(())
+    }
+
+    /// Draw the HUD uploaded for frame `frame` with its top left corner at `position`,
+    /// into the attachment currently being rendered to, decoding its sRGB colors with
+    /// `decode`
+    #[allow(clippy::too_many_arguments)]
+    pub fn draw(
+        &self,
+        output: OutputId,
+        command_buffer: vk::CommandBuffer,
+        shapes: &mut ShapeRenderer,
+        format: vk::Format,
+        output_size: Size<i32, Physical>,
+        frame: usize,
+        position: Point<i32, Physical>,
+        decode: &ColorTransform,
+    ) -> StarforgeResult<()> {
+        let Some(hud) = self.outputs.get(&output) else {
+            return Ok(());
+        };
+        let texture = &hud.textures[frame % hud.textures.len()];
+        let extent = texture.image.extent();
+        let rect = Rectangle::new(
+            position,
+            Size::from((extent.width as i32, extent.height as i32)),
+        );
+        let shape = WindowShape {
+            corner_radii: CornerRadii::uniform(PADDING as f32),
+            ..Default::default()
+        };
+        shapes.record(
+            command_buffer,
+            format,
+            output_size,
+            texture.set,
+            rect,
+            FULL_TEXTURE,
+            &shape,
+            decode,
+            1.0,
+        )
+    }
+
+    pub fn remove_output(&mut self, output: OutputId) {
+        self.outputs.remove(&output);
+    }
+}
+
+impl Drop for HudRenderer {
+    fn drop(&mut self) {
+        self.outputs.clear();
+        unsafe {
+            self.context.untrack(self.sampler);
+            self.context.device().destroy_sampler(self.sampler, None);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn every_glyph_fits_its_cell() {
+        for (c, rows) in FONT {
+            assert!(rows.iter().all(|row| *row < 0b1000), "{c}");
+        }
+    }
+
+    #[test]
+    fn hud_lines_fit_the_texture() {
+        let p = Percentiles {
+            p50: Duration::from_micros(1234),
+            p90: Duration::from_micros(2346),
+            p99: Duration::from_millis(123),
+            max: Duration::from_secs(1),
+        };
+        let summary = FrameStatsSummary {
+            frames: 10,
+            cpu_record: Some(p),
+            gpu: Some(p),
+            acquire_wait: None,
+            present_latency: Some(p),
+            passes: (0..30)
+                .map(|i| (format!("a very long pass name {i}"), p))
+                .collect(),
+        };
+        let lines = hud_text(&summary);
+        assert_eq!(lines.len(), HUD_LINES);
+        assert!(lines.iter().all(|line| line.chars().count() <= HUD_COLUMNS));
+        assert_eq!(lines[1], "cpu         1.23    2.35  123.00 1000.00");
+    }
+
+    #[test]
+    fn rasterized_text_is_drawn_over_the_background() {
+        let pixels = rasterize(&["1".to_string()]);
+        let (width, height) = texture_size();
+        assert_eq!(pixels.len(), width * height * 4);
+
+        let pixel = |x: usize, y: usize| &pixels[(y * width + x) * 4..][..4];
+        assert_eq!(pixel(0, 0), BACKGROUND_COLOR);
+        // Bottom row of "1" is fully set
+        let y = PADDING + 4 * HUD_SCALE;
+        assert_eq!(pixel(PADDING, y), TEXT_COLOR);
+        assert_eq!(pixel(PADDING + 2 * HUD_SCALE, y), TEXT_COLOR);
+        assert_eq!(pixel(PADDING + 3 * HUD_SCALE, y), BACKGROUND_COLOR);
+    }
+}
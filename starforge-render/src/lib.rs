@@ -3,42 +3,112 @@
 //! This library provides a modular, extensible Vulkan rendering pipeline
 
 use ash::vk;
+use smithay::backend::allocator::Format;
+use smithay::backend::allocator::dmabuf::Dmabuf;
+use smithay::backend::renderer::utils::Buffer;
 use smithay::reexports::rustix::path::Arg;
-use smithay::utils::Size;
+use smithay::reexports::wayland_server::protocol::wl_buffer::WlBuffer;
+use smithay::utils::{Buffer as BufferCoords, Physical, Point, Rectangle, Size};
+use smithay::wayland::drm_syncobj::DrmSyncPoint;
+use starforge_core::decoration::TitleBar;
+use starforge_core::protocols::{
+    color_management::ImageDescription,
+    color_representation::ColorRepresentationSurfaceCachedState, gamma_control::GammaRamp,
+};
 use starforge_core::{StarforgeError, StarforgeResult};
 use std::collections::HashMap;
 use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tracing::{info, warn};
 
+mod accessibility;
+mod blur;
+mod capture;
 mod color;
+mod composite;
 mod core;
+mod cursor;
+mod damage;
+mod decoration;
+mod dmabuf;
 mod error;
 mod frame;
+mod handles;
+mod hud;
 mod memory;
+mod night_light;
 mod pipeline;
+mod presentation;
+mod profiling;
 mod render_pass;
 mod resources;
+mod shader;
+mod shape;
 mod swapchain;
 mod sync;
+mod yuv;
 
+pub use crate::accessibility::{
+    ColorBlindness, ColorFilter, ColorMatrix, Magnifier, MagnifierFollow, MagnifierSettings,
+};
+pub use crate::blur::{BlurSettings, MAX_BLUR_PASSES, expand_blur_damage};
+pub use crate::capture::{CaptureTarget, OffscreenLayer, OffscreenTarget};
+pub use crate::color::{
+    COLOR_GLSL, ColorPipeline, ColorSpace, ColorTransform, ColorTransformUniform, GammaCurve,
+    HdrMetadata, Primaries, ToneMapper, ToneMapping, TransferFunction,
+};
+pub use crate::cursor::{CursorDamage, CursorTexture};
+pub use crate::decoration::{TitleBarStyle, rasterize_title_bar};
+pub use crate::error::{ShaderDiagnostic, ShaderError};
+pub use crate::handles::LeakedHandle;
+pub use crate::night_light::{
+    ColorAdjustment, GAMMA_LUT_SIZE, GammaLut, GammaLutUniform, NEUTRAL_TEMPERATURE,
+    white_point_gains,
+};
+pub use crate::presentation::{PresentFeedback, PresentFlags};
+pub use crate::profiling::{
+    FrameStats, FrameStatsSummary, FrameTimings, GpuScope, MAX_GPU_SCOPES, Percentiles,
+    RollingWindow, STATS_WINDOW,
+};
+pub use crate::resources::TextureId;
+pub use crate::shader::{
+    BACKDROP_SAMPLER_BINDING, BACKDROP_TEXTURE_BINDING, CompiledShader, EFFECT_DESCRIPTOR_SET,
+    EffectId, EffectShader, INPUT_SAMPLER_BINDING, INPUT_TEXTURE_BINDING, SHADER_ABI_VERSION,
+    ShaderLanguage, ShaderUniforms, UNIFORMS_BINDING, compile_effect, compile_effect_vertex_stage,
+    effect_descriptor_set_layout_bindings,
+};
+pub use crate::shape::{
+    Border, CornerRadii, FULL_TEXTURE, Shadow, WindowShape, rounded_rect_sdf, shadow_alpha,
+};
+pub use crate::swapchain::OutputId;
 use crate::{
+    accessibility::AccessibilityRenderer,
+    blur::BlurRenderer,
+    composite::{Compositor, WORKING_FORMAT},
     core::Context,
+    cursor::CursorRenderer,
+    damage::{ElementDamage, Footprint, OutputPasses},
+    decoration::TitleBarRenderer,
+    hud::HudRenderer,
+    memory::image_barrier,
+    night_light::OutputNightLight,
+    pipeline::{create_texture_sets, write_texture_set},
+    presentation::PresentTracker,
+    profiling::{GpuTimer, OutputProfiler},
+    resources::ResourceManager,
+    shader::ShaderManager,
+    shape::ShapeRenderer,
+    swapchain::{OutputSwapchain, SwapchainConfig /*, PresentInfo*/},
     //pipeline::PipelineCache,
-    //resources::ResourceManager,
-    swapchain::{OutputId, OutputSwapchain, SwapchainConfig /*, PresentInfo*/},
+    sync::ExplicitSync,
 };
 
-pub struct DmaBufImportInfo {
-    pub fd: std::os::unix::io::RawFd,
-    pub width: u32,
-    pub height: u32,
-    pub format: vk::Format,
-    pub modifier: u64,
-}
-
+#[derive(Clone, Copy)]
 pub struct SurfaceCreateInfo {
     pub handles: RawHandles,
 }
 
+#[derive(Clone, Copy)]
 pub enum RawHandles {
     Winit {
         display: *mut std::ffi::c_void,
@@ -51,18 +121,41 @@ pub enum RawHandles {
 
 pub enum RenderElement {
     ClientSurface {
-        //texture_id: TextureId,
+        texture_id: TextureId,
         position: (i32, i32),
         size: (u32, u32),
+        /// Part of the texture shown, in texture coordinates, cropped by the viewport
+        source: [f32; 4],
         damage: Vec<(i32, i32, u32, u32)>, // Damage regions in surface coordinates
-        color_space: vk::ColorSpaceKHR,
-        eotf: vk::Format,
+        /// Colour space of the texture, sRGB unless the client described it
+        image_description: Arc<ImageDescription>,
+        /// Blur whatever lies behind the surface, for frosted windows and layer surfaces
+        backdrop_blur: Option<BlurSettings>,
+        /// Rounded corners, border and drop shadow
+        shape: WindowShape,
+        /// Opaque region in surface coordinates, before `WindowShape::opaque_region` clips
+        /// the rounded corners out of it
+        opaque_region: Vec<(i32, i32, u32, u32)>,
     },
+    /// A rectangle filled with a straight sRGB colour, replacing what is below it
     SolidColor {
         rect: (i32, i32, u32, u32), // Position and size
         color: [f32; 4],            // RGBA color
     },
-    // Other elements: cursor, background image, UI panels, etc...
+    /// Frame statistics of the output, drawn with their top left corner at `position`
+    StatsHud { position: (i32, i32) },
+    /// The pointer cursor, drawn last with its top left corner at `position`
+    Cursor {
+        texture: CursorTexture,
+        position: (i32, i32),
+        size: (u32, u32),
+    },
+    /// The title bar of a server-side decorated window, uploaded with `key`
+    TitleBar {
+        key: u64,
+        rect: (i32, i32, u32, u32),
+    },
+    // Other elements: background image, UI panels, etc...
 }
 
 /// The core rendering context for Starforge
@@ -72,28 +165,104 @@ pub struct StarforgeRenderer {
 
     /// Per-output state managers
     outputs: RwLock<HashMap<OutputId, OutputSwapchain>>,
-    //// Central resource manager
-    //resource_manager: ResourceManager,
 
+    /// Working space and transform builder for color management
+    color_pipeline: ColorPipeline,
+
+    /// Effect shader modules
+    shaders: RwLock<ShaderManager>,
+
+    /// Backdrop blur pipelines and cached blurs
+    blur: RwLock<BlurRenderer>,
+
+    /// Rounded corner, border and shadow pipelines
+    shapes: RwLock<ShapeRenderer>,
+
+    /// Working images of outputs and their encode pass
+    compositor: RwLock<Compositor>,
+
+    /// Per-output GPU timestamps and frame statistics
+    profilers: RwLock<HashMap<OutputId, OutputProfiler>>,
+
+    /// Vblank counters of outputs, for presentation feedback
+    presentation: RwLock<HashMap<OutputId, PresentTracker>>,
+
+    /// Frame statistics overlay
+    hud: RwLock<HudRenderer>,
+
+    /// Colour temperature, gamma and brightness of outputs
+    night_light: RwLock<HashMap<OutputId, OutputNightLight>>,
+
+    /// Colour filters and magnifiers of outputs
+    accessibility: RwLock<AccessibilityRenderer>,
+
+    /// Cursor textures and per-output cursor damage
+    cursor: RwLock<CursorRenderer>,
+
+    /// Title bars of server-side decorated windows
+    title_bars: RwLock<TitleBarRenderer>,
+
+    /// What outputs showed in their last frame, to find what changed in the next one
+    frame_damage: RwLock<HashMap<OutputId, ElementDamage>>,
+
+    /// Central resource manager
+    resource_manager: RwLock<ResourceManager>,
+
+    /// Acquire and release synchronization with clients
+    explicit_sync: RwLock<ExplicitSync>,
+
+    /// Images of outputs rendered without a swapchain, which captures read from
+    offscreen_outputs: RwLock<HashMap<OutputId, OffscreenTarget>>,
+
+    /// Tone mapping operators of offscreen outputs, swapchains keep their own
+    offscreen_tone_mapping: RwLock<HashMap<OutputId, ToneMapping>>,
+
+    /// How each output was created, to recreate it after device loss
+    output_configs: RwLock<HashMap<OutputId, (SurfaceCreateInfo, SwapchainConfig)>>,
     //// Pipeline cache
     //pipeline_cache: PipelineCache,
 }
 
 impl StarforgeRenderer {
     pub fn new() -> StarforgeResult<Self> {
-        let context = Arc::new(Context::new(
-            "Starforge".into_c_str().unwrap().as_ref(),
-            0,
-            &[],
-            true,
-        )?);
-        //let resource_manager = ResourceManager::new(context.clone())?;
+        let context = create_context()?;
+        let shaders = ShaderManager::new(context.clone())?;
+        let blur = BlurRenderer::new(context.clone())?;
+        let shapes = ShapeRenderer::new(context.clone())?;
+        let compositor = Compositor::new(context.clone())?;
+        let hud = HudRenderer::new(context.clone())?;
+        let accessibility = AccessibilityRenderer::new(context.clone())?;
+        let cursor = CursorRenderer::new(context.clone(), shapes.texture_set_layout())?;
+        let title_bars = TitleBarRenderer::new(
+            context.clone(),
+            shapes.texture_set_layout(),
+            TitleBarStyle::default(),
+        )?;
+        let resource_manager = ResourceManager::new(context.clone());
+        let explicit_sync = ExplicitSync::new(context.clone())?;
         //let pipeline_cache = PipelineCache::new(context.clone())?;
 
         Ok(Self {
             context,
             outputs: RwLock::new(HashMap::new()),
-            //resource_manager,
+            color_pipeline: ColorPipeline::default(),
+            shaders: RwLock::new(shaders),
+            blur: RwLock::new(blur),
+            shapes: RwLock::new(shapes),
+            compositor: RwLock::new(compositor),
+            profilers: RwLock::new(HashMap::new()),
+            presentation: RwLock::new(HashMap::new()),
+            hud: RwLock::new(hud),
+            night_light: RwLock::new(HashMap::new()),
+            accessibility: RwLock::new(accessibility),
+            cursor: RwLock::new(cursor),
+            title_bars: RwLock::new(title_bars),
+            frame_damage: RwLock::new(HashMap::new()),
+            resource_manager: RwLock::new(resource_manager),
+            explicit_sync: RwLock::new(explicit_sync),
+            offscreen_outputs: RwLock::new(HashMap::new()),
+            offscreen_tone_mapping: RwLock::new(HashMap::new()),
+            output_configs: RwLock::new(HashMap::new()),
             //pipeline_cache,
         })
     }
@@ -105,11 +274,185 @@ impl StarforgeRenderer {
         create_info: SurfaceCreateInfo,
         initial_config: SwapchainConfig,
     ) -> StarforgeResult<()> {
-        let swapchain = OutputSwapchain::new(self.context.clone(), create_info, initial_config)?;
+        self.create_output(id, create_info, initial_config.clone())?;
+        self.output_configs
+            .write()
+            .unwrap()
+            .insert(id, (create_info, initial_config));
+        Ok(())
+    }
+
+    fn create_output(
+        &self,
+        id: OutputId,
+        create_info: SurfaceCreateInfo,
+        config: SwapchainConfig,
+    ) -> StarforgeResult<()> {
+        let swapchain = OutputSwapchain::new(self.context.clone(), create_info, config)?;
+        let profiler = OutputProfiler {
+            timer: GpuTimer::new(self.context.clone(), swapchain.image_count())?,
+            stats: FrameStats::default(),
+        };
         self.outputs.write().unwrap().insert(id, swapchain);
+        self.profilers.write().unwrap().insert(id, profiler);
+        Ok(())
+    }
+
+    /// Register an output rendered into an offscreen image instead of a window, such as
+    /// a headless one
+    ///
+    /// Registering the output again, e.g. after a mode change, replaces its image.
+    pub fn register_offscreen_output(
+        &self,
+        id: OutputId,
+        size: Size<i32, Physical>,
+    ) -> StarforgeResult<()> {
+        let target = OffscreenTarget::new(self.context.clone(), size)?;
+        self.offscreen_outputs.write().unwrap().insert(id, target);
+        self.frame_damage.write().unwrap().remove(&id);
+        if !self.profilers.read().unwrap().contains_key(&id) {
+            let profiler = OutputProfiler {
+                timer: GpuTimer::new(self.context.clone(), 1)?,
+                stats: FrameStats::default(),
+            };
+            self.profilers.write().unwrap().insert(id, profiler);
+        }
         Ok(())
     }
 
+    /// Read back the last frame of an offscreen output, as rows of B8G8R8A8 pixels
+    ///
+    /// For presenting through a backend that can't share the image, e.g. a nested window
+    /// drawn by another API.
+    pub fn read_output_pixels(&self, id: OutputId) -> StarforgeResult<Vec<u8>> {
+        let outputs = self.offscreen_outputs.read().unwrap();
+        let source = outputs.get(&id).ok_or(StarforgeError::OutputNotFound)?;
+        capture::read_pixels(&self.context, source)
+    }
+
+    /// Image, view, format and extent an offscreen output renders into, which is kept in
+    /// [`OffscreenTarget::LAYOUT`] between frames
+    pub fn offscreen_output_image(
+        &self,
+        id: OutputId,
+    ) -> StarforgeResult<(vk::Image, vk::ImageView, vk::Format, vk::Extent2D)> {
+        let outputs = self.offscreen_outputs.read().unwrap();
+        let target = outputs.get(&id).ok_or(StarforgeError::OutputNotFound)?;
+        Ok((
+            target.image(),
+            target.view(),
+            target.format(),
+            target.extent(),
+        ))
+    }
+
+    /// Copy `regions` of what an offscreen output shows into a capture client's buffer
+    ///
+    /// The buffer has to have the size of the output. With `cursor`, the cursor is drawn
+    /// over the copy and its rectangle copied too; the rectangle it left has to be part of
+    /// `regions`.
+    pub fn capture_output(
+        &self,
+        id: OutputId,
+        target: CaptureTarget<'_>,
+        regions: &[Rectangle<i32, BufferCoords>],
+        cursor: Option<(CursorTexture, Rectangle<i32, Physical>)>,
+    ) -> StarforgeResult<()> {
+        let outputs = self.offscreen_outputs.read().unwrap();
+        let source = outputs.get(&id).ok_or(StarforgeError::OutputNotFound)?;
+        self.shapes.write().unwrap().reset_decodes();
+        capture::capture(
+            &self.context,
+            source,
+            target,
+            regions,
+            cursor.map(|(_, rect)| rect),
+            |command_buffer, format, size| match cursor {
+                // The copy is encoded already, and so is the cursor
+                Some((texture, rect)) => self.draw_cursor(
+                    command_buffer,
+                    format,
+                    size,
+                    texture,
+                    rect,
+                    &ColorTransform::IDENTITY,
+                ),
+                None => Ok(()),
+            },
+        )
+    }
+
+    /// Draw the cursor over what an offscreen output shows, once its captures were taken
+    ///
+    /// Captures copy the output without the cursor, so frames of outputs that are captured
+    /// leave it out and have it drawn with this. The next frame replaces it.
+    pub fn draw_output_cursor(
+        &self,
+        id: OutputId,
+        texture: CursorTexture,
+        rect: Rectangle<i32, Physical>,
+    ) -> StarforgeResult<()> {
+        let outputs = self.offscreen_outputs.read().unwrap();
+        let target = outputs.get(&id).ok_or(StarforgeError::OutputNotFound)?;
+        self.shapes.write().unwrap().reset_decodes();
+        capture::draw_over(&self.context, target, |command_buffer, format, size| {
+            // The output is encoded already, and so is the cursor
+            self.draw_cursor(
+                command_buffer,
+                format,
+                size,
+                texture,
+                rect,
+                &ColorTransform::IDENTITY,
+            )
+        })
+    }
+
+    /// Create an image to draw a window into, to capture it on its own
+    ///
+    /// The target belongs to the caller and has to be created again after device loss.
+    pub fn create_offscreen_target(
+        &self,
+        size: Size<i32, Physical>,
+    ) -> StarforgeResult<OffscreenTarget> {
+        OffscreenTarget::new(self.context.clone(), size)
+    }
+
+    /// Draw client textures into an offscreen target, replacing what it showed
+    ///
+    /// Draws a window with its subsurfaces and popups, bottom-most first, whether it's
+    /// shown on an output or not.
+    pub fn render_offscreen(
+        &self,
+        target: &OffscreenTarget,
+        layers: &[OffscreenLayer],
+    ) -> StarforgeResult<()> {
+        capture::render_layers(
+            &self.context,
+            target,
+            &mut self.resource_manager.write().unwrap(),
+            &mut self.shapes.write().unwrap(),
+            layers,
+        )
+    }
+
+    /// Copy `regions` of an offscreen target into a capture client's buffer of its size
+    pub fn capture_offscreen(
+        &self,
+        source: &OffscreenTarget,
+        target: CaptureTarget<'_>,
+        regions: &[Rectangle<i32, BufferCoords>],
+    ) -> StarforgeResult<()> {
+        capture::capture(&self.context, source, target, regions, None, |_, _, _| {
+            Ok(())
+        })
+    }
+
+    /// Formats and modifiers of dma-bufs captures can be written into
+    pub fn capture_dmabuf_formats(&self) -> Vec<Format> {
+        dmabuf::capture_formats(&self.context)
+    }
+
     /// Trigger reconfiguration for an output
     pub fn configure_output(&self, id: OutputId, config: SwapchainConfig) -> StarforgeResult<()> {
         // let swapchain = self
@@ -122,31 +465,1333 @@ impl StarforgeRenderer {
         Ok(())
     }
 
+    /// The color space an output is encoded in, sRGB for offscreen outputs
+    pub fn output_color_space(&self, id: OutputId) -> StarforgeResult<ColorSpace> {
+        if let Some(swapchain) = self.outputs.read().unwrap().get(&id) {
+            return Ok(swapchain.color_space());
+        }
+        match self.offscreen_outputs.read().unwrap().contains_key(&id) {
+            true => Ok(ColorSpace::SRGB),
+            false => Err(StarforgeError::OutputNotFound),
+        }
+    }
+
+    /// Transform encoding the blended working space for an output, tone mapping content
+    /// that peaks at `content_peak_nits` when the output can't show it
+    pub fn output_encode_transform(
+        &self,
+        id: OutputId,
+        content_peak_nits: f32,
+    ) -> StarforgeResult<ColorTransform> {
+        let color_space = self.output_color_space(id)?;
+        let transform = match self.outputs.read().unwrap().get(&id) {
+            Some(swapchain) => swapchain.encode_transform(&self.color_pipeline, content_peak_nits),
+            None => {
+                // Offscreen images are SDR, anything brighter than reference white is
+                // compressed into it
+                let transform = self.color_pipeline.encode_transform(color_space);
+                let display_peak_nits = self.color_pipeline.reference_white_nits;
+                if content_peak_nits > display_peak_nits {
+                    let tone_mapping = self
+                        .offscreen_tone_mapping
+                        .read()
+                        .unwrap()
+                        .get(&id)
+                        .copied()
+                        .unwrap_or_default();
+                    transform.with_tone_mapping(self.color_pipeline.tone_mapper(
+                        tone_mapping,
+                        content_peak_nits,
+                        display_peak_nits,
+                    ))
+                } else {
+                    transform
+                }
+            }
+        };
+        Ok(match self.night_light.read().unwrap().get(&id) {
+            Some(night_light) => night_light.apply_to(transform, color_space.primaries),
+            None => transform,
+        })
+    }
+
+    /// Change the colour temperature, gamma and brightness of an output
+    pub fn set_color_adjustment(&self, id: OutputId, adjustment: ColorAdjustment) {
+        self.night_light
+            .write()
+            .unwrap()
+            .entry(id)
+            .or_default()
+            .adjustment = adjustment;
+    }
+
+    /// The colour temperature, gamma and brightness of an output
+    pub fn color_adjustment(&self, id: OutputId) -> ColorAdjustment {
+        self.night_light
+            .read()
+            .unwrap()
+            .get(&id)
+            .map(|night_light| night_light.adjustment)
+            .unwrap_or_default()
+    }
+
+    /// Replace the colour adjustment of an output with a client's gamma tables, or
+    /// restore it with `None`
+    pub fn set_gamma_ramp(&self, id: OutputId, ramp: Option<&GammaRamp>) {
+        self.night_light
+            .write()
+            .unwrap()
+            .entry(id)
+            .or_default()
+            .gamma_lut = ramp.map(GammaLut::from_ramp);
+    }
+
+    /// Gamma tables of an output for the encode pass, `None` while no client set any
+    pub fn output_gamma_lut(&self, id: OutputId) -> Option<GammaLutUniform> {
+        self.night_light
+            .read()
+            .unwrap()
+            .get(&id)
+            .and_then(|night_light| night_light.gamma_lut.as_ref())
+            .map(GammaLut::to_uniform)
+    }
+
+    /// Send new HDR mastering metadata for an output
+    pub fn set_hdr_metadata(&self, id: OutputId, metadata: HdrMetadata) -> StarforgeResult<()> {
+        let mut outputs = self.outputs.write().unwrap();
+        let swapchain = outputs.get_mut(&id).ok_or(StarforgeError::OutputNotFound)?;
+        swapchain.set_hdr_metadata(metadata);
+        Ok(())
+    }
+
+    /// Change the tone mapping operator of an output
+    pub fn set_tone_mapping(&self, id: OutputId, tone_mapping: ToneMapping) -> StarforgeResult<()> {
+        if let Some(swapchain) = self.outputs.write().unwrap().get_mut(&id) {
+            swapchain.set_tone_mapping(tone_mapping);
+            return Ok(());
+        }
+        if !self.offscreen_outputs.read().unwrap().contains_key(&id) {
+            return Err(StarforgeError::OutputNotFound);
+        }
+        self.offscreen_tone_mapping
+            .write()
+            .unwrap()
+            .insert(id, tone_mapping);
+        Ok(())
+    }
+
+    /// Load an effect shader from a GLSL or WGSL file
+    pub fn load_effect_shader(&self, path: &std::path::Path) -> StarforgeResult<EffectId> {
+        Ok(self.shaders.write().unwrap().load(path)?)
+    }
+
+    /// Unload an effect shader
+    pub fn unload_effect_shader(&self, id: EffectId) {
+        self.shaders.write().unwrap().unload(id);
+    }
+
+    /// Recompile effect shaders whose files changed, returning the ones that were replaced
+    pub fn reload_effect_shaders(&self) -> Vec<EffectId> {
+        self.shaders.write().unwrap().reload_changed()
+    }
+
+    /// Record the backdrop blur of `region` on an output, reusing the cached blur when
+    /// nothing in `damage_below` touches it. Returns the view of the blurred region.
+    #[allow(clippy::too_many_arguments)]
+    pub fn record_backdrop_blur(
+        &self,
+        id: OutputId,
+        command_buffer: vk::CommandBuffer,
+        backdrop: vk::Image,
+        format: vk::Format,
+        region: Rectangle<i32, Physical>,
+        settings: BlurSettings,
+        damage_below: &[Rectangle<i32, Physical>],
+    ) -> StarforgeResult<vk::ImageView> {
+        let scope = self.begin_gpu_scope(id, command_buffer, "blur")?;
+        let view = self.blur.write().unwrap().record(
+            id,
+            command_buffer,
+            backdrop,
+            format,
+            region,
+            settings,
+            damage_below,
+        );
+        if let Some(scope) = scope {
+            self.end_gpu_scope(id, command_buffer, scope)?;
+        }
+        view
+    }
+
+    /// Release cached blurs of an output that weren't used in the frame just recorded
+    pub fn end_blur_frame(&self, id: OutputId) {
+        self.blur.write().unwrap().end_frame(id);
+    }
+
+    /// Layout of the surface texture set passed to [`Self::record_window_shape`]
+    pub fn shape_texture_set_layout(&self) -> vk::DescriptorSetLayout {
+        self.shapes.read().unwrap().texture_set_layout()
+    }
+
+    /// Bilinear sampler to put in the surface texture sets passed to
+    /// [`Self::record_window_shape`]
+    pub fn surface_sampler(&self) -> vk::Sampler {
+        self.shapes.read().unwrap().surface_sampler()
+    }
+
+    /// Record the `source` part of a client surface texture with geometry `rect`, clipped
+    /// to its rounded corners and surrounded by its border and shadow, into the attachment
+    /// being rendered to
+    ///
+    /// `source` is in texture coordinates, [`FULL_TEXTURE`] unless a viewport crops the
+    /// surface. `rect` should come from `starforge_core::scale::to_physical_rect`, so
+    /// surfaces that touch don't leave seams at fractional scales. `decode` brings the
+    /// texture and the shape's colours into the attachment's colour space.
+    #[allow(clippy::too_many_arguments)]
+    pub fn record_window_shape(
+        &self,
+        id: OutputId,
+        command_buffer: vk::CommandBuffer,
+        format: vk::Format,
+        output_size: Size<i32, Physical>,
+        texture_set: vk::DescriptorSet,
+        rect: Rectangle<i32, Physical>,
+        source: [f32; 4],
+        shape: &WindowShape,
+        decode: &ColorTransform,
+        opacity: f32,
+    ) -> StarforgeResult<()> {
+        let scope = self.begin_gpu_scope(id, command_buffer, "windows")?;
+        let result = self.shapes.write().unwrap().record(
+            command_buffer,
+            format,
+            output_size,
+            texture_set,
+            rect,
+            source,
+            shape,
+            decode,
+            opacity,
+        );
+        if let Some(scope) = scope {
+            self.end_gpu_scope(id, command_buffer, scope)?;
+        }
+        result
+    }
+
+    /// Start GPU timing of frame `frame` on an output, after waiting for the previous
+    /// submission of that frame slot, and add that submission's timings to the statistics
+    pub fn begin_gpu_frame(
+        &self,
+        id: OutputId,
+        command_buffer: vk::CommandBuffer,
+        frame: usize,
+    ) -> StarforgeResult<()> {
+        let mut profilers = self.profilers.write().unwrap();
+        let profiler = profilers
+            .get_mut(&id)
+            .ok_or(StarforgeError::OutputNotFound)?;
+        let timings = profiler.timer.begin_frame(command_buffer, frame);
+        profiler.stats.add_gpu_timings(timings);
+        Ok(())
+    }
+
+    /// End GPU timing of the frame being recorded on an output
+    pub fn end_gpu_frame(
+        &self,
+        id: OutputId,
+        command_buffer: vk::CommandBuffer,
+    ) -> StarforgeResult<()> {
+        let mut profilers = self.profilers.write().unwrap();
+        let profiler = profilers
+            .get_mut(&id)
+            .ok_or(StarforgeError::OutputNotFound)?;
+        profiler.timer.end_frame(command_buffer);
+        Ok(())
+    }
+
+    /// Start timing a pass or effect under `label`, e.g. `"effect 3"` for an effect shader
+    ///
+    /// Returns `None` when the GPU can't write timestamps or the frame has used up its
+    /// [`MAX_GPU_SCOPES`].
+    pub fn begin_gpu_scope(
+        &self,
+        id: OutputId,
+        command_buffer: vk::CommandBuffer,
+        label: &str,
+    ) -> StarforgeResult<Option<GpuScope>> {
+        let mut profilers = self.profilers.write().unwrap();
+        let profiler = profilers
+            .get_mut(&id)
+            .ok_or(StarforgeError::OutputNotFound)?;
+        Ok(profiler.timer.begin_scope(command_buffer, label))
+    }
+
+    pub fn end_gpu_scope(
+        &self,
+        id: OutputId,
+        command_buffer: vk::CommandBuffer,
+        scope: GpuScope,
+    ) -> StarforgeResult<()> {
+        let mut profilers = self.profilers.write().unwrap();
+        let profiler = profilers
+            .get_mut(&id)
+            .ok_or(StarforgeError::OutputNotFound)?;
+        profiler.timer.end_scope(command_buffer, scope);
+        Ok(())
+    }
+
+    /// Add the CPU timings of a frame recorded for an output
+    pub fn record_frame_timings(&self, id: OutputId, timings: FrameTimings) -> StarforgeResult<()> {
+        let mut profilers = self.profilers.write().unwrap();
+        let profiler = profilers
+            .get_mut(&id)
+            .ok_or(StarforgeError::OutputNotFound)?;
+        profiler.stats.add_frame_timings(timings);
+        Ok(())
+    }
+
+    /// Add the time between submitting a frame and it being shown on an output
+    pub fn record_present_latency(&self, id: OutputId, latency: Duration) -> StarforgeResult<()> {
+        let mut profilers = self.profilers.write().unwrap();
+        let profiler = profilers
+            .get_mut(&id)
+            .ok_or(StarforgeError::OutputNotFound)?;
+        profiler.stats.add_present_latency(latency);
+        Ok(())
+    }
+
+    /// Let the frames of an output tear, or go back to vsync
+    ///
+    /// Switching recreates the swapchain, so callers should only change it when the
+    /// content calling for it comes or goes. Only swapchain outputs present frames
+    /// themselves, offscreen ones are shown however their backend draws them.
+    pub fn set_tearing(&self, id: OutputId, tearing: bool) -> StarforgeResult<()> {
+        match self.outputs.write().unwrap().get_mut(&id) {
+            Some(output) => output.set_tearing(tearing),
+            None if self.offscreen_outputs.read().unwrap().contains_key(&id) => Err(
+                StarforgeError::RendererError("offscreen outputs can't tear".into()),
+            ),
+            None => Err(StarforgeError::OutputNotFound),
+        }
+    }
+
+    /// Record that the last frame of an output, submitted at `submitted`, reached the
+    /// screen at `time`
+    ///
+    /// Returns the feedback for the clients drawn in the frame. Outputs without a
+    /// swapchain, like a nested window, can't tell whether frames waited for a vblank.
+    pub fn frame_presented(
+        &self,
+        id: OutputId,
+        submitted: Duration,
+        time: Duration,
+        refresh: Option<Duration>,
+    ) -> PresentFeedback {
+        let vsync = self
+            .outputs
+            .read()
+            .unwrap()
+            .get(&id)
+            .is_some_and(|output| output.presents_at_vblank());
+        if let Some(profiler) = self.profilers.write().unwrap().get_mut(&id) {
+            profiler
+                .stats
+                .add_present_latency(time.saturating_sub(submitted));
+        }
+        let flags = PresentFlags {
+            vsync,
+            ..Default::default()
+        };
+        self.presentation
+            .write()
+            .unwrap()
+            .entry(id)
+            .or_default()
+            .presented(time, refresh, flags)
+    }
+
+    /// Percentiles of the recent frames of an output
+    pub fn frame_stats(&self, id: OutputId) -> StarforgeResult<FrameStatsSummary> {
+        let profilers = self.profilers.read().unwrap();
+        let profiler = profilers.get(&id).ok_or(StarforgeError::OutputNotFound)?;
+        Ok(profiler.stats.summary())
+    }
+
+    /// Upload the statistics HUD of frame `frame` on an output, outside of rendering
+    pub fn upload_stats_hud(
+        &self,
+        id: OutputId,
+        command_buffer: vk::CommandBuffer,
+        frame: usize,
+    ) -> StarforgeResult<()> {
+        let frames_in_flight = self.frames_in_flight(id)?;
+        let summary = self.frame_stats(id)?;
+        self.hud.write().unwrap().upload(
+            id,
+            command_buffer,
+            &self.shapes.read().unwrap(),
+            frames_in_flight,
+            frame,
+            &summary,
+        )
+    }
+
+    /// Number of frames of an output that can be in flight at once, one for offscreen
+    /// outputs, which wait for every frame
+    fn frames_in_flight(&self, id: OutputId) -> StarforgeResult<usize> {
+        if let Some(swapchain) = self.outputs.read().unwrap().get(&id) {
+            return Ok(swapchain.image_count());
+        }
+        match self.offscreen_outputs.read().unwrap().contains_key(&id) {
+            true => Ok(1),
+            false => Err(StarforgeError::OutputNotFound),
+        }
+    }
+
+    /// Draw the statistics HUD uploaded with [`Self::upload_stats_hud`] for a
+    /// [`RenderElement::StatsHud`], into the attachment being rendered to, decoding its
+    /// sRGB colours with `decode`
+    #[allow(clippy::too_many_arguments)]
+    pub fn draw_stats_hud(
+        &self,
+        id: OutputId,
+        command_buffer: vk::CommandBuffer,
+        format: vk::Format,
+        output_size: Size<i32, Physical>,
+        frame: usize,
+        position: (i32, i32),
+        decode: &ColorTransform,
+    ) -> StarforgeResult<()> {
+        self.hud.read().unwrap().draw(
+            id,
+            command_buffer,
+            &mut self.shapes.write().unwrap(),
+            format,
+            output_size,
+            frame,
+            position.into(),
+            decode,
+        )
+    }
+
+    /// Set the colour filter and magnifier of every output
+    pub fn set_accessibility_defaults(&self, filter: ColorFilter, magnifier: MagnifierSettings) {
+        self.accessibility
+            .write()
+            .unwrap()
+            .set_defaults(filter, magnifier);
+    }
+
+    /// The colour filter of an output
+    pub fn color_filter(&self, id: OutputId) -> ColorFilter {
+        self.accessibility.read().unwrap().color_filter(id)
+    }
+
+    /// Change the colour filter of an output, taking effect with its next frame
+    pub fn set_color_filter(&self, id: OutputId, filter: ColorFilter) {
+        self.accessibility
+            .write()
+            .unwrap()
+            .set_color_filter(id, filter);
+    }
+
+    /// The magnifier of an output
+    pub fn magnifier(&self, id: OutputId) -> Magnifier {
+        self.accessibility.read().unwrap().magnifier(id)
+    }
+
+    /// Change the magnifier of an output, it zooms smoothly to the new settings
+    pub fn set_magnifier(&self, id: OutputId, settings: MagnifierSettings) {
+        self.accessibility
+            .write()
+            .unwrap()
+            .set_magnifier(id, settings);
+    }
+
+    /// Move the magnifier of an output towards the pointer or keyboard focus at `now`,
+    /// returning whether it needs another frame to settle
+    pub fn update_magnifier(
+        &self,
+        id: OutputId,
+        now: Duration,
+        pointer: Point<f64, Physical>,
+        focus: Option<Rectangle<i32, Physical>>,
+    ) -> bool {
+        self.accessibility
+            .write()
+            .unwrap()
+            .update_magnifier(id, now, pointer, focus)
+    }
+
+    /// Record the colour filter and magnifier of an output over its finished image
+    ///
+    /// `image` has to be in `TRANSFER_SRC_OPTIMAL` layout and is left in
+    /// `COLOR_ATTACHMENT_OPTIMAL` layout. Returns `false` without recording anything when
+    /// the output has neither.
+    #[allow(clippy::too_many_arguments)]
+    pub fn record_accessibility_pass(
+        &self,
+        id: OutputId,
+        command_buffer: vk::CommandBuffer,
+        image: vk::Image,
+        view: vk::ImageView,
+        format: vk::Format,
+        extent: vk::Extent2D,
+    ) -> StarforgeResult<bool> {
+        let scope = self.begin_gpu_scope(id, command_buffer, "accessibility")?;
+        let recorded = self.accessibility.write().unwrap().record(
+            id,
+            command_buffer,
+            image,
+            view,
+            format,
+            extent,
+        );
+        if let Some(scope) = scope {
+            self.end_gpu_scope(id, command_buffer, scope)?;
+        }
+        recorded
+    }
+
+    /// Upload a themed cursor frame for [`CursorTexture::Named`], once per `key`
+    pub fn upload_cursor_frame(
+        &self,
+        key: u64,
+        size: Size<i32, Physical>,
+        pixels: &[u8],
+    ) -> StarforgeResult<()> {
+        self.cursor.write().unwrap().upload_frame(key, size, pixels)
+    }
+
+    /// Draw a [`RenderElement::Cursor`] into the attachment being rendered to, decoding
+    /// its sRGB colours with `decode`
+    pub fn draw_cursor(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        format: vk::Format,
+        output_size: Size<i32, Physical>,
+        texture: CursorTexture,
+        rect: Rectangle<i32, Physical>,
+        decode: &ColorTransform,
+    ) -> StarforgeResult<()> {
+        let resources = self.resource_manager.read().unwrap();
+        let client_view = match texture {
+            CursorTexture::Client(id) => Some(
+                resources
+                    .texture(id)
+                    .ok_or_else(|| {
+                        StarforgeError::RendererError(format!("unknown texture {}", id.0))
+                    })?
+                    .view(),
+            ),
+            CursorTexture::Named(_) => None,
+        };
+        self.cursor.write().unwrap().draw(
+            command_buffer,
+            &mut self.shapes.write().unwrap(),
+            format,
+            output_size,
+            texture,
+            client_view,
+            rect,
+            decode,
+        )
+    }
+
+    /// Damage the cursor causes on an output by being drawn as `cursor` this frame, or
+    /// hidden with `None`
+    pub fn cursor_damage(
+        &self,
+        id: OutputId,
+        cursor: Option<(CursorTexture, Rectangle<i32, Physical>)>,
+    ) -> Vec<Rectangle<i32, Physical>> {
+        self.cursor.write().unwrap().damage(id, cursor)
+    }
+
+    /// Set the colours of title bars, redrawing the ones uploaded already
+    pub fn set_title_bar_style(&self, style: TitleBarStyle) {
+        self.title_bars.write().unwrap().set_style(style);
+    }
+
+    /// Upload the title bar of a window at `size` on an output with `scale`, outside of
+    /// rendering; it is only rasterized again when it looks different
+    pub fn upload_title_bar(
+        &self,
+        title_bar: &TitleBar,
+        size: Size<i32, Physical>,
+        scale: f64,
+    ) -> StarforgeResult<()> {
+        self.title_bars
+            .write()
+            .unwrap()
+            .upload(title_bar, size, scale)
+    }
+
+    /// Draw a [`RenderElement::TitleBar`] into the attachment being rendered to, decoding
+    /// its sRGB colours with `decode`
+    pub fn draw_title_bar(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        format: vk::Format,
+        output_size: Size<i32, Physical>,
+        key: u64,
+        rect: Rectangle<i32, Physical>,
+        decode: &ColorTransform,
+    ) -> StarforgeResult<()> {
+        self.title_bars.write().unwrap().draw(
+            command_buffer,
+            &mut self.shapes.write().unwrap(),
+            format,
+            output_size,
+            key,
+            rect,
+            decode,
+        )
+    }
+
+    /// Release title bars of windows that weren't uploaded or drawn since the last call
+    ///
+    /// Windows are all shown on one output, so this is called once per frame of that
+    /// output.
+    pub fn end_title_bar_frame(&self) {
+        self.title_bars.write().unwrap().end_frame();
+    }
+
     /// Unregister an output
     pub fn unregister_output(&self, id: OutputId) -> StarforgeResult<()> {
+        self.blur.write().unwrap().remove_output(id);
+        self.compositor.write().unwrap().remove_output(id);
+        self.hud.write().unwrap().remove_output(id);
+        self.accessibility.write().unwrap().remove_output(id);
+        self.night_light.write().unwrap().remove(&id);
+        self.cursor.write().unwrap().remove_output(id);
+        self.frame_damage.write().unwrap().remove(&id);
+        self.profilers.write().unwrap().remove(&id);
+        self.presentation.write().unwrap().remove(&id);
         self.outputs.write().unwrap().remove(&id);
+        self.offscreen_outputs.write().unwrap().remove(&id);
+        self.offscreen_tone_mapping.write().unwrap().remove(&id);
+        self.output_configs.write().unwrap().remove(&id);
         Ok(())
     }
 
-    /// Imports a frame via DMA-BUF
-    pub fn import_dma_buf(&self /*, info: &DmaBufImportInfo*/) -> StarforgeResult<()> {
-        //let resource = self.resource_manager.import_dma_buf(self.context.clone(), info)?;
-        Ok(())
+    /// Import a dma-buf as a texture
+    ///
+    /// The dma-buf is sampled in place, YUV dma-bufs are converted into RGB every time a
+    /// frame samples them. Importing the same dma-buf again returns the same texture until
+    /// it is released or the client destroys the buffer.
+    pub fn import_dma_buf(&self, dmabuf: &Dmabuf) -> StarforgeResult<TextureId> {
+        self.resource_manager
+            .write()
+            .unwrap()
+            .import_dma_buf(dmabuf)
+    }
+
+    /// Set how the YUV values of a texture map to RGB, from the wp_color_representation_v1
+    /// state of the surface it is shown on
+    ///
+    /// RGB textures ignore it. Unset values are picked from the size of the buffer.
+    pub fn set_color_representation(
+        &self,
+        texture_id: TextureId,
+        representation: ColorRepresentationSurfaceCachedState,
+    ) -> StarforgeResult<()> {
+        self.resource_manager
+            .write()
+            .unwrap()
+            .set_color_representation(texture_id, representation)
+    }
+
+    /// Formats and modifiers dma-bufs can be imported with
+    pub fn dmabuf_formats(&self) -> Vec<Format> {
+        dmabuf::supported_formats(&self.context)
+    }
+
+    /// DRM node of the device, to advertise in dma-buf feedback
+    pub fn drm_node(&self) -> Option<smithay::reexports::rustix::fs::Dev> {
+        self.context.drm_node().map(|(major, minor)| {
+            smithay::reexports::rustix::fs::makedev(major as u32, minor as u32)
+        })
+    }
+
+    /// Upload a wl_shm buffer into a texture
+    pub fn import_shm_buffer(&self, buffer: &WlBuffer) -> StarforgeResult<TextureId> {
+        self.resource_manager
+            .write()
+            .unwrap()
+            .import_shm_buffer(buffer)
     }
 
     /// Signal buffer release intent
-    pub fn release_buffer(&self /*, texture_id: TextureId*/) -> StarforgeResult<()> {
-        //self.resource_manager.schedule_release(texture_id)?;
-        Ok(())
+    pub fn release_buffer(&self, texture_id: TextureId) -> StarforgeResult<()> {
+        self.cursor
+            .write()
+            .unwrap()
+            .forget_client_texture(texture_id);
+        self.resource_manager
+            .write()
+            .unwrap()
+            .schedule_release(texture_id)
     }
 
-    /// Orchestrate rendering for one output for one frame
-    pub fn render_frame(
+    /// Sample a client buffer in the next frame of an output
+    ///
+    /// The frame waits for `acquire_point` on the GPU before sampling, and the buffer is
+    /// held until the frame completed. Without an acquire point the buffer relies on
+    /// implicit synchronization.
+    pub fn use_client_buffer(
         &self,
-        id: OutputId, /*, elements: &[RenderElement], frame_config: &OutputFrameConfig*/
+        output: OutputId,
+        buffer: &Buffer,
+        acquire_point: Option<&DrmSyncPoint>,
     ) -> StarforgeResult<()> {
-        //let swapchain = self.outputs.read().unwrap().get(&id).ok_or(StarforgeError::OutputNotFound)?;
-        //swapchain.render_frame(&self.resource_manager, &self.pipeline_cache, elements, frame_config)?;
+        self.explicit_sync
+            .write()
+            .unwrap()
+            .use_buffer(output, buffer, acquire_point)
+    }
+
+    /// Semaphores to wait on and signal in the submission of an output's frame
+    ///
+    /// Has to be called exactly once per submission, the signal makes the frame's client
+    /// buffers releasable once it completed. [`Self::render_frame`] calls it itself.
+    pub fn frame_sync_infos(
+        &self,
+        output: OutputId,
+    ) -> (
+        Vec<vk::SemaphoreSubmitInfo<'static>>,
+        vk::SemaphoreSubmitInfo<'static>,
+    ) {
+        self.explicit_sync.write().unwrap().submit_infos(output)
+    }
+
+    /// Release client buffers of completed frames, signalling their release points
+    pub fn retire_frames(&self) -> StarforgeResult<()> {
+        self.explicit_sync.write().unwrap().retire()
+    }
+
+    /// Whether the GPU device was lost and `recover_from_device_loss` must be called
+    pub fn is_device_lost(&self) -> bool {
+        self.context.is_lost()
+    }
+
+    /// Mark the device as lost without a GPU fault, for testing recovery
+    pub fn simulate_device_loss(&self) {
+        self.context.simulate_device_loss();
+    }
+
+    /// Rebuild the renderer on a new Vulkan device after the old one was lost
+    ///
+    /// Outputs, effect shaders and client textures are recreated from what the renderer
+    /// knows about them; Wayland state is untouched. Cached blurs, HUDs and frame
+    /// statistics start over. Can be called again if the GPU isn't back yet.
+    pub fn recover_from_device_loss(&mut self) -> StarforgeResult<()> {
+        warn!("Vulkan device lost, recreating the renderer.");
+
+        // Swapchains own the window surfaces, so they must go before new ones are created
+        self.outputs.get_mut().unwrap().clear();
+        self.profilers.get_mut().unwrap().clear();
+
+        let context = create_context()?;
+        let shaders = self.shaders.read().unwrap().recreate(context.clone())?;
+        *self.shaders.get_mut().unwrap() = shaders;
+        *self.blur.get_mut().unwrap() = BlurRenderer::new(context.clone())?;
+        *self.shapes.get_mut().unwrap() = ShapeRenderer::new(context.clone())?;
+        *self.compositor.get_mut().unwrap() = Compositor::new(context.clone())?;
+        *self.hud.get_mut().unwrap() = HudRenderer::new(context.clone())?;
+        let accessibility = self
+            .accessibility
+            .read()
+            .unwrap()
+            .recreate(context.clone())?;
+        *self.accessibility.get_mut().unwrap() = accessibility;
+        let set_layout = self.shapes.get_mut().unwrap().texture_set_layout();
+        *self.cursor.get_mut().unwrap() = CursorRenderer::new(context.clone(), set_layout)?;
+        let title_bars = self
+            .title_bars
+            .read()
+            .unwrap()
+            .recreate(context.clone(), set_layout)?;
+        *self.title_bars.get_mut().unwrap() = title_bars;
+        self.frame_damage.get_mut().unwrap().clear();
+        *self.explicit_sync.get_mut().unwrap() = ExplicitSync::new(context.clone())?;
+        self.resource_manager
+            .get_mut()
+            .unwrap()
+            .recreate(context.clone());
+        // Offscreen outputs come back cleared, their next frame fills them again
+        let offscreen_outputs = self
+            .offscreen_outputs
+            .get_mut()
+            .unwrap()
+            .drain()
+            .map(|(id, target)| (id, target.extent()))
+            .collect::<Vec<_>>();
+        for (id, extent) in offscreen_outputs {
+            let size = (extent.width as i32, extent.height as i32).into();
+            let target = OffscreenTarget::new(context.clone(), size)?;
+            self.offscreen_outputs.get_mut().unwrap().insert(id, target);
+            let profiler = OutputProfiler {
+                timer: GpuTimer::new(context.clone(), 1)?,
+                stats: FrameStats::default(),
+            };
+            self.profilers.get_mut().unwrap().insert(id, profiler);
+        }
+        self.context = context;
+
+        let configs: Vec<_> = self
+            .output_configs
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(&id, (create_info, config))| (id, *create_info, config.clone()))
+            .collect();
+        for (id, create_info, config) in configs {
+            self.create_output(id, create_info, config)?;
+        }
+
+        info!("Renderer recovered from device loss.");
         Ok(())
     }
+
+    /// Draw `elements` into an offscreen output for one frame, bottom-most first,
+    /// replacing what it showed
+    ///
+    /// Elements are blended in the linear working space, each decoded from its own colour
+    /// space, and the result is encoded once for the output before its colour filter and
+    /// magnifier run. Surfaces with a backdrop blur are drawn over a blur of what is
+    /// below them. Client textures have to be imported, their buffers passed to
+    /// [`Self::use_client_buffer`], and cursors and title bars uploaded beforehand; the
+    /// statistics HUD is uploaded here.
+    ///
+    /// Returns what changed since the last frame of the output, from where elements came,
+    /// left or moved in the stack and the content damage of client surfaces, grown by the
+    /// reach of the blurs it touches. All of the output changed when its encoding, gamma
+    /// ramp or colour filter did, or while it is magnified. Blurs are only recomputed when
+    /// their backdrop was damaged.
+    pub fn render_frame(
+        &self,
+        id: OutputId,
+        elements: &[RenderElement],
+    ) -> StarforgeResult<Vec<Rectangle<i32, Physical>>> {
+        if self.outputs.read().unwrap().contains_key(&id) {
+            return Err(StarforgeError::RendererError(
+                "presenting to a swapchain isn't supported yet".into(),
+            ));
+        }
+        let content_peak_nits = elements
+            .iter()
+            .filter_map(|element| match element {
+                RenderElement::ClientSurface {
+                    image_description, ..
+                } => Some(self.color_pipeline.description_peak_nits(image_description)),
+                _ => None,
+            })
+            .fold(self.color_pipeline.reference_white_nits, f32::max);
+        let encode = self.output_encode_transform(id, content_peak_nits)?;
+        let gamma_lut = self.output_gamma_lut(id);
+        let srgb_decode = self.color_pipeline.decode_transform(ColorSpace::SRGB);
+        let summary = elements
+            .iter()
+            .any(|element| matches!(element, RenderElement::StatsHud { .. }))
+            .then(|| self.frame_stats(id))
+            .transpose()?;
+
+        let offscreen_outputs = self.offscreen_outputs.read().unwrap();
+        let target = offscreen_outputs
+            .get(&id)
+            .ok_or(StarforgeError::OutputNotFound)?;
+        let mut resources = self.resource_manager.write().unwrap();
+        let mut shapes = self.shapes.write().unwrap();
+        let mut blur = self.blur.write().unwrap();
+        let mut compositor = self.compositor.write().unwrap();
+        let mut cursor = self.cursor.write().unwrap();
+        let mut title_bars = self.title_bars.write().unwrap();
+        let mut hud = self.hud.write().unwrap();
+        let mut accessibility = self.accessibility.write().unwrap();
+        let mut profilers = self.profilers.write().unwrap();
+        let profiler = profilers
+            .get_mut(&id)
+            .ok_or(StarforgeError::OutputNotFound)?;
+
+        let extent = target.extent();
+        let output_size: Size<i32, Physical> = (extent.width as i32, extent.height as i32).into();
+        let (working_image, working_view) = compositor.working_image(id, extent)?;
+
+        let client_textures: Vec<_> = elements
+            .iter()
+            .filter_map(|element| match *element {
+                RenderElement::ClientSurface { texture_id, .. } => Some(texture_id),
+                RenderElement::Cursor {
+                    texture: CursorTexture::Client(texture_id),
+                    ..
+                } => Some(texture_id),
+                _ => None,
+            })
+            .collect();
+        let texture_view = |resources: &ResourceManager, texture_id: TextureId| {
+            resources
+                .texture(texture_id)
+                .map(|texture| texture.view())
+                .ok_or_else(|| {
+                    StarforgeError::RendererError(format!("unknown texture {}", texture_id.0))
+                })
+        };
+        let surface_views = elements
+            .iter()
+            .filter_map(|element| match *element {
+                RenderElement::ClientSurface { texture_id, .. } => {
+                    Some(texture_view(&resources, texture_id))
+                }
+                _ => None,
+            })
+            .collect::<StarforgeResult<Vec<_>>>()?;
+        let mut dmabufs: Vec<_> = client_textures
+            .into_iter()
+            .filter(|&texture_id| {
+                resources
+                    .texture(texture_id)
+                    .is_some_and(|texture| texture.needs_acquire())
+            })
+            .collect();
+        dmabufs.sort_by_key(|texture_id| texture_id.0);
+        dmabufs.dedup();
+        let blurs = elements
+            .iter()
+            .filter(|element| {
+                matches!(
+                    element,
+                    RenderElement::ClientSurface {
+                        backdrop_blur: Some(_),
+                        ..
+                    }
+                )
+            })
+            .count();
+
+        // Blurred backdrops get their own sets after those of the surfaces, written once
+        // the blur recorded them
+        let (descriptor_pool, sets) = create_texture_sets(
+            &self.context,
+            shapes.texture_set_layout(),
+            (surface_views.len() + blurs).max(1) as u32,
+        )?;
+        for (&set, &view) in sets.iter().zip(&surface_views) {
+            write_texture_set(&self.context, set, view, shapes.surface_sampler());
+        }
+        let (surface_sets, blur_sets) = sets.split_at(surface_views.len());
+
+        let footprints = elements
+            .iter()
+            .map(|element| Footprint::of(element, &title_bars))
+            .collect();
+        let passes = OutputPasses {
+            encode,
+            gamma_lut,
+            filter: accessibility.color_filter(id),
+            magnified: accessibility.magnifier(id).is_active(),
+        };
+        let mut frame_damage = self.frame_damage.write().unwrap();
+        let damage = frame_damage
+            .entry(id)
+            .or_default()
+            .update(output_size, footprints);
+        let blur_regions: Vec<_> = elements
+            .iter()
+            .filter_map(|element| match element {
+                RenderElement::ClientSurface {
+                    position,
+                    size,
+                    backdrop_blur: Some(settings),
+                    ..
+                } => Rectangle::new((*position).into(), (size.0 as i32, size.1 as i32).into())
+                    .intersection(Rectangle::from_size(output_size))
+                    .map(|region| (region, *settings)),
+                _ => None,
+            })
+            .collect();
+
+        shapes.reset_decodes();
+        let device = self.context.device();
+        let begin_working = |command_buffer: vk::CommandBuffer, load_op: vk::AttachmentLoadOp| {
+            let attachments = [vk::RenderingAttachmentInfo::default()
+                .image_view(working_view)
+                .image_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+                .load_op(load_op)
+                .store_op(vk::AttachmentStoreOp::STORE)
+                .clear_value(vk::ClearValue::default())];
+            let rendering_info = vk::RenderingInfo::default()
+                .render_area(vk::Rect2D {
+                    offset: vk::Offset2D::default(),
+                    extent,
+                })
+                .layer_count(1)
+                .color_attachments(&attachments);
+            unsafe { device.cmd_begin_rendering(command_buffer, &rendering_info) };
+        };
+        let record = |command_buffer: vk::CommandBuffer| -> StarforgeResult<()> {
+            // Offscreen outputs wait for every frame, so there's only one in flight
+            if let Some(summary) = &summary {
+                hud.upload(id, command_buffer, &shapes, 1, 0, summary)?;
+            }
+            for &texture_id in &dmabufs {
+                resources.record_acquire(texture_id, command_buffer);
+            }
+            let timings = profiler.timer.begin_frame(command_buffer, 0);
+            profiler.stats.add_gpu_timings(timings);
+
+            let scope = profiler.timer.begin_scope(command_buffer, "windows");
+            unsafe {
+                image_barrier(
+                    device,
+                    command_buffer,
+                    working_image,
+                    vk::ImageLayout::UNDEFINED,
+                    vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                    (vk::PipelineStageFlags2::NONE, vk::AccessFlags2::NONE),
+                    (
+                        vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT,
+                        vk::AccessFlags2::COLOR_ATTACHMENT_WRITE,
+                    ),
+                );
+            }
+            begin_working(command_buffer, vk::AttachmentLoadOp::CLEAR);
+            let mut surface_sets = surface_sets.iter();
+            let mut blur_sets = blur_sets.iter();
+            let mut stack = elements.iter().enumerate();
+            let drawn = stack.try_for_each(|(index, element)| match element {
+                RenderElement::ClientSurface {
+                    position,
+                    size,
+                    source,
+                    image_description,
+                    backdrop_blur,
+                    shape,
+                    ..
+                } => {
+                    let rect =
+                        Rectangle::new((*position).into(), (size.0 as i32, size.1 as i32).into());
+                    let blur_set = backdrop_blur.as_ref().and_then(|_| blur_sets.next());
+                    if let (Some(settings), Some(&blur_set)) = (backdrop_blur, blur_set)
+                        && let Some(region) = rect
+                            .intersection(Rectangle::from_size(output_size))
+                            .filter(|region| !region.is_empty())
+                    {
+                        // The blur copies what was drawn so far out of the working image
+                        unsafe {
+                            device.cmd_end_rendering(command_buffer);
+                            image_barrier(
+                                device,
+                                command_buffer,
+                                working_image,
+                                vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                                (
+                                    vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT,
+                                    vk::AccessFlags2::COLOR_ATTACHMENT_WRITE,
+                                ),
+                                (
+                                    vk::PipelineStageFlags2::COPY,
+                                    vk::AccessFlags2::TRANSFER_READ,
+                                ),
+                            );
+                        }
+                        let scope = profiler.timer.begin_scope(command_buffer, "blur");
+                        let damage_below =
+                            expand_blur_damage(&damage.below(index), &[(region, *settings)]);
+                        let blurred = blur.record(
+                            id,
+                            command_buffer,
+                            working_image,
+                            WORKING_FORMAT,
+                            region,
+                            *settings,
+                            &damage_below,
+                        );
+                        if let Some(scope) = scope {
+                            profiler.timer.end_scope(command_buffer, scope);
+                        }
+                        unsafe {
+                            image_barrier(
+                                device,
+                                command_buffer,
+                                working_image,
+                                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                                vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                                (
+                                    vk::PipelineStageFlags2::COPY,
+                                    vk::AccessFlags2::TRANSFER_READ,
+                                ),
+                                (
+                                    vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT,
+                                    vk::AccessFlags2::COLOR_ATTACHMENT_READ
+                                        | vk::AccessFlags2::COLOR_ATTACHMENT_WRITE,
+                                ),
+                            );
+                        }
+                        begin_working(command_buffer, vk::AttachmentLoadOp::LOAD);
+                        // The blur ran in the working space, there's nothing to decode
+                        write_texture_set(
+                            &self.context,
+                            blur_set,
+                            blurred?,
+                            shapes.surface_sampler(),
+                        );
+                        shapes.record(
+                            command_buffer,
+                            WORKING_FORMAT,
+                            output_size,
+                            blur_set,
+                            region,
+                            FULL_TEXTURE,
+                            &shape.backdrop(rect, region),
+                            &ColorTransform::IDENTITY,
+                            1.0,
+                        )?;
+                    }
+                    shapes.record(
+                        command_buffer,
+                        WORKING_FORMAT,
+                        output_size,
+                        *surface_sets.next().unwrap(),
+                        rect,
+                        *source,
+                        shape,
+                        // Descriptions are checked when clients create them, so sRGB
+                        // is only a fallback
+                        &self
+                            .color_pipeline
+                            .description_decode_transform(image_description)
+                            .unwrap_or(srgb_decode),
+                        1.0,
+                    )
+                }
+                RenderElement::SolidColor { rect, color } => {
+                    let rect = Rectangle::<i32, Physical>::new(
+                        (rect.0, rect.1).into(),
+                        (rect.2 as i32, rect.3 as i32).into(),
+                    );
+                    let Some(rect) = rect
+                        .intersection(Rectangle::from_size(output_size))
+                        .filter(|rect| !rect.is_empty())
+                    else {
+                        return Ok(());
+                    };
+                    let [r, g, b] = srgb_decode.apply([color[0], color[1], color[2]]);
+                    let alpha = color[3];
+                    let attachment = vk::ClearAttachment {
+                        aspect_mask: vk::ImageAspectFlags::COLOR,
+                        color_attachment: 0,
+                        clear_value: vk::ClearValue {
+                            color: vk::ClearColorValue {
+                                float32: [r * alpha, g * alpha, b * alpha, alpha],
+                            },
+                        },
+                    };
+                    let clear_rect = vk::ClearRect {
+                        rect: vk::Rect2D {
+                            offset: vk::Offset2D {
+                                x: rect.loc.x,
+                                y: rect.loc.y,
+                            },
+                            extent: vk::Extent2D {
+                                width: rect.size.w as u32,
+                                height: rect.size.h as u32,
+                            },
+                        },
+                        base_array_layer: 0,
+                        layer_count: 1,
+                    };
+                    unsafe {
+                        device.cmd_clear_attachments(command_buffer, &[attachment], &[clear_rect])
+                    };
+                    Ok(())
+                }
+                RenderElement::StatsHud { position } => hud.draw(
+                    id,
+                    command_buffer,
+                    &mut shapes,
+                    WORKING_FORMAT,
+                    output_size,
+                    0,
+                    (*position).into(),
+                    &srgb_decode,
+                ),
+                RenderElement::Cursor {
+                    texture,
+                    position,
+                    size,
+                } => {
+                    let client_view = match *texture {
+                        CursorTexture::Client(texture_id) => {
+                            Some(texture_view(&resources, texture_id)?)
+                        }
+                        CursorTexture::Named(_) => None,
+                    };
+                    cursor.draw(
+                        command_buffer,
+                        &mut shapes,
+                        WORKING_FORMAT,
+                        output_size,
+                        *texture,
+                        client_view,
+                        Rectangle::new((*position).into(), (size.0 as i32, size.1 as i32).into()),
+                        &srgb_decode,
+                    )
+                }
+                RenderElement::TitleBar { key, rect } => title_bars.draw(
+                    command_buffer,
+                    &mut shapes,
+                    WORKING_FORMAT,
+                    output_size,
+                    *key,
+                    Rectangle::new(
+                        (rect.0, rect.1).into(),
+                        (rect.2 as i32, rect.3 as i32).into(),
+                    ),
+                    &srgb_decode,
+                ),
+            });
+            unsafe {
+                device.cmd_end_rendering(command_buffer);
+            }
+            drawn?;
+            if let Some(scope) = scope {
+                profiler.timer.end_scope(command_buffer, scope);
+            }
+
+            let scope = profiler.timer.begin_scope(command_buffer, "encode");
+            unsafe {
+                image_barrier(
+                    device,
+                    command_buffer,
+                    working_image,
+                    vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                    vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                    (
+                        vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT,
+                        vk::AccessFlags2::COLOR_ATTACHMENT_WRITE,
+                    ),
+                    (
+                        vk::PipelineStageFlags2::FRAGMENT_SHADER,
+                        vk::AccessFlags2::SHADER_SAMPLED_READ,
+                    ),
+                );
+                image_barrier(
+                    device,
+                    command_buffer,
+                    target.image(),
+                    OffscreenTarget::LAYOUT,
+                    vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                    (
+                        vk::PipelineStageFlags2::ALL_TRANSFER,
+                        vk::AccessFlags2::TRANSFER_READ,
+                    ),
+                    (
+                        vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT,
+                        vk::AccessFlags2::COLOR_ATTACHMENT_WRITE,
+                    ),
+                );
+            }
+            compositor.record_encode(
+                id,
+                command_buffer,
+                target.view(),
+                target.format(),
+                extent,
+                &encode,
+                gamma_lut.as_ref(),
+            )?;
+            unsafe {
+                image_barrier(
+                    device,
+                    command_buffer,
+                    target.image(),
+                    vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                    OffscreenTarget::LAYOUT,
+                    (
+                        vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT,
+                        vk::AccessFlags2::COLOR_ATTACHMENT_WRITE,
+                    ),
+                    (
+                        vk::PipelineStageFlags2::ALL_TRANSFER,
+                        vk::AccessFlags2::TRANSFER_READ,
+                    ),
+                );
+            }
+            if let Some(scope) = scope {
+                profiler.timer.end_scope(command_buffer, scope);
+            }
+
+            let scope = profiler.timer.begin_scope(command_buffer, "accessibility");
+            let filtered = accessibility.record(
+                id,
+                command_buffer,
+                target.image(),
+                target.view(),
+                target.format(),
+                extent,
+            )?;
+            if filtered {
+                unsafe {
+                    image_barrier(
+                        device,
+                        command_buffer,
+                        target.image(),
+                        vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                        OffscreenTarget::LAYOUT,
+                        (
+                            vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT,
+                            vk::AccessFlags2::COLOR_ATTACHMENT_WRITE,
+                        ),
+                        (
+                            vk::PipelineStageFlags2::ALL_TRANSFER,
+                            vk::AccessFlags2::TRANSFER_READ,
+                        ),
+                    );
+                }
+            }
+            if let Some(scope) = scope {
+                profiler.timer.end_scope(command_buffer, scope);
+            }
+
+            for &texture_id in &dmabufs {
+                resources.record_release(texture_id, command_buffer);
+            }
+            profiler.timer.end_frame(command_buffer);
+            Ok(())
+        };
+        // Client buffers handed to `use_client_buffer` are held until the frame completed
+        let (waits, signal) = self.explicit_sync.write().unwrap().submit_infos(id);
+        let result = self.context.submit_and_wait_with(&waits, &[signal], record);
+
+        blur.end_frame(id);
+        self.context.untrack(descriptor_pool);
+        unsafe {
+            device.destroy_descriptor_pool(descriptor_pool, None);
+        }
+        match result {
+            Ok(()) => {
+                let damage = expand_blur_damage(&damage.all(), &blur_regions);
+                let output_damage = frame_damage.get_mut(&id).expect("tracked above");
+                Ok(output_damage.output_damage(output_size, damage, passes))
+            }
+            Err(e) => {
+                // What the output shows is unknown, the next frame replaces all of it
+                frame_damage.remove(&id);
+                Err(e)
+            }
+        }
+    }
+}
+
+fn create_context() -> StarforgeResult<Arc<Context>> {
+    Ok(Arc::new(Context::new(
+        "Starforge".into_c_str().unwrap().as_ref(),
+        0,
+        &[],
+        true,
+    )?))
 }
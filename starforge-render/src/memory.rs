@@ -1,3 +1,289 @@
 //! Starforge Render - Vulkan Memory Management
 //!
 //! This module handles wrappers for buffers/images, memory allocation, and DMA-BUF import logic
+
+use crate::core::Context;
+use ash::vk;
+use starforge_core::{StarforgeError, StarforgeResult};
+use std::sync::Arc;
+use vk_mem::Alloc;
+
+/// A 2D color image allocated in device memory, with a view covering all of it
+pub struct AllocatedImage {
+    context: Arc<Context>,
+    image: vk::Image,
+    view: vk::ImageView,
+    allocation: vk_mem::Allocation,
+    format: vk::Format,
+    extent: vk::Extent2D,
+}
+
+impl AllocatedImage {
+    pub fn new(
+        context: Arc<Context>,
+        extent: vk::Extent2D,
+        format: vk::Format,
+        usage: vk::ImageUsageFlags,
+    ) -> StarforgeResult<Self> {
+        Self::with_components(
+            context,
+            extent,
+            format,
+            usage,
+            vk::ComponentMapping::default(),
+        )
+    }
+
+    /// Like [`Self::new`], with the view swizzling channels as `components` says
+    pub fn with_components(
+        context: Arc<Context>,
+        extent: vk::Extent2D,
+        format: vk::Format,
+        usage: vk::ImageUsageFlags,
+        components: vk::ComponentMapping,
+    ) -> StarforgeResult<Self> {
+        let image_info = vk::ImageCreateInfo::default()
+            .image_type(vk::ImageType::TYPE_2D)
+            .format(format)
+            .extent(vk::Extent3D {
+                width: extent.width,
+                height: extent.height,
+                depth: 1,
+            })
+            .mip_levels(1)
+            .array_layers(1)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .tiling(vk::ImageTiling::OPTIMAL)
+            .usage(usage)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE)
+            .initial_layout(vk::ImageLayout::UNDEFINED);
+        let allocation_info = vk_mem::AllocationCreateInfo {
+            usage: vk_mem::MemoryUsage::AutoPreferDevice,
+            ..Default::default()
+        };
+
+        unsafe {
+            let (image, mut allocation) = context
+                .allocator()
+                .create_image(&image_info, &allocation_info)
+                .map_err(|e| StarforgeError::RendererError(e.to_string()))?;
+
+            let view_info = vk::ImageViewCreateInfo::default()
+                .image(image)
+                .view_type(vk::ImageViewType::TYPE_2D)
+                .format(format)
+                .components(components)
+                .subresource_range(COLOR_SUBRESOURCE_RANGE);
+            let view = match context.device().create_image_view(&view_info, None) {
+                Ok(view) => view,
+                Err(e) => {
+                    context.allocator().destroy_image(image, &mut allocation);
+                    return Err(StarforgeError::RendererError(e.to_string()));
+                }
+            };
+            context.track(image);
+            context.track(view);
+
+            Ok(Self {
+                context,
+                image,
+                view,
+                allocation,
+                format,
+                extent,
+            })
+        }
+    }
+
+    pub fn image(&self) -> vk::Image {
+        self.image
+    }
+
+    pub fn view(&self) -> vk::ImageView {
+        self.view
+    }
+
+    pub fn format(&self) -> vk::Format {
+        self.format
+    }
+
+    pub fn extent(&self) -> vk::Extent2D {
+        self.extent
+    }
+}
+
+impl Drop for AllocatedImage {
+    fn drop(&mut self) {
+        unsafe {
+            self.context.untrack(self.view);
+            self.context.untrack(self.image);
+            self.context.device().destroy_image_view(self.view, None);
+            self.context
+                .allocator()
+                .destroy_image(self.image, &mut self.allocation);
+        }
+    }
+}
+
+/// A buffer in host-visible memory that stays mapped for its whole lifetime
+pub struct MappedBuffer {
+    context: Arc<Context>,
+    buffer: vk::Buffer,
+    allocation: vk_mem::Allocation,
+    mapped: *mut u8,
+    size: vk::DeviceSize,
+}
+
+impl MappedBuffer {
+    pub fn new(
+        context: Arc<Context>,
+        size: vk::DeviceSize,
+        usage: vk::BufferUsageFlags,
+    ) -> StarforgeResult<Self> {
+        Self::with_access(
+            context,
+            size,
+            usage,
+            vk_mem::AllocationCreateFlags::HOST_ACCESS_SEQUENTIAL_WRITE,
+        )
+    }
+
+    /// Like [`Self::new`], for buffers the device writes and the host reads back
+    pub fn readback(
+        context: Arc<Context>,
+        size: vk::DeviceSize,
+        usage: vk::BufferUsageFlags,
+    ) -> StarforgeResult<Self> {
+        Self::with_access(
+            context,
+            size,
+            usage,
+            vk_mem::AllocationCreateFlags::HOST_ACCESS_RANDOM,
+        )
+    }
+
+    fn with_access(
+        context: Arc<Context>,
+        size: vk::DeviceSize,
+        usage: vk::BufferUsageFlags,
+        host_access: vk_mem::AllocationCreateFlags,
+    ) -> StarforgeResult<Self> {
+        let buffer_info = vk::BufferCreateInfo::default()
+            .size(size)
+            .usage(usage)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE);
+        let allocation_info = vk_mem::AllocationCreateInfo {
+            usage: vk_mem::MemoryUsage::AutoPreferHost,
+            flags: host_access | vk_mem::AllocationCreateFlags::MAPPED,
+            ..Default::default()
+        };
+
+        unsafe {
+            let (buffer, allocation) = context
+                .allocator()
+                .create_buffer(&buffer_info, &allocation_info)
+                .map_err(|e| StarforgeError::RendererError(e.to_string()))?;
+            let mapped = context
+                .allocator()
+                .get_allocation_info(&allocation)
+                .mapped_data
+                .cast::<u8>();
+            context.track(buffer);
+
+            Ok(Self {
+                context,
+                buffer,
+                allocation,
+                mapped,
+                size,
+            })
+        }
+    }
+
+    pub fn buffer(&self) -> vk::Buffer {
+        self.buffer
+    }
+
+    pub fn size(&self) -> vk::DeviceSize {
+        self.size
+    }
+
+    /// Copy `data` to the start of the buffer and flush it to the device
+    pub fn write(&mut self, data: &[u8]) -> StarforgeResult<()> {
+        self.write_at(0, data)
+    }
+
+    /// Copy `data` to `offset` bytes into the buffer and flush it to the device
+    pub fn write_at(&mut self, offset: vk::DeviceSize, data: &[u8]) -> StarforgeResult<()> {
+        if offset + data.len() as vk::DeviceSize > self.size {
+            return Err(StarforgeError::RendererError(
+                "write exceeds buffer size".into(),
+            ));
+        }
+        unsafe {
+            std::ptr::copy_nonoverlapping(
+                data.as_ptr(),
+                self.mapped.add(offset as usize),
+                data.len(),
+            );
+            self.context
+                .allocator()
+                .flush_allocation(&self.allocation, offset, data.len() as vk::DeviceSize)
+                .map_err(|e| StarforgeError::RendererError(e.to_string()))
+        }
+    }
+
+    /// Make what the device wrote visible to the host and return the whole buffer
+    pub fn read(&self) -> StarforgeResult<&[u8]> {
+        unsafe {
+            self.context
+                .allocator()
+                .invalidate_allocation(&self.allocation, 0, self.size)
+                .map_err(|e| StarforgeError::RendererError(e.to_string()))?;
+            Ok(std::slice::from_raw_parts(self.mapped, self.size as usize))
+        }
+    }
+}
+
+impl Drop for MappedBuffer {
+    fn drop(&mut self) {
+        unsafe {
+            self.context.untrack(self.buffer);
+            self.context
+                .allocator()
+                .destroy_buffer(self.buffer, &mut self.allocation);
+        }
+    }
+}
+
+/// Record a layout transition of a whole color image
+pub(crate) unsafe fn image_barrier(
+    device: &ash::Device,
+    command_buffer: vk::CommandBuffer,
+    image: vk::Image,
+    old_layout: vk::ImageLayout,
+    new_layout: vk::ImageLayout,
+    src: (vk::PipelineStageFlags2, vk::AccessFlags2),
+    dst: (vk::PipelineStageFlags2, vk::AccessFlags2),
+) {
+    let barriers = [vk::ImageMemoryBarrier2::default()
+        .src_stage_mask(src.0)
+        .src_access_mask(src.1)
+        .dst_stage_mask(dst.0)
+        .dst_access_mask(dst.1)
+        .old_layout(old_layout)
+        .new_layout(new_layout)
+        .image(image)
+        .subresource_range(COLOR_SUBRESOURCE_RANGE)];
+    let dependency = vk::DependencyInfo::default().image_memory_barriers(&barriers);
+    unsafe { device.cmd_pipeline_barrier2(command_buffer, &dependency) };
+}
+
+/// The single mip level and layer of a color image
+pub const COLOR_SUBRESOURCE_RANGE: vk::ImageSubresourceRange = vk::ImageSubresourceRange {
+    aspect_mask: vk::ImageAspectFlags::COLOR,
+    base_mip_level: 0,
+    level_count: 1,
+    base_array_layer: 0,
+    layer_count: 1,
+};
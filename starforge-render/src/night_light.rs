@@ -0,0 +1,294 @@
+//! Starforge Render - Night Light
+//!
+//! This module implements the per-output colour adjustments of the final encode pass: a
+//! colour temperature, and a gamma and brightness curve.
+//!
+//! The temperature moves the white point along the Planckian locus. Its per-channel gains
+//! are folded into the encode matrix, in linear light of the output's primaries, so
+//! 6500 K leaves colours unchanged. The curve is applied to the encoded values.
+//!
+//! Gamma tables uploaded through wlr-gamma-control replace these adjustments while a
+//! client holds them, as tools like wlsunset compute the temperature themselves.
+
+use crate::color::{ColorTransform, GammaCurve, Mat3, Primaries, xy_to_xyz};
+use starforge_core::protocols::gamma_control::GammaRamp;
+
+/// Number of entries per channel of [`GammaLut`], also the gamma size offered to clients
+pub const GAMMA_LUT_SIZE: usize = 256;
+
+/// Temperature of the D65 white point, at which no adjustment happens
+pub const NEUTRAL_TEMPERATURE: u32 = 6500;
+
+/// Range of the Planckian locus approximation
+const MIN_TEMPERATURE: u32 = 1667;
+const MAX_TEMPERATURE: u32 = 25000;
+
+/// Colour temperature, gamma and brightness of an output
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ColorAdjustment {
+    /// White point in Kelvin, lower is warmer
+    pub temperature: u32,
+    /// Gamma applied to encoded values, above 1 brightens mid-tones
+    pub gamma: f32,
+    /// Brightness factor, 1 keeps the output's peak
+    pub brightness: f32,
+}
+
+impl Default for ColorAdjustment {
+    fn default() -> Self {
+        Self {
+            temperature: NEUTRAL_TEMPERATURE,
+            gamma: 1.0,
+            brightness: 1.0,
+        }
+    }
+}
+
+impl ColorAdjustment {
+    pub fn is_identity(&self) -> bool {
+        *self == Self::default()
+    }
+
+    fn curve(&self) -> GammaCurve {
+        GammaCurve {
+            gamma: self.gamma.max(f32::EPSILON),
+            brightness: self.brightness.max(0.0),
+        }
+    }
+
+    /// Add the adjustment to a transform encoding for an output with `primaries`
+    pub fn apply_to(&self, mut transform: ColorTransform, primaries: Primaries) -> ColorTransform {
+        if self.temperature != NEUTRAL_TEMPERATURE {
+            let [r, g, b] = white_point_gains(self.temperature, primaries);
+            let gains = Mat3([[r, 0.0, 0.0], [0.0, g, 0.0], [0.0, 0.0, b]]);
+            transform.matrix = gains.mul(&transform.matrix);
+        }
+        let curve = self.curve();
+        if !curve.is_identity() {
+            transform.curve = Some(curve);
+        }
+        transform
+    }
+}
+
+/// CIE 1931 xy chromaticity of a black body at `temperature` Kelvin, after Kim et al.
+fn planckian_locus(temperature: u32) -> [f32; 2] {
+    let t = temperature.clamp(MIN_TEMPERATURE, MAX_TEMPERATURE) as f64;
+    let (t2, t3) = (t * t, t * t * t);
+    let x = if t <= 4000.0 {
+        -0.266_123_9e9 / t3 - 0.234_358_9e6 / t2 + 0.877_695_6e3 / t + 0.179_910
+    } else {
+        -3.025_846_9e9 / t3 + 2.107_037_9e6 / t2 + 0.222_634_7e3 / t + 0.240_390
+    };
+    let (x2, x3) = (x * x, x * x * x);
+    let y = if t <= 2222.0 {
+        -1.106_381_4 * x3 - 1.348_110_20 * x2 + 2.185_558_32 * x - 0.202_196_83
+    } else if t <= 4000.0 {
+        -0.954_947_6 * x3 - 1.374_185_93 * x2 + 2.091_370_15 * x - 0.167_488_67
+    } else {
+        3.081_758_0 * x3 - 5.873_386_70 * x2 + 3.751_129_97 * x - 0.370_014_83
+    };
+    [x as f32, y as f32]
+}
+
+/// Per-channel gains in linear light of `primaries` that move white to `temperature`,
+/// scaled so the largest gain is 1
+pub fn white_point_gains(temperature: u32, primaries: Primaries) -> [f32; 3] {
+    let to_rgb = primaries
+        .chromaticities()
+        .xyz_to_rgb()
+        .expect("well-known primaries are linearly independent");
+    let white = |temperature| to_rgb.apply(xy_to_xyz(planckian_locus(temperature)));
+    // Relative to the locus at 6500 K, which sits slightly off D65
+    let target = white(temperature);
+    let neutral = white(NEUTRAL_TEMPERATURE);
+    let gains: [f32; 3] = std::array::from_fn(|i| (target[i] / neutral[i]).max(0.0));
+    let max = gains.into_iter().fold(f32::EPSILON, f32::max);
+    gains.map(|gain| gain / max)
+}
+
+/// Per-channel gamma tables resampled to [`GAMMA_LUT_SIZE`] entries
+#[derive(Clone, Debug, PartialEq)]
+pub struct GammaLut {
+    entries: [[f32; 3]; GAMMA_LUT_SIZE],
+}
+
+impl GammaLut {
+    /// Resample a client's gamma ramps, interpolating linearly between their entries
+    pub fn from_ramp(ramp: &GammaRamp) -> Self {
+        let channels = [&ramp.red, &ramp.green, &ramp.blue];
+        Self {
+            entries: std::array::from_fn(|i| {
+                let position = i as f32 / (GAMMA_LUT_SIZE - 1) as f32;
+                channels.map(|channel| sample_ramp(channel, position))
+            }),
+        }
+    }
+
+    /// Look up encoded values, as `apply_gamma_lut` in `color.glsl` does
+    pub fn apply(&self, encoded: [f32; 3]) -> [f32; 3] {
+        std::array::from_fn(|channel| {
+            let position = encoded[channel].clamp(0.0, 1.0) * (GAMMA_LUT_SIZE - 1) as f32;
+            let lower = position.floor() as usize;
+            let upper = (lower + 1).min(GAMMA_LUT_SIZE - 1);
+            let t = position - lower as f32;
+            let (a, b) = (self.entries[lower][channel], self.entries[upper][channel]);
+            a + (b - a) * t
+        })
+    }
+
+    /// The tables laid out for a std140 uniform block
+    pub fn to_uniform(&self) -> GammaLutUniform {
+        GammaLutUniform {
+            entries: self.entries.map(|[r, g, b]| [r, g, b, 0.0]),
+        }
+    }
+}
+
+/// Value of a ramp at `position` between 0 and 1
+fn sample_ramp(ramp: &[u16], position: f32) -> f32 {
+    let Some(last) = ramp.len().checked_sub(1) else {
+        return position;
+    };
+    let index = position * last as f32;
+    let lower = index.floor() as usize;
+    let upper = (lower + 1).min(last);
+    let t = index - lower as f32;
+    let value = ramp[lower] as f32 + (ramp[upper] as f32 - ramp[lower] as f32) * t;
+    value / u16::MAX as f32
+}
+
+/// GPU layout of a [`GammaLut`], matching `GammaLut` in `color.glsl`
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GammaLutUniform {
+    /// Red, green and blue entries, each padded to a vec4
+    pub entries: [[f32; 4]; GAMMA_LUT_SIZE],
+}
+
+/// Colour adjustments of one output
+#[derive(Clone, Debug, Default)]
+pub(crate) struct OutputNightLight {
+    pub adjustment: ColorAdjustment,
+    /// Tables of the client holding gamma control of the output
+    pub gamma_lut: Option<GammaLut>,
+}
+
+impl OutputNightLight {
+    /// Add the output's adjustment to its encode transform, unless client gamma tables
+    /// replace it
+    pub fn apply_to(&self, transform: ColorTransform, primaries: Primaries) -> ColorTransform {
+        match self.gamma_lut {
+            Some(_) => transform,
+            None => self.adjustment.apply_to(transform, primaries),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::{COLOR_GLSL, ColorPipeline, ColorSpace};
+    use crate::shader::compile_internal;
+    use naga::ShaderStage;
+
+    fn assert_close(a: [f32; 3], b: [f32; 3]) {
+        for i in 0..3 {
+            assert!((a[i] - b[i]).abs() < 1e-3, "{a:?} != {b:?}");
+        }
+    }
+
+    #[test]
+    fn color_glsl_compiles() {
+        let source = format!(
+            "#version 450\n{COLOR_GLSL}\n\
+             layout(set = 0, binding = 0) uniform Transform {{ ColorTransform transform; }};\n\
+             layout(set = 0, binding = 1) uniform Lut {{ GammaLut lut; }};\n\
+             layout(location = 0) out vec4 color;\n\
+             void main() {{ color = vec4(apply_gamma_lut(lut, \
+             apply_color_transform(transform, vec3(0.5))), 1.0); }}\n"
+        );
+        compile_internal(&source, ShaderStage::Fragment).unwrap();
+    }
+
+    #[test]
+    fn neutral_temperature_keeps_white() {
+        assert_close(
+            white_point_gains(NEUTRAL_TEMPERATURE, Primaries::Bt709),
+            [1.0; 3],
+        );
+    }
+
+    #[test]
+    fn low_temperatures_are_warm() {
+        let [r, g, b] = white_point_gains(3000, Primaries::Bt709);
+        assert_eq!(r, 1.0);
+        assert!(g < r && b < g);
+        let [_, warmer_g, warmer_b] = white_point_gains(2000, Primaries::Bt709);
+        assert!(warmer_g < g && warmer_b < b);
+    }
+
+    #[test]
+    fn high_temperatures_are_cool() {
+        let [r, _, b] = white_point_gains(10000, Primaries::Bt709);
+        assert_eq!(b, 1.0);
+        assert!(r < b);
+    }
+
+    #[test]
+    fn adjustment_changes_the_encode_transform() {
+        let transform = ColorPipeline::default().encode_transform(ColorSpace::SRGB);
+        let identity = ColorAdjustment::default().apply_to(transform, Primaries::Bt709);
+        assert_eq!(identity, transform);
+
+        let adjustment = ColorAdjustment {
+            temperature: 4000,
+            gamma: 1.0,
+            brightness: 0.5,
+        };
+        let adjusted = adjustment.apply_to(transform, Primaries::Bt709);
+        let white = adjusted.apply([1.0; 3]);
+        assert!(white[0] > white[2]);
+        assert!(white[0] <= 0.5 + 1e-3);
+    }
+
+    #[test]
+    fn gamma_curve_brightens_mid_tones() {
+        let curve = GammaCurve {
+            gamma: 2.0,
+            brightness: 1.0,
+        };
+        assert!((curve.apply(0.25) - 0.5).abs() < 1e-6);
+        assert_eq!(curve.apply(1.0), 1.0);
+    }
+
+    #[test]
+    fn identity_ramps_give_an_identity_lut() {
+        let lut = GammaLut::from_ramp(&GammaRamp::identity(1024));
+        assert_close(lut.apply([0.0, 0.3, 1.0]), [0.0, 0.3, 1.0]);
+    }
+
+    #[test]
+    fn short_ramps_are_interpolated() {
+        let ramp = GammaRamp {
+            red: vec![0, u16::MAX],
+            green: vec![u16::MAX, 0],
+            blue: vec![0, 0],
+        };
+        let lut = GammaLut::from_ramp(&ramp);
+        assert_close(lut.apply([0.25, 0.25, 0.25]), [0.25, 0.75, 0.0]);
+    }
+
+    #[test]
+    fn client_tables_replace_the_adjustment() {
+        let transform = ColorPipeline::default().encode_transform(ColorSpace::SRGB);
+        let night_light = OutputNightLight {
+            adjustment: ColorAdjustment {
+                temperature: 3000,
+                ..Default::default()
+            },
+            gamma_lut: Some(GammaLut::from_ramp(&GammaRamp::identity(256))),
+        };
+        assert_eq!(night_light.apply_to(transform, Primaries::Bt709), transform);
+    }
+}
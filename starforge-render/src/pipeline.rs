@@ -1,3 +1,313 @@
 //! Starforge Render - Vulkan Pipeline
 //!
 //! This module handles Vulkan render pipelines and shader loading
+
+use crate::core::Context;
+use ash::vk;
+use starforge_core::{StarforgeError, StarforgeResult};
+
+/// How a pipeline combines its output with the attachment
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BlendMode {
+    /// Overwrite the attachment
+    Replace,
+    /// Premultiplied alpha "over"
+    PremultipliedOver,
+}
+
+/// Bilinear sampler clamping to the edge of the texture
+pub fn create_linear_sampler(context: &Context) -> StarforgeResult<vk::Sampler> {
+    let sampler_info = vk::SamplerCreateInfo::default()
+        .mag_filter(vk::Filter::LINEAR)
+        .min_filter(vk::Filter::LINEAR)
+        .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+        .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+        .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE);
+    unsafe {
+        context
+            .device()
+            .create_sampler(&sampler_info, None)
+            .inspect(|&sampler| context.track(sampler))
+            .map_err(|e| StarforgeError::RendererError(e.to_string()))
+    }
+}
+
+/// Layout of a set sampling one texture: a sampled image at binding 0, its sampler at 1
+pub fn create_texture_set_layout(context: &Context) -> StarforgeResult<vk::DescriptorSetLayout> {
+    let bindings = [
+        vk::DescriptorSetLayoutBinding::default()
+            .binding(0)
+            .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+            .descriptor_count(1)
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT),
+        vk::DescriptorSetLayoutBinding::default()
+            .binding(1)
+            .descriptor_type(vk::DescriptorType::SAMPLER)
+            .descriptor_count(1)
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT),
+    ];
+    let layout_info = vk::DescriptorSetLayoutCreateInfo::default().bindings(&bindings);
+    unsafe {
+        context
+            .device()
+            .create_descriptor_set_layout(&layout_info, None)
+            .inspect(|&layout| context.track(layout))
+            .map_err(|e| StarforgeError::RendererError(e.to_string()))
+    }
+}
+
+/// A descriptor pool holding `count` sets of `layout`, which has to be a
+/// [`create_texture_set_layout`] layout
+pub fn create_texture_sets(
+    context: &Context,
+    layout: vk::DescriptorSetLayout,
+    count: u32,
+) -> StarforgeResult<(vk::DescriptorPool, Vec<vk::DescriptorSet>)> {
+    let pool_sizes = [
+        vk::DescriptorPoolSize {
+            ty: vk::DescriptorType::SAMPLED_IMAGE,
+            descriptor_count: count,
+        },
+        vk::DescriptorPoolSize {
+            ty: vk::DescriptorType::SAMPLER,
+            descriptor_count: count,
+        },
+    ];
+    let pool_info = vk::DescriptorPoolCreateInfo::default()
+        .max_sets(count)
+        .pool_sizes(&pool_sizes);
+    let device = context.device();
+    unsafe {
+        let descriptor_pool = device
+            .create_descriptor_pool(&pool_info, None)
+            .map_err(|e| StarforgeError::RendererError(e.to_string()))?;
+        let layouts = vec![layout; count as usize];
+        let allocate_info = vk::DescriptorSetAllocateInfo::default()
+            .descriptor_pool(descriptor_pool)
+            .set_layouts(&layouts);
+        match device.allocate_descriptor_sets(&allocate_info) {
+            Ok(sets) => {
+                context.track(descriptor_pool);
+                Ok((descriptor_pool, sets))
+            }
+            Err(e) => {
+                device.destroy_descriptor_pool(descriptor_pool, None);
+                Err(StarforgeError::RendererError(e.to_string()))
+            }
+        }
+    }
+}
+
+/// Point a set of [`create_texture_set_layout`] at `view`, sampled with `sampler`
+pub fn write_texture_set(
+    context: &Context,
+    set: vk::DescriptorSet,
+    view: vk::ImageView,
+    sampler: vk::Sampler,
+) {
+    let image_info = [vk::DescriptorImageInfo::default()
+        .image_view(view)
+        .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)];
+    let sampler_info = [vk::DescriptorImageInfo::default().sampler(sampler)];
+    let writes = [
+        vk::WriteDescriptorSet::default()
+            .dst_set(set)
+            .dst_binding(0)
+            .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+            .image_info(&image_info),
+        vk::WriteDescriptorSet::default()
+            .dst_set(set)
+            .dst_binding(1)
+            .descriptor_type(vk::DescriptorType::SAMPLER)
+            .image_info(&sampler_info),
+    ];
+    unsafe { context.device().update_descriptor_sets(&writes, &[]) };
+}
+
+/// Layout of a set holding one uniform buffer of `descriptor_type` at binding 0, read by
+/// the fragment stage
+pub fn create_uniform_set_layout(
+    context: &Context,
+    descriptor_type: vk::DescriptorType,
+) -> StarforgeResult<vk::DescriptorSetLayout> {
+    let bindings = [vk::DescriptorSetLayoutBinding::default()
+        .binding(0)
+        .descriptor_type(descriptor_type)
+        .descriptor_count(1)
+        .stage_flags(vk::ShaderStageFlags::FRAGMENT)];
+    let layout_info = vk::DescriptorSetLayoutCreateInfo::default().bindings(&bindings);
+    unsafe {
+        context
+            .device()
+            .create_descriptor_set_layout(&layout_info, None)
+            .inspect(|&layout| context.track(layout))
+            .map_err(|e| StarforgeError::RendererError(e.to_string()))
+    }
+}
+
+/// A descriptor pool holding one set of a [`create_uniform_set_layout`] layout, pointing at
+/// `range` bytes of `buffer`
+pub fn create_uniform_set(
+    context: &Context,
+    layout: vk::DescriptorSetLayout,
+    descriptor_type: vk::DescriptorType,
+    buffer: vk::Buffer,
+    range: vk::DeviceSize,
+) -> StarforgeResult<(vk::DescriptorPool, vk::DescriptorSet)> {
+    let pool_sizes = [vk::DescriptorPoolSize {
+        ty: descriptor_type,
+        descriptor_count: 1,
+    }];
+    let pool_info = vk::DescriptorPoolCreateInfo::default()
+        .max_sets(1)
+        .pool_sizes(&pool_sizes);
+    let device = context.device();
+    unsafe {
+        let descriptor_pool = device
+            .create_descriptor_pool(&pool_info, None)
+            .map_err(|e| StarforgeError::RendererError(e.to_string()))?;
+        let layouts = [layout];
+        let allocate_info = vk::DescriptorSetAllocateInfo::default()
+            .descriptor_pool(descriptor_pool)
+            .set_layouts(&layouts);
+        let set = match device.allocate_descriptor_sets(&allocate_info) {
+            Ok(sets) => sets[0],
+            Err(e) => {
+                device.destroy_descriptor_pool(descriptor_pool, None);
+                return Err(StarforgeError::RendererError(e.to_string()));
+            }
+        };
+        let buffer_info = [vk::DescriptorBufferInfo::default()
+            .buffer(buffer)
+            .offset(0)
+            .range(range)];
+        let writes = [vk::WriteDescriptorSet::default()
+            .dst_set(set)
+            .dst_binding(0)
+            .descriptor_type(descriptor_type)
+            .buffer_info(&buffer_info)];
+        device.update_descriptor_sets(&writes, &[]);
+        context.track(descriptor_pool);
+        Ok((descriptor_pool, set))
+    }
+}
+
+/// Pipeline layout with a single descriptor set and push constants of type `P`
+pub fn create_pipeline_layout<P>(
+    context: &Context,
+    set_layout: vk::DescriptorSetLayout,
+    push_constant_stages: vk::ShaderStageFlags,
+) -> StarforgeResult<vk::PipelineLayout> {
+    create_pipeline_layout_with_sets::<P>(context, &[set_layout], push_constant_stages)
+}
+
+/// Like [`create_pipeline_layout`], with set `i` laid out as `set_layouts[i]`
+///
+/// `P` can be `()` for pipelines without push constants.
+pub fn create_pipeline_layout_with_sets<P>(
+    context: &Context,
+    set_layouts: &[vk::DescriptorSetLayout],
+    push_constant_stages: vk::ShaderStageFlags,
+) -> StarforgeResult<vk::PipelineLayout> {
+    let push_constant_ranges = [vk::PushConstantRange::default()
+        .stage_flags(push_constant_stages)
+        .size(std::mem::size_of::<P>() as u32)];
+    let push_constant_ranges = match std::mem::size_of::<P>() {
+        0 => &[][..],
+        _ => &push_constant_ranges[..],
+    };
+    let layout_info = vk::PipelineLayoutCreateInfo::default()
+        .set_layouts(set_layouts)
+        .push_constant_ranges(push_constant_ranges);
+    unsafe {
+        context
+            .device()
+            .create_pipeline_layout(&layout_info, None)
+            .inspect(|&layout| context.track(layout))
+            .map_err(|e| StarforgeError::RendererError(e.to_string()))
+    }
+}
+
+/// Graphics pipeline drawing non-indexed triangles without vertex buffers into one color
+/// attachment of `format` with dynamic rendering. Viewport and scissor are dynamic.
+pub fn create_graphics_pipeline(
+    context: &Context,
+    layout: vk::PipelineLayout,
+    vertex_module: vk::ShaderModule,
+    fragment_module: vk::ShaderModule,
+    format: vk::Format,
+    blend: BlendMode,
+) -> StarforgeResult<vk::Pipeline> {
+    let stages = [
+        vk::PipelineShaderStageCreateInfo::default()
+            .stage(vk::ShaderStageFlags::VERTEX)
+            .module(vertex_module)
+            .name(c"main"),
+        vk::PipelineShaderStageCreateInfo::default()
+            .stage(vk::ShaderStageFlags::FRAGMENT)
+            .module(fragment_module)
+            .name(c"main"),
+    ];
+    let vertex_input = vk::PipelineVertexInputStateCreateInfo::default();
+    let input_assembly = vk::PipelineInputAssemblyStateCreateInfo::default()
+        .topology(vk::PrimitiveTopology::TRIANGLE_LIST);
+    let viewport = vk::PipelineViewportStateCreateInfo::default()
+        .viewport_count(1)
+        .scissor_count(1);
+    let rasterization = vk::PipelineRasterizationStateCreateInfo::default()
+        .polygon_mode(vk::PolygonMode::FILL)
+        .cull_mode(vk::CullModeFlags::NONE)
+        .line_width(1.0);
+    let multisample = vk::PipelineMultisampleStateCreateInfo::default()
+        .rasterization_samples(vk::SampleCountFlags::TYPE_1);
+    let attachment = vk::PipelineColorBlendAttachmentState::default()
+        .color_write_mask(vk::ColorComponentFlags::RGBA);
+    let attachment = match blend {
+        BlendMode::Replace => attachment,
+        BlendMode::PremultipliedOver => attachment
+            .blend_enable(true)
+            .src_color_blend_factor(vk::BlendFactor::ONE)
+            .dst_color_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
+            .color_blend_op(vk::BlendOp::ADD)
+            .src_alpha_blend_factor(vk::BlendFactor::ONE)
+            .dst_alpha_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
+            .alpha_blend_op(vk::BlendOp::ADD),
+    };
+    let attachments = [attachment];
+    let color_blend = vk::PipelineColorBlendStateCreateInfo::default().attachments(&attachments);
+    let dynamic_states = [vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
+    let dynamic = vk::PipelineDynamicStateCreateInfo::default().dynamic_states(&dynamic_states);
+    let formats = [format];
+    let mut rendering =
+        vk::PipelineRenderingCreateInfo::default().color_attachment_formats(&formats);
+
+    let create_info = vk::GraphicsPipelineCreateInfo::default()
+        .stages(&stages)
+        .vertex_input_state(&vertex_input)
+        .input_assembly_state(&input_assembly)
+        .viewport_state(&viewport)
+        .rasterization_state(&rasterization)
+        .multisample_state(&multisample)
+        .color_blend_state(&color_blend)
+        .dynamic_state(&dynamic)
+        .layout(layout)
+        .push_next(&mut rendering);
+
+    unsafe {
+        context
+            .device()
+            .create_graphics_pipelines(vk::PipelineCache::null(), &[create_info], None)
+            .map(|pipelines| pipelines[0])
+            .inspect(|&pipeline| context.track(pipeline))
+            .map_err(|(_, e)| StarforgeError::RendererError(e.to_string()))
+    }
+}
+
+/// View push constant data as bytes
+///
+/// `T` has to be a `#[repr(C)]` struct without padding.
+pub fn push_constant_bytes<T: Copy>(value: &T) -> &[u8] {
+    unsafe {
+        std::slice::from_raw_parts((value as *const T).cast::<u8>(), std::mem::size_of::<T>())
+    }
+}
@@ -0,0 +1,107 @@
+//! Starforge Render - Presentation feedback
+//!
+//! Tracks when the frames of each output reached the screen, for the presentation
+//! feedback sent to clients. Each output counts vblanks from its first frame: with a
+//! known refresh interval, the count advances by the number of refresh cycles between two
+//! presentations, so skipped vblanks show up as gaps in the sequence.
+
+use std::time::Duration;
+
+/// How a frame was presented, as reported to clients
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PresentFlags {
+    /// The frame was shown at a vblank, without tearing
+    pub vsync: bool,
+    /// The timestamp comes from the display hardware
+    pub hw_clock: bool,
+    /// The display hardware signalled that the frame is shown
+    pub hw_completion: bool,
+    /// Client buffers were scanned out without a copy
+    pub zero_copy: bool,
+}
+
+/// When and how a frame reached the screen
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PresentFeedback {
+    /// Presentation time on `CLOCK_MONOTONIC`
+    pub time: Duration,
+    /// Interval between vblanks of the output, if known
+    pub refresh: Option<Duration>,
+    /// Vblank counter of the output at the presentation
+    pub sequence: u64,
+    pub flags: PresentFlags,
+}
+
+/// Presentation history of one output
+#[derive(Debug, Default)]
+pub(crate) struct PresentTracker {
+    sequence: u64,
+    last: Option<Duration>,
+}
+
+impl PresentTracker {
+    /// Record a frame presented at `time`
+    pub fn presented(
+        &mut self,
+        time: Duration,
+        refresh: Option<Duration>,
+        flags: PresentFlags,
+    ) -> PresentFeedback {
+        let cycles = match (self.last, refresh) {
+            (Some(last), Some(refresh)) if !refresh.is_zero() && time > last => {
+                let elapsed = (time - last).as_secs_f64() / refresh.as_secs_f64();
+                (elapsed.round() as u64).max(1)
+            }
+            (Some(_), _) => 1,
+            (None, _) => 0,
+        };
+        self.sequence += cycles;
+        self.last = Some(time);
+        PresentFeedback {
+            time,
+            refresh,
+            sequence: self.sequence,
+            flags,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const REFRESH: Duration = Duration::from_micros(16_667);
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn sequence_counts_vblanks_between_presentations() {
+        let mut tracker = PresentTracker::default();
+        let flags = PresentFlags::default();
+        assert_eq!(tracker.presented(ms(100), Some(REFRESH), flags).sequence, 0);
+        assert_eq!(
+            tracker
+                .presented(ms(100) + REFRESH, Some(REFRESH), flags)
+                .sequence,
+            1
+        );
+        // Two vblanks went by without a frame
+        assert_eq!(
+            tracker
+                .presented(ms(100) + REFRESH * 4, Some(REFRESH), flags)
+                .sequence,
+            4
+        );
+    }
+
+    #[test]
+    fn sequence_counts_frames_without_a_refresh_interval() {
+        let mut tracker = PresentTracker::default();
+        let flags = PresentFlags::default();
+        tracker.presented(ms(10), None, flags);
+        tracker.presented(ms(70), None, flags);
+        assert_eq!(tracker.presented(ms(75), None, flags).sequence, 2);
+    }
+}
@@ -0,0 +1,453 @@
+//! Starforge Render - Frame Profiling
+//!
+//! This module measures how long frames take, on the CPU with timers around recording and
+//! on the GPU with timestamp queries around every pass and effect.
+//!
+//! Each output keeps a rolling window of recent frames, so percentiles describe the last
+//! few seconds rather than the whole session.
+
+use crate::core::Context;
+use ash::vk;
+use starforge_core::{StarforgeError, StarforgeResult};
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::warn;
+
+/// Number of frames kept per output
+pub const STATS_WINDOW: usize = 240;
+
+/// Most timestamp scopes recorded in one frame, including the frame itself
+pub const MAX_GPU_SCOPES: u32 = 64;
+
+/// Label of the scope covering a whole frame
+const FRAME_SCOPE: &str = "frame";
+
+/// The most recent samples of one measurement
+#[derive(Clone, Debug)]
+pub struct RollingWindow {
+    samples: VecDeque<Duration>,
+    capacity: usize,
+}
+
+impl RollingWindow {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            samples: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    /// Add a sample, dropping the oldest once the window is full
+    pub fn push(&mut self, sample: Duration) {
+        if self.samples.len() == self.capacity {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(sample);
+    }
+
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    pub fn latest(&self) -> Option<Duration> {
+        self.samples.back().copied()
+    }
+
+    /// Nearest-rank percentile, `percentile` in `[0, 100]`
+    pub fn percentile(&self, percentile: f64) -> Option<Duration> {
+        let mut sorted: Vec<_> = self.samples.iter().copied().collect();
+        sorted.sort_unstable();
+        nearest_rank(&sorted, percentile)
+    }
+
+    pub fn percentiles(&self) -> Option<Percentiles> {
+        let mut sorted: Vec<_> = self.samples.iter().copied().collect();
+        sorted.sort_unstable();
+        Some(Percentiles {
+            p50: nearest_rank(&sorted, 50.0)?,
+            p90: nearest_rank(&sorted, 90.0)?,
+            p99: nearest_rank(&sorted, 99.0)?,
+            max: *sorted.last()?,
+        })
+    }
+}
+
+fn nearest_rank(sorted: &[Duration], percentile: f64) -> Option<Duration> {
+    let rank = (percentile.clamp(0.0, 100.0) / 100.0 * sorted.len() as f64).ceil() as usize;
+    sorted.get(rank.max(1) - 1).copied()
+}
+
+/// Summary of a [`RollingWindow`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Percentiles {
+    pub p50: Duration,
+    pub p90: Duration,
+    pub p99: Duration,
+    pub max: Duration,
+}
+
+/// CPU-side timings of one frame
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FrameTimings {
+    /// Time spent recording command buffers
+    pub cpu_record: Duration,
+    /// Time spent waiting for a swapchain image
+    pub acquire_wait: Duration,
+}
+
+/// Rolling frame statistics of one output
+#[derive(Clone, Debug)]
+pub struct FrameStats {
+    cpu_record: RollingWindow,
+    gpu: RollingWindow,
+    acquire_wait: RollingWindow,
+    present_latency: RollingWindow,
+    passes: BTreeMap<String, RollingWindow>,
+}
+
+impl Default for FrameStats {
+    fn default() -> Self {
+        Self {
+            cpu_record: RollingWindow::new(STATS_WINDOW),
+            gpu: RollingWindow::new(STATS_WINDOW),
+            acquire_wait: RollingWindow::new(STATS_WINDOW),
+            present_latency: RollingWindow::new(STATS_WINDOW),
+            passes: BTreeMap::new(),
+        }
+    }
+}
+
+impl FrameStats {
+    pub fn add_frame_timings(&mut self, timings: FrameTimings) {
+        self.cpu_record.push(timings.cpu_record);
+        self.acquire_wait.push(timings.acquire_wait);
+    }
+
+    /// Add the time from submission to the frame being shown
+    pub fn add_present_latency(&mut self, latency: Duration) {
+        self.present_latency.push(latency);
+    }
+
+    /// Add the GPU scopes of one frame, as read back by [`GpuTimer::begin_frame`]
+    ///
+    /// Scopes sharing a label, like one per window, are summed into one sample.
+    pub fn add_gpu_timings(&mut self, timings: Vec<(String, Duration)>) {
+        let mut totals = BTreeMap::<String, Duration>::new();
+        for (label, duration) in timings {
+            *totals.entry(label).or_default() += duration;
+        }
+        for (label, duration) in totals {
+            if label == FRAME_SCOPE {
+                self.gpu.push(duration);
+            } else {
+                self.passes
+                    .entry(label)
+                    .or_insert_with(|| RollingWindow::new(STATS_WINDOW))
+                    .push(duration);
+            }
+        }
+    }
+
+    pub fn summary(&self) -> FrameStatsSummary {
+        FrameStatsSummary {
+            frames: self.cpu_record.len(),
+            cpu_record: self.cpu_record.percentiles(),
+            gpu: self.gpu.percentiles(),
+            acquire_wait: self.acquire_wait.percentiles(),
+            present_latency: self.present_latency.percentiles(),
+            passes: self
+                .passes
+                .iter()
+                .filter_map(|(label, window)| Some((label.clone(), window.percentiles()?)))
+                .collect(),
+        }
+    }
+}
+
+/// Percentiles of every measurement of an output
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FrameStatsSummary {
+    /// Number of frames the CPU timings cover
+    pub frames: usize,
+    pub cpu_record: Option<Percentiles>,
+    /// Whole frames on the GPU
+    pub gpu: Option<Percentiles>,
+    pub acquire_wait: Option<Percentiles>,
+    pub present_latency: Option<Percentiles>,
+    /// GPU time of each labelled pass and effect
+    pub passes: Vec<(String, Percentiles)>,
+}
+
+/// A timestamp scope opened with [`GpuTimer::begin_scope`]
+#[must_use]
+#[derive(Debug)]
+pub struct GpuScope {
+    query: u32,
+}
+
+/// Queries of one frame in flight
+#[derive(Default)]
+struct FrameQueries {
+    labels: Vec<(String, u32)>,
+    frame_scope: Option<u32>,
+    next_query: u32,
+}
+
+/// Timestamp queries of one output, with one range of queries per frame in flight
+pub struct GpuTimer {
+    context: Arc<Context>,
+    /// Null when the graphics queue can't write timestamps
+    query_pool: vk::QueryPool,
+    timestamp_period: f64,
+    valid_mask: u64,
+    frames: Vec<FrameQueries>,
+    current: usize,
+}
+
+impl GpuTimer {
+    pub fn new(context: Arc<Context>, frames_in_flight: usize) -> StarforgeResult<Self> {
+        let valid_bits = unsafe {
+            context
+                .instance()
+                .get_physical_device_queue_family_properties(context.physical_device())
+                .get(context.graphics_queue().family_index as usize)
+                .map_or(0, |family| family.timestamp_valid_bits)
+        };
+        let timestamp_period = context.physical_device_properties().limits.timestamp_period;
+        let frames = (0..frames_in_flight.max(1))
+            .map(|_| FrameQueries::default())
+            .collect::<Vec<_>>();
+
+        let query_pool = if valid_bits == 0 {
+            warn!("Graphics queue has no timestamp support, GPU timings disabled.");
+            vk::QueryPool::null()
+        } else {
+            let pool_info = vk::QueryPoolCreateInfo::default()
+                .query_type(vk::QueryType::TIMESTAMP)
+                .query_count(frames.len() as u32 * MAX_GPU_SCOPES * 2);
+            unsafe {
+                context
+                    .device()
+                    .create_query_pool(&pool_info, None)
+                    .inspect(|&pool| context.track(pool))
+                    .map_err(|e| StarforgeError::RendererError(e.to_string()))?
+            }
+        };
+
+        Ok(Self {
+            context,
+            query_pool,
+            timestamp_period: timestamp_period as f64,
+            valid_mask: if valid_bits >= 64 {
+                u64::MAX
+            } else {
+                (1 << valid_bits) - 1
+            },
+            frames,
+            current: 0,
+        })
+    }
+
+    fn first_query(&self, frame: usize) -> u32 {
+        frame as u32 * MAX_GPU_SCOPES * 2
+    }
+
+    /// Start timing frame `frame` (an index below the number of frames in flight) and
+    /// return the scopes recorded the last time that frame slot was used
+    ///
+    /// The previous submission using the slot has to be complete, which waiting on its
+    /// fence guarantees.
+    pub fn begin_frame(
+        &mut self,
+        command_buffer: vk::CommandBuffer,
+        frame: usize,
+    ) -> Vec<(String, Duration)> {
+        let frame = frame % self.frames.len();
+        self.current = frame;
+        if self.query_pool == vk::QueryPool::null() {
+            return Vec::new();
+        }
+
+        let results = self.read_results(frame);
+        let first_query = self.first_query(frame);
+        unsafe {
+            self.context.device().cmd_reset_query_pool(
+                command_buffer,
+                self.query_pool,
+                first_query,
+                MAX_GPU_SCOPES * 2,
+            );
+        }
+        self.frames[frame] = FrameQueries::default();
+        let scope = self.begin_scope(command_buffer, FRAME_SCOPE);
+        self.frames[frame].frame_scope = scope.map(|scope| scope.query);
+        results
+    }
+
+    /// Close the scope covering the whole frame
+    pub fn end_frame(&mut self, command_buffer: vk::CommandBuffer) {
+        if let Some(query) = self.frames[self.current].frame_scope.take() {
+            self.end_scope(command_buffer, GpuScope { query });
+        }
+    }
+
+    /// Start timing the commands recorded until [`Self::end_scope`], under `label`
+    ///
+    /// Returns `None` when timestamps are unsupported or the frame has run out of scopes.
+    pub fn begin_scope(
+        &mut self,
+        command_buffer: vk::CommandBuffer,
+        label: &str,
+    ) -> Option<GpuScope> {
+        if self.query_pool == vk::QueryPool::null() {
+            return None;
+        }
+        let first_query = self.first_query(self.current);
+        let queries = &mut self.frames[self.current];
+        if queries.next_query + 2 > MAX_GPU_SCOPES * 2 {
+            return None;
+        }
+        let query = first_query + queries.next_query;
+        queries.next_query += 2;
+        queries.labels.push((label.to_string(), query));
+        self.write_timestamp(command_buffer, query);
+        Some(GpuScope { query })
+    }
+
+    pub fn end_scope(&mut self, command_buffer: vk::CommandBuffer, scope: GpuScope) {
+        self.write_timestamp(command_buffer, scope.query + 1);
+    }
+
+    fn write_timestamp(&self, command_buffer: vk::CommandBuffer, query: u32) {
+        unsafe {
+            self.context.device().cmd_write_timestamp2(
+                command_buffer,
+                vk::PipelineStageFlags2::ALL_COMMANDS,
+                self.query_pool,
+                query,
+            );
+        }
+    }
+
+    fn read_results(&self, frame: usize) -> Vec<(String, Duration)> {
+        let queries = &self.frames[frame];
+        if queries.next_query == 0 {
+            return Vec::new();
+        }
+        let mut timestamps = vec![0u64; queries.next_query as usize];
+        let result = unsafe {
+            self.context.device().get_query_pool_results(
+                self.query_pool,
+                self.first_query(frame),
+                &mut timestamps,
+                vk::QueryResultFlags::TYPE_64,
+            )
+        };
+        if let Err(e) = result {
+            warn!("Failed to read GPU timestamps: {}", e);
+            return Vec::new();
+        }
+
+        let first_query = self.first_query(frame);
+        queries
+            .labels
+            .iter()
+            .map(|(label, query)| {
+                let index = (query - first_query) as usize;
+                let ticks = timestamps[index + 1].wrapping_sub(timestamps[index]) & self.valid_mask;
+                let nanos = ticks as f64 * self.timestamp_period;
+                (label.clone(), Duration::from_nanos(nanos as u64))
+            })
+            .collect()
+    }
+}
+
+impl Drop for GpuTimer {
+    fn drop(&mut self) {
+        unsafe {
+            self.context.untrack(self.query_pool);
+            self.context
+                .device()
+                .destroy_query_pool(self.query_pool, None);
+        }
+    }
+}
+
+/// GPU timer and statistics of one output
+pub struct OutputProfiler {
+    pub timer: GpuTimer,
+    pub stats: FrameStats,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn percentiles_use_nearest_rank() {
+        let mut window = RollingWindow::new(100);
+        for i in 1..=100 {
+            window.push(ms(i));
+        }
+        assert_eq!(
+            window.percentiles(),
+            Some(Percentiles {
+                p50: ms(50),
+                p90: ms(90),
+                p99: ms(99),
+                max: ms(100),
+            })
+        );
+        assert_eq!(window.percentile(0.0), Some(ms(1)));
+        assert_eq!(window.percentile(100.0), Some(ms(100)));
+    }
+
+    #[test]
+    fn rolling_window_drops_old_samples() {
+        let mut window = RollingWindow::new(3);
+        for i in [10, 1, 2, 3] {
+            window.push(ms(i));
+        }
+        assert_eq!(window.len(), 3);
+        assert_eq!(window.latest(), Some(ms(3)));
+        assert_eq!(window.percentiles().unwrap().max, ms(3));
+    }
+
+    #[test]
+    fn empty_windows_have_no_percentiles() {
+        let window = RollingWindow::new(3);
+        assert_eq!(window.percentiles(), None);
+        assert_eq!(window.percentile(50.0), None);
+    }
+
+    #[test]
+    fn gpu_timings_are_split_by_label() {
+        let mut stats = FrameStats::default();
+        stats.add_gpu_timings(vec![
+            (FRAME_SCOPE.to_string(), ms(4)),
+            ("blur".to_string(), ms(1)),
+            ("windows".to_string(), ms(2)),
+            ("windows".to_string(), ms(3)),
+        ]);
+        stats.add_frame_timings(FrameTimings {
+            cpu_record: ms(1),
+            acquire_wait: ms(0),
+        });
+        let summary = stats.summary();
+        assert_eq!(summary.frames, 1);
+        assert_eq!(summary.gpu.unwrap().p50, ms(4));
+        assert_eq!(summary.present_latency, None);
+        let labels: Vec<_> = summary.passes.iter().map(|(l, _)| l.as_str()).collect();
+        assert_eq!(labels, ["blur", "windows"]);
+        assert_eq!(summary.passes[1].1.max, ms(5));
+    }
+}
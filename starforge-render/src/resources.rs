@@ -1,3 +1,383 @@
 //! Starforge Render - Resource Management
 //!
 //! Higher level resource tracking (e.g. managing imported textures)
+//!
+//! Every client buffer turned into a texture is registered here together with the buffer
+//! it came from, so textures can be rebuilt on a new device after the old one was lost.
+//! YUV dma-bufs are converted into RGB here whenever they are acquired for a frame.
+
+use crate::core::Context;
+use crate::dmabuf::DmabufImage;
+use crate::memory::{AllocatedImage, MappedBuffer, image_barrier};
+use crate::yuv::{YuvConverter, YuvFormat, YuvImage};
+use ash::vk;
+use smithay::backend::allocator::Buffer;
+use smithay::backend::allocator::dmabuf::{Dmabuf, WeakDmabuf};
+use smithay::reexports::wayland_server::protocol::{wl_buffer::WlBuffer, wl_shm};
+use smithay::wayland::shm::{BufferData, with_buffer_contents};
+use starforge_core::protocols::color_representation::ColorRepresentationSurfaceCachedState;
+use starforge_core::{StarforgeError, StarforgeResult};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tracing::{info, warn};
+
+/// Handle of a client texture
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct TextureId(pub u64);
+
+/// Where the contents of a texture come from
+enum TextureSource {
+    /// Copied from a wl_shm buffer
+    Shm(WlBuffer),
+    /// Sampled directly from a dma-buf, until the client destroys it
+    Dmabuf(WeakDmabuf),
+}
+
+/// The image of a client texture
+pub enum TextureImage {
+    Shm(AllocatedImage),
+    Dmabuf(DmabufImage),
+    /// A YUV dma-buf, sampled through its RGB conversion
+    Yuv(YuvImage),
+}
+
+impl TextureImage {
+    pub fn view(&self) -> vk::ImageView {
+        match self {
+            Self::Shm(image) => image.view(),
+            Self::Dmabuf(image) => image.view(),
+            Self::Yuv(image) => image.view(),
+        }
+    }
+
+    /// Whether the image has to be acquired before and released after every use, see
+    /// [`ResourceManager::record_acquire`]
+    pub fn needs_acquire(&self) -> bool {
+        matches!(self, Self::Dmabuf(_) | Self::Yuv(_))
+    }
+}
+
+/// A texture and the client buffer it holds the contents of
+struct ClientTexture {
+    source: TextureSource,
+    image: TextureImage,
+    /// How YUV values map to RGB, as the surface showing the buffer asked for
+    representation: ColorRepresentationSurfaceCachedState,
+}
+
+/// Registry of every live client texture
+pub struct ResourceManager {
+    context: Arc<Context>,
+    textures: HashMap<TextureId, ClientTexture>,
+    /// Textures of dma-bufs, which are imported once and reused for every commit
+    dmabuf_textures: HashMap<WeakDmabuf, TextureId>,
+    /// Conversion pass of YUV textures, created with the first one. Declared after the
+    /// textures so they are dropped first.
+    yuv_converter: Option<YuvConverter>,
+    next_id: u64,
+}
+
+impl ResourceManager {
+    pub fn new(context: Arc<Context>) -> Self {
+        Self {
+            context,
+            textures: HashMap::new(),
+            dmabuf_textures: HashMap::new(),
+            yuv_converter: None,
+            next_id: 0,
+        }
+    }
+
+    fn insert(&mut self, source: TextureSource, image: TextureImage) -> TextureId {
+        let id = TextureId(self.next_id);
+        self.next_id += 1;
+        self.textures.insert(
+            id,
+            ClientTexture {
+                source,
+                image,
+                representation: Default::default(),
+            },
+        );
+        id
+    }
+
+    /// Upload the contents of a wl_shm buffer into a new texture
+    pub fn import_shm_buffer(&mut self, buffer: &WlBuffer) -> StarforgeResult<TextureId> {
+        let image = upload_shm_buffer(&self.context, buffer)?;
+        Ok(self.insert(TextureSource::Shm(buffer.clone()), TextureImage::Shm(image)))
+    }
+
+    /// Import a dma-buf as a texture, or return the texture it was imported as before
+    pub fn import_dma_buf(&mut self, dmabuf: &Dmabuf) -> StarforgeResult<TextureId> {
+        self.remove_destroyed_dmabufs();
+        if let Some(&id) = self.dmabuf_textures.get(&dmabuf.weak()) {
+            return Ok(id);
+        }
+        let image = self.import_dmabuf_image(dmabuf)?;
+        let id = self.insert(TextureSource::Dmabuf(dmabuf.weak()), image);
+        self.dmabuf_textures.insert(dmabuf.weak(), id);
+        Ok(id)
+    }
+
+    /// Import a dma-buf as an RGB image, or as the planes of a YUV image
+    fn import_dmabuf_image(&mut self, dmabuf: &Dmabuf) -> StarforgeResult<TextureImage> {
+        if YuvFormat::from_fourcc(dmabuf.format().code).is_none() {
+            return DmabufImage::import(self.context.clone(), dmabuf).map(TextureImage::Dmabuf);
+        }
+        if self.yuv_converter.is_none() {
+            self.yuv_converter = Some(YuvConverter::new(self.context.clone())?);
+        }
+        let converter = self.yuv_converter.as_ref().unwrap();
+        YuvImage::import(self.context.clone(), converter, dmabuf).map(TextureImage::Yuv)
+    }
+
+    /// Set how the YUV values of a texture map to RGB
+    ///
+    /// Takes effect the next time the texture is acquired; RGB textures ignore it.
+    pub fn set_color_representation(
+        &mut self,
+        id: TextureId,
+        representation: ColorRepresentationSurfaceCachedState,
+    ) -> StarforgeResult<()> {
+        let texture = self
+            .textures
+            .get_mut(&id)
+            .ok_or_else(|| StarforgeError::RendererError(format!("unknown texture {}", id.0)))?;
+        texture.representation = representation;
+        Ok(())
+    }
+
+    pub fn texture(&self, id: TextureId) -> Option<&TextureImage> {
+        self.textures.get(&id).map(|texture| &texture.image)
+    }
+
+    /// Take a texture over from the client before sampling it in `command_buffer`
+    ///
+    /// YUV textures are converted into RGB and handed back to the client right away, so
+    /// this has to be recorded outside of rendering. Textures that don't need acquiring
+    /// are left alone.
+    pub fn record_acquire(&mut self, id: TextureId, command_buffer: vk::CommandBuffer) {
+        let Some(texture) = self.textures.get_mut(&id) else {
+            return;
+        };
+        match &mut texture.image {
+            TextureImage::Shm(_) => {}
+            TextureImage::Dmabuf(image) => image.record_acquire(command_buffer),
+            TextureImage::Yuv(image) => {
+                if let Some(converter) = &self.yuv_converter {
+                    converter.record(command_buffer, image, &texture.representation);
+                }
+            }
+        }
+    }
+
+    /// Hand a texture back to the client after the last use in `command_buffer`
+    pub fn record_release(&self, id: TextureId, command_buffer: vk::CommandBuffer) {
+        // YUV planes went back to the client right after their conversion
+        if let Some(TextureImage::Dmabuf(image)) = self.texture(id) {
+            image.record_release(command_buffer);
+        }
+    }
+
+    /// Drop the textures of dma-bufs every client reference is gone of
+    fn remove_destroyed_dmabufs(&mut self) {
+        let textures = &mut self.textures;
+        self.dmabuf_textures.retain(|dmabuf, id| {
+            let alive = !dmabuf.is_gone();
+            if !alive {
+                textures.remove(id);
+            }
+            alive
+        });
+    }
+
+    /// Destroy a texture once the client buffer is no longer shown
+    pub fn schedule_release(&mut self, id: TextureId) -> StarforgeResult<()> {
+        let texture = self
+            .textures
+            .remove(&id)
+            .ok_or_else(|| StarforgeError::RendererError(format!("unknown texture {}", id.0)))?;
+        if let TextureSource::Dmabuf(dmabuf) = texture.source {
+            self.dmabuf_textures.remove(&dmabuf);
+        }
+        Ok(())
+    }
+
+    /// Rebuild every texture on `context` from its client buffer, keeping its id
+    ///
+    /// Textures whose buffers the client destroyed in the meantime are dropped; the next
+    /// commit of their surface imports a new one. Dma-bufs are imported again, they still
+    /// hold the client's contents.
+    pub fn recreate(&mut self, context: Arc<Context>) {
+        let sources: Vec<_> = self
+            .textures
+            .drain()
+            .map(|(id, texture)| (id, texture.source, texture.representation))
+            .collect();
+        self.dmabuf_textures.clear();
+        self.yuv_converter = None;
+        self.context = context;
+
+        for (id, source, representation) in sources {
+            let image = match &source {
+                TextureSource::Shm(buffer) => {
+                    upload_shm_buffer(&self.context, buffer).map(TextureImage::Shm)
+                }
+                TextureSource::Dmabuf(dmabuf) => match dmabuf.upgrade() {
+                    Some(dmabuf) => self.import_dmabuf_image(&dmabuf),
+                    None => continue,
+                },
+            };
+            match image {
+                Ok(image) => {
+                    if let TextureSource::Dmabuf(dmabuf) = &source {
+                        self.dmabuf_textures.insert(dmabuf.clone(), id);
+                    }
+                    self.textures.insert(
+                        id,
+                        ClientTexture {
+                            source,
+                            image,
+                            representation,
+                        },
+                    );
+                }
+                Err(e) => warn!("Dropping texture {} after device loss: {}", id.0, e),
+            }
+        }
+        info!("Re-uploaded {} client textures.", self.textures.len());
+    }
+}
+
+/// Vulkan format and swizzle of a wl_shm format
+fn shm_format(format: wl_shm::Format) -> Option<(vk::Format, vk::ComponentMapping)> {
+    let opaque = vk::ComponentMapping {
+        a: vk::ComponentSwizzle::ONE,
+        ..Default::default()
+    };
+    match format {
+        wl_shm::Format::Argb8888 => Some((vk::Format::B8G8R8A8_UNORM, Default::default())),
+        wl_shm::Format::Xrgb8888 => Some((vk::Format::B8G8R8A8_UNORM, opaque)),
+        wl_shm::Format::Abgr8888 => Some((vk::Format::R8G8B8A8_UNORM, Default::default())),
+        wl_shm::Format::Xbgr8888 => Some((vk::Format::R8G8B8A8_UNORM, opaque)),
+        _ => None,
+    }
+}
+
+/// Copy a wl_shm buffer into a new sampled image
+fn upload_shm_buffer(context: &Arc<Context>, buffer: &WlBuffer) -> StarforgeResult<AllocatedImage> {
+    let (staging, data) = with_buffer_contents(buffer, |ptr, len, data| {
+        let BufferData {
+            offset,
+            height,
+            stride,
+            ..
+        } = data;
+        let start = offset as usize;
+        let size = stride as usize * height as usize;
+        if start + size > len {
+            return Err(StarforgeError::RendererError(
+                "shm buffer exceeds its pool".into(),
+            ));
+        }
+        let mut staging = MappedBuffer::new(
+            context.clone(),
+            size as vk::DeviceSize,
+            vk::BufferUsageFlags::TRANSFER_SRC,
+        )?;
+        staging.write(unsafe { std::slice::from_raw_parts(ptr.add(start), size) })?;
+        Ok((staging, data))
+    })
+    .map_err(|e| StarforgeError::RendererError(e.to_string()))??;
+
+    let (format, components) = shm_format(data.format).ok_or_else(|| {
+        StarforgeError::RendererError(format!("unsupported shm format {:?}", data.format))
+    })?;
+    let extent = vk::Extent2D {
+        width: data.width as u32,
+        height: data.height as u32,
+    };
+    upload_image(
+        context,
+        &staging,
+        extent,
+        data.stride as u32 / 4,
+        format,
+        components,
+    )
+}
+
+/// Copy 4 byte per pixel rows of `row_length` pixels from `staging` into a new sampled image
+pub fn upload_image(
+    context: &Arc<Context>,
+    staging: &MappedBuffer,
+    extent: vk::Extent2D,
+    row_length: u32,
+    format: vk::Format,
+    components: vk::ComponentMapping,
+) -> StarforgeResult<AllocatedImage> {
+    let image = AllocatedImage::with_components(
+        context.clone(),
+        extent,
+        format,
+        vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED,
+        components,
+    )?;
+
+    context.submit_and_wait(|command_buffer| {
+        let device = context.device();
+        unsafe {
+            image_barrier(
+                device,
+                command_buffer,
+                image.image(),
+                vk::ImageLayout::UNDEFINED,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                (vk::PipelineStageFlags2::NONE, vk::AccessFlags2::NONE),
+                (
+                    vk::PipelineStageFlags2::COPY,
+                    vk::AccessFlags2::TRANSFER_WRITE,
+                ),
+            );
+            let region = vk::BufferImageCopy::default()
+                .buffer_row_length(row_length)
+                .image_subresource(vk::ImageSubresourceLayers {
+                    aspect_mask: vk::ImageAspectFlags::COLOR,
+                    mip_level: 0,
+                    base_array_layer: 0,
+                    layer_count: 1,
+                })
+                .image_extent(vk::Extent3D {
+                    width: extent.width,
+                    height: extent.height,
+                    depth: 1,
+                });
+            device.cmd_copy_buffer_to_image(
+                command_buffer,
+                staging.buffer(),
+                image.image(),
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &[region],
+            );
+            image_barrier(
+                device,
+                command_buffer,
+                image.image(),
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                (
+                    vk::PipelineStageFlags2::COPY,
+                    vk::AccessFlags2::TRANSFER_WRITE,
+                ),
+                (
+                    vk::PipelineStageFlags2::FRAGMENT_SHADER,
+                    vk::AccessFlags2::SHADER_SAMPLED_READ,
+                ),
+            );
+        }
+        Ok(())
+    })?;
+
+    Ok(image)
+}
@@ -0,0 +1,822 @@
+//! Starforge Render - Effect Shaders
+//!
+//! This module defines the effect shader ABI and compiles user effect shaders to SPIR-V.
+//!
+//! Effect shaders are fragment shaders written in GLSL or WGSL. The ABI declarations from
+//! `shaders/effect_abi.*` are inserted into every shader before it is compiled, so effects
+//! only contain their own logic. The compiled module is validated against the ABI and
+//! recompiled whenever its file changes. Diagnostics always refer to lines of the user's
+//! file, not of the combined source.
+
+use crate::core::Context;
+use crate::error::{ShaderDiagnostic, ShaderError};
+use ash::vk;
+use naga::{
+    AddressSpace, Binding, ImageClass, ImageDimension, Module, ScalarKind, ShaderStage, Span,
+    TypeInner, VectorSize,
+    valid::{Capabilities, ModuleInfo, ValidationFlags, Validator},
+};
+use starforge_core::StarforgeResult;
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+use tracing::{error, info};
+
+/// Version of the effect shader ABI
+pub const SHADER_ABI_VERSION: u32 = 1;
+
+/// Descriptor set holding every effect shader binding
+pub const EFFECT_DESCRIPTOR_SET: u32 = 0;
+/// Uniform buffer holding [`ShaderUniforms`]
+pub const UNIFORMS_BINDING: u32 = 0;
+/// The element being drawn
+pub const INPUT_TEXTURE_BINDING: u32 = 1;
+pub const INPUT_SAMPLER_BINDING: u32 = 2;
+/// Everything composited below the element so far
+pub const BACKDROP_TEXTURE_BINDING: u32 = 3;
+pub const BACKDROP_SAMPLER_BINDING: u32 = 4;
+
+/// GLSL declarations inserted after the `#version` line of GLSL effects
+const ABI_GLSL: &str = include_str!("../shaders/effect_abi.glsl");
+/// WGSL declarations prepended to WGSL effects
+const ABI_WGSL: &str = include_str!("../shaders/effect_abi.wgsl");
+/// Vertex stage shared by every effect
+const EFFECT_VERTEX_GLSL: &str = include_str!("../shaders/effect.vert");
+
+/// How often effect shader files are checked for changes
+const RELOAD_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Standard uniforms available to every effect shader, laid out for std140
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ShaderUniforms {
+    /// Seconds since the compositor started
+    pub time: f32,
+    /// Opacity of the element, to be multiplied into the output
+    pub opacity: f32,
+    /// Size of the output in physical pixels
+    pub output_size: [f32; 2],
+    /// Position and size of the element on the output in physical pixels
+    pub element_rect: [f32; 4],
+}
+
+/// The descriptor set layout bindings described by the ABI
+pub fn effect_descriptor_set_layout_bindings() -> [vk::DescriptorSetLayoutBinding<'static>; 5] {
+    let binding = |binding, descriptor_type, stage_flags| {
+        vk::DescriptorSetLayoutBinding::default()
+            .binding(binding)
+            .descriptor_type(descriptor_type)
+            .descriptor_count(1)
+            .stage_flags(stage_flags)
+    };
+    [
+        binding(
+            UNIFORMS_BINDING,
+            vk::DescriptorType::UNIFORM_BUFFER,
+            vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+        ),
+        binding(
+            INPUT_TEXTURE_BINDING,
+            vk::DescriptorType::SAMPLED_IMAGE,
+            vk::ShaderStageFlags::FRAGMENT,
+        ),
+        binding(
+            INPUT_SAMPLER_BINDING,
+            vk::DescriptorType::SAMPLER,
+            vk::ShaderStageFlags::FRAGMENT,
+        ),
+        binding(
+            BACKDROP_TEXTURE_BINDING,
+            vk::DescriptorType::SAMPLED_IMAGE,
+            vk::ShaderStageFlags::FRAGMENT,
+        ),
+        binding(
+            BACKDROP_SAMPLER_BINDING,
+            vk::DescriptorType::SAMPLER,
+            vk::ShaderStageFlags::FRAGMENT,
+        ),
+    ]
+}
+
+/// Source language of an effect shader
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ShaderLanguage {
+    Glsl,
+    Wgsl,
+}
+
+impl ShaderLanguage {
+    /// Pick the language from a file extension
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()? {
+            "glsl" | "frag" => Some(ShaderLanguage::Glsl),
+            "wgsl" => Some(ShaderLanguage::Wgsl),
+            _ => None,
+        }
+    }
+}
+
+/// A shader compiled to SPIR-V
+#[derive(Clone, Debug)]
+pub struct CompiledShader {
+    pub spirv: Vec<u32>,
+    pub entry_point: String,
+}
+
+/// Compile an effect shader and validate it against the ABI
+pub fn compile_effect(
+    source: &str,
+    language: ShaderLanguage,
+) -> Result<CompiledShader, ShaderError> {
+    let (assembled, map) = match language {
+        ShaderLanguage::Glsl => insert_glsl_abi(source),
+        ShaderLanguage::Wgsl => {
+            let map = SourceMap {
+                insert_after: 0,
+                inserted: line_count(ABI_WGSL),
+            };
+            (format!("{ABI_WGSL}{source}"), map)
+        }
+    };
+    let unit = SourceUnit {
+        source: &assembled,
+        map,
+    };
+
+    let module = unit.parse(language, ShaderStage::Fragment)?;
+    let info = unit.validate(&module, Capabilities::empty())?;
+    let entry_point = check_abi(&module, &unit)?;
+    let spirv = write_spirv(&module, &info, ShaderStage::Fragment, &entry_point)?;
+    Ok(CompiledShader { spirv, entry_point })
+}
+
+/// Compile the vertex stage shared by every effect
+pub fn compile_effect_vertex_stage() -> Result<CompiledShader, ShaderError> {
+    compile_internal(EFFECT_VERTEX_GLSL, ShaderStage::Vertex)
+}
+
+/// Compile one of the renderer's own GLSL shaders, which may use push constants
+pub(crate) fn compile_internal(
+    source: &str,
+    stage: ShaderStage,
+) -> Result<CompiledShader, ShaderError> {
+    let unit = SourceUnit {
+        source,
+        map: SourceMap::default(),
+    };
+    let module = unit.parse(ShaderLanguage::Glsl, stage)?;
+    let info = unit.validate(&module, Capabilities::PUSH_CONSTANT)?;
+    let entry_point = "main".to_string();
+    let spirv = write_spirv(&module, &info, stage, &entry_point)?;
+    Ok(CompiledShader { spirv, entry_point })
+}
+
+/// Where the ABI declarations were inserted into a user's source
+#[derive(Clone, Copy, Debug, Default)]
+struct SourceMap {
+    /// Last user line before the inserted lines
+    insert_after: u32,
+    /// Number of inserted lines
+    inserted: u32,
+}
+
+impl SourceMap {
+    /// Map a line of the combined source back to the user's file
+    fn user_line(&self, line: u32) -> Option<u32> {
+        if line <= self.insert_after {
+            Some(line)
+        } else if line <= self.insert_after + self.inserted {
+            None
+        } else {
+            Some(line - self.inserted)
+        }
+    }
+}
+
+/// Insert the GLSL ABI after the `#version` directive, adding one if the shader has none
+fn insert_glsl_abi(source: &str) -> (String, SourceMap) {
+    let version = source
+        .split_inclusive('\n')
+        .enumerate()
+        .scan(0, |offset, (index, line)| {
+            *offset += line.len();
+            Some((index, *offset, line))
+        })
+        .find(|(_, _, line)| line.trim_start().starts_with("#version"));
+
+    match version {
+        Some((index, end, _)) => {
+            let mut assembled = source[..end].to_string();
+            if !assembled.ends_with('\n') {
+                assembled.push('\n');
+            }
+            assembled.push_str(ABI_GLSL);
+            assembled.push_str(&source[end..]);
+            let map = SourceMap {
+                insert_after: index as u32 + 1,
+                inserted: line_count(ABI_GLSL),
+            };
+            (assembled, map)
+        }
+        None => {
+            let map = SourceMap {
+                insert_after: 0,
+                inserted: line_count(ABI_GLSL) + 1,
+            };
+            (format!("#version 450\n{ABI_GLSL}{source}"), map)
+        }
+    }
+}
+
+fn line_count(text: &str) -> u32 {
+    text.matches('\n').count() as u32
+}
+
+/// A combined source and the mapping back to the user's lines
+struct SourceUnit<'a> {
+    source: &'a str,
+    map: SourceMap,
+}
+
+impl SourceUnit<'_> {
+    fn parse(&self, language: ShaderLanguage, stage: ShaderStage) -> Result<Module, ShaderError> {
+        match language {
+            ShaderLanguage::Glsl => naga::front::glsl::Frontend::default()
+                .parse(&stage.into(), self.source)
+                .map_err(|errors| {
+                    ShaderError::Compile(
+                        errors
+                            .errors
+                            .iter()
+                            .map(|error| self.diagnostic(error.meta, error.kind.to_string()))
+                            .collect(),
+                    )
+                }),
+            ShaderLanguage::Wgsl => naga::front::wgsl::parse_str(self.source).map_err(|error| {
+                let span = error
+                    .labels()
+                    .next()
+                    .map_or(Span::UNDEFINED, |(span, _)| span);
+                ShaderError::Compile(vec![self.diagnostic(span, error.message().to_string())])
+            }),
+        }
+    }
+
+    fn validate(
+        &self,
+        module: &Module,
+        capabilities: Capabilities,
+    ) -> Result<ModuleInfo, ShaderError> {
+        Validator::new(ValidationFlags::all(), capabilities)
+            .validate(module)
+            .map_err(|error| {
+                let span = error
+                    .spans()
+                    .next()
+                    .map_or(Span::UNDEFINED, |&(span, _)| span);
+                // The top-level error only names the function, the cause is further down
+                let mut message = error.as_inner().to_string();
+                let mut cause = std::error::Error::source(error.as_inner());
+                while let Some(inner) = cause {
+                    message.push_str(": ");
+                    message.push_str(&inner.to_string());
+                    cause = inner.source();
+                }
+                ShaderError::Compile(vec![self.diagnostic(span, message)])
+            })
+    }
+
+    fn diagnostic(&self, span: Span, message: String) -> ShaderDiagnostic {
+        if !span.is_defined() {
+            return ShaderDiagnostic {
+                line: None,
+                column: 0,
+                message,
+            };
+        }
+        let location = span.location(self.source);
+        match self.map.user_line(location.line_number) {
+            Some(line) => ShaderDiagnostic {
+                line: Some(line),
+                column: location.line_position,
+                message,
+            },
+            None => ShaderDiagnostic {
+                line: None,
+                column: 0,
+                message: format!("{message} (in the effect ABI declarations)"),
+            },
+        }
+    }
+}
+
+/// Resource type of a binding declared by the ABI
+#[derive(Clone, Copy, PartialEq)]
+enum AbiResource {
+    Uniforms,
+    Texture,
+    Sampler,
+}
+
+fn abi_resource(binding: u32) -> Option<AbiResource> {
+    match binding {
+        UNIFORMS_BINDING => Some(AbiResource::Uniforms),
+        INPUT_TEXTURE_BINDING | BACKDROP_TEXTURE_BINDING => Some(AbiResource::Texture),
+        INPUT_SAMPLER_BINDING | BACKDROP_SAMPLER_BINDING => Some(AbiResource::Sampler),
+        _ => None,
+    }
+}
+
+/// Check resources and the entry point interface against the ABI, returning the entry point
+fn check_abi(module: &Module, unit: &SourceUnit) -> Result<String, ShaderError> {
+    let mut problems = Vec::new();
+
+    for (handle, global) in module.global_variables.iter() {
+        let span = module.global_variables.get_span(handle);
+        let name = global.name.as_deref().unwrap_or("<unnamed>");
+        let inner = &module.types[global.ty].inner;
+        match (global.space, &global.binding) {
+            (AddressSpace::Private | AddressSpace::WorkGroup, _) => {}
+            (_, Some(binding)) if binding.group == EFFECT_DESCRIPTOR_SET => {
+                let expected = abi_resource(binding.binding);
+                if expected.is_none() {
+                    problems.push(unit.diagnostic(
+                        span,
+                        format!("`{name}` uses binding {}, which is not part of the effect ABI", binding.binding),
+                    ));
+                } else if expected != resource_kind(module, global.space, inner) {
+                    problems.push(unit.diagnostic(
+                        span,
+                        format!("`{name}` does not match the type of ABI binding {}", binding.binding),
+                    ));
+                }
+            }
+            _ => problems.push(unit.diagnostic(
+                span,
+                format!(
+                    "`{name}` is not part of the effect ABI, effects may only use set {EFFECT_DESCRIPTOR_SET}"
+                ),
+            )),
+        }
+    }
+
+    let fragment_entry_points: Vec<_> = module
+        .entry_points
+        .iter()
+        .filter(|entry_point| entry_point.stage == ShaderStage::Fragment)
+        .collect();
+    let [entry_point] = fragment_entry_points[..] else {
+        problems.push(unit.diagnostic(
+            Span::UNDEFINED,
+            format!(
+                "expected exactly one fragment entry point, found {}",
+                fragment_entry_points.len()
+            ),
+        ));
+        return Err(ShaderError::Abi(problems));
+    };
+
+    // Inputs: only the texture coordinate at location 0, and any builtins
+    for argument in &entry_point.function.arguments {
+        for (binding, ty) in interface(module, argument.ty, argument.binding.as_ref()) {
+            if let Binding::Location { location, .. } = binding
+                && (location != 0 || !is_float_vector(module, ty, VectorSize::Bi))
+            {
+                problems.push(unit.diagnostic(
+                    Span::UNDEFINED,
+                    format!(
+                        "input at location {location} is not provided, the only input is `vec2 tex_coord` at location 0"
+                    ),
+                ));
+            }
+        }
+    }
+
+    // Outputs: a single color at location 0
+    let outputs = entry_point
+        .function
+        .result
+        .as_ref()
+        .map(|result| interface(module, result.ty, result.binding.as_ref()))
+        .unwrap_or_default();
+    let mut has_color = false;
+    for (binding, ty) in outputs {
+        if let Binding::Location { location, .. } = binding {
+            if location == 0 && is_float_vector(module, ty, VectorSize::Quad) {
+                has_color = true;
+            } else {
+                problems.push(unit.diagnostic(
+                    Span::UNDEFINED,
+                    format!("output at location {location} is not consumed, the only output is `vec4` at location 0"),
+                ));
+            }
+        }
+    }
+    if !has_color {
+        problems.push(unit.diagnostic(
+            Span::UNDEFINED,
+            "the entry point does not write a `vec4` color to location 0".to_string(),
+        ));
+    }
+
+    if problems.is_empty() {
+        Ok(entry_point.name.clone())
+    } else {
+        Err(ShaderError::Abi(problems))
+    }
+}
+
+fn resource_kind(module: &Module, space: AddressSpace, inner: &TypeInner) -> Option<AbiResource> {
+    match (space, inner) {
+        (AddressSpace::Uniform, TypeInner::Struct { members, .. }) => {
+            let layout: Vec<_> = members
+                .iter()
+                .map(|member| (member.offset, vector_size(module, member.ty)))
+                .collect();
+            let expected = [
+                (0, None),
+                (4, None),
+                (8, Some(VectorSize::Bi)),
+                (16, Some(VectorSize::Quad)),
+            ];
+            let matches = layout.len() == expected.len()
+                && members
+                    .iter()
+                    .zip(expected)
+                    .all(|(member, (offset, size))| {
+                        member.offset == offset
+                            && match size {
+                                Some(size) => is_float_vector(module, member.ty, size),
+                                None => matches!(
+                                    module.types[member.ty].inner,
+                                    TypeInner::Scalar(scalar) if scalar == naga::Scalar::F32
+                                ),
+                            }
+                    });
+            matches.then_some(AbiResource::Uniforms)
+        }
+        (
+            AddressSpace::Handle,
+            TypeInner::Image {
+                dim: ImageDimension::D2,
+                arrayed: false,
+                class:
+                    ImageClass::Sampled {
+                        kind: ScalarKind::Float,
+                        multi: false,
+                    },
+            },
+        ) => Some(AbiResource::Texture),
+        (AddressSpace::Handle, TypeInner::Sampler { comparison: false }) => {
+            Some(AbiResource::Sampler)
+        }
+        _ => None,
+    }
+}
+
+fn vector_size(module: &Module, ty: naga::Handle<naga::Type>) -> Option<VectorSize> {
+    match module.types[ty].inner {
+        TypeInner::Vector { size, .. } => Some(size),
+        _ => None,
+    }
+}
+
+fn is_float_vector(module: &Module, ty: naga::Handle<naga::Type>, size: VectorSize) -> bool {
+    matches!(
+        module.types[ty].inner,
+        TypeInner::Vector { size: s, scalar } if s == size && scalar == naga::Scalar::F32
+    )
+}
+
+/// Flatten an entry point argument or result into its bindings
+fn interface(
+    module: &Module,
+    ty: naga::Handle<naga::Type>,
+    binding: Option<&Binding>,
+) -> Vec<(Binding, naga::Handle<naga::Type>)> {
+    match (binding, &module.types[ty].inner) {
+        (Some(binding), _) => vec![(binding.clone(), ty)],
+        (None, TypeInner::Struct { members, .. }) => members
+            .iter()
+            .filter_map(|member| Some((member.binding.clone()?, member.ty)))
+            .collect(),
+        (None, _) => Vec::new(),
+    }
+}
+
+fn write_spirv(
+    module: &Module,
+    info: &ModuleInfo,
+    stage: ShaderStage,
+    entry_point: &str,
+) -> Result<Vec<u32>, ShaderError> {
+    let mut options = naga::back::spv::Options::default();
+    // Sources are written for Vulkan's coordinate conventions already
+    options
+        .flags
+        .remove(naga::back::spv::WriterFlags::ADJUST_COORDINATE_SPACE);
+    let pipeline_options = naga::back::spv::PipelineOptions {
+        shader_stage: stage,
+        entry_point: entry_point.to_string(),
+    };
+    naga::back::spv::write_vec(module, info, &options, Some(&pipeline_options)).map_err(|error| {
+        ShaderError::Compile(vec![ShaderDiagnostic {
+            line: None,
+            column: 0,
+            message: error.to_string(),
+        }])
+    })
+}
+
+/// Identifier of a loaded effect shader
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct EffectId(pub u32);
+
+/// An effect shader loaded from a file
+pub struct EffectShader {
+    path: PathBuf,
+    language: ShaderLanguage,
+    modified: Option<SystemTime>,
+    module: vk::ShaderModule,
+    entry_point: CString,
+    generation: u32,
+}
+
+impl EffectShader {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn module(&self) -> vk::ShaderModule {
+        self.module
+    }
+
+    pub fn entry_point(&self) -> &CString {
+        &self.entry_point
+    }
+
+    /// Incremented on every successful reload, so pipelines know to rebuild
+    pub fn generation(&self) -> u32 {
+        self.generation
+    }
+}
+
+/// Owns the shader modules of the effect vertex stage and every loaded effect
+pub struct ShaderManager {
+    context: Arc<Context>,
+    vertex_module: vk::ShaderModule,
+    effects: HashMap<EffectId, EffectShader>,
+    next_id: u32,
+    last_poll: Instant,
+}
+
+impl ShaderManager {
+    pub fn new(context: Arc<Context>) -> StarforgeResult<Self> {
+        let vertex = compile_effect_vertex_stage()?;
+        let vertex_module = create_shader_module(&context, &vertex.spirv)?;
+        Ok(Self {
+            context,
+            vertex_module,
+            effects: HashMap::new(),
+            next_id: 0,
+            last_poll: Instant::now(),
+        })
+    }
+
+    /// Build the same effects, under the same ids, on a new context after the device was
+    /// lost. Effects that no longer compile are dropped.
+    pub fn recreate(&self, context: Arc<Context>) -> StarforgeResult<Self> {
+        let mut manager = Self::new(context)?;
+        manager.next_id = self.next_id;
+        for (&id, effect) in &self.effects {
+            match manager.build(&effect.path, effect.language) {
+                Ok((module, entry_point)) => {
+                    manager.effects.insert(
+                        id,
+                        EffectShader {
+                            path: effect.path.clone(),
+                            language: effect.language,
+                            modified: modified_time(&effect.path),
+                            module,
+                            entry_point,
+                            generation: effect.generation + 1,
+                        },
+                    );
+                }
+                Err(e) => error!("Dropping effect shader {}: {}", effect.path.display(), e),
+            }
+        }
+        Ok(manager)
+    }
+
+    /// Load and compile an effect shader from a file
+    pub fn load(&mut self, path: &Path) -> Result<EffectId, ShaderError> {
+        let language = ShaderLanguage::from_path(path)
+            .ok_or_else(|| ShaderError::UnknownLanguage(path.to_path_buf()))?;
+        let modified = modified_time(path);
+        let (module, entry_point) = self.build(path, language)?;
+
+        let id = EffectId(self.next_id);
+        self.next_id += 1;
+        self.effects.insert(
+            id,
+            EffectShader {
+                path: path.to_path_buf(),
+                language,
+                modified,
+                module,
+                entry_point,
+                generation: 0,
+            },
+        );
+        info!("Loaded effect shader {}", path.display());
+        Ok(id)
+    }
+
+    /// Unload an effect shader, destroying its module
+    pub fn unload(&mut self, id: EffectId) {
+        if let Some(effect) = self.effects.remove(&id) {
+            unsafe {
+                self.context.untrack(effect.module);
+                self.context
+                    .device()
+                    .destroy_shader_module(effect.module, None);
+            }
+        }
+    }
+
+    /// Recompile effects whose files changed since they were last compiled.
+    ///
+    /// Polls at most every [`RELOAD_POLL_INTERVAL`]. An effect that fails to compile keeps
+    /// its previous module and the error is logged. Returns the effects that were replaced.
+    pub fn reload_changed(&mut self) -> Vec<EffectId> {
+        if self.last_poll.elapsed() < RELOAD_POLL_INTERVAL {
+            return Vec::new();
+        }
+        self.last_poll = Instant::now();
+
+        let changed: Vec<_> = self
+            .effects
+            .iter()
+            .filter_map(|(&id, effect)| {
+                let modified = modified_time(&effect.path);
+                (modified.is_some() && modified != effect.modified).then_some((id, modified))
+            })
+            .collect();
+
+        let mut reloaded = Vec::new();
+        for (id, modified) in changed {
+            let (path, language) = {
+                let effect = &self.effects[&id];
+                (effect.path.clone(), effect.language)
+            };
+            let result = self.build(&path, language);
+            let effect = self.effects.get_mut(&id).unwrap();
+            // Remember the timestamp either way so a broken file is only reported once
+            effect.modified = modified;
+            match result {
+                Ok((module, entry_point)) => {
+                    unsafe {
+                        self.context.untrack(effect.module);
+                        self.context
+                            .device()
+                            .destroy_shader_module(effect.module, None);
+                    }
+                    effect.module = module;
+                    effect.entry_point = entry_point;
+                    effect.generation += 1;
+                    info!("Reloaded effect shader {}", path.display());
+                    reloaded.push(id);
+                }
+                Err(e) => error!("Keeping previous version of {}: {}", path.display(), e),
+            }
+        }
+        reloaded
+    }
+
+    fn build(
+        &self,
+        path: &Path,
+        language: ShaderLanguage,
+    ) -> Result<(vk::ShaderModule, CString), ShaderError> {
+        let source =
+            std::fs::read_to_string(path).map_err(|e| ShaderError::Io(path.to_path_buf(), e))?;
+        let compiled = compile_effect(&source, language)?;
+        let module = create_shader_module(&self.context, &compiled.spirv)?;
+        let entry_point =
+            CString::new(compiled.entry_point).expect("entry point names contain no nul bytes");
+        Ok((module, entry_point))
+    }
+}
+
+impl Drop for ShaderManager {
+    fn drop(&mut self) {
+        unsafe {
+            let device = self.context.device();
+            for effect in self.effects.values() {
+                self.context.untrack(effect.module);
+                device.destroy_shader_module(effect.module, None);
+            }
+            self.context.untrack(self.vertex_module);
+            device.destroy_shader_module(self.vertex_module, None);
+        }
+    }
+}
+
+pub(crate) fn create_shader_module(
+    context: &Context,
+    spirv: &[u32],
+) -> Result<vk::ShaderModule, ShaderError> {
+    let create_info = vk::ShaderModuleCreateInfo::default().code(spirv);
+    unsafe { context.device().create_shader_module(&create_info, None) }
+        .inspect(|&module| context.track(module))
+        .map_err(ShaderError::Vulkan)
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GLSL_EFFECT: &str = "#version 450
+
+void main() {
+    vec4 color = texture(sampler2D(input_texture, input_sampler), tex_coord);
+    frag_color = color * starforge.opacity;
+}
+";
+
+    const WGSL_EFFECT: &str = "@fragment
+fn main(@location(0) tex_coord: vec2<f32>) -> @location(0) vec4<f32> {
+    let backdrop = textureSample(backdrop_texture, backdrop_sampler, tex_coord);
+    return backdrop * starforge.opacity;
+}
+";
+
+    #[test]
+    fn glsl_effect_compiles() {
+        let compiled = compile_effect(GLSL_EFFECT, ShaderLanguage::Glsl).unwrap();
+        assert_eq!(compiled.spirv[0], 0x0723_0203);
+        assert_eq!(compiled.entry_point, "main");
+    }
+
+    #[test]
+    fn glsl_effect_without_version_compiles() {
+        let source = GLSL_EFFECT.trim_start_matches("#version 450\n");
+        compile_effect(source, ShaderLanguage::Glsl).unwrap();
+    }
+
+    #[test]
+    fn wgsl_effect_compiles() {
+        let compiled = compile_effect(WGSL_EFFECT, ShaderLanguage::Wgsl).unwrap();
+        assert_eq!(compiled.spirv[0], 0x0723_0203);
+    }
+
+    #[test]
+    fn vertex_stage_compiles() {
+        compile_effect_vertex_stage().unwrap();
+    }
+
+    #[test]
+    fn glsl_errors_report_user_lines() {
+        let source = "#version 450\n\nvoid main() {\n    frag_color = undefined_value;\n}\n";
+        let error = compile_effect(source, ShaderLanguage::Glsl).unwrap_err();
+        let diagnostic = &error.diagnostics()[0];
+        assert_eq!(diagnostic.line, Some(4));
+    }
+
+    #[test]
+    fn wgsl_errors_report_user_lines() {
+        let source =
+            "@fragment\nfn main() -> @location(0) vec4<f32> {\n    return vec4<f32>(1.0)\n}\n";
+        let error = compile_effect(source, ShaderLanguage::Wgsl).unwrap_err();
+        let diagnostic = &error.diagnostics()[0];
+        assert!(matches!(diagnostic.line, Some(3 | 4)), "{diagnostic}");
+    }
+
+    #[test]
+    fn extra_bindings_violate_the_abi() {
+        let source = "@group(0) @binding(7) var<uniform> extra: vec4<f32>;
+
+@fragment
+fn main() -> @location(0) vec4<f32> {
+    return extra;
+}
+";
+        let error = compile_effect(source, ShaderLanguage::Wgsl).unwrap_err();
+        assert!(matches!(error, ShaderError::Abi(_)));
+        assert_eq!(error.diagnostics()[0].line, Some(1));
+    }
+
+    #[test]
+    fn missing_color_output_violates_the_abi() {
+        let source = "@fragment\nfn main(@location(0) tex_coord: vec2<f32>) {}\n";
+        let error = compile_effect(source, ShaderLanguage::Wgsl).unwrap_err();
+        assert!(matches!(error, ShaderError::Abi(_)));
+    }
+}
@@ -0,0 +1,643 @@
+//! Starforge Render - Window Shapes
+//!
+//! This module draws client surfaces with rounded corners, borders and drop shadows.
+//!
+//! Everything is derived from the signed distance to the window's rounded rectangle, so
+//! edges are antialiased at any radius. Borders grow outwards from the surface geometry,
+//! and shadows approximate a Gaussian blur of the outer shape with the error function.
+//!
+//! Surfaces are sampled bilinearly from the part of their texture a viewport crops out.
+//! Buffers rendered at the output's scale onto a pixel-aligned rectangle land on texel
+//! centres and come out unfiltered; scaled buffers are interpolated smoothly.
+//!
+//! Every draw decodes the texture, border and shadow colors with a [`ColorTransform`] into
+//! the space of the attachment, the linear working space when compositing an output. The
+//! transforms sit in a uniform buffer bound with a dynamic offset, one slot per distinct
+//! transform since the last [`ShapeRenderer::reset_decodes`].
+
+use crate::color::{ColorTransform, ColorTransformUniform, with_color_glsl};
+use crate::core::Context;
+use crate::memory::MappedBuffer;
+use crate::pipeline::{
+    BlendMode, create_graphics_pipeline, create_linear_sampler, create_pipeline_layout_with_sets,
+    create_texture_set_layout, create_uniform_set, create_uniform_set_layout, push_constant_bytes,
+};
+use crate::shader::{compile_internal, create_shader_module};
+use ash::vk;
+use naga::ShaderStage;
+use smithay::utils::{Physical, Point, Rectangle, Size};
+use starforge_core::{StarforgeError, StarforgeResult};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+const SHAPE_VERT: &str = include_str!("../shaders/shape.vert");
+const SHAPE_FRAG: &str = include_str!("../shaders/shape.frag");
+
+/// Texture coordinates covering a whole texture, as x, y, width and height
+pub const FULL_TEXTURE: [f32; 4] = [0.0, 0.0, 1.0, 1.0];
+
+/// Distinct decode transforms a submission can use, a handful of colour spaces at most
+const MAX_DECODES: usize = 64;
+
+/// Radii of the four corners of a window, in pixels
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct CornerRadii {
+    pub top_left: f32,
+    pub top_right: f32,
+    pub bottom_right: f32,
+    pub bottom_left: f32,
+}
+
+impl CornerRadii {
+    pub const ZERO: Self = Self::uniform(0.0);
+
+    pub const fn uniform(radius: f32) -> Self {
+        Self {
+            top_left: radius,
+            top_right: radius,
+            bottom_right: radius,
+            bottom_left: radius,
+        }
+    }
+
+    /// Limit every radius to half the shorter side, so corners never overlap
+    pub fn clamped(self, width: f32, height: f32) -> Self {
+        let limit = width.min(height).max(0.0) / 2.0;
+        let clamp = |r: f32| r.clamp(0.0, limit);
+        Self {
+            top_left: clamp(self.top_left),
+            top_right: clamp(self.top_right),
+            bottom_right: clamp(self.bottom_right),
+            bottom_left: clamp(self.bottom_left),
+        }
+    }
+
+    fn to_array(self) -> [f32; 4] {
+        [
+            self.top_left,
+            self.top_right,
+            self.bottom_right,
+            self.bottom_left,
+        ]
+    }
+}
+
+/// A solid border around a window
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Border {
+    /// Width in pixels, drawn outside the surface geometry
+    pub width: f32,
+    /// Straight-alpha RGBA color
+    pub color: [f32; 4],
+}
+
+/// A drop shadow below a window
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Shadow {
+    /// Offset of the shadow from the window in pixels
+    pub offset: [f32; 2],
+    /// Blur radius in pixels, twice the standard deviation of the Gaussian
+    pub radius: f32,
+    /// Distance the shadow shape is grown by before blurring
+    pub spread: f32,
+    /// Straight-alpha RGBA color
+    pub color: [f32; 4],
+}
+
+impl Shadow {
+    fn sigma(&self) -> f32 {
+        self.radius.max(0.0) / 2.0
+    }
+}
+
+/// Per-window shape parameters
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct WindowShape {
+    pub corner_radii: CornerRadii,
+    pub border: Option<Border>,
+    pub shadow: Option<Shadow>,
+}
+
+impl WindowShape {
+    /// Whether the surface is drawn as a plain rectangle
+    pub fn is_plain(&self) -> bool {
+        self.corner_radii == CornerRadii::ZERO && self.border.is_none() && self.shadow.is_none()
+    }
+
+    fn border_width(&self) -> f32 {
+        self.border.map_or(0.0, |border| border.width.max(0.0))
+    }
+
+    /// The area drawn for a window with geometry `rect`, including border and shadow
+    pub fn visual_bounds<Kind>(&self, rect: Rectangle<i32, Kind>) -> Rectangle<i32, Kind> {
+        let border = self.border_width().ceil() as i32;
+        let mut bounds = grow(rect, border);
+        if let Some(shadow) = self.shadow {
+            // Three standard deviations hold all but a fraction of a percent of the blur
+            let extent = (self.border_width() + shadow.spread + shadow.sigma() * 3.0).ceil();
+            let mut shadow_rect = grow(rect, extent.max(0.0) as i32);
+            shadow_rect.loc += Point::from((
+                shadow.offset[0].round() as i32,
+                shadow.offset[1].round() as i32,
+            ));
+            bounds = bounds.merge(shadow_rect);
+        }
+        bounds
+    }
+
+    /// Shape of the blurred backdrop of a window with geometry `rect`, of which `region`
+    /// is blurred
+    ///
+    /// The backdrop follows the window's corners, except where `region` was cut off by
+    /// the edge of the output, and has no border or shadow of its own.
+    pub fn backdrop<Kind>(&self, rect: Rectangle<i32, Kind>, region: Rectangle<i32, Kind>) -> Self {
+        let left = region.loc.x > rect.loc.x;
+        let top = region.loc.y > rect.loc.y;
+        let right = region.loc.x + region.size.w < rect.loc.x + rect.size.w;
+        let bottom = region.loc.y + region.size.h < rect.loc.y + rect.size.h;
+        let keep = |clipped: bool, radius: f32| if clipped { 0.0 } else { radius };
+        Self {
+            corner_radii: CornerRadii {
+                top_left: keep(top || left, self.corner_radii.top_left),
+                top_right: keep(top || right, self.corner_radii.top_right),
+                bottom_right: keep(bottom || right, self.corner_radii.bottom_right),
+                bottom_left: keep(bottom || left, self.corner_radii.bottom_left),
+            },
+            ..Default::default()
+        }
+    }
+
+    /// The client's opaque region minus the corners that rounding makes transparent
+    pub fn opaque_region<Kind>(
+        &self,
+        size: Size<i32, Kind>,
+        opaque: &[Rectangle<i32, Kind>],
+    ) -> Vec<Rectangle<i32, Kind>> {
+        let radii = self.corner_radii.clamped(size.w as f32, size.h as f32);
+        let corner = |x: i32, y: i32, radius: f32| {
+            let r = radius.ceil() as i32;
+            let loc = Point::from((
+                if x == 0 { 0 } else { size.w - r },
+                if y == 0 { 0 } else { size.h - r },
+            ));
+            Rectangle::new(loc, Size::from((r, r)))
+        };
+        let corners = [
+            corner(0, 0, radii.top_left),
+            corner(1, 0, radii.top_right),
+            corner(1, 1, radii.bottom_right),
+            corner(0, 1, radii.bottom_left),
+        ]
+        .into_iter()
+        .filter(|corner| !corner.is_empty());
+        Rectangle::subtract_rects_many(opaque.iter().copied(), corners)
+    }
+}
+
+fn grow<Kind>(rect: Rectangle<i32, Kind>, amount: i32) -> Rectangle<i32, Kind> {
+    Rectangle::new(
+        rect.loc - Point::from((amount, amount)),
+        rect.size + Size::from((amount * 2, amount * 2)),
+    )
+}
+
+/// Signed distance from `point` (relative to the centre) to a rounded rectangle with the
+/// given half size. Negative inside, mirroring `rounded_rect_sdf` in shape.frag.
+pub fn rounded_rect_sdf(point: [f32; 2], half_size: [f32; 2], radii: CornerRadii) -> f32 {
+    let [x, y] = point;
+    let r = match (x < 0.0, y < 0.0) {
+        (true, true) => radii.top_left,
+        (false, true) => radii.top_right,
+        (false, false) => radii.bottom_right,
+        (true, false) => radii.bottom_left,
+    };
+    let qx = x.abs() - half_size[0] + r;
+    let qy = y.abs() - half_size[1] + r;
+    let outside = qx.max(0.0).hypot(qy.max(0.0));
+    qx.max(qy).min(0.0) + outside - r
+}
+
+/// Coverage of a Gaussian-blurred edge at signed distance `distance`
+pub fn shadow_alpha(distance: f32, sigma: f32) -> f32 {
+    if sigma <= 0.0 {
+        return (0.5 - distance).clamp(0.0, 1.0);
+    }
+    0.5 - 0.5 * erf(distance / (sigma * std::f32::consts::SQRT_2))
+}
+
+/// Abramowitz and Stegun approximation of the error function, as used by shape.frag
+fn erf(x: f32) -> f32 {
+    let a = x.abs();
+    let t = 1.0 + (0.278393 + (0.230389 + 0.078108 * (a * a)) * a) * a;
+    let t = t * t;
+    x.signum() * (1.0 - 1.0 / (t * t))
+}
+
+fn premultiply([r, g, b, a]: [f32; 4]) -> [f32; 4] {
+    [r * a, g * a, b * a, a]
+}
+
+/// Push constants of the shape shaders, matching `ShapePushConstants` in shape.*
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct ShapePushConstants {
+    bounds: [f32; 4],
+    rect: [f32; 4],
+    source: [f32; 4],
+    radii: [f32; 4],
+    border_color: [f32; 4],
+    shadow_color: [f32; 4],
+    output_size: [f32; 2],
+    shadow_offset: [f32; 2],
+    border_width: f32,
+    shadow_sigma: f32,
+    shadow_spread: f32,
+    opacity: f32,
+}
+
+/// Records client surfaces drawn with a [`WindowShape`]
+pub struct ShapeRenderer {
+    context: Arc<Context>,
+    vertex_module: vk::ShaderModule,
+    fragment_module: vk::ShaderModule,
+    texture_set_layout: vk::DescriptorSetLayout,
+    pipeline_layout: vk::PipelineLayout,
+    pipelines: HashMap<vk::Format, vk::Pipeline>,
+    /// Bilinear sampler for surface textures
+    sampler: vk::Sampler,
+    decode_set_layout: vk::DescriptorSetLayout,
+    decode_pool: vk::DescriptorPool,
+    decode_set: vk::DescriptorSet,
+    /// [`MAX_DECODES`] transform slots, `decode_stride` bytes apart
+    decode_buffer: MappedBuffer,
+    decode_stride: vk::DeviceSize,
+    /// Transforms written to the slots since the last reset, in slot order
+    decodes: Vec<ColorTransformUniform>,
+}
+
+impl ShapeRenderer {
+    pub fn new(context: Arc<Context>) -> StarforgeResult<Self> {
+        let vertex = compile_internal(SHAPE_VERT, ShaderStage::Vertex)?;
+        let fragment = compile_internal(&with_color_glsl(SHAPE_FRAG), ShaderStage::Fragment)?;
+        let vertex_module = create_shader_module(&context, &vertex.spirv)?;
+        let fragment_module = create_shader_module(&context, &fragment.spirv)?;
+        let texture_set_layout = create_texture_set_layout(&context)?;
+        let decode_set_layout =
+            create_uniform_set_layout(&context, vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC)?;
+        let pipeline_layout = create_pipeline_layout_with_sets::<ShapePushConstants>(
+            &context,
+            &[texture_set_layout, decode_set_layout],
+            vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+        )?;
+        let sampler = create_linear_sampler(&context)?;
+
+        let alignment = context
+            .physical_device_properties()
+            .limits
+            .min_uniform_buffer_offset_alignment
+            .max(1);
+        let decode_size = std::mem::size_of::<ColorTransformUniform>() as vk::DeviceSize;
+        let decode_stride = decode_size.div_ceil(alignment) * alignment;
+        let decode_buffer = MappedBuffer::new(
+            context.clone(),
+            decode_stride * MAX_DECODES as vk::DeviceSize,
+            vk::BufferUsageFlags::UNIFORM_BUFFER,
+        )?;
+        let (decode_pool, decode_set) = create_uniform_set(
+            &context,
+            decode_set_layout,
+            vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC,
+            decode_buffer.buffer(),
+            decode_size,
+        )?;
+
+        Ok(Self {
+            context,
+            vertex_module,
+            fragment_module,
+            texture_set_layout,
+            pipeline_layout,
+            pipelines: HashMap::new(),
+            sampler,
+            decode_set_layout,
+            decode_pool,
+            decode_set,
+            decode_buffer,
+            decode_stride,
+            decodes: Vec::new(),
+        })
+    }
+
+    /// Layout of the descriptor set holding the surface texture passed to [`Self::record`]
+    pub fn texture_set_layout(&self) -> vk::DescriptorSetLayout {
+        self.texture_set_layout
+    }
+
+    /// Sampler to put in texture sets of client surfaces
+    pub fn surface_sampler(&self) -> vk::Sampler {
+        self.sampler
+    }
+
+    /// Let the decode transform slots be rewritten
+    ///
+    /// Only call this while no submission recorded with [`Self::record`] is pending.
+    pub fn reset_decodes(&mut self) {
+        self.decodes.clear();
+    }
+
+    /// Offset of the slot holding `decode`, writing it to a free slot if no draw since the
+    /// last reset used it
+    fn decode_offset(&mut self, decode: &ColorTransform) -> StarforgeResult<u32> {
+        let uniform = decode.to_uniform();
+        let slot = match self.decodes.iter().position(|known| *known == uniform) {
+            Some(slot) => slot,
+            None if self.decodes.len() < MAX_DECODES => {
+                let slot = self.decodes.len();
+                self.decode_buffer.write_at(
+                    slot as vk::DeviceSize * self.decode_stride,
+                    push_constant_bytes(&uniform),
+                )?;
+                self.decodes.push(uniform);
+                slot
+            }
+            None => {
+                return Err(StarforgeError::RendererError(format!(
+                    "more than {MAX_DECODES} colour transforms in one submission"
+                )));
+            }
+        };
+        Ok((slot as vk::DeviceSize * self.decode_stride) as u32)
+    }
+
+    /// Draw the `source` part of a surface texture, in texture coordinates, stretched to
+    /// geometry `rect` into the attachment currently being rendered to, which has `format`
+    /// and `output_size`
+    ///
+    /// `decode` brings the texture and the shape's colors into the attachment's space.
+    #[allow(clippy::too_many_arguments)]
+    pub fn record(
+        &mut self,
+        command_buffer: vk::CommandBuffer,
+        format: vk::Format,
+        output_size: Size<i32, Physical>,
+        texture_set: vk::DescriptorSet,
+        rect: Rectangle<i32, Physical>,
+        source: [f32; 4],
+        shape: &WindowShape,
+        decode: &ColorTransform,
+        opacity: f32,
+    ) -> StarforgeResult<()> {
+        let decode_offset = self.decode_offset(decode)?;
+        let pipeline = match self.pipelines.get(&format) {
+            Some(&pipeline) => pipeline,
+            None => {
+                let pipeline = create_graphics_pipeline(
+                    &self.context,
+                    self.pipeline_layout,
+                    self.vertex_module,
+                    self.fragment_module,
+                    format,
+                    BlendMode::PremultipliedOver,
+                )?;
+                self.pipelines.insert(format, pipeline);
+                pipeline
+            }
+        };
+
+        let bounds = shape.visual_bounds(rect);
+        let radii = shape
+            .corner_radii
+            .clamped(rect.size.w as f32, rect.size.h as f32);
+        let border = shape.border.unwrap_or(Border {
+            width: 0.0,
+            color: [0.0; 4],
+        });
+        let shadow = shape.shadow.unwrap_or(Shadow {
+            offset: [0.0; 2],
+            radius: 0.0,
+            spread: 0.0,
+            color: [0.0; 4],
+        });
+        let to_array = |r: Rectangle<i32, Physical>| {
+            [
+                r.loc.x as f32,
+                r.loc.y as f32,
+                r.size.w as f32,
+                r.size.h as f32,
+            ]
+        };
+        let push_constants = ShapePushConstants {
+            bounds: to_array(bounds),
+            rect: to_array(rect),
+            source,
+            radii: radii.to_array(),
+            border_color: premultiply(border.color),
+            shadow_color: premultiply(shadow.color),
+            output_size: [output_size.w as f32, output_size.h as f32],
+            shadow_offset: shadow.offset,
+            border_width: shape.border_width(),
+            shadow_sigma: shadow.sigma(),
+            shadow_spread: shadow.spread,
+            opacity,
+        };
+
+        let device = self.context.device();
+        unsafe {
+            device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, pipeline);
+            device.cmd_set_viewport(
+                command_buffer,
+                0,
+                &[vk::Viewport {
+                    x: 0.0,
+                    y: 0.0,
+                    width: output_size.w as f32,
+                    height: output_size.h as f32,
+                    min_depth: 0.0,
+                    max_depth: 1.0,
+                }],
+            );
+            device.cmd_set_scissor(
+                command_buffer,
+                0,
+                &[vk::Rect2D {
+                    offset: vk::Offset2D::default(),
+                    extent: vk::Extent2D {
+                        width: output_size.w as u32,
+                        height: output_size.h as u32,
+                    },
+                }],
+            );
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline_layout,
+                0,
+                &[texture_set, self.decode_set],
+                &[decode_offset],
+            );
+            device.cmd_push_constants(
+                command_buffer,
+                self.pipeline_layout,
+                vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+                0,
+                push_constant_bytes(&push_constants),
+            );
+            device.cmd_draw(command_buffer, 6, 1, 0, 0);
+        }
+        Ok(())
+    }
+}
+
+impl Drop for ShapeRenderer {
+    fn drop(&mut self) {
+        unsafe {
+            let device = self.context.device();
+            for &pipeline in self.pipelines.values() {
+                self.context.untrack(pipeline);
+                device.destroy_pipeline(pipeline, None);
+            }
+            self.context.untrack(self.pipeline_layout);
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+            self.context.untrack(self.sampler);
+            device.destroy_sampler(self.sampler, None);
+            self.context.untrack(self.decode_pool);
+            device.destroy_descriptor_pool(self.decode_pool, None);
+            self.context.untrack(self.decode_set_layout);
+            device.destroy_descriptor_set_layout(self.decode_set_layout, None);
+            self.context.untrack(self.texture_set_layout);
+            device.destroy_descriptor_set_layout(self.texture_set_layout, None);
+            self.context.untrack(self.vertex_module);
+            device.destroy_shader_module(self.vertex_module, None);
+            self.context.untrack(self.fragment_module);
+            device.destroy_shader_module(self.fragment_module, None);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use smithay::utils::Logical;
+
+    fn rect(x: i32, y: i32, w: i32, h: i32) -> Rectangle<i32, Logical> {
+        Rectangle::new((x, y).into(), (w, h).into())
+    }
+
+    #[test]
+    fn shape_shaders_compile() {
+        compile_internal(SHAPE_VERT, ShaderStage::Vertex).unwrap();
+        compile_internal(&with_color_glsl(SHAPE_FRAG), ShaderStage::Fragment).unwrap();
+    }
+
+    #[test]
+    fn push_constants_fit_the_guaranteed_limit() {
+        assert!(std::mem::size_of::<ShapePushConstants>() <= 128);
+    }
+
+    #[test]
+    fn sdf_of_a_sharp_rectangle() {
+        let half = [50.0, 25.0];
+        assert_eq!(rounded_rect_sdf([0.0, 0.0], half, CornerRadii::ZERO), -25.0);
+        assert_eq!(rounded_rect_sdf([60.0, 0.0], half, CornerRadii::ZERO), 10.0);
+        assert_eq!(rounded_rect_sdf([50.0, 10.0], half, CornerRadii::ZERO), 0.0);
+    }
+
+    #[test]
+    fn sdf_follows_each_corner_radius() {
+        let half = [50.0, 50.0];
+        let radii = CornerRadii {
+            top_left: 10.0,
+            top_right: 0.0,
+            bottom_right: 20.0,
+            bottom_left: 0.0,
+        };
+        // The corner point itself is outside a rounded corner by r * (sqrt(2) - 1)
+        let d = rounded_rect_sdf([-50.0, -50.0], half, radii);
+        assert!((d - 10.0 * (2f32.sqrt() - 1.0)).abs() < 1e-4);
+        let d = rounded_rect_sdf([50.0, 50.0], half, radii);
+        assert!((d - 20.0 * (2f32.sqrt() - 1.0)).abs() < 1e-4);
+        assert_eq!(rounded_rect_sdf([50.0, -50.0], half, radii), 0.0);
+    }
+
+    #[test]
+    fn shadow_is_half_covered_at_the_edge() {
+        assert!((shadow_alpha(0.0, 4.0) - 0.5).abs() < 1e-6);
+        assert!(shadow_alpha(-20.0, 4.0) > 0.999);
+        assert!(shadow_alpha(20.0, 4.0) < 0.001);
+        let mut previous = 1.0;
+        for i in -40..40 {
+            let alpha = shadow_alpha(i as f32 * 0.5, 4.0);
+            assert!(alpha <= previous);
+            previous = alpha;
+        }
+    }
+
+    #[test]
+    fn rounded_corners_are_not_opaque() {
+        let shape = WindowShape {
+            corner_radii: CornerRadii::uniform(7.5),
+            ..Default::default()
+        };
+        let opaque = shape.opaque_region((100, 50).into(), &[rect(0, 0, 100, 50)]);
+        let area: i32 = opaque.iter().map(|r| r.size.w * r.size.h).sum();
+        assert_eq!(area, 100 * 50 - 4 * 8 * 8);
+        for corner in [(0, 0), (99, 0), (99, 49), (0, 49)] {
+            assert!(!opaque.iter().any(|r| r.contains(corner)));
+        }
+        assert!(opaque.iter().any(|r| r.contains((50, 25))));
+    }
+
+    #[test]
+    fn square_windows_keep_their_opaque_region() {
+        let opaque = WindowShape::default().opaque_region((100, 50).into(), &[rect(0, 0, 100, 50)]);
+        assert_eq!(opaque, vec![rect(0, 0, 100, 50)]);
+    }
+
+    #[test]
+    fn backdrops_keep_the_corners_on_the_output() {
+        let shape = WindowShape {
+            corner_radii: CornerRadii::uniform(8.0),
+            border: Some(Border {
+                width: 2.0,
+                color: [1.0; 4],
+            }),
+            shadow: None,
+        };
+        let window = rect(-50, 100, 200, 100);
+        let backdrop = shape.backdrop(window, rect(0, 100, 150, 100));
+        assert_eq!(
+            backdrop.corner_radii,
+            CornerRadii {
+                top_left: 0.0,
+                top_right: 8.0,
+                bottom_right: 8.0,
+                bottom_left: 0.0,
+            }
+        );
+        assert_eq!(backdrop.border, None);
+        assert_eq!(
+            shape.backdrop(window, window).corner_radii,
+            shape.corner_radii
+        );
+    }
+
+    #[test]
+    fn visual_bounds_include_border_and_shadow() {
+        let shape = WindowShape {
+            corner_radii: CornerRadii::uniform(8.0),
+            border: Some(Border {
+                width: 2.0,
+                color: [1.0; 4],
+            }),
+            shadow: Some(Shadow {
+                offset: [0.0, 4.0],
+                radius: 8.0,
+                spread: 0.0,
+                color: [0.0, 0.0, 0.0, 0.5],
+            }),
+        };
+        let bounds = shape.visual_bounds(rect(100, 100, 200, 100));
+        // Border 2 + three sigmas of 4, shifted down by 4
+        assert_eq!(bounds, rect(86, 90, 228, 128));
+    }
+}
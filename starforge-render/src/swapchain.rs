@@ -2,20 +2,52 @@
 //!
 //! This module handles Vulkan swapchain configuration per output and presentation
 
+use crate::color::{ColorPipeline, ColorSpace, ColorTransform, HdrMetadata, ToneMapping};
 use crate::core::Context;
 use ash::vk;
-use starforge_core::StarforgeResult;
+use starforge_core::{StarforgeError, StarforgeResult};
 use std::sync::Arc;
+use tracing::{error, info, warn};
 
-#[derive(Eq, Hash, PartialEq)]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
 pub struct OutputId(pub u32);
 
 /// Swapchain configuration
+#[derive(Clone)]
 pub struct SwapchainConfig {
     pub desired_width: u32,
     pub desired_height: u32,
     pub desired_present_mode: vk::PresentModeKHR,
     pub enable_hdr: bool,
+    /// Operator used when content is brighter than the output can show
+    pub tone_mapping: ToneMapping,
+    /// Mastering metadata sent to the display when an HDR format is in use
+    pub hdr_metadata: HdrMetadata,
+}
+
+impl SwapchainConfig {
+    /// Configuration for a surface of `width`x`height`, presenting at the vblank with
+    /// `vsync` and in an HDR format with `enable_hdr` when the display offers one
+    pub fn new(
+        width: u32,
+        height: u32,
+        vsync: bool,
+        enable_hdr: bool,
+        tone_mapping: ToneMapping,
+    ) -> Self {
+        Self {
+            desired_width: width,
+            desired_height: height,
+            // Mailbox doesn't wait for the vblank, but never tears either
+            desired_present_mode: match vsync {
+                true => vk::PresentModeKHR::FIFO,
+                false => vk::PresentModeKHR::MAILBOX,
+            },
+            enable_hdr,
+            tone_mapping,
+            hdr_metadata: HdrMetadata::default(),
+        }
+    }
 }
 
 /// Swapchain state
@@ -23,9 +55,16 @@ pub struct OutputSwapchain {
     context: Arc<Context>,
     surface_loader: ash::khr::surface::Instance, // Loader for surface functions
     swapchain_loader: ash::khr::swapchain::Device, // Loader for swapchain functions
+    hdr_metadata_loader: ash::ext::hdr_metadata::Device, // Loader for HDR metadata functions
     surface: vk::SurfaceKHR,
     surface_format: vk::SurfaceFormatKHR,
+    color_space: ColorSpace,
+    tone_mapping: ToneMapping,
+    hdr_metadata: HdrMetadata,
     present_mode: vk::PresentModeKHR,
+    /// Present mode picked for the config, used whenever the output doesn't tear
+    vsync_present_mode: vk::PresentModeKHR,
+    tearing: bool,
     swapchain: vk::SwapchainKHR,
     images: Vec<vk::Image>,
     image_views: Vec<vk::ImageView>,
@@ -44,9 +83,389 @@ impl OutputSwapchain {
         // Step 1: Load surface and swapchain extension functions
         let surface_loader = ash::khr::surface::Instance::new(&context._entry, &context.instance);
         let swapchain_loader = ash::khr::swapchain::Device::new(&context.instance, &context.device);
+        let hdr_metadata_loader =
+            ash::ext::hdr_metadata::Device::new(&context.instance, &context.device);
 
         // Step 2: Create VkSurfaceKHR
-        todo!()
+        let surface = unsafe {
+            create_surface(
+                &context._entry,
+                &context.instance,
+                surface_create_info.handles,
+            )?
+        };
+
+        context.track(surface);
+
+        // Steps 3 to 5 only need the surface cleaned up if they fail
+        let parameters = unsafe {
+            create_swapchain_for_surface(
+                &context,
+                &surface_loader,
+                &swapchain_loader,
+                surface,
+                &initial_config,
+            )
+        };
+        let (swapchain, surface_format, present_mode, extent) = match parameters {
+            Ok(parameters) => parameters,
+            Err(e) => {
+                context.untrack(surface);
+                unsafe { surface_loader.destroy_surface(surface, None) };
+                return Err(e);
+            }
+        };
+        context.track(swapchain);
+        let color_space = ColorSpace::from_vk(surface_format.color_space).unwrap_or_default();
+
+        // From here on `Drop` releases whatever was created if a step fails
+        let mut output = Self {
+            context,
+            surface_loader,
+            swapchain_loader,
+            hdr_metadata_loader,
+            surface,
+            surface_format,
+            color_space,
+            tone_mapping: initial_config.tone_mapping,
+            hdr_metadata: initial_config.hdr_metadata,
+            present_mode,
+            vsync_present_mode: present_mode,
+            tearing: false,
+            swapchain,
+            images: Vec::new(),
+            image_views: Vec::new(),
+            extent,
+            image_available_semaphores: Vec::new(),
+            in_flight_fences: Vec::new(),
+            current_frame: 0,
+        };
+
+        // Step 6: Create the image views and per-frame synchronization objects
+        output.create_image_views()?;
+
+        // Step 7: Tell the display about the content when it is HDR
+        output.send_hdr_metadata();
+
+        Ok(output)
+    }
+
+    /// Fetch the images of the swapchain and create their views, plus synchronization
+    /// objects for frames in flight if there are more images than before
+    fn create_image_views(&mut self) -> StarforgeResult<()> {
+        self.images = unsafe {
+            self.swapchain_loader
+                .get_swapchain_images(self.swapchain)
+                .map_err(|e| StarforgeError::RendererError(e.to_string()))?
+        };
+        let context = self.context.clone();
+        for &image in &self.images {
+            let view = unsafe { create_image_view(&context, image, self.surface_format.format)? };
+            context.track(view);
+            self.image_views.push(view);
+        }
+        for _ in self.in_flight_fences.len()..self.images.len() {
+            unsafe {
+                let semaphore = context
+                    .device
+                    .create_semaphore(&vk::SemaphoreCreateInfo::default(), None)
+                    .map_err(|e| StarforgeError::RendererError(e.to_string()))?;
+                context.track(semaphore);
+                self.image_available_semaphores.push(semaphore);
+                let fence = context
+                    .device
+                    .create_fence(
+                        &vk::FenceCreateInfo::default().flags(vk::FenceCreateFlags::SIGNALED),
+                        None,
+                    )
+                    .map_err(|e| StarforgeError::RendererError(e.to_string()))?;
+                context.track(fence);
+                self.in_flight_fences.push(fence);
+            }
+        }
+        Ok(())
+    }
+
+    /// Number of swapchain images, and so of frames in flight
+    pub fn image_count(&self) -> usize {
+        self.images.len()
+    }
+
+    /// The color space images of this swapchain are encoded in
+    pub fn color_space(&self) -> ColorSpace {
+        self.color_space
+    }
+
+    /// Let frames tear or go back to vsync, recreating the swapchain with another present
+    /// mode
+    ///
+    /// Tearing uses IMMEDIATE if the surface supports it, FIFO_RELAXED otherwise, and
+    /// stays on vsync if it supports neither.
+    pub fn set_tearing(&mut self, tearing: bool) -> StarforgeResult<()> {
+        if tearing == self.tearing {
+            return Ok(());
+        }
+        let (capabilities, present_modes) = unsafe {
+            let capabilities = self
+                .surface_loader
+                .get_physical_device_surface_capabilities(
+                    self.context.physical_device(),
+                    self.surface,
+                )
+                .map_err(|e| StarforgeError::RendererError(e.to_string()))?;
+            let present_modes = self
+                .surface_loader
+                .get_physical_device_surface_present_modes(
+                    self.context.physical_device(),
+                    self.surface,
+                )
+                .map_err(|e| StarforgeError::RendererError(e.to_string()))?;
+            (capabilities, present_modes)
+        };
+        let present_mode = if tearing {
+            choose_tearing_present_mode(&present_modes).unwrap_or(self.vsync_present_mode)
+        } else {
+            self.vsync_present_mode
+        };
+        if present_mode == self.present_mode {
+            self.tearing = tearing;
+            return Ok(());
+        }
+        info!("Output switching to present mode {:?}", present_mode);
+
+        let context = self.context.clone();
+        unsafe {
+            // The old images may still be in use by frames in flight
+            context
+                .device
+                .device_wait_idle()
+                .map_err(|e| StarforgeError::RendererError(e.to_string()))?;
+            let swapchain = create_swapchain(
+                &self.swapchain_loader,
+                self.surface,
+                &capabilities,
+                self.surface_format,
+                present_mode,
+                self.extent,
+                self.swapchain,
+            )?;
+            for view in self.image_views.drain(..) {
+                context.untrack(view);
+                context.device.destroy_image_view(view, None);
+            }
+            context.untrack(self.swapchain);
+            self.swapchain_loader
+                .destroy_swapchain(self.swapchain, None);
+            context.track(swapchain);
+            self.swapchain = swapchain;
+        }
+        self.present_mode = present_mode;
+        self.create_image_views()?;
+        // Only now, so a failed switch is retried on the next call
+        self.tearing = tearing;
+        self.send_hdr_metadata();
+        Ok(())
+    }
+
+    /// Whether every present waits for a vblank, so frames never tear
+    pub fn presents_at_vblank(&self) -> bool {
+        !matches!(
+            self.present_mode,
+            vk::PresentModeKHR::IMMEDIATE | vk::PresentModeKHR::FIFO_RELAXED
+        )
+    }
+
+    /// Whether the output is driven with an HDR transfer function
+    pub fn is_hdr(&self) -> bool {
+        self.color_space.transfer.is_hdr()
+    }
+
+    /// Replace the mastering metadata and send it to the display
+    pub fn set_hdr_metadata(&mut self, metadata: HdrMetadata) {
+        self.hdr_metadata = metadata;
+        self.send_hdr_metadata();
+    }
+
+    /// Set the operator used to compress content brighter than the output
+    pub fn set_tone_mapping(&mut self, tone_mapping: ToneMapping) {
+        self.tone_mapping = tone_mapping;
+    }
+
+    /// Transform encoding the working space for this output.
+    ///
+    /// Content that peaks above the output's peak luminance is tone mapped with the
+    /// configured operator.
+    pub fn encode_transform(
+        &self,
+        pipeline: &ColorPipeline,
+        content_peak_nits: f32,
+    ) -> ColorTransform {
+        let transform = pipeline.encode_transform(self.color_space);
+        let display_peak_nits = if self.is_hdr() {
+            self.hdr_metadata.max_luminance
+        } else {
+            pipeline.reference_white_nits
+        };
+        if content_peak_nits > display_peak_nits {
+            transform.with_tone_mapping(pipeline.tone_mapper(
+                self.tone_mapping,
+                content_peak_nits,
+                display_peak_nits,
+            ))
+        } else {
+            transform
+        }
+    }
+
+    fn send_hdr_metadata(&self) {
+        if !self.is_hdr() {
+            return;
+        }
+        unsafe {
+            self.hdr_metadata_loader
+                .set_hdr_metadata(&[self.swapchain], &[self.hdr_metadata.to_vk()]);
+        }
+    }
+}
+
+impl Drop for OutputSwapchain {
+    fn drop(&mut self) {
+        let context = &self.context;
+        unsafe {
+            // Frames still in flight may use the images and signal the fences
+            if let Err(e) = context.device.device_wait_idle() {
+                error!("Failed to wait for device idle: {}", e);
+            }
+            for &fence in &self.in_flight_fences {
+                context.untrack(fence);
+                context.device.destroy_fence(fence, None);
+            }
+            for &semaphore in &self.image_available_semaphores {
+                context.untrack(semaphore);
+                context.device.destroy_semaphore(semaphore, None);
+            }
+            for &view in &self.image_views {
+                context.untrack(view);
+                context.device.destroy_image_view(view, None);
+            }
+            // The images belong to the swapchain, which has to go before its surface
+            context.untrack(self.swapchain);
+            self.swapchain_loader
+                .destroy_swapchain(self.swapchain, None);
+            context.untrack(self.surface);
+            self.surface_loader.destroy_surface(self.surface, None);
+        }
+    }
+}
+
+/// Query the surface, pick the swapchain parameters and create the swapchain
+unsafe fn create_swapchain_for_surface(
+    context: &Context,
+    surface_loader: &ash::khr::surface::Instance,
+    swapchain_loader: &ash::khr::swapchain::Device,
+    surface: vk::SurfaceKHR,
+    config: &SwapchainConfig,
+) -> StarforgeResult<(
+    vk::SwapchainKHR,
+    vk::SurfaceFormatKHR,
+    vk::PresentModeKHR,
+    vk::Extent2D,
+)> {
+    // Step 3: Query surface support
+    let (capabilities, formats, present_modes) = unsafe {
+        let capabilities = surface_loader
+            .get_physical_device_surface_capabilities(context.physical_device(), surface)
+            .map_err(|e| StarforgeError::RendererError(e.to_string()))?;
+        let formats = surface_loader
+            .get_physical_device_surface_formats(context.physical_device(), surface)
+            .map_err(|e| StarforgeError::RendererError(e.to_string()))?;
+        let present_modes = surface_loader
+            .get_physical_device_surface_present_modes(context.physical_device(), surface)
+            .map_err(|e| StarforgeError::RendererError(e.to_string()))?;
+        (capabilities, formats, present_modes)
+    };
+
+    // Step 4: Pick the swapchain parameters
+    let surface_format = choose_surface_format(&formats, config.enable_hdr);
+    let present_mode = choose_present_mode(&present_modes, config.desired_present_mode);
+    let extent = choose_swap_extent(&capabilities, config.desired_width, config.desired_height);
+    info!(
+        "Output swapchain format {:?} in {:?}, present mode {:?}",
+        surface_format.format, surface_format.color_space, present_mode
+    );
+
+    // Step 5: Create the swapchain
+    let swapchain = unsafe {
+        create_swapchain(
+            swapchain_loader,
+            surface,
+            &capabilities,
+            surface_format,
+            present_mode,
+            extent,
+            vk::SwapchainKHR::null(),
+        )?
+    };
+    Ok((swapchain, surface_format, present_mode, extent))
+}
+
+unsafe fn create_swapchain(
+    swapchain_loader: &ash::khr::swapchain::Device,
+    surface: vk::SurfaceKHR,
+    capabilities: &vk::SurfaceCapabilitiesKHR,
+    surface_format: vk::SurfaceFormatKHR,
+    present_mode: vk::PresentModeKHR,
+    extent: vk::Extent2D,
+    old_swapchain: vk::SwapchainKHR,
+) -> StarforgeResult<vk::SwapchainKHR> {
+    // One image more than the minimum so we never wait on the presentation engine
+    let mut image_count = capabilities.min_image_count + 1;
+    if capabilities.max_image_count > 0 {
+        image_count = image_count.min(capabilities.max_image_count);
+    }
+
+    let create_info = vk::SwapchainCreateInfoKHR::default()
+        .surface(surface)
+        .min_image_count(image_count)
+        .image_format(surface_format.format)
+        .image_color_space(surface_format.color_space)
+        .image_extent(extent)
+        .image_array_layers(1)
+        .image_usage(vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_DST)
+        .image_sharing_mode(vk::SharingMode::EXCLUSIVE)
+        .pre_transform(capabilities.current_transform)
+        .composite_alpha(vk::CompositeAlphaFlagsKHR::OPAQUE)
+        .present_mode(present_mode)
+        .clipped(true)
+        .old_swapchain(old_swapchain);
+
+    unsafe {
+        swapchain_loader
+            .create_swapchain(&create_info, None)
+            .map_err(|e| StarforgeError::RendererError(e.to_string()))
+    }
+}
+
+unsafe fn create_image_view(
+    context: &Context,
+    image: vk::Image,
+    format: vk::Format,
+) -> StarforgeResult<vk::ImageView> {
+    let create_info = vk::ImageViewCreateInfo::default()
+        .image(image)
+        .view_type(vk::ImageViewType::TYPE_2D)
+        .format(format)
+        .subresource_range(
+            vk::ImageSubresourceRange::default()
+                .aspect_mask(vk::ImageAspectFlags::COLOR)
+                .level_count(1)
+                .layer_count(1),
+        );
+    unsafe {
+        context
+            .device
+            .create_image_view(&create_info, None)
+            .map_err(|e| StarforgeError::RendererError(e.to_string()))
     }
 }
 
@@ -77,18 +496,96 @@ unsafe fn create_surface(
     }
 }
 
-// Helper functions (need implementation) - place outside impl block
+/// HDR surface formats in order of preference: HDR10/PQ first, then scRGB
+const HDR_SURFACE_FORMATS: [(vk::Format, vk::ColorSpaceKHR); 4] = [
+    (
+        vk::Format::A2B10G10R10_UNORM_PACK32,
+        vk::ColorSpaceKHR::HDR10_ST2084_EXT,
+    ),
+    (
+        vk::Format::A2R10G10B10_UNORM_PACK32,
+        vk::ColorSpaceKHR::HDR10_ST2084_EXT,
+    ),
+    (
+        vk::Format::R16G16B16A16_SFLOAT,
+        vk::ColorSpaceKHR::HDR10_ST2084_EXT,
+    ),
+    (
+        vk::Format::R16G16B16A16_SFLOAT,
+        vk::ColorSpaceKHR::EXTENDED_SRGB_LINEAR_EXT,
+    ),
+];
+
+/// SDR surface formats in order of preference.
+///
+/// UNORM formats are used because the final pass encodes the transfer function itself.
+const SDR_SURFACE_FORMATS: [(vk::Format, vk::ColorSpaceKHR); 4] = [
+    (
+        vk::Format::A2B10G10R10_UNORM_PACK32,
+        vk::ColorSpaceKHR::SRGB_NONLINEAR,
+    ),
+    (
+        vk::Format::A2R10G10B10_UNORM_PACK32,
+        vk::ColorSpaceKHR::SRGB_NONLINEAR,
+    ),
+    (
+        vk::Format::B8G8R8A8_UNORM,
+        vk::ColorSpaceKHR::SRGB_NONLINEAR,
+    ),
+    (
+        vk::Format::R8G8B8A8_UNORM,
+        vk::ColorSpaceKHR::SRGB_NONLINEAR,
+    ),
+];
+
 fn choose_surface_format(
     available_formats: &[vk::SurfaceFormatKHR],
     prefer_hdr: bool,
 ) -> vk::SurfaceFormatKHR {
-    // Logic to select format:
-    // If prefer_hdr, look for HDR formats first (e.g., R16G16B16A16_SFLOAT with HDR10/P3 colorspace).
-    // Fallback to standard formats like B8G8R8A8_SRGB or R8G8B8A8_SRGB.
-    // Return a default if none are suitable (e.g., the first available format).
-    *available_formats
-        .first()
-        .expect("No surface formats available!") // Basic placeholder
+    let find = |candidates: &[(vk::Format, vk::ColorSpaceKHR)]| {
+        candidates.iter().find_map(|&(format, color_space)| {
+            available_formats
+                .iter()
+                .find(|f| f.format == format && f.color_space == color_space)
+                .copied()
+        })
+    };
+
+    if prefer_hdr {
+        if let Some(format) = find(&HDR_SURFACE_FORMATS) {
+            return format;
+        }
+        warn!("HDR requested but the surface offers no HDR formats, falling back to SDR");
+    }
+    find(&SDR_SURFACE_FORMATS).unwrap_or_else(|| {
+        *available_formats
+            .first()
+            .expect("No surface formats available!")
+    })
+}
+
+fn choose_present_mode(
+    available_modes: &[vk::PresentModeKHR],
+    desired_mode: vk::PresentModeKHR,
+) -> vk::PresentModeKHR {
+    if available_modes.contains(&desired_mode) {
+        desired_mode
+    } else {
+        // FIFO is the only mode every implementation has to support
+        vk::PresentModeKHR::FIFO
+    }
+}
+
+/// Present mode that lets frames tear, if the surface has one
+fn choose_tearing_present_mode(
+    available_modes: &[vk::PresentModeKHR],
+) -> Option<vk::PresentModeKHR> {
+    [
+        vk::PresentModeKHR::IMMEDIATE,
+        vk::PresentModeKHR::FIFO_RELAXED,
+    ]
+    .into_iter()
+    .find(|mode| available_modes.contains(mode))
 }
 
 fn choose_swap_extent(
@@ -113,3 +610,78 @@ fn choose_swap_extent(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tearing_prefers_immediate_presents() {
+        let modes = [
+            vk::PresentModeKHR::FIFO,
+            vk::PresentModeKHR::FIFO_RELAXED,
+            vk::PresentModeKHR::IMMEDIATE,
+        ];
+        assert_eq!(
+            choose_tearing_present_mode(&modes),
+            Some(vk::PresentModeKHR::IMMEDIATE)
+        );
+        assert_eq!(
+            choose_tearing_present_mode(&modes[..2]),
+            Some(vk::PresentModeKHR::FIFO_RELAXED)
+        );
+        // Mailbox never tears
+        assert_eq!(
+            choose_tearing_present_mode(&[vk::PresentModeKHR::FIFO, vk::PresentModeKHR::MAILBOX]),
+            None
+        );
+    }
+
+    fn surface_format(format: vk::Format, color_space: vk::ColorSpaceKHR) -> vk::SurfaceFormatKHR {
+        vk::SurfaceFormatKHR {
+            format,
+            color_space,
+        }
+    }
+
+    #[test]
+    fn hdr_prefers_pq_over_scrgb() {
+        let scrgb = surface_format(
+            vk::Format::R16G16B16A16_SFLOAT,
+            vk::ColorSpaceKHR::EXTENDED_SRGB_LINEAR_EXT,
+        );
+        let pq = surface_format(
+            vk::Format::A2R10G10B10_UNORM_PACK32,
+            vk::ColorSpaceKHR::HDR10_ST2084_EXT,
+        );
+        let srgb = surface_format(
+            vk::Format::B8G8R8A8_UNORM,
+            vk::ColorSpaceKHR::SRGB_NONLINEAR,
+        );
+        assert_eq!(choose_surface_format(&[srgb, scrgb, pq], true), pq);
+        assert_eq!(choose_surface_format(&[srgb, scrgb], true), scrgb);
+    }
+
+    #[test]
+    fn sdr_formats_are_used_without_hdr() {
+        let pq = surface_format(
+            vk::Format::A2B10G10R10_UNORM_PACK32,
+            vk::ColorSpaceKHR::HDR10_ST2084_EXT,
+        );
+        let bgra = surface_format(
+            vk::Format::B8G8R8A8_UNORM,
+            vk::ColorSpaceKHR::SRGB_NONLINEAR,
+        );
+        let ten_bit = surface_format(
+            vk::Format::A2B10G10R10_UNORM_PACK32,
+            vk::ColorSpaceKHR::SRGB_NONLINEAR,
+        );
+        // HDR formats are left alone unless asked for
+        assert_eq!(choose_surface_format(&[pq, bgra, ten_bit], false), ten_bit);
+        // Displays without HDR formats fall back to SDR
+        assert_eq!(choose_surface_format(&[bgra, ten_bit], true), ten_bit);
+        // Unknown formats are taken as a last resort
+        let srgb = surface_format(vk::Format::B8G8R8A8_SRGB, vk::ColorSpaceKHR::SRGB_NONLINEAR);
+        assert_eq!(choose_surface_format(&[srgb], true), srgb);
+    }
+}
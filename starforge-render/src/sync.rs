@@ -1,3 +1,294 @@
 //! Starforge Render - Sync
 //!
 //! This module handles Vulkan synchronization primitives
+//!
+//! Client buffers committed with linux-drm-syncobj-v1 sync points are synchronized
+//! explicitly. Their acquire point is exported as a sync file and imported into a binary
+//! semaphore that the frame sampling the buffer waits on. The buffer itself is held until a
+//! timeline semaphore shows that frame completed; dropping the last reference to it signals
+//! the release point. Buffers without sync points keep relying on the implicit fences of
+//! their dma-bufs.
+
+use crate::core::Context;
+use crate::swapchain::OutputId;
+use ash::vk;
+use smithay::backend::renderer::utils::Buffer;
+use smithay::wayland::drm_syncobj::DrmSyncPoint;
+use starforge_core::{StarforgeError, StarforgeResult};
+use std::collections::{HashMap, VecDeque};
+use std::os::fd::{AsRawFd, IntoRawFd, OwnedFd};
+use std::sync::Arc;
+use tracing::{error, warn};
+
+/// How long to block on an acquire point when the device can't wait for it on the GPU
+const CPU_ACQUIRE_TIMEOUT_NSEC: i64 = 100_000_000;
+
+/// Client synchronization of one frame
+#[derive(Default)]
+struct FrameSync {
+    /// Signalled by the acquire points of the sampled buffers
+    acquire_semaphores: Vec<vk::Semaphore>,
+    /// Sampled buffers, kept alive until the frame completed
+    buffers: Vec<Buffer>,
+}
+
+/// Values queued in submission order, each waiting for a timeline value to be reached
+struct InFlight<T> {
+    entries: VecDeque<(u64, T)>,
+}
+
+impl<T> Default for InFlight<T> {
+    fn default() -> Self {
+        Self {
+            entries: VecDeque::new(),
+        }
+    }
+}
+
+impl<T> InFlight<T> {
+    fn push(&mut self, value: u64, entry: T) {
+        self.entries.push_back((value, entry));
+    }
+
+    /// Remove every entry whose value the timeline reached
+    fn retire(&mut self, completed: u64) -> Vec<T> {
+        let mut retired = Vec::new();
+        while self
+            .entries
+            .front()
+            .is_some_and(|&(value, _)| value <= completed)
+        {
+            retired.push(self.entries.pop_front().unwrap().1);
+        }
+        retired
+    }
+
+    fn drain(&mut self) -> impl Iterator<Item = T> + '_ {
+        self.entries.drain(..).map(|(_, entry)| entry)
+    }
+}
+
+/// Explicit synchronization with clients, per output
+pub struct ExplicitSync {
+    context: Arc<Context>,
+    semaphore_fd: ash::khr::external_semaphore_fd::Device,
+    /// Whether sync files can be imported into semaphores; if not, acquire points are
+    /// waited for on the CPU
+    sync_file_import: bool,
+    /// Incremented by every frame submission
+    timeline: vk::Semaphore,
+    next_value: u64,
+    /// Frames being recorded
+    recording: HashMap<OutputId, FrameSync>,
+    /// Submitted frames, by the timeline value they signal
+    in_flight: InFlight<FrameSync>,
+}
+
+impl ExplicitSync {
+    pub fn new(context: Arc<Context>) -> StarforgeResult<Self> {
+        let semaphore_fd =
+            ash::khr::external_semaphore_fd::Device::new(&context.instance, &context.device);
+        let sync_file_import = supports_sync_file_import(&context);
+        if !sync_file_import {
+            warn!("Sync file import not supported, acquire points will be waited on the CPU.");
+        }
+
+        let mut type_info = vk::SemaphoreTypeCreateInfo::default()
+            .semaphore_type(vk::SemaphoreType::TIMELINE)
+            .initial_value(0);
+        let create_info = vk::SemaphoreCreateInfo::default().push_next(&mut type_info);
+        let timeline =
+            context.check(unsafe { context.device().create_semaphore(&create_info, None) })?;
+        context.track(timeline);
+
+        Ok(Self {
+            context,
+            semaphore_fd,
+            sync_file_import,
+            timeline,
+            next_value: 1,
+            recording: HashMap::new(),
+            in_flight: InFlight::default(),
+        })
+    }
+
+    /// Sample `buffer` in the next frame of `output`
+    ///
+    /// With an acquire point the frame waits for it before sampling. The buffer is released
+    /// once the frame completed and nothing else holds it.
+    pub fn use_buffer(
+        &mut self,
+        output: OutputId,
+        buffer: &Buffer,
+        acquire_point: Option<&DrmSyncPoint>,
+    ) -> StarforgeResult<()> {
+        let semaphore = match acquire_point {
+            Some(point) if self.sync_file_import => Some(self.import_acquire_point(point)?),
+            Some(point) => {
+                point
+                    .wait(CPU_ACQUIRE_TIMEOUT_NSEC)
+                    .map_err(|e| StarforgeError::RendererError(e.to_string()))?;
+                None
+            }
+            None => None,
+        };
+
+        let frame = self.recording.entry(output).or_default();
+        frame.acquire_semaphores.extend(semaphore);
+        frame.buffers.push(buffer.clone());
+        Ok(())
+    }
+
+    /// Semaphores the next submission for `output` has to wait on and signal
+    ///
+    /// Moves the frame being recorded to the in-flight list, so this has to be called
+    /// exactly once per submission. The returned infos stay valid until the next call.
+    pub fn submit_infos(
+        &mut self,
+        output: OutputId,
+    ) -> (
+        Vec<vk::SemaphoreSubmitInfo<'static>>,
+        vk::SemaphoreSubmitInfo<'static>,
+    ) {
+        let frame = self.recording.remove(&output).unwrap_or_default();
+        let waits = frame
+            .acquire_semaphores
+            .iter()
+            .map(|&semaphore| {
+                vk::SemaphoreSubmitInfo::default()
+                    .semaphore(semaphore)
+                    .stage_mask(vk::PipelineStageFlags2::FRAGMENT_SHADER)
+            })
+            .collect();
+        let signal = vk::SemaphoreSubmitInfo::default()
+            .semaphore(self.timeline)
+            .value(self.next_value)
+            .stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS);
+
+        self.in_flight.push(self.next_value, frame);
+        self.next_value += 1;
+        (waits, signal)
+    }
+
+    /// Release the buffers and semaphores of every frame that completed
+    pub fn retire(&mut self) -> StarforgeResult<()> {
+        let completed = self.context.check(unsafe {
+            self.context
+                .device()
+                .get_semaphore_counter_value(self.timeline)
+        })?;
+        for frame in self.in_flight.retire(completed) {
+            self.destroy_frame(frame);
+        }
+        Ok(())
+    }
+
+    fn import_acquire_point(&self, point: &DrmSyncPoint) -> StarforgeResult<vk::Semaphore> {
+        let sync_file = point
+            .export_sync_file()
+            .map_err(|e| StarforgeError::RendererError(e.to_string()))?;
+        let device = self.context.device();
+        let semaphore = self
+            .context
+            .check(unsafe { device.create_semaphore(&vk::SemaphoreCreateInfo::default(), None) })?;
+        let result = unsafe { import_sync_file(&self.semaphore_fd, semaphore, sync_file) };
+        if result.is_err() {
+            unsafe { device.destroy_semaphore(semaphore, None) };
+        }
+        self.context.check(result)?;
+        self.context.track(semaphore);
+        Ok(semaphore)
+    }
+
+    /// Destroy the acquire semaphores of a frame and drop its buffers
+    fn destroy_frame(&self, frame: FrameSync) {
+        for semaphore in frame.acquire_semaphores {
+            self.context.untrack(semaphore);
+            unsafe { self.context.device().destroy_semaphore(semaphore, None) };
+        }
+        // Dropping the last reference to a buffer signals its release point
+        drop(frame.buffers);
+    }
+}
+
+impl Drop for ExplicitSync {
+    fn drop(&mut self) {
+        let last_value = self.next_value - 1;
+        let semaphores = [self.timeline];
+        let values = [last_value];
+        let wait_info = vk::SemaphoreWaitInfo::default()
+            .semaphores(&semaphores)
+            .values(&values);
+        if !self.context.is_lost()
+            && let Err(e) = unsafe { self.context.device().wait_semaphores(&wait_info, u64::MAX) }
+        {
+            error!("Failed to wait for frames in flight: {}", e);
+        }
+
+        // Buffers are released even when the device is lost, clients would stall otherwise
+        let frames: Vec<_> = self
+            .in_flight
+            .drain()
+            .chain(self.recording.drain().map(|(_, frame)| frame))
+            .collect();
+        for frame in frames {
+            self.destroy_frame(frame);
+        }
+        self.context.untrack(self.timeline);
+        unsafe { self.context.device().destroy_semaphore(self.timeline, None) };
+    }
+}
+
+/// Whether sync files can be imported into binary semaphores
+fn supports_sync_file_import(context: &Context) -> bool {
+    let info = vk::PhysicalDeviceExternalSemaphoreInfo::default()
+        .handle_type(vk::ExternalSemaphoreHandleTypeFlags::SYNC_FD);
+    let mut properties = vk::ExternalSemaphoreProperties::default();
+    unsafe {
+        context
+            .instance()
+            .get_physical_device_external_semaphore_properties(
+                context.physical_device(),
+                &info,
+                &mut properties,
+            );
+    }
+    properties
+        .external_semaphore_features
+        .contains(vk::ExternalSemaphoreFeatureFlags::IMPORTABLE)
+}
+
+/// Give the payload of `sync_file` to `semaphore` until its next wait
+unsafe fn import_sync_file(
+    loader: &ash::khr::external_semaphore_fd::Device,
+    semaphore: vk::Semaphore,
+    sync_file: OwnedFd,
+) -> ash::prelude::VkResult<()> {
+    let import_info = vk::ImportSemaphoreFdInfoKHR::default()
+        .semaphore(semaphore)
+        .flags(vk::SemaphoreImportFlags::TEMPORARY)
+        .handle_type(vk::ExternalSemaphoreHandleTypeFlags::SYNC_FD)
+        .fd(sync_file.as_raw_fd());
+    unsafe { loader.import_semaphore_fd(&import_info) }?;
+    // The semaphore owns the file descriptor once the import succeeded
+    let _ = sync_file.into_raw_fd();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entries_retire_in_submission_order() {
+        let mut in_flight = InFlight::default();
+        in_flight.push(1, 'a');
+        in_flight.push(2, 'b');
+        in_flight.push(3, 'c');
+
+        assert!(in_flight.retire(0).is_empty());
+        assert_eq!(in_flight.retire(2), vec!['a', 'b']);
+        assert!(in_flight.retire(2).is_empty());
+        assert_eq!(in_flight.drain().collect::<Vec<_>>(), vec!['c']);
+    }
+}
@@ -0,0 +1,779 @@
+//! Starforge Render - YUV Video Buffers
+//!
+//! This module samples the YUV dma-bufs video players send: NV12, P010, YUV420 and YUYV.
+//! Every plane is imported as an image of its own, and a conversion pass turns the planes
+//! into an RGB image whenever the texture is acquired for a frame. From there on the
+//! texture is sampled like any other.
+//!
+//! The pass applies the YCbCr to RGB matrix and range the surface asked for with
+//! wp_color_representation_v1, and samples chroma at its position relative to luma. Without
+//! a request, SD-sized buffers use BT.601 and larger ones BT.709, both with the limited
+//! range, which is what video decoders produce unless told otherwise.
+
+use crate::color::Mat3;
+use crate::core::Context;
+use crate::dmabuf::DmabufImage;
+use crate::memory::{AllocatedImage, image_barrier};
+use crate::pipeline::{
+    BlendMode, create_graphics_pipeline, create_linear_sampler, create_pipeline_layout,
+    push_constant_bytes,
+};
+use crate::shader::{compile_internal, create_shader_module};
+use ash::vk;
+use naga::ShaderStage;
+use smithay::backend::allocator::{Buffer, Fourcc, dmabuf::Dmabuf};
+use starforge_core::protocols::color_representation::{
+    ChromaLocation, ColorRange, ColorRepresentationSurfaceCachedState, MatrixCoefficients,
+};
+use starforge_core::{StarforgeError, StarforgeResult};
+use std::sync::Arc;
+
+const FULLSCREEN_VERT: &str = include_str!("../shaders/fullscreen.vert");
+const YUV_FRAG: &str = include_str!("../shaders/yuv.frag");
+
+/// Format of converted images, keeping the 10 bits of P010
+const RGB_FORMAT: vk::Format = vk::Format::A2B10G10R10_UNORM_PACK32;
+
+/// Tallest buffer still treated as SD video when the client didn't pick a matrix
+const SD_MAX_HEIGHT: u32 = 576;
+
+/// Importable YUV formats
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum YuvFormat {
+    /// 8 bit luma plane and a half-size plane of interleaved Cb and Cr
+    Nv12,
+    /// Like NV12 with 10 bits in the high bits of every 16 bit value
+    P010,
+    /// 8 bit luma plane and half-size Cb and Cr planes
+    Yuv420,
+    /// Packed Y0 Cb Y1 Cr, chroma shared by two horizontally adjacent pixels
+    Yuyv,
+}
+
+/// Vulkan format and subsampling of one plane of a YUV format
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PlaneLayout {
+    pub format: vk::Format,
+    pub horizontal_subsampling: u32,
+    pub vertical_subsampling: u32,
+}
+
+impl PlaneLayout {
+    const fn new(format: vk::Format, horizontal: u32, vertical: u32) -> Self {
+        Self {
+            format,
+            horizontal_subsampling: horizontal,
+            vertical_subsampling: vertical,
+        }
+    }
+
+    /// Size of the plane of a buffer of `extent`
+    pub fn extent(&self, extent: vk::Extent2D) -> vk::Extent2D {
+        vk::Extent2D {
+            width: extent.width.div_ceil(self.horizontal_subsampling),
+            height: extent.height.div_ceil(self.vertical_subsampling),
+        }
+    }
+}
+
+const NV12_PLANES: [PlaneLayout; 2] = [
+    PlaneLayout::new(vk::Format::R8_UNORM, 1, 1),
+    PlaneLayout::new(vk::Format::R8G8_UNORM, 2, 2),
+];
+const P010_PLANES: [PlaneLayout; 2] = [
+    PlaneLayout::new(vk::Format::R16_UNORM, 1, 1),
+    PlaneLayout::new(vk::Format::R16G16_UNORM, 2, 2),
+];
+const YUV420_PLANES: [PlaneLayout; 3] = [
+    PlaneLayout::new(vk::Format::R8_UNORM, 1, 1),
+    PlaneLayout::new(vk::Format::R8_UNORM, 2, 2),
+    PlaneLayout::new(vk::Format::R8_UNORM, 2, 2),
+];
+/// Every texel holds Y0, Cb, Y1 and Cr of two pixels
+const YUYV_PLANES: [PlaneLayout; 1] = [PlaneLayout::new(vk::Format::R8G8B8A8_UNORM, 2, 1)];
+
+/// How yuv.frag reads the planes, matching the `LAYOUT_*` constants there
+const LAYOUT_SEMI_PLANAR: u32 = 0;
+const LAYOUT_PLANAR: u32 = 1;
+const LAYOUT_PACKED: u32 = 2;
+
+impl YuvFormat {
+    pub const ALL: [Self; 4] = [Self::Nv12, Self::P010, Self::Yuv420, Self::Yuyv];
+
+    pub fn from_fourcc(fourcc: Fourcc) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|format| format.fourcc() == fourcc)
+    }
+
+    pub fn fourcc(self) -> Fourcc {
+        match self {
+            Self::Nv12 => Fourcc::Nv12,
+            Self::P010 => Fourcc::P010,
+            Self::Yuv420 => Fourcc::Yuv420,
+            Self::Yuyv => Fourcc::Yuyv,
+        }
+    }
+
+    pub fn planes(self) -> &'static [PlaneLayout] {
+        match self {
+            Self::Nv12 => &NV12_PLANES,
+            Self::P010 => &P010_PLANES,
+            Self::Yuv420 => &YUV420_PLANES,
+            Self::Yuyv => &YUYV_PLANES,
+        }
+    }
+
+    fn bit_depth(self) -> u32 {
+        match self {
+            Self::P010 => 10,
+            Self::Nv12 | Self::Yuv420 | Self::Yuyv => 8,
+        }
+    }
+
+    /// Factor turning sampled values into fractions of the format's maximum value
+    ///
+    /// P010 keeps its 10 bits in the high bits, so the largest value samples as
+    /// 65472 / 65535 rather than 1.
+    fn value_scale(self) -> f32 {
+        match self {
+            Self::P010 => 65535.0 / 65472.0,
+            Self::Nv12 | Self::Yuv420 | Self::Yuyv => 1.0,
+        }
+    }
+
+    fn shader_layout(self) -> u32 {
+        match self {
+            Self::Nv12 | Self::P010 => LAYOUT_SEMI_PLANAR,
+            Self::Yuv420 => LAYOUT_PLANAR,
+            Self::Yuyv => LAYOUT_PACKED,
+        }
+    }
+}
+
+/// Luma weights of red and blue of a matrix
+fn luma_weights(coefficients: MatrixCoefficients) -> Option<(f32, f32)> {
+    match coefficients {
+        MatrixCoefficients::Identity => None,
+        MatrixCoefficients::Bt601 => Some((0.299, 0.114)),
+        MatrixCoefficients::Bt709 => Some((0.2126, 0.0722)),
+        MatrixCoefficients::Bt2020 => Some((0.2627, 0.0593)),
+    }
+}
+
+/// An affine transform from sampled Y, Cb and Cr to RGB, `matrix * ycbcr + offset`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct YcbcrConversion {
+    matrix: Mat3,
+    offset: [f32; 3],
+}
+
+impl YcbcrConversion {
+    /// Conversion of values of `bit_depth` bits encoded with `coefficients` and `range`
+    pub fn new(coefficients: MatrixCoefficients, range: ColorRange, bit_depth: u32) -> Self {
+        // Undo the range: luma to 0..1, chroma to -0.5..0.5
+        let max = ((1u32 << bit_depth) - 1) as f32;
+        let step = (1u32 << (bit_depth - 8)) as f32;
+        let (luma_scale, luma_offset, chroma_scale) = match range {
+            ColorRange::Full => (1.0, 0.0, 1.0),
+            ColorRange::Limited => (max / (219.0 * step), -16.0 / 219.0, max / (224.0 * step)),
+        };
+        let chroma_offset = match range {
+            ColorRange::Full => -((1u32 << (bit_depth - 1)) as f32) / max,
+            ColorRange::Limited => -128.0 / 224.0,
+        };
+        let range_scale = [luma_scale, chroma_scale, chroma_scale];
+        let range_offset = [luma_offset, chroma_offset, chroma_offset];
+
+        let matrix = match luma_weights(coefficients) {
+            Some((kr, kb)) => {
+                let kg = 1.0 - kr - kb;
+                Mat3([
+                    [1.0, 0.0, 2.0 * (1.0 - kr)],
+                    [
+                        1.0,
+                        -2.0 * kb * (1.0 - kb) / kg,
+                        -2.0 * kr * (1.0 - kr) / kg,
+                    ],
+                    [1.0, 2.0 * (1.0 - kb), 0.0],
+                ])
+            }
+            // The planes hold G, B and R unchanged
+            None => {
+                return Self {
+                    matrix: Mat3([[0.0, 0.0, 1.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]]),
+                    offset: [0.0; 3],
+                };
+            }
+        };
+        let scaled = Mat3(
+            matrix
+                .0
+                .map(|row| std::array::from_fn(|i| row[i] * range_scale[i])),
+        );
+        Self {
+            matrix: scaled,
+            offset: matrix.apply(range_offset),
+        }
+    }
+
+    /// Column-major 4x4 matrix with the offset in the last column, for the shader
+    fn to_columns(self) -> [[f32; 4]; 4] {
+        let [c0, c1, c2] = self.matrix.columns();
+        let [o0, o1, o2] = self.offset;
+        [
+            [c0[0], c0[1], c0[2], 0.0],
+            [c1[0], c1[1], c1[2], 0.0],
+            [c2[0], c2[1], c2[2], 0.0],
+            [o0, o1, o2, 1.0],
+        ]
+    }
+}
+
+/// Matrix and range of a buffer of `extent` whose surface didn't pick any
+pub fn default_coefficients(extent: vk::Extent2D) -> (MatrixCoefficients, ColorRange) {
+    if extent.height <= SD_MAX_HEIGHT {
+        (MatrixCoefficients::Bt601, ColorRange::Limited)
+    } else {
+        (MatrixCoefficients::Bt709, ColorRange::Limited)
+    }
+}
+
+/// Where chroma samples sit relative to the centre of their chroma texel, in chroma texels
+///
+/// A texel of a plane subsampled by two covers two luma samples; sampling it at its centre
+/// assumes chroma sits between them. Co-sited chroma lies a quarter texel further
+/// towards the first luma sample, so the chroma plane is sampled that far the other way.
+pub fn chroma_offset(location: ChromaLocation) -> [f32; 2] {
+    match location {
+        ChromaLocation::Type0 => [0.25, 0.0],
+        ChromaLocation::Type1 => [0.0, 0.0],
+        ChromaLocation::Type2 => [0.25, 0.25],
+        ChromaLocation::Type3 => [0.0, 0.25],
+        ChromaLocation::Type4 => [0.25, -0.25],
+        ChromaLocation::Type5 => [0.0, -0.25],
+    }
+}
+
+/// Push constants of yuv.frag, matching `YuvPushConstants`
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct YuvPushConstants {
+    matrix: [[f32; 4]; 4],
+    luma_size: [f32; 2],
+    chroma_offset: [f32; 2],
+    value_scale: f32,
+    plane_layout: u32,
+}
+
+/// A YUV dma-buf and the RGB image its planes are converted into
+pub struct YuvImage {
+    context: Arc<Context>,
+    format: YuvFormat,
+    planes: Vec<DmabufImage>,
+    rgb: AllocatedImage,
+    descriptor_pool: vk::DescriptorPool,
+    descriptor_set: vk::DescriptorSet,
+}
+
+impl YuvImage {
+    pub fn import(
+        context: Arc<Context>,
+        converter: &YuvConverter,
+        dmabuf: &Dmabuf,
+    ) -> StarforgeResult<Self> {
+        let code = dmabuf.format().code;
+        let format = YuvFormat::from_fourcc(code).ok_or_else(|| {
+            StarforgeError::RendererError(format!("unsupported dma-buf format {code:?}"))
+        })?;
+        if dmabuf.num_planes() != format.planes().len() {
+            return Err(StarforgeError::RendererError(format!(
+                "expected {} dma-buf planes for {code:?}, got {}",
+                format.planes().len(),
+                dmabuf.num_planes()
+            )));
+        }
+        let extent = vk::Extent2D {
+            width: dmabuf.width(),
+            height: dmabuf.height(),
+        };
+        let planes = format
+            .planes()
+            .iter()
+            .enumerate()
+            .map(|(index, plane)| {
+                DmabufImage::import_plane(
+                    context.clone(),
+                    dmabuf,
+                    index,
+                    plane.format,
+                    plane.extent(extent),
+                )
+            })
+            .collect::<StarforgeResult<Vec<_>>>()?;
+        let rgb = AllocatedImage::new(
+            context.clone(),
+            extent,
+            RGB_FORMAT,
+            vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+        )?;
+        let (descriptor_pool, descriptor_set) = converter.create_plane_set(&planes)?;
+
+        Ok(Self {
+            context,
+            format,
+            planes,
+            rgb,
+            descriptor_pool,
+            descriptor_set,
+        })
+    }
+
+    pub fn view(&self) -> vk::ImageView {
+        self.rgb.view()
+    }
+
+    pub fn extent(&self) -> vk::Extent2D {
+        self.rgb.extent()
+    }
+}
+
+impl Drop for YuvImage {
+    fn drop(&mut self) {
+        unsafe {
+            self.context.untrack(self.descriptor_pool);
+            self.context
+                .device()
+                .destroy_descriptor_pool(self.descriptor_pool, None);
+        }
+    }
+}
+
+/// Records the conversion of YUV images into RGB and owns its pipeline
+pub struct YuvConverter {
+    context: Arc<Context>,
+    vertex_module: vk::ShaderModule,
+    fragment_module: vk::ShaderModule,
+    sampler: vk::Sampler,
+    descriptor_set_layout: vk::DescriptorSetLayout,
+    pipeline_layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+}
+
+impl YuvConverter {
+    pub fn new(context: Arc<Context>) -> StarforgeResult<Self> {
+        let vertex = compile_internal(FULLSCREEN_VERT, ShaderStage::Vertex)?;
+        let fragment = compile_internal(YUV_FRAG, ShaderStage::Fragment)?;
+        let vertex_module = create_shader_module(&context, &vertex.spirv)?;
+        let fragment_module = create_shader_module(&context, &fragment.spirv)?;
+
+        let sampler = create_linear_sampler(&context)?;
+        let descriptor_set_layout = create_plane_set_layout(&context)?;
+        let pipeline_layout = create_pipeline_layout::<YuvPushConstants>(
+            &context,
+            descriptor_set_layout,
+            vk::ShaderStageFlags::FRAGMENT,
+        )?;
+        let pipeline = create_graphics_pipeline(
+            &context,
+            pipeline_layout,
+            vertex_module,
+            fragment_module,
+            RGB_FORMAT,
+            BlendMode::Replace,
+        )?;
+
+        Ok(Self {
+            context,
+            vertex_module,
+            fragment_module,
+            sampler,
+            descriptor_set_layout,
+            pipeline_layout,
+            pipeline,
+        })
+    }
+
+    /// A descriptor set sampling `planes`, in a pool of its own
+    ///
+    /// Formats with fewer than three planes repeat their last one in the unused bindings.
+    fn create_plane_set(
+        &self,
+        planes: &[DmabufImage],
+    ) -> StarforgeResult<(vk::DescriptorPool, vk::DescriptorSet)> {
+        let context = &self.context;
+        let device = context.device();
+        let pool_sizes = [
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::SAMPLED_IMAGE,
+                descriptor_count: PLANE_BINDINGS,
+            },
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::SAMPLER,
+                descriptor_count: 1,
+            },
+        ];
+        let pool_info = vk::DescriptorPoolCreateInfo::default()
+            .max_sets(1)
+            .pool_sizes(&pool_sizes);
+        let descriptor_pool =
+            context.check(unsafe { device.create_descriptor_pool(&pool_info, None) })?;
+        let layouts = [self.descriptor_set_layout];
+        let allocate_info = vk::DescriptorSetAllocateInfo::default()
+            .descriptor_pool(descriptor_pool)
+            .set_layouts(&layouts);
+        let descriptor_set = match unsafe { device.allocate_descriptor_sets(&allocate_info) } {
+            Ok(sets) => sets[0],
+            Err(e) => {
+                unsafe { device.destroy_descriptor_pool(descriptor_pool, None) };
+                return Err(StarforgeError::RendererError(e.to_string()));
+            }
+        };
+        context.track(descriptor_pool);
+
+        let image_infos: Vec<_> = (0..PLANE_BINDINGS as usize)
+            .map(|binding| {
+                let plane = &planes[binding.min(planes.len() - 1)];
+                [vk::DescriptorImageInfo::default()
+                    .image_view(plane.view())
+                    .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)]
+            })
+            .collect();
+        let sampler_info = [vk::DescriptorImageInfo::default().sampler(self.sampler)];
+        let mut writes: Vec<_> = image_infos
+            .iter()
+            .enumerate()
+            .map(|(binding, info)| {
+                vk::WriteDescriptorSet::default()
+                    .dst_set(descriptor_set)
+                    .dst_binding(binding as u32)
+                    .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+                    .image_info(info)
+            })
+            .collect();
+        writes.push(
+            vk::WriteDescriptorSet::default()
+                .dst_set(descriptor_set)
+                .dst_binding(PLANE_BINDINGS)
+                .descriptor_type(vk::DescriptorType::SAMPLER)
+                .image_info(&sampler_info),
+        );
+        unsafe { device.update_descriptor_sets(&writes, &[]) };
+
+        Ok((descriptor_pool, descriptor_set))
+    }
+
+    /// Take the planes of `image` over from the client, convert them into its RGB image
+    /// and hand them back, all in `command_buffer`
+    ///
+    /// Has to be recorded outside of rendering. The RGB image is left in
+    /// `SHADER_READ_ONLY_OPTIMAL` layout for the fragment shaders of the frame.
+    pub fn record(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        image: &mut YuvImage,
+        representation: &ColorRepresentationSurfaceCachedState,
+    ) {
+        let extent = image.extent();
+        let (coefficients, range) = representation
+            .coefficients()
+            .unwrap_or_else(|| default_coefficients(extent));
+        let location = representation
+            .chroma_location()
+            .unwrap_or(ChromaLocation::Type0);
+        let conversion = YcbcrConversion::new(coefficients, range, image.format.bit_depth());
+
+        // The offset is applied in normalised coordinates of the chroma plane, and only
+        // along the axes it is subsampled in
+        let chroma_plane = image.format.planes().last().unwrap();
+        let chroma_extent = chroma_plane.extent(extent);
+        let offset = chroma_offset(location);
+        let chroma_offset = [
+            if chroma_plane.horizontal_subsampling > 1 {
+                offset[0] / chroma_extent.width as f32
+            } else {
+                0.0
+            },
+            if chroma_plane.vertical_subsampling > 1 {
+                offset[1] / chroma_extent.height as f32
+            } else {
+                0.0
+            },
+        ];
+        let push_constants = YuvPushConstants {
+            matrix: conversion.to_columns(),
+            luma_size: [extent.width as f32, extent.height as f32],
+            chroma_offset,
+            value_scale: image.format.value_scale(),
+            plane_layout: image.format.shader_layout(),
+        };
+
+        for plane in &mut image.planes {
+            plane.record_acquire(command_buffer);
+        }
+        let device = self.context.device();
+        unsafe {
+            image_barrier(
+                device,
+                command_buffer,
+                image.rgb.image(),
+                vk::ImageLayout::UNDEFINED,
+                vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                (
+                    vk::PipelineStageFlags2::FRAGMENT_SHADER,
+                    vk::AccessFlags2::NONE,
+                ),
+                (
+                    vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT,
+                    vk::AccessFlags2::COLOR_ATTACHMENT_WRITE,
+                ),
+            );
+            let attachments = [vk::RenderingAttachmentInfo::default()
+                .image_view(image.rgb.view())
+                .image_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+                .load_op(vk::AttachmentLoadOp::DONT_CARE)
+                .store_op(vk::AttachmentStoreOp::STORE)];
+            let render_area = vk::Rect2D {
+                offset: vk::Offset2D::default(),
+                extent,
+            };
+            let rendering_info = vk::RenderingInfo::default()
+                .render_area(render_area)
+                .layer_count(1)
+                .color_attachments(&attachments);
+            device.cmd_begin_rendering(command_buffer, &rendering_info);
+            device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline,
+            );
+            device.cmd_set_viewport(
+                command_buffer,
+                0,
+                &[vk::Viewport {
+                    x: 0.0,
+                    y: 0.0,
+                    width: extent.width as f32,
+                    height: extent.height as f32,
+                    min_depth: 0.0,
+                    max_depth: 1.0,
+                }],
+            );
+            device.cmd_set_scissor(command_buffer, 0, &[render_area]);
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline_layout,
+                0,
+                &[image.descriptor_set],
+                &[],
+            );
+            device.cmd_push_constants(
+                command_buffer,
+                self.pipeline_layout,
+                vk::ShaderStageFlags::FRAGMENT,
+                0,
+                push_constant_bytes(&push_constants),
+            );
+            device.cmd_draw(command_buffer, 3, 1, 0, 0);
+            device.cmd_end_rendering(command_buffer);
+            image_barrier(
+                device,
+                command_buffer,
+                image.rgb.image(),
+                vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                (
+                    vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT,
+                    vk::AccessFlags2::COLOR_ATTACHMENT_WRITE,
+                ),
+                (
+                    vk::PipelineStageFlags2::FRAGMENT_SHADER,
+                    vk::AccessFlags2::SHADER_SAMPLED_READ,
+                ),
+            );
+        }
+        for plane in &image.planes {
+            plane.record_release(command_buffer);
+        }
+    }
+}
+
+impl Drop for YuvConverter {
+    fn drop(&mut self) {
+        unsafe {
+            let device = self.context.device();
+            self.context.untrack(self.pipeline);
+            device.destroy_pipeline(self.pipeline, None);
+            self.context.untrack(self.pipeline_layout);
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+            self.context.untrack(self.descriptor_set_layout);
+            device.destroy_descriptor_set_layout(self.descriptor_set_layout, None);
+            self.context.untrack(self.sampler);
+            device.destroy_sampler(self.sampler, None);
+            self.context.untrack(self.vertex_module);
+            device.destroy_shader_module(self.vertex_module, None);
+            self.context.untrack(self.fragment_module);
+            device.destroy_shader_module(self.fragment_module, None);
+        }
+    }
+}
+
+/// Sampled plane images at bindings 0 to 2, their sampler after them
+const PLANE_BINDINGS: u32 = 3;
+
+fn create_plane_set_layout(context: &Context) -> StarforgeResult<vk::DescriptorSetLayout> {
+    let mut bindings: Vec<_> = (0..PLANE_BINDINGS)
+        .map(|binding| {
+            vk::DescriptorSetLayoutBinding::default()
+                .binding(binding)
+                .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+                .descriptor_count(1)
+                .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+        })
+        .collect();
+    bindings.push(
+        vk::DescriptorSetLayoutBinding::default()
+            .binding(PLANE_BINDINGS)
+            .descriptor_type(vk::DescriptorType::SAMPLER)
+            .descriptor_count(1)
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT),
+    );
+    let layout_info = vk::DescriptorSetLayoutCreateInfo::default().bindings(&bindings);
+    unsafe {
+        context
+            .device()
+            .create_descriptor_set_layout(&layout_info, None)
+            .inspect(|&layout| context.track(layout))
+            .map_err(|e| StarforgeError::RendererError(e.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Convert sampled values, clamping the result like the shader does
+    fn convert(conversion: &YcbcrConversion, ycbcr: [f32; 3]) -> [f32; 3] {
+        let rgb = conversion.matrix.apply(ycbcr);
+        std::array::from_fn(|i| (rgb[i] + conversion.offset[i]).clamp(0.0, 1.0))
+    }
+
+    fn assert_close(a: [f32; 3], b: [f32; 3]) {
+        for i in 0..3 {
+            assert!((a[i] - b[i]).abs() < 2e-3, "{a:?} != {b:?}");
+        }
+    }
+
+    /// 8 bit code values as sampled
+    fn codes(y: u32, cb: u32, cr: u32) -> [f32; 3] {
+        [y as f32 / 255.0, cb as f32 / 255.0, cr as f32 / 255.0]
+    }
+
+    #[test]
+    fn yuv_shader_compiles() {
+        compile_internal(YUV_FRAG, ShaderStage::Fragment).unwrap();
+    }
+
+    #[test]
+    fn limited_range_black_and_white() {
+        for coefficients in [
+            MatrixCoefficients::Bt601,
+            MatrixCoefficients::Bt709,
+            MatrixCoefficients::Bt2020,
+        ] {
+            let conversion = YcbcrConversion::new(coefficients, ColorRange::Limited, 8);
+            assert_close(convert(&conversion, codes(16, 128, 128)), [0.0; 3]);
+            assert_close(convert(&conversion, codes(235, 128, 128)), [1.0; 3]);
+        }
+    }
+
+    #[test]
+    fn ten_bit_limited_range_scales_the_codes() {
+        let conversion = YcbcrConversion::new(MatrixCoefficients::Bt2020, ColorRange::Limited, 10);
+        let sample = |code: u32| code as f32 / 1023.0;
+        assert_close(
+            convert(&conversion, [sample(64), sample(512), sample(512)]),
+            [0.0; 3],
+        );
+        assert_close(
+            convert(&conversion, [sample(940), sample(512), sample(512)]),
+            [1.0; 3],
+        );
+    }
+
+    #[test]
+    fn full_range_primaries() {
+        // BT.709 red: Y = Kr, Cb = -Kr / (2 (1 - Kb)), Cr = 0.5
+        let (kr, kb) = (0.2126, 0.0722);
+        let red = [
+            kr,
+            -kr / (2.0 * (1.0 - kb)) + 128.0 / 255.0,
+            0.5 + 128.0 / 255.0,
+        ];
+        let conversion = YcbcrConversion::new(MatrixCoefficients::Bt709, ColorRange::Full, 8);
+        assert_close(convert(&conversion, red), [1.0, 0.0, 0.0]);
+        assert_close(convert(&conversion, codes(0, 128, 128)), [0.0; 3]);
+        assert_close(convert(&conversion, codes(255, 128, 128)), [1.0; 3]);
+    }
+
+    #[test]
+    fn identity_reorders_gbr() {
+        let conversion = YcbcrConversion::new(MatrixCoefficients::Identity, ColorRange::Full, 8);
+        assert_close(convert(&conversion, [0.2, 0.4, 0.6]), [0.6, 0.2, 0.4]);
+    }
+
+    #[test]
+    fn plane_layouts() {
+        let extent = vk::Extent2D {
+            width: 1921,
+            height: 1081,
+        };
+        let chroma = YuvFormat::Nv12.planes()[1].extent(extent);
+        assert_eq!((chroma.width, chroma.height), (961, 541));
+        assert_eq!(YuvFormat::Yuv420.planes().len(), 3);
+        let packed = YuvFormat::Yuyv.planes()[0].extent(extent);
+        assert_eq!((packed.width, packed.height), (961, 1081));
+        assert_eq!(YuvFormat::from_fourcc(Fourcc::P010), Some(YuvFormat::P010));
+        assert_eq!(YuvFormat::from_fourcc(Fourcc::Argb8888), None);
+    }
+
+    #[test]
+    fn p010_scales_to_ten_bits() {
+        let white = (940u32 << 6) as f32 / 65535.0 * YuvFormat::P010.value_scale();
+        assert!((white - 940.0 / 1023.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn co_sited_chroma_moves_towards_the_first_luma_sample() {
+        assert_eq!(chroma_offset(ChromaLocation::Type1), [0.0, 0.0]);
+        assert_eq!(chroma_offset(ChromaLocation::Type0), [0.25, 0.0]);
+        assert_eq!(chroma_offset(ChromaLocation::Type2), [0.25, 0.25]);
+        assert_eq!(chroma_offset(ChromaLocation::Type5), [0.0, -0.25]);
+    }
+
+    #[test]
+    fn default_matrix_depends_on_the_buffer_height() {
+        let sd = vk::Extent2D {
+            width: 720,
+            height: 576,
+        };
+        let hd = vk::Extent2D {
+            width: 1280,
+            height: 720,
+        };
+        assert_eq!(
+            default_coefficients(sd),
+            (MatrixCoefficients::Bt601, ColorRange::Limited)
+        );
+        assert_eq!(
+            default_coefficients(hd),
+            (MatrixCoefficients::Bt709, ColorRange::Limited)
+        );
+    }
+
+    #[test]
+    fn push_constants_have_no_padding() {
+        assert_eq!(std::mem::size_of::<YuvPushConstants>(), 88);
+    }
+}